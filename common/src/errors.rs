@@ -25,8 +25,18 @@ impl MegaError {
         }
     }
 
+    /// Prints the error to stderr, or the help and the version of clap to stdout, which are
+    /// of the code 0.
     pub fn print(&self) {
-        panic!("{}", self.error.as_ref().unwrap());
+        let Some(error) = &self.error else {
+            return;
+        };
+        match error.downcast_ref::<clap::Error>() {
+            Some(e) => {
+                let _ = e.print();
+            }
+            None => eprintln!("error: {:#}", error),
+        }
     }
 
     pub fn unknown_subcommand(cmd: &str) -> MegaError {
@@ -51,11 +61,19 @@ impl From<anyhow::Error> for MegaError {
 
 impl From<clap::Error> for MegaError {
     fn from(err: clap::Error) -> MegaError {
-        let code = i32::from(err.use_stderr());
+        // 2 of the usage errors, and 0 of the help and the version, as clap exits with
+        let code = err.exit_code();
         MegaError::new(err.into(), code)
     }
 }
 
+/// The errors of the servers, e.g. the port in use.
+impl From<Box<dyn std::error::Error>> for MegaError {
+    fn from(err: Box<dyn std::error::Error>) -> MegaError {
+        MegaError::new(anyhow::anyhow!("{}", err), 1)
+    }
+}
+
 impl From<std::io::Error> for MegaError {
     fn from(err: std::io::Error) -> MegaError {
         MegaError::new(err.into(), 1)
@@ -73,6 +91,10 @@ impl From<sea_orm::DbErr> for MegaError {
 pub enum GitLFSError {
     #[error("Something went wrong in Git LFS")]
     GeneralError(String),
+    #[error("Git LFS object or lock not found: {0}")]
+    NotFound(String),
+    #[error("Git LFS operation is forbidden: {0}")]
+    Forbidden(String),
 }
 
 #[cfg(test)]
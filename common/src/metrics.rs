@@ -0,0 +1,23 @@
+//! The metrics registry shared by all the servers of the process, so a single scrape of the
+//! `/metrics` covers the whole gateway. The names of the metrics are prefixed by `mega_`.
+
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use prometheus_client::encoding::text::encode;
+use prometheus_client::registry::Registry;
+
+static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+pub fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY
+        .get_or_init(|| Mutex::new(Registry::with_prefix("mega")))
+        .lock()
+        .unwrap()
+}
+
+/// Encode all the registered metrics in the OpenMetrics text format.
+pub fn encode_text() -> String {
+    let mut buffer = String::new();
+    encode(&mut buffer, &registry()).unwrap();
+    buffer
+}
@@ -11,6 +11,8 @@ pub struct Model {
     pub git_id: String,
     pub object_type: String,
     pub data: Vec<u8>,
+    /// The compression of the data like `zstd`, none if it's saved as it is.
+    pub compression: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -10,5 +10,7 @@ pub mod mr;
 pub mod mr_info;
 pub mod node;
 pub mod refs;
+pub mod repo_config;
+pub mod symrefs;
 pub mod issue;
 pub mod repo_directory;
\ No newline at end of file
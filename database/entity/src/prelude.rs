@@ -8,4 +8,6 @@ pub use super::mr::Entity as Mr;
 pub use super::mr_info::Entity as MrInfo;
 pub use super::node::Entity as Node;
 pub use super::refs::Entity as Refs;
+pub use super::repo_config::Entity as RepoConfig;
 pub use super::repo_directory::Entity as RepoDirectory;
+pub use super::symrefs::Entity as Symrefs;
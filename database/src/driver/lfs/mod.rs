@@ -1,2 +1,3 @@
+pub mod s3;
 pub mod storage;
 pub mod structs;
@@ -1,14 +1,57 @@
-use sha256::digest;
-use std::fs;
-use std::io::prelude::*;
+use std::io::ErrorKind;
 use std::path;
 use std::path::PathBuf;
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use common::errors::GitLFSError;
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// The stream of the object content, so the objects are not buffered in memory.
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+/// The storage of the LFS objects content, the meta of which is saved in the `ObjectStorage`.
+#[async_trait]
+pub trait LfsStorage: Send + Sync {
+    /// Get the content of the object from the offset `start`.
+    async fn get(&self, meta: &MetaObject, start: u64) -> Result<ByteStream, GitLFSError>;
+
+    /// Get the bytes `start..end` of the content of the object, of which nothing after the
+    /// `end` is read.
+    async fn get_range(
+        &self,
+        meta: &MetaObject,
+        start: u64,
+        end: u64,
+    ) -> Result<ByteStream, GitLFSError> {
+        Ok(take_bytes(self.get(meta, start).await?, end - start))
+    }
+
+    /// Save the content of the object, returns `false` if the size or the oid of the content
+    /// is not the same as the meta, and nothing is saved.
+    async fn put(&self, meta: &MetaObject, body: ByteStream) -> Result<bool, GitLFSError>;
+
+    async fn exist(&self, meta: &MetaObject) -> bool;
+
+    /// Remove the content of the object, it's fine if it doesn't exist.
+    async fn delete(&self, meta: &MetaObject) -> Result<(), GitLFSError>;
+
+    /// A presigned URL for the client to download the object from the backend directly.
+    /// `None` if it's not supported, then the object is proxied by the gateway.
+    fn download_url(&self, _meta: &MetaObject) -> Option<String> {
+        None
+    }
+}
 
 pub struct ContentStore {
     base_path: PathBuf,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct MetaObject {
     pub oid: String,
     pub size: i64,
@@ -17,48 +60,132 @@ pub struct MetaObject {
 
 impl ContentStore {
     pub fn new(base: PathBuf) -> ContentStore {
-        fs::create_dir_all(&base).expect("Create directory failed!");
+        std::fs::create_dir_all(&base).expect("Create directory failed!");
         ContentStore { base_path: base }
     }
 
-    pub fn get(&self, meta: &MetaObject, start: i64) -> fs::File {
-        let path = path::Path::new(&self.base_path).join(transform_key(meta.oid.to_owned()));
+    fn object_path(&self, meta: &MetaObject) -> PathBuf {
+        path::Path::new(&self.base_path).join(transform_key(meta.oid.to_owned()))
+    }
+}
 
-        let mut file = fs::File::open(path).expect("Open file failed!");
+#[async_trait]
+impl LfsStorage for ContentStore {
+    async fn get(&self, meta: &MetaObject, start: u64) -> Result<ByteStream, GitLFSError> {
+        let mut file = fs::File::open(self.object_path(meta))
+            .await
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => GitLFSError::NotFound(meta.oid.to_owned()),
+                _ => GitLFSError::GeneralError(e.to_string()),
+            })?;
         if start > 0 {
-            file.seek(std::io::SeekFrom::Start(start as u64))
-                .expect("Shift file pointer failed");
+            file.seek(std::io::SeekFrom::Start(start))
+                .await
+                .map_err(|e| GitLFSError::GeneralError(e.to_string()))?;
         }
 
-        file
+        let stream = futures::stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let mut buf = vec![0; 64 * 1024];
+            match file.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(Bytes::from(buf)), Some(file)))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+        Ok(Box::pin(stream))
     }
 
-    pub fn put(&self, meta: &MetaObject, body_content: &[u8]) -> bool {
-        let path = path::Path::new(&self.base_path).join(transform_key(meta.oid.to_owned()));
+    async fn put(&self, meta: &MetaObject, mut body: ByteStream) -> Result<bool, GitLFSError> {
+        let path = self.object_path(meta);
         let dir = path.parent().unwrap();
-        fs::create_dir_all(dir).expect("Create directory failed!");
-
-        let mut file = fs::File::create(&path).expect("Open file failed");
-        let lenght_written = file.write(body_content).expect("Write file failed");
-        if lenght_written as i64 != meta.size {
-            return false;
+        let io_err = |e: std::io::Error| GitLFSError::GeneralError(e.to_string());
+        fs::create_dir_all(dir).await.map_err(io_err)?;
+
+        // Write to a temporary file, which is moved to the object path after verified.
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path).await.map_err(io_err)?;
+        let mut hasher = Sha256::new();
+        let mut length_written = 0;
+        let written: Result<(), std::io::Error> = async {
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                hasher.update(&chunk);
+                length_written += chunk.len() as i64;
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await
+        }
+        .await;
+        if let Err(e) = written {
+            // e.g. the upload is aborted, nothing is left
+            fs::remove_file(&tmp_path).await.ok();
+            return Err(io_err(e));
         }
 
-        let hash = digest(body_content);
-        if hash != meta.oid {
-            return false;
+        let hash = hex::encode(hasher.finalize());
+        if length_written != meta.size || hash != meta.oid {
+            fs::remove_file(&tmp_path).await.map_err(io_err)?;
+            return Ok(false);
         }
-        true
+        fs::rename(&tmp_path, &path).await.map_err(io_err)?;
+        Ok(true)
     }
 
-    pub fn exist(&self, meta: &MetaObject) -> bool {
-        let path = path::Path::new(&self.base_path).join(transform_key(meta.oid.to_owned()));
+    async fn exist(&self, meta: &MetaObject) -> bool {
+        path::Path::exists(&self.object_path(meta))
+    }
 
-        path::Path::exists(&path)
+    async fn delete(&self, meta: &MetaObject) -> Result<(), GitLFSError> {
+        match fs::remove_file(self.object_path(meta)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(GitLFSError::GeneralError(e.to_string()))
+            }
+            _ => Ok(()),
+        }
     }
 }
 
-fn transform_key(key: String) -> String {
+/// Collect the content stream into a whole for the small objects, e.g. in tests.
+pub async fn read_to_end(mut stream: ByteStream) -> Result<Vec<u8>, std::io::Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(data)
+}
+
+/// The first `len` bytes of the stream, which is dropped after them.
+pub fn take_bytes(stream: ByteStream, len: u64) -> ByteStream {
+    Box::pin(futures::stream::unfold(
+        Some((stream, len)),
+        |state| async move {
+            let (mut stream, len) = state?;
+            if len == 0 {
+                return None;
+            }
+            match stream.next().await? {
+                Ok(mut chunk) => {
+                    chunk.truncate(len.min(chunk.len() as u64) as usize);
+                    let rest = len - chunk.len() as u64;
+                    Some((Ok(chunk), Some((stream, rest))))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        },
+    ))
+}
+
+/// Build a content stream from the bytes in memory.
+pub fn bytes_stream(data: impl Into<Bytes>) -> ByteStream {
+    let data: Bytes = data.into();
+    Box::pin(futures::stream::once(async move { Ok(data) }))
+}
+
+pub(crate) fn transform_key(key: String) -> String {
     if key.len() < 5 {
         key
     } else {
@@ -77,8 +204,8 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn test_content_store() {
+    #[tokio::test]
+    async fn test_content_store() {
         let meta = MetaObject {
             oid: "6ae8a75555209fd6c44157c0aed8016e763ff435a19cf186f76863140143ff72".to_owned(),
             size: 12,
@@ -91,8 +218,32 @@ mod tests {
         source.push("tests/objects");
 
         let content_store = ContentStore::new(source);
-        assert!(content_store.put(&meta, content));
+        assert!(content_store.put(&meta, bytes_stream(content)).await.unwrap());
+
+        assert!(content_store.exist(&meta).await);
+        let data = read_to_end(content_store.get(&meta, 5).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(data, b"content");
+        let data = read_to_end(content_store.get_range(&meta, 5, 9).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(data, b"cont");
+    }
 
-        assert!(content_store.exist(&meta));
+    #[tokio::test]
+    async fn test_content_store_mismatch() {
+        let meta = MetaObject {
+            oid: "0000000000000000000000000000000000000000000000000000000000000000".to_owned(),
+            size: 12,
+            exist: false,
+        };
+        let content_store = ContentStore::new(env::temp_dir().join("mega_lfs_mismatch"));
+        // the oid is not the sha256 of the content
+        assert!(!content_store
+            .put(&meta, bytes_stream("test content"))
+            .await
+            .unwrap());
+        assert!(!content_store.exist(&meta).await);
     }
 }
@@ -47,8 +47,7 @@ impl RequestVars {
     }
 
     pub async fn verify_link(&self, ext_origin: String) -> String {
-        let path = format!("/verify/{}", &self.oid);
-        format!("{}{}", ext_origin, path)
+        format!("{}/objects/verify", ext_origin)
     }
 }
 
@@ -67,6 +66,7 @@ pub struct User {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BatchVars {
+    #[serde(default)]
     pub transfers: Vec<String>,
     pub operation: String,
     pub objects: Vec<RequestVars>,
@@ -0,0 +1,855 @@
+//! The storage of which everything is kept in the memory of the process, so the handlers can
+//! be tested without a database, and nothing is left after it is dropped. It is selected by
+//! the `memory://` url, e.g. for a demo of a small repo.
+//!
+//! The ids of the models are the same as the auto increment ones of the databases if not set.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use common::errors::{GitLFSError, MegaError};
+use entity::{
+    commit, git_obj, issue, mr, mr_info, node, refs, repo_config, repo_directory, symrefs,
+};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, DatabaseConnection, DbErr, EntityTrait, Iterable, Set,
+    TryIntoModel, Value,
+};
+
+use crate::driver::lfs::storage::MetaObject;
+use crate::driver::lfs::structs::{Lock, RequestVars};
+use crate::driver::{delete_lock, ObjectStorage, RefUpdate};
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    /// Never connected, the methods of the trait which need it are not available
+    connection: DatabaseConnection,
+    objects: Mutex<HashMap<String, git_obj::Model>>,
+    refs: Mutex<Vec<refs::Model>>,
+    metas: Mutex<HashMap<String, MetaObject>>,
+    /// The locks by the repo
+    locks: Mutex<HashMap<String, Vec<Lock>>>,
+    mr_objects: Mutex<Vec<mr::Model>>,
+    mr_infos: Mutex<Vec<mr_info::Model>>,
+    commits: Mutex<Vec<commit::Model>>,
+    nodes: Mutex<Vec<node::Model>>,
+    issues: Mutex<Vec<issue::Model>>,
+    directories: Mutex<Vec<repo_directory::Model>>,
+    repo_configs: Mutex<HashMap<String, repo_config::Model>>,
+    /// The symbolic refs by the repo and the name
+    symrefs: Mutex<HashMap<(String, String), symrefs::Model>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for MemoryStorage {
+    fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    async fn ping(&self) -> Result<(), MegaError> {
+        Ok(())
+    }
+
+    async fn save_obj_data(&self, obj_data: Vec<git_obj::ActiveModel>) -> Result<bool, MegaError> {
+        let mut objects = self.objects.lock().unwrap();
+        for model in obj_data {
+            let git_id = model.git_id.as_ref().clone();
+            objects.entry(git_id.clone()).or_insert(git_obj::Model {
+                id: model.id.unwrap(),
+                git_id,
+                object_type: model.object_type.unwrap(),
+                data: model.data.unwrap(),
+                compression: None,
+            });
+        }
+        Ok(true)
+    }
+
+    async fn get_existing_obj_ids(
+        &self,
+        git_ids: Vec<String>,
+    ) -> Result<HashSet<String>, MegaError> {
+        let objects = self.objects.lock().unwrap();
+        Ok(git_ids
+            .into_iter()
+            .filter(|id| objects.contains_key(id))
+            .collect())
+    }
+
+    async fn get_obj_data_by_ids(
+        &self,
+        git_ids: Vec<String>,
+    ) -> Result<Vec<git_obj::Model>, MegaError> {
+        let objects = self.objects.lock().unwrap();
+        Ok(git_ids
+            .iter()
+            .filter_map(|id| objects.get(id).cloned())
+            .collect())
+    }
+
+    async fn get_obj_data_by_id(&self, git_id: &str) -> Result<Option<git_obj::Model>, MegaError> {
+        Ok(self.objects.lock().unwrap().get(git_id).cloned())
+    }
+
+    async fn get_ref_object_id(&self, repo_path: &str) -> Result<Vec<refs::Model>, MegaError> {
+        let refs = self.refs.lock().unwrap();
+        Ok(refs
+            .iter()
+            .filter(|r| r.repo_path == repo_path)
+            .cloned()
+            .collect())
+    }
+
+    async fn search_refs(&self, path_str: &str) -> Result<Vec<refs::Model>, MegaError> {
+        // the refs of the repos of which the path is a prefix, the same as the databases
+        let refs = self.refs.lock().unwrap();
+        Ok(refs
+            .iter()
+            .filter(|r| path_str.starts_with(&r.repo_path))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_all_refs(&self) -> Result<Vec<refs::Model>, MegaError> {
+        Ok(self.refs.lock().unwrap().clone())
+    }
+
+    async fn search_commits(&self, path_str: &str) -> Result<Vec<commit::Model>, MegaError> {
+        let commits = self.commits.lock().unwrap();
+        Ok(commits
+            .iter()
+            .filter(|c| path_str.starts_with(&c.repo_path))
+            .cloned()
+            .collect())
+    }
+
+    async fn save_mr_objects(&self, objects: Vec<mr::ActiveModel>) -> Result<bool, MegaError> {
+        let objects = objects
+            .into_iter()
+            .map(|model| model.try_into_model())
+            .collect::<Result<Vec<_>, DbErr>>()?;
+        self.mr_objects.lock().unwrap().extend(objects);
+        Ok(true)
+    }
+
+    async fn get_mr_objects_by_type(
+        &self,
+        mr_id: i64,
+        object_type: &str,
+    ) -> Result<Vec<mr::Model>, MegaError> {
+        let objects = self.mr_objects.lock().unwrap();
+        Ok(objects
+            .iter()
+            .filter(|o| o.mr_id == mr_id && o.object_type == object_type)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_mr_id_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<mr::Model>, MegaError> {
+        let objects = self.mr_objects.lock().unwrap();
+        Ok(objects
+            .iter()
+            .filter(|o| hashes.contains(&o.git_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn save_mr_info(&self, mut mr_info: mr_info::ActiveModel) -> Result<bool, MegaError> {
+        let mut infos = self.mr_infos.lock().unwrap();
+        if mr_info.id.is_not_set() {
+            mr_info.id = Set(infos.iter().map(|i| i.id).max().unwrap_or(0) + 1);
+        }
+        infos.push(mr_info.try_into_model()?);
+        Ok(true)
+    }
+
+    async fn get_mr_infos(&self, mr_ids: Vec<i64>) -> Result<Vec<mr_info::Model>, MegaError> {
+        let infos = self.mr_infos.lock().unwrap();
+        Ok(infos
+            .iter()
+            .filter(|i| mr_ids.contains(&i.mr_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_commit_by_hash(&self, hash: &str) -> Result<Option<commit::Model>, MegaError> {
+        let commits = self.commits.lock().unwrap();
+        Ok(commits.iter().find(|c| c.git_id == hash).cloned())
+    }
+
+    async fn get_commit_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<commit::Model>, MegaError> {
+        let commits = self.commits.lock().unwrap();
+        Ok(commits
+            .iter()
+            .filter(|c| hashes.contains(&c.git_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_all_commits_by_path(
+        &self,
+        repo_path: &str,
+    ) -> Result<Vec<commit::Model>, MegaError> {
+        let commits = self.commits.lock().unwrap();
+        Ok(commits
+            .iter()
+            .filter(|c| c.repo_path == repo_path)
+            .cloned()
+            .collect())
+    }
+
+    async fn save_commits(&self, models: Vec<commit::ActiveModel>) -> Result<bool, MegaError> {
+        let mut commits = self.commits.lock().unwrap();
+        let mut id = commits.iter().map(|c| c.id).max().unwrap_or(0);
+        for mut model in models {
+            if model.id.is_not_set() {
+                id += 1;
+                model.id = Set(id);
+            }
+            set_null(&mut model.author);
+            set_null(&mut model.committer);
+            set_null(&mut model.content);
+            commits.push(model.try_into_model()?);
+        }
+        Ok(true)
+    }
+
+    async fn get_nodes_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<node::Model>, MegaError> {
+        let nodes = self.nodes.lock().unwrap();
+        Ok(nodes
+            .iter()
+            .filter(|n| hashes.contains(&n.git_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_node_by_hash(&self, hash: &str) -> Result<Option<node::Model>, MegaError> {
+        let nodes = self.nodes.lock().unwrap();
+        Ok(nodes.iter().find(|n| n.git_id == hash).cloned())
+    }
+
+    async fn get_node_by_path(&self, path: &Path) -> Result<Vec<node::Model>, MegaError> {
+        let path = path.to_str().unwrap();
+        let nodes = self.nodes.lock().unwrap();
+        Ok(nodes
+            .iter()
+            .filter(|n| n.repo_path == path)
+            .cloned()
+            .collect())
+    }
+
+    async fn save_nodes(&self, models: Vec<node::ActiveModel>) -> Result<bool, MegaError> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut id = nodes.iter().map(|n| n.id).max().unwrap_or(0);
+        for mut model in models {
+            if model.id.is_not_set() {
+                id += 1;
+                model.id = Set(id);
+            }
+            set_null(&mut model.name);
+            set_null(&mut model.content_sha);
+            nodes.push(model.try_into_model()?);
+        }
+        Ok(true)
+    }
+
+    async fn search_root_node_by_path(&self, repo_path: &Path) -> Option<node::Model> {
+        let name = repo_path.file_name().unwrap().to_str().unwrap();
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .iter()
+            .find(|n| n.name.as_deref() == Some(name))
+            .or_else(|| nodes.iter().find(|n| n.name.as_deref() == Some("")))
+            .cloned()
+    }
+
+    async fn save_refs(&self, save_models: Vec<refs::ActiveModel>) -> Result<bool, MegaError> {
+        for model in save_models {
+            self.update_ref(
+                model.repo_path.as_ref(),
+                model.ref_name.as_ref(),
+                model.ref_git_id.as_ref(),
+            )
+            .await?;
+        }
+        Ok(true)
+    }
+
+    async fn update_refs(&self, old_id: String, new_id: String, path: &Path) {
+        let path = path.to_str().unwrap();
+        let mut refs = self.refs.lock().unwrap();
+        if let Some(r) = refs
+            .iter_mut()
+            .find(|r| r.repo_path == path && r.ref_git_id == old_id)
+        {
+            r.ref_git_id = new_id;
+            r.updated_at = chrono::Utc::now().naive_utc();
+        }
+    }
+
+    async fn delete_refs(&self, old_id: String, path: &Path) {
+        let path = path.to_str().unwrap();
+        self.refs
+            .lock()
+            .unwrap()
+            .retain(|r| !(r.repo_path == path && r.ref_git_id == old_id));
+    }
+
+    async fn update_ref(
+        &self,
+        repo_path: &str,
+        ref_name: &str,
+        git_id: &str,
+    ) -> Result<(), MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut refs = self.refs.lock().unwrap();
+        match refs
+            .iter_mut()
+            .find(|r| r.repo_path == repo_path && r.ref_name == ref_name)
+        {
+            Some(r) => {
+                r.ref_git_id = git_id.to_owned();
+                r.updated_at = now;
+            }
+            None => {
+                let id = refs.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+                refs.push(refs::Model {
+                    id,
+                    repo_path: repo_path.to_owned(),
+                    ref_name: ref_name.to_owned(),
+                    ref_git_id: git_id.to_owned(),
+                    created_at: now,
+                    updated_at: now,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply_ref_updates(
+        &self,
+        repo_path: &str,
+        updates: &[RefUpdate],
+        atomic: bool,
+    ) -> Result<Vec<bool>, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        // locked during all the updates, like the transaction of the databases
+        let mut refs = self.refs.lock().unwrap();
+        let position = |refs: &[refs::Model], name: &str| {
+            refs.iter()
+                .position(|r| r.repo_path == repo_path && r.ref_name == name)
+        };
+        let current: Vec<bool> = updates
+            .iter()
+            .map(|update| {
+                let index = position(&refs, &update.ref_name);
+                update.is_current(index.map(|i| refs[i].ref_git_id.as_str()))
+            })
+            .collect();
+        if atomic && current.contains(&false) {
+            return Ok(current);
+        }
+        for (update, _) in updates.iter().zip(&current).filter(|(_, c)| **c) {
+            match (position(&refs, &update.ref_name), &update.new_id) {
+                (Some(i), Some(new_id)) => {
+                    refs[i].ref_git_id = new_id.clone();
+                    refs[i].updated_at = now;
+                }
+                (Some(i), None) => {
+                    refs.remove(i);
+                }
+                (None, Some(new_id)) => {
+                    let id = refs.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+                    refs.push(refs::Model {
+                        id,
+                        repo_path: repo_path.to_owned(),
+                        ref_name: update.ref_name.clone(),
+                        ref_git_id: new_id.clone(),
+                        created_at: now,
+                        updated_at: now,
+                    });
+                }
+                (None, None) => {}
+            }
+        }
+        Ok(current)
+    }
+
+    async fn lfs_get_meta(&self, v: &RequestVars) -> Result<MetaObject, GitLFSError> {
+        self.metas
+            .lock()
+            .unwrap()
+            .get(&v.oid)
+            .cloned()
+            .ok_or_else(|| GitLFSError::GeneralError("".to_string()))
+    }
+
+    async fn lfs_put_meta(&self, v: &RequestVars) -> Result<MetaObject, GitLFSError> {
+        let mut metas = self.metas.lock().unwrap();
+        let meta = metas.entry(v.oid.clone()).or_insert(MetaObject {
+            oid: v.oid.clone(),
+            size: v.size,
+            exist: true,
+        });
+        Ok(meta.clone())
+    }
+
+    async fn lfs_get_metas(&self) -> Result<Vec<MetaObject>, GitLFSError> {
+        Ok(self.metas.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn lfs_delete_meta(&self, v: &RequestVars) -> Result<(), GitLFSError> {
+        self.metas.lock().unwrap().remove(&v.oid);
+        Ok(())
+    }
+
+    async fn lfs_get_locks(&self, refspec: &str) -> Result<Vec<Lock>, GitLFSError> {
+        self.locks
+            .lock()
+            .unwrap()
+            .get(refspec)
+            .cloned()
+            .ok_or_else(|| GitLFSError::NotFound(refspec.to_owned()))
+    }
+
+    async fn lfs_add_lock(&self, repo: &str, mut locks: Vec<Lock>) -> Result<(), GitLFSError> {
+        let mut saved = self.locks.lock().unwrap();
+        let repo_locks = saved.entry(repo.to_owned()).or_default();
+        repo_locks.append(&mut locks);
+        repo_locks.sort_by(|a, b| {
+            a.locked_at
+                .partial_cmp(&b.locked_at)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Ok(())
+    }
+
+    async fn lfs_delete_lock(
+        &self,
+        repo: &str,
+        user: Option<String>,
+        id: &str,
+        force: bool,
+    ) -> Result<Lock, GitLFSError> {
+        let mut saved = self.locks.lock().unwrap();
+        let Some(repo_locks) = saved.get_mut(repo) else {
+            return Err(GitLFSError::NotFound(id.to_owned()));
+        };
+        let lock = delete_lock(repo_locks, user, id, force)?;
+        if repo_locks.is_empty() {
+            saved.remove(repo);
+        }
+        Ok(lock)
+    }
+
+    async fn get_repo_config(
+        &self,
+        repo_path: &str,
+    ) -> Result<Option<repo_config::Model>, MegaError> {
+        Ok(self.repo_configs.lock().unwrap().get(repo_path).cloned())
+    }
+
+    async fn save_repo_config(&self, model: repo_config::Model) -> Result<(), MegaError> {
+        self.repo_configs
+            .lock()
+            .unwrap()
+            .insert(model.repo_path.clone(), model);
+        Ok(())
+    }
+
+    async fn get_symref(
+        &self,
+        repo_path: &str,
+        ref_name: &str,
+    ) -> Result<Option<symrefs::Model>, MegaError> {
+        let key = (repo_path.to_owned(), ref_name.to_owned());
+        Ok(self.symrefs.lock().unwrap().get(&key).cloned())
+    }
+
+    async fn save_symref(&self, model: symrefs::Model) -> Result<(), MegaError> {
+        let key = (model.repo_path.clone(), model.ref_name.clone());
+        self.symrefs.lock().unwrap().insert(key, model);
+        Ok(())
+    }
+
+    async fn save_issue(&self, mut issue: issue::ActiveModel) -> Result<bool, MegaError> {
+        let mut issues = self.issues.lock().unwrap();
+        if issue.id.is_not_set() {
+            issue.id = Set(issues.iter().map(|i| i.id).max().unwrap_or(0) + 1);
+        }
+        set_null(&mut issue.closed_at);
+        issues.push(issue.try_into_model()?);
+        Ok(true)
+    }
+
+    async fn update_issue(&self, issue: issue::ActiveModel) -> Result<bool, MegaError> {
+        let mut issues = self.issues.lock().unwrap();
+        let id = issue.id.as_ref();
+        let Some(saved) = issues.iter_mut().find(|i| i.id == *id) else {
+            return Err(DbErr::RecordNotFound(format!("issue {}", id)).into());
+        };
+        *saved = merge(saved.clone().into(), issue).try_into_model()?;
+        Ok(true)
+    }
+
+    async fn get_issue_by_id(&self, id: i64) -> Result<Option<issue::Model>, MegaError> {
+        let issues = self.issues.lock().unwrap();
+        Ok(issues.iter().find(|i| i.id == id).cloned())
+    }
+
+    async fn save_directory(
+        &self,
+        mut model: repo_directory::ActiveModel,
+    ) -> Result<i32, MegaError> {
+        let mut directories = self.directories.lock().unwrap();
+        if model.id.is_not_set() {
+            model.id = Set(directories.iter().map(|d| d.id).max().unwrap_or(0) + 1);
+        }
+        // the default of the databases, of a directory at the root
+        if model.pid.is_not_set() {
+            model.pid = Set(0);
+        }
+        let model = model.try_into_model()?;
+        let id = model.id;
+        directories.push(model);
+        Ok(id)
+    }
+
+    async fn get_directory_by_full_path(
+        &self,
+        path: &str,
+    ) -> Result<Option<repo_directory::Model>, DbErr> {
+        let directories = self.directories.lock().unwrap();
+        Ok(directories.iter().find(|d| d.full_path == path).cloned())
+    }
+
+    async fn get_directory_by_pid(&self, pid: i32) -> Result<Vec<repo_directory::Model>, DbErr> {
+        let directories = self.directories.lock().unwrap();
+        Ok(directories
+            .iter()
+            .filter(|d| d.pid == pid)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_repo_directories(&self) -> Result<Vec<repo_directory::Model>, MegaError> {
+        let directories = self.directories.lock().unwrap();
+        Ok(directories.iter().filter(|d| d.is_repo).cloned().collect())
+    }
+
+    async fn delete_repo(&self, repo_path: &str) -> Result<(), MegaError> {
+        self.refs
+            .lock()
+            .unwrap()
+            .retain(|r| r.repo_path != repo_path);
+        self.commits
+            .lock()
+            .unwrap()
+            .retain(|c| c.repo_path != repo_path);
+        self.nodes
+            .lock()
+            .unwrap()
+            .retain(|n| n.repo_path != repo_path);
+        self.repo_configs.lock().unwrap().remove(repo_path);
+        self.symrefs
+            .lock()
+            .unwrap()
+            .retain(|(path, _), _| path != repo_path);
+        self.directories
+            .lock()
+            .unwrap()
+            .retain(|d| d.full_path != repo_path);
+        Ok(())
+    }
+
+    async fn delete_obj_data(&self, git_ids: Vec<String>) -> Result<(), MegaError> {
+        let mut objects = self.objects.lock().unwrap();
+        for git_id in git_ids {
+            objects.remove(&git_id);
+        }
+        Ok(())
+    }
+}
+
+/// A nullable column not set is null, like the default of the databases.
+fn set_null<T>(value: &mut ActiveValue<Option<T>>)
+where
+    Option<T>: Into<Value>,
+{
+    if value.is_not_set() {
+        *value = Set(None);
+    }
+}
+
+/// The saved model of which the columns set by the update are replaced, like an `UPDATE`.
+fn merge<A: ActiveModelTrait>(mut saved: A, update: A) -> A {
+    for column in <A::Entity as EntityTrait>::Column::iter() {
+        if let ActiveValue::Set(value) = update.get(column) {
+            saved.set(column, value);
+        }
+    }
+    saved
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use entity::{git_obj, issue};
+    use sea_orm::{ActiveValue::NotSet, Set};
+
+    use super::MemoryStorage;
+    use crate::driver::{ObjectStorage, RefUpdate};
+
+    fn blob(id: i64, git_id: &str, data: &[u8]) -> git_obj::ActiveModel {
+        git_obj::ActiveModel {
+            id: Set(id),
+            git_id: Set(git_id.to_owned()),
+            object_type: Set("blob".to_owned()),
+            data: Set(data.to_vec()),
+            compression: Set(None),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_objects() {
+        let storage = MemoryStorage::new();
+        let git_id = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        assert!(!storage.exists(git_id).await.unwrap());
+        storage
+            .put_object(blob(1, git_id, b"Hello, World!"))
+            .await
+            .unwrap();
+        // saved once by the git id
+        storage
+            .put_object(blob(2, git_id, b"Hello, World!"))
+            .await
+            .unwrap();
+
+        assert!(storage.exists(git_id).await.unwrap());
+        let object = storage.get_object(git_id).await.unwrap().unwrap();
+        assert_eq!(object.id, 1);
+        assert_eq!(object.data, b"Hello, World!");
+        let objects = storage
+            .get_obj_data_by_ids(vec![git_id.to_owned(), "0".repeat(40)])
+            .await
+            .unwrap();
+        assert_eq!(objects.len(), 1);
+        assert!(storage.get_object(&"0".repeat(40)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refs() {
+        let storage = MemoryStorage::new();
+        let (main, dev) = ("1".repeat(40), "2".repeat(40));
+        storage
+            .update_ref("/projects/repo", "refs/heads/main", &main)
+            .await
+            .unwrap();
+        storage
+            .update_ref("/projects/repo", "refs/heads/dev", &dev)
+            .await
+            .unwrap();
+        storage
+            .update_ref("/projects/other", "refs/heads/main", &dev)
+            .await
+            .unwrap();
+        let get = |name: &'static str| storage.get_ref("/projects/repo", name);
+        assert_eq!(
+            get("refs/heads/main").await.unwrap().unwrap().ref_git_id,
+            main
+        );
+
+        // the ref is moved, not added
+        storage
+            .update_ref("/projects/repo", "refs/heads/main", &dev)
+            .await
+            .unwrap();
+        assert_eq!(
+            get("refs/heads/main").await.unwrap().unwrap().ref_git_id,
+            dev
+        );
+        assert_eq!(
+            storage
+                .get_ref_object_id("/projects/repo")
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            storage
+                .search_refs("/projects/repo/src")
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let path = Path::new("/projects/other");
+        storage.update_refs(dev.clone(), main.clone(), path).await;
+        let other = storage.get_ref("/projects/other", "refs/heads/main").await;
+        assert_eq!(other.unwrap().unwrap().ref_git_id, main);
+        storage.delete_refs(main, path).await;
+        assert!(storage
+            .search_refs("/projects/other")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply_ref_updates() {
+        let storage = MemoryStorage::new();
+        let (main, dev) = ("1".repeat(40), "2".repeat(40));
+        let repo = "/projects/repo";
+        storage
+            .update_ref(repo, "refs/heads/main", &main)
+            .await
+            .unwrap();
+        let update = |name: &str, old_id: Option<&String>, new_id: Option<&String>| RefUpdate {
+            ref_name: name.to_owned(),
+            old_id: old_id.cloned(),
+            new_id: new_id.cloned(),
+        };
+        let updates = [
+            update("refs/heads/dev", None, Some(&dev)),
+            // stale, main is not dev
+            update("refs/heads/main", Some(&dev), Some(&main)),
+        ];
+        let get = |name: &'static str| storage.get_ref(repo, name);
+
+        // nothing is applied by an atomic one
+        let current = storage.apply_ref_updates(repo, &updates, true).await;
+        assert_eq!(current.unwrap(), [true, false]);
+        assert!(get("refs/heads/dev").await.unwrap().is_none());
+
+        // but the current ones are applied by the others
+        let current = storage.apply_ref_updates(repo, &updates, false).await;
+        assert_eq!(current.unwrap(), [true, false]);
+        assert_eq!(
+            get("refs/heads/dev").await.unwrap().unwrap().ref_git_id,
+            dev
+        );
+        assert_eq!(
+            get("refs/heads/main").await.unwrap().unwrap().ref_git_id,
+            main
+        );
+
+        let updates = [
+            update("refs/heads/dev", Some(&dev), None),
+            update("refs/heads/main", Some(&main), Some(&dev)),
+        ];
+        let current = storage.apply_ref_updates(repo, &updates, true).await;
+        assert_eq!(current.unwrap(), [true, true]);
+        assert!(get("refs/heads/dev").await.unwrap().is_none());
+        assert_eq!(
+            get("refs/heads/main").await.unwrap().unwrap().ref_git_id,
+            dev
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ref_updates_race() {
+        let storage = Arc::new(MemoryStorage::new());
+        let main = "0".repeat(40);
+        storage
+            .update_ref("/projects/repo", "refs/heads/main", &main)
+            .await
+            .unwrap();
+        // all of them read the main, but only one is applied
+        let tasks: Vec<_> = (1..=8)
+            .map(|i| {
+                let (storage, main) = (storage.clone(), main.clone());
+                tokio::spawn(async move {
+                    let updates = [RefUpdate {
+                        ref_name: "refs/heads/main".to_owned(),
+                        old_id: Some(main),
+                        new_id: Some(i.to_string().repeat(40)),
+                    }];
+                    let current = storage.apply_ref_updates("/projects/repo", &updates, false);
+                    current.await.unwrap()[0]
+                })
+            })
+            .collect();
+        let mut applied = 0;
+        for task in tasks {
+            applied += task.await.unwrap() as usize;
+        }
+        assert_eq!(applied, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ref_creates_race() {
+        let storage = Arc::new(MemoryStorage::new());
+        // none of them sees the ref, but only one creates it
+        let tasks: Vec<_> = (1..=8)
+            .map(|i| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    let updates = [RefUpdate {
+                        ref_name: "refs/heads/main".to_owned(),
+                        old_id: None,
+                        new_id: Some(i.to_string().repeat(40)),
+                    }];
+                    let current = storage.apply_ref_updates("/projects/repo", &updates, false);
+                    current.await.unwrap()[0]
+                })
+            })
+            .collect();
+        let mut applied = 0;
+        for task in tasks {
+            applied += task.await.unwrap() as usize;
+        }
+        assert_eq!(applied, 1);
+        let refs = storage.get_ref_object_id("/projects/repo").await.unwrap();
+        assert_eq!(refs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_issue() {
+        let storage = MemoryStorage::new();
+        let now = chrono::Utc::now().naive_utc();
+        let issue = issue::ActiveModel {
+            id: NotSet,
+            number: Set(1),
+            title: Set("the first issue".to_owned()),
+            sender_name: Set("alice".to_owned()),
+            sender_id: Set(1),
+            state: Set("open".to_owned()),
+            created_at: Set(now),
+            updated_at: Set(now),
+            closed_at: Set(None),
+            repo_path: Set("/projects/repo".to_owned()),
+            repo_id: Set(1),
+        };
+        storage.save_issue(issue).await.unwrap();
+        let update = issue::ActiveModel {
+            id: Set(1),
+            state: Set("closed".to_owned()),
+            closed_at: Set(Some(now)),
+            ..Default::default()
+        };
+        storage.update_issue(update).await.unwrap();
+
+        let issue = storage.get_issue_by_id(1).await.unwrap().unwrap();
+        assert_eq!(issue.state, "closed");
+        assert_eq!(issue.closed_at, Some(now));
+        assert_eq!(issue.title, "the first issue");
+    }
+}
@@ -5,11 +5,10 @@
 extern crate common;
 
 use std::cmp::min;
+use std::collections::HashSet;
 use std::path::Path;
 
 use async_trait::async_trait;
-use chrono::DateTime;
-use chrono::Utc;
 
 use entity::commit;
 use entity::git_obj;
@@ -20,30 +19,66 @@ use entity::mr;
 use entity::mr_info;
 use entity::node;
 use entity::refs;
+use entity::repo_config;
+use entity::symrefs;
 
 use entity::repo_directory;
+use sea_orm::sea_query::Expr;
+use sea_orm::sea_query::OnConflict;
 use sea_orm::ActiveModelTrait;
 use sea_orm::ColumnTrait;
+use sea_orm::Condition;
+use sea_orm::ConnectionTrait;
 use sea_orm::DatabaseConnection;
+use sea_orm::DbBackend;
 use sea_orm::DbErr;
 use sea_orm::EntityTrait;
 use sea_orm::QueryFilter;
+use sea_orm::QuerySelect;
+use sea_orm::QueryTrait;
 use sea_orm::Set;
+use sea_orm::TransactionTrait;
 
 use crate::driver::lfs::storage::MetaObject;
 use crate::driver::lfs::structs::Lock;
 use crate::driver::lfs::structs::RequestVars;
+use crate::utils::compression;
 use common::errors::GitLFSError;
 use common::errors::MegaError;
 
 pub mod lfs;
+pub mod memory;
 pub mod mysql;
 pub mod postgres;
+pub mod retry;
+pub mod sqlite;
+
+/// An update of a ref of a push, which is applied only if the ref is still the old id. The id
+/// is none for a ref which doesn't exist, i.e. the old id of a created ref and the new id of a
+/// deleted one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    pub ref_name: String,
+    pub old_id: Option<String>,
+    pub new_id: Option<String>,
+}
+
+impl RefUpdate {
+    /// Whether the ref of the id, none if it doesn't exist, is still the old id.
+    pub fn is_current(&self, ref_git_id: Option<&str>) -> bool {
+        self.old_id.as_deref() == ref_git_id
+    }
+}
 
 #[async_trait]
 pub trait ObjectStorage: Send + Sync {
     fn get_connection(&self) -> &DatabaseConnection;
 
+    /// Check whether the database is reachable.
+    async fn ping(&self) -> Result<(), MegaError> {
+        Ok(self.get_connection().ping().await?)
+    }
+
     async fn save_mr_objects(&self, objects: Vec<mr::ActiveModel>) -> Result<bool, MegaError> {
         batch_save_model(self.get_connection(), objects).await?;
         Ok(true)
@@ -80,23 +115,122 @@ pub trait ObjectStorage: Send + Sync {
             .unwrap())
     }
 
+    /// The ones of the `git_ids` which are saved, without loading the data of the objects.
+    async fn get_existing_obj_ids(
+        &self,
+        git_ids: Vec<String>,
+    ) -> Result<HashSet<String>, MegaError> {
+        let ids: Vec<String> = git_obj::Entity::find()
+            .select_only()
+            .column(git_obj::Column::GitId)
+            .filter(git_obj::Column::GitId.is_in(git_ids))
+            .into_tuple()
+            .all(self.get_connection())
+            .await?;
+        Ok(ids.into_iter().collect())
+    }
+
+    /// The ones of the `obj_data` to save, which are neither saved nor earlier in the
+    /// `obj_data`, so an object is saved once by the git id however many commits or repos have it.
+    async fn unsaved_obj_data(
+        &self,
+        obj_data: Vec<git_obj::ActiveModel>,
+    ) -> Result<Vec<git_obj::ActiveModel>, MegaError> {
+        let mut saved = HashSet::new();
+        for chunk in obj_data.chunks(1000) {
+            let git_ids = chunk.iter().map(|m| m.git_id.as_ref().clone()).collect();
+            saved.extend(self.get_existing_obj_ids(git_ids).await?);
+        }
+        Ok(obj_data
+            .into_iter()
+            .filter(|m| saved.insert(m.git_id.as_ref().clone()))
+            .collect())
+    }
+
     async fn get_obj_data_by_ids(
         &self,
         git_ids: Vec<String>,
     ) -> Result<Vec<git_obj::Model>, MegaError> {
-        Ok(git_obj::Entity::find()
+        git_obj::Entity::find()
             .filter(git_obj::Column::GitId.is_in(git_ids))
             .all(self.get_connection())
-            .await
-            .unwrap())
+            .await?
+            .into_iter()
+            .map(compression::decompress)
+            .collect()
     }
 
     async fn get_obj_data_by_id(&self, git_id: &str) -> Result<Option<git_obj::Model>, MegaError> {
-        Ok(git_obj::Entity::find()
+        git_obj::Entity::find()
             .filter(git_obj::Column::GitId.eq(git_id))
             .one(self.get_connection())
-            .await
-            .unwrap())
+            .await?
+            .map(compression::decompress)
+            .transpose()
+    }
+
+    /// The object of the git id.
+    async fn get_object(&self, git_id: &str) -> Result<Option<git_obj::Model>, MegaError> {
+        self.get_obj_data_by_id(git_id).await
+    }
+
+    /// Save the object, which is kept as it is if it is saved already.
+    async fn put_object(&self, object: git_obj::ActiveModel) -> Result<(), MegaError> {
+        self.save_obj_data(vec![object]).await?;
+        Ok(())
+    }
+
+    /// Whether the object of the git id is saved.
+    async fn exists(&self, git_id: &str) -> Result<bool, MegaError> {
+        Ok(self
+            .get_existing_obj_ids(vec![git_id.to_owned()])
+            .await?
+            .contains(git_id))
+    }
+
+    /// The ref of the repo by the full name, e.g. `refs/heads/main`.
+    async fn get_ref(
+        &self,
+        repo_path: &str,
+        ref_name: &str,
+    ) -> Result<Option<refs::Model>, MegaError> {
+        Ok(self
+            .get_ref_object_id(repo_path)
+            .await?
+            .into_iter()
+            .find(|r| r.ref_name == ref_name))
+    }
+
+    /// Point the ref of the repo to the git id, the ref is created if it does not exist.
+    async fn update_ref(
+        &self,
+        repo_path: &str,
+        ref_name: &str,
+        git_id: &str,
+    ) -> Result<(), MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        match self.get_ref(repo_path, ref_name).await? {
+            Some(model) => {
+                let mut model: refs::ActiveModel = model.into();
+                model.ref_git_id = Set(git_id.to_owned());
+                model.updated_at = Set(now);
+                model.update(self.get_connection()).await?;
+            }
+            None => {
+                let model = refs::ActiveModel {
+                    repo_path: Set(repo_path.to_owned()),
+                    ref_name: Set(ref_name.to_owned()),
+                    ref_git_id: Set(git_id.to_owned()),
+                    created_at: Set(now),
+                    updated_at: Set(now),
+                    ..Default::default()
+                };
+                refs::Entity::insert(model)
+                    .exec(self.get_connection())
+                    .await?;
+            }
+        }
+        Ok(())
     }
 
     async fn get_mr_id_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<mr::Model>, MegaError> {
@@ -146,6 +280,11 @@ pub trait ObjectStorage: Send + Sync {
 
     async fn search_refs(&self, path_str: &str) -> Result<Vec<refs::Model>, MegaError>;
 
+    /// The refs of all the repos, e.g. to find the objects reachable from any of them.
+    async fn get_all_refs(&self) -> Result<Vec<refs::Model>, MegaError> {
+        Ok(refs::Entity::find().all(self.get_connection()).await?)
+    }
+
     async fn search_commits(&self, path_str: &str) -> Result<Vec<commit::Model>, MegaError>;
 
     async fn save_refs(&self, save_models: Vec<refs::ActiveModel>) -> Result<bool, MegaError> {
@@ -181,6 +320,32 @@ pub trait ObjectStorage: Send + Sync {
             .unwrap();
     }
 
+    /// Apply the updates of the refs of the repo in a transaction, returns whether each of them
+    /// is current, i.e. the ref is still the old id, and only the current ones are applied. If
+    /// they are `atomic`, none is applied unless all of them are current.
+    ///
+    /// Each one is a compare-and-swap of the ref, the conditional `UPDATE` or `DELETE` of the
+    /// old id, or the `INSERT` of a new ref checked by the unique index, so of two pushes racing
+    /// to update a ref only the first one is applied.
+    async fn apply_ref_updates(
+        &self,
+        repo_path: &str,
+        updates: &[RefUpdate],
+        atomic: bool,
+    ) -> Result<Vec<bool>, MegaError> {
+        let txn = self.get_connection().begin().await?;
+        let mut current = Vec::new();
+        for update in updates {
+            current.push(compare_and_swap_ref(&txn, repo_path, update).await?);
+        }
+        if atomic && current.contains(&false) {
+            txn.rollback().await?;
+        } else {
+            txn.commit().await?;
+        }
+        Ok(current)
+    }
+
     async fn get_nodes_by_hashes(
         &self,
         hashes: Vec<String>,
@@ -290,13 +455,29 @@ pub trait ObjectStorage: Send + Sync {
         }
     }
 
+    /// The meta of all the LFS objects saved.
+    async fn lfs_get_metas(&self) -> Result<Vec<MetaObject>, GitLFSError> {
+        let metas = meta::Entity::find()
+            .all(self.get_connection())
+            .await
+            .map_err(|e| GitLFSError::GeneralError(e.to_string()))?;
+        Ok(metas
+            .into_iter()
+            .map(|m| MetaObject {
+                oid: m.oid,
+                size: m.size,
+                exist: m.exist,
+            })
+            .collect())
+    }
+
     async fn lfs_delete_meta(&self, v: &RequestVars) -> Result<(), GitLFSError> {
         let res = meta::Entity::delete_by_id(v.oid.to_owned())
             .exec(self.get_connection())
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(_) => Err(GitLFSError::GeneralError("".to_string())),
+            Err(e) => Err(GitLFSError::GeneralError(e.to_string())),
         }
     }
 
@@ -304,7 +485,7 @@ pub trait ObjectStorage: Send + Sync {
         let result = locks::Entity::find_by_id(refspec)
             .one(self.get_connection())
             .await
-            .unwrap();
+            .map_err(|e| GitLFSError::GeneralError(e.to_string()))?;
 
         match result {
             Some(val) => {
@@ -312,68 +493,27 @@ pub trait ObjectStorage: Send + Sync {
                 let locks: Vec<Lock> = serde_json::from_str(&data).unwrap();
                 Ok(locks)
             }
-            None => Err(GitLFSError::GeneralError("".to_string())),
+            None => Err(GitLFSError::NotFound(refspec.to_owned())),
         }
     }
 
     async fn lfs_get_filtered_locks(
         &self,
         refspec: &str,
+        id: &str,
         path: &str,
         cursor: &str,
         limit: &str,
     ) -> Result<(Vec<Lock>, String), GitLFSError> {
-        let mut locks = match self.lfs_get_locks(refspec).await {
+        let locks = match self.lfs_get_locks(refspec).await {
             Ok(locks) => locks,
-            Err(_) => vec![],
+            // No lock of the ref yet.
+            Err(GitLFSError::NotFound(_)) => vec![],
+            Err(e) => return Err(e),
         };
 
-        println!("Locks retrieved: {:?}", locks);
-
-        if !cursor.is_empty() {
-            let mut last_seen = -1;
-            for (i, v) in locks.iter().enumerate() {
-                if v.id == *cursor {
-                    last_seen = i as i32;
-                    break;
-                }
-            }
-
-            if last_seen > -1 {
-                locks = locks.split_off(last_seen as usize);
-            } else {
-                // Cursor not found.
-                return Err(GitLFSError::GeneralError("".to_string()));
-            }
-        }
-
-        if !path.is_empty() {
-            let mut filterd = Vec::<Lock>::new();
-            for lock in locks.iter() {
-                if lock.path == *path {
-                    filterd.push(Lock {
-                        id: lock.id.to_owned(),
-                        path: lock.path.to_owned(),
-                        owner: lock.owner.clone(),
-                        locked_at: lock.locked_at.to_owned(),
-                    });
-                }
-            }
-            locks = filterd;
-        }
-
-        let mut next = "".to_string();
-        if !limit.is_empty() {
-            let mut size = limit.parse::<i64>().unwrap();
-            size = min(size, locks.len() as i64);
-
-            if size + 1 < locks.len() as i64 {
-                next = locks[size as usize].id.to_owned();
-            }
-            let _ = locks.split_off(size as usize);
-        }
-
-        Ok((locks, next))
+        tracing::debug!("Locks retrieved: {:?}", locks);
+        filter_locks(locks, id, path, cursor, limit)
     }
 
     async fn lfs_add_lock(&self, repo: &str, locks: Vec<Lock>) -> Result<(), GitLFSError> {
@@ -434,86 +574,105 @@ pub trait ObjectStorage: Send + Sync {
         }
     }
 
+    /// Delete the lock of the id, a lock owned by other user can only be deleted with `force`.
     async fn lfs_delete_lock(
         &self,
         repo: &str,
-        _user: Option<String>,
+        user: Option<String>,
         id: &str,
         force: bool,
     ) -> Result<Lock, GitLFSError> {
         let result = locks::Entity::find_by_id(repo.to_owned())
             .one(self.get_connection())
             .await
-            .unwrap();
+            .map_err(|e| GitLFSError::GeneralError(e.to_string()))?;
 
-        match result {
-            // Exist, then delete.
-            Some(val) => {
-                let d = val.data.to_owned();
-                let locks_from_data = if !d.is_empty() {
-                    let locks_from_data: Vec<Lock> = serde_json::from_str(&d).unwrap();
-                    locks_from_data
-                } else {
-                    vec![]
-                };
+        // Not exist, error.
+        let Some(val) = result else {
+            return Err(GitLFSError::NotFound(id.to_owned()));
+        };
+        let d = val.data.to_owned();
+        let mut new_locks = if !d.is_empty() {
+            let locks_from_data: Vec<Lock> = serde_json::from_str(&d)
+                .map_err(|e| GitLFSError::GeneralError(format!("Invalid locks: {}", e)))?;
+            locks_from_data
+        } else {
+            vec![]
+        };
+        let lock_to_delete = delete_lock(&mut new_locks, user, id, force)?;
 
-                let mut new_locks = Vec::<Lock>::new();
-                let mut lock_to_delete = Lock {
-                    id: "".to_owned(),
-                    path: "".to_owned(),
-                    owner: None,
-                    locked_at: {
-                        let locked_at: DateTime<Utc> = DateTime::<Utc>::MIN_UTC;
-                        locked_at.to_rfc3339()
-                    },
-                };
+        // No locks remains, delete the repo from database.
+        if new_locks.is_empty() {
+            locks::Entity::delete_by_id(repo.to_owned())
+                .exec(self.get_connection())
+                .await
+                .map_err(|e| GitLFSError::GeneralError(e.to_string()))?;
 
-                for lock in locks_from_data.iter() {
-                    if lock.id == *id {
-                        if Option::is_some(&lock.owner) && !force {
-                            return Err(GitLFSError::GeneralError("".to_string()));
-                        }
-                        lock_to_delete.id = lock.id.to_owned();
-                        lock_to_delete.path = lock.path.to_owned();
-                        lock_to_delete.owner = lock.owner.clone();
-                        lock_to_delete.locked_at = lock.locked_at.to_owned();
-                    } else if !lock.id.is_empty() {
-                        new_locks.push(Lock {
-                            id: lock.id.to_owned(),
-                            path: lock.path.to_owned(),
-                            owner: lock.owner.clone(),
-                            locked_at: lock.locked_at.to_owned(),
-                        });
-                    }
-                }
-                if lock_to_delete.id.is_empty() {
-                    return Err(GitLFSError::GeneralError("".to_string()));
-                }
+            return Ok(lock_to_delete);
+        }
 
-                // No locks remains, delete the repo from database.
-                if new_locks.is_empty() {
-                    locks::Entity::delete_by_id(repo.to_owned())
-                        .exec(self.get_connection())
-                        .await
-                        .unwrap();
+        // Update remaining locks.
+        let data = serde_json::to_string(&new_locks)
+            .map_err(|e| GitLFSError::GeneralError(e.to_string()))?;
 
-                    return Ok(lock_to_delete);
-                }
+        let mut lock_to: locks::ActiveModel = val.into();
+        lock_to.data = Set(data.to_owned());
+        lock_to
+            .update(self.get_connection())
+            .await
+            .map_err(|e| GitLFSError::GeneralError(e.to_string()))?;
+        Ok(lock_to_delete)
+    }
 
-                // Update remaining locks.
-                let data = serde_json::to_string(&new_locks).unwrap();
+    /// The settings saved of the repo, none if they are never saved.
+    async fn get_repo_config(
+        &self,
+        repo_path: &str,
+    ) -> Result<Option<repo_config::Model>, MegaError> {
+        Ok(repo_config::Entity::find_by_id(repo_path)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Save the settings of the repo, replacing the ones saved before.
+    async fn save_repo_config(&self, model: repo_config::Model) -> Result<(), MegaError> {
+        let model: repo_config::ActiveModel = model.into();
+        repo_config::Entity::insert(model)
+            .on_conflict(
+                OnConflict::column(repo_config::Column::RepoPath)
+                    .update_columns([repo_config::Column::Config, repo_config::Column::UpdatedAt])
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
 
-                let mut lock_to: locks::ActiveModel = val.into();
-                lock_to.data = Set(data.to_owned());
-                let res = lock_to.update(self.get_connection()).await;
-                match res.is_ok() {
-                    true => Ok(lock_to_delete),
-                    false => Err(GitLFSError::GeneralError("".to_string())),
-                }
-            }
-            // Not exist, error.
-            None => Err(GitLFSError::GeneralError("".to_string())),
-        }
+    /// The symbolic ref of the repo like `HEAD`, none if it's never saved.
+    async fn get_symref(
+        &self,
+        repo_path: &str,
+        ref_name: &str,
+    ) -> Result<Option<symrefs::Model>, MegaError> {
+        Ok(
+            symrefs::Entity::find_by_id((repo_path.to_owned(), ref_name.to_owned()))
+                .one(self.get_connection())
+                .await?,
+        )
+    }
+
+    /// Save the symbolic ref of the repo, replacing the target of it saved before.
+    async fn save_symref(&self, model: symrefs::Model) -> Result<(), MegaError> {
+        let model: symrefs::ActiveModel = model.into();
+        symrefs::Entity::insert(model)
+            .on_conflict(
+                OnConflict::columns([symrefs::Column::RepoPath, symrefs::Column::RefName])
+                    .update_columns([symrefs::Column::Target, symrefs::Column::UpdatedAt])
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
     }
 
     async fn save_issue(&self, issue: issue::ActiveModel) -> Result<bool, MegaError> {
@@ -561,6 +720,189 @@ pub trait ObjectStorage: Send + Sync {
         .all(self.get_connection())
         .await
     }
+
+    /// The directories which are repos, including the ones created without any push.
+    async fn get_repo_directories(&self) -> Result<Vec<repo_directory::Model>, MegaError> {
+        Ok(repo_directory::Entity::find()
+            .filter(repo_directory::Column::IsRepo.eq(true))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Delete the refs, the commits, the nodes, the settings and the directory of the repo in a
+    /// transaction. The objects are kept, which may be shared by the other repos.
+    async fn delete_repo(&self, repo_path: &str) -> Result<(), MegaError> {
+        let txn = self.get_connection().begin().await?;
+        refs::Entity::delete_many()
+            .filter(refs::Column::RepoPath.eq(repo_path))
+            .exec(&txn)
+            .await?;
+        commit::Entity::delete_many()
+            .filter(commit::Column::RepoPath.eq(repo_path))
+            .exec(&txn)
+            .await?;
+        node::Entity::delete_many()
+            .filter(node::Column::RepoPath.eq(repo_path))
+            .exec(&txn)
+            .await?;
+        repo_config::Entity::delete_by_id(repo_path)
+            .exec(&txn)
+            .await?;
+        symrefs::Entity::delete_many()
+            .filter(symrefs::Column::RepoPath.eq(repo_path))
+            .exec(&txn)
+            .await?;
+        repo_directory::Entity::delete_many()
+            .filter(repo_directory::Column::FullPath.eq(repo_path))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// Delete the objects of the ids, e.g. the ones of a deleted repo which no other repo
+    /// references.
+    async fn delete_obj_data(&self, git_ids: Vec<String>) -> Result<(), MegaError> {
+        for chunk in git_ids.chunks(1000) {
+            git_obj::Entity::delete_many()
+                .filter(git_obj::Column::GitId.is_in(chunk.to_vec()))
+                .exec(self.get_connection())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Delete the lock of the id from the locks of a repo, a lock owned by other user can only be
+/// deleted with `force`.
+fn delete_lock(
+    locks: &mut Vec<Lock>,
+    user: Option<String>,
+    id: &str,
+    force: bool,
+) -> Result<Lock, GitLFSError> {
+    let Some(index) = locks.iter().position(|lock| lock.id == *id) else {
+        return Err(GitLFSError::NotFound(id.to_owned()));
+    };
+    let owned_by_others = match &locks[index].owner {
+        Some(owner) => user.as_ref() != Some(&owner.name),
+        None => false,
+    };
+    if owned_by_others && !force {
+        return Err(GitLFSError::Forbidden(format!(
+            "lock {} is owned by others",
+            id
+        )));
+    }
+    let lock = locks.remove(index);
+    locks.retain(|lock| !lock.id.is_empty());
+    Ok(lock)
+}
+
+/// Filter the locks of a repo by the id and the path, and take a page of the ones filtered.
+///
+/// The `cursor` is the id of the first lock of the page, and the returned cursor is the one
+/// of the next page, which is empty if there is no more lock.
+fn filter_locks(
+    mut locks: Vec<Lock>,
+    id: &str,
+    path: &str,
+    cursor: &str,
+    limit: &str,
+) -> Result<(Vec<Lock>, String), GitLFSError> {
+    if !id.is_empty() {
+        locks.retain(|lock| lock.id == *id);
+    }
+    if !path.is_empty() {
+        locks.retain(|lock| lock.path == *path);
+    }
+
+    if !cursor.is_empty() {
+        match locks.iter().position(|v| v.id == *cursor) {
+            Some(last_seen) => locks = locks.split_off(last_seen),
+            // Cursor not found.
+            None => return Err(GitLFSError::GeneralError("".to_string())),
+        }
+    }
+
+    let mut next = "".to_string();
+    if !limit.is_empty() {
+        let size = limit
+            .parse::<usize>()
+            .map_err(|_| GitLFSError::GeneralError(format!("invalid limit: {}", limit)))?;
+        let size = min(size, locks.len());
+
+        if size < locks.len() {
+            next = locks[size].id.to_owned();
+        }
+        locks.truncate(size);
+    }
+
+    Ok((locks, next))
+}
+
+/// Apply the update of the ref if it's still the old id, returns whether it's applied.
+async fn compare_and_swap_ref<C: ConnectionTrait>(
+    db: &C,
+    repo_path: &str,
+    update: &RefUpdate,
+) -> Result<bool, DbErr> {
+    let now = chrono::Utc::now().naive_utc();
+    let of_ref = Condition::all()
+        .add(refs::Column::RepoPath.eq(repo_path))
+        .add(refs::Column::RefName.eq(update.ref_name.as_str()));
+    let swapped = match (&update.old_id, &update.new_id) {
+        (Some(old_id), Some(new_id)) => {
+            refs::Entity::update_many()
+                .col_expr(refs::Column::RefGitId, Expr::value(new_id.as_str()))
+                .col_expr(refs::Column::UpdatedAt, Expr::value(now))
+                .filter(of_ref)
+                .filter(refs::Column::RefGitId.eq(old_id.as_str()))
+                .exec(db)
+                .await?
+                .rows_affected
+                > 0
+        }
+        (Some(old_id), None) => {
+            refs::Entity::delete_many()
+                .filter(of_ref)
+                .filter(refs::Column::RefGitId.eq(old_id.as_str()))
+                .exec(db)
+                .await?
+                .rows_affected
+                > 0
+        }
+        (None, None) => refs::Entity::find().filter(of_ref).one(db).await?.is_none(),
+        (None, Some(new_id)) => {
+            let model = refs::ActiveModel {
+                repo_path: Set(repo_path.to_owned()),
+                ref_name: Set(update.ref_name.clone()),
+                ref_git_id: Set(new_id.clone()),
+                created_at: Set(now),
+                updated_at: Set(now),
+                ..Default::default()
+            };
+            // there is no row to compare, the ref created meanwhile by another push is kept by
+            // the unique index of the refs, so nothing is inserted
+            let insert = refs::Entity::insert(model);
+            let stmt = match db.get_database_backend() {
+                DbBackend::MySql => {
+                    let mut stmt = insert.build(DbBackend::MySql);
+                    stmt.sql = stmt.sql.replacen("INSERT", "INSERT IGNORE", 1);
+                    stmt
+                }
+                backend => insert
+                    .on_conflict(
+                        OnConflict::columns([refs::Column::RepoPath, refs::Column::RefName])
+                            .do_nothing()
+                            .to_owned(),
+                    )
+                    .build(backend),
+            };
+            db.execute(stmt).await?.rows_affected() > 0
+        }
+    };
+    Ok(swapped)
 }
 
 /// Performs batch saving of models in the database.
@@ -603,3 +945,64 @@ where
     futures::future::join_all(results).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::filter_locks;
+    use crate::driver::lfs::structs::Lock;
+
+    fn locks() -> Vec<Lock> {
+        ["a.bin", "b.bin", "a.bin"]
+            .iter()
+            .enumerate()
+            .map(|(i, path)| Lock {
+                id: i.to_string(),
+                path: path.to_string(),
+                locked_at: "2023-10-01T00:00:00+00:00".to_string(),
+                owner: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_locks_pagination() {
+        let (page, next) = filter_locks(locks(), "", "", "", "2").unwrap();
+        assert_eq!(page.iter().map(|l| l.id.as_str()).collect::<Vec<_>>(), ["0", "1"]);
+        assert_eq!(next, "2");
+
+        let (page, next) = filter_locks(locks(), "", "", &next, "2").unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, "2");
+        assert!(next.is_empty());
+
+        // unknown cursor
+        assert!(filter_locks(locks(), "", "", "9", "2").is_err());
+    }
+
+    #[test]
+    fn test_filter_locks_by_path() {
+        let (page, next) = filter_locks(locks(), "", "a.bin", "", "").unwrap();
+        assert_eq!(page.len(), 2);
+        assert!(next.is_empty());
+        let (page, next) = filter_locks(locks(), "", "a.bin", "", "1").unwrap();
+        assert_eq!(page[0].id, "0");
+        assert_eq!(next, "2");
+
+        let (page, next) = filter_locks(locks(), "", "a.bin", &next, "1").unwrap();
+        assert_eq!(page[0].id, "2");
+        assert!(next.is_empty());
+    }
+
+    #[test]
+    fn test_filter_locks_by_id() {
+        // filtered before the page is taken
+        let (page, next) = filter_locks(locks(), "2", "", "", "1").unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, "2");
+        assert!(next.is_empty());
+        assert!(filter_locks(locks(), "2", "b.bin", "", "")
+            .unwrap()
+            .0
+            .is_empty());
+    }
+}
@@ -21,15 +21,20 @@ use crate::driver::batch_save_model;
 
 use crate::driver::MegaError;
 use crate::driver::ObjectStorage;
+use crate::utils::compression::ObjectCompression;
 
 #[derive(Debug, Default)]
 pub struct MysqlStorage {
     pub connection: DatabaseConnection,
+    pub object_compression: ObjectCompression,
 }
 
 impl MysqlStorage {
     pub fn new(connection: DatabaseConnection) -> MysqlStorage {
-        MysqlStorage { connection }
+        MysqlStorage {
+            connection,
+            object_compression: ObjectCompression::None,
+        }
     }
 }
 
@@ -40,6 +45,8 @@ impl ObjectStorage for MysqlStorage {
     }
 
     async fn save_obj_data(&self, obj_data: Vec<git_obj::ActiveModel>) -> Result<bool, MegaError> {
+        let obj_data = self.unsaved_obj_data(obj_data).await?;
+        let obj_data = self.object_compression.compress_obj_data(obj_data);
         let packet_size = obj_data
             .iter()
             .map(|model| model.clone().try_into_model().unwrap().data.len())
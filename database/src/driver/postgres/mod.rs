@@ -1 +1 @@
-pub mod storage;
\ No newline at end of file
+pub mod storage;
@@ -1,43 +1,303 @@
 use async_trait::async_trait;
 use common::errors::MegaError;
 use entity::{commit, git_obj, refs};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use sea_orm::{DatabaseBackend, DatabaseConnection, EntityTrait, Statement};
 
 use crate::driver::{batch_save_model, ObjectStorage};
+use crate::utils::compression::ObjectCompression;
 
 #[derive(Debug, Default)]
-pub struct PgStorage {
+pub struct PostgresStorage {
     pub connection: DatabaseConnection,
+    pub object_compression: ObjectCompression,
 }
 
-impl PgStorage {
-    pub fn new(connection: DatabaseConnection) -> PgStorage {
-        PgStorage { connection }
+impl PostgresStorage {
+    pub fn new(connection: DatabaseConnection) -> PostgresStorage {
+        PostgresStorage {
+            connection,
+            object_compression: ObjectCompression::None,
+        }
     }
 }
 
 #[async_trait]
-impl ObjectStorage for PgStorage {
+impl ObjectStorage for PostgresStorage {
     fn get_connection(&self) -> &DatabaseConnection {
         &self.connection
     }
 
     async fn save_obj_data(&self, obj_data: Vec<git_obj::ActiveModel>) -> Result<bool, MegaError> {
+        let obj_data = self.unsaved_obj_data(obj_data).await?;
+        let obj_data = self.object_compression.compress_obj_data(obj_data);
         batch_save_model(self.get_connection(), obj_data).await?;
         Ok(true)
     }
 
     async fn search_refs(&self, path_str: &str) -> Result<Vec<refs::Model>, MegaError> {
         Ok(refs::Entity::find()
-            .filter(refs::Column::RepoPath.contains(path_str))
+            .from_raw_sql(Statement::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                r#"SELECT * FROM refs where $1 LIKE repo_path || '%'"#,
+                [path_str.into()],
+            ))
             .all(&self.connection)
             .await?)
     }
 
     async fn search_commits(&self, path_str: &str) -> Result<Vec<commit::Model>, MegaError> {
         Ok(commit::Entity::find()
-            .filter(commit::Column::RepoPath.contains(path_str))
+            .from_raw_sql(Statement::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                r#"SELECT * FROM "commit" where $1 LIKE repo_path || '%'"#,
+                [path_str.into()],
+            ))
             .all(&self.connection)
             .await?)
     }
 }
+
+/// These tests need a Postgres database initialized by `sql/postgres`, the url of which
+/// is set by the `MEGA_DB_POSTGRESQL_URL` env.
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use std::path::Path;
+
+    use entity::{commit, git_obj, refs};
+    use sea_orm::{Database, EntityTrait, Set};
+    use sha1::{Digest, Sha1};
+
+    use super::PostgresStorage;
+    use crate::driver::{ObjectStorage, RefUpdate};
+    use crate::utils::compression::ObjectCompression;
+
+    async fn storage() -> PostgresStorage {
+        let url =
+            std::env::var("MEGA_DB_POSTGRESQL_URL").expect("MEGA_DB_POSTGRESQL_URL is not set");
+        PostgresStorage::new(Database::connect(url).await.unwrap())
+    }
+
+    /// A repo path not used by the other test runs
+    fn repo_path() -> String {
+        let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+        format!("/projects/pg_test_{}", nanos)
+    }
+
+    #[tokio::test]
+    async fn test_obj_data() {
+        let storage = storage().await;
+        let id = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+        let git_id = format!("{:040x}", id);
+        let model = git_obj::ActiveModel {
+            id: Set(id),
+            git_id: Set(git_id.clone()),
+            object_type: Set("blob".to_owned()),
+            data: Set(b"Hello, World!".to_vec()),
+            compression: Set(None),
+        };
+        assert!(storage.save_obj_data(vec![model]).await.unwrap());
+
+        let obj = storage.get_obj_data_by_id(&git_id).await.unwrap().unwrap();
+        assert_eq!(obj.data, b"Hello, World!".to_vec());
+        let objs = storage.get_obj_data_by_ids(vec![git_id]).await.unwrap();
+        assert_eq!(objs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_obj_data_dedup() {
+        let storage = storage().await;
+        let id = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+        let git_id = format!("{:040x}", id);
+        let model = |id: i64| git_obj::ActiveModel {
+            id: Set(id),
+            git_id: Set(git_id.clone()),
+            object_type: Set("blob".to_owned()),
+            data: Set(b"the same blob".to_vec()),
+            compression: Set(None),
+        };
+        // twice in a batch, and again by another save
+        storage
+            .save_obj_data(vec![model(id), model(id + 1)])
+            .await
+            .unwrap();
+        storage.save_obj_data(vec![model(id + 2)]).await.unwrap();
+
+        let objs = storage.get_obj_data_by_ids(vec![git_id]).await.unwrap();
+        assert_eq!(objs.len(), 1);
+        assert_eq!(objs[0].id, id);
+    }
+
+    #[tokio::test]
+    async fn test_obj_data_zstd() {
+        let mut storage = storage().await;
+        storage.object_compression = ObjectCompression::zstd(19).unwrap();
+        let id = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+        // a blob not saved by the other test runs
+        let data = format!("{}\n", id).repeat(1000).into_bytes();
+        let mut hasher = Sha1::new();
+        hasher.update(format!("blob {}\0", data.len()));
+        hasher.update(&data);
+        let git_id = hex::encode(hasher.finalize());
+        let model = git_obj::ActiveModel {
+            id: Set(id),
+            git_id: Set(git_id.clone()),
+            object_type: Set("blob".to_owned()),
+            data: Set(data.clone()),
+            compression: Set(None),
+        };
+        storage.save_obj_data(vec![model]).await.unwrap();
+
+        let obj = storage.get_obj_data_by_id(&git_id).await.unwrap().unwrap();
+        assert_eq!(obj.data, data);
+        let raw = git_obj::Entity::find_by_id(id)
+            .one(&storage.connection)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(raw.data.len() < data.len() / 10);
+        assert_eq!(raw.compression.as_deref(), Some("zstd"));
+    }
+
+    #[tokio::test]
+    async fn test_refs() {
+        let storage = storage().await;
+        let repo_path = repo_path();
+        let now = chrono::Utc::now().naive_utc();
+        let model = refs::ActiveModel {
+            repo_path: Set(repo_path.clone()),
+            ref_name: Set("refs/heads/master".to_owned()),
+            ref_git_id: Set("0".repeat(40)),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        storage.save_refs(vec![model]).await.unwrap();
+
+        // the refs of the repo are found by a sub path
+        let refs = storage.search_refs(&format!("{}/src", repo_path)).await.unwrap();
+        assert_eq!(refs.len(), 1);
+
+        storage
+            .update_refs("0".repeat(40), "1".repeat(40), Path::new(&repo_path))
+            .await;
+        let refs = storage.get_ref_object_id(&repo_path).await.unwrap();
+        assert_eq!(refs[0].ref_git_id, "1".repeat(40));
+    }
+
+    #[tokio::test]
+    async fn test_apply_ref_updates() {
+        let storage = storage().await;
+        let repo_path = repo_path();
+        let (main, dev) = ("1".repeat(40), "2".repeat(40));
+        let update = |name: &str, old_id: Option<&String>, new_id: Option<&String>| RefUpdate {
+            ref_name: name.to_owned(),
+            old_id: old_id.cloned(),
+            new_id: new_id.cloned(),
+        };
+        let created = [update("refs/heads/main", None, Some(&main))];
+        let current = storage.apply_ref_updates(&repo_path, &created, true).await;
+        assert_eq!(current.unwrap(), [true]);
+
+        let updates = [
+            update("refs/heads/dev", None, Some(&dev)),
+            update("refs/heads/main", Some(&dev), None),
+        ];
+        // the transaction is rolled back
+        let current = storage.apply_ref_updates(&repo_path, &updates, true).await;
+        assert_eq!(current.unwrap(), [true, false]);
+        let refs = storage.get_ref_object_id(&repo_path).await.unwrap();
+        assert_eq!(refs.len(), 1);
+
+        let current = storage.apply_ref_updates(&repo_path, &updates, false).await;
+        assert_eq!(current.unwrap(), [true, false]);
+        let dev_ref = storage.get_ref(&repo_path, "refs/heads/dev").await;
+        assert_eq!(dev_ref.unwrap().unwrap().ref_git_id, dev);
+        let main_ref = storage.get_ref(&repo_path, "refs/heads/main").await;
+        assert_eq!(main_ref.unwrap().unwrap().ref_git_id, main);
+    }
+
+    #[tokio::test]
+    async fn test_ref_updates_race() {
+        let storage = storage().await;
+        let repo_path = repo_path();
+        let main = "1".repeat(40);
+        let update = |old_id: Option<&String>, new_id: String| {
+            [RefUpdate {
+                ref_name: "refs/heads/main".to_owned(),
+                old_id: old_id.cloned(),
+                new_id: Some(new_id),
+            }]
+        };
+        let created = update(None, main.clone());
+        let current = storage.apply_ref_updates(&repo_path, &created, false).await;
+        assert_eq!(current.unwrap(), [true]);
+
+        // both of them read the main, but only one is applied
+        let (first, second) = (
+            update(Some(&main), "2".repeat(40)),
+            update(Some(&main), "3".repeat(40)),
+        );
+        let (first, second) = tokio::join!(
+            storage.apply_ref_updates(&repo_path, &first, false),
+            storage.apply_ref_updates(&repo_path, &second, false)
+        );
+        let (first, second) = (first.unwrap()[0], second.unwrap()[0]);
+        assert!(first ^ second);
+        let main_ref = storage.get_ref(&repo_path, "refs/heads/main").await;
+        let expected = if first { "2" } else { "3" }.repeat(40);
+        assert_eq!(main_ref.unwrap().unwrap().ref_git_id, expected);
+    }
+
+    #[tokio::test]
+    async fn test_ref_creates_race() {
+        let storage = storage().await;
+        let repo_path = repo_path();
+        let created = |new_id: String| {
+            [RefUpdate {
+                ref_name: "refs/heads/main".to_owned(),
+                old_id: None,
+                new_id: Some(new_id),
+            }]
+        };
+        // none of them sees the ref, but only one creates it
+        let (first, second) = (created("1".repeat(40)), created("2".repeat(40)));
+        let (first, second) = tokio::join!(
+            storage.apply_ref_updates(&repo_path, &first, false),
+            storage.apply_ref_updates(&repo_path, &second, false)
+        );
+        let (first, second) = (first.unwrap()[0], second.unwrap()[0]);
+        assert!(first ^ second);
+        let refs = storage.get_ref_object_id(&repo_path).await.unwrap();
+        assert_eq!(refs.len(), 1);
+        let expected = if first { "1" } else { "2" }.repeat(40);
+        assert_eq!(refs[0].ref_git_id, expected);
+    }
+
+    #[tokio::test]
+    async fn test_commits() {
+        let storage = storage().await;
+        let repo_path = repo_path();
+        let now = chrono::Utc::now().naive_utc();
+        let git_id = format!("{:040x}", chrono::Utc::now().timestamp_nanos_opt().unwrap());
+        let model = commit::ActiveModel {
+            git_id: Set(git_id.clone()),
+            tree: Set("0".repeat(40)),
+            pid: Set(vec![]),
+            repo_path: Set(repo_path.clone()),
+            author: Set(None),
+            committer: Set(None),
+            content: Set(Some("init".to_owned())),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        storage.save_commits(vec![model]).await.unwrap();
+
+        let commit = storage.get_commit_by_hash(&git_id).await.unwrap().unwrap();
+        assert_eq!(commit.repo_path, repo_path);
+        let commits = storage.search_commits(&format!("{}/src", repo_path)).await.unwrap();
+        assert_eq!(commits.len(), 1);
+        let commits = storage.get_all_commits_by_path(&repo_path).await.unwrap();
+        assert_eq!(commits[0].git_id, git_id);
+    }
+}
@@ -0,0 +1,424 @@
+//! The timeout and the retries of the operations of a database storage, so a push isn't aborted
+//! by a transient error like a reset connection or a deadlock.
+//!
+//! An operation is retried with the backoff doubled each time, unless the error of it is not
+//! retryable, e.g. a constraint violation, which fails it at once. The retried operations are
+//! the ones of which the error is returned rather than panicked, and which are idempotent, i.e.
+//! the object writes which skip the saved objects, and the transactions of the refs which are
+//! rolled back on the error.
+
+use std::collections::HashSet;
+use std::env;
+use std::future::Future;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::errors::MegaError;
+use entity::{commit, git_obj, mr, refs, repo_config, symrefs};
+use sea_orm::{DatabaseConnection, DbErr, RuntimeErr};
+
+use crate::driver::{ObjectStorage, RefUpdate};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_RETRIES: u32 = 3;
+pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The primary result codes of SQLite, of which the extended ones are in the higher bits.
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryOptions {
+    /// The max time of an attempt, after which it's retried as a transient error.
+    pub timeout: Duration,
+    /// The max attempts after the first one.
+    pub retries: u32,
+    /// The wait before the first retry.
+    pub backoff: Duration,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        RetryOptions {
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+        }
+    }
+}
+
+impl RetryOptions {
+    /// The options of the `MEGA_DB_TIMEOUT` seconds, the `MEGA_DB_RETRIES` and the
+    /// `MEGA_DB_RETRY_BACKOFF` milliseconds envs, the defaults if they are not set.
+    pub fn from_env() -> Result<RetryOptions, MegaError> {
+        fn var<T: std::str::FromStr>(name: &str) -> Result<Option<T>, MegaError> {
+            match env::var(name) {
+                Ok(value) => {
+                    Ok(Some(value.trim().parse().map_err(|_| {
+                        anyhow::anyhow!("invalid {}: {}", name, value)
+                    })?))
+                }
+                Err(_) => Ok(None),
+            }
+        }
+        let defaults = RetryOptions::default();
+        Ok(RetryOptions {
+            timeout: var("MEGA_DB_TIMEOUT")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.timeout),
+            retries: var("MEGA_DB_RETRIES")?.unwrap_or(defaults.retries),
+            backoff: var("MEGA_DB_RETRY_BACKOFF")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.backoff),
+        })
+    }
+
+    /// Run the operation, which is retried on the timeout or a retryable error, see
+    /// [`is_retryable`]. The error after the last attempt tells how many attempts are made.
+    pub async fn run<T, F, Fut>(&self, operation: &str, mut attempt: F) -> Result<T, MegaError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, MegaError>>,
+    {
+        let mut retries = 0;
+        loop {
+            let (error, retryable) = match tokio::time::timeout(self.timeout, attempt()).await {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(e)) => {
+                    let retryable = e.error.as_ref().is_some_and(|error| {
+                        error.downcast_ref::<DbErr>().is_some_and(is_retryable)
+                    });
+                    (e, retryable)
+                }
+                Err(_) => (
+                    MegaError::new(anyhow::anyhow!("timed out after {:?}", self.timeout), 1),
+                    true,
+                ),
+            };
+            if !retryable {
+                return Err(error);
+            }
+            if retries == self.retries {
+                return Err(MegaError::new(
+                    anyhow::anyhow!(
+                        "the {} failed after {} attempts: {}",
+                        operation,
+                        retries + 1,
+                        error
+                    ),
+                    error.code,
+                ));
+            }
+            let backoff = self.backoff * 2u32.saturating_pow(retries);
+            tracing::warn!(
+                "The {} failed, retrying in {:?}: {}",
+                operation,
+                backoff,
+                error
+            );
+            tokio::time::sleep(backoff).await;
+            retries += 1;
+        }
+    }
+}
+
+/// Whether the error is transient, like a lost connection, a deadlock or a serialization
+/// failure, so the operation may succeed if it's retried.
+pub fn is_retryable(error: &DbErr) -> bool {
+    match error {
+        DbErr::ConnectionAcquire(_) => true,
+        DbErr::Conn(RuntimeErr::Internal(_)) => true,
+        DbErr::Conn(RuntimeErr::SqlxError(e))
+        | DbErr::Exec(RuntimeErr::SqlxError(e))
+        | DbErr::Query(RuntimeErr::SqlxError(e)) => is_retryable_sqlx(e),
+        _ => false,
+    }
+}
+
+fn is_retryable_sqlx(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        // the SQLSTATE of the serialization failures and the deadlocks, e.g. the 1213 of MySQL,
+        // the lock wait timeout of MySQL, and the busy or locked file of SQLite
+        sqlx::Error::Database(e) => {
+            matches!(e.code().as_deref(), Some("40001") | Some("40P01"))
+                || e.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>()
+                    .is_some_and(|e| e.number() == 1205)
+                || (e.try_downcast_ref::<sqlx::sqlite::SqliteError>().is_some()
+                    && e.code()
+                        .and_then(|code| code.parse::<i32>().ok())
+                        .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED)))
+        }
+        _ => false,
+    }
+}
+
+/// The storage of which the idempotent operations are retried by the options.
+pub struct RetryStorage<S> {
+    inner: S,
+    options: RetryOptions,
+}
+
+impl<S: ObjectStorage> RetryStorage<S> {
+    pub fn new(inner: S, options: RetryOptions) -> RetryStorage<S> {
+        RetryStorage { inner, options }
+    }
+}
+
+#[async_trait]
+impl<S: ObjectStorage> ObjectStorage for RetryStorage<S> {
+    fn get_connection(&self) -> &DatabaseConnection {
+        self.inner.get_connection()
+    }
+
+    async fn save_obj_data(&self, obj_data: Vec<git_obj::ActiveModel>) -> Result<bool, MegaError> {
+        self.options
+            .run("save of the objects", || {
+                self.inner.save_obj_data(obj_data.clone())
+            })
+            .await
+    }
+
+    async fn save_mr_objects(&self, objects: Vec<mr::ActiveModel>) -> Result<bool, MegaError> {
+        self.options
+            .run("save of the objects of the push", || {
+                self.inner.save_mr_objects(objects.clone())
+            })
+            .await
+    }
+
+    async fn get_existing_obj_ids(
+        &self,
+        git_ids: Vec<String>,
+    ) -> Result<HashSet<String>, MegaError> {
+        self.options
+            .run("read of the saved objects", || {
+                self.inner.get_existing_obj_ids(git_ids.clone())
+            })
+            .await
+    }
+
+    async fn search_refs(&self, path_str: &str) -> Result<Vec<refs::Model>, MegaError> {
+        self.options
+            .run("read of the refs", || self.inner.search_refs(path_str))
+            .await
+    }
+
+    async fn get_all_refs(&self) -> Result<Vec<refs::Model>, MegaError> {
+        self.options
+            .run("read of the refs", || self.inner.get_all_refs())
+            .await
+    }
+
+    async fn search_commits(&self, path_str: &str) -> Result<Vec<commit::Model>, MegaError> {
+        self.options
+            .run("read of the commits", || {
+                self.inner.search_commits(path_str)
+            })
+            .await
+    }
+
+    async fn get_commit_by_hash(&self, hash: &str) -> Result<Option<commit::Model>, MegaError> {
+        self.options
+            .run("read of the commit", || self.inner.get_commit_by_hash(hash))
+            .await
+    }
+
+    async fn get_commit_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<commit::Model>, MegaError> {
+        self.options
+            .run("read of the commits", || {
+                self.inner.get_commit_by_hashes(hashes.clone())
+            })
+            .await
+    }
+
+    async fn get_all_commits_by_path(
+        &self,
+        repo_path: &str,
+    ) -> Result<Vec<commit::Model>, MegaError> {
+        self.options
+            .run("read of the commits", || {
+                self.inner.get_all_commits_by_path(repo_path)
+            })
+            .await
+    }
+
+    // not retried, a commit saved before the error would be saved twice
+    async fn save_commits(&self, commits: Vec<commit::ActiveModel>) -> Result<bool, MegaError> {
+        self.inner.save_commits(commits).await
+    }
+
+    async fn update_ref(
+        &self,
+        repo_path: &str,
+        ref_name: &str,
+        git_id: &str,
+    ) -> Result<(), MegaError> {
+        self.options
+            .run("update of the ref", || {
+                self.inner.update_ref(repo_path, ref_name, git_id)
+            })
+            .await
+    }
+
+    async fn apply_ref_updates(
+        &self,
+        repo_path: &str,
+        updates: &[RefUpdate],
+        atomic: bool,
+    ) -> Result<Vec<bool>, MegaError> {
+        self.options
+            .run("update of the refs", || {
+                self.inner.apply_ref_updates(repo_path, updates, atomic)
+            })
+            .await
+    }
+
+    async fn get_repo_config(
+        &self,
+        repo_path: &str,
+    ) -> Result<Option<repo_config::Model>, MegaError> {
+        self.options
+            .run("read of the repo config", || {
+                self.inner.get_repo_config(repo_path)
+            })
+            .await
+    }
+
+    async fn save_repo_config(&self, model: repo_config::Model) -> Result<(), MegaError> {
+        self.options
+            .run("save of the repo config", || {
+                self.inner.save_repo_config(model.clone())
+            })
+            .await
+    }
+
+    async fn get_symref(
+        &self,
+        repo_path: &str,
+        ref_name: &str,
+    ) -> Result<Option<symrefs::Model>, MegaError> {
+        self.options
+            .run("read of the symbolic ref", || {
+                self.inner.get_symref(repo_path, ref_name)
+            })
+            .await
+    }
+
+    async fn save_symref(&self, model: symrefs::Model) -> Result<(), MegaError> {
+        self.options
+            .run("save of the symbolic ref", || {
+                self.inner.save_symref(model.clone())
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use common::errors::MegaError;
+    use entity::{commit, git_obj, refs};
+    use sea_orm::{DatabaseConnection, DbErr, RuntimeErr};
+
+    use super::{is_retryable, RetryOptions, RetryStorage};
+    use crate::driver::ObjectStorage;
+
+    /// Fails the first `failures` saves by the error.
+    struct FlakyStorage {
+        connection: DatabaseConnection,
+        failures: u32,
+        error: fn() -> DbErr,
+        attempts: AtomicU32,
+    }
+
+    impl FlakyStorage {
+        fn new(failures: u32, error: fn() -> DbErr) -> FlakyStorage {
+            FlakyStorage {
+                connection: DatabaseConnection::default(),
+                failures,
+                error,
+                attempts: AtomicU32::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStorage for FlakyStorage {
+        fn get_connection(&self) -> &DatabaseConnection {
+            &self.connection
+        }
+
+        async fn save_obj_data(&self, _: Vec<git_obj::ActiveModel>) -> Result<bool, MegaError> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err((self.error)().into());
+            }
+            Ok(true)
+        }
+
+        async fn search_refs(&self, _: &str) -> Result<Vec<refs::Model>, MegaError> {
+            // longer than the timeout
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(Vec::new())
+        }
+
+        async fn search_commits(&self, _: &str) -> Result<Vec<commit::Model>, MegaError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn reset() -> DbErr {
+        DbErr::Conn(RuntimeErr::Internal(String::from("connection reset")))
+    }
+
+    fn options() -> RetryOptions {
+        RetryOptions {
+            timeout: Duration::from_millis(100),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_once() {
+        let storage = RetryStorage::new(FlakyStorage::new(1, reset), options());
+        assert!(storage.save_obj_data(Vec::new()).await.unwrap());
+        assert_eq!(storage.inner.attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let storage = RetryStorage::new(FlakyStorage::new(10, reset), options());
+        let err = storage.save_obj_data(Vec::new()).await.unwrap_err();
+        assert!(err.to_string().contains("after 3 attempts"), "{}", err);
+        assert!(err.to_string().contains("connection reset"), "{}", err);
+        assert_eq!(storage.inner.attempts.load(Ordering::SeqCst), 3);
+
+        let err = storage.search_refs("/repo").await.unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_not_retryable() {
+        let storage =
+            RetryStorage::new(FlakyStorage::new(1, || DbErr::RecordNotInserted), options());
+        let err = storage.save_obj_data(Vec::new()).await.unwrap_err();
+        assert_eq!(err.to_string(), DbErr::RecordNotInserted.to_string());
+        assert_eq!(storage.inner.attempts.load(Ordering::SeqCst), 1);
+
+        assert!(is_retryable(&reset()));
+        assert!(!is_retryable(&DbErr::Custom(String::from("bad"))));
+        assert!(!is_retryable(&DbErr::Exec(RuntimeErr::Internal(
+            String::from("duplicate key")
+        ))));
+    }
+}
@@ -0,0 +1,510 @@
+//! The storage in a single SQLite file, for a personal server without a database server.
+//!
+//! The file is created and migrated at the first connection, since nobody else runs the SQL of
+//! it, and it's in the WAL mode, so the fetches keep reading while a push is writing. Only the
+//! parents of the commits, of which SQLite has no array type, are kept as a JSON array.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::errors::MegaError;
+use entity::{commit, git_obj, refs};
+use sea_orm::sea_query::{Value, Values};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseBackend, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryResult, QueryTrait, Select, SqlxSqliteConnector, Statement,
+};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+
+use crate::driver::{batch_save_model, ObjectStorage};
+use crate::migration;
+use crate::utils::compression::ObjectCompression;
+
+/// The wait for the lock of the file held by another connection, e.g. of a push.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_CONNECTIONS: u32 = 8;
+
+#[derive(Debug, Default)]
+pub struct SqliteStorage {
+    pub connection: DatabaseConnection,
+    pub object_compression: ObjectCompression,
+}
+
+impl SqliteStorage {
+    pub fn new(connection: DatabaseConnection) -> SqliteStorage {
+        SqliteStorage {
+            connection,
+            object_compression: ObjectCompression::None,
+        }
+    }
+
+    /// Open the database of the url like `sqlite://mega.db`, `sqlite:///var/lib/mega/mega.db`
+    /// or `sqlite::memory:`, and apply the pending migrations. The pool is of the
+    /// `MEGA_DB_MAX_CONNECTIONS` env, or a single connection of an in-memory database, which
+    /// is lost if the connection is closed.
+    pub async fn connect(db_url: &str) -> Result<SqliteStorage, MegaError> {
+        let options = SqliteConnectOptions::from_str(db_url)
+            .map_err(|e| anyhow::anyhow!("invalid SQLite url {}: {}", db_url, e))?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT);
+        let in_memory = db_url.contains(":memory:") || db_url.contains("mode=memory");
+        let pool = if in_memory {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+        } else {
+            let max_connections = std::env::var("MEGA_DB_MAX_CONNECTIONS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+            SqlitePoolOptions::new().max_connections(max_connections)
+        }
+        .connect_with(options)
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to open {}: {}", db_url, e))?;
+        let connection = SqlxSqliteConnector::from_sqlx_sqlite_pool(pool);
+        for applied in migration::migrate(&connection).await? {
+            tracing::info!(
+                "Migrated {} by {} {}",
+                db_url,
+                applied.version,
+                applied.name
+            );
+        }
+        Ok(SqliteStorage::new(connection))
+    }
+
+    async fn find_commits(
+        &self,
+        select: Select<commit::Entity>,
+    ) -> Result<Vec<commit::Model>, MegaError> {
+        self.query_commits(select.build(DatabaseBackend::Sqlite))
+            .await
+    }
+
+    async fn query_commits(&self, statement: Statement) -> Result<Vec<commit::Model>, MegaError> {
+        let rows = self.connection.query_all(statement).await?;
+        rows.iter().map(commit_from_row).collect()
+    }
+}
+
+/// The commit of the row, of which the `pid` is a JSON array.
+fn commit_from_row(row: &QueryResult) -> Result<commit::Model, MegaError> {
+    let pid = match row.try_get::<Option<String>>("", "pid")? {
+        Some(pid) => serde_json::from_str(&pid)
+            .map_err(|e| anyhow::anyhow!("invalid parents of the commit {}: {}", pid, e))?,
+        None => Vec::new(),
+    };
+    Ok(commit::Model {
+        id: row.try_get("", "id")?,
+        git_id: row.try_get("", "git_id")?,
+        tree: row.try_get("", "tree")?,
+        pid,
+        repo_path: row.try_get("", "repo_path")?,
+        author: row.try_get("", "author")?,
+        committer: row.try_get("", "committer")?,
+        content: row.try_get("", "content")?,
+        created_at: row.try_get("", "created_at")?,
+        updated_at: row.try_get("", "updated_at")?,
+    })
+}
+
+/// Bind the arrays of the statement as the JSON arrays, which are not supported by SQLite.
+fn bind_arrays(mut statement: Statement) -> Statement {
+    if let Some(Values(values)) = &mut statement.values {
+        for value in values.iter_mut() {
+            if let Value::Array(_, array) = value {
+                let items: Vec<String> = array
+                    .iter()
+                    .flat_map(|array| array.iter())
+                    .filter_map(|item| match item {
+                        Value::String(Some(item)) => Some(item.to_string()),
+                        _ => None,
+                    })
+                    .collect();
+                *value = serde_json::to_string(&items).unwrap().into();
+            }
+        }
+    }
+    statement
+}
+
+#[async_trait]
+impl ObjectStorage for SqliteStorage {
+    fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    async fn save_obj_data(&self, obj_data: Vec<git_obj::ActiveModel>) -> Result<bool, MegaError> {
+        let obj_data = self.unsaved_obj_data(obj_data).await?;
+        let obj_data = self.object_compression.compress_obj_data(obj_data);
+        batch_save_model(self.get_connection(), obj_data).await?;
+        Ok(true)
+    }
+
+    async fn get_commit_by_hash(&self, hash: &str) -> Result<Option<commit::Model>, MegaError> {
+        let select = commit::Entity::find().filter(commit::Column::GitId.eq(hash));
+        Ok(self.find_commits(select).await?.into_iter().next())
+    }
+
+    async fn get_commit_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<commit::Model>, MegaError> {
+        self.find_commits(commit::Entity::find().filter(commit::Column::GitId.is_in(hashes)))
+            .await
+    }
+
+    async fn get_all_commits_by_path(
+        &self,
+        repo_path: &str,
+    ) -> Result<Vec<commit::Model>, MegaError> {
+        self.find_commits(commit::Entity::find().filter(commit::Column::RepoPath.eq(repo_path)))
+            .await
+    }
+
+    async fn search_refs(&self, path_str: &str) -> Result<Vec<refs::Model>, MegaError> {
+        Ok(refs::Entity::find()
+            .from_raw_sql(Statement::from_sql_and_values(
+                DatabaseBackend::Sqlite,
+                r#"SELECT * FROM refs where ? LIKE repo_path || '%'"#,
+                [path_str.into()],
+            ))
+            .all(&self.connection)
+            .await?)
+    }
+
+    async fn search_commits(&self, path_str: &str) -> Result<Vec<commit::Model>, MegaError> {
+        self.query_commits(Statement::from_sql_and_values(
+            DatabaseBackend::Sqlite,
+            r#"SELECT * FROM "commit" where ? LIKE repo_path || '%'"#,
+            [path_str.into()],
+        ))
+        .await
+    }
+
+    async fn save_commits(&self, commits: Vec<commit::ActiveModel>) -> Result<bool, MegaError> {
+        // the 10 columns of each, in the limit of the variables of a statement
+        for chunk in commits.chunks(100) {
+            let insert = commit::Entity::insert_many(chunk.iter().cloned());
+            self.connection
+                .execute(bind_arrays(insert.build(DatabaseBackend::Sqlite)))
+                .await?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use common::errors::GitLFSError;
+    use entity::{commit, git_obj, locks, refs, repo_directory};
+    use sea_orm::{ConnectionTrait, DatabaseBackend, EntityTrait, Set, Statement};
+    use sha1::{Digest, Sha1};
+
+    use super::SqliteStorage;
+    use crate::driver::lfs::structs::Lock;
+    use crate::driver::{ObjectStorage, RefUpdate};
+    use crate::utils::compression::ObjectCompression;
+
+    async fn storage() -> SqliteStorage {
+        SqliteStorage::connect("sqlite::memory:").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_obj_data() {
+        let mut storage = storage().await;
+        let blob = |id: i64, data: &[u8]| {
+            let mut hasher = Sha1::new();
+            hasher.update(format!("blob {}\0", data.len()));
+            hasher.update(data);
+            let git_id = hex::encode(hasher.finalize());
+            let model = git_obj::ActiveModel {
+                id: Set(id),
+                git_id: Set(git_id.clone()),
+                object_type: Set("blob".to_owned()),
+                data: Set(data.to_vec()),
+                compression: Set(None),
+            };
+            (git_id, model)
+        };
+        let data = b"Hello, World!\n".repeat(100);
+        let (first, model) = blob(1, &data);
+        assert!(storage.save_obj_data(vec![model.clone()]).await.unwrap());
+        // saved again, and the new one is compressed by zstd
+        storage.object_compression = ObjectCompression::zstd(19).unwrap();
+        let (second, second_model) = blob(2, &b"the second\n".repeat(100));
+        storage
+            .save_obj_data(vec![model, second_model])
+            .await
+            .unwrap();
+
+        let obj = storage.get_obj_data_by_id(&first).await.unwrap().unwrap();
+        assert_eq!(obj.data, data);
+        let objs = storage
+            .get_obj_data_by_ids(vec![first, second.clone()])
+            .await
+            .unwrap();
+        assert_eq!(objs.len(), 2);
+        let raw = git_obj::Entity::find_by_id(2)
+            .one(&storage.connection)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(raw.data.len() < 100);
+        assert_eq!(raw.compression.as_deref(), Some("zstd"));
+        let obj = storage.get_obj_data_by_id(&second).await.unwrap().unwrap();
+        assert_eq!(obj.data, b"the second\n".repeat(100));
+        assert!(storage
+            .get_obj_data_by_id(&"f".repeat(40))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refs() {
+        let storage = storage().await;
+        let now = chrono::Utc::now().naive_utc();
+        let model = refs::ActiveModel {
+            repo_path: Set("/projects/mega".to_owned()),
+            ref_name: Set("refs/heads/master".to_owned()),
+            ref_git_id: Set("0".repeat(40)),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        storage.save_refs(vec![model]).await.unwrap();
+
+        // the refs of the repo are found by a sub path
+        let refs = storage.search_refs("/projects/mega/src").await.unwrap();
+        assert_eq!(refs.len(), 1);
+        assert!(storage
+            .search_refs("/projects/other")
+            .await
+            .unwrap()
+            .is_empty());
+
+        storage
+            .update_refs("0".repeat(40), "1".repeat(40), Path::new("/projects/mega"))
+            .await;
+        let refs = storage.get_ref_object_id("/projects/mega").await.unwrap();
+        assert_eq!(refs[0].ref_git_id, "1".repeat(40));
+    }
+
+    #[tokio::test]
+    async fn test_apply_ref_updates() {
+        let storage = storage().await;
+        let repo_path = "/projects/mega";
+        let (main, dev) = ("1".repeat(40), "2".repeat(40));
+        let update = |name: &str, old_id: Option<&String>, new_id: Option<&String>| RefUpdate {
+            ref_name: name.to_owned(),
+            old_id: old_id.cloned(),
+            new_id: new_id.cloned(),
+        };
+        let created = [update("refs/heads/main", None, Some(&main))];
+        let current = storage.apply_ref_updates(repo_path, &created, true).await;
+        assert_eq!(current.unwrap(), [true]);
+        // the ref is created already
+        let current = storage.apply_ref_updates(repo_path, &created, true).await;
+        assert_eq!(current.unwrap(), [false]);
+
+        let updates = [
+            update("refs/heads/dev", None, Some(&dev)),
+            update("refs/heads/main", Some(&dev), None),
+        ];
+        // the transaction is rolled back
+        let current = storage.apply_ref_updates(repo_path, &updates, true).await;
+        assert_eq!(current.unwrap(), [true, false]);
+        assert_eq!(storage.get_ref_object_id(repo_path).await.unwrap().len(), 1);
+
+        let current = storage.apply_ref_updates(repo_path, &updates, false).await;
+        assert_eq!(current.unwrap(), [true, false]);
+        let dev_ref = storage.get_ref(repo_path, "refs/heads/dev").await;
+        assert_eq!(dev_ref.unwrap().unwrap().ref_git_id, dev);
+
+        // both of them read the main, but only one is applied
+        let racing = |new_id: &str| {
+            [RefUpdate {
+                ref_name: "refs/heads/main".to_owned(),
+                old_id: Some(main.clone()),
+                new_id: Some(new_id.to_owned()),
+            }]
+        };
+        let (first, second) = (racing(&"3".repeat(40)), racing(&"4".repeat(40)));
+        let (first, second) = tokio::join!(
+            storage.apply_ref_updates(repo_path, &first, false),
+            storage.apply_ref_updates(repo_path, &second, false)
+        );
+        let (first, second) = (first.unwrap()[0], second.unwrap()[0]);
+        assert!(first ^ second);
+        let main_ref = storage.get_ref(repo_path, "refs/heads/main").await;
+        let expected = if first { "3" } else { "4" }.repeat(40);
+        assert_eq!(main_ref.unwrap().unwrap().ref_git_id, expected);
+    }
+
+    #[tokio::test]
+    async fn test_commits() {
+        let storage = storage().await;
+        let now = chrono::Utc::now().naive_utc();
+        let model = |git_id: &str, pid: Vec<String>| commit::ActiveModel {
+            git_id: Set(git_id.to_owned()),
+            tree: Set("0".repeat(40)),
+            pid: Set(pid),
+            repo_path: Set("/projects/mega".to_owned()),
+            author: Set(None),
+            committer: Set(None),
+            content: Set(Some("init".to_owned())),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        let (first, second) = ("1".repeat(40), "2".repeat(40));
+        storage
+            .save_commits(vec![
+                model(&first, vec![]),
+                model(&second, vec![first.clone()]),
+            ])
+            .await
+            .unwrap();
+
+        let commit = storage.get_commit_by_hash(&second).await.unwrap().unwrap();
+        assert_eq!(commit.pid, vec![first.clone()]);
+        assert_eq!(commit.created_at, now);
+        let commits = storage.search_commits("/projects/mega/src").await.unwrap();
+        assert_eq!(commits.len(), 2);
+        let commits = storage
+            .get_commit_by_hashes(vec![first.clone()])
+            .await
+            .unwrap();
+        assert!(commits[0].pid.is_empty());
+        let commits = storage
+            .get_all_commits_by_path("/projects/mega")
+            .await
+            .unwrap();
+        assert_eq!(commits.len(), 2);
+        assert!(storage
+            .get_commit_by_hash(&"3".repeat(40))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_repo() {
+        let storage = storage().await;
+        let now = chrono::Utc::now().naive_utc();
+        for repo_path in ["/projects/mega", "/projects/other"] {
+            storage
+                .update_ref(repo_path, "refs/heads/main", &"1".repeat(40))
+                .await
+                .unwrap();
+            storage
+                .save_directory(repo_directory::ActiveModel {
+                    name: Set(repo_path.rsplit('/').next().unwrap().to_owned()),
+                    is_repo: Set(true),
+                    full_path: Set(repo_path.to_owned()),
+                    created_at: Set(now),
+                    updated_at: Set(now),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let object = git_obj::ActiveModel {
+            git_id: Set("2".repeat(40)),
+            object_type: Set("blob".to_owned()),
+            data: Set(b"mega".to_vec()),
+            ..Default::default()
+        };
+        storage.save_obj_data(vec![object]).await.unwrap();
+
+        storage.delete_repo("/projects/mega").await.unwrap();
+        storage.delete_obj_data(vec!["2".repeat(40)]).await.unwrap();
+        assert!(storage
+            .get_ref_object_id("/projects/mega")
+            .await
+            .unwrap()
+            .is_empty());
+        let directories = storage.get_repo_directories().await.unwrap();
+        assert_eq!(directories.len(), 1);
+        assert_eq!(directories[0].full_path, "/projects/other");
+        assert_eq!(storage.get_all_refs().await.unwrap().len(), 1);
+        assert!(!storage.exists(&"2".repeat(40)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lfs_delete_lock() {
+        let storage = storage().await;
+        let lock = Lock {
+            id: "1".to_owned(),
+            path: "a.bin".to_owned(),
+            locked_at: "2023-10-01T00:00:00+00:00".to_owned(),
+            owner: None,
+        };
+        storage
+            .lfs_add_lock("/projects/mega", vec![lock])
+            .await
+            .unwrap();
+        let deleted = storage
+            .lfs_delete_lock("/projects/mega", None, "1", false)
+            .await
+            .unwrap();
+        assert_eq!(deleted.path, "a.bin");
+        // the repo of no locks is deleted
+        assert!(locks::Entity::find_by_id("/projects/mega")
+            .one(&storage.connection)
+            .await
+            .unwrap()
+            .is_none());
+
+        // the data broken is an error rather than a panic
+        locks::Entity::insert(locks::ActiveModel {
+            id: Set("/projects/broken".to_owned()),
+            data: Set("[{".to_owned()),
+        })
+        .exec(&storage.connection)
+        .await
+        .unwrap();
+        let res = storage
+            .lfs_delete_lock("/projects/broken", None, "1", false)
+            .await;
+        assert!(matches!(res, Err(GitLFSError::GeneralError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_file_in_wal_mode() {
+        let path = std::env::temp_dir().join(format!("mega_sqlite_{}.db", std::process::id()));
+        let url = format!("sqlite://{}", path.display());
+        let storage = SqliteStorage::connect(&url).await.unwrap();
+        let mode = storage
+            .connection
+            .query_one(Statement::from_string(
+                DatabaseBackend::Sqlite,
+                "PRAGMA journal_mode",
+            ))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(mode.try_get::<String>("", "journal_mode").unwrap(), "wal");
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &"1".repeat(40))
+            .await
+            .unwrap();
+        storage.connection.close().await.unwrap();
+
+        // kept in the file, which is not migrated again
+        let storage = SqliteStorage::connect(&url).await.unwrap();
+        let main_ref = storage.get_ref("/projects/mega", "refs/heads/main").await;
+        assert_eq!(main_ref.unwrap().unwrap().ref_git_id, "1".repeat(40));
+        storage.connection.close().await.unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
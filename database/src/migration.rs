@@ -0,0 +1,415 @@
+//! The migrations of the schema, which are the SQL files under `sql/` of each database embedded
+//! in the binary, e.g. by `mega db migrate`.
+//!
+//! The applied ones are recorded in the `mega_migrations` table by the version, so the pending
+//! ones are applied in the order of the versions, and migrating again does nothing. The first
+//! one creates the tables if they don't exist, so a database initialized by the SQL files before
+//! is migrated as well.
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, Statement, TransactionTrait, Value};
+
+use common::errors::MegaError;
+
+/// The table of the versions of the applied migrations.
+pub const MIGRATIONS_TABLE: &str = "mega_migrations";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Migration {
+    /// The date of it like `20230523` of the file, in the order of which the migrations are
+    /// applied.
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+const MYSQL_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 20230523,
+        name: "init",
+        sql: include_str!("../../sql/mysql/mysql_20230523__init.sql"),
+    },
+    Migration {
+        version: 20261015,
+        name: "symrefs",
+        sql: include_str!("../../sql/mysql/mysql_20261015__symrefs.sql"),
+    },
+    Migration {
+        version: 20261016,
+        name: "object_compression",
+        sql: include_str!("../../sql/mysql/mysql_20261016__object_compression.sql"),
+    },
+    Migration {
+        version: 20261017,
+        name: "refs_unique",
+        sql: include_str!("../../sql/mysql/mysql_20261017__refs_unique.sql"),
+    },
+    Migration {
+        version: 20261018,
+        name: "repo_config",
+        sql: include_str!("../../sql/mysql/mysql_20261018__repo_config.sql"),
+    },
+];
+
+const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2023092,
+        name: "init",
+        sql: include_str!("../../sql/postgres/pg_2023092__init.sql"),
+    },
+    Migration {
+        version: 20261015,
+        name: "symrefs",
+        sql: include_str!("../../sql/postgres/pg_20261015__symrefs.sql"),
+    },
+    Migration {
+        version: 20261016,
+        name: "object_compression",
+        sql: include_str!("../../sql/postgres/pg_20261016__object_compression.sql"),
+    },
+    Migration {
+        version: 20261017,
+        name: "refs_unique",
+        sql: include_str!("../../sql/postgres/pg_20261017__refs_unique.sql"),
+    },
+    Migration {
+        version: 20261018,
+        name: "repo_config",
+        sql: include_str!("../../sql/postgres/pg_20261018__repo_config.sql"),
+    },
+];
+
+const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 20261014,
+        name: "init",
+        sql: include_str!("../../sql/sqlite/sqlite_20261014__init.sql"),
+    },
+    Migration {
+        version: 20261015,
+        name: "symrefs",
+        sql: include_str!("../../sql/sqlite/sqlite_20261015__symrefs.sql"),
+    },
+    Migration {
+        version: 20261016,
+        name: "object_compression",
+        sql: include_str!("../../sql/sqlite/sqlite_20261016__object_compression.sql"),
+    },
+    Migration {
+        version: 20261017,
+        name: "refs_unique",
+        sql: include_str!("../../sql/sqlite/sqlite_20261017__refs_unique.sql"),
+    },
+];
+
+/// The migrations of the database.
+pub fn migrations(backend: DbBackend) -> &'static [Migration] {
+    match backend {
+        DbBackend::MySql => MYSQL_MIGRATIONS,
+        DbBackend::Postgres => POSTGRES_MIGRATIONS,
+        DbBackend::Sqlite => SQLITE_MIGRATIONS,
+    }
+}
+
+/// The backend of the connection, none if it's disconnected, e.g. of the memory storage.
+fn backend(connection: &DatabaseConnection) -> Option<DbBackend> {
+    match connection {
+        DatabaseConnection::Disconnected => None,
+        _ => Some(connection.get_database_backend()),
+    }
+}
+
+/// The versions of the applied migrations, none if the table of them doesn't exist.
+async fn applied_versions(
+    connection: &DatabaseConnection,
+    backend: DbBackend,
+) -> Result<Vec<i64>, MegaError> {
+    let tables = match backend {
+        DbBackend::MySql => {
+            "information_schema.tables WHERE table_schema = DATABASE() AND table_name"
+        }
+        DbBackend::Postgres => {
+            "information_schema.tables WHERE table_schema = current_schema() AND table_name"
+        }
+        DbBackend::Sqlite => "sqlite_master WHERE type = 'table' AND name",
+    };
+    let exists = connection
+        .query_one(Statement::from_sql_and_values(
+            backend,
+            format!(
+                "SELECT COUNT(*) AS count FROM {} = {}",
+                tables,
+                placeholder(backend, 1)
+            ),
+            [MIGRATIONS_TABLE.into()],
+        ))
+        .await?
+        .map(|row| row.try_get::<i64>("", "count"))
+        .transpose()?
+        .unwrap_or_default()
+        > 0;
+    if !exists {
+        return Ok(Vec::new());
+    }
+    let rows = connection
+        .query_all(Statement::from_string(
+            backend,
+            format!("SELECT version FROM {}", MIGRATIONS_TABLE),
+        ))
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| row.try_get::<i64>("", "version"))
+        .collect::<Result<_, _>>()?)
+}
+
+fn placeholder(backend: DbBackend, n: usize) -> String {
+    match backend {
+        DbBackend::Postgres => format!("${}", n),
+        _ => String::from("?"),
+    }
+}
+
+/// The migrations which are not applied to the database yet.
+pub async fn pending_migrations(
+    connection: &DatabaseConnection,
+) -> Result<Vec<&'static Migration>, MegaError> {
+    let Some(backend) = backend(connection) else {
+        return Ok(Vec::new());
+    };
+    let applied = applied_versions(connection, backend).await?;
+    Ok(migrations(backend)
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect())
+}
+
+/// Apply the pending migrations in order, returns the applied ones. Each one is applied in a
+/// transaction with the record of it, though the DDL of MySQL is committed at once.
+pub async fn migrate(
+    connection: &DatabaseConnection,
+) -> Result<Vec<&'static Migration>, MegaError> {
+    let Some(backend) = backend(connection) else {
+        return Ok(Vec::new());
+    };
+    connection
+        .execute_unprepared(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             version BIGINT NOT NULL PRIMARY KEY, \
+             name VARCHAR(255) NOT NULL, \
+             applied_at TIMESTAMP NOT NULL)",
+            MIGRATIONS_TABLE
+        ))
+        .await?;
+
+    let pending = pending_migrations(connection).await?;
+    for migration in &pending {
+        tracing::info!(
+            "Applying the migration {} {}",
+            migration.version,
+            migration.name
+        );
+        let txn = connection.begin().await?;
+        for statement in statements(migration.sql) {
+            txn.execute_unprepared(statement).await.map_err(|e| {
+                anyhow::anyhow!(
+                    "the migration {} {} failed: {}",
+                    migration.version,
+                    migration.name,
+                    e
+                )
+            })?;
+        }
+        txn.execute(Statement::from_sql_and_values(
+            backend,
+            format!(
+                "INSERT INTO {} (version, name, applied_at) VALUES ({}, {}, {})",
+                MIGRATIONS_TABLE,
+                placeholder(backend, 1),
+                placeholder(backend, 2),
+                placeholder(backend, 3)
+            ),
+            [
+                migration.version.into(),
+                migration.name.into(),
+                Value::from(chrono::Utc::now().naive_utc()),
+            ],
+        ))
+        .await?;
+        txn.commit().await?;
+    }
+    Ok(pending)
+}
+
+/// The statements of the SQL, which end with `;` at the end of a line. The comments are kept,
+/// and the empty ones are skipped.
+fn statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let (mut start, mut end) = (0, 0);
+    for line in sql.split_inclusive('\n') {
+        end += line.len();
+        if line.trim_end().ends_with(';') {
+            statements.push(sql[start..end].trim());
+            start = end;
+        }
+    }
+    statements.retain(|statement| {
+        statement
+            .lines()
+            .any(|line| !line.trim().is_empty() && !line.trim_start().starts_with("--"))
+    });
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::DbBackend;
+
+    use super::{migrations, statements};
+
+    #[test]
+    fn test_statements() {
+        let sql = "-- a comment\nCREATE TABLE a (\n  id INT -- the id\n);\n\nCREATE INDEX i ON a (id);\n-- the end\n";
+        assert_eq!(
+            statements(sql),
+            vec![
+                "-- a comment\nCREATE TABLE a (\n  id INT -- the id\n);",
+                "CREATE INDEX i ON a (id);"
+            ]
+        );
+        for backend in [DbBackend::MySql, DbBackend::Postgres, DbBackend::Sqlite] {
+            let migrations = migrations(backend);
+            assert!(migrations.windows(2).all(|m| m[0].version < m[1].version));
+            // a statement of each table
+            let sql = migrations[0].sql;
+            let tables = statements(sql)
+                .iter()
+                .filter(|statement| statement.contains("CREATE TABLE"))
+                .count();
+            assert_eq!(tables, sql.matches("CREATE TABLE").count());
+            assert!(tables >= 8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod sqlite_tests {
+    use sea_orm::{ConnectionTrait, Database, DbBackend, Statement};
+
+    use super::{migrate, migrations, pending_migrations};
+
+    #[tokio::test]
+    async fn test_migrate_fresh_database() {
+        let connection = Database::connect("sqlite::memory:").await.unwrap();
+        assert_eq!(
+            pending_migrations(&connection).await.unwrap().len(),
+            migrations(DbBackend::Sqlite).len()
+        );
+        let applied = migrate(&connection).await.unwrap();
+        assert_eq!(
+            applied,
+            migrations(DbBackend::Sqlite).iter().collect::<Vec<_>>()
+        );
+        let rows = connection
+            .query_all(Statement::from_string(
+                DbBackend::Sqlite,
+                "SELECT name FROM sqlite_master WHERE type = 'table'",
+            ))
+            .await
+            .unwrap();
+        let tables: Vec<String> = rows
+            .iter()
+            .map(|row| row.try_get("", "name").unwrap())
+            .collect();
+        for table in [
+            "commit",
+            "node",
+            "refs",
+            "mr",
+            "git_obj",
+            "repo_config",
+            "symrefs",
+            "mega_migrations",
+        ] {
+            assert!(
+                tables.iter().any(|t| t == table),
+                "{} in {:?}",
+                table,
+                tables
+            );
+        }
+        assert!(pending_migrations(&connection).await.unwrap().is_empty());
+        assert!(migrate(&connection).await.unwrap().is_empty());
+    }
+}
+
+/// These tests need a Postgres database of the `MEGA_DB_POSTGRESQL_URL` env, of which the user
+/// can create the databases.
+#[cfg(all(test, feature = "postgres"))]
+mod postgres_tests {
+    use sea_orm::{ConnectionTrait, Database, DbBackend, Statement};
+
+    use super::{migrate, migrations, pending_migrations};
+
+    #[tokio::test]
+    async fn test_migrate_fresh_database() {
+        let url =
+            std::env::var("MEGA_DB_POSTGRESQL_URL").expect("MEGA_DB_POSTGRESQL_URL is not set");
+        let admin = Database::connect(&url).await.unwrap();
+        let name = format!("mega_migrate_{}", std::process::id());
+        let create = format!("DROP DATABASE IF EXISTS {0}; CREATE DATABASE {0}", name);
+        for statement in create.split("; ") {
+            admin.execute_unprepared(statement).await.unwrap();
+        }
+        let (base, _) = url.rsplit_once('/').unwrap();
+        let connection = Database::connect(format!("{}/{}", base, name))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            pending_migrations(&connection).await.unwrap().len(),
+            migrations(DbBackend::Postgres).len()
+        );
+        let applied = migrate(&connection).await.unwrap();
+        assert_eq!(
+            applied,
+            migrations(DbBackend::Postgres).iter().collect::<Vec<_>>()
+        );
+        let rows = connection
+            .query_all(Statement::from_string(
+                DbBackend::Postgres,
+                "SELECT table_name FROM information_schema.tables WHERE table_schema = 'public'",
+            ))
+            .await
+            .unwrap();
+        let tables: Vec<String> = rows
+            .iter()
+            .map(|row| row.try_get("", "table_name").unwrap())
+            .collect();
+        for table in [
+            "commit",
+            "node",
+            "refs",
+            "mr",
+            "git_obj",
+            "repo_config",
+            "symrefs",
+            "mega_migrations",
+        ] {
+            assert!(
+                tables.iter().any(|t| t == table),
+                "{} in {:?}",
+                table,
+                tables
+            );
+        }
+        // and nothing to migrate again
+        assert!(pending_migrations(&connection).await.unwrap().is_empty());
+        assert!(migrate(&connection).await.unwrap().is_empty());
+
+        connection.close().await.unwrap();
+        admin
+            .execute_unprepared(&format!("DROP DATABASE {}", name))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+//! The compression of the data of the git objects saved in the database, e.g. by zstd of a
+//! high level for the cold storage. It is set for the deployment by the
+//! `MEGA_DB_OBJECT_ZSTD_LEVEL` env, and the objects sent to the clients are the same as ever.
+//!
+//! The compression of the data is recorded in the `compression` column of the object, so the
+//! data saved before the compression is switched on, or after it is switched off, is read as it
+//! is, e.g. a blob which is a `.zst` file keeps the bytes of its own.
+
+use std::env;
+
+use common::errors::MegaError;
+use entity::git_obj;
+use sea_orm::Set;
+
+/// The `compression` of the objects compressed by zstd.
+pub const ZSTD: &str = "zstd";
+
+/// The largest data of an object to decompress, of which the size is declared by the frame.
+const MAX_OBJECT_SIZE: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ObjectCompression {
+    /// The data is saved as it is
+    #[default]
+    None,
+    /// The data is compressed by zstd of the level
+    Zstd(i32),
+}
+
+impl ObjectCompression {
+    /// The compression of the `MEGA_DB_OBJECT_ZSTD_LEVEL` env, none if it is not set.
+    pub fn from_env() -> Result<ObjectCompression, MegaError> {
+        match env::var("MEGA_DB_OBJECT_ZSTD_LEVEL") {
+            Ok(level) => ObjectCompression::zstd(
+                level
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid MEGA_DB_OBJECT_ZSTD_LEVEL: {}", level))?,
+            ),
+            Err(_) => Ok(ObjectCompression::None),
+        }
+    }
+
+    /// The zstd compression of the level, which is from 1 to 22.
+    pub fn zstd(level: i32) -> Result<ObjectCompression, MegaError> {
+        if !zstd::compression_level_range().contains(&level) || level == 0 {
+            return Err(anyhow::anyhow!("invalid zstd level: {}", level).into());
+        }
+        Ok(ObjectCompression::Zstd(level))
+    }
+
+    /// The data to save of an object and the compression of it, none if it is saved as it is.
+    pub fn compress(&self, data: Vec<u8>) -> (Vec<u8>, Option<String>) {
+        match self {
+            ObjectCompression::None => (data, None),
+            ObjectCompression::Zstd(level) => match zstd::bulk::compress(&data, *level) {
+                Ok(compressed) => (compressed, Some(ZSTD.to_owned())),
+                Err(_) => (data, None),
+            },
+        }
+    }
+
+    /// The objects to save, of which the data is compressed.
+    pub fn compress_obj_data(
+        &self,
+        obj_data: Vec<git_obj::ActiveModel>,
+    ) -> Vec<git_obj::ActiveModel> {
+        if *self == ObjectCompression::None {
+            return obj_data;
+        }
+        obj_data
+            .into_iter()
+            .map(|mut model| {
+                let (data, compression) = self.compress(model.data.take().unwrap_or_default());
+                model.data = Set(data);
+                model.compression = Set(compression);
+                model
+            })
+            .collect()
+    }
+}
+
+/// The object of which the data is decompressed by the compression it was saved with.
+pub fn decompress(mut model: git_obj::Model) -> Result<git_obj::Model, MegaError> {
+    match model.compression.as_deref() {
+        None => return Ok(model),
+        Some(ZSTD) => {}
+        Some(compression) => {
+            return Err(anyhow::anyhow!(
+                "unknown compression of object {}: {}",
+                model.git_id,
+                compression
+            )
+            .into())
+        }
+    }
+    let size = match zstd::zstd_safe::get_frame_content_size(&model.data) {
+        Ok(Some(size)) if size <= MAX_OBJECT_SIZE => size,
+        _ => {
+            return Err(
+                anyhow::anyhow!("invalid zstd frame size of object {}", model.git_id).into(),
+            )
+        }
+    };
+    model.data = zstd::bulk::decompress(&model.data, size as usize)
+        .map_err(|e| anyhow::anyhow!("failed to decompress object {}: {}", model.git_id, e))?;
+    model.compression = None;
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::git_obj;
+
+    use super::{decompress, ObjectCompression, MAX_OBJECT_SIZE, ZSTD};
+
+    fn blob(data: &[u8], compression: Option<&str>) -> git_obj::Model {
+        git_obj::Model {
+            id: 1,
+            git_id: "8ab686eafeb1f44702738c8b0f24f2567c36da6d".to_owned(),
+            object_type: "blob".to_owned(),
+            data: data.to_vec(),
+            compression: compression.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let data = "the compressible line of a blob\n"
+            .repeat(1000)
+            .into_bytes();
+        let compression = ObjectCompression::zstd(19).unwrap();
+        let (compressed, compression) = compression.compress(data.clone());
+        assert_eq!(compression.as_deref(), Some(ZSTD));
+        assert!(compressed.len() < data.len() / 10);
+        let model = decompress(blob(&compressed, Some(ZSTD))).unwrap();
+        assert_eq!(model.data, data);
+        assert_eq!(model.compression, None);
+
+        // saved before the compression
+        assert_eq!(decompress(blob(&data, None)).unwrap().data, data);
+    }
+
+    #[test]
+    fn test_zstd_blob_saved_as_it_is() {
+        // a `.zst` file of the repo is not the compressed data of the object
+        let file = zstd::bulk::compress(b"a zstd file", 3).unwrap();
+        assert_eq!(decompress(blob(&file, None)).unwrap().data, file);
+
+        let (compressed, _) = ObjectCompression::zstd(3).unwrap().compress(file.clone());
+        assert_eq!(
+            decompress(blob(&compressed, Some(ZSTD))).unwrap().data,
+            file
+        );
+    }
+
+    #[test]
+    fn test_zstd_invalid_frame() {
+        assert!(decompress(blob(b"not a frame", Some(ZSTD))).is_err());
+        assert!(decompress(blob(b"x", Some("lz4"))).is_err());
+
+        // the frame of which the declared size is larger than the max is not decompressed
+        let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd, 0xe0];
+        frame.extend((MAX_OBJECT_SIZE + 1).to_le_bytes());
+        frame.extend([0x01, 0x00, 0x00]);
+        assert_eq!(
+            zstd::zstd_safe::get_frame_content_size(&frame).unwrap(),
+            Some(MAX_OBJECT_SIZE + 1)
+        );
+        assert!(decompress(blob(&frame, Some(ZSTD))).is_err());
+    }
+
+    #[test]
+    fn test_zstd_level() {
+        assert!(ObjectCompression::zstd(0).is_err());
+        assert!(ObjectCompression::zstd(23).is_err());
+        assert_eq!(
+            ObjectCompression::None.compress(b"x".to_vec()),
+            (b"x".to_vec(), None)
+        );
+    }
+}
@@ -10,7 +10,7 @@ pub fn set_up_options() -> Result<(), OptionError> {
 
     // Get the option from the id generator instance.
     let options = IdInstance::get_options();
-    println!("First setting: {:?}", options);
+    tracing::debug!(?options, "Set up the id generator");
     Ok(())
 }
 
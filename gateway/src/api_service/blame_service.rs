@@ -0,0 +1,305 @@
+//! The blame of the files like `git blame`, which tells the commit changing each line last.
+//!
+//! The lines are passed from a commit to each parent of which the lines of the file are the same
+//! by the diff of them, and the ones left are of the commit. The commits are walked from the
+//! newest by the time, so the lines from the children of a merge are passed together. The path
+//! of the file isn't followed across the renames.
+//!
+//! A blame is costly of a long history, while it never changes of the commit and the path, so
+//! the recent ones are cached.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use axum::http::StatusCode;
+use axum::response::Json;
+use git::hash::Hash;
+use git::internal::object::commit::Commit;
+use git::internal::object::tree::TreeItemMode;
+use lru::LruCache;
+
+use crate::api_service::browse_service::{person, BrowseService};
+use crate::api_service::diff_service::{edit_script, lines, Edit};
+use crate::model::browse::{BlameDetail, BlameLine};
+
+/// The blames kept by default.
+const BLAME_CACHE_SIZE: usize = 256;
+
+/// The recent blames of the commits and the paths.
+pub struct BlameCache {
+    blames: Mutex<LruCache<(Hash, String), Arc<BlameDetail>>>,
+}
+
+impl Default for BlameCache {
+    fn default() -> Self {
+        BlameCache::new(BLAME_CACHE_SIZE)
+    }
+}
+
+impl BlameCache {
+    /// The cache of at most the `capacity` blames, at least one.
+    pub fn new(capacity: usize) -> BlameCache {
+        BlameCache {
+            blames: Mutex::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+        }
+    }
+
+    pub fn get(&self, commit: &Hash, path: &str) -> Option<Arc<BlameDetail>> {
+        self.blames
+            .lock()
+            .unwrap()
+            .get(&(*commit, path.to_owned()))
+            .cloned()
+    }
+
+    pub fn insert(&self, commit: Hash, path: &str, blame: BlameDetail) -> Arc<BlameDetail> {
+        let blame = Arc::new(blame);
+        self.blames
+            .lock()
+            .unwrap()
+            .put((commit, path.to_owned()), blame.clone());
+        blame
+    }
+}
+
+/// A commit to walk of the blob of the file, and the lines left like `(line, original_line)`
+/// of the indexes of the blamed file and the blob.
+struct Pending {
+    commit: Commit,
+    blob: Hash,
+    lines: Vec<(usize, usize)>,
+}
+
+impl BrowseService {
+    /// The blame of the file of the path at the ref like `main/src/lib.rs`.
+    pub async fn get_blame(
+        &self,
+        repo_path: &str,
+        spec: &str,
+    ) -> Result<Json<BlameDetail>, (StatusCode, String)> {
+        let (commit, path) = self.resolve(repo_path, spec).await?;
+        let path = path.trim_end_matches('/');
+        if let Some(blame) = self.blames.get(&commit, path) {
+            return Ok(Json(BlameDetail::clone(&blame)));
+        }
+        let blame = self.blame(commit, path).await?;
+        let blame = self.blames.insert(commit, path, blame);
+        Ok(Json(BlameDetail::clone(&blame)))
+    }
+
+    async fn blame(&self, tip: Hash, path: &str) -> Result<BlameDetail, (StatusCode, String)> {
+        let commit = self.read_commit(&tip).await?;
+        let Some(blob) = self.file(&commit, path).await? else {
+            return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+        };
+        let data = self.read(&blob).await?.data;
+        let contents = lines(&data);
+        let mut owners = vec![(tip, 0); contents.len()];
+        let mut authors = HashMap::new();
+        let mut queue = BinaryHeap::from([(commit.committer.timestamp, tip)]);
+        let mut pending = HashMap::from([(
+            tip,
+            Pending {
+                commit,
+                blob,
+                lines: (0..contents.len()).map(|i| (i, i)).collect(),
+            },
+        )]);
+        while let Some((_, id)) = queue.pop() {
+            let Some(Pending {
+                commit,
+                blob,
+                lines: mut left,
+            }) = pending.remove(&id)
+            else {
+                continue;
+            };
+            let data = self.read(&blob).await?.data;
+            for parent_id in &commit.parent_tree_ids {
+                if left.is_empty() {
+                    break;
+                }
+                let parent = match pending.get(parent_id) {
+                    Some(parent) => (parent.blob, None),
+                    None => {
+                        let parent = self.read_commit(parent_id).await?;
+                        match self.file(&parent, path).await? {
+                            Some(blob) => (blob, Some(parent)),
+                            None => continue,
+                        }
+                    }
+                };
+                let passed = if parent.0 == blob {
+                    std::mem::take(&mut left)
+                } else {
+                    let parent_data = self.read(&parent.0).await?.data;
+                    let mut origins = HashMap::new();
+                    for edit in edit_script(&lines(&parent_data), &lines(&data)) {
+                        if let Edit::Equal(old, new) = edit {
+                            origins.insert(new, old);
+                        }
+                    }
+                    let mut passed = Vec::new();
+                    left.retain(|&(line, original)| match origins.get(&original) {
+                        Some(&old) => {
+                            passed.push((line, old));
+                            false
+                        }
+                        None => true,
+                    });
+                    passed
+                };
+                if passed.is_empty() {
+                    continue;
+                }
+                match pending.entry(*parent_id) {
+                    Entry::Occupied(mut entry) => entry.get_mut().lines.extend(passed),
+                    Entry::Vacant(entry) => {
+                        let commit = match parent.1 {
+                            Some(commit) => commit,
+                            None => self.read_commit(parent_id).await?,
+                        };
+                        queue.push((commit.committer.timestamp, *parent_id));
+                        entry.insert(Pending {
+                            commit,
+                            blob: parent.0,
+                            lines: passed,
+                        });
+                    }
+                }
+            }
+            if !left.is_empty() {
+                authors
+                    .entry(id)
+                    .or_insert_with(|| person(commit.author.clone()));
+            }
+            for (line, original) in left {
+                owners[line] = (id, original);
+            }
+        }
+        let lines =
+            contents
+                .iter()
+                .zip(owners)
+                .enumerate()
+                .map(|(i, (content, (commit, original)))| BlameLine {
+                    line: i + 1,
+                    commit: commit.to_plain_str(),
+                    author: authors[&commit].clone(),
+                    original_line: original + 1,
+                    content: String::from_utf8_lossy(
+                        content.strip_suffix(b"\n").unwrap_or(content),
+                    )
+                    .into_owned(),
+                });
+        Ok(BlameDetail {
+            commit: tip.to_plain_str(),
+            path: path.to_owned(),
+            lines: lines.collect(),
+        })
+    }
+
+    /// The blob of the file of the path in the commit, none if it's not a file.
+    async fn file(
+        &self,
+        commit: &Commit,
+        path: &str,
+    ) -> Result<Option<Hash>, (StatusCode, String)> {
+        Ok(match self.walk(commit.tree_id, path).await? {
+            Some((TreeItemMode::Blob | TreeItemMode::BlobExecutable | TreeItemMode::Link, id)) => {
+                Some(id)
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::StatusCode;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+
+    use crate::api_service::browse_service::tests::{commit, service, tree};
+    use crate::lfs_gc::tests::save;
+
+    #[tokio::test]
+    async fn test_get_blame() {
+        let storage = Arc::new(MemoryStorage::new());
+        let v1 = save(&storage, "blob", b"a\nb\nc\n".to_vec()).await;
+        let v2 = save(&storage, "blob", b"new\na\nB\nc\n".to_vec()).await;
+        let other = save(&storage, "blob", b"other\n".to_vec()).await;
+        let tree1 = tree(&storage, &[("100644", "f.txt", v1)]).await;
+        let tree2 = tree(
+            &storage,
+            &[("100644", "f.txt", v1), ("100644", "g.txt", other)],
+        )
+        .await;
+        let tree3 = tree(
+            &storage,
+            &[("100644", "f.txt", v2), ("100644", "g.txt", other)],
+        )
+        .await;
+        let c1 = commit(&storage, tree1, &[], 0, "add f").await;
+        // not changing the file
+        let c2 = commit(&storage, tree2, &[c1], 1, "add g").await;
+        let c3 = commit(&storage, tree3, &[c2], 2, "change f").await;
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &c3.to_plain_str())
+            .await
+            .unwrap();
+        let service = service(&storage, std::env::temp_dir().join("mega_blame"));
+
+        let blame = service
+            .get_blame("/projects/mega", "main/f.txt")
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(blame.commit, c3.to_plain_str());
+        let lines: Vec<_> = blame
+            .lines
+            .iter()
+            .map(|l| {
+                (
+                    l.line,
+                    l.commit.clone(),
+                    l.original_line,
+                    l.content.as_str(),
+                )
+            })
+            .collect();
+        let (c1, c3) = (c1.to_plain_str(), c3.to_plain_str());
+        assert_eq!(
+            lines,
+            [
+                (1, c3.clone(), 1, "new"),
+                (2, c1.clone(), 1, "a"),
+                (3, c3.clone(), 3, "B"),
+                (4, c1.clone(), 3, "c"),
+            ]
+        );
+        assert_eq!(blame.lines[0].author.timestamp, 1700000002);
+        assert!(service
+            .blames
+            .get(&git::hash::Hash::new_from_str(&c3), "f.txt")
+            .is_some());
+
+        let blame = service
+            .get_blame("/projects/mega", &format!("{}/f.txt", c1))
+            .await
+            .unwrap()
+            .0;
+        assert!(blame.lines.iter().all(|l| l.commit == c1));
+
+        for spec in ["main/none.txt", "main", "main/f.txt/a"] {
+            let err = service.get_blame("/projects/mega", spec).await.unwrap_err();
+            assert_eq!(err.0, StatusCode::NOT_FOUND, "{}", spec);
+        }
+    }
+}
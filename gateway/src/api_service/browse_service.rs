@@ -0,0 +1,927 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::body::StreamBody;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+
+use database::driver::lfs::storage::LfsStorage;
+use database::driver::lfs::structs::RequestVars;
+use database::driver::ObjectStorage;
+use entity::git_obj;
+use git::hash::Hash;
+use git::internal::commit_graph::CommitGraphCache;
+use git::internal::object::commit::Commit;
+use git::internal::object::signature::Signature;
+use git::internal::object::tag::Tag;
+use git::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use git::internal::object::ObjectT;
+use git::lfs::pointer::pointer_oid;
+use git::protocol::symref::HEAD;
+use git::protocol::{PackProtocol, Protocol};
+
+use crate::api_service::blame_service::BlameCache;
+use crate::api_service::repo_service::{self, failed, RepoService};
+use crate::model::browse::{CommitDetail, CommitList, Person, TagDetail, TreeDetail, TreeEntry};
+use crate::model::query::CommitQuery;
+
+/// The header of the type of the object served, e.g. `blob`.
+pub const OBJECT_TYPE_HEADER: &str = "X-Git-Object-Type";
+
+/// The parts of the API of a repo after the name of it, e.g. `objects` of
+/// `projects/mega/objects/:oid`.
+const KEYWORDS: &[&str] = &[
+    "blame", "commits", "diff", "objects", "raw", "search", "tree",
+];
+
+/// The browsing of the objects of the repos without a clone, e.g. of a web UI.
+#[derive(Clone)]
+pub struct BrowseService {
+    pub storage: Arc<dyn ObjectStorage>,
+    /// The content of the LFS objects of which the files are the pointers
+    pub lfs_storage: Arc<dyn LfsStorage>,
+    /// The graphs the history is walked by, which are shared with the fetches
+    pub commit_graphs: Arc<CommitGraphCache>,
+    pub blames: Arc<BlameCache>,
+}
+
+impl BrowseService {
+    /// The object of the repo like `git cat-file`, of which the type is in the
+    /// [`OBJECT_TYPE_HEADER`]. A tree, a commit or a tag is parsed to the JSON of the `json`,
+    /// and a blob is always the raw data.
+    pub async fn get_object(
+        &self,
+        repo_path: &str,
+        oid: &str,
+        json: bool,
+    ) -> Result<Response, (StatusCode, String)> {
+        if !is_object_id(oid) {
+            return Err((StatusCode::BAD_REQUEST, "Invalid object id".to_string()));
+        }
+        self.check_repo(repo_path).await?;
+        let object = self
+            .storage
+            .get_obj_data_by_id(oid)
+            .await
+            .map_err(failed("Failed to read the object"))?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Object not found".to_string()))?;
+        let headers = [(OBJECT_TYPE_HEADER, object.object_type.clone())];
+        if !json || object.object_type == "blob" {
+            let content_type = [(CONTENT_TYPE, "application/octet-stream")];
+            return Ok((headers, content_type, object.data).into_response());
+        }
+        let json = match object.object_type.as_str() {
+            "tree" => Json(self.tree_detail(object.git_id, object.data).await?).into_response(),
+            _ => object_json(object).ok_or_else(|| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Unknown object type".to_string(),
+                )
+            })?,
+        };
+        Ok((headers, json).into_response())
+    }
+
+    /// The file of the path at the ref like `main/src/lib.rs`, which is the content of the LFS
+    /// object if it's a pointer of an uploaded one, or the entries of a directory.
+    pub async fn get_raw(
+        &self,
+        repo_path: &str,
+        spec: &str,
+    ) -> Result<Response, (StatusCode, String)> {
+        let (commit, path) = self.resolve(repo_path, spec).await?;
+        let tree = self.read_commit(&commit).await?.tree_id;
+        let (mode, id) = self
+            .walk(tree, path)
+            .await?
+            .filter(|(mode, _)| *mode != TreeItemMode::Commit)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Path not found".to_string()))?;
+        let object = self.read(&id).await?;
+        if mode == TreeItemMode::Tree {
+            return Ok(Json(self.tree_detail(object.git_id, object.data).await?).into_response());
+        }
+        let headers = [(OBJECT_TYPE_HEADER, object.object_type.clone())];
+        let content_type = [(CONTENT_TYPE, "application/octet-stream")];
+        if let Some(oid) = pointer_oid(&object.data) {
+            let vars = RequestVars {
+                oid,
+                ..Default::default()
+            };
+            // the pointer itself if the content is not uploaded
+            if let Ok(meta) = self.storage.lfs_get_meta(&vars).await {
+                let stream = self.lfs_storage.get(&meta, 0).await.map_err(|e| {
+                    tracing::error!("Failed to read the LFS object {}: {}", meta.oid, e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to read the LFS object".to_string(),
+                    )
+                })?;
+                let length = [(CONTENT_LENGTH, meta.size.to_string())];
+                return Ok((headers, content_type, length, StreamBody::new(stream)).into_response());
+            }
+        }
+        Ok((headers, content_type, object.data).into_response())
+    }
+
+    /// The entries of the directory of the path at the ref like `main/src`, of which the empty
+    /// path is the root.
+    pub async fn get_tree(
+        &self,
+        repo_path: &str,
+        spec: &str,
+    ) -> Result<Json<TreeDetail>, (StatusCode, String)> {
+        let (commit, path) = self.resolve(repo_path, spec).await?;
+        let tree = self.read_commit(&commit).await?.tree_id;
+        let id = match self.walk(tree, path).await? {
+            Some((TreeItemMode::Tree, id)) => id,
+            _ => return Err((StatusCode::NOT_FOUND, "Directory not found".to_string())),
+        };
+        let object = self.read(&id).await?;
+        Ok(Json(self.tree_detail(object.git_id, object.data).await?))
+    }
+
+    /// The entries of the tree in the order of git with the sizes of the blobs.
+    async fn tree_detail(
+        &self,
+        oid: String,
+        data: Vec<u8>,
+    ) -> Result<TreeDetail, (StatusCode, String)> {
+        let mut items = Tree::new_from_data(data).tree_items;
+        sort_items(&mut items);
+        let blobs = items
+            .iter()
+            .filter(|item| entry_type(item.mode) == "blob")
+            .map(|item| item.id.to_plain_str())
+            .collect();
+        let sizes: HashMap<String, usize> = self
+            .storage
+            .get_obj_data_by_ids(blobs)
+            .await
+            .map_err(failed("Failed to read the objects"))?
+            .into_iter()
+            .map(|object| (object.git_id, object.data.len()))
+            .collect();
+        let entries = items.into_iter().map(|item| {
+            let oid = item.id.to_plain_str();
+            TreeEntry {
+                size: sizes.get(&oid).copied(),
+                name: item.name,
+                mode: mode_string(item.mode),
+                object_type: entry_type(item.mode).to_owned(),
+                kind: entry_kind(item.mode).to_owned(),
+                oid,
+            }
+        });
+        Ok(TreeDetail {
+            oid,
+            entries: entries.collect(),
+        })
+    }
+
+    /// The history of the ref like `main` from the newest commit, of which a commit is always
+    /// before the parents, by the commit-graph of the repo, a page after the cursor each.
+    pub async fn list_commits(
+        &self,
+        repo_path: &str,
+        spec: &str,
+        query: &CommitQuery,
+    ) -> Result<Json<CommitList>, (StatusCode, String)> {
+        let tip = self.resolve_ref(repo_path, spec).await?;
+        let mut protocol = PackProtocol::new(
+            PathBuf::from(repo_path),
+            self.storage.clone(),
+            Protocol::Http,
+        );
+        protocol.commit_graphs = self.commit_graphs.clone();
+        let ids = protocol.commit_graph().await.rev_list(&[tip], &[]);
+        let start = match &query.cursor {
+            Some(cursor) => {
+                ids.iter()
+                    .position(|id| id.to_plain_str() == *cursor)
+                    .ok_or_else(|| (StatusCode::BAD_REQUEST, "Invalid cursor".to_string()))?
+                    + 1
+            }
+            None => 0,
+        };
+        let path = query
+            .path
+            .as_deref()
+            .map(|path| path.trim_matches('/'))
+            .filter(|path| !path.is_empty());
+        let mut commits: Vec<CommitDetail> = Vec::new();
+        let mut next_cursor = None;
+        for id in &ids[start..] {
+            let commit = self.read_commit(id).await?;
+            if let Some(path) = path {
+                if !self.changes(&commit, path).await? {
+                    continue;
+                }
+            }
+            if commits.len() == query.limit() {
+                next_cursor = commits.last().map(|c| c.oid.clone());
+                break;
+            }
+            commits.push(commit_detail(commit));
+        }
+        Ok(Json(CommitList {
+            commits,
+            next_cursor,
+        }))
+    }
+
+    /// The commit changes the entry of the path, i.e. it differs from the one of each parent,
+    /// or the root commit has it.
+    async fn changes(&self, commit: &Commit, path: &str) -> Result<bool, (StatusCode, String)> {
+        let entry = self.walk(commit.tree_id, path).await?;
+        if commit.parent_tree_ids.is_empty() {
+            return Ok(entry.is_some());
+        }
+        for parent in &commit.parent_tree_ids {
+            let parent = self.read_commit(parent).await?;
+            if self.walk(parent.tree_id, path).await? == entry {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The commit of the ref and the path after it of the spec like `main/src/lib.rs`, of which
+    /// the ref is the longest one matching the start, by the full name like `refs/heads/main`,
+    /// the name of a branch or a tag, `HEAD`, or the full id of a commit.
+    pub(crate) async fn resolve<'a>(
+        &self,
+        repo_path: &str,
+        spec: &'a str,
+    ) -> Result<(Hash, &'a str), (StatusCode, String)> {
+        self.check_repo(repo_path).await?;
+        let not_found = || (StatusCode::NOT_FOUND, "Ref not found".to_string());
+        let refs = self
+            .storage
+            .get_ref_object_id(repo_path)
+            .await
+            .map_err(failed("Failed to read the refs"))?;
+        let spec = spec.trim_start_matches('/');
+        let ends = spec
+            .match_indices('/')
+            .map(|(i, _)| i)
+            .chain([spec.len()])
+            .collect::<Vec<_>>();
+        let mut found = None;
+        for &end in ends.iter().rev() {
+            let name = &spec[..end];
+            let id = if name == HEAD {
+                let protocol = PackProtocol::new(
+                    PathBuf::from(repo_path),
+                    self.storage.clone(),
+                    Protocol::Http,
+                );
+                let head = protocol
+                    .head(&refs)
+                    .await
+                    .map_err(failed("Failed to read the HEAD"))?;
+                head.map(|head| head.id)
+            } else {
+                ["", "refs/heads/", "refs/tags/"].iter().find_map(|prefix| {
+                    let full_name = format!("{}{}", prefix, name);
+                    refs.iter()
+                        .find(|r| r.ref_name == full_name)
+                        .map(|r| r.ref_git_id.clone())
+                })
+            };
+            if let Some(id) = id {
+                found = Some((id, &spec[end..]));
+                break;
+            }
+        }
+        let (mut id, path) = match found {
+            Some((id, path)) => (Hash::new_from_str(&id), path.trim_start_matches('/')),
+            None => {
+                let (oid, path) = spec.split_once('/').unwrap_or((spec, ""));
+                if !is_object_id(oid) {
+                    return Err(not_found());
+                }
+                (Hash::new_from_str(oid), path)
+            }
+        };
+        // the commit of an annotated tag
+        loop {
+            let object = self.read(&id).await.map_err(|_| not_found())?;
+            match object.object_type.as_str() {
+                "commit" => return Ok((id, path)),
+                "tag" => id = Tag::new_from_data(object.data).object_hash,
+                _ => return Err(not_found()),
+            }
+        }
+    }
+
+    /// The commit of the spec of a ref without a path.
+    pub(crate) async fn resolve_ref(
+        &self,
+        repo_path: &str,
+        spec: &str,
+    ) -> Result<Hash, (StatusCode, String)> {
+        match self.resolve(repo_path, spec).await? {
+            (id, "") => Ok(id),
+            _ => Err((StatusCode::NOT_FOUND, "Ref not found".to_string())),
+        }
+    }
+
+    /// The mode and the id of the entry of the path like `src/lib.rs` in the tree, of which the
+    /// empty path is the tree itself, none if there is no such entry.
+    pub(crate) async fn walk(
+        &self,
+        tree: Hash,
+        path: &str,
+    ) -> Result<Option<(TreeItemMode, Hash)>, (StatusCode, String)> {
+        let mut entry = (TreeItemMode::Tree, tree);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if entry.0 != TreeItemMode::Tree {
+                return Ok(None);
+            }
+            let tree = self.read_tree(&entry.1).await?;
+            match tree.tree_items.into_iter().find(|item| item.name == name) {
+                Some(item) => entry = (item.mode, item.id),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(entry))
+    }
+
+    /// The object of the id, which is an error if it's not found as it's referenced.
+    pub(crate) async fn read(&self, id: &Hash) -> Result<git_obj::Model, (StatusCode, String)> {
+        let oid = id.to_plain_str();
+        self.storage
+            .get_obj_data_by_id(&oid)
+            .await
+            .map_err(failed("Failed to read the object"))?
+            .ok_or_else(|| {
+                tracing::error!("The object {} is not found", oid);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to read the object".to_string(),
+                )
+            })
+    }
+
+    pub(crate) async fn read_commit(&self, id: &Hash) -> Result<Commit, (StatusCode, String)> {
+        let mut commit = Commit::new_from_data(self.read(id).await?.data);
+        commit.id = *id;
+        Ok(commit)
+    }
+
+    pub(crate) async fn read_tree(&self, id: &Hash) -> Result<Tree, (StatusCode, String)> {
+        let mut tree = Tree::new_from_data(self.read(id).await?.data);
+        tree.id = *id;
+        Ok(tree)
+    }
+
+    /// The repo is not found if there is no ref of it, and it's not created.
+    async fn check_repo(&self, repo_path: &str) -> Result<(), (StatusCode, String)> {
+        let repo_service = RepoService {
+            storage: self.storage.clone(),
+        };
+        match repo_service.exists(repo_path).await? {
+            true => Ok(()),
+            false => Err((StatusCode::NOT_FOUND, "Repo not found".to_string())),
+        }
+    }
+}
+
+/// The path of the repo, the keyword and the rest of the path like `projects/mega/objects/:oid`.
+/// The repo is the part before the first keyword, or before the `.git/` of the name if it has
+/// one, e.g. of a repo with a directory named like a keyword.
+pub fn split_path(path: &str) -> Option<(String, &str, &str)> {
+    let path = path.trim_start_matches('/');
+    let (name, rest) = match path.split_once(".git/") {
+        Some(split) => split,
+        None => {
+            let start = path
+                .match_indices('/')
+                .map(|(i, _)| i + 1)
+                .find(|&i| KEYWORDS.contains(&path[i..].split('/').next().unwrap()))?;
+            (&path[..start - 1], &path[start..])
+        }
+    };
+    let (keyword, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    if !KEYWORDS.contains(&keyword) {
+        return None;
+    }
+    Some((repo_service::repo_path(name)?, keyword, rest))
+}
+
+fn is_object_id(oid: &str) -> bool {
+    oid.len() == 40 && oid.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The JSON of a commit or a tag.
+fn object_json(object: git_obj::Model) -> Option<Response> {
+    let oid = object.git_id;
+    let response = match object.object_type.as_str() {
+        "commit" => {
+            let mut commit = Commit::new_from_data(object.data);
+            commit.id = Hash::new_from_str(&oid);
+            Json(commit_detail(commit)).into_response()
+        }
+        "tag" => {
+            let tag = Tag::new_from_data(object.data);
+            Json(TagDetail {
+                oid,
+                object: tag.object_hash.to_plain_str(),
+                object_type: tag.object_type.to_string(),
+                tag: tag.tag_name,
+                tagger: person(tag.tagger),
+                message: tag.message.trim_start_matches('\n').to_owned(),
+            })
+            .into_response()
+        }
+        _ => return None,
+    };
+    Some(response)
+}
+
+fn commit_detail(commit: Commit) -> CommitDetail {
+    CommitDetail {
+        oid: commit.id.to_plain_str(),
+        tree: commit.tree_id.to_plain_str(),
+        parents: commit
+            .parent_tree_ids
+            .iter()
+            .map(|id| id.to_plain_str())
+            .collect(),
+        author: person(commit.author),
+        committer: person(commit.committer),
+        message: commit_message(&commit.message).to_owned(),
+    }
+}
+
+/// Sort the entries of a tree in the order of git, i.e. of the names of which a directory ends
+/// with a `/`.
+pub(crate) fn sort_items(items: &mut [TreeItem]) {
+    items.sort_by_cached_key(|item| match item.mode {
+        TreeItemMode::Tree => format!("{}/", item.name),
+        _ => item.name.clone(),
+    });
+}
+
+/// The mode like `100644` or `040000` of a tree.
+pub(crate) fn mode_string(mode: TreeItemMode) -> String {
+    format!("{:0>6}", String::from_utf8_lossy(mode.to_bytes()))
+}
+
+/// The type of the object of the entry of a tree, a symlink is a blob.
+fn entry_type(mode: TreeItemMode) -> &'static str {
+    match mode {
+        TreeItemMode::Tree => "tree",
+        TreeItemMode::Commit => "commit",
+        TreeItemMode::Blob | TreeItemMode::BlobExecutable | TreeItemMode::Link => "blob",
+    }
+}
+
+fn entry_kind(mode: TreeItemMode) -> &'static str {
+    match mode {
+        TreeItemMode::Blob => "file",
+        TreeItemMode::BlobExecutable => "executable",
+        TreeItemMode::Link => "symlink",
+        TreeItemMode::Tree => "directory",
+        TreeItemMode::Commit => "submodule",
+    }
+}
+
+pub(crate) fn person(signature: Signature) -> Person {
+    Person {
+        name: signature.name,
+        email: signature.email,
+        timestamp: signature.timestamp,
+        timezone: signature.timezone,
+    }
+}
+
+/// The message of the rest of a commit after the committer, which is after the other headers
+/// like the `gpgsig` and an empty line.
+fn commit_message(rest: &str) -> &str {
+    match rest.strip_prefix('\n') {
+        Some(message) => message,
+        None => rest.split_once("\n\n").map_or("", |(_, message)| message),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use database::driver::lfs::storage::ContentStore;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+    use git::hash::Hash;
+    use git::internal::commit_graph::CommitGraph;
+    use hyper::StatusCode;
+
+    use super::{split_path, BrowseService, OBJECT_TYPE_HEADER};
+    use crate::lfs_gc::tests::{pointer, save, upload};
+    use crate::model::browse::{CommitDetail, CommitList, TreeDetail, TreeEntry};
+    use crate::model::query::CommitQuery;
+
+    /// The service of the storage, of which the LFS objects are in the dir.
+    pub(crate) fn service(storage: &Arc<MemoryStorage>, dir: PathBuf) -> BrowseService {
+        BrowseService {
+            storage: storage.clone(),
+            lfs_storage: Arc::new(ContentStore::new(dir)),
+            commit_graphs: Arc::default(),
+            blames: Arc::default(),
+        }
+    }
+
+    /// Save the tree of the entries like `(mode, name, id)`.
+    pub(crate) async fn tree(storage: &MemoryStorage, entries: &[(&str, &str, Hash)]) -> Hash {
+        let mut data = Vec::new();
+        for (mode, name, id) in entries {
+            data.extend(format!("{} {}\0", mode, name).into_bytes());
+            data.extend(id.as_bytes());
+        }
+        save(storage, "tree", data).await
+    }
+
+    /// Save the commit of the tree and the parents, of which the time is `1700000000 + time`.
+    pub(crate) async fn commit(
+        storage: &MemoryStorage,
+        tree: Hash,
+        parents: &[Hash],
+        time: usize,
+        message: &str,
+    ) -> Hash {
+        let mut data = format!("tree {}\n", tree.to_plain_str());
+        for parent in parents {
+            data.push_str(&format!("parent {}\n", parent.to_plain_str()));
+        }
+        let author = format!("mega <mega@example.com> {} +0800", 1700000000 + time);
+        data.push_str(&format!(
+            "author {}\ncommitter {}\n\n{}\n",
+            author, author, message
+        ));
+        save(storage, "commit", data.into_bytes()).await
+    }
+
+    async fn body(resp: axum::response::Response) -> Vec<u8> {
+        hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_split_path() {
+        let oid = "1".repeat(40);
+        let path = format!("projects/mega/objects/{}", oid);
+        assert_eq!(
+            split_path(&path),
+            Some(("/projects/mega".to_owned(), "objects", oid.as_str()))
+        );
+        // a directory named like a keyword of a repo with `.git`
+        let path = format!("objects/mega.git/objects/{}", oid);
+        assert_eq!(
+            split_path(&path),
+            Some(("/objects/mega".to_owned(), "objects", oid.as_str()))
+        );
+        for path in [
+            "projects/mega",
+            "objects/1",
+            "projects/../objects/1",
+            "mega.git/refs",
+        ] {
+            assert_eq!(split_path(path), None, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_object() {
+        let storage = Arc::new(MemoryStorage::new());
+        let blob = save(&storage, "blob", b"hello\n".to_vec()).await;
+        let src = tree(&storage, &[("100755", "run.sh", blob)]).await;
+        let root = tree(
+            &storage,
+            &[("100644", "a.txt", blob), ("40000", "src", src)],
+        )
+        .await;
+        let head = commit(&storage, root, &[], 0, "init").await;
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &head.to_plain_str())
+            .await
+            .unwrap();
+        let service = service(&storage, std::env::temp_dir().join("mega_browse_object"));
+
+        let resp = service
+            .get_object("/projects/mega", &blob.to_plain_str(), true)
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()[OBJECT_TYPE_HEADER], "blob");
+        assert_eq!(body(resp).await, b"hello\n");
+
+        let resp = service
+            .get_object("/projects/mega", &root.to_plain_str(), true)
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()[OBJECT_TYPE_HEADER], "tree");
+        let tree: TreeDetail = serde_json::from_slice(&body(resp).await).unwrap();
+        assert_eq!(
+            tree.entries,
+            [
+                TreeEntry {
+                    name: "a.txt".to_owned(),
+                    mode: "100644".to_owned(),
+                    object_type: "blob".to_owned(),
+                    kind: "file".to_owned(),
+                    oid: blob.to_plain_str(),
+                    size: Some(6),
+                },
+                TreeEntry {
+                    name: "src".to_owned(),
+                    mode: "040000".to_owned(),
+                    object_type: "tree".to_owned(),
+                    kind: "directory".to_owned(),
+                    oid: src.to_plain_str(),
+                    size: None,
+                },
+            ]
+        );
+
+        let resp = service
+            .get_object("/projects/mega", &head.to_plain_str(), true)
+            .await
+            .unwrap();
+        let commit: CommitDetail = serde_json::from_slice(&body(resp).await).unwrap();
+        assert_eq!(commit.tree, root.to_plain_str());
+        assert!(commit.parents.is_empty());
+        assert_eq!(commit.author.email, "mega@example.com");
+        assert_eq!(commit.committer.timestamp, 1700000000);
+        assert_eq!(commit.message, "init\n");
+        // the raw data without the `json`
+        let resp = service
+            .get_object("/projects/mega", &head.to_plain_str(), false)
+            .await
+            .unwrap();
+        assert!(body(resp).await.starts_with(b"tree "));
+
+        let err = service
+            .get_object("/projects/mega", &"1".repeat(40), false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+        let err = service
+            .get_object("/projects/none", &blob.to_plain_str(), false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+        let err = service
+            .get_object("/projects/mega", "HEAD", false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_raw() {
+        let dir = std::env::temp_dir().join(format!("mega_browse_raw_{}", std::process::id()));
+        let storage = Arc::new(MemoryStorage::new());
+        let meta = upload(
+            &storage,
+            &ContentStore::new(dir.clone()),
+            "the large file\n",
+        )
+        .await;
+        let large = save(&storage, "blob", pointer(&meta)).await;
+        // the pointer of an object which is not uploaded
+        let unknown = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 1\n",
+            "a".repeat(64)
+        );
+        let unknown = save(&storage, "blob", unknown.into_bytes()).await;
+        let readme = save(&storage, "blob", b"# mega\n".to_vec()).await;
+        let lib = save(&storage, "blob", b"fn main() {}\n".to_vec()).await;
+        let src = tree(&storage, &[("100644", "lib.rs", lib)]).await;
+        let submodule = Hash::new_from_str(&"1".repeat(40));
+        let crates = tree(
+            &storage,
+            &[("40000", "src", src), ("160000", "vendor", submodule)],
+        )
+        .await;
+        let root = tree(
+            &storage,
+            &[
+                ("100644", "README.md", readme),
+                ("40000", "crates", crates),
+                ("100644", "large.bin", large),
+                ("100644", "unknown.bin", unknown),
+            ],
+        )
+        .await;
+        let head = commit(&storage, root, &[], 0, "init").await;
+        let old_root = tree(&storage, &[("100644", "README.md", lib)]).await;
+        let old = commit(&storage, old_root, &[], 0, "old").await;
+        let tagger = "mega <mega@example.com> 1700000000 +0800";
+        let tag = format!(
+            "object {}\ntype commit\ntag v1\ntagger {}\n\nv1\n",
+            old.to_plain_str(),
+            tagger
+        );
+        let tag = save(&storage, "tag", tag.into_bytes()).await;
+        for (name, id) in [
+            ("refs/heads/main", head),
+            ("refs/heads/feature/a", old),
+            ("refs/tags/v1", tag),
+        ] {
+            storage
+                .update_ref("/projects/mega", name, &id.to_plain_str())
+                .await
+                .unwrap();
+        }
+        let service = service(&storage, dir.clone());
+        let raw = |spec: String| {
+            let service = &service;
+            async move {
+                match service.get_raw("/projects/mega", &spec).await {
+                    Ok(resp) => (resp.status(), body(resp).await),
+                    Err((status, _)) => (status, Vec::new()),
+                }
+            }
+        };
+        let ok = |content: &[u8]| (StatusCode::OK, content.to_vec());
+
+        assert_eq!(
+            raw("main/crates/src/lib.rs".into()).await,
+            ok(b"fn main() {}\n")
+        );
+        assert_eq!(raw("HEAD/README.md".into()).await, ok(b"# mega\n"));
+        let spec = format!("{}/README.md", head.to_plain_str());
+        assert_eq!(raw(spec).await, ok(b"# mega\n"));
+        // the longest ref, and the commit of a tag
+        assert_eq!(
+            raw("feature/a/README.md".into()).await,
+            ok(b"fn main() {}\n")
+        );
+        assert_eq!(raw("v1/README.md".into()).await, ok(b"fn main() {}\n"));
+
+        // the content of the LFS object, or the pointer if it's not uploaded
+        assert_eq!(raw("main/large.bin".into()).await, ok(b"the large file\n"));
+        let (status, content) = raw("main/unknown.bin".into()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(content.starts_with(b"version https://git-lfs.github.com/spec/v1\n"));
+
+        // the entries of a directory
+        let (status, content) = raw("main/crates/".into()).await;
+        assert_eq!(status, StatusCode::OK);
+        let listing: TreeDetail = serde_json::from_slice(&content).unwrap();
+        assert_eq!(listing.oid, crates.to_plain_str());
+        let names: Vec<_> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["src", "vendor"]);
+        let (_, content) = raw("main".into()).await;
+        let listing: TreeDetail = serde_json::from_slice(&content).unwrap();
+        assert_eq!(listing.entries.len(), 4);
+
+        for spec in [
+            "main/none",
+            "main/README.md/none",
+            "main/crates/vendor",
+            "none/README.md",
+            "feature/README.md",
+        ] {
+            let (status, _) = raw(spec.into()).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", spec);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_commits() {
+        let storage = Arc::new(MemoryStorage::new());
+        let a1 = save(&storage, "blob", b"a\n".to_vec()).await;
+        let a2 = save(&storage, "blob", b"a2\n".to_vec()).await;
+        let b = save(&storage, "blob", b"b\n".to_vec()).await;
+        let tree1 = tree(&storage, &[("100644", "a.txt", a1)]).await;
+        let tree2 = tree(&storage, &[("100644", "a.txt", a1), ("100644", "b.txt", b)]).await;
+        let tree3 = tree(&storage, &[("100644", "a.txt", a2), ("100644", "b.txt", b)]).await;
+        let c1 = commit(&storage, tree1, &[], 0, "add a").await;
+        let c2 = commit(&storage, tree2, &[c1], 1, "add b").await;
+        let c3 = commit(&storage, tree3, &[c2], 2, "change a").await;
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &c3.to_plain_str())
+            .await
+            .unwrap();
+        let service = service(&storage, std::env::temp_dir().join("mega_browse_commits"));
+        service.commit_graphs.insert(
+            std::path::Path::new("/projects/mega"),
+            CommitGraph::from_commits([(c1, vec![]), (c2, vec![c1]), (c3, vec![c2])]),
+        );
+        let list = |limit: usize, cursor: Option<&str>, path: Option<&str>| {
+            let query = CommitQuery {
+                limit: Some(limit),
+                cursor: cursor.map(str::to_owned),
+                path: path.map(str::to_owned),
+            };
+            let service = &service;
+            async move { service.list_commits("/projects/mega", "main", &query).await }
+        };
+        let oids = |page: &CommitList| {
+            page.commits
+                .iter()
+                .map(|c| c.oid.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let page = list(2, None, None).await.unwrap().0;
+        assert_eq!(oids(&page), [c3.to_plain_str(), c2.to_plain_str()]);
+        assert_eq!(page.commits[0].message, "change a\n");
+        assert_eq!(page.commits[0].parents, [c2.to_plain_str()]);
+        assert_eq!(page.commits[0].author.timestamp, 1700000002);
+        assert_eq!(page.next_cursor, Some(c2.to_plain_str()));
+
+        let page = list(2, page.next_cursor.as_deref(), None).await.unwrap().0;
+        assert_eq!(oids(&page), [c1.to_plain_str()]);
+        assert_eq!(page.next_cursor, None);
+        // the page after the root commit
+        let page = list(2, Some(&c1.to_plain_str()), None).await.unwrap().0;
+        assert_eq!(page.commits, []);
+        assert_eq!(page.next_cursor, None);
+
+        // the commits changing the path
+        let page = list(10, None, Some("/a.txt")).await.unwrap().0;
+        assert_eq!(oids(&page), [c3.to_plain_str(), c1.to_plain_str()]);
+        let page = list(1, None, Some("b.txt")).await.unwrap().0;
+        assert_eq!(oids(&page), [c2.to_plain_str()]);
+        assert_eq!(page.next_cursor, None);
+
+        let err = list(2, Some("none"), None).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        let err = service
+            .list_commits("/projects/mega", "main/a.txt", &CommitQuery::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_tree() {
+        let storage = Arc::new(MemoryStorage::new());
+        let blob = save(&storage, "blob", b"hello\n".to_vec()).await;
+        let target = save(&storage, "blob", b"a.txt".to_vec()).await;
+        let submodule = Hash::new_from_str(&"1".repeat(40));
+        let dir = tree(&storage, &[("100644", "b.txt", blob)]).await;
+        // not in the order of git
+        let root = tree(
+            &storage,
+            &[
+                ("160000", "vendor", submodule),
+                ("40000", "a", dir),
+                ("100755", "run.sh", blob),
+                ("100644", "a.txt", blob),
+                ("120000", "link", target),
+                ("100644", "a-b", blob),
+            ],
+        )
+        .await;
+        let head = commit(&storage, root, &[], 0, "init").await;
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &head.to_plain_str())
+            .await
+            .unwrap();
+        let service = service(&storage, std::env::temp_dir().join("mega_browse_tree"));
+
+        let listing = service.get_tree("/projects/mega", "main").await.unwrap().0;
+        assert_eq!(listing.oid, root.to_plain_str());
+        let entries: Vec<_> = listing
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.mode.as_str(), e.kind.as_str(), e.size))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("a-b", "100644", "file", Some(6)),
+                ("a.txt", "100644", "file", Some(6)),
+                ("a", "040000", "directory", None),
+                ("link", "120000", "symlink", Some(5)),
+                ("run.sh", "100755", "executable", Some(6)),
+                ("vendor", "160000", "submodule", None),
+            ]
+        );
+        let submodule_entry = &listing.entries[5];
+        assert_eq!(submodule_entry.object_type, "commit");
+        assert_eq!(submodule_entry.oid, submodule.to_plain_str());
+
+        let listing = service
+            .get_tree("/projects/mega", "main/a/")
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(listing.oid, dir.to_plain_str());
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!(listing.entries[0].name, "b.txt");
+
+        for spec in ["main/a.txt", "main/vendor", "main/none", "dev"] {
+            let err = service.get_tree("/projects/mega", spec).await.unwrap_err();
+            assert_eq!(err.0, StatusCode::NOT_FOUND, "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! The diffs between the commits of the repos like `git diff`, of the files changed between the
+//! trees and the hunks of the lines changed of each, which the blame walks the history by as well.
+//!
+//! The lines are compared by the Myers' algorithm, and a file with a NUL in the first 8000 bytes
+//! is binary like git, which has no hunk. The renames are detected of the deleted and the added
+//! files, the same blob at first and then the most similar one of at least half of the lines.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::http::StatusCode;
+use axum::response::Json;
+use diffs::{myers, Diff};
+use git::hash::Hash;
+use git::internal::object::tree::TreeItemMode;
+
+use crate::api_service::browse_service::{mode_string, BrowseService};
+use crate::model::browse::{DiffDetail, FileDiff, Hunk};
+use crate::model::query::DiffQuery;
+
+/// The lines of the context around the changes of a hunk.
+const CONTEXT: usize = 3;
+
+/// The percent of the lines of a file renamed kept at least.
+const RENAME_SIMILARITY: usize = 50;
+
+/// The most deleted or added files of which the renames other than the exact ones are detected,
+/// like the `diff.renameLimit` of git.
+const RENAME_LIMIT: usize = 400;
+
+/// The bytes of a file looked for a NUL to tell a binary one.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// A line of the edit script from the old lines to the new ones, of the indexes of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+struct Script(Vec<Edit>);
+
+impl Diff for Script {
+    type Error = ();
+
+    fn equal(&mut self, old: usize, new: usize, len: usize) -> Result<(), ()> {
+        self.0
+            .extend((0..len).map(|i| Edit::Equal(old + i, new + i)));
+        Ok(())
+    }
+
+    fn delete(&mut self, old: usize, len: usize, _new: usize) -> Result<(), ()> {
+        self.0.extend((old..old + len).map(Edit::Delete));
+        Ok(())
+    }
+
+    fn insert(&mut self, _old: usize, new: usize, new_len: usize) -> Result<(), ()> {
+        self.0.extend((new..new + new_len).map(Edit::Insert));
+        Ok(())
+    }
+}
+
+/// The lines of the data, each with the `\n` ending it but the last one may be without.
+pub(crate) fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|b| *b == b'\n').collect()
+}
+
+pub(crate) fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// The edit script from the old lines to the new ones, of which the deletions are before the
+/// insertions of each change.
+pub(crate) fn edit_script(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    let mut script = Script(Vec::new());
+    let _ = myers::diff(&mut script, old, 0, old.len(), new, 0, new.len());
+    let mut edits = script.0;
+    let mut start = 0;
+    while start < edits.len() {
+        let end = edits[start..]
+            .iter()
+            .position(|edit| matches!(edit, Edit::Equal(..)))
+            .map_or(edits.len(), |i| start + i);
+        edits[start..end].sort_by_key(|edit| matches!(edit, Edit::Insert(_)));
+        start = end + 1;
+    }
+    edits
+}
+
+/// The hunks of the edit script with the lines of the context around the changes, of which the
+/// ones with at most twice the context between are merged.
+fn hunks(old: &[&[u8]], new: &[&[u8]], edits: &[Edit]) -> Vec<Hunk> {
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(edits.len());
+        match ranges.last_mut() {
+            Some(range) if start <= range.1 => range.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+    // the lines before each edit
+    let mut before = Vec::with_capacity(edits.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for edit in edits {
+        before.push((old_line, new_line));
+        match edit {
+            Edit::Equal(..) => (old_line, new_line) = (old_line + 1, new_line + 1),
+            Edit::Delete(_) => old_line += 1,
+            Edit::Insert(_) => new_line += 1,
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let mut lines = Vec::new();
+            let (mut old_lines, mut new_lines) = (0, 0);
+            for edit in &edits[start..end] {
+                let (prefix, line) = match *edit {
+                    Edit::Equal(i, _) => {
+                        old_lines += 1;
+                        new_lines += 1;
+                        (' ', old[i])
+                    }
+                    Edit::Delete(i) => {
+                        old_lines += 1;
+                        ('-', old[i])
+                    }
+                    Edit::Insert(i) => {
+                        new_lines += 1;
+                        ('+', new[i])
+                    }
+                };
+                let text = line.strip_suffix(b"\n");
+                lines.push(format!(
+                    "{}{}",
+                    prefix,
+                    String::from_utf8_lossy(text.unwrap_or(line))
+                ));
+                if text.is_none() {
+                    lines.push("\\ No newline at end of file".to_owned());
+                }
+            }
+            let (old_before, new_before) = before[start];
+            // a range without a line starts at the line before it like git
+            let old_start = old_before + usize::from(old_lines > 0);
+            let new_start = new_before + usize::from(new_lines > 0);
+            Hunk {
+                header: format!(
+                    "@@ -{} +{} @@",
+                    hunk_range(old_start, old_lines),
+                    hunk_range(new_start, new_lines)
+                ),
+                old_start,
+                old_lines,
+                new_start,
+                new_lines,
+                lines,
+            }
+        })
+        .collect()
+}
+
+fn hunk_range(start: usize, lines: usize) -> String {
+    match lines {
+        1 => start.to_string(),
+        _ => format!("{},{}", start, lines),
+    }
+}
+
+/// The percent of the lines kept from the old data to the new one.
+fn similarity(old: &[u8], new: &[u8]) -> usize {
+    let (old, new) = (lines(old), lines(new));
+    if old.is_empty() || new.is_empty() {
+        return 0;
+    }
+    let equal = edit_script(&old, &new)
+        .iter()
+        .filter(|edit| matches!(edit, Edit::Equal(..)))
+        .count();
+    equal * 200 / (old.len() + new.len())
+}
+
+type Entry = (TreeItemMode, Hash);
+
+/// A file changed between the trees of the path before, of the entry before and after.
+struct Change {
+    path: String,
+    old_path: Option<String>,
+    old: Option<Entry>,
+    new: Option<Entry>,
+}
+
+impl BrowseService {
+    /// The diff from the commit of the `base` to the one of the `head` of the refs like `main`,
+    /// or from the first parent of the `head`.
+    pub async fn get_diff(
+        &self,
+        repo_path: &str,
+        query: &DiffQuery,
+    ) -> Result<Json<DiffDetail>, (StatusCode, String)> {
+        let head = self.resolve_ref(repo_path, &query.head).await?;
+        let base = match &query.base {
+            Some(base) => Some(self.resolve_ref(repo_path, base).await?),
+            None => self
+                .read_commit(&head)
+                .await?
+                .parent_tree_ids
+                .first()
+                .copied(),
+        };
+        let old_tree = match base {
+            Some(base) => Some(self.read_commit(&base).await?.tree_id),
+            None => None,
+        };
+        let new_tree = self.read_commit(&head).await?.tree_id;
+        let mut changes = self.tree_changes(old_tree, Some(new_tree)).await?;
+        if query.renames {
+            changes = self.detect_renames(changes).await?;
+        }
+        let mut files = Vec::with_capacity(changes.len());
+        for change in changes {
+            files.push(self.file_diff(change).await?);
+        }
+        Ok(Json(DiffDetail {
+            base: base.map(|base| base.to_plain_str()),
+            head: head.to_plain_str(),
+            insertions: files.iter().map(|file| file.insertions).sum(),
+            deletions: files.iter().map(|file| file.deletions).sum(),
+            files,
+        }))
+    }
+
+    /// The files changed from the old tree to the new one sorted by the paths, of which a file
+    /// replaced by a directory or the reverse is a deletion and an addition. The subtrees of the
+    /// same id are skipped.
+    async fn tree_changes(
+        &self,
+        old: Option<Hash>,
+        new: Option<Hash>,
+    ) -> Result<Vec<Change>, (StatusCode, String)> {
+        let mut changes = Vec::new();
+        let mut stack = vec![(String::new(), old, new)];
+        while let Some((prefix, old, new)) = stack.pop() {
+            let mut entries: BTreeMap<String, (Option<Entry>, Option<Entry>)> = BTreeMap::new();
+            if let Some(old) = old {
+                for item in self.read_tree(&old).await?.tree_items {
+                    entries.entry(item.name).or_default().0 = Some((item.mode, item.id));
+                }
+            }
+            if let Some(new) = new {
+                for item in self.read_tree(&new).await?.tree_items {
+                    entries.entry(item.name).or_default().1 = Some((item.mode, item.id));
+                }
+            }
+            for (name, (old, new)) in entries {
+                if old == new {
+                    continue;
+                }
+                let path = format!("{}{}", prefix, name);
+                let tree = |entry: Option<Entry>| entry.filter(|e| e.0 == TreeItemMode::Tree);
+                let file = |entry: Option<Entry>| entry.filter(|e| e.0 != TreeItemMode::Tree);
+                let (old_tree, new_tree) = (tree(old), tree(new));
+                if old_tree.is_some() || new_tree.is_some() {
+                    stack.push((
+                        format!("{}/", path),
+                        old_tree.map(|e| e.1),
+                        new_tree.map(|e| e.1),
+                    ));
+                }
+                let (old, new) = (file(old), file(new));
+                if old.is_some() || new.is_some() {
+                    changes.push(Change {
+                        path,
+                        old_path: None,
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(changes)
+    }
+
+    /// Pair the deleted files with the added ones renamed from them, of the same blob at first,
+    /// and then of the most similar text.
+    async fn detect_renames(
+        &self,
+        changes: Vec<Change>,
+    ) -> Result<Vec<Change>, (StatusCode, String)> {
+        let file = |entry: &Option<Entry>| entry.filter(|e| e.0 != TreeItemMode::Commit);
+        let (mut deleted, mut added) = (Vec::new(), Vec::new());
+        let mut others = Vec::new();
+        for change in changes {
+            match (
+                file(&change.old),
+                file(&change.new),
+                &change.old,
+                &change.new,
+            ) {
+                (Some(_), None, _, None) => deleted.push(Some(change)),
+                (None, Some(_), None, _) => added.push(change),
+                _ => others.push(change),
+            }
+        }
+        let mut renamed = Vec::new();
+        let mut rest = Vec::new();
+        let mut by_id: HashMap<Hash, Vec<usize>> = HashMap::new();
+        for (i, change) in deleted.iter().enumerate().rev() {
+            let id = change.as_ref().unwrap().old.unwrap().1;
+            by_id.entry(id).or_default().push(i);
+        }
+        for change in added {
+            let id = change.new.unwrap().1;
+            match by_id.get_mut(&id).and_then(|indexes| indexes.pop()) {
+                Some(i) => renamed.push((deleted[i].take().unwrap(), change)),
+                None => rest.push(change),
+            }
+        }
+        let mut added = Vec::new();
+        let candidates = deleted.iter().flatten().count();
+        if candidates > 0 && candidates <= RENAME_LIMIT && rest.len() <= RENAME_LIMIT {
+            let mut old_data = HashMap::new();
+            for change in deleted.iter().flatten() {
+                let id = change.old.unwrap().1;
+                let data = self.read(&id).await?.data;
+                if !is_binary(&data) {
+                    old_data.insert(id, data);
+                }
+            }
+            for change in rest {
+                let data = self.read(&change.new.unwrap().1).await?.data;
+                let best = match is_binary(&data) {
+                    true => None,
+                    false => deleted
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, old)| {
+                            let old = old_data.get(&old.as_ref()?.old.unwrap().1)?;
+                            Some((similarity(old, &data), i))
+                        })
+                        .filter(|(score, _)| *score >= RENAME_SIMILARITY)
+                        .max_by_key(|(score, i)| (*score, std::cmp::Reverse(*i))),
+                };
+                match best {
+                    Some((_, i)) => renamed.push((deleted[i].take().unwrap(), change)),
+                    None => added.push(change),
+                }
+            }
+        } else {
+            added = rest;
+        }
+        others.extend(deleted.into_iter().flatten());
+        others.extend(added);
+        others.extend(renamed.into_iter().map(|(old, new)| Change {
+            path: new.path,
+            old_path: Some(old.path),
+            old: old.old,
+            new: new.new,
+        }));
+        others.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(others)
+    }
+
+    /// The diff of the lines of a file changed, none of a binary file or a submodule.
+    async fn file_diff(&self, change: Change) -> Result<FileDiff, (StatusCode, String)> {
+        let status = match (&change.old, &change.new, &change.old_path) {
+            (_, _, Some(_)) => "renamed",
+            (None, _, _) => "added",
+            (_, None, _) => "deleted",
+            _ => "modified",
+        };
+        let mut data = Vec::new();
+        for entry in [&change.old, &change.new] {
+            data.push(match entry {
+                Some((TreeItemMode::Commit, _)) => None,
+                Some((_, id)) => Some(self.read(id).await?.data),
+                None => Some(Vec::new()),
+            });
+        }
+        let (old_data, new_data) = (data[0].as_deref(), data[1].as_deref());
+        let binary = old_data.is_some_and(is_binary) || new_data.is_some_and(is_binary);
+        let mut diff = FileDiff {
+            path: change.path,
+            old_path: change.old_path,
+            status: status.to_owned(),
+            old_oid: change.old.map(|e| e.1.to_plain_str()),
+            new_oid: change.new.map(|e| e.1.to_plain_str()),
+            old_mode: change.old.map(|e| mode_string(e.0)),
+            new_mode: change.new.map(|e| mode_string(e.0)),
+            binary,
+            insertions: 0,
+            deletions: 0,
+            hunks: Vec::new(),
+        };
+        if let (Some(old_data), Some(new_data), false) = (old_data, new_data, binary) {
+            let (old, new) = (lines(old_data), lines(new_data));
+            let edits = edit_script(&old, &new);
+            for edit in &edits {
+                match edit {
+                    Edit::Delete(_) => diff.deletions += 1,
+                    Edit::Insert(_) => diff.insertions += 1,
+                    Edit::Equal(..) => {}
+                }
+            }
+            diff.hunks = hunks(&old, &new, &edits);
+        }
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+
+    use super::{edit_script, hunks, lines, Edit};
+    use crate::api_service::browse_service::tests::{commit, service, tree};
+    use crate::lfs_gc::tests::save;
+    use crate::model::query::DiffQuery;
+
+    #[test]
+    fn test_hunks() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n".as_slice();
+        let new = b"1\n2\ntwo\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n17".as_slice();
+        let (old, new) = (lines(old), lines(new));
+        let edits = edit_script(&old, &new);
+        assert_eq!(edits[2..4], [Edit::Delete(2), Edit::Insert(2)]);
+        let found = hunks(&old, &new, &edits);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].header, "@@ -1,6 +1,6 @@");
+        assert_eq!(found[0].lines, [" 1", " 2", "-3", "+two", " 4", " 5", " 6"]);
+        assert_eq!(found[1].header, "@@ -14,3 +14,4 @@");
+        assert_eq!(
+            found[1].lines,
+            [" 14", " 15", " 16", "+17", "\\ No newline at end of file"]
+        );
+        // a new file
+        let found = hunks(&[], &new[..1], &edit_script(&[], &new[..1]));
+        assert_eq!(found[0].header, "@@ -0,0 +1 @@");
+    }
+
+    #[tokio::test]
+    async fn test_get_diff() {
+        let storage = Arc::new(MemoryStorage::new());
+        let readme = save(&storage, "blob", b"# mega\n\nmonorepo\n".to_vec()).await;
+        let changed = save(&storage, "blob", b"# mega\n\nthe monorepo\n".to_vec()).await;
+        let code = b"fn main() {\n    println!(\"mega\");\n}\n".repeat(2);
+        let lib = save(&storage, "blob", code.clone()).await;
+        let mut moved_code = code.clone();
+        moved_code.extend(b"// moved\n");
+        let moved = save(&storage, "blob", moved_code).await;
+        let logo = save(&storage, "blob", b"\x89PNG\0\x01".to_vec()).await;
+        let src = tree(&storage, &[("100644", "lib.rs", lib)]).await;
+        let root1 = tree(
+            &storage,
+            &[("100644", "README.md", readme), ("40000", "src", src)],
+        )
+        .await;
+        let c1 = commit(&storage, root1, &[], 0, "init").await;
+        let crates = tree(&storage, &[("100644", "lib.rs", moved)]).await;
+        let root2 = tree(
+            &storage,
+            &[
+                ("100644", "README.md", changed),
+                ("40000", "crates", crates),
+                ("100644", "logo.png", logo),
+            ],
+        )
+        .await;
+        let c2 = commit(&storage, root2, &[c1], 1, "change").await;
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &c2.to_plain_str())
+            .await
+            .unwrap();
+        let service = service(&storage, std::env::temp_dir().join("mega_diff"));
+        let diff = |base: Option<&str>, renames: bool| {
+            let query = DiffQuery {
+                base: base.map(str::to_owned),
+                head: "main".to_owned(),
+                renames,
+            };
+            let service = &service;
+            async move { service.get_diff("/projects/mega", &query).await.unwrap().0 }
+        };
+
+        let detail = diff(None, false).await;
+        assert_eq!(detail.base, Some(c1.to_plain_str()));
+        let files: Vec<_> = detail
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.status.as_str()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("README.md", "modified"),
+                ("crates/lib.rs", "added"),
+                ("logo.png", "added"),
+                ("src/lib.rs", "deleted"),
+            ]
+        );
+        let readme = &detail.files[0];
+        assert_eq!((readme.insertions, readme.deletions), (1, 1));
+        assert_eq!(readme.hunks[0].header, "@@ -1,3 +1,3 @@");
+        assert_eq!(
+            readme.hunks[0].lines,
+            [" # mega", " ", "-monorepo", "+the monorepo"]
+        );
+        let logo = &detail.files[2];
+        assert!(logo.binary);
+        assert_eq!(logo.new_mode.as_deref(), Some("100644"));
+        assert_eq!(logo.hunks, []);
+        assert_eq!((logo.insertions, logo.deletions), (0, 0));
+        assert_eq!(detail.files[1].insertions, 7);
+        assert_eq!(detail.files[3].old_oid, Some(lib.to_plain_str()));
+        assert_eq!((detail.insertions, detail.deletions), (8, 7));
+
+        // the file moved and changed
+        let detail = diff(Some(&c1.to_plain_str()), true).await;
+        assert_eq!(detail.files.len(), 3);
+        let moved = &detail.files[1];
+        assert_eq!(moved.path, "crates/lib.rs");
+        assert_eq!(moved.old_path.as_deref(), Some("src/lib.rs"));
+        assert_eq!(moved.status, "renamed");
+        assert_eq!((moved.insertions, moved.deletions), (1, 0));
+        assert_eq!(moved.hunks[0].lines.last().unwrap(), "+// moved");
+        assert_eq!((detail.insertions, detail.deletions), (2, 1));
+
+        // the root commit
+        let service = &service;
+        let query = DiffQuery {
+            base: None,
+            head: c1.to_plain_str(),
+            renames: false,
+        };
+        let detail = service.get_diff("/projects/mega", &query).await.unwrap().0;
+        assert_eq!(detail.base, None);
+        assert!(detail.files.iter().all(|f| f.status == "added"));
+        assert_eq!(detail.files.len(), 2);
+    }
+}
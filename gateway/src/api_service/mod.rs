@@ -1 +1,6 @@
-pub mod obj_service;
\ No newline at end of file
+pub mod blame_service;
+pub mod browse_service;
+pub mod diff_service;
+pub mod obj_service;
+pub mod repo_service;
+pub mod search_service;
@@ -0,0 +1,433 @@
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::response::Json;
+
+use common::errors::MegaError;
+use database::driver::lfs::storage::LfsStorage;
+use database::driver::ObjectStorage;
+use git::hash::Hash;
+use git::protocol::symref::branch_name;
+use git::protocol::{PackProtocol, Protocol};
+
+use crate::lfs_gc;
+use crate::model::query::PageQuery;
+use crate::model::repo::{RefList, Repo, RepoDeleted, RepoHead, RepoList};
+
+/// The listing, the creation, the default branches and the deletion of the repos, e.g. of a
+/// dashboard.
+pub struct RepoService {
+    pub storage: Arc<dyn ObjectStorage>,
+}
+
+impl RepoService {
+    /// The repos of which there is any ref or which are created, by the name.
+    pub async fn list_repos(
+        &self,
+        query: &PageQuery,
+    ) -> Result<Json<RepoList>, (StatusCode, String)> {
+        let refs = self
+            .storage
+            .get_all_refs()
+            .await
+            .map_err(failed("Failed to read the refs"))?;
+        let directories = self
+            .storage
+            .get_repo_directories()
+            .await
+            .map_err(failed("Failed to read the refs"))?;
+        let names: BTreeSet<String> = refs
+            .into_iter()
+            .map(|r| r.repo_path)
+            .chain(directories.into_iter().map(|d| d.full_path))
+            .collect();
+        let (names, next_cursor) = query.page(names.into_iter().collect(), |name| name.as_str());
+        Ok(Json(RepoList {
+            repos: names.into_iter().map(|name| Repo { name }).collect(),
+            next_cursor,
+        }))
+    }
+
+    /// The refs of the repo like `/projects/mega` by the name.
+    pub async fn list_refs(
+        &self,
+        repo_path: &str,
+        query: &PageQuery,
+    ) -> Result<Json<RefList>, (StatusCode, String)> {
+        let mut refs = self
+            .storage
+            .get_ref_object_id(repo_path)
+            .await
+            .map_err(failed("Failed to read the refs"))?;
+        if refs.is_empty() {
+            return Err((StatusCode::NOT_FOUND, "Repo not found".to_string()));
+        }
+        refs.sort_by(|a, b| a.ref_name.cmp(&b.ref_name));
+        let (refs, next_cursor) = query.page(refs, |r| r.ref_name.as_str());
+        Ok(Json(RefList {
+            refs: refs
+                .into_iter()
+                .map(|r| (r.ref_name, r.ref_git_id))
+                .collect(),
+            next_cursor,
+        }))
+    }
+
+    /// Create the empty repo of the path like `/projects/mega`, with the directories of it like
+    /// the first push does. It's a conflict if the repo or a directory of the path exists.
+    pub async fn create_repo(&self, repo_path: &str) -> Result<Json<Repo>, (StatusCode, String)> {
+        if self.exists(repo_path).await? {
+            return Err((StatusCode::CONFLICT, "Repo already exists".to_string()));
+        }
+        PackProtocol::new(
+            PathBuf::from(repo_path),
+            self.storage.clone(),
+            Protocol::Http,
+        )
+        .handle_directory()
+        .await
+        .map_err(|e| failed("Failed to create the repo")(MegaError::new(e.into(), 1)))?;
+        Ok(Json(Repo {
+            name: repo_path.to_owned(),
+        }))
+    }
+
+    /// Point the `HEAD` of the repo to the branch of the name like `main`, which needn't be
+    /// pushed yet, so a clone checks it out.
+    pub async fn set_head(
+        &self,
+        repo_path: &str,
+        name: &str,
+    ) -> Result<Json<RepoHead>, (StatusCode, String)> {
+        let Some(target) = branch_name(name) else {
+            return Err((StatusCode::BAD_REQUEST, "Invalid branch name".to_string()));
+        };
+        if !self.exists(repo_path).await? {
+            return Err((StatusCode::NOT_FOUND, "Repo not found".to_string()));
+        }
+        PackProtocol::new(
+            PathBuf::from(repo_path),
+            self.storage.clone(),
+            Protocol::Http,
+        )
+        .set_head(&target)
+        .await
+        .map_err(failed("Failed to save the HEAD"))?;
+        Ok(Json(RepoHead { ref_name: target }))
+    }
+
+    /// Delete the repo with the objects and the LFS objects of it which are not reachable from
+    /// the refs of any other repo, like the LFS gc. The objects of a push to another repo during
+    /// the deletion may be removed if they are sent by none of the pushes, so it's better done
+    /// when the repos sharing the objects, e.g. the forks, are not pushed.
+    pub async fn delete_repo(
+        &self,
+        repo_path: &str,
+        lfs_storage: &dyn LfsStorage,
+    ) -> Result<Json<RepoDeleted>, (StatusCode, String)> {
+        if !self.exists(repo_path).await? {
+            return Err((StatusCode::NOT_FOUND, "Repo not found".to_string()));
+        }
+        let failed = failed("Failed to delete the repo");
+        let storage = self.storage.as_ref();
+        let tips: VecDeque<Hash> = storage
+            .get_ref_object_id(repo_path)
+            .await
+            .map_err(&failed)?
+            .iter()
+            .map(|r| Hash::new_from_str(&r.ref_git_id))
+            .collect();
+        let (shared, shared_oids) = lfs_gc::reachable(storage, Some(repo_path))
+            .await
+            .map_err(&failed)?;
+        let mut seen = shared.clone();
+        let mut oids = HashSet::new();
+        lfs_gc::walk(storage, tips, &mut seen, &mut oids)
+            .await
+            .map_err(&failed)?;
+        let objects: Vec<String> = seen
+            .difference(&shared)
+            .map(|hash| hash.to_plain_str())
+            .collect();
+
+        storage.delete_repo(repo_path).await.map_err(&failed)?;
+        storage
+            .delete_obj_data(objects.clone())
+            .await
+            .map_err(&failed)?;
+        let metas = storage
+            .lfs_get_metas()
+            .await
+            .map_err(|e| failed(lfs_gc::lfs_error(e)))?;
+        let mut lfs_objects = 0;
+        for meta in metas {
+            if oids.contains(&meta.oid) && !shared_oids.contains(&meta.oid) {
+                lfs_gc::remove_lfs_object(storage, lfs_storage, &meta)
+                    .await
+                    .map_err(&failed)?;
+                lfs_objects += 1;
+            }
+        }
+        tracing::info!(
+            "Deleted the repo {} with {} objects and {} LFS objects",
+            repo_path,
+            objects.len(),
+            lfs_objects
+        );
+        Ok(Json(RepoDeleted {
+            name: repo_path.to_owned(),
+            objects: objects.len(),
+            lfs_objects,
+        }))
+    }
+
+    /// The repo exists if there is any ref of it, or the directory of it is created.
+    pub(crate) async fn exists(&self, repo_path: &str) -> Result<bool, (StatusCode, String)> {
+        let refs = self
+            .storage
+            .get_ref_object_id(repo_path)
+            .await
+            .map_err(failed("Failed to read the refs"))?;
+        let directory = self
+            .storage
+            .get_directory_by_full_path(repo_path)
+            .await
+            .map_err(|e| failed("Failed to read the refs")(e.into()))?;
+        Ok(!refs.is_empty() || directory.is_some())
+    }
+}
+
+/// The path of the repo of the name like `projects/mega.git`, none if it's empty or any part of
+/// it is `.` or `..`.
+pub fn repo_path(name: &str) -> Option<String> {
+    let name = name.trim_matches('/');
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    let valid = !name.is_empty()
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\'));
+    valid.then(|| format!("/{}", name))
+}
+
+/// The error of the storage is logged, and only the message is told to the client.
+pub(crate) fn failed(message: &'static str) -> impl Fn(MegaError) -> (StatusCode, String) {
+    move |e| {
+        tracing::error!("{}: {}", message, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use database::driver::lfs::storage::{ContentStore, LfsStorage};
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+    use git::hash::Hash;
+    use hyper::StatusCode;
+
+    use super::{repo_path, RepoService};
+    use crate::lfs_gc::tests::{pointer, save, upload};
+    use crate::model::query::PageQuery;
+
+    #[tokio::test]
+    async fn test_pagination() {
+        let storage = Arc::new(MemoryStorage::new());
+        for i in 0..5 {
+            let name = format!("refs/heads/b{}", i);
+            storage
+                .update_ref("/projects/mega", &name, &i.to_string().repeat(40))
+                .await
+                .unwrap();
+        }
+        storage
+            .update_ref("/projects/other", "refs/heads/main", &"1".repeat(40))
+            .await
+            .unwrap();
+        let service = RepoService { storage };
+
+        let query = |limit, cursor: Option<&str>| PageQuery {
+            limit: Some(limit),
+            cursor: cursor.map(str::to_owned),
+        };
+        let page = service
+            .list_refs("/projects/mega", &query(2, None))
+            .await
+            .unwrap();
+        assert_eq!(
+            page.refs.keys().collect::<Vec<_>>(),
+            ["refs/heads/b0", "refs/heads/b1"]
+        );
+        assert_eq!(page.next_cursor.as_deref(), Some("refs/heads/b1"));
+        let page = service
+            .list_refs("/projects/mega", &query(2, Some("refs/heads/b3")))
+            .await
+            .unwrap();
+        assert_eq!(page.refs.keys().collect::<Vec<_>>(), ["refs/heads/b4"]);
+        assert_eq!(page.next_cursor, None);
+
+        let repos = service.list_repos(&query(1, None)).await.unwrap();
+        assert_eq!(repos.repos[0].name, "/projects/mega");
+        let repos = service
+            .list_repos(&query(1, repos.next_cursor.as_deref()))
+            .await
+            .unwrap();
+        assert_eq!(repos.repos[0].name, "/projects/other");
+        assert_eq!(repos.next_cursor, None);
+
+        let err = service
+            .list_refs("/projects/none", &PageQuery::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_repo_path() {
+        assert_eq!(
+            repo_path("projects/mega").as_deref(),
+            Some("/projects/mega")
+        );
+        assert_eq!(
+            repo_path("/projects/mega.git/").as_deref(),
+            Some("/projects/mega")
+        );
+        for name in [
+            "",
+            "/",
+            ".git",
+            "projects//mega",
+            "projects/../etc",
+            "./mega",
+        ] {
+            assert_eq!(repo_path(name), None, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_repo() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &"1".repeat(40))
+            .await
+            .unwrap();
+        let service = RepoService {
+            storage: storage.clone(),
+        };
+        let repo = service.create_repo("/projects/new").await.unwrap();
+        assert_eq!(repo.name, "/projects/new");
+        let directory = storage
+            .get_directory_by_full_path("/projects/new")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(directory.is_repo);
+        let parent = storage
+            .get_directory_by_full_path("/projects")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(directory.pid, parent.id);
+        assert!(!parent.is_repo);
+
+        // listed without any ref
+        let repos = service.list_repos(&PageQuery::default()).await.unwrap();
+        let names: Vec<_> = repos.repos.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["/projects/mega", "/projects/new"]);
+
+        for name in ["/projects/new", "/projects/mega", "/projects"] {
+            let err = service.create_repo(name).await.unwrap_err();
+            assert_eq!(err.0, StatusCode::CONFLICT, "{}", name);
+        }
+    }
+
+    /// Save a commit of the tree of the blobs like `(name, id)`.
+    async fn commit(storage: &MemoryStorage, blobs: &[(&str, Hash)]) -> Hash {
+        let mut tree = Vec::new();
+        for (name, id) in blobs {
+            tree.extend(format!("100644 {}\0", name).into_bytes());
+            tree.extend(id.as_bytes());
+        }
+        let tree = save(storage, "tree", tree).await;
+        let author = "mega <mega@example.com> 1700000000 +0800";
+        let commit = format!(
+            "tree {}\nauthor {}\ncommitter {}\n\nadd the files\n",
+            tree.to_plain_str(),
+            author,
+            author
+        );
+        save(storage, "commit", commit.into_bytes()).await
+    }
+
+    #[tokio::test]
+    async fn test_delete_repo() {
+        let dir = std::env::temp_dir().join(format!("mega_delete_repo_{}", std::process::id()));
+        let storage = Arc::new(MemoryStorage::new());
+        let lfs_storage = ContentStore::new(dir.clone());
+        let own = upload(&storage, &lfs_storage, "the content of the repo").await;
+        let shared = upload(&storage, &lfs_storage, "the shared content").await;
+        let own_pointer = save(&storage, "blob", pointer(&own)).await;
+        let shared_pointer = save(&storage, "blob", pointer(&shared)).await;
+        let deleted = commit(
+            &storage,
+            &[("own.bin", own_pointer), ("shared.bin", shared_pointer)],
+        )
+        .await;
+        let kept = commit(&storage, &[("shared.bin", shared_pointer)]).await;
+        storage
+            .update_ref("/projects/fork", "refs/heads/main", &deleted.to_plain_str())
+            .await
+            .unwrap();
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &kept.to_plain_str())
+            .await
+            .unwrap();
+        let service = RepoService {
+            storage: storage.clone(),
+        };
+        let summary = service
+            .delete_repo("/projects/fork", &lfs_storage)
+            .await
+            .unwrap();
+        // the commit, the tree and the pointer of the repo only
+        assert_eq!(summary.objects, 3);
+        assert_eq!(summary.lfs_objects, 1);
+        assert!(storage
+            .get_ref_object_id("/projects/fork")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .get_obj_data_by_id(&deleted.to_plain_str())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .get_obj_data_by_id(&own_pointer.to_plain_str())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!lfs_storage.exist(&own).await);
+
+        // the objects of the other repo are kept
+        assert!(storage
+            .get_obj_data_by_id(&shared_pointer.to_plain_str())
+            .await
+            .unwrap()
+            .is_some());
+        assert!(lfs_storage.exist(&shared).await);
+        let metas = storage.lfs_get_metas().await.unwrap();
+        assert_eq!(metas.len(), 1);
+        assert_eq!(metas[0].oid, shared.oid);
+
+        let err = service
+            .delete_repo("/projects/fork", &lfs_storage)
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+//! The search of the lines of the files at a ref like `git grep`, of a web UI.
+//!
+//! The files are searched in the order of the paths, and each file matched is streamed as one
+//! line of JSON at once, so the answer of a large repo starts before all the files are read. The
+//! search stops at the limit of the lines matched, or when the client is gone. The binary files,
+//! the LFS pointers, the symlinks and the submodules are skipped.
+
+use std::io;
+
+use axum::body::{Bytes, StreamBody};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use futures::stream;
+use git::hash::Hash;
+use git::internal::object::tree::TreeItemMode;
+use git::lfs::pointer::pointer_oid;
+use git::protocol::symref::HEAD;
+use regex::bytes::Regex;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::api_service::browse_service::{sort_items, BrowseService};
+use crate::api_service::diff_service::{is_binary, lines};
+use crate::model::browse::{SearchFile, SearchMatch};
+use crate::model::query::SearchQuery;
+
+/// The chars of a line matched answered at most.
+const MAX_CONTENT_LEN: usize = 256;
+
+/// The files matched read ahead of the client.
+const STREAM_BUFFER: usize = 16;
+
+impl BrowseService {
+    /// The files of the ref of which the lines match the query, as the lines of JSON of
+    /// [`SearchFile`].
+    pub async fn search(
+        self,
+        repo_path: &str,
+        query: &SearchQuery,
+    ) -> Result<Response, (StatusCode, String)> {
+        if query.q.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "Empty query".to_string()));
+        }
+        let pattern = match query.regex {
+            true => query.q.clone(),
+            false => regex::escape(&query.q),
+        };
+        let regex = Regex::new(&pattern)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid regex: {}", e)))?;
+        let spec = query.reference.as_deref().unwrap_or(HEAD);
+        let commit = self.resolve_ref(repo_path, spec).await?;
+        let tree = self.read_commit(&commit).await?.tree_id;
+        let limit = query.limit();
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            if let Err((_, message)) = self.grep(tree, &regex, limit, &sender).await {
+                let _ = sender.send(Err(io::Error::other(message))).await;
+            }
+        });
+        let body = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        });
+        Ok((
+            [(CONTENT_TYPE, "application/x-ndjson")],
+            StreamBody::new(body),
+        )
+            .into_response())
+    }
+
+    /// Send the files of the tree matched to the sender until the `limit` lines are matched.
+    async fn grep(
+        &self,
+        tree: Hash,
+        regex: &Regex,
+        mut limit: usize,
+        sender: &Sender<io::Result<Bytes>>,
+    ) -> Result<(), (StatusCode, String)> {
+        let mut items = self.read_tree(&tree).await?.tree_items;
+        sort_items(&mut items);
+        let mut stack = vec![(String::new(), items.into_iter())];
+        while let Some((prefix, items)) = stack.last_mut() {
+            let Some(item) = items.next() else {
+                stack.pop();
+                continue;
+            };
+            let path = format!("{}{}", prefix, item.name);
+            match item.mode {
+                TreeItemMode::Tree => {
+                    let mut items = self.read_tree(&item.id).await?.tree_items;
+                    sort_items(&mut items);
+                    stack.push((format!("{}/", path), items.into_iter()));
+                    continue;
+                }
+                TreeItemMode::Blob | TreeItemMode::BlobExecutable => {}
+                TreeItemMode::Link | TreeItemMode::Commit => continue,
+            }
+            let data = self.read(&item.id).await?.data;
+            if is_binary(&data) || pointer_oid(&data).is_some() {
+                continue;
+            }
+            let mut matches = Vec::new();
+            for (i, line) in lines(&data).into_iter().enumerate() {
+                let line = line.strip_suffix(b"\n").unwrap_or(line);
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                if !regex.is_match(line) {
+                    continue;
+                }
+                matches.push(SearchMatch {
+                    line: i + 1,
+                    content: snippet(line),
+                });
+                limit -= 1;
+                if limit == 0 {
+                    break;
+                }
+            }
+            if matches.is_empty() {
+                continue;
+            }
+            let file = SearchFile {
+                path,
+                oid: item.id.to_plain_str(),
+                matches,
+            };
+            let mut data = serde_json::to_vec(&file).unwrap();
+            data.push(b'\n');
+            // the client is gone
+            if sender.send(Ok(data.into())).await.is_err() || limit == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The line of which a long one is cut at a char.
+fn snippet(line: &[u8]) -> String {
+    let content = String::from_utf8_lossy(line);
+    match content.char_indices().nth(MAX_CONTENT_LEN) {
+        Some((end, _)) => content[..end].to_owned(),
+        None => content.into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::StatusCode;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+
+    use crate::api_service::browse_service::tests::{commit, service, tree};
+    use crate::lfs_gc::tests::save;
+    use crate::model::browse::SearchFile;
+    use crate::model::query::SearchQuery;
+
+    #[tokio::test]
+    async fn test_search() {
+        let storage = Arc::new(MemoryStorage::new());
+        let lib = save(
+            &storage,
+            "blob",
+            b"fn main() {\n    mega::run();\n}\n// run mega\n".to_vec(),
+        )
+        .await;
+        let readme = save(&storage, "blob", b"# monorepo\r\n".to_vec()).await;
+        let binary = save(&storage, "blob", b"\0mega::run".to_vec()).await;
+        let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+        let lfs = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12\n",
+            oid
+        );
+        let pointer = save(&storage, "blob", lfs.into_bytes()).await;
+        let src = tree(&storage, &[("100644", "main.rs", lib)]).await;
+        let root = tree(
+            &storage,
+            &[
+                ("100644", "README.md", readme),
+                ("100644", "logo.png", binary),
+                ("100644", "model.bin", pointer),
+                ("40000", "src", src),
+                ("120000", "link", lib),
+            ],
+        )
+        .await;
+        let head = commit(&storage, root, &[], 0, "init").await;
+        storage
+            .update_ref("/projects/mega", "refs/heads/main", &head.to_plain_str())
+            .await
+            .unwrap();
+        let service = service(&storage, std::env::temp_dir().join("mega_search"));
+        let search = |q: &str, regex: bool, limit: Option<usize>| {
+            let query = SearchQuery {
+                reference: Some("main".to_owned()),
+                q: q.to_owned(),
+                regex,
+                limit,
+            };
+            let service = service.clone();
+            async move {
+                let resp = service.search("/projects/mega", &query).await?;
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                let files: Vec<SearchFile> = body
+                    .split(|b| *b == b'\n')
+                    .filter(|line| !line.is_empty())
+                    .map(|line| serde_json::from_slice(line).unwrap())
+                    .collect();
+                Ok::<_, (StatusCode, String)>(files)
+            }
+        };
+        let lines = |files: &[SearchFile]| {
+            files
+                .iter()
+                .flat_map(|f| f.matches.iter().map(|m| (f.path.clone(), m.line)))
+                .collect::<Vec<_>>()
+        };
+
+        let files = search("mega::run", false, None).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "src/main.rs");
+        assert_eq!(files[0].oid, lib.to_plain_str());
+        assert_eq!(files[0].matches[0].content, "    mega::run();");
+        assert_eq!(lines(&files), [("src/main.rs".to_owned(), 2)]);
+
+        // not in the LFS pointer
+        assert_eq!(search("sha256", false, None).await.unwrap(), []);
+        let files = search("monorepo", false, None).await.unwrap();
+        assert_eq!(files[0].matches[0].content, "# monorepo");
+
+        let files = search(r"^(#|//) \w+", true, None).await.unwrap();
+        assert_eq!(
+            lines(&files),
+            [("README.md".to_owned(), 1), ("src/main.rs".to_owned(), 4)]
+        );
+        // the literal of the regex
+        assert_eq!(search(r"\w+", false, None).await.unwrap(), []);
+        let files = search("mega|main", true, Some(2)).await.unwrap();
+        assert_eq!(
+            lines(&files),
+            [("src/main.rs".to_owned(), 1), ("src/main.rs".to_owned(), 2)]
+        );
+
+        for q in ["", "("] {
+            let err = search(q, true, None).await.unwrap_err();
+            assert_eq!(err.0, StatusCode::BAD_REQUEST, "{}", q);
+        }
+    }
+}
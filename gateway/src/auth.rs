@@ -0,0 +1,349 @@
+//! The authentication of the git smart HTTP and LFS endpoints.
+//!
+//! The credentials are sent by the HTTP Basic or the Bearer `Authorization` header, and
+//! validated by an [`Authenticator`], then the user is passed to the handlers by the request
+//! extensions. The fetch and clone need the [`Access::Read`], the push needs the
+//! [`Access::Write`], and the `/admin` API of the server needs the [`Access::Admin`].
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::{engine::general_purpose, Engine};
+use database::driver::lfs::structs::User;
+use hyper::{Body, Method, Request, StatusCode};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::https::AppState;
+
+/// The write access implies the read access, and the admin access implies both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Credentials {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+impl Credentials {
+    /// Parse the `Authorization` header, `None` if the scheme is not supported or malformed.
+    pub fn from_header(value: &str) -> Option<Credentials> {
+        let (scheme, param) = value.trim().split_once(' ')?;
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = general_purpose::STANDARD.decode(param.trim()).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+            Some(Credentials::Basic {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            })
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            Some(Credentials::Bearer(param.trim().to_owned()))
+        } else {
+            None
+        }
+    }
+}
+
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// Validate the credentials, returns the user and the granted access, or `None` if the
+    /// credentials are wrong.
+    async fn authenticate(&self, credentials: &Credentials) -> Option<(User, Access)>;
+}
+
+/// The users listed in a file, one `name:secret[:read|:admin]` per line. The secret is either
+/// the password of the Basic credentials or the Bearer token of the user, and the users are
+/// read-only with the `read` suffix, or the admins with the `admin` suffix.
+///
+/// Only the SHA-256 digests of the secrets are kept, which are compared in constant time, so
+/// the time of a wrong password doesn't tell how much of it is right. The users of the tokens
+/// are looked up by the digests as well.
+#[derive(Default)]
+pub struct FileAuthenticator {
+    users: HashMap<String, (Secret, Access)>,
+    tokens: HashMap<Secret, String>,
+}
+
+/// The SHA-256 digest of a secret.
+type Secret = [u8; 32];
+
+fn digest(secret: &str) -> Secret {
+    Sha256::digest(secret.as_bytes()).into()
+}
+
+impl FileAuthenticator {
+    pub fn load(path: &Path) -> io::Result<FileAuthenticator> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let mut authenticator = FileAuthenticator::default();
+        for (no, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}:{}: expect `name:secret[:read|:admin]`",
+                        path.display(),
+                        no + 1
+                    ),
+                )
+            };
+            let mut fields = line.splitn(3, ':');
+            let (name, secret) = match (fields.next(), fields.next()) {
+                (Some(name), Some(secret)) if !name.is_empty() && !secret.is_empty() => {
+                    (name, secret)
+                }
+                _ => return Err(invalid()),
+            };
+            let access = match fields.next() {
+                None => Access::Write,
+                Some("read") => Access::Read,
+                Some("admin") => Access::Admin,
+                Some(_) => return Err(invalid()),
+            };
+            authenticator.add_user(name, secret, access);
+        }
+        Ok(authenticator)
+    }
+
+    pub fn add_user(&mut self, name: &str, secret: &str, access: Access) {
+        let secret = digest(secret);
+        if let Some((old, _)) = self.users.insert(name.to_owned(), (secret, access)) {
+            if self.tokens.get(&old).is_some_and(|user| user == name) {
+                self.tokens.remove(&old);
+            }
+        }
+        self.tokens.insert(secret, name.to_owned());
+    }
+}
+
+#[async_trait]
+impl Authenticator for FileAuthenticator {
+    async fn authenticate(&self, credentials: &Credentials) -> Option<(User, Access)> {
+        let found = match credentials {
+            Credentials::Basic { username, password } => {
+                let password = digest(password);
+                self.users
+                    .get_key_value(username)
+                    .filter(|(_, (secret, _))| bool::from(secret.ct_eq(&password)))
+            }
+            Credentials::Bearer(token) => self
+                .tokens
+                .get(&digest(token))
+                .and_then(|name| self.users.get_key_value(name)),
+        };
+        found.map(|(name, (_, access))| {
+            let user = User {
+                name: name.to_owned(),
+            };
+            (user, *access)
+        })
+    }
+}
+
+/// The access needed by the request, the push, the changes of LFS objects and locks and the
+/// verification of the locks before a push, the
+/// creation, the default branches and the deletion of the repos, and the test deliveries of the
+/// webhooks need the write access, and the `/admin` API needs the admin access.
+pub fn required_access(req: &Request<Body>) -> Access {
+    let path = req.uri().path();
+    if path.starts_with("/admin/") {
+        return Access::Admin;
+    }
+    let query = req.uri().query().unwrap_or_default();
+    let receive_pack = path.ends_with("/git-receive-pack")
+        || (path.ends_with("/info/refs") && query.contains("service=git-receive-pack"));
+    let lfs_write = req.method() == Method::PUT
+        || req.method() == Method::PATCH
+        || (req.method() == Method::POST
+            && (path.ends_with("/locks")
+                || path.ends_with("/locks/verify")
+                || path.ends_with("/unlock")));
+    let repo_write = req.method() == Method::DELETE
+        || (req.method() == Method::POST
+            && (path.ends_with("/repos") || path.ends_with("/webhooks/ping")));
+    if receive_pack || lfs_write || repo_write {
+        Access::Write
+    } else {
+        Access::Read
+    }
+}
+
+/// The request which can't be undone, e.g. the deletion of a repo.
+fn is_destructive(req: &Request<Body>) -> bool {
+    req.method() == Method::DELETE
+}
+
+fn forbidden(message: &'static str) -> Response {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(axum::body::boxed(Body::from(message)))
+        .unwrap()
+}
+
+fn unauthorized() -> Response {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(WWW_AUTHENTICATE, "Basic realm=\"mega\", charset=\"UTF-8\"")
+        .body(axum::body::boxed(Body::from("Authentication required\n")))
+        .unwrap()
+}
+
+/// The middleware checks the credentials before the request is handled, if an authenticator
+/// is set. The anonymous users are allowed to fetch with the `anonymous_read` option.
+///
+/// Without an authenticator, the admin and the destructive requests are forbidden, as anyone
+/// could send them.
+pub async fn auth_layer(
+    State(state): State<AppState>,
+    mut req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let required = required_access(&req);
+    let Some(authenticator) = state.authenticator.clone() else {
+        if required == Access::Admin || is_destructive(&req) {
+            tracing::info!("{} is not allowed without the auth", req.uri().path());
+            return forbidden("Authentication is not configured\n");
+        }
+        return next.run(req).await;
+    };
+    let credentials = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Credentials::from_header);
+
+    let Some(credentials) = credentials else {
+        if required == Access::Read && state.options.anonymous_read {
+            return next.run(req).await;
+        }
+        return unauthorized();
+    };
+    match authenticator.authenticate(&credentials).await {
+        Some((user, access)) if access >= required => {
+            req.extensions_mut().insert(user);
+            next.run(req).await
+        }
+        Some((user, _)) => {
+            let message = match required {
+                Access::Admin => "Admin access required\n",
+                _ => "Write access required\n",
+            };
+            tracing::info!("{} is not allowed to {}", user.name, req.uri().path());
+            forbidden(message)
+        }
+        None => unauthorized(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use database::driver::lfs::structs::User;
+    use hyper::{Body, Method, Request};
+
+    use super::{required_access, Access, Authenticator, Credentials, FileAuthenticator};
+
+    #[test]
+    fn test_credentials_from_header() {
+        // "alice:secret"
+        assert_eq!(
+            Credentials::from_header("Basic YWxpY2U6c2VjcmV0"),
+            Some(Credentials::Basic {
+                username: "alice".to_owned(),
+                password: "secret".to_owned(),
+            })
+        );
+        assert_eq!(
+            Credentials::from_header("bearer token"),
+            Some(Credentials::Bearer("token".to_owned()))
+        );
+        assert_eq!(Credentials::from_header("Basic !!!"), None);
+        assert_eq!(Credentials::from_header("Digest abc"), None);
+    }
+
+    #[test]
+    fn test_required_access() {
+        let access = |method: Method, uri: &str| {
+            let req = Request::builder().method(method).uri(uri);
+            required_access(&req.body(Body::empty()).unwrap())
+        };
+        let lfs = "/projects/mega.git/info/lfs";
+        let lfs = |path: &str| format!("{}{}", lfs, path);
+        assert_eq!(access(Method::GET, &lfs("/locks")), Access::Read);
+        assert_eq!(access(Method::POST, &lfs("/objects/batch")), Access::Read);
+        // the locks are verified by the push
+        assert_eq!(access(Method::POST, &lfs("/locks/verify")), Access::Write);
+        assert_eq!(access(Method::POST, &lfs("/locks/1/unlock")), Access::Write);
+        assert_eq!(access(Method::GET, "/admin/cache"), Access::Admin);
+    }
+
+    #[tokio::test]
+    async fn test_file_authenticator() {
+        let path = std::env::temp_dir().join("mega_auth_users");
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "# users\nalice:secret\n\nbob:token:read").unwrap();
+        let authenticator = FileAuthenticator::load(&path).unwrap();
+
+        let alice = Credentials::Basic {
+            username: "alice".to_owned(),
+            password: "secret".to_owned(),
+        };
+        let user = |name: &str| User {
+            name: name.to_owned(),
+        };
+        assert_eq!(
+            authenticator.authenticate(&alice).await,
+            Some((user("alice"), Access::Write))
+        );
+        assert_eq!(
+            authenticator
+                .authenticate(&Credentials::Bearer("token".to_owned()))
+                .await,
+            Some((user("bob"), Access::Read))
+        );
+        let wrong = Credentials::Basic {
+            username: "alice".to_owned(),
+            password: "token".to_owned(),
+        };
+        assert_eq!(authenticator.authenticate(&wrong).await, None);
+
+        // the old token of the user is not valid any more
+        let mut authenticator = authenticator;
+        authenticator.add_user("bob", "new-token", Access::Read);
+        let token = |token: &str| Credentials::Bearer(token.to_owned());
+        assert_eq!(authenticator.authenticate(&token("token")).await, None);
+        assert_eq!(
+            authenticator.authenticate(&token("new-token")).await,
+            Some((user("bob"), Access::Read))
+        );
+
+        std::fs::write(&path, "alice:secret:admin").unwrap();
+        let authenticator = FileAuthenticator::load(&path).unwrap();
+        assert_eq!(
+            authenticator.authenticate(&alice).await,
+            Some((user("alice"), Access::Admin))
+        );
+        std::fs::write(&path, "alice:secret:owner").unwrap();
+        let err = FileAuthenticator::load(&path).err().unwrap();
+        assert!(err
+            .to_string()
+            .ends_with(":1: expect `name:secret[:read|:admin]`"));
+    }
+}
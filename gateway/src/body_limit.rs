@@ -0,0 +1,149 @@
+//! The limits of the request bodies of the pushes and the LFS requests, so a client can't
+//! exhaust the memory of the server by an enormous pack.
+//!
+//! A body declared larger than the limit by the `Content-Length` is rejected before it's read,
+//! and the others are counted as they are streamed to the handler, which fails to read the rest
+//! once the limit is exceeded. Both are answered by `413 Payload Too Large`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::header::CONTENT_LENGTH;
+use axum::middleware::Next;
+use axum::response::Response;
+use clap::Args;
+use futures::StreamExt;
+use hyper::{Body, Method, Request, StatusCode};
+
+use crate::https::AppState;
+
+/// Parameters of the body limits, the bodies are not limited if the size is not set.
+#[derive(Args, Clone, Copy, Debug, Default)]
+pub struct BodyLimitOptions {
+    /// The max size of the body of a push in bytes, i.e. the commands and the pack
+    #[arg(long, value_name = "BYTES")]
+    pub max_push_size: Option<u64>,
+
+    /// The max size of the body of an LFS request in bytes, e.g. the content of an upload
+    #[arg(long, value_name = "BYTES")]
+    pub max_lfs_request_size: Option<u64>,
+}
+
+impl BodyLimitOptions {
+    /// The limit of the body of the request, the fetches are not limited.
+    pub fn limit_of(&self, req: &Request<Body>) -> Option<u64> {
+        let path = req.uri().path();
+        if path.ends_with("/git-receive-pack") {
+            self.max_push_size
+        } else if req.method() == Method::GET
+            || req.method() == Method::HEAD
+            || path.ends_with("/git-upload-pack")
+        {
+            None
+        } else {
+            self.max_lfs_request_size
+        }
+    }
+}
+
+fn too_large(limit: u64) -> Response {
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(axum::body::boxed(Body::from(format!(
+            "The request body is larger than the limit of {} bytes\n",
+            limit
+        ))))
+        .unwrap()
+}
+
+/// The middleware limits the body of the request to the size of the options.
+pub async fn body_limit_layer(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(limit) = state.options.body_limit.limit_of(&req) else {
+        return next.run(req).await;
+    };
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if let Some(size) = declared.filter(|size| *size > limit) {
+        tracing::info!("rejected a body of {} bytes to {}", size, req.uri());
+        return too_large(limit);
+    }
+
+    let (parts, body) = req.into_parts();
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let flag = exceeded.clone();
+    let mut received = 0;
+    let body = body.map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        received += chunk.len() as u64;
+        if received > limit {
+            flag.store(true, Ordering::Relaxed);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the body is larger than the limit",
+            ));
+        }
+        Ok(chunk)
+    });
+    let uri = parts.uri.clone();
+    let resp = next
+        .run(Request::from_parts(parts, Body::wrap_stream(body)))
+        .await;
+    // The handler fails to read the body, whatever it answers.
+    if exceeded.load(Ordering::Relaxed) {
+        tracing::info!("aborted a body larger than {} bytes to {}", limit, uri);
+        return too_large(limit);
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use hyper::{Body, Request};
+
+    use super::BodyLimitOptions;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        body_limit: BodyLimitOptions,
+    }
+
+    #[test]
+    fn test_limit_of() {
+        let options = Cli::parse_from([
+            "mega",
+            "--max-push-size",
+            "100",
+            "--max-lfs-request-size",
+            "10",
+        ])
+        .body_limit;
+        let limit = |method: &str, path: &str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            options.limit_of(&req)
+        };
+        assert_eq!(limit("POST", "/repo.git/git-receive-pack"), Some(100));
+        assert_eq!(limit("POST", "/repo.git/git-upload-pack"), None);
+        assert_eq!(limit("GET", "/repo.git/info/refs"), None);
+        assert_eq!(limit("POST", "/repo.git/info/lfs/objects/batch"), Some(10));
+        assert_eq!(limit("PUT", "/repo.git/info/lfs/objects/1234"), Some(10));
+        // not limited by default
+        let req = Request::post("/repo.git/git-receive-pack")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(Cli::parse_from(["mega"]).body_limit.limit_of(&req), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! The precomputed bundles of the repos, which are advertised by the `bundle-uri` command of
+//! the protocol v2, so a clone downloads the bundle, e.g. from a CDN, and fetches only the refs
+//! updated since it's made over the normal protocol.
+//!
+//! The bundle of a repo is saved as `<dir>/<repo>.bundle`, and served at `<repo>.git/bundle`
+//! unless the `--bundle-base-url` of a static hosting of the dir is set. The bundles are
+//! regenerated every interval if the refs of the repo differ from the header of the saved one,
+//! which is replaced by the rename of a temporary file, so a download never sees a partial one.
+//! A repo without a bundle yet, e.g. a new one, is advertised no bundle.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Args;
+use database::driver::ObjectStorage;
+use git::internal::commit_graph::CommitGraphCache;
+use git::protocol::bundle::parse_header;
+use git::protocol::{PackProtocol, Protocol};
+
+use crate::PackOptions;
+
+/// Parameters of the precomputed bundles of the repos
+#[derive(Args, Clone, Debug)]
+pub struct BundleOptions {
+    /// Save the bundles of the repos to this dir and advertise them to the clones, none are made
+    /// if not set
+    #[arg(long, value_name = "DIR")]
+    pub bundle_dir: Option<PathBuf>,
+
+    /// Regenerate the bundles of which the refs are updated every this many seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 3600)]
+    pub bundle_interval: u64,
+
+    /// The static hosting of the `bundle_dir`, e.g. `https://cdn.example.com/bundles`, to which
+    /// `<repo>.bundle` is appended, the server itself if not set
+    #[arg(long, value_name = "URL")]
+    pub bundle_base_url: Option<String>,
+}
+
+pub struct Bundles {
+    dir: PathBuf,
+    interval: Duration,
+    base_url: Option<String>,
+    storage: Arc<dyn ObjectStorage>,
+    commit_graphs: Arc<CommitGraphCache>,
+    pack: PackOptions,
+}
+
+impl Bundles {
+    /// The bundles of the options, none without the `--bundle-dir`.
+    pub fn new(
+        options: &BundleOptions,
+        storage: Arc<dyn ObjectStorage>,
+        commit_graphs: Arc<CommitGraphCache>,
+        pack: PackOptions,
+    ) -> io::Result<Option<Bundles>> {
+        let Some(dir) = &options.bundle_dir else {
+            return Ok(None);
+        };
+        std::fs::create_dir_all(dir)?;
+        Ok(Some(Bundles {
+            dir: dir.clone(),
+            interval: Duration::from_secs(options.bundle_interval.max(1)),
+            base_url: options
+                .bundle_base_url
+                .as_ref()
+                .map(|url| url.trim_end_matches('/').to_owned()),
+            storage,
+            commit_graphs,
+            pack,
+        }))
+    }
+
+    /// The file of the bundle of the repo like `/projects/demo`, none if the path isn't one in
+    /// the dir, e.g. with a `..`.
+    pub fn path(&self, repo_path: &Path) -> Option<PathBuf> {
+        let repo = repo_path.to_string_lossy();
+        let repo = repo.trim_matches('/');
+        let valid = !repo.is_empty()
+            && Path::new(repo)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        valid.then(|| self.dir.join(format!("{}.bundle", repo)))
+    }
+
+    /// The uri of the bundle of the repo to advertise, of the `origin` like
+    /// `https://git.example.com` without the base url, none if it has no bundle yet.
+    pub fn uri(&self, repo_path: &Path, origin: &str) -> Option<String> {
+        if !self.path(repo_path)?.is_file() {
+            return None;
+        }
+        let repo = repo_path.to_string_lossy();
+        let repo = repo.trim_matches('/');
+        Some(match &self.base_url {
+            Some(base_url) => format!("{}/{}.bundle", base_url, repo),
+            None => format!("{}/{}.git/bundle", origin, repo),
+        })
+    }
+
+    /// Make the bundle of the repo unless the saved one has the same refs, true if it's made.
+    pub async fn generate(&self, repo_path: &Path) -> anyhow::Result<bool> {
+        let mut pack_protocol =
+            PackProtocol::new(repo_path.to_owned(), self.storage.clone(), Protocol::Http);
+        pack_protocol.window = self.pack.window;
+        pack_protocol.depth = self.pack.depth;
+        pack_protocol.commit_graphs = self.commit_graphs.clone();
+        let refs = pack_protocol.bundle_refs().await?;
+        let path = self
+            .path(repo_path)
+            .ok_or_else(|| anyhow::anyhow!("invalid path of the repo"))?;
+        let saved = match std::fs::File::open(&path) {
+            Ok(file) => parse_header(BufReader::new(file))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if saved.as_ref() == Some(&refs) {
+            return Ok(false);
+        }
+        let data = pack_protocol.bundle().await?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = path.with_extension("bundle.tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(true)
+    }
+
+    /// Make the bundles of all the repos of which the refs are updated.
+    pub async fn generate_all(&self) -> anyhow::Result<()> {
+        let refs = self
+            .storage
+            .get_all_refs()
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to list the refs: {}", e))?;
+        let repos: BTreeSet<String> = refs.into_iter().map(|r| r.repo_path).collect();
+        for repo in repos {
+            match self.generate(Path::new(&repo)).await {
+                Ok(true) => tracing::info!("Made the bundle of {}", repo),
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Failed to make the bundle of {}: {}", repo, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Regenerate the bundles every interval in the background, the first time at once.
+    pub fn spawn(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.generate_all().await {
+                    tracing::warn!("Failed to make the bundles: {}", e);
+                }
+            }
+        });
+    }
+}
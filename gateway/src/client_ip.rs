@@ -0,0 +1,289 @@
+//! The IP of the client behind the reverse proxies, by which the requests are rate limited and
+//! logged.
+//!
+//! The `Forwarded` or the `X-Forwarded-For` header is trusted only if the peer of the connection
+//! is a trusted proxy. The addresses of it are walked from the right, where each proxy appends
+//! the one it's connected by, and the first one which is not a trusted proxy is the client, so
+//! the client can't forge its IP by the header.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::{ConnectInfo, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use clap::Args;
+use hyper::header::FORWARDED;
+use hyper::{Body, HeaderMap, Request};
+use ipnet::IpNet;
+
+use crate::https::AppState;
+
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+/// Parameters of the reverse proxies in front of the server.
+#[derive(Args, Clone, Debug, Default)]
+pub struct ProxyOptions {
+    /// Take the client IP from the `Forwarded` or the `X-Forwarded-For` header set by the
+    /// reverse proxy, whatever the address of it is
+    #[arg(long)]
+    pub trusted_proxy: bool,
+
+    /// Take the client IP from the `Forwarded` or the `X-Forwarded-For` header of the reverse
+    /// proxies in these networks only, comma separated like `10.0.0.0/8,fd00::/8`
+    #[arg(long, value_name = "CIDR", value_delimiter = ',', value_parser = parse_cidr)]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+/// Parse a network like `10.0.0.0/8`, or an address like `10.0.0.1` of itself only.
+pub fn parse_cidr(cidr: &str) -> Result<IpNet, String> {
+    cidr.parse::<IpNet>()
+        .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("{} is not a CIDR like 10.0.0.0/8", cidr))
+}
+
+impl ProxyOptions {
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The IP of the client of the request from the `peer`. The peer is unknown of a Unix domain
+    /// socket, which is trusted if any proxy is. The IPv4-mapped IPv6 addresses like
+    /// `::ffff:10.0.0.1`, e.g. of a dual-stack socket, are the IPv4 ones of them.
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer.map(|ip| ip.to_canonical());
+        let peer_trusted = match peer {
+            Some(ip) => self.trusted_proxy || self.is_trusted(ip),
+            None => self.trusted_proxy || !self.trusted_proxies.is_empty(),
+        };
+        if !peer_trusted {
+            return peer;
+        }
+        let mut client = peer;
+        for hop in forwarded_for(headers).into_iter().rev() {
+            // an obfuscated or malformed one, of which the proxy after it is the client
+            let Some(ip) = hop.map(|ip| ip.to_canonical()) else {
+                break;
+            };
+            client = Some(ip);
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The addresses of the `for` of the `Forwarded` headers, or those of the `X-Forwarded-For` if
+/// there is no `Forwarded`, from the client to the last proxy.
+pub fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values(FORWARDED.as_str());
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect();
+    }
+    values(X_FORWARDED_FOR)
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// Parse a node like `192.0.2.60`, `"[2001:db8::17]:4711"` or `192.0.2.60:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, _) = rest.split_once(']')?;
+        return ip.parse().ok();
+    }
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// The IP of the client, which is in the extensions of the requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// The middleware puts the [`ClientIp`] in the request, before it's logged or rate limited.
+pub async fn client_ip_layer(
+    State(state): State<AppState>,
+    mut req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(ip) = state.options.proxy.client_ip(peer, req.headers()) {
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use hyper::HeaderMap;
+
+    use super::{forwarded_for, parse_cidr, ProxyOptions};
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_forwarded_for() {
+        let xff = headers(&[
+            ("X-Forwarded-For", "203.0.113.7, 2001:db8::1"),
+            ("X-Forwarded-For", "10.0.0.2:4711, bad"),
+        ]);
+        assert_eq!(
+            forwarded_for(&xff),
+            vec![
+                Some(ip("203.0.113.7")),
+                Some(ip("2001:db8::1")),
+                Some(ip("10.0.0.2")),
+                None
+            ]
+        );
+        // the `Forwarded` is preferred
+        let forwarded = headers(&[
+            ("X-Forwarded-For", "198.51.100.1"),
+            (
+                "Forwarded",
+                r#"for=192.0.2.60;proto=http;by=203.0.113.43, For="[2001:db8:cafe::17]:4711""#,
+            ),
+            ("Forwarded", "for=_hidden"),
+        ]);
+        assert_eq!(
+            forwarded_for(&forwarded),
+            vec![Some(ip("192.0.2.60")), Some(ip("2001:db8:cafe::17")), None]
+        );
+        assert!(forwarded_for(&HeaderMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_client_ip_without_trusted_proxy() {
+        let options = ProxyOptions::default();
+        let xff = headers(&[("X-Forwarded-For", "203.0.113.7, 10.0.0.2")]);
+        // the header is forged by the client
+        assert_eq!(
+            options.client_ip(Some(ip("198.51.100.9")), &xff),
+            Some(ip("198.51.100.9"))
+        );
+        assert_eq!(options.client_ip(None, &xff), None);
+
+        // the peer is not a trusted proxy
+        let options = ProxyOptions {
+            trusted_proxy: false,
+            trusted_proxies: vec![parse_cidr("10.0.0.0/8").unwrap()],
+        };
+        assert_eq!(
+            options.client_ip(Some(ip("198.51.100.9")), &xff),
+            Some(ip("198.51.100.9"))
+        );
+    }
+
+    #[test]
+    fn test_client_ip_with_trusted_proxies() {
+        let options = ProxyOptions {
+            trusted_proxy: false,
+            trusted_proxies: vec![
+                parse_cidr("10.0.0.0/8").unwrap(),
+                parse_cidr("fd00::/8").unwrap(),
+                parse_cidr("192.0.2.1").unwrap(),
+            ],
+        };
+        // the client, a proxy of it which is not trusted, and two trusted hops
+        let xff = headers(&[(
+            "X-Forwarded-For",
+            "203.0.113.7, 198.51.100.20, fd00::5, 10.1.2.3",
+        )]);
+        assert_eq!(
+            options.client_ip(Some(ip("192.0.2.1")), &xff),
+            Some(ip("198.51.100.20"))
+        );
+        // all the hops are trusted
+        let xff = headers(&[("X-Forwarded-For", "203.0.113.7, 10.1.2.3")]);
+        assert_eq!(
+            options.client_ip(Some(ip("10.0.0.1")), &xff),
+            Some(ip("203.0.113.7"))
+        );
+        // the peer on a Unix domain socket
+        assert_eq!(options.client_ip(None, &xff), Some(ip("203.0.113.7")));
+        // no header
+        assert_eq!(
+            options.client_ip(Some(ip("10.0.0.1")), &HeaderMap::new()),
+            Some(ip("10.0.0.1"))
+        );
+        // the trusted proxy of an obfuscated client
+        let forwarded = headers(&[("Forwarded", "for=unknown, for=10.1.2.3")]);
+        assert_eq!(
+            options.client_ip(Some(ip("10.0.0.1")), &forwarded),
+            Some(ip("10.1.2.3"))
+        );
+    }
+
+    #[test]
+    fn test_client_ip_of_mapped_ipv4() {
+        let options = ProxyOptions {
+            trusted_proxy: false,
+            trusted_proxies: vec![parse_cidr("10.0.0.0/8").unwrap()],
+        };
+        // the peer of a dual-stack socket, and a hop of a proxy of it
+        let xff = headers(&[("X-Forwarded-For", "203.0.113.7, ::ffff:10.1.2.3")]);
+        assert_eq!(
+            options.client_ip(Some(ip("::ffff:10.0.0.1")), &xff),
+            Some(ip("203.0.113.7"))
+        );
+        let xff = headers(&[("X-Forwarded-For", "::ffff:203.0.113.7")]);
+        assert_eq!(
+            options.client_ip(Some(ip("::ffff:10.0.0.1")), &xff),
+            Some(ip("203.0.113.7"))
+        );
+        // not trusted, but the peer is the IPv4 one
+        assert_eq!(
+            options.client_ip(Some(ip("::ffff:198.51.100.9")), &xff),
+            Some(ip("198.51.100.9"))
+        );
+    }
+
+    #[test]
+    fn test_client_ip_of_any_proxy() {
+        // only the peer is trusted, of which the last hop is the client
+        let options = ProxyOptions {
+            trusted_proxy: true,
+            trusted_proxies: Vec::new(),
+        };
+        let xff = headers(&[("X-Forwarded-For", "10.0.0.1, 192.168.1.1")]);
+        assert_eq!(
+            options.client_ip(Some(ip("198.51.100.9")), &xff),
+            Some(ip("192.168.1.1"))
+        );
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("mega").is_err());
+    }
+}
@@ -0,0 +1,348 @@
+//! The compression of the HTTP bodies, negotiated by the `Accept-Encoding` of the client for
+//! the responses, and by the `Content-Encoding` of the requests, e.g. git compresses the large
+//! requests of `git-upload-pack` by gzip.
+//!
+//! Both gzip and deflate are supported, and the bodies are coded as they are streamed, so
+//! the progress of a response is not held back. A request body is decoded before the body
+//! limit, of which the decoded bytes are counted. Only the responses of the compressible
+//! types are compressed, e.g. the ref advertisements and the JSON, rather than the packs which
+//! are compressed already.
+
+use std::io::{self, Write};
+
+use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use flate2::write::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+use flate2::Compression;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, HeaderMap, Method, Request, StatusCode};
+
+/// The responses smaller than this are not worth compressing.
+const MIN_COMPRESSED_SIZE: u64 = 256;
+
+/// The types of the responses which are compressed.
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "application/json",
+    "application/vnd.git-lfs+json",
+    "application/x-git-upload-pack-advertisement",
+    "application/x-git-receive-pack-advertisement",
+    "text/",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Encoding> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    fn encoder(self) -> Coder {
+        match self {
+            Encoding::Gzip => Coder::GzipEncoder(GzEncoder::new(Vec::new(), Compression::fast())),
+            Encoding::Deflate => {
+                Coder::DeflateEncoder(ZlibEncoder::new(Vec::new(), Compression::fast()))
+            }
+        }
+    }
+
+    fn decoder(self) -> Coder {
+        match self {
+            Encoding::Gzip => Coder::GzipDecoder(GzDecoder::new(Vec::new())),
+            Encoding::Deflate => Coder::DeflateDecoder(ZlibDecoder::new(Vec::new())),
+        }
+    }
+}
+
+/// The encoding of the response accepted by the `Accept-Encoding`, gzip is preferred if both
+/// are accepted. None if neither is, or it's disabled by `q=0`.
+pub fn accepted_encoding(headers: &HeaderMap) -> Option<Encoding> {
+    let mut accepted = Vec::new();
+    for value in headers.get_all(ACCEPT_ENCODING) {
+        for item in value.to_str().unwrap_or_default().split(',') {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let disabled = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .any(|q| q.trim().parse::<f32>().is_ok_and(|q| q <= 0.0));
+            let encoding = match name {
+                "*" => Some(Encoding::Gzip),
+                name => Encoding::from_name(name),
+            };
+            if let (Some(encoding), false) = (encoding, disabled) {
+                accepted.push(encoding);
+            }
+        }
+    }
+    [Encoding::Gzip, Encoding::Deflate]
+        .into_iter()
+        .find(|encoding| accepted.contains(encoding))
+}
+
+/// The encoder or the decoder of a body.
+enum Coder {
+    GzipEncoder(GzEncoder<Vec<u8>>),
+    DeflateEncoder(ZlibEncoder<Vec<u8>>),
+    GzipDecoder(GzDecoder<Vec<u8>>),
+    DeflateDecoder(ZlibDecoder<Vec<u8>>),
+}
+
+macro_rules! each_coder {
+    ($coder:expr, $c:ident => $body:expr) => {
+        match $coder {
+            Coder::GzipEncoder($c) => $body,
+            Coder::DeflateEncoder($c) => $body,
+            Coder::GzipDecoder($c) => $body,
+            Coder::DeflateDecoder($c) => $body,
+        }
+    };
+}
+
+impl Coder {
+    /// Code the chunk, returns the output of it, which is flushed so nothing is held back.
+    fn code(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        each_coder!(self, c => {
+            c.write_all(chunk)?;
+            c.flush()?;
+            Ok(std::mem::take(c.get_mut()))
+        })
+    }
+
+    /// The rest of the output at the end of the input.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        each_coder!(self, c => c.finish())
+    }
+}
+
+/// The body coded by the coder as it's streamed.
+fn code_body<B>(body: B, coder: Coder) -> Body
+where
+    B: HttpBody<Data = Bytes> + Send + Unpin + 'static,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let stream = futures::stream::unfold(Some((body, coder)), |state| async move {
+        let (mut body, mut coder) = state?;
+        loop {
+            let result = match body.data().await {
+                Some(Ok(chunk)) => match coder.code(&chunk) {
+                    Ok(output) if output.is_empty() => continue,
+                    Ok(output) => Ok(Bytes::from(output)),
+                    Err(e) => return Some((Err(e), None)),
+                },
+                Some(Err(e)) => return Some((Err(io::Error::other(e)), None)),
+                None => return Some((coder.finish().map(Bytes::from), None)),
+            };
+            return Some((result, Some((body, coder))));
+        }
+    });
+    Body::wrap_stream(stream)
+}
+
+fn is_compressible(resp: &Response) -> bool {
+    let headers = resp.headers();
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let small = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .or(resp.body().size_hint().exact())
+        .is_some_and(|size| size < MIN_COMPRESSED_SIZE);
+    resp.status().is_success()
+        && resp.status() != StatusCode::NO_CONTENT
+        && resp.status() != StatusCode::PARTIAL_CONTENT
+        && !headers.contains_key(CONTENT_ENCODING)
+        && !small
+        && COMPRESSIBLE_TYPES
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix))
+}
+
+fn unsupported(encoding: &str) -> Response {
+    Response::builder()
+        .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        .body(axum::body::boxed(Body::from(format!(
+            "The content encoding {} is not supported\n",
+            encoding
+        ))))
+        .unwrap()
+}
+
+/// The middleware decodes the compressed request body, and compresses the response if the
+/// client accepts it.
+pub async fn compression_layer(mut req: Request<Body>, next: Next<Body>) -> Response {
+    if let Some(value) = req.headers().get(CONTENT_ENCODING) {
+        let name = value.to_str().unwrap_or_default().to_owned();
+        if !name.trim().eq_ignore_ascii_case("identity") {
+            let Some(encoding) = Encoding::from_name(&name) else {
+                return unsupported(&name);
+            };
+            let headers = req.headers_mut();
+            headers.remove(CONTENT_ENCODING);
+            // of the compressed body
+            headers.remove(CONTENT_LENGTH);
+            req = req.map(|body| code_body(body, encoding.decoder()));
+        }
+    }
+
+    let encoding = accepted_encoding(req.headers()).filter(|_| req.method() != Method::HEAD);
+    let mut resp = next.run(req).await;
+    resp.headers_mut()
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(encoding) = encoding.filter(|_| is_compressible(&resp)) else {
+        return resp;
+    };
+    let headers = resp.headers_mut();
+    headers.remove(CONTENT_LENGTH);
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    resp.map(|body| axum::body::boxed(code_body(body, encoding.encoder())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+    use axum::middleware;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use hyper::{Body, HeaderMap, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::{accepted_encoding, compression_layer, Encoding};
+
+    fn app() -> Router {
+        let json = "[".to_owned() + &r#"{"name":"/projects/mega"},"#.repeat(100) + "{}]";
+        Router::new()
+            .route("/echo", post(|body: String| async move { body }))
+            .route(
+                "/json",
+                get(move || async move { ([(CONTENT_TYPE, "application/json")], json) }),
+            )
+            .route(
+                "/pack",
+                get(|| async {
+                    let pack = vec![b'P'; 1024];
+                    (
+                        [(CONTENT_TYPE, "application/x-git-upload-pack-result")],
+                        pack,
+                    )
+                }),
+            )
+            .route("/small", get(|| async { "ok" }))
+            .layer(middleware::from_fn(compression_layer))
+    }
+
+    fn headers(accept: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, accept.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_accepted_encoding() {
+        assert_eq!(accepted_encoding(&HeaderMap::new()), None);
+        assert_eq!(
+            accepted_encoding(&headers("deflate, gzip;q=1.0")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            accepted_encoding(&headers("gzip;q=0, deflate")),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(accepted_encoding(&headers("*")), Some(Encoding::Gzip));
+        assert_eq!(accepted_encoding(&headers("br, identity")), None);
+    }
+
+    #[tokio::test]
+    async fn test_decode_request() {
+        let body = "0032want d767d4967b3e14ede397c552b9d352af52a4bbd9\n00000009done\n";
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(body.as_bytes()).unwrap();
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(body.as_bytes()).unwrap();
+
+        for (encoding, compressed) in [
+            ("gzip", gzip.finish().unwrap()),
+            ("deflate", deflate.finish().unwrap()),
+        ] {
+            let req = Request::post("/echo")
+                .header(CONTENT_ENCODING, encoding)
+                .header("Content-Length", compressed.len())
+                .body(Body::from(compressed))
+                .unwrap();
+            let resp = app().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let echoed = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            assert_eq!(&echoed[..], body.as_bytes(), "{}", encoding);
+        }
+
+        let req = Request::post("/echo")
+            .header(CONTENT_ENCODING, "br")
+            .body(Body::from("body"))
+            .unwrap();
+        let resp = app().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_compress_response() {
+        let get = |uri: &str, accept: Option<&str>| {
+            let mut req = Request::get(uri);
+            if let Some(accept) = accept {
+                req = req.header(ACCEPT_ENCODING, accept);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+        let resp = app().oneshot(get("/json", None)).await.unwrap();
+        assert!(!resp.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(resp.headers()[VARY], "accept-encoding");
+        let json = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+
+        let resp = app().oneshot(get("/json", Some("gzip"))).await.unwrap();
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        let compressed = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(compressed.len() < json.len());
+        let mut decoded = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, json);
+
+        let resp = app().oneshot(get("/json", Some("deflate"))).await.unwrap();
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "deflate");
+        let compressed = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let mut decoded = Vec::new();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, json);
+
+        // the packs are compressed already, and the small ones are not worth it
+        for uri in ["/pack", "/small"] {
+            let resp = app().oneshot(get(uri, Some("gzip"))).await.unwrap();
+            assert!(!resp.headers().contains_key(CONTENT_ENCODING), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! The CORS of the HTTP server, so the git clients in the browsers of the allowed origins call
+//! the gateway directly.
+//!
+//! There is no CORS unless an origin is allowed, then the preflight `OPTIONS` of the origin is
+//! answered before the authentication, and the responses to it have the
+//! `Access-Control-Allow-*` headers. The requests of the other origins are served as before,
+//! without the headers, so the browsers block them.
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use clap::Args;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Parameters of the CORS, which is disabled if no origin is allowed.
+#[derive(Args, Clone, Debug, Default)]
+pub struct CorsOptions {
+    /// The origin allowed to call the server from the browsers, e.g. `https://git.example.com`,
+    /// or `*` for any origin, repeat it for more
+    #[arg(long = "cors-allowed-origin", value_name = "ORIGIN", value_parser = parse_origin)]
+    pub allowed_origins: Vec<HeaderValue>,
+
+    /// The methods allowed to the origins
+    #[arg(
+        long = "cors-allowed-method",
+        value_name = "METHOD",
+        value_delimiter = ',',
+        default_value = "GET,HEAD,POST,PUT,PATCH,DELETE",
+        value_parser = parse_method
+    )]
+    pub allowed_methods: Vec<Method>,
+
+    /// The request headers allowed to the origins
+    #[arg(
+        long = "cors-allowed-header",
+        value_name = "HEADER",
+        value_delimiter = ',',
+        default_value = "authorization,content-type,accept,git-protocol,git-namespace",
+        value_parser = parse_header
+    )]
+    pub allowed_headers: Vec<HeaderName>,
+
+    /// Let the origins send the credentials, which needs the explicit origins rather than `*`
+    #[arg(long = "cors-allow-credentials")]
+    pub allow_credentials: bool,
+}
+
+impl CorsOptions {
+    fn any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    /// Check the options before the server is started.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allow_credentials && self.any_origin() {
+            return Err(String::from(
+                "--cors-allow-credentials needs the explicit origins rather than `*`",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The layer of the CORS, none if no origin is allowed.
+    pub fn layer(&self) -> Option<CorsLayer> {
+        if self.allowed_origins.is_empty() {
+            return None;
+        }
+        let origin = if self.any_origin() {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::list(self.allowed_origins.clone())
+        };
+        Some(
+            CorsLayer::new()
+                .allow_origin(origin)
+                .allow_methods(self.allowed_methods.clone())
+                .allow_headers(self.allowed_headers.clone())
+                .allow_credentials(self.allow_credentials),
+        )
+    }
+}
+
+fn parse_origin(value: &str) -> Result<HeaderValue, String> {
+    if value != "*" && !(value.starts_with("http://") || value.starts_with("https://")) {
+        return Err(format!(
+            "invalid origin `{}`, e.g. `https://git.example.com`",
+            value
+        ));
+    }
+    // The origins of the browsers have no trailing slash.
+    HeaderValue::from_str(value.trim_end_matches('/')).map_err(|e| e.to_string())
+}
+
+fn parse_method(value: &str) -> Result<Method, String> {
+    value
+        .to_ascii_uppercase()
+        .parse()
+        .map_err(|_| format!("invalid method `{}`", value))
+}
+
+fn parse_header(value: &str) -> Result<HeaderName, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid header `{}`", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::CorsOptions;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        cors: CorsOptions,
+    }
+
+    #[test]
+    fn test_cors_options() {
+        let cors = Cli::parse_from(["mega"]).cors;
+        assert!(cors.layer().is_none());
+
+        let cors = Cli::parse_from([
+            "mega",
+            "--cors-allowed-origin",
+            "https://git.example.com/",
+            "--cors-allowed-method",
+            "get,post",
+        ])
+        .cors;
+        assert_eq!(cors.allowed_origins, ["https://git.example.com"]);
+        assert_eq!(cors.allowed_methods, ["GET", "POST"]);
+        assert!(cors.validate().is_ok());
+        assert!(cors.layer().is_some());
+
+        assert!(Cli::try_parse_from(["mega", "--cors-allowed-origin", "git.example.com"]).is_err());
+        let cors = Cli::parse_from([
+            "mega",
+            "--cors-allowed-origin",
+            "*",
+            "--cors-allow-credentials",
+        ])
+        .cors;
+        assert!(cors.validate().is_err());
+    }
+}
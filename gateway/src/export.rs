@@ -0,0 +1,386 @@
+//! Export a repository in the storage as a packfile or a git bundle without the server, e.g. to
+//! back it up or to move it to another git server.
+//!
+//! The objects reachable from the refs are collected by walking the commits, the trees and the
+//! annotated tags, and written by the pack writer with the delta compression. A bundle is the
+//! pack with the refs in a header, so it can be cloned by `git clone <file>`.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::{Args, ValueEnum};
+use common::errors::MegaError;
+use database::driver::ObjectStorage;
+use database::DataSource;
+use entity::refs;
+use git::hash::Hash;
+use git::internal::object::commit::Commit;
+use git::internal::object::tag::Tag;
+use git::internal::object::tree::{Tree, TreeItemMode};
+use git::internal::object::ObjectT;
+use git::internal::pack::encode::write_pack;
+use git::internal::ObjectType;
+
+use crate::webhook::filter::RefFilter;
+use crate::PackOptions;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The packfile of the objects only
+    Pack,
+    /// The git bundle, of the refs and the packfile
+    Bundle,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ExportOptions {
+    /// The path of the repository to export, e.g. `/projects/mega`
+    #[arg(long, value_name = "PATH")]
+    pub repo: String,
+
+    /// The file to write the pack or the bundle to
+    #[arg(long, value_name = "FILE")]
+    pub out: PathBuf,
+
+    /// Export the objects reachable from the refs matching the pattern only, e.g.
+    /// `refs/heads/main` or `refs/tags/v*`, can be repeated; all the refs if not set
+    #[arg(long = "ref", value_name = "REFSPEC")]
+    pub refs: Vec<String>,
+
+    #[arg(long, value_enum, default_value = "pack")]
+    pub format: ExportFormat,
+
+    /// The database to read the repository from, e.g. `postgres://postgres@localhost/mega`, the
+    /// one of the `data_source` if not set
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    #[arg(short, long, value_enum, default_value = "postgres")]
+    pub data_source: DataSource,
+
+    #[clap(flatten)]
+    pub pack: PackOptions,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub refs: usize,
+    pub objects: usize,
+}
+
+impl fmt::Display for ExportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "refs: {}, objects: {}", self.refs, self.objects)
+    }
+}
+
+/// An object as it's saved, so the hash of it is kept even if the parsed one would be
+/// serialized differently, e.g. a commit with a signature.
+struct RawObject {
+    hash: Hash,
+    object_type: ObjectType,
+    data: Vec<u8>,
+}
+
+impl fmt::Display for RawObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.object_type, self.hash)
+    }
+}
+
+impl ObjectT for RawObject {
+    fn get_hash(&self) -> Hash {
+        self.hash
+    }
+
+    fn set_hash(&mut self, h: Hash) {
+        self.hash = h;
+    }
+
+    fn get_type(&self) -> ObjectType {
+        self.object_type
+    }
+
+    fn get_raw(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    /// Only built from the models, of which the type is known.
+    fn new_from_data(data: Vec<u8>) -> Self {
+        RawObject {
+            hash: Hash::default(),
+            object_type: ObjectType::Blob,
+            data,
+        }
+    }
+}
+
+/// Connect the storage of the options, and export the repository to the `out` file, which is
+/// removed if the export fails.
+pub async fn export_repo(options: &ExportOptions) -> Result<ExportSummary, MegaError> {
+    let filter = RefFilter::new(&options.refs)?;
+    let storage =
+        database::storage_from_options(options.database_url.as_deref(), &options.data_source)
+            .await?;
+    let file = File::create(&options.out).map_err(|e| {
+        MegaError::new(
+            anyhow::anyhow!("failed to create {}: {}", options.out.display(), e),
+            1,
+        )
+    })?;
+    let mut out = BufWriter::new(file);
+    let result = export_repo_to(
+        storage,
+        &options.repo,
+        &filter,
+        options.format,
+        options.pack,
+        &mut out,
+    )
+    .await
+    .and_then(|summary| {
+        out.flush()?;
+        Ok(summary)
+    });
+    if result.is_err() {
+        // not to leave a broken pack
+        drop(out);
+        let _ = std::fs::remove_file(&options.out);
+    }
+    result
+}
+
+/// Write the objects reachable from the refs of the repository matching the `filter` in the
+/// `format`. It's an error if no ref matches, or an object reachable is not in the storage.
+pub async fn export_repo_to<W: Write>(
+    storage: Arc<dyn ObjectStorage>,
+    repo: &str,
+    filter: &RefFilter,
+    format: ExportFormat,
+    pack: PackOptions,
+    out: &mut W,
+) -> Result<ExportSummary, MegaError> {
+    let mut refs: Vec<refs::Model> = storage
+        .search_refs(repo)
+        .await?
+        .into_iter()
+        .filter(|r| r.repo_path == repo && filter.matches(&r.ref_name))
+        .collect();
+    if refs.is_empty() {
+        return Err(MegaError::new(
+            anyhow::anyhow!("no ref of {} to export", repo),
+            1,
+        ));
+    }
+    refs.sort_by(|a, b| a.ref_name.cmp(&b.ref_name));
+
+    let tips = refs
+        .iter()
+        .map(|r| Hash::new_from_str(&r.ref_git_id))
+        .collect();
+    let objects = reachable_objects(storage.as_ref(), tips).await?;
+    let summary = ExportSummary {
+        refs: refs.len(),
+        objects: objects.len(),
+    };
+    let data = write_pack(objects, pack.window, pack.depth)?;
+
+    if format == ExportFormat::Bundle {
+        writeln!(out, "# v2 git bundle")?;
+        for r in &refs {
+            writeln!(out, "{} {}", r.ref_git_id, r.ref_name)?;
+        }
+        writeln!(out)?;
+    }
+    out.write_all(&data)?;
+    Ok(summary)
+}
+
+/// The objects reachable from the `tips`, the commits of the submodules are not followed.
+async fn reachable_objects(
+    storage: &dyn ObjectStorage,
+    tips: Vec<Hash>,
+) -> Result<Vec<Arc<dyn ObjectT>>, MegaError> {
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<Hash> = tips.into();
+    let mut objects: Vec<Arc<dyn ObjectT>> = Vec::new();
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        let model = storage
+            .get_obj_data_by_id(&hash.to_plain_str())
+            .await?
+            .ok_or_else(|| {
+                MegaError::new(anyhow::anyhow!("the object {} is not found", hash), 1)
+            })?;
+        let object_type =
+            ObjectType::from_string(&model.object_type).map_err(|e| MegaError::new(e.into(), 1))?;
+        match object_type {
+            ObjectType::Commit => {
+                let commit = Commit::new_from_data(model.data.clone());
+                queue.push_back(commit.tree_id);
+                queue.extend(commit.parent_tree_ids);
+            }
+            ObjectType::Tree => {
+                let tree = Tree::new_from_data(model.data.clone());
+                queue.extend(
+                    tree.tree_items
+                        .into_iter()
+                        .filter(|item| item.mode != TreeItemMode::Commit)
+                        .map(|item| item.id),
+                );
+            }
+            ObjectType::Tag => queue.push_back(Tag::new_from_data(model.data.clone()).object_hash),
+            _ => {}
+        }
+        objects.push(Arc::new(RawObject {
+            hash,
+            object_type,
+            data: model.data,
+        }));
+    }
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::Path;
+    use std::process::Command;
+    use std::sync::Arc;
+
+    use git::internal::pack::encode::{DEFAULT_DEPTH, DEFAULT_WINDOW};
+    use git::internal::pack::preload::DEFAULT_MAX_DELTA_DEPTH;
+
+    use super::{export_repo_to, ExportFormat, ExportSummary};
+    use crate::import::import_pack_into;
+    use crate::tests::MockStorage;
+    use crate::webhook::filter::RefFilter;
+    use crate::PackOptions;
+
+    const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+    const REPO: &str = "/projects/d50df";
+    /// The last commit of the pack, and one of the 30th from it.
+    const HEAD: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+    const OLD: &str = "a5c709196faf51169ea911b94bb8144f8fc5f297";
+
+    const OPTIONS: PackOptions = PackOptions {
+        window: DEFAULT_WINDOW,
+        depth: DEFAULT_DEPTH,
+        upload_pack_keepalive: 0,
+        decode_threads: None,
+        max_delta_depth: DEFAULT_MAX_DELTA_DEPTH,
+    };
+
+    async fn storage() -> Arc<MockStorage> {
+        let storage = Arc::new(MockStorage::default());
+        import_pack_into(File::open(PACK).unwrap(), storage.clone(), 100)
+            .await
+            .unwrap();
+        storage.add_ref(REPO, "refs/heads/main", HEAD);
+        storage.add_ref(REPO, "refs/tags/old", OLD);
+        storage
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_pack() {
+        let storage = storage().await;
+        let filter = RefFilter::new::<&str>(&[]).unwrap();
+        let mut data = Vec::new();
+        let summary = export_repo_to(
+            storage,
+            REPO,
+            &filter,
+            ExportFormat::Pack,
+            OPTIONS,
+            &mut data,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            summary,
+            ExportSummary {
+                refs: 2,
+                objects: 324
+            }
+        );
+
+        let dir = std::env::temp_dir().join("mega_export_pack");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "--quiet", "--bare"]);
+        std::fs::write(dir.join("objects/pack/export.pack"), &data).unwrap();
+        git(&dir, &["index-pack", "objects/pack/export.pack"]);
+        git(&dir, &["update-ref", "refs/heads/main", HEAD]);
+        git(&dir, &["fsck", "--strict"]);
+        assert_eq!(git(&dir, &["rev-list", "--count", "main"]), "37\n");
+    }
+
+    #[tokio::test]
+    async fn test_export_bundle() {
+        let storage = storage().await;
+        let filter = RefFilter::new(&["refs/tags/*"]).unwrap();
+        let mut data = Vec::new();
+        let summary = export_repo_to(
+            storage.clone(),
+            REPO,
+            &filter,
+            ExportFormat::Bundle,
+            OPTIONS,
+            &mut data,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            summary,
+            ExportSummary {
+                refs: 1,
+                objects: 105
+            }
+        );
+
+        let dir = std::env::temp_dir().join("mega_export_bundle");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("export.bundle"), &data).unwrap();
+        git(
+            &dir,
+            &["clone", "--quiet", "--mirror", "export.bundle", "repo"],
+        );
+        let refs = git(&dir.join("repo"), &["show-ref"]);
+        assert_eq!(refs, format!("{} refs/tags/old\n", OLD));
+
+        // no ref of the other repos
+        let err = export_repo_to(
+            storage,
+            "/projects/other",
+            &filter,
+            ExportFormat::Bundle,
+            OPTIONS,
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code, 1);
+    }
+}
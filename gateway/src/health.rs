@@ -0,0 +1,140 @@
+//! The liveness and readiness probes, e.g. of Kubernetes.
+//!
+//! `GET /healthz` is always ok while the process is up, and `GET /readyz` checks the storage and
+//! the Redis if any, which are not ready if unreachable or not responding in time.
+
+use std::future::Future;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::Json;
+use hyper::StatusCode;
+use kvcache::connector::Connector;
+use serde_json::{json, Value};
+
+use crate::https::AppState;
+
+/// The probe should not hang with a hung database.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The commit is set by `MEGA_BUILD_COMMIT` at build time, e.g. `git rev-parse --short HEAD`.
+fn build_info() -> Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_version": git::VERSION,
+        "commit": option_env!("MEGA_BUILD_COMMIT").unwrap_or("unknown"),
+    })
+}
+
+pub async fn healthz() -> Json<Value> {
+    let mut body = build_info();
+    body["status"] = json!("ok");
+    Json(body)
+}
+
+/// Ping a dependency, the error answered if it fails or doesn't respond in time.
+async fn check(ping: impl Future<Output = Result<(), String>>) -> Result<(), String> {
+    match tokio::time::timeout(READY_TIMEOUT, ping).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("no response in {:?}", READY_TIMEOUT)),
+    }
+}
+
+pub async fn readyz(state: State<AppState>) -> (StatusCode, Json<Value>) {
+    let storage = check(async { state.storage.ping().await.map_err(|e| e.to_string()) });
+    let kvcache = async {
+        let kvcache = state.kvcache.clone()?;
+        // the client of Redis is blocking
+        let ping = async move {
+            match tokio::task::spawn_blocking(move || kvcache.ping()).await {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        };
+        Some(check(ping).await)
+    };
+    let (storage, kvcache) = tokio::join!(storage, kvcache);
+
+    let mut status = StatusCode::OK;
+    let mut body = json!({"status": "ok"});
+    // the Redis is checked only if it's set
+    for (name, result) in [("storage", Some(storage)), ("kvcache", kvcache)] {
+        match result {
+            Some(Ok(())) => body[name] = json!("ok"),
+            Some(Err(e)) => {
+                tracing::warn!("{} is not ready: {}", name, e);
+                status = StatusCode::SERVICE_UNAVAILABLE;
+                body["status"] = json!("unavailable");
+                body[name] = json!(e);
+            }
+            None => {}
+        }
+    }
+    (status, Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use database::DataSource;
+    use hyper::{Body, Request, StatusCode};
+    use kvcache::connector::redis::RedisClient;
+    use redis::IntoConnectionInfo;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::https::{app, tests::state, AppState};
+
+    async fn get(state: AppState, uri: &str) -> (StatusCode, Value) {
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let resp = app(state).oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let (status, body) = get(state(), "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["git_version"], git::VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_readyz() {
+        let (status, body) = get(state(), "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["storage"], "ok");
+        assert!(body.get("kvcache").is_none());
+
+        // the storage of which the database connection failed
+        let state = AppState {
+            storage: database::disconnected_storage(&DataSource::Postgres),
+            ..state()
+        };
+        let (status, body) = get(state, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_readyz_kvcache() {
+        let kvcache = |url: &str| {
+            let info = url.into_connection_info().unwrap();
+            Some(Arc::new(RedisClient::with_pool(info, 1, 0).unwrap()))
+        };
+        // nothing listens on the port 1
+        let state = AppState {
+            kvcache: kvcache("redis://127.0.0.1:1"),
+            ..state()
+        };
+        let (status, body) = get(state, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["storage"], "ok");
+        assert_ne!(body["kvcache"], "ok");
+        assert!(body["kvcache"].is_string());
+    }
+}
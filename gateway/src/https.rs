@@ -5,58 +5,512 @@
 //!
 
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::str::FromStr;
-use std::{net::SocketAddr, sync::Arc};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{io, net::SocketAddr, sync::Arc};
 
 use anyhow::Result;
 
-use axum::extract::{Query, State};
+use axum::extract::{ConnectInfo, Query, State};
+use axum::middleware;
 use axum::response::Response;
 use axum::routing::get;
-use axum::{Router, Server};
-use clap::Args;
-use database::driver::lfs::structs::LockListQuery;
+use axum::Router;
+use clap::{Args, Command, FromArgMatches, ValueEnum};
+use common::errors::MegaError;
+use database::driver::lfs::s3::S3Config;
+use database::driver::lfs::storage::LfsStorage;
+use database::driver::lfs::structs::{LockListQuery, User};
 use database::driver::ObjectStorage;
 use database::DataSource;
+use futures::stream;
+use git::internal::commit_graph::CommitGraphCache;
+use git::internal::object_cache::SharedObjectCache;
+use git::lfs::partial::{self, PartialUploads};
 use git::lfs::{self, LfsConfig};
-use git::protocol::{http, ServiceType};
+use git::protocol::dumb::{self, DumbFile};
+use git::protocol::namespace;
+use git::protocol::{http, v2, ServiceType};
 use git::protocol::{PackProtocol, Protocol};
-use hyper::{Body, Request, StatusCode, Uri};
+use hyper::header::{HeaderValue, HOST};
+use hyper::server::conn::Http;
+use hyper::{Body, HeaderMap, Request, StatusCode, Uri};
+use kvcache::connector::redis::RedisClient;
 use regex::Regex;
 use serde::Deserialize;
-use tower::ServiceBuilder;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+use crate::api_service::blame_service::BlameCache;
+use crate::auth::{auth_layer, Authenticator, FileAuthenticator};
+use crate::body_limit::{body_limit_layer, BodyLimitOptions};
+use crate::bundle::{BundleOptions, Bundles};
+use crate::client_ip::{client_ip_layer, ProxyOptions};
+use crate::cors::CorsOptions;
+use crate::{compression, health, logging, metrics};
+use crate::rate_limit::{rate_limit_layer, RateLimitOptions, RateLimits};
+use crate::read_through::{ReadThrough, ReadThroughOptions, READ_THROUGH_HEADER};
+use crate::replication::{ReplicatedHooks, ReplicationOptions, Replicator};
+use crate::repo_config::{RepoConfig, RepoConfigs};
+use crate::shutdown::{Connections, Shutdown, ShutdownOptions};
+use crate::timeout::{IdleStream, TimeoutOptions, Timeouts};
+use crate::tls::TlsServer;
+use crate::uds::{self, UnixServer};
+use crate::{HookOptions, PackOptions};
+use crate::webhook::delivery::{DeliveryOptions, Dispatcher};
+use crate::webhook::filter::RefFilter;
+use crate::webhook::{self, Event};
+
+/// How often the expired partial LFS uploads and the old delivery attempts are removed.
+const PARTIAL_UPLOADS_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 /// Parameters for starting the HTTP service
 #[derive(Args, Clone, Debug)]
 pub struct HttpOptions {
-    /// Server start hostname
+    /// Server start hostname, or the Unix domain socket like `unix:/run/mega/http.sock`
     #[arg(long, default_value_t = String::from("127.0.0.1"))]
     pub host: String,
 
     #[arg(short, long, default_value_t = 8000)]
     pub port: u16,
 
-    #[arg(short, long, value_name = "FILE")]
-    key_path: Option<PathBuf>,
+    /// Listen on these addresses at once rather than the `host` and the `port`, like
+    /// `10.0.0.1:8000`, `[2001:db8::1]:443` or `unix:/run/mega/http.sock`
+    #[arg(long, value_name = "ADDR", value_parser = ListenAddr::parse)]
+    pub listen: Vec<ListenAddr>,
+
+    /// The permissions of the Unix domain sockets to listen on, in octal
+    #[arg(long, value_name = "MODE", default_value = "660", value_parser = uds::parse_mode)]
+    pub unix_socket_mode: u32,
+
+    /// The private key in PEM, serve over TLS with the `tls_cert`
+    #[arg(short = 'k', long, alias = "key-path", value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
 
-    #[arg(short, long, value_name = "FILE")]
-    cert_path: Option<PathBuf>,
+    /// The certificate chain in PEM, serve over TLS with the `tls_key`
+    #[arg(short = 'c', long, alias = "cert-path", value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
 
     #[arg(short, long, default_value_os_t = PathBuf::from("lfs_content"))]
     pub lfs_content_path: PathBuf,
 
     #[arg(short, long, value_enum, default_value = "postgres")]
     pub data_source: DataSource,
+
+    /// The database like `mysql://root@localhost/mega` or `memory://`, of which the scheme
+    /// selects the backend rather than the `data_source`
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    /// The storage of the LFS objects content
+    #[arg(long, value_enum, default_value = "local")]
+    pub lfs_storage: LfsStorageType,
+
+    /// Remove the partial LFS uploads not resumed in this time, in seconds
+    #[arg(long, default_value_t = partial::DEFAULT_TTL.as_secs())]
+    pub lfs_partial_ttl: u64,
+
+    /// Check the SHA-256 of the LFS objects against the oid as they are downloaded, to catch
+    /// the corruption of the storage
+    #[arg(long)]
+    pub lfs_verify_download: bool,
+
+    /// The max size of an LFS object in bytes, the larger ones are rejected by the batch API
+    #[arg(long, value_name = "BYTES")]
+    pub max_lfs_object_size: Option<u64>,
+
+    /// Reject the pushes of the blobs larger than this many bytes which are not LFS pointers,
+    /// so the large files are tracked by LFS
+    #[arg(long, value_name = "BYTES")]
+    pub enforce_lfs_threshold: Option<u64>,
+
+    /// The memory of the objects cached for the fetches of all the repos in MiB, which is
+    /// warmed and flushed by the `/admin/cache` API, 0 to disable the cache
+    #[arg(long, value_name = "MIB", default_value_t = 64)]
+    pub object_cache_size: usize,
+
+    #[clap(flatten)]
+    pub s3: S3Options,
+
+    /// The users allowed to fetch and push, one `name:secret[:read|:admin]` per line, the server
+    /// is open to everyone if not set
+    #[arg(long, value_name = "FILE")]
+    pub auth_file: Option<PathBuf>,
+
+    /// Allow to fetch and clone without credentials, used with the `auth_file`
+    #[arg(long)]
+    pub anonymous_read: bool,
+
+    #[clap(flatten)]
+    pub rate_limit: RateLimitOptions,
+
+    #[clap(flatten)]
+    pub proxy: ProxyOptions,
+
+    #[clap(flatten)]
+    pub body_limit: BodyLimitOptions,
+
+    #[clap(flatten)]
+    pub cors: CorsOptions,
+
+    #[clap(flatten)]
+    pub delivery: DeliveryOptions,
+
+    #[clap(flatten)]
+    pub pack: PackOptions,
+
+    #[clap(flatten)]
+    pub hooks: HookOptions,
+
+    #[clap(flatten)]
+    pub replication: ReplicationOptions,
+
+    #[clap(flatten)]
+    pub read_through: ReadThroughOptions,
+
+    #[clap(flatten)]
+    pub bundle: BundleOptions,
+
+    /// Serve the `/metrics` on this admin port rather than the `port`
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    #[clap(flatten)]
+    pub timeouts: TimeoutOptions,
+
+    #[clap(flatten)]
+    pub shutdown: ShutdownOptions,
+}
+
+/// An address of the server to listen on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp { host: String, port: u16 },
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// Parse the address like `host:port`, `[ipv6]:port` or `unix:/path/to/sock`.
+    pub fn parse(addr: &str) -> Result<ListenAddr, String> {
+        if let Some(path) = uds::socket_path(addr) {
+            if path.as_os_str().is_empty() {
+                return Err(String::from("the path of the Unix domain socket is empty"));
+            }
+            return Ok(ListenAddr::Unix(path.to_owned()));
+        }
+        let invalid = || format!("{} is not like host:port", addr);
+        let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse().map_err(|_| invalid())?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(ListenAddr::Tcp {
+            host: host.to_owned(),
+            port,
+        })
+    }
+
+    /// Bind the address, of which the error tells the address.
+    pub async fn bind(&self, unix_socket_mode: u32) -> std::io::Result<Listener> {
+        match self {
+            ListenAddr::Tcp { host, port } => Ok(Listener::Tcp(crate::bind(host, *port).await?)),
+            ListenAddr::Unix(path) => Ok(Listener::Unix(UnixServer::bind(path, unix_socket_mode)?)),
+        }
+    }
+}
+
+/// A bound address of the server.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixServer),
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfsStorageType {
+    /// Save the objects in the `lfs_content_path`
+    Local,
+    /// Save the objects in S3 or the compatible services like MinIO
+    S3,
+}
+
+/// Parameters of the S3 storage, used with `--lfs-storage s3`
+#[derive(Args, Clone, Debug)]
+pub struct S3Options {
+    /// The endpoint of S3, e.g. `http://127.0.0.1:9000` of MinIO
+    #[arg(long = "s3-endpoint", env = "MEGA_S3_ENDPOINT", default_value_t = String::from("https://s3.amazonaws.com"))]
+    pub endpoint: String,
+
+    #[arg(long = "s3-bucket", env = "MEGA_S3_BUCKET", default_value_t = String::from("mega"))]
+    pub bucket: String,
+
+    #[arg(long = "s3-region", env = "MEGA_S3_REGION", default_value_t = String::from("us-east-1"))]
+    pub region: String,
+
+    #[arg(long = "s3-access-key", env = "MEGA_S3_ACCESS_KEY", default_value_t = String::new(), hide_env_values = true)]
+    pub access_key: String,
+
+    #[arg(long = "s3-secret-key", env = "MEGA_S3_SECRET_KEY", default_value_t = String::new(), hide_env_values = true)]
+    pub secret_key: String,
+
+    /// Let the clients download the objects from S3 by presigned urls instead of the gateway
+    #[arg(long = "s3-presign")]
+    pub presign: bool,
+}
+
+impl From<S3Options> for S3Config {
+    fn from(value: S3Options) -> Self {
+        Self {
+            endpoint: value.endpoint,
+            bucket: value.bucket,
+            region: value.region,
+            access_key: value.access_key,
+            secret_key: value.secret_key,
+            presign: value.presign,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub storage: Arc<dyn ObjectStorage>,
+    pub lfs_storage: Arc<dyn LfsStorage>,
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    /// The Redis of the `REDIS_CONFIG` env, of which the `/readyz` checks the connection.
+    pub kvcache: Option<Arc<RedisClient<String, Vec<u8>>>>,
+    pub rate_limits: Arc<RateLimits>,
+    /// Deliver the push events if any webhook url is set.
+    pub webhooks: Option<Arc<Dispatcher>>,
+    /// Replicate the pushes if any replication url is set.
+    pub replicator: Option<Arc<Replicator>>,
+    /// Fetch the missing objects from the upstream if the upstream url is set.
+    pub read_through: Option<Arc<ReadThrough>>,
+    /// Advertise the bundles of the repos to the clones if the bundle dir is set.
+    pub bundles: Option<Arc<Bundles>>,
+    /// The commit-graphs of the fetch negotiation, updated by the pushes.
+    pub commit_graphs: Arc<CommitGraphCache>,
+    /// The blames of the API of the files of the commits.
+    pub blames: Arc<BlameCache>,
+    /// The objects of the fetches.
+    pub objects: Arc<SharedObjectCache>,
+    /// The settings of the repos overriding the `options`.
+    pub repo_configs: Arc<RepoConfigs>,
     pub options: HttpOptions,
 }
 
+impl AppState {
+    /// The storages are shared by all the requests rather than created for each one.
+    fn lfs_config(&self) -> LfsConfig {
+        LfsConfig {
+            host: self.options.host.clone(),
+            port: self.options.port,
+            storage: self.storage.clone(),
+            lfs_storage: self.lfs_storage.clone(),
+            partial_uploads: self.options.partial_uploads(),
+            verify_download: self.options.lfs_verify_download,
+            max_object_size: self.options.max_lfs_object_size,
+        }
+    }
+
+    /// The LFS config of the repo of the LFS request, of which the settings of the repo like
+    /// the `max_lfs_object_size` override the options.
+    async fn repo_lfs_config(&self, uri: &Uri) -> Result<LfsConfig, (StatusCode, String)> {
+        let repo_config = self.repo_config(&lfs_repo_path(uri)).await?;
+        let lfs_config = self.lfs_config();
+        Ok(LfsConfig {
+            max_object_size: repo_config
+                .max_lfs_object_size
+                .or(lfs_config.max_object_size),
+            ..lfs_config
+        })
+    }
+
+    /// The storage of the repo for the request, of which the missing objects are read through,
+    /// unless the request is of the read-through of another server.
+    fn storage_of(&self, repo_path: &Path, headers: &HeaderMap) -> Arc<dyn ObjectStorage> {
+        match &self.read_through {
+            Some(read_through) if !headers.contains_key(READ_THROUGH_HEADER) => {
+                read_through.storage(repo_path)
+            }
+            _ => self.storage.clone(),
+        }
+    }
+
+    /// The uri of the bundle of the repo to advertise, of the host the client requests. None of
+    /// a namespace, as the bundle is of the refs of the repo.
+    fn bundle_uri(
+        &self,
+        repo_path: &Path,
+        namespace: Option<&str>,
+        headers: &HeaderMap,
+    ) -> Option<String> {
+        let bundles = self.bundles.as_ref().filter(|_| namespace.is_none())?;
+        let scheme = match self.options.tls_cert {
+            Some(_) => "https",
+            None => "http",
+        };
+        let host = match headers.get(HOST).and_then(|host| host.to_str().ok()) {
+            Some(host) => host.to_owned(),
+            None => format!("{}:{}", self.options.host, self.options.port),
+        };
+        bundles.uri(repo_path, &format!("{}://{}", scheme, host))
+    }
+
+    async fn repo_config(
+        &self,
+        repo_path: &std::path::Path,
+    ) -> Result<Arc<RepoConfig>, (StatusCode, String)> {
+        let repo_path = repo_path.to_string_lossy();
+        self.repo_configs
+            .get(&self.storage, &repo_path)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to read the config of {}: {}", repo_path, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    String::from("Failed to read the config of the repo"),
+                )
+            })
+    }
+}
+
+impl HttpOptions {
+    /// The partial uploads are in the local files even if the objects are in S3.
+    pub fn partial_uploads(&self) -> PartialUploads {
+        PartialUploads::new(
+            self.lfs_content_path.join("partial"),
+            Duration::from_secs(self.lfs_partial_ttl),
+        )
+    }
+
+    /// The addresses to listen on, the `host` and the `port` if no `listen` is set.
+    pub fn listen_addrs(&self) -> Vec<ListenAddr> {
+        if !self.listen.is_empty() {
+            return self.listen.clone();
+        }
+        match uds::socket_path(&self.host) {
+            Some(path) => vec![ListenAddr::Unix(path.to_owned())],
+            None => vec![ListenAddr::Tcp {
+                host: self.host.clone(),
+                port: self.port,
+            }],
+        }
+    }
+
+    /// Build the options without the command line, e.g. to embed the gateway, of which the
+    /// fields not set are the defaults of the command line.
+    pub fn builder() -> HttpOptionsBuilder {
+        let command = HttpOptions::augment_args(Command::new("mega"));
+        let matches = command.get_matches_from(["mega"]);
+        HttpOptions::from_arg_matches(&matches)
+            .expect("the defaults of the options are valid")
+            .into()
+    }
+}
+
+/// The builder of the [`HttpOptions`], which are validated by the [`HttpOptionsBuilder::build`].
+#[derive(Clone, Debug)]
+pub struct HttpOptionsBuilder {
+    options: HttpOptions,
+}
+
+impl From<HttpOptions> for HttpOptionsBuilder {
+    /// Validate the options of the command line by the builder.
+    fn from(options: HttpOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl HttpOptionsBuilder {
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.options.host = host.into();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.options.port = port;
+        self
+    }
+
+    /// The path is created by the server, but the parent of it must exist.
+    pub fn lfs_content_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.lfs_content_path = path.into();
+        self
+    }
+
+    /// Listen on the address too, rather than the `host` and the `port`.
+    pub fn listen(mut self, addr: ListenAddr) -> Self {
+        self.options.listen.push(addr);
+        self
+    }
+
+    pub fn data_source(mut self, data_source: DataSource) -> Self {
+        self.options.data_source = data_source;
+        self
+    }
+
+    /// Connect the database of the url rather than the one of the data source.
+    pub fn database_url(mut self, url: impl Into<String>) -> Self {
+        self.options.database_url = Some(url.into());
+        self
+    }
+
+    pub fn lfs_storage(mut self, lfs_storage: LfsStorageType) -> Self {
+        self.options.lfs_storage = lfs_storage;
+        self
+    }
+
+    /// Serve over TLS with the private key and the certificate chain in PEM.
+    pub fn tls(mut self, key: impl Into<PathBuf>, cert: impl Into<PathBuf>) -> Self {
+        self.options.tls_key = Some(key.into());
+        self.options.tls_cert = Some(cert.into());
+        self
+    }
+
+    pub fn build(self) -> Result<HttpOptions, MegaError> {
+        let options = self.options;
+        let invalid = |message: String| Err(MegaError::new(anyhow::anyhow!(message), 1));
+        if options.host.trim().is_empty() {
+            return invalid(String::from("the host is empty"));
+        }
+        if uds::socket_path(&options.host).is_some_and(|path| path.as_os_str().is_empty()) {
+            return invalid(String::from("the path of the Unix domain socket is empty"));
+        }
+        if options.port == 0 {
+            return invalid(String::from("the port must be greater than 0"));
+        }
+        for addr in &options.listen {
+            if let ListenAddr::Tcp { host, port: 0 } = addr {
+                return invalid(format!(
+                    "the port of the listen address {} must be greater than 0",
+                    host
+                ));
+            }
+        }
+        if let Some(url) = &options.database_url {
+            DataSource::parse_url(url)?;
+        }
+        // a relative path like `lfs_content` is of the current directory
+        let parent = match options.lfs_content_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => std::path::Path::new("."),
+        };
+        if !parent.is_dir() {
+            return invalid(format!(
+                "the parent of the lfs content path {} does not exist",
+                options.lfs_content_path.display()
+            ));
+        }
+        Ok(options)
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct GetParams {
     pub service: Option<String>,
@@ -71,46 +525,384 @@ pub fn remove_git_suffix(uri: Uri, git_suffix: &str) -> PathBuf {
     PathBuf::from(uri.path().replace(".git", "").replace(git_suffix, ""))
 }
 
-pub async fn http_server(options: &HttpOptions) -> Result<(), Box<dyn std::error::Error>> {
-    let HttpOptions {
-        host,
-        port,
-        key_path: _,
-        cert_path: _,
-        lfs_content_path: _,
-        data_source,
-    } = options;
-    let server_url = format!("{}:{}", host, port);
+/// The header of the ref namespace of a git request, of which the path segment takes the
+/// place, see [`namespace`].
+pub const NAMESPACE_HEADER: &str = "Git-Namespace";
 
-    // let config =  LfsConfig::from(options.to_owned());
-    let state = AppState {
-        storage:database::init(data_source).await,
-        options: options.to_owned(),
+/// The repo of the git request of the `git_suffix`, and the ref namespace of it by the path,
+/// like `/projects/demo.git/namespaces/a/info/refs`, or the [`NAMESPACE_HEADER`].
+fn git_repo(
+    uri: &Uri,
+    headers: &HeaderMap,
+    git_suffix: &str,
+) -> Result<(PathBuf, Option<String>), (StatusCode, String)> {
+    let invalid = || (StatusCode::BAD_REQUEST, String::from("Invalid namespace\n"));
+    let path = uri.path().strip_suffix(git_suffix).unwrap_or(uri.path());
+    let (repo, namespace) = namespace::split_path(path).ok_or_else(invalid)?;
+    let namespace = match (namespace, headers.get(NAMESPACE_HEADER)) {
+        (Some(namespace), _) => Some(namespace.to_owned()),
+        (None, Some(value)) => {
+            let namespace = value.to_str().ok().filter(|ns| namespace::is_valid(ns));
+            Some(namespace.ok_or_else(invalid)?.to_owned())
+        }
+        (None, None) => None,
     };
-    let app = Router::new()
-        .nest("/api/v1", api_routers::routers(state.clone()))
+    Ok((PathBuf::from(repo.replace(".git", "")), namespace))
+}
+
+/// The `GIT_PROTOCOL` of the client, e.g. `version=2` of the protocol v2.
+fn git_protocol(headers: &HeaderMap) -> Option<&str> {
+    headers.get(v2::GIT_PROTOCOL_HEADER)?.to_str().ok()
+}
+
+/// The prefixes of the refs to advertise by the `ref-prefix` parameters, e.g.
+/// `info/refs?service=git-upload-pack&ref-prefix=refs/heads/`, none of which is all the refs.
+fn ref_prefixes(uri: &Uri) -> Vec<String> {
+    let Ok(Query(params)) = Query::<Vec<(String, String)>>::try_from_uri(uri) else {
+        return Vec::new();
+    };
+    params
+        .into_iter()
+        .filter(|(key, _)| key == "ref-prefix")
+        .map(|(_, prefix)| prefix)
+        .collect()
+}
+
+pub fn app(state: AppState) -> Router {
+    let router = Router::new()
         .route(
             "/*path",
             get(get_method_router)
+                .head(head_method_router)
                 .post(post_method_router)
-                .put(put_method_router),
+                .put(put_method_router)
+                .patch(patch_method_router),
         )
-        .layer(ServiceBuilder::new().layer(CorsLayer::new().allow_origin(Any)))
-        .with_state(state);
+        // Limit the bodies as they are read by the handlers.
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            body_limit_layer,
+        ))
+        // Check the credentials of the git and LFS requests before handling them.
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth_layer))
+        // Limit the requests even before the authentication.
+        .route_layer(middleware::from_fn_with_state(state.clone(), rate_limit_layer))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .nest("/api/v1", api_routers::routers(state.clone()))
+        .merge(api_routers::admin_routers(state.clone()));
+    let router = match state.options.metrics_port {
+        Some(_) => router,
+        None => router.route("/metrics", get(metrics::metrics_handler)),
+    };
+    // Answer the preflights before the rate limiting and the authentication.
+    let router = match state.options.cors.layer() {
+        Some(cors) => router.layer(cors),
+        None => router,
+    };
+    router
+        // Decode the requests before the body limit, of which the decoded bytes are counted.
+        .layer(middleware::from_fn(compression::compression_layer))
+        .layer(middleware::from_fn_with_state("http", metrics::metrics_layer))
+        .layer(middleware::from_fn(logging::trace_layer))
+        // The client IP is logged and rate limited.
+        .layer(middleware::from_fn_with_state(state.clone(), client_ip_layer))
+        .with_state(state)
+}
+
+/// Serve until the `shutdown`, of which the requests in flight are finished in the grace period.
+pub async fn http_server(
+    options: &HttpOptions,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let HttpOptions {
+        host: _,
+        port: _,
+        listen: _,
+        unix_socket_mode,
+        tls_key,
+        tls_cert,
+        lfs_content_path,
+        data_source,
+        database_url,
+        lfs_storage: _,
+        lfs_partial_ttl: _,
+        lfs_verify_download: _,
+        max_lfs_object_size: _,
+        enforce_lfs_threshold: _,
+        object_cache_size,
+        s3: _,
+        auth_file,
+        anonymous_read: _,
+        rate_limit,
+        proxy: _,
+        body_limit: _,
+        cors,
+        delivery,
+        pack,
+        hooks: _,
+        replication,
+        read_through,
+        bundle,
+        metrics_port,
+        timeouts,
+        shutdown: shutdown_options,
+    } = options;
+    // Fail fast on the bad certificate or the port in use before anything else is started.
+    crate::prepare_lfs_content_path(lfs_content_path)?;
+    cors.validate()?;
+    let addrs = options.listen_addrs();
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in &addrs {
+        listeners.push(addr.bind(*unix_socket_mode).await?);
+    }
+    // The admin port is of the first TCP host, or the loopback if the server is on sockets only.
+    let metrics_host = addrs
+        .iter()
+        .find_map(|addr| match addr {
+            ListenAddr::Tcp { host, .. } => Some(host.as_str()),
+            ListenAddr::Unix(_) => None,
+        })
+        .unwrap_or("127.0.0.1");
+    let metrics_listener = crate::bind_metrics(metrics_host, *metrics_port).await?;
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some(Arc::new(TlsServer::new(cert.clone(), key.clone())?)),
+        _ => None,
+    };
+    let authenticator = match auth_file {
+        Some(path) => Some(Arc::new(FileAuthenticator::load(path)?) as Arc<dyn Authenticator>),
+        None => None,
+    };
+
+    // Fail fast if the database is down at the start, so the server is restarted until it's up.
+    // The connections lost later are reconnected by the pool, and reported by the `/readyz`.
+    let storage = database::storage_from_options(database_url.as_deref(), data_source)
+        .await
+        .map_err(|e| io::Error::other(format!("failed to connect the database: {}", e)))?;
+    crate::migrate::warn_pending_migrations(&storage).await;
+    let kvcache = RedisClient::from_env()
+        .map_err(|e| io::Error::other(format!("invalid Redis config: {}", e)))?
+        .map(Arc::new);
+    // Even without the `--webhook-url`, a repo may have the receivers of its own.
+    let webhooks = Arc::new(Dispatcher::new(delivery)?);
+    // Deliver the events pending before the restart.
+    webhooks.resume()?;
+    // The commit-graphs of the replications are the ones of the fetches.
+    let commit_graphs = Arc::new(CommitGraphCache::default());
+    let replicator =
+        Replicator::new(replication, storage.clone(), commit_graphs.clone(), *pack)?.map(Arc::new);
+    let lfs_storage = crate::lfs_storage_from_options(options);
+    let objects = Arc::new(SharedObjectCache::new(object_cache_size * 1024 * 1024));
+    let read_through = ReadThrough::new(
+        read_through,
+        storage.clone(),
+        lfs_storage.clone(),
+        objects.clone(),
+    )?
+    .map(Arc::new);
+    let bundles =
+        Bundles::new(bundle, storage.clone(), commit_graphs.clone(), *pack)?.map(Arc::new);
+    if let Some(bundles) = &bundles {
+        bundles.clone().spawn();
+    }
+    let state = AppState {
+        storage,
+        lfs_storage,
+        authenticator,
+        kvcache,
+        rate_limits: Arc::new(rate_limit.into()),
+        webhooks: Some(webhooks),
+        replicator,
+        read_through,
+        bundles,
+        commit_graphs,
+        blames: Arc::default(),
+        objects,
+        repo_configs: Arc::default(),
+        options: options.to_owned(),
+    };
+    // Remove the partial uploads which are never resumed, and the old delivery attempts.
+    let partial_uploads = options.partial_uploads();
+    let webhooks = state.webhooks.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PARTIAL_UPLOADS_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = partial_uploads.remove_expired().await {
+                tracing::warn!("Failed to remove the expired LFS uploads: {}", e);
+            }
+            if let Some(Err(e)) = webhooks.as_ref().map(|webhooks| webhooks.prune_log()) {
+                tracing::warn!("Failed to prune the log of the deliveries: {}", e);
+            }
+        }
+    });
+    let app = app(state);
+
+    if let Some(metrics_listener) = metrics_listener {
+        tokio::spawn(metrics::serve(metrics_listener));
+    }
+    serve_all(
+        listeners,
+        tls,
+        app,
+        shutdown,
+        shutdown_options.grace_period(),
+        timeouts.timeouts(),
+    )
+    .await
+}
 
-    let addr = SocketAddr::from_str(&server_url).unwrap();
-    Server::bind(&addr).serve(app.into_make_service()).await?;
+/// Serve the app on all the listeners like the [`serve`] of each, which are shut down together.
+/// If one of them fails, the others are shut down too, and the error of it is returned.
+pub async fn serve_all(
+    listeners: Vec<Listener>,
+    tls: Option<Arc<TlsServer>>,
+    app: Router,
+    shutdown: Shutdown,
+    grace: Duration,
+    timeouts: Timeouts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let servers = listeners.into_iter().map(|listener| {
+        let (tls, app, shutdown) = (tls.clone(), app.clone(), shutdown.clone());
+        async move {
+            let result = match listener {
+                Listener::Tcp(listener) => {
+                    serve(listener, tls, app, shutdown.clone(), grace, timeouts).await
+                }
+                // The proxy in front of the socket terminates the TLS.
+                Listener::Unix(server) => {
+                    let server = server.serve(app, shutdown.clone(), timeouts);
+                    match shutdown.graceful(server, grace).await.transpose() {
+                        Ok(Some(())) => {
+                            tracing::info!("The server is shut down");
+                            Ok(())
+                        }
+                        Ok(None) => Ok(()),
+                        Err(e) => Err(e.into()),
+                    }
+                }
+            };
+            if result.is_err() {
+                shutdown.trigger();
+            }
+            result
+        }
+    });
+    for result in futures::future::join_all(servers).await {
+        result?;
+    }
+    Ok(())
+}
+
+/// Serve the app on the listener until the `shutdown`, and then wait at most the `grace` period
+/// for the requests in flight. The connections are dropped by the `timeouts`.
+pub async fn serve(
+    listener: TcpListener,
+    tls: Option<Arc<TlsServer>>,
+    app: Router,
+    shutdown: Shutdown,
+    grace: Duration,
+    timeouts: Timeouts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let result = match tls {
+        Some(tls) => {
+            let server = tls.serve(listener, app, shutdown.clone(), timeouts);
+            shutdown.graceful(server, grace).await.transpose()?
+        }
+        None => {
+            let server = serve_tcp(listener, app, shutdown.clone(), timeouts);
+            shutdown.graceful(server, grace).await.transpose()?
+        }
+    };
+    if result.is_some() {
+        tracing::info!("The server is shut down");
+    }
+    Ok(())
+}
 
+/// Accept the connections until the `shutdown` like the [`TlsServer`] without the TLS, and
+/// return once all of them are closed.
+async fn serve_tcp(
+    listener: TcpListener,
+    app: Router,
+    shutdown: Shutdown,
+    timeouts: Timeouts,
+) -> std::io::Result<()> {
+    let connections = Connections::new();
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                // Like the hyper server, retry after the errors like too many open files, which
+                // are gone once some connections are closed.
+                Err(e) => {
+                    tracing::warn!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            },
+            _ = shutdown.triggered() => break,
+        };
+        let handshake_done = Arc::new(AtomicBool::new(false));
+        let stream = IdleStream::new(stream, timeouts, handshake_done.clone());
+        let app = app.clone();
+        let shutdown = shutdown.clone();
+        let guard = connections.guard();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let result = serve_connection(stream, app, Some(peer), handshake_done, shutdown).await;
+            if let Err(e) = result {
+                tracing::warn!("Failed to serve connection {}: {}", peer, e);
+            }
+        });
+    }
+    drop(listener);
+    connections.closed().await;
     Ok(())
 }
 
+/// Serve the requests of the connection, which is closed after the request in flight once the
+/// `shutdown` is triggered. The handshake of the connection is done by the first request.
+pub(crate) async fn serve_connection<S>(
+    stream: S,
+    app: Router,
+    peer: Option<SocketAddr>,
+    handshake_done: Arc<AtomicBool>,
+    shutdown: Shutdown,
+) -> Result<(), hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // The client IP is used by the rate limiting.
+    let service = tower::service_fn(move |mut req: Request<Body>| {
+        handshake_done.store(true, Ordering::Relaxed);
+        if let Some(peer) = peer {
+            req.extensions_mut().insert(ConnectInfo(peer));
+        }
+        app.clone().oneshot(req)
+    });
+    let connection = Http::new().serve_connection(stream, service);
+    let triggered = shutdown.triggered();
+    tokio::pin!(connection, triggered);
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            result = connection.as_mut() => break result,
+            _ = &mut triggered, if !closing => {
+                connection.as_mut().graceful_shutdown();
+                closing = true;
+            }
+        }
+    }
+}
+
 async fn get_method_router(
     state: State<AppState>,
     Query(params): Query<GetParams>,
     uri: Uri,
+    headers: HeaderMap,
 ) -> Result<Response<Body>, (StatusCode, String)> {
-    let mut lfs_config: LfsConfig = state.options.clone().into();
-    lfs_config.storage = state.storage.clone();
+    let lfs_config = state.lfs_config();
 
     // Routing LFS services.
     if Regex::new(r"/objects/[a-z0-9]+$")
@@ -121,7 +913,12 @@ async fn get_method_router(
         let path = uri.path().to_owned();
         let tokens: Vec<&str> = path.split('/').collect();
         // The `:oid` field is the last field.
-        return lfs::http::lfs_download_object(&lfs_config, tokens[tokens.len() - 1]).await;
+        let lfs_config = LfsConfig {
+            storage: state.storage_of(&lfs_repo_path(&uri), &headers),
+            ..lfs_config
+        };
+        return lfs::http::lfs_download_object(&lfs_config, tokens[tokens.len() - 1], &headers)
+            .await;
     } else if Regex::new(r"/locks$").unwrap().is_match(uri.path()) {
         // Load query parameters into struct.
         let lock_list_query = LockListQuery {
@@ -134,7 +931,13 @@ async fn get_method_router(
         return lfs::http::lfs_retrieve_lock(&lfs_config, lock_list_query).await;
     }
 
-    let service_name = params.service.unwrap();
+    if params.service.is_none() && uri.path().ends_with("/bundle") {
+        return get_bundle(&state, &uri, &headers).await;
+    }
+    // The clients of the dumb protocol fetch the files without the `service`.
+    let Some(service_name) = params.service else {
+        return dumb_get(&state, &uri, &headers).await;
+    };
     let service_type = service_name.parse::<ServiceType>().unwrap();
 
     // # Discovering Reference
@@ -149,11 +952,13 @@ async fn get_method_router(
             String::from("Operation not supported\n"),
         ));
     }
-    let mut pack_protocol = PackProtocol::new(
-        remove_git_suffix(uri, "/info/refs"),
-        state.storage.clone(),
-        Protocol::Http,
-    );
+    let (repo_path, namespace) = git_repo(&uri, &headers, "/info/refs")?;
+    let mut pack_protocol = PackProtocol::new(repo_path, state.storage.clone(), Protocol::Http);
+    pack_protocol.bundle_uri =
+        state.bundle_uri(&pack_protocol.path, namespace.as_deref(), &headers);
+    pack_protocol.namespace = namespace;
+    pack_protocol.use_v2(git_protocol(&headers));
+    pack_protocol.ref_prefixes = ref_prefixes(&uri);
     let mut headers = HashMap::new();
     headers.insert(
         "Content-Type".to_string(),
@@ -174,13 +979,120 @@ async fn get_method_router(
     Ok(resp.body(body).unwrap())
 }
 
+/// The precomputed bundle of the repo advertised by the `bundle-uri`, see [`crate::bundle`].
+async fn get_bundle(
+    state: &AppState,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, String::from("Not found"));
+    let (repo_path, namespace) = git_repo(uri, headers, "/bundle")?;
+    let path = state
+        .bundles
+        .as_ref()
+        .filter(|_| namespace.is_none())
+        .and_then(|bundles| bundles.path(&repo_path))
+        .ok_or_else(not_found)?;
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => {
+            tracing::error!("Failed to read the bundle {}: {}", path.display(), e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                String::from("Failed to read the bundle"),
+            ));
+        }
+    };
+    // The file is replaced rather than rewritten, so the opened one is read to the end.
+    let stream = stream::try_unfold(file, |mut file| async move {
+        let mut buf = vec![0; 64 * 1024];
+        let n = file.read(&mut buf).await?;
+        buf.truncate(n);
+        Ok::<_, std::io::Error>((n > 0).then_some((buf, file)))
+    });
+    Ok(Response::builder()
+        .header("Content-Type", "application/x-git-bundle")
+        .header("Cache-Control", "no-cache, max-age=0, must-revalidate")
+        .body(Body::wrap_stream(stream))
+        .unwrap())
+}
+
+/// The files of the dumb protocol, see [`dumb`].
+async fn dumb_get(
+    state: &AppState,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let Some((repo_path, file)) = DumbFile::parse(uri.path()) else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            String::from("Operation not supported\n"),
+        ));
+    };
+    let repo_path = repo_path.to_string_lossy();
+    let not_found = || (StatusCode::NOT_FOUND, String::from("Not found"));
+    let internal = |e: MegaError| {
+        tracing::error!("Failed to read {} of {}: {}", uri.path(), repo_path, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from("Failed to read the repo"),
+        )
+    };
+    const TEXT: &str = "text/plain; charset=utf-8";
+    // The objects never change, unlike the refs.
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+    let (content_type, body) = match file {
+        DumbFile::InfoRefs => (
+            TEXT,
+            dumb::info_refs(&state.storage, &repo_path, &ref_prefixes(uri))
+                .await
+                .map_err(internal)?
+                .into_bytes(),
+        ),
+        DumbFile::Head => (
+            TEXT,
+            dumb::head(&state.storage, &repo_path)
+                .await
+                .map_err(internal)?
+                .ok_or_else(not_found)?
+                .into_bytes(),
+        ),
+        DumbFile::Packs => (TEXT, Vec::new()),
+        DumbFile::Object(git_id) => {
+            let storage = state.storage_of(Path::new(repo_path.as_ref()), headers);
+            let object = dumb::loose_object(&storage, &git_id)
+                .await
+                .map_err(internal)?
+                .ok_or_else(not_found)?;
+            let etag = http::etag(&git_id);
+            if http::if_none_match(headers, &etag) {
+                let mut resp = http::not_modified(&etag);
+                resp.headers_mut()
+                    .insert("Cache-Control", HeaderValue::from_static(IMMUTABLE));
+                return Ok(resp);
+            }
+            let resp = Response::builder()
+                .header("Content-Type", "application/x-git-loose-object")
+                .header("Cache-Control", IMMUTABLE)
+                .header("ETag", etag);
+            return Ok(resp.body(Body::from(object)).unwrap());
+        }
+        DumbFile::Pack(_) => return Err(not_found()),
+    };
+    Ok(Response::builder()
+        .header("Content-Type", content_type)
+        .header("Cache-Control", "no-cache, max-age=0, must-revalidate")
+        .body(Body::from(body))
+        .unwrap())
+}
+
 async fn post_method_router(
     state: State<AppState>,
     uri: Uri,
     req: Request<Body>,
 ) -> Result<Response<Body>, (StatusCode, String)> {
-    let mut lfs_config: LfsConfig = state.options.clone().into();
-    lfs_config.storage = state.storage.clone();
+    let lfs_config = state.lfs_config();
 
     // Routing LFS services.
     if Regex::new(r"/locks/verify$").unwrap().is_match(uri.path()) {
@@ -194,29 +1106,59 @@ async fn post_method_router(
         // The `:id` field is just ahead of the last field.
         return lfs::http::lfs_delete_lock(&lfs_config, tokens[tokens.len() - 2], req).await;
     } else if Regex::new(r"/objects/batch$").unwrap().is_match(uri.path()) {
+        let lfs_config = LfsConfig {
+            storage: state.storage_of(&lfs_repo_path(&uri), req.headers()),
+            ..state.repo_lfs_config(&uri).await?
+        };
         return lfs::http::lfs_process_batch(&lfs_config, req).await;
+    } else if Regex::new(r"/objects/verify$").unwrap().is_match(uri.path()) {
+        return lfs::http::lfs_verify_object(&lfs_config, req).await;
     }
 
     if Regex::new(r"/git-upload-pack$")
         .unwrap()
         .is_match(uri.path())
     {
-        let pack_protocol = PackProtocol::new(
-            remove_git_suffix(uri, "/git-upload-pack"),
-            state.storage.clone(),
-            Protocol::Http,
-        );
+        let (repo_path, namespace) = git_repo(&uri, req.headers(), "/git-upload-pack")?;
+        let storage = state.storage_of(&repo_path, req.headers());
+        let mut pack_protocol = PackProtocol::new(repo_path, storage, Protocol::Http);
+        pack_protocol.bundle_uri =
+            state.bundle_uri(&pack_protocol.path, namespace.as_deref(), req.headers());
+        pack_protocol.namespace = namespace;
+        pack_protocol.window = state.options.pack.window;
+        pack_protocol.depth = state.options.pack.depth;
+        pack_protocol.keepalive = state.options.pack.keepalive();
+        pack_protocol.commit_graphs = state.commit_graphs.clone();
+        pack_protocol.objects = state.objects.clone();
+        pack_protocol.use_v2(git_protocol(req.headers()));
+        pack_protocol.ref_prefixes = ref_prefixes(&uri);
         http::git_upload_pack(req, pack_protocol).await
     } else if Regex::new(r"/git-receive-pack$")
         .unwrap()
         .is_match(uri.path())
     {
-        let pack_protocol = PackProtocol::new(
-            remove_git_suffix(uri, "/git-receive-pack"),
-            state.storage.clone(),
-            Protocol::Http,
-        );
-        http::git_receive_pack(req, pack_protocol).await
+        let (repo_path, namespace) = git_repo(&uri, req.headers(), "/git-receive-pack")?;
+        let mut pack_protocol =
+            PackProtocol::new(repo_path, state.storage.clone(), Protocol::Http);
+        pack_protocol.namespace = namespace;
+        let repo_config = state.repo_config(&pack_protocol.path).await?;
+        pack_protocol.commit_graphs = state.commit_graphs.clone();
+        pack_protocol.enforce_lfs_threshold = repo_config
+            .enforce_lfs_threshold
+            .or(state.options.enforce_lfs_threshold);
+        pack_protocol.hooks =
+            ReplicatedHooks::wrap(state.options.hooks.hooks(), state.replicator.as_ref());
+        pack_protocol.decode = state.options.pack.decode_options();
+        let pusher = req.extensions().get::<User>().map(|user| user.name.clone());
+        let resp = http::git_receive_pack(req, &mut pack_protocol).await?;
+        match &state.webhooks {
+            // nothing is pushed by a dry run
+            Some(webhooks) if !pack_protocol.dry_run() => {
+                notify_push(webhooks, &repo_config, &pack_protocol, pusher.as_deref()).await
+            }
+            _ => {}
+        }
+        Ok(resp)
     } else {
         Err((
             StatusCode::FORBIDDEN,
@@ -225,17 +1167,97 @@ async fn post_method_router(
     }
 }
 
+/// Send the events of the refs updated by the push to the webhook receivers.
+async fn notify_push(
+    webhooks: &Arc<Dispatcher>,
+    repo_config: &RepoConfig,
+    pack_protocol: &PackProtocol,
+    pusher: Option<&str>,
+) {
+    let repo = pack_protocol.path.to_string_lossy();
+    let urls = repo_config.webhook_urls.as_ref().unwrap_or(webhooks.urls());
+    if urls.is_empty() {
+        return;
+    }
+    // validated when saved
+    let filter = repo_config
+        .webhook_ref_filters
+        .as_ref()
+        .and_then(|filters| RefFilter::new(filters).ok());
+    // the failed ones are not updated
+    for command in pack_protocol.command_list.iter().filter(|c| c.is_ok()) {
+        let accepted = match &filter {
+            Some(filter) => filter.matches(&command.ref_name),
+            None => webhooks.accepts(&command.ref_name),
+        };
+        if !accepted {
+            continue;
+        }
+        let commits =
+            webhook::event::new_commits(&pack_protocol.storage, &command.old_id, &command.new_id)
+                .await;
+        let event = Event::from_command(&repo, pusher, command, commits);
+        let payload = serde_json::to_value(&event).unwrap();
+        if let Err(e) = webhooks.dispatch_to(urls, event.name(), payload) {
+            tracing::error!("Failed to queue the {} event: {}", event.name(), e);
+        }
+    }
+}
+
+/// The repo of an LFS request, e.g. `/projects/demo` of `/projects/demo.git/info/lfs/objects/batch`
+/// or of an object.
+fn lfs_repo_path(uri: &Uri) -> PathBuf {
+    let path = uri.path();
+    let path = path.rsplit_once("/objects/").map_or(path, |(repo, _)| repo);
+    let path = path.strip_suffix("/info/lfs").unwrap_or(path);
+    PathBuf::from(path.strip_suffix(".git").unwrap_or(path))
+}
+
+/// The `HEAD` of an LFS object reports the bytes of the resumable upload received.
+async fn head_method_router(
+    state: State<AppState>,
+    uri: Uri,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    match lfs_object_id(&uri) {
+        Some(oid) => lfs::http::lfs_upload_status(&state.lfs_config(), oid).await,
+        None => Err((StatusCode::NOT_FOUND, String::from("Not found"))),
+    }
+}
+
+/// The `PATCH` of an LFS object appends a chunk of the resumable upload.
+async fn patch_method_router(
+    state: State<AppState>,
+    uri: Uri,
+    req: Request<Body>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    match lfs_object_id(&uri) {
+        Some(oid) => {
+            let lfs_config = state.repo_lfs_config(&uri).await?;
+            lfs::http::lfs_append_object(&lfs_config, oid, req).await
+        }
+        None => Err((
+            StatusCode::FORBIDDEN,
+            String::from("Operation not supported"),
+        )),
+    }
+}
+
+/// The `:oid` of `/objects/:oid`, which is the last field of the path.
+fn lfs_object_id(uri: &Uri) -> Option<&str> {
+    let oid = uri.path().strip_prefix('/')?.rsplit_once("/objects/")?.1;
+    Regex::new(r"^[a-z0-9]+$").unwrap().is_match(oid).then_some(oid)
+}
+
 async fn put_method_router(
     state: State<AppState>,
     uri: Uri,
     req: Request<Body>,
 ) -> Result<Response<Body>, (StatusCode, String)> {
-    let mut lfs_config: LfsConfig = state.options.clone().into();
-    lfs_config.storage = state.storage.clone();
     if Regex::new(r"/objects/[a-z0-9]+$")
         .unwrap()
         .is_match(uri.path())
     {
+        let lfs_config = state.repo_lfs_config(&uri).await?;
         // Retrieve the `:oid` field from path.
         let path = uri.path().to_owned();
         let tokens: Vec<&str> = path.split('/').collect();
@@ -253,28 +1275,418 @@ mod api_routers {
     use std::collections::HashMap;
 
     use axum::{
-        extract::{Query, State},
-        response::IntoResponse,
-        routing::get,
+        extract::{Path, Query, State},
+        middleware,
+        response::{IntoResponse, Response},
+        routing::{get, post},
         Json, Router,
     };
-    use hyper::StatusCode;
+    use hyper::{StatusCode, Uri};
+    use common::errors::MegaError;
+    use git::protocol::{PackProtocol, Protocol};
+    use serde::de::DeserializeOwned;
+    use serde::Deserialize;
 
     use crate::{
-        api_service::obj_service::ObjectService,
-        model::{object_detail::{BlobObjects, Directories}, query::DirectoryQuery},
+        api_service::{
+            browse_service::{self, BrowseService},
+            obj_service::ObjectService,
+            repo_service::{self, RepoService},
+        },
+        auth::auth_layer,
+        model::{
+            cache::{
+                CacheFlushed, CacheStatus, CacheWarmed, WarmCache, DEFAULT_WARM_COMMITS,
+                MAX_WARM_COMMITS,
+            },
+            object_detail::{BlobObjects, Directories},
+            query::{CommitQuery, DiffQuery, DirectoryQuery, ObjectQuery, PageQuery, SearchQuery},
+            repo::{CreateRepo, RefList, Repo, RepoDeleted, RepoHead, RepoList},
+            webhook::Webhook,
+        },
+        replication::ReplicaStatus,
+        repo_config::RepoConfig,
+        webhook::{
+            delivery::PingResult,
+            log::{self, DeliveryAttempt},
+            PingEvent,
+        },
     };
 
     use super::AppState;
 
     pub fn routers<S>(state: AppState) -> Router<S> {
+        // The settings are changed, and the webhooks are tested, by the users of the write
+        // access only.
+        let repo_config = Router::new()
+            .route("/repo-config", get(get_repo_config).put(put_repo_config))
+            .route("/webhooks", get(list_webhooks))
+            .route("/webhooks/ping", post(ping_webhooks))
+            .route("/webhooks/:id/deliveries", get(list_deliveries))
+            .route_layer(middleware::from_fn_with_state(state.clone(), auth_layer));
+        // The listings need the read access like a fetch, and the creation, the default branch
+        // and the deletion need the write access.
+        let repos = Router::new()
+            .route("/repos", get(list_repos).post(create_repo))
+            .route(
+                "/repos/*path",
+                get(get_repo).put(put_repo_head).delete(delete_repo),
+            )
+            .route_layer(middleware::from_fn_with_state(state.clone(), auth_layer));
         Router::new()
             .route("/blob", get(get_blob_object))
             .route("/tree", get(get_directories))
             .route("/object", get(get_origin_object))
+            .merge(repo_config)
+            .merge(repos)
+            .with_state(state)
+    }
+
+    /// The API of the administrators of the server, which is merged rather than nested, so the
+    /// `/admin` prefix is kept for the authentication to require the admin access.
+    pub fn admin_routers<S>(state: AppState) -> Router<S> {
+        Router::new()
+            .route("/admin/cache", get(get_cache))
+            .route("/admin/cache/flush", post(flush_cache))
+            .route("/admin/cache/warm", post(warm_cache))
+            .route("/admin/replication", get(get_replication))
+            .route_layer(middleware::from_fn_with_state(state.clone(), auth_layer))
             .with_state(state)
     }
 
+    async fn get_cache(state: State<AppState>) -> Json<CacheStatus> {
+        Json(CacheStatus::from(state.objects.as_ref()))
+    }
+
+    /// The replications of the repos pushed since the start, none without a replication url.
+    async fn get_replication(state: State<AppState>) -> Json<Vec<ReplicaStatus>> {
+        match &state.replicator {
+            Some(replicator) => Json(replicator.statuses()),
+            None => Json(Vec::new()),
+        }
+    }
+
+    async fn flush_cache(state: State<AppState>) -> Json<CacheFlushed> {
+        let flushed = state.objects.clear();
+        tracing::info!("Flushed {} objects of the cache", flushed);
+        Json(CacheFlushed {
+            flushed,
+            cache: CacheStatus::from(state.objects.as_ref()),
+        })
+    }
+
+    /// Load the objects of the latest commits of the repo into the cache, so the first fetches
+    /// of it after a restart don't load all of them from the storage.
+    async fn warm_cache(
+        state: State<AppState>,
+        Json(body): Json<WarmCache>,
+    ) -> Result<Json<CacheWarmed>, (StatusCode, String)> {
+        let Some(repo_path) = repo_service::repo_path(&body.repo) else {
+            return Err((StatusCode::BAD_REQUEST, "Invalid repo name".to_string()));
+        };
+        let commits = body.commits.unwrap_or(DEFAULT_WARM_COMMITS);
+        if commits == 0 || commits > MAX_WARM_COMMITS {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("The commits must be from 1 to {}", MAX_WARM_COMMITS),
+            ));
+        }
+        if state.objects.capacity() == 0 {
+            return Err((StatusCode::CONFLICT, "The cache is disabled".to_string()));
+        }
+        let server_error = |e: MegaError| {
+            tracing::error!("Failed to warm the cache of {}: {}", repo_path, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to warm the cache".to_string(),
+            )
+        };
+        let refs = state
+            .storage
+            .search_refs(&repo_path)
+            .await
+            .map_err(server_error)?;
+        if !refs.iter().any(|r| r.repo_path == repo_path) {
+            return Err((StatusCode::NOT_FOUND, "Repo not found".to_string()));
+        }
+        let mut pack_protocol =
+            PackProtocol::new(repo_path.clone().into(), state.storage.clone(), Protocol::Http);
+        pack_protocol.commit_graphs = state.commit_graphs.clone();
+        pack_protocol.objects = state.objects.clone();
+        let loaded = pack_protocol
+            .warm_objects(commits)
+            .await
+            .map_err(server_error)?;
+        tracing::info!("Loaded {} objects of {} into the cache", loaded, repo_path);
+        Ok(Json(CacheWarmed {
+            repo: repo_path,
+            loaded,
+            cache: CacheStatus::from(state.objects.as_ref()),
+        }))
+    }
+
+    async fn list_repos(
+        Query(query): Query<PageQuery>,
+        state: State<AppState>,
+    ) -> Result<Json<RepoList>, (StatusCode, String)> {
+        let repo_service = RepoService {
+            storage: state.storage.clone(),
+        };
+        repo_service.list_repos(&query).await
+    }
+
+    /// The refs of the repo of the path like `projects/mega/refs`, or the objects of it like
+    /// `projects/mega/objects/:oid`, `projects/mega/raw/main/src/lib.rs` and
+    /// `projects/mega/tree/main/src`, the history like `projects/mega/commits/main`, the diff of
+    /// the commits like `projects/mega/diff?base=v1&head=main`, the blame of a file like
+    /// `projects/mega/blame/main/src/lib.rs`, and the search like `projects/mega/search?q=fn`.
+    async fn get_repo(
+        Path(path): Path<String>,
+        uri: Uri,
+        state: State<AppState>,
+    ) -> Result<Response, (StatusCode, String)> {
+        let not_found = || (StatusCode::NOT_FOUND, "Not found".to_string());
+        if let Some(name) = path.trim_start_matches('/').strip_suffix("/refs") {
+            let query = query(&uri)?;
+            return list_refs(name, &query, &state)
+                .await
+                .map(IntoResponse::into_response);
+        }
+        let (repo_path, keyword, rest) = browse_service::split_path(&path).ok_or_else(not_found)?;
+        let browse_service = BrowseService {
+            storage: state.storage.clone(),
+            lfs_storage: state.lfs_storage.clone(),
+            commit_graphs: state.commit_graphs.clone(),
+            blames: state.blames.clone(),
+        };
+        match keyword {
+            "blame" => browse_service
+                .get_blame(&repo_path, rest)
+                .await
+                .map(IntoResponse::into_response),
+            "commits" => {
+                let query: CommitQuery = query(&uri)?;
+                browse_service
+                    .list_commits(&repo_path, rest, &query)
+                    .await
+                    .map(IntoResponse::into_response)
+            }
+            "diff" if rest.is_empty() => {
+                let query: DiffQuery = query(&uri)?;
+                browse_service
+                    .get_diff(&repo_path, &query)
+                    .await
+                    .map(IntoResponse::into_response)
+            }
+            "objects" => {
+                let query: ObjectQuery = query(&uri)?;
+                let json = query.format.as_deref() == Some("json");
+                browse_service.get_object(&repo_path, rest, json).await
+            }
+            "raw" => browse_service.get_raw(&repo_path, rest).await,
+            "search" if rest.is_empty() => {
+                let query: SearchQuery = query(&uri)?;
+                browse_service.search(&repo_path, &query).await
+            }
+            "tree" => browse_service
+                .get_tree(&repo_path, rest)
+                .await
+                .map(IntoResponse::into_response),
+            _ => Err(not_found()),
+        }
+    }
+
+    /// The query of the request, which is parsed by each API of a repo.
+    fn query<T: DeserializeOwned>(uri: &Uri) -> Result<T, (StatusCode, String)> {
+        let Query(query) =
+            Query::try_from_uri(uri).map_err(|e| (StatusCode::BAD_REQUEST, e.body_text()))?;
+        Ok(query)
+    }
+
+    async fn list_refs(
+        name: &str,
+        query: &PageQuery,
+        state: &AppState,
+    ) -> Result<Json<RefList>, (StatusCode, String)> {
+        let repo_path = format!("/{}", name.trim_end_matches(".git"));
+        let repo_service = RepoService {
+            storage: state.storage.clone(),
+        };
+        repo_service.list_refs(&repo_path, query).await
+    }
+
+    /// Set the default branch of the repo of the path like `projects/mega/head`.
+    async fn put_repo_head(
+        Path(path): Path<String>,
+        state: State<AppState>,
+        Json(head): Json<RepoHead>,
+    ) -> Result<Json<RepoHead>, (StatusCode, String)> {
+        let Some(name) = path.trim_start_matches('/').strip_suffix("/head") else {
+            return Err((StatusCode::NOT_FOUND, "Not found".to_string()));
+        };
+        let Some(repo_path) = repo_service::repo_path(name) else {
+            return Err((StatusCode::BAD_REQUEST, "Invalid repo name".to_string()));
+        };
+        let repo_service = RepoService {
+            storage: state.storage.clone(),
+        };
+        repo_service.set_head(&repo_path, &head.ref_name).await
+    }
+
+    #[derive(Deserialize)]
+    struct PingQuery {
+        repo_path: Option<String>,
+    }
+
+    /// Send a `ping` event to the receivers of the repo of the `repo_path`, or the ones of the
+    /// server, and tell how each of them responds.
+    async fn ping_webhooks(
+        Query(query): Query<PingQuery>,
+        state: State<AppState>,
+    ) -> Result<Json<Vec<PingResult>>, (StatusCode, String)> {
+        let not_configured = || {
+            (
+                StatusCode::NOT_FOUND,
+                "No webhook is configured".to_string(),
+            )
+        };
+        let Some(webhooks) = &state.webhooks else {
+            return Err(not_configured());
+        };
+        let repo_urls = match &query.repo_path {
+            Some(repo_path) => state
+                .repo_config(repo_path.as_ref())
+                .await?
+                .webhook_urls
+                .clone(),
+            None => None,
+        };
+        let urls = repo_urls.unwrap_or_else(|| webhooks.urls().clone());
+        if urls.is_empty() {
+            return Err(not_configured());
+        }
+        let payload = serde_json::to_value(PingEvent::new(query.repo_path.as_deref())).unwrap();
+        Ok(Json(webhooks.ping(&urls, payload).await))
+    }
+
+    /// The receivers of the server, of which the ids are the same as the ones in the log.
+    async fn list_webhooks(state: State<AppState>) -> Json<Vec<Webhook>> {
+        let urls = match &state.webhooks {
+            Some(webhooks) => webhooks.urls().clone(),
+            None => Vec::new(),
+        };
+        Json(
+            urls.into_iter()
+                .map(|url| Webhook {
+                    id: log::webhook_id(&url),
+                    url,
+                })
+                .collect(),
+        )
+    }
+
+    /// The attempts of the deliveries to the receiver of the id from the newest, at most the
+    /// `limit` of the query.
+    async fn list_deliveries(
+        Path(id): Path<String>,
+        Query(query): Query<PageQuery>,
+        state: State<AppState>,
+    ) -> Result<Json<Vec<DeliveryAttempt>>, (StatusCode, String)> {
+        let Some(webhooks) = &state.webhooks else {
+            return Ok(Json(Vec::new()));
+        };
+        let attempts = webhooks.attempts(&id, query.limit()).map_err(|e| {
+            tracing::error!("Failed to read the log of the deliveries: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read the log of the deliveries".to_string(),
+            )
+        })?;
+        Ok(Json(attempts))
+    }
+
+    async fn create_repo(
+        state: State<AppState>,
+        Json(body): Json<CreateRepo>,
+    ) -> Result<(StatusCode, Json<Repo>), (StatusCode, String)> {
+        let Some(repo_path) = repo_service::repo_path(&body.name) else {
+            return Err((StatusCode::BAD_REQUEST, "Invalid repo name".to_string()));
+        };
+        let repo_service = RepoService {
+            storage: state.storage.clone(),
+        };
+        let repo = repo_service.create_repo(&repo_path).await?;
+        Ok((StatusCode::CREATED, repo))
+    }
+
+    #[derive(Deserialize)]
+    struct DeleteRepoQuery {
+        #[serde(default)]
+        confirm: bool,
+    }
+
+    /// Delete the repo of the path like `projects/mega`, which must be confirmed by the
+    /// `confirm=true` of the query since nothing of it is left.
+    async fn delete_repo(
+        Path(path): Path<String>,
+        Query(query): Query<DeleteRepoQuery>,
+        state: State<AppState>,
+    ) -> Result<Json<RepoDeleted>, (StatusCode, String)> {
+        let Some(repo_path) = repo_service::repo_path(&path) else {
+            return Err((StatusCode::BAD_REQUEST, "Invalid repo name".to_string()));
+        };
+        if !query.confirm {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Deleting the repo needs to be confirmed by confirm=true".to_string(),
+            ));
+        }
+        let repo_service = RepoService {
+            storage: state.storage.clone(),
+        };
+        let deleted = repo_service
+            .delete_repo(&repo_path, state.lfs_storage.as_ref())
+            .await?;
+        state.repo_configs.remove(&repo_path);
+        state.commit_graphs.remove(repo_path.as_ref());
+        Ok(deleted)
+    }
+
+    #[derive(Deserialize)]
+    struct RepoConfigQuery {
+        repo_path: String,
+    }
+
+    async fn get_repo_config(
+        Query(query): Query<RepoConfigQuery>,
+        state: State<AppState>,
+    ) -> Result<Json<RepoConfig>, (StatusCode, String)> {
+        let config = state.repo_config(query.repo_path.as_ref()).await?;
+        Ok(Json(config.as_ref().clone()))
+    }
+
+    async fn put_repo_config(
+        Query(query): Query<RepoConfigQuery>,
+        state: State<AppState>,
+        Json(config): Json<RepoConfig>,
+    ) -> Result<Json<RepoConfig>, (StatusCode, String)> {
+        config
+            .validate()
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+        state
+            .repo_configs
+            .update(&state.storage, &query.repo_path, config.clone())
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to save the config of {}: {}", query.repo_path, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    String::from("Failed to save the config of the repo"),
+                )
+            })?;
+        Ok(Json(config))
+    }
+
     async fn get_blob_object(
         Query(query): Query<HashMap<String, String>>,
         state: State<AppState>,
@@ -311,4 +1723,1457 @@ mod api_routers {
 }
 
 #[cfg(test)]
-mod tests {}
+pub(crate) mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use axum::http::header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS,
+        ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, HOST, ORIGIN, RETRY_AFTER, WWW_AUTHENTICATE,
+    };
+    use base64::{engine::general_purpose, Engine};
+    use clap::Parser;
+    use common::utils::ZERO_ID;
+    use database::driver::lfs::storage::ContentStore;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::{ObjectStorage, RefUpdate};
+    use git::internal::object_cache::SharedObjectCache;
+    use git::protocol::bundle::parse_header;
+    use axum::response::Response;
+    use axum::routing::post;
+    use axum::Router;
+    use hyper::{Body, Method, Request, StatusCode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    use super::{
+        app, serve, serve_all, AppState, HttpOptions, HttpOptionsBuilder, ListenAddr, Listener,
+        NAMESPACE_HEADER,
+    };
+    use crate::auth::{Access, FileAuthenticator};
+    use crate::bundle::{BundleOptions, Bundles};
+    use crate::model::cache::{CacheFlushed, CacheStatus, CacheWarmed};
+    use crate::model::repo::RepoHead;
+    use crate::rate_limit::RateLimits;
+    use crate::shutdown::Shutdown;
+    use crate::tests::MockStorage;
+    use crate::timeout::Timeouts;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        http: HttpOptions,
+    }
+
+    pub(crate) fn state() -> AppState {
+        let mut authenticator = FileAuthenticator::default();
+        authenticator.add_user("alice", "secret", Access::Write);
+        authenticator.add_user("bob", "secret", Access::Read);
+        authenticator.add_user("root", "secret", Access::Admin);
+        AppState {
+            storage: Arc::new(MockStorage::default()),
+            lfs_storage: Arc::new(ContentStore::new(std::env::temp_dir().join("mega_lfs_auth"))),
+            authenticator: Some(Arc::new(authenticator)),
+            kvcache: None,
+            rate_limits: Arc::default(),
+            webhooks: None,
+            replicator: None,
+            read_through: None,
+            bundles: None,
+            commit_graphs: Arc::default(),
+            blames: Arc::default(),
+            objects: Arc::new(SharedObjectCache::new(1024 * 1024)),
+            repo_configs: Arc::default(),
+            options: Cli::parse_from(["mega"]).http,
+        }
+    }
+
+    /// Delete the `main` branch, so there is no pack to unpack.
+    fn push(credentials: Option<&str>) -> Request<Body> {
+        let command = format!(
+            "{} {} refs/heads/main\0report-status\n",
+            "1".repeat(40),
+            "0".repeat(40)
+        );
+        let body = format!("{:04x}{}0000", command.len() + 4, command);
+        let mut req = Request::post("/repo.git/git-receive-pack");
+        if let Some(credentials) = credentials {
+            let encoded = general_purpose::STANDARD.encode(credentials);
+            req = req.header(AUTHORIZATION, format!("Basic {}", encoded));
+        }
+        req.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_push_without_credentials() {
+        let resp = app(state()).oneshot(push(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(resp.headers()[WWW_AUTHENTICATE]
+            .to_str()
+            .unwrap()
+            .starts_with("Basic"));
+    }
+
+    #[tokio::test]
+    async fn test_push_with_wrong_credentials() {
+        let resp = app(state())
+            .oneshot(push(Some("alice:wrong")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // the read-only user
+        let resp = app(state())
+            .oneshot(push(Some("bob:secret")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_push_with_valid_credentials() {
+        let resp = app(state())
+            .oneshot(push(Some("alice:secret")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()["Content-Type"],
+            "application/x-git-receive-pack-result"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_push_and_fetch_in_memory() {
+        const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+        const HEAD: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+        let state = AppState {
+            storage: Arc::new(MemoryStorage::new()),
+            ..state()
+        };
+        let app = app(state);
+        let credentials = format!("Basic {}", general_purpose::STANDARD.encode("alice:secret"));
+
+        let command = format!(
+            "{} {} refs/heads/main\0report-status\n",
+            "0".repeat(40),
+            HEAD
+        );
+        let mut body = format!("{:04x}{}0000", command.len() + 4, command).into_bytes();
+        body.extend(std::fs::read(PACK).unwrap());
+        let req = Request::post("/projects/demo.git/git-receive-pack")
+            .header(AUTHORIZATION, &credentials)
+            .body(Body::from(body))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let report = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&report).contains("ok refs/heads/main"));
+
+        let req = Request::get("/projects/demo.git/info/refs?service=git-upload-pack")
+            .header(AUTHORIZATION, &credentials)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let refs = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let refs = String::from_utf8_lossy(&refs);
+        assert!(refs.contains(&format!("{} refs/heads/main", HEAD)));
+
+        // the capabilities of the protocol v2 instead of the refs, and then the commands
+        let req = Request::get("/projects/demo.git/info/refs?service=git-upload-pack")
+            .header(AUTHORIZATION, &credentials)
+            .header("Git-Protocol", "version=2")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let caps = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(caps.starts_with(b"000eversion 2\n"));
+        let v2_command = |lines: &[&str]| {
+            let mut body = String::new();
+            for line in lines {
+                match *line {
+                    "0000" | "0001" => body.push_str(line),
+                    line => body.push_str(&format!("{:04x}{}\n", line.len() + 5, line)),
+                }
+            }
+            Request::post("/projects/demo.git/git-upload-pack")
+                .header(AUTHORIZATION, &credentials)
+                .header("Git-Protocol", "version=2")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let req = v2_command(&["command=ls-refs", "0001", "ref-prefix refs/tags/", "0000"]);
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await.unwrap(),
+            "0000"
+        );
+        let want = format!("want {}", HEAD);
+        let req = v2_command(&["command=fetch", "0001", &want, "done", "0000"]);
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let pack = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(pack.starts_with(b"000dpackfile\n"));
+        assert!(pack.windows(5).any(|w| w == b"\x01PACK"));
+
+        // the loose object of the dumb protocol, which is not sent again if it's cached
+        let object = format!("/projects/demo.git/objects/{}/{}", &HEAD[..2], &HEAD[2..]);
+        let get = |etag: Option<&str>| {
+            let mut req = Request::get(&object).header(AUTHORIZATION, &credentials);
+            if let Some(etag) = etag {
+                req = req.header("If-None-Match", etag);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+        let resp = app.clone().oneshot(get(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers()["ETag"].to_str().unwrap().to_owned();
+        assert_eq!(etag, format!("\"{}\"", HEAD));
+        let resp = app.clone().oneshot(get(Some(&etag))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()["ETag"], etag.as_str());
+        assert!(hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+        let resp = app.clone().oneshot(get(Some("\"1234\""))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // the object of the API
+        let object = format!("/api/v1/repos/projects/demo/objects/{}?format=json", HEAD);
+        let req = Request::get(&object)
+            .header(AUTHORIZATION, &credentials)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["X-Git-Object-Type"], "commit");
+
+        // the history of the pushed branch
+        let req = Request::get("/api/v1/repos/projects/demo/commits/HEAD?limit=1")
+            .header(AUTHORIZATION, &credentials)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page["commits"][0]["oid"], HEAD);
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() {
+        const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+        const HEAD: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+        const OLDER: &str = "c8b60e643248e4b12c8e3c13eae58e914383ab0c";
+        let state = AppState {
+            storage: Arc::new(MemoryStorage::new()),
+            ..state()
+        };
+        let app = app(state);
+        let credentials = format!("Basic {}", general_purpose::STANDARD.encode("alice:secret"));
+        let request = |req: hyper::http::request::Builder, namespace: Option<&str>| {
+            let req = req.header(AUTHORIZATION, &credentials);
+            match namespace {
+                Some(namespace) => req.header(NAMESPACE_HEADER, namespace),
+                None => req,
+            }
+        };
+
+        // the same branch of the namespaces by the path and by the header
+        let pushes = [
+            (
+                "/projects/demo.git/namespaces/a/git-receive-pack",
+                None,
+                HEAD,
+            ),
+            ("/projects/demo.git/git-receive-pack", Some("b"), OLDER),
+        ];
+        for (path, namespace, id) in pushes {
+            let command = format!("{} {} refs/heads/main\0report-status\n", ZERO_ID, id);
+            let mut body = format!("{:04x}{}0000", command.len() + 4, command).into_bytes();
+            body.extend(std::fs::read(PACK).unwrap());
+            let req = request(Request::post(path), namespace);
+            let resp = app
+                .clone()
+                .oneshot(req.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let report = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            assert!(String::from_utf8_lossy(&report).contains("ok refs/heads/main"));
+        }
+
+        let info_refs = |path: &str, namespace: Option<&str>| {
+            let req = request(Request::get(path), namespace)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (status, String::from_utf8_lossy(&body).into_owned())
+            }
+        };
+        let query = "info/refs?service=git-upload-pack";
+        let (_, refs) = info_refs(&format!("/projects/demo.git/{}", query), Some("a")).await;
+        assert!(refs.contains(&format!("{} HEAD\0", HEAD)), "{}", refs);
+        assert!(
+            refs.contains(&format!("{} refs/heads/main\n", HEAD)),
+            "{}",
+            refs
+        );
+        assert!(!refs.contains(OLDER), "{}", refs);
+        let path = format!("/projects/demo.git/namespaces/b/{}", query);
+        let (_, refs) = info_refs(&path, None).await;
+        assert!(
+            refs.contains(&format!("{} refs/heads/main\n", OLDER)),
+            "{}",
+            refs
+        );
+        assert!(!refs.contains(HEAD), "{}", refs);
+
+        // the repo without a namespace has the refs of all of them
+        let (_, refs) = info_refs(&format!("/projects/demo.git/{}", query), None).await;
+        assert!(refs.contains(&format!("{} refs/namespaces/a/refs/heads/main", HEAD)));
+        assert!(refs.contains(&format!("{} refs/namespaces/b/refs/heads/main", OLDER)));
+
+        let path = format!("/projects/demo.git/{}", query);
+        let (status, _) = info_refs(&path, Some("../a")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_ref_prefixes() {
+        let storage = Arc::new(MemoryStorage::new());
+        let commit = ZERO_ID.replace('0', "1");
+        let names = (0..1000)
+            .map(|i| format!("refs/pull/{}/head", i))
+            .chain(["refs/heads/main".to_owned(), "refs/tags/v1".to_owned()]);
+        let updates: Vec<RefUpdate> = names
+            .map(|ref_name| RefUpdate {
+                ref_name,
+                old_id: None,
+                new_id: Some(commit.clone()),
+            })
+            .collect();
+        storage
+            .apply_ref_updates("/projects/demo", &updates, true)
+            .await
+            .unwrap();
+        let app = app(AppState {
+            storage,
+            ..state()
+        });
+        let credentials = format!("Basic {}", general_purpose::STANDARD.encode("bob:secret"));
+        let get = |uri: &str| {
+            let req = Request::get(uri)
+                .header(AUTHORIZATION, &credentials)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                String::from_utf8_lossy(&body).into_owned()
+            }
+        };
+
+        let smart = "/projects/demo.git/info/refs?service=git-upload-pack";
+        assert_eq!(get(smart).await.matches(" refs/pull/").count(), 1000);
+        let refs = get(&format!("{}&ref-prefix=refs/heads/", smart)).await;
+        let main = format!("{} refs/heads/main\0", commit);
+        assert!(refs.contains(&main), "{}", refs);
+        assert!(!refs.contains("refs/pull/"), "{}", refs);
+        assert!(!refs.contains("refs/tags/"), "{}", refs);
+        // of the dumb protocol, of more than one prefix
+        let dumb = "/projects/demo.git/info/refs?ref-prefix=refs/tags/&ref-prefix=refs/heads/";
+        let refs = get(dumb).await;
+        assert_eq!(
+            refs,
+            format!("{commit}\trefs/heads/main\n{commit}\trefs/tags/v1\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repo_head() {
+        let storage = Arc::new(MemoryStorage::new());
+        let updates: Vec<RefUpdate> = [("refs/heads/main", '1'), ("refs/heads/dev", '2')]
+            .into_iter()
+            .map(|(name, id)| RefUpdate {
+                ref_name: name.to_owned(),
+                old_id: None,
+                new_id: Some(id.to_string().repeat(40)),
+            })
+            .collect();
+        storage
+            .apply_ref_updates("/projects/demo", &updates, true)
+            .await
+            .unwrap();
+        let app = app(AppState {
+            storage,
+            ..state()
+        });
+        let send = |method: Method, user: &str, uri: &str, body: &str| {
+            let credentials = general_purpose::STANDARD.encode(format!("{}:secret", user));
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(AUTHORIZATION, format!("Basic {}", credentials))
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_owned()))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (status, String::from_utf8_lossy(&body).into_owned())
+            }
+        };
+        let smart = "/projects/demo.git/info/refs?service=git-upload-pack";
+        let advertised = || send(Method::GET, "bob", smart, "");
+
+        let (_, refs) = advertised().await;
+        let head = format!("{} HEAD\0", "1".repeat(40));
+        assert!(refs.contains(&head), "{}", refs);
+        assert!(refs.contains(" symref=HEAD:refs/heads/main"), "{}", refs);
+
+        let uri = "/api/v1/repos/projects/demo/head";
+        let (status, _) = send(Method::PUT, "bob", uri, r#"{"ref": "dev"}"#).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = send(Method::PUT, "alice", uri, r#"{"ref": "dev"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        let head: RepoHead = serde_json::from_str(&body).unwrap();
+        assert_eq!(head.ref_name, "refs/heads/dev");
+
+        let (_, refs) = advertised().await;
+        let head = format!("{} HEAD\0", "2".repeat(40));
+        assert!(refs.contains(&head), "{}", refs);
+        assert!(refs.contains(" symref=HEAD:refs/heads/dev"), "{}", refs);
+        assert!(!refs.contains("symref=HEAD:refs/heads/main"), "{}", refs);
+        let (_, head) = send(Method::GET, "bob", "/projects/demo.git/HEAD", "").await;
+        assert_eq!(head, "ref: refs/heads/dev\n");
+
+        let (status, _) = send(Method::PUT, "alice", uri, r#"{"ref": "a..b"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let uri = "/api/v1/repos/projects/none/head";
+        let (status, _) = send(Method::PUT, "alice", uri, r#"{"ref": "main"}"#).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_bundle_uri() {
+        const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+        const HEAD: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+        let dir = std::env::temp_dir().join("mega_bundles_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage: Arc<dyn ObjectStorage> = Arc::new(MemoryStorage::new());
+        let options = BundleOptions {
+            bundle_dir: Some(dir.clone()),
+            bundle_interval: 3600,
+            bundle_base_url: None,
+        };
+        let state = state();
+        let bundles = Bundles::new(
+            &options,
+            storage.clone(),
+            Arc::default(),
+            state.options.pack,
+        )
+        .unwrap()
+        .map(Arc::new);
+        let app = app(AppState {
+            storage,
+            bundles: bundles.clone(),
+            ..state
+        });
+        let credentials = format!("Basic {}", general_purpose::STANDARD.encode("alice:secret"));
+        let send = |req: hyper::http::request::Builder, body: Vec<u8>| {
+            let req = req
+                .header(AUTHORIZATION, &credentials)
+                .header(HOST, "git.example.com")
+                .header("Git-Protocol", "version=2")
+                .body(Body::from(body))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await;
+                (status, body.unwrap())
+            }
+        };
+        let command = format!("{} {} refs/heads/main\0report-status\n", ZERO_ID, HEAD);
+        let mut body = format!("{:04x}{}0000", command.len() + 4, command).into_bytes();
+        body.extend(std::fs::read(PACK).unwrap());
+        let (status, _) = send(
+            Request::post("/projects/demo.git/git-receive-pack"),
+            body,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let capabilities = || {
+            send(
+                Request::get("/projects/demo.git/info/refs?service=git-upload-pack"),
+                Vec::new(),
+            )
+        };
+        let bundle_uri = || {
+            let body = b"0017command=bundle-uri\n0000".to_vec();
+            send(Request::post("/projects/demo.git/git-upload-pack"), body)
+        };
+
+        // no bundle is made yet
+        let (_, caps) = capabilities().await;
+        assert!(!String::from_utf8_lossy(&caps).contains("bundle-uri"));
+        let (status, _) = send(Request::get("/projects/demo.git/bundle"), Vec::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let bundles = bundles.unwrap();
+        assert!(bundles.generate(Path::new("/projects/demo")).await.unwrap());
+        // the refs are the same
+        assert!(!bundles.generate(Path::new("/projects/demo")).await.unwrap());
+        let (_, caps) = capabilities().await;
+        assert!(String::from_utf8_lossy(&caps).contains("000fbundle-uri\n"));
+        let (_, list) = bundle_uri().await;
+        let list = String::from_utf8_lossy(&list);
+        assert!(list.contains("bundle.mode=all\n"), "{}", list);
+        assert!(
+            list.contains("bundle.all.uri=http://git.example.com/projects/demo.git/bundle\n"),
+            "{}",
+            list
+        );
+
+        let (status, bundle) = send(Request::get("/projects/demo.git/bundle"), Vec::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut reader = std::io::Cursor::new(&bundle[..]);
+        let refs = parse_header(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            refs,
+            [
+                (HEAD.to_owned(), "HEAD".to_owned()),
+                (HEAD.to_owned(), "refs/heads/main".to_owned())
+            ]
+        );
+        assert!(bundle[reader.position() as usize..].starts_with(b"PACK"));
+
+        let (status, _) = send(Request::get("/projects/none.git/bundle"), Vec::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(Request::get("/../demo.git/bundle"), Vec::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_cache() {
+        const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+        const HEAD: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+        let state = AppState {
+            storage: Arc::new(MemoryStorage::new()),
+            ..state()
+        };
+        let objects = state.objects.clone();
+        let app = app(state);
+        let basic = |user: &str| {
+            let user = format!("{}:secret", user);
+            format!("Basic {}", general_purpose::STANDARD.encode(user))
+        };
+
+        let command = format!(
+            "{} {} refs/heads/main\0report-status\n",
+            "0".repeat(40),
+            HEAD
+        );
+        let mut body = format!("{:04x}{}0000", command.len() + 4, command).into_bytes();
+        body.extend(std::fs::read(PACK).unwrap());
+        let req = Request::post("/projects/demo.git/git-receive-pack")
+            .header(AUTHORIZATION, basic("alice"))
+            .body(Body::from(body))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        hyper::body::to_bytes(resp.into_body()).await.unwrap();
+
+        let admin = |path: &str, user: &str, body: &str| {
+            Request::post(path)
+                .header(AUTHORIZATION, basic(user))
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_owned()))
+                .unwrap()
+        };
+        let warm = r#"{"repo": "projects/demo.git"}"#;
+        // the admin access is needed even for the users of the write access
+        let resp = app
+            .clone()
+            .oneshot(admin("/admin/cache/warm", "alice", warm))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = app
+            .clone()
+            .oneshot(admin("/admin/cache/warm", "root", r#"{"repo": "missing"}"#))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        assert_eq!(objects.size().0, 0);
+        let resp = app
+            .clone()
+            .oneshot(admin("/admin/cache/warm", "root", warm))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let warmed: CacheWarmed = serde_json::from_slice(&body).unwrap();
+        assert_eq!(warmed.repo, "/projects/demo");
+        assert!(warmed.loaded > 0);
+        assert_eq!(warmed.cache.objects, warmed.loaded);
+        assert_eq!(objects.size().0, warmed.loaded);
+        // nothing is new for the second time
+        let resp = app
+            .clone()
+            .oneshot(admin("/admin/cache/warm", "root", warm))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let again: CacheWarmed = serde_json::from_slice(&body).unwrap();
+        assert_eq!((again.loaded, again.cache.objects), (0, warmed.loaded));
+
+        let resp = app
+            .clone()
+            .oneshot(admin("/admin/cache/flush", "root", ""))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let flushed: CacheFlushed = serde_json::from_slice(&body).unwrap();
+        assert_eq!(flushed.flushed, warmed.loaded);
+        let req = Request::get("/admin/cache")
+            .header(AUTHORIZATION, basic("root"))
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let status: CacheStatus = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, flushed.cache);
+        let emptied = (status.objects, status.bytes, status.hits, status.misses);
+        assert_eq!(emptied, (0, 0, 0, 0));
+        assert_eq!(status.capacity, 1024 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_no_authenticator() {
+        let state = AppState {
+            storage: Arc::new(MemoryStorage::new()),
+            authenticator: None,
+            ..state()
+        };
+        let app = app(state);
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+
+        // anyone could be the admin, or delete the repos
+        for (method, uri) in [
+            ("GET", "/admin/cache"),
+            ("POST", "/admin/cache/flush"),
+            ("POST", "/admin/cache/warm"),
+            ("GET", "/admin/replication"),
+            ("DELETE", "/api/v1/repos/projects/demo.git"),
+        ] {
+            let resp = app.clone().oneshot(request(method, uri)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+        let resp = app
+            .oneshot(request("GET", "/api/v1/repos"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_repo_config_lfs_limit() {
+        // the sha256 of "hello world\n"
+        const OID: &str = "a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447";
+        let state = AppState {
+            storage: Arc::new(MemoryStorage::new()),
+            options: Cli::parse_from(["mega", "--max-lfs-object-size", "10"]).http,
+            ..state()
+        };
+        let app = app(state);
+        let credentials = |user: &str| {
+            format!(
+                "Basic {}",
+                general_purpose::STANDARD.encode(format!("{}:secret", user))
+            )
+        };
+        let batch = |repo: &str| {
+            let body = serde_json::json!({
+                "operation": "upload",
+                "objects": [{
+                    "oid": OID,
+                    "size": 12,
+                }],
+            });
+            Request::post(format!("/{}.git/info/lfs/objects/batch", repo))
+                .header(AUTHORIZATION, credentials("alice"))
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let upload = |repo: &str| {
+            Request::put(format!("/{}.git/info/lfs/objects/{}", repo, OID))
+                .header(AUTHORIZATION, credentials("alice"))
+                .body(Body::from("hello world\n"))
+                .unwrap()
+        };
+        let put = |user: &str, config: &str| {
+            Request::put("/api/v1/repo-config?repo_path=/projects/media")
+                .header(AUTHORIZATION, credentials(user))
+                .header("Content-Type", "application/json")
+                .body(Body::from(config.to_owned()))
+                .unwrap()
+        };
+        let json = |body: hyper::body::Bytes| -> serde_json::Value {
+            serde_json::from_slice(&body).unwrap()
+        };
+
+        // the global limit
+        let resp = app.clone().oneshot(batch("projects/media")).await.unwrap();
+        let body = json(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        assert_eq!(body["objects"][0]["error"]["code"], 422);
+
+        // only the users of the write access change the config
+        let config = r#"{"max_lfs_object_size": 100}"#;
+        let resp = app.clone().oneshot(put("bob", config)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = app
+            .clone()
+            .oneshot(put("alice", r#"{"max_size": 1}"#))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let resp = app.clone().oneshot(put("alice", config)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // the limit of the repo overrides the global one, which is kept for the others
+        let resp = app.clone().oneshot(batch("projects/media")).await.unwrap();
+        let body = json(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        assert!(body["objects"][0]["actions"]["upload"].is_object(), "{}", body);
+        let resp = app.clone().oneshot(batch("projects/other")).await.unwrap();
+        let body = json(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        assert_eq!(body["objects"][0]["error"]["code"], 422);
+        // and of the upload
+        let resp = app.clone().oneshot(upload("projects/media")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = Request::get("/api/v1/repo-config?repo_path=/projects/media")
+            .header(AUTHORIZATION, credentials("bob"))
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        let body = json(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        assert_eq!(body, serde_json::json!({"max_lfs_object_size": 100}));
+    }
+
+    #[tokio::test]
+    async fn test_list_repos_and_refs() {
+        let storage = Arc::new(MemoryStorage::new());
+        for (name, id) in [
+            ("refs/heads/main", "1"),
+            ("refs/heads/dev", "2"),
+            ("refs/tags/v1.0", "3"),
+            ("refs/tags/v2.0", "4"),
+        ] {
+            storage
+                .update_ref("/projects/mega", name, &id.repeat(40))
+                .await
+                .unwrap();
+        }
+        storage
+            .update_ref("/projects/docs", "refs/heads/main", &"5".repeat(40))
+            .await
+            .unwrap();
+        let app = app(AppState { storage, ..state() });
+        let get = |uri: &str, credentials: Option<&str>| {
+            let mut req = Request::get(uri);
+            if let Some(credentials) = credentials {
+                let encoded = general_purpose::STANDARD.encode(credentials);
+                req = req.header(AUTHORIZATION, format!("Basic {}", encoded));
+            }
+            req.body(Body::empty()).unwrap()
+        };
+        let json = |body: hyper::body::Bytes| -> serde_json::Value {
+            serde_json::from_slice(&body).unwrap()
+        };
+
+        // the same auth as a fetch
+        let resp = app
+            .clone()
+            .oneshot(get("/api/v1/repos", None))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = app
+            .clone()
+            .oneshot(get("/api/v1/repos", Some("bob:secret")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = json(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "repos": [{"name": "/projects/docs"}, {"name": "/projects/mega"}],
+                "next_cursor": null,
+            })
+        );
+
+        let uri = "/api/v1/repos/projects/mega.git/refs?limit=3";
+        let resp = app
+            .clone()
+            .oneshot(get(uri, Some("bob:secret")))
+            .await
+            .unwrap();
+        let body = json(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "refs": {
+                    "refs/heads/dev": "2".repeat(40),
+                    "refs/heads/main": "1".repeat(40),
+                    "refs/tags/v1.0": "3".repeat(40),
+                },
+                "next_cursor": "refs/tags/v1.0",
+            })
+        );
+        let uri = "/api/v1/repos/projects/mega/refs?limit=3&cursor=refs/tags/v1.0";
+        let resp = app
+            .clone()
+            .oneshot(get(uri, Some("bob:secret")))
+            .await
+            .unwrap();
+        let body = json(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "refs": {"refs/tags/v2.0": "4".repeat(40)},
+                "next_cursor": null,
+            })
+        );
+
+        let uri = "/api/v1/repos/projects/none/refs";
+        let resp = app.oneshot(get(uri, Some("bob:secret"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_ping_webhooks() {
+        use crate::webhook::delivery::{verify_signature, Dispatcher, SIGNATURE_HEADER};
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let receiver = Router::new().route(
+            "/",
+            post({
+                let received = received.clone();
+                move |headers: hyper::HeaderMap, body: String| async move {
+                    let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_owned();
+                    received.lock().unwrap().push((signature, body));
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(receiver.into_make_service());
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        let queue_path = std::env::temp_dir().join("mega_webhook_ping_api");
+        let _ = std::fs::remove_dir_all(&queue_path);
+        let options = Cli::parse_from([
+            "mega",
+            "--webhook-url",
+            &url,
+            "--webhook-secret",
+            "secret",
+            "--webhook-queue-path",
+            queue_path.to_str().unwrap(),
+        ])
+        .http;
+        let webhooks = app(AppState {
+            storage: Arc::new(MemoryStorage::new()),
+            webhooks: Some(Arc::new(Dispatcher::new(&options.delivery).unwrap())),
+            ..state()
+        });
+        let ping = |user: &str, uri: &str| {
+            let credentials = general_purpose::STANDARD.encode(format!("{}:secret", user));
+            Request::post(uri)
+                .header(AUTHORIZATION, format!("Basic {}", credentials))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let resp = webhooks
+            .clone()
+            .oneshot(ping("bob", "/api/v1/webhooks/ping"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let uri = "/api/v1/webhooks/ping?repo_path=/projects/mega";
+        let resp = webhooks.clone().oneshot(ping("alice", uri)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(resp.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(body[0]["url"], url);
+        assert_eq!(body[0]["status"], 204);
+        assert!(body[0]["latency_ms"].is_u64());
+
+        let (signature, body) = received.lock().unwrap()[0].clone();
+        assert!(verify_signature(b"secret", body.as_bytes(), &signature));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["repo"], "/projects/mega");
+
+        // the attempt is logged by the id of the receiver
+        let get = |uri: &str| {
+            let credentials = general_purpose::STANDARD.encode("bob:secret");
+            Request::get(uri)
+                .header(AUTHORIZATION, format!("Basic {}", credentials))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let resp = webhooks
+            .clone()
+            .oneshot(get("/api/v1/webhooks"))
+            .await
+            .unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(resp.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(body[0]["url"], url);
+        let id = body[0]["id"].as_str().unwrap();
+        let uri = format!("/api/v1/webhooks/{}/deliveries", id);
+        let resp = webhooks.oneshot(get(&uri)).await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(resp.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["event"], "ping");
+        assert_eq!(body[0]["status"], 204);
+
+        // no receiver
+        let resp = app(state())
+            .oneshot(ping("alice", "/api/v1/webhooks/ping"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_create_and_delete_repo() {
+        let storage = Arc::new(MemoryStorage::new());
+        let app = app(AppState {
+            storage: storage.clone(),
+            ..state()
+        });
+        let request = |method: &str, uri: &str, user: &str, body: &str| {
+            let credentials = general_purpose::STANDARD.encode(format!("{}:secret", user));
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(AUTHORIZATION, format!("Basic {}", credentials))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_owned()))
+                .unwrap()
+        };
+        let create = |user: &str, name: &str| {
+            let body = serde_json::json!({ "name": name }).to_string();
+            request("POST", "/api/v1/repos", user, &body)
+        };
+
+        // the write access is needed
+        let resp = app
+            .clone()
+            .oneshot(create("bob", "projects/new"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = app
+            .clone()
+            .oneshot(create("alice", "projects/new.git"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"name":"/projects/new"}"#);
+        let resp = app
+            .clone()
+            .oneshot(create("alice", "/projects/new"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = app
+            .clone()
+            .oneshot(create("alice", "projects/../new"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = app
+            .clone()
+            .oneshot(request("GET", "/api/v1/repos", "bob", ""))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("/projects/new"));
+
+        // the deletion is confirmed by the query
+        let uri = "/api/v1/repos/projects/new.git";
+        let resp = app
+            .clone()
+            .oneshot(request("DELETE", uri, "alice", ""))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let uri = "/api/v1/repos/projects/new.git?confirm=true";
+        let resp = app
+            .clone()
+            .oneshot(request("DELETE", uri, "bob", ""))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = app
+            .clone()
+            .oneshot(request("DELETE", uri, "alice", ""))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(resp.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"name": "/projects/new", "objects": 0, "lfs_objects": 0})
+        );
+        assert!(storage
+            .get_directory_by_full_path("/projects/new")
+            .await
+            .unwrap()
+            .is_none());
+
+        let resp = app
+            .oneshot(request("DELETE", uri, "alice", ""))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let options = Cli::parse_from([
+            "mega",
+            "--rate-limit-write",
+            "0.01",
+            "--rate-limit-write-burst",
+            "3",
+            "--trusted-proxy",
+        ])
+        .http;
+        let state = AppState {
+            rate_limits: Arc::new(RateLimits::from(&options.rate_limit)),
+            options,
+            ..state()
+        };
+        let app = app(state);
+        let push_from = |ip: &str| {
+            let mut req = push(Some("alice:secret"));
+            req.headers_mut()
+                .insert("X-Forwarded-For", format!("10.0.0.1, {}", ip).parse().unwrap());
+            req
+        };
+
+        for _ in 0..3 {
+            let resp = app.clone().oneshot(push_from("192.168.1.1")).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        let resp = app.clone().oneshot(push_from("192.168.1.1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()[RETRY_AFTER], "100");
+
+        // the other client is not limited
+        let resp = app.oneshot(push_from("192.168.1.2")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        // a request taking a while, like a push writing the objects
+        let (started_tx, started_rx) = oneshot::channel();
+        let started_tx = Arc::new(Mutex::new(Some(started_tx)));
+        let app = Router::new().route(
+            "/push",
+            post(move || async move {
+                if let Some(tx) = started_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "pushed"
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = serve(
+            listener,
+            None,
+            app,
+            shutdown.clone(),
+            Duration::from_secs(10),
+            Timeouts::default(),
+        );
+
+        let client = async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"POST /push HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            started_rx.await.unwrap();
+            shutdown.trigger();
+
+            // the request in flight is finished, and then the connection is closed
+            let mut resp = String::new();
+            stream.read_to_string(&mut resp).await.unwrap();
+            resp
+        };
+        let (result, resp) = tokio::join!(server, client);
+        result.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+        assert!(resp.ends_with("pushed"));
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let timeouts = Timeouts {
+            handshake: Some(Duration::from_millis(300)),
+            idle: Some(Duration::from_millis(600)),
+        };
+        let server = serve(
+            listener,
+            None,
+            app(state()),
+            shutdown.clone(),
+            Duration::from_secs(10),
+            timeouts,
+        );
+
+        let client = async {
+            // a half-open clone which never sends the request
+            let start = Instant::now();
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+            assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+
+            // the connection kept alive after a request is dropped once it's idle
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let mut resp = Vec::new();
+            while !String::from_utf8_lossy(&resp).contains(r#""status":"ok""#) {
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0, "{}", String::from_utf8_lossy(&resp));
+                resp.extend_from_slice(&buf[..n]);
+            }
+            let start = Instant::now();
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+            assert!(start.elapsed() >= Duration::from_millis(500));
+            shutdown.trigger();
+        };
+        let (result, ()) = tokio::join!(server, client);
+        result.unwrap();
+    }
+
+    /// The preflight of the origin, and then the fetch of it.
+    async fn cors_requests(state: AppState, origin: &str) -> (Response, Response) {
+        let app = app(AppState {
+            storage: Arc::new(MemoryStorage::new()),
+            ..state
+        });
+        let preflight = Request::options("/repo.git/info/refs?service=git-upload-pack")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .header(ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::empty())
+            .unwrap();
+        let preflight = app.clone().oneshot(preflight).await.unwrap();
+        let fetch = Request::get("/repo.git/info/refs?service=git-upload-pack")
+            .header(ORIGIN, origin)
+            .header(AUTHORIZATION, "Basic Ym9iOnNlY3JldA==")
+            .body(Body::empty())
+            .unwrap();
+        (preflight, app.oneshot(fetch).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_cors() {
+        let allowed = AppState {
+            options: Cli::parse_from([
+                "mega",
+                "--cors-allowed-origin",
+                "https://git.example.com",
+                "--cors-allow-credentials",
+            ])
+            .http,
+            ..state()
+        };
+        let (preflight, fetch) = cors_requests(allowed.clone(), "https://git.example.com").await;
+        // answered before the authentication
+        assert_eq!(preflight.status(), StatusCode::OK);
+        assert_eq!(
+            preflight.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://git.example.com"
+        );
+        assert!(preflight.headers()[ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap()
+            .contains("GET"));
+        assert!(preflight.headers()[ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap()
+            .contains("authorization"));
+        assert_eq!(
+            preflight.headers()[ACCESS_CONTROL_ALLOW_CREDENTIALS],
+            "true"
+        );
+        assert_eq!(fetch.status(), StatusCode::OK);
+        assert_eq!(
+            fetch.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://git.example.com"
+        );
+
+        // the other origins have no headers
+        let (preflight, fetch) = cors_requests(allowed, "https://evil.example.com").await;
+        assert!(!preflight
+            .headers()
+            .contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!fetch.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // and there is no CORS by default
+        let (preflight, fetch) = cors_requests(state(), "https://git.example.com").await;
+        assert!(!preflight
+            .headers()
+            .contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(fetch.status(), StatusCode::OK);
+        assert!(!fetch.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn test_options_builder() {
+        let lfs_content_path = std::env::temp_dir().join("mega_lfs_builder");
+        let options = HttpOptions::builder()
+            .host("0.0.0.0")
+            .port(8001)
+            .lfs_content_path(&lfs_content_path)
+            .build()
+            .unwrap();
+        assert_eq!(options.host, "0.0.0.0");
+        assert_eq!(options.port, 8001);
+        assert_eq!(options.lfs_content_path, lfs_content_path);
+        // the defaults of the command line
+        let defaults = Cli::parse_from(["mega"]).http;
+        assert_eq!(options.lfs_partial_ttl, defaults.lfs_partial_ttl);
+        assert_eq!(
+            options.rate_limit.rate_limit_read_burst,
+            defaults.rate_limit.rate_limit_read_burst
+        );
+        assert!(HttpOptions::builder().build().is_ok());
+        assert!(HttpOptionsBuilder::from(defaults).build().is_ok());
+    }
+
+    #[test]
+    fn test_options_builder_invalid() {
+        let err = HttpOptions::builder().host(" ").build().unwrap_err();
+        assert!(err.to_string().contains("host"), "{}", err);
+        let err = HttpOptions::builder().host("unix:").build().unwrap_err();
+        assert!(err.to_string().contains("socket"), "{}", err);
+        let err = HttpOptions::builder().port(0).build().unwrap_err();
+        assert!(err.to_string().contains("port"), "{}", err);
+        let err = HttpOptions::builder()
+            .lfs_content_path("/mega_not_found/lfs_content")
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+        assert_eq!(err.code, 1);
+        let err = HttpOptions::builder()
+            .database_url("mongodb://localhost/mega")
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("Unsupported"), "{}", err);
+        assert!(HttpOptions::builder()
+            .database_url("memory://")
+            .build()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_serve_all() {
+        let mut listeners = Vec::new();
+        let mut addrs = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap());
+            listeners.push(Listener::Tcp(listener));
+        }
+        let shutdown = Shutdown::new();
+        let server = serve_all(
+            listeners,
+            None,
+            app(state()),
+            shutdown.clone(),
+            Duration::from_secs(10),
+            Timeouts::default(),
+        );
+
+        let client = async {
+            for addr in &addrs {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                stream
+                    .write_all(
+                        b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                let mut resp = String::new();
+                stream.read_to_string(&mut resp).await.unwrap();
+                assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+                assert!(resp.contains(r#""status":"ok""#), "{}", resp);
+            }
+            shutdown.trigger();
+        };
+        let (result, ()) = tokio::join!(server, client);
+        result.unwrap();
+        for addr in &addrs {
+            assert!(TcpStream::connect(addr).await.is_err());
+        }
+    }
+
+    #[test]
+    fn test_listen_addrs() {
+        assert_eq!(
+            ListenAddr::parse("10.0.0.1:8000"),
+            Ok(ListenAddr::Tcp {
+                host: String::from("10.0.0.1"),
+                port: 8000
+            })
+        );
+        assert_eq!(
+            ListenAddr::parse("[2001:db8::1]:443"),
+            Ok(ListenAddr::Tcp {
+                host: String::from("2001:db8::1"),
+                port: 443
+            })
+        );
+        assert_eq!(
+            ListenAddr::parse("unix:/run/mega/http.sock"),
+            Ok(ListenAddr::Unix(PathBuf::from("/run/mega/http.sock")))
+        );
+        assert!(ListenAddr::parse("10.0.0.1").is_err());
+        assert!(ListenAddr::parse(":8000").is_err());
+        assert!(ListenAddr::parse("unix:").is_err());
+
+        let options = Cli::parse_from(["mega", "--port", "8001"]).http;
+        assert_eq!(
+            options.listen_addrs(),
+            vec![ListenAddr::Tcp {
+                host: String::from("127.0.0.1"),
+                port: 8001
+            }]
+        );
+        let options = Cli::parse_from([
+            "mega",
+            "--listen",
+            "127.0.0.1:8001",
+            "--listen",
+            "[::1]:8002",
+        ])
+        .http;
+        assert_eq!(options.listen_addrs().len(), 2);
+        assert!(HttpOptions::builder()
+            .listen(ListenAddr::parse("127.0.0.1:0").unwrap())
+            .build()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let state = AppState {
+            options: Cli::parse_from([
+                "mega",
+                "--max-push-size",
+                "1024",
+                "--max-lfs-request-size",
+                "16",
+            ])
+            .http,
+            ..state()
+        };
+        let credentials = format!("Basic {}", general_purpose::STANDARD.encode("alice:secret"));
+
+        // an endless pack is aborted at the limit rather than buffered
+        let endless = futures::stream::repeat_with(|| Ok::<_, std::io::Error>(vec![0u8; 4096]));
+        let req = Request::post("/repo.git/git-receive-pack")
+            .header(AUTHORIZATION, &credentials)
+            .body(Body::wrap_stream(endless))
+            .unwrap();
+        let resp = app(state.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // and a larger one declared is rejected before it's read
+        let req = Request::post("/repo.git/git-receive-pack")
+            .header(AUTHORIZATION, &credentials)
+            .header(CONTENT_LENGTH, 2048)
+            .body(Body::from(vec![0u8; 2048]))
+            .unwrap();
+        let resp = app(state.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // the LFS requests have the limit of their own
+        let req = Request::post("/repo.git/info/lfs/objects/batch")
+            .header(AUTHORIZATION, &credentials)
+            .body(Body::from(r#"{"operation": "upload", "objects": []}"#))
+            .unwrap();
+        let resp = app(state.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // the push under the limit is served
+        let resp = app(state)
+            .oneshot(push(Some("alice:secret")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,205 @@
+//! Import the objects of a packfile into the storage without the server, e.g. to migrate
+//! the repositories in bulk.
+//!
+//! The pack is decoded object by object by [`PackStream`], so the size of it is not limited by
+//! the memory, and the objects are saved in batches.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use common::errors::MegaError;
+use database::driver::ObjectStorage;
+use database::utils::id_generator::generate_id;
+use database::DataSource;
+use entity::git_obj;
+use git::internal::pack::stream::PackStream;
+use git::internal::ObjectType;
+use sea_orm::Set;
+
+#[derive(Args, Clone, Debug)]
+pub struct ImportOptions {
+    /// The packfile to import
+    #[arg(long, value_name = "FILE")]
+    pub pack: PathBuf,
+
+    /// The database to save the objects in, e.g. `postgres://postgres@localhost/mega`, the one
+    /// of the `data_source` if not set
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    #[arg(short, long, value_enum, default_value = "postgres")]
+    pub data_source: DataSource,
+
+    /// Save the objects in batches of this size
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    pub batch_size: usize,
+}
+
+/// The counts of the imported objects by type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub commits: usize,
+    pub trees: usize,
+    pub blobs: usize,
+    pub tags: usize,
+    /// The objects of the pack saved already, which are not saved again
+    pub deduplicated: usize,
+}
+
+impl ImportSummary {
+    fn count(&mut self, object_type: ObjectType) {
+        match object_type {
+            ObjectType::Commit => self.commits += 1,
+            ObjectType::Tree => self.trees += 1,
+            ObjectType::Blob => self.blobs += 1,
+            ObjectType::Tag => self.tags += 1,
+            // the deltas are resolved by the decoder
+            ObjectType::OffsetDelta | ObjectType::HashDelta => {}
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.commits + self.trees + self.blobs + self.tags
+    }
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "commit: {}, tree: {}, blob: {}, tag: {}, total: {}, deduplicated: {}",
+            self.commits,
+            self.trees,
+            self.blobs,
+            self.tags,
+            self.total(),
+            self.deduplicated
+        )
+    }
+}
+
+/// Connect the storage of the options, and import the pack into it.
+pub async fn import_pack(options: &ImportOptions) -> Result<ImportSummary, MegaError> {
+    let storage =
+        database::storage_from_options(options.database_url.as_deref(), &options.data_source)
+            .await?;
+    let pack = File::open(&options.pack).map_err(|e| {
+        MegaError::new(
+            anyhow::anyhow!("failed to open {}: {}", options.pack.display(), e),
+            1,
+        )
+    })?;
+    import_pack_into(pack, storage, options.batch_size).await
+}
+
+/// Decode the pack and save the objects in the storage, the bases of a thin pack are loaded
+/// from the storage too. The objects saved before a bad one are kept.
+pub async fn import_pack_into<R: Read>(
+    pack: R,
+    storage: Arc<dyn ObjectStorage>,
+    batch_size: usize,
+) -> Result<ImportSummary, MegaError> {
+    let git_error = |e: git::errors::GitError| MegaError::new(e.into(), 1);
+    let mut stream = PackStream::new(pack).map_err(git_error)?;
+    stream.set_storage(Some(storage.clone()));
+
+    let mut summary = ImportSummary::default();
+    let mut batch = Vec::with_capacity(batch_size);
+    while let Some(object) = stream.next_object().await.map_err(git_error)? {
+        summary.count(object.object_type);
+        batch.push(git_obj::ActiveModel {
+            id: Set(generate_id()),
+            git_id: Set(object.hash.to_plain_str()),
+            object_type: Set(object.object_type.to_string()),
+            data: Set(object.data.clone()),
+            compression: Set(None),
+        });
+        if batch.len() >= batch_size {
+            save_batch(&storage, std::mem::take(&mut batch), &mut summary).await?;
+            tracing::info!("Imported git objects: {}", stream.progress());
+        }
+    }
+    if !batch.is_empty() {
+        save_batch(&storage, batch, &mut summary).await?;
+    }
+    Ok(summary)
+}
+
+/// Save the objects of the batch which are not saved yet, and count the others.
+async fn save_batch(
+    storage: &Arc<dyn ObjectStorage>,
+    batch: Vec<git_obj::ActiveModel>,
+    summary: &mut ImportSummary,
+) -> Result<(), MegaError> {
+    let count = batch.len();
+    let unsaved = storage.unsaved_obj_data(batch).await?;
+    summary.deduplicated += count - unsaved.len();
+    if !unsaved.is_empty() {
+        storage.save_obj_data(unsaved).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use super::{import_pack_into, ImportSummary};
+    use crate::tests::MockStorage;
+
+    const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+
+    #[tokio::test]
+    async fn test_import_pack() {
+        let storage = Arc::new(MockStorage::default());
+        let summary = import_pack_into(File::open(PACK).unwrap(), storage.clone(), 100)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                commits: 37,
+                trees: 154,
+                blobs: 133,
+                tags: 0,
+                deduplicated: 0,
+            }
+        );
+        assert_eq!(storage.saved(), 324);
+        assert_eq!(
+            summary.to_string(),
+            "commit: 37, tree: 154, blob: 133, tag: 0, total: 324, deduplicated: 0"
+        );
+
+        // all the objects are saved already
+        let summary = import_pack_into(File::open(PACK).unwrap(), storage.clone(), 100)
+            .await
+            .unwrap();
+        assert_eq!(summary.total(), 324);
+        assert_eq!(summary.deduplicated, 324);
+        assert_eq!(storage.saved(), 324);
+    }
+
+    #[tokio::test]
+    async fn test_import_bad_pack() {
+        let storage = Arc::new(MockStorage::default());
+        let mut data = std::fs::read(PACK).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        let err = import_pack_into(Cursor::new(data), storage.clone(), 100)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, 1);
+
+        let err = import_pack_into(Cursor::new(b"not a pack".to_vec()), storage, 100)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, 1);
+    }
+}
@@ -0,0 +1,336 @@
+//! The garbage collection of the LFS objects, of which the content is removed if no ref of any
+//! repository points to it, e.g. after the branches of the large files are deleted.
+//!
+//! An object is kept if a blob reachable from a ref is the pointer of it. It's safe against the
+//! pushes during the gc by the snapshot: the metas are listed before the refs are read, so an
+//! object uploaded later is never a candidate, and the refs are read again after the walk until
+//! none of them is updated, so the objects of a push finished meanwhile are kept as well.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use common::errors::{GitLFSError, MegaError};
+use database::driver::lfs::s3::S3Storage;
+use database::driver::lfs::storage::{ContentStore, LfsStorage, MetaObject};
+use database::driver::lfs::structs::RequestVars;
+use database::driver::ObjectStorage;
+use database::DataSource;
+use git::hash::Hash;
+use git::internal::object::commit::Commit;
+use git::internal::object::tag::Tag;
+use git::internal::object::tree::{Tree, TreeItemMode};
+use git::internal::object::ObjectT;
+use git::internal::ObjectType;
+use git::lfs::pointer::pointer_oid;
+
+use crate::https::{LfsStorageType, S3Options};
+use crate::StorageOptions;
+
+#[derive(Args, Clone, Debug)]
+pub struct LfsGcOptions {
+    /// Only report the objects to remove, nothing is removed
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// The database of the refs and the LFS metas, e.g. `postgres://postgres@localhost/mega`,
+    /// the one of the `data_source` if not set
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    #[arg(short, long, value_enum, default_value = "postgres")]
+    pub data_source: DataSource,
+
+    #[arg(short, long, default_value_os_t = PathBuf::from("lfs_content"))]
+    pub lfs_content_path: PathBuf,
+
+    /// The storage of the LFS objects content
+    #[arg(long, value_enum, default_value = "local")]
+    pub lfs_storage: LfsStorageType,
+
+    #[clap(flatten)]
+    pub s3: S3Options,
+}
+
+impl StorageOptions for LfsGcOptions {
+    fn data_source(&self) -> &DataSource {
+        &self.data_source
+    }
+
+    fn lfs_storage(&self) -> Arc<dyn LfsStorage> {
+        match self.lfs_storage {
+            LfsStorageType::Local => Arc::new(ContentStore::new(self.lfs_content_path.clone())),
+            LfsStorageType::S3 => Arc::new(S3Storage::new(self.s3.clone().into())),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LfsGcSummary {
+    /// The objects referenced by a ref
+    pub kept: usize,
+    /// The objects removed, or to remove of a dry run
+    pub removed: Vec<MetaObject>,
+}
+
+impl LfsGcSummary {
+    /// The size of the objects removed.
+    pub fn reclaimed(&self) -> u64 {
+        self.removed.iter().map(|meta| meta.size as u64).sum()
+    }
+}
+
+impl fmt::Display for LfsGcSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "kept: {}, removed: {}, reclaimed: {} bytes",
+            self.kept,
+            self.removed.len(),
+            self.reclaimed()
+        )
+    }
+}
+
+/// Connect the storages of the options, and remove the LFS objects not referenced.
+pub async fn lfs_gc(options: &LfsGcOptions) -> Result<LfsGcSummary, MegaError> {
+    let storage =
+        database::storage_from_options(options.database_url.as_deref(), &options.data_source)
+            .await?;
+    lfs_gc_in(storage, options.lfs_storage(), options.dry_run).await
+}
+
+/// Remove the LFS objects of which no blob reachable from the refs is the pointer, the meta
+/// first, so an object is never served without the content.
+pub async fn lfs_gc_in(
+    storage: Arc<dyn ObjectStorage>,
+    lfs_storage: Arc<dyn LfsStorage>,
+    dry_run: bool,
+) -> Result<LfsGcSummary, MegaError> {
+    let metas = storage.lfs_get_metas().await.map_err(lfs_error)?;
+    let (_, referenced) = reachable(storage.as_ref(), None).await?;
+
+    let mut summary = LfsGcSummary::default();
+    for meta in metas {
+        if referenced.contains(&meta.oid) {
+            summary.kept += 1;
+            continue;
+        }
+        if !dry_run {
+            remove_lfs_object(storage.as_ref(), lfs_storage.as_ref(), &meta).await?;
+        }
+        summary.removed.push(meta);
+    }
+    Ok(summary)
+}
+
+/// Remove the meta of the LFS object, then the content of it.
+pub(crate) async fn remove_lfs_object(
+    storage: &dyn ObjectStorage,
+    lfs_storage: &dyn LfsStorage,
+    meta: &MetaObject,
+) -> Result<(), MegaError> {
+    let vars = RequestVars {
+        oid: meta.oid.clone(),
+        size: meta.size,
+        ..Default::default()
+    };
+    storage.lfs_delete_meta(&vars).await.map_err(lfs_error)?;
+    lfs_storage.delete(meta).await.map_err(lfs_error)
+}
+
+/// The objects and the oids of the pointers reachable from the refs of all the repos but the
+/// `except` one, of which the refs are read until none is updated during the walk.
+pub(crate) async fn reachable(
+    storage: &dyn ObjectStorage,
+    except: Option<&str>,
+) -> Result<(HashSet<Hash>, HashSet<String>), MegaError> {
+    let mut seen = HashSet::new();
+    let mut oids = HashSet::new();
+    let mut tips = HashSet::new();
+    loop {
+        let refs: HashSet<String> = storage
+            .get_all_refs()
+            .await?
+            .into_iter()
+            .filter(|r| except != Some(r.repo_path.as_str()))
+            .map(|r| r.ref_git_id)
+            .collect();
+        if refs.is_subset(&tips) {
+            return Ok((seen, oids));
+        }
+        let queue = refs
+            .difference(&tips)
+            .map(|id| Hash::new_from_str(id))
+            .collect();
+        walk(storage, queue, &mut seen, &mut oids).await?;
+        tips.extend(refs);
+    }
+}
+
+/// Walk the objects from the queue, which are not `seen`, and collect the oids of the pointers.
+/// It's an error if an object is missing, then nothing is known to be unreferenced.
+pub(crate) async fn walk(
+    storage: &dyn ObjectStorage,
+    mut queue: VecDeque<Hash>,
+    seen: &mut HashSet<Hash>,
+    oids: &mut HashSet<String>,
+) -> Result<(), MegaError> {
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        let model = storage
+            .get_obj_data_by_id(&hash.to_plain_str())
+            .await?
+            .ok_or_else(|| {
+                MegaError::new(anyhow::anyhow!("the object {} is not found", hash), 1)
+            })?;
+        let object_type =
+            ObjectType::from_string(&model.object_type).map_err(|e| MegaError::new(e.into(), 1))?;
+        match object_type {
+            ObjectType::Commit => {
+                let commit = Commit::new_from_data(model.data);
+                queue.push_back(commit.tree_id);
+                queue.extend(commit.parent_tree_ids);
+            }
+            ObjectType::Tree => queue.extend(
+                Tree::new_from_data(model.data)
+                    .tree_items
+                    .into_iter()
+                    .filter(|item| item.mode != TreeItemMode::Commit)
+                    .map(|item| item.id),
+            ),
+            ObjectType::Tag => queue.push_back(Tag::new_from_data(model.data).object_hash),
+            ObjectType::Blob => oids.extend(pointer_oid(&model.data)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn lfs_error(e: GitLFSError) -> MegaError {
+    match e {
+        GitLFSError::GeneralError(message) => MegaError::new(anyhow::anyhow!(message), 1),
+        e => MegaError::new(e.into(), 1),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use database::driver::lfs::storage::{bytes_stream, ContentStore, LfsStorage, MetaObject};
+    use database::driver::lfs::structs::RequestVars;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+    use entity::git_obj;
+    use git::hash::Hash;
+    use sea_orm::Set;
+    use sha2::{Digest, Sha256};
+
+    use super::lfs_gc_in;
+
+    /// Save the object in the storage, returns the id of it.
+    pub(crate) async fn save(storage: &MemoryStorage, object_type: &str, data: Vec<u8>) -> Hash {
+        let mut raw = format!("{} {}\0", object_type, data.len()).into_bytes();
+        raw.extend(&data);
+        let hash = Hash::new(&raw);
+        let model = git_obj::ActiveModel {
+            id: Set(0),
+            git_id: Set(hash.to_plain_str()),
+            object_type: Set(object_type.to_owned()),
+            data: Set(data),
+            compression: Set(None),
+        };
+        storage.save_obj_data(vec![model]).await.unwrap();
+        hash
+    }
+
+    /// Upload the content as an LFS object, returns the meta of it.
+    pub(crate) async fn upload(
+        storage: &MemoryStorage,
+        lfs_storage: &ContentStore,
+        content: &str,
+    ) -> MetaObject {
+        let vars = RequestVars {
+            oid: hex::encode(Sha256::digest(content)),
+            size: content.len() as i64,
+            ..Default::default()
+        };
+        let meta = storage.lfs_put_meta(&vars).await.unwrap();
+        assert!(lfs_storage
+            .put(&meta, bytes_stream(content.to_owned()))
+            .await
+            .unwrap());
+        meta
+    }
+
+    pub(crate) fn pointer(meta: &MetaObject) -> Vec<u8> {
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+            meta.oid, meta.size
+        )
+        .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_lfs_gc() {
+        let dir = std::env::temp_dir().join(format!("mega_lfs_gc_{}", std::process::id()));
+        let storage = Arc::new(MemoryStorage::new());
+        let lfs_storage = Arc::new(ContentStore::new(dir.clone()));
+        let referenced = upload(&storage, &lfs_storage, "the referenced content").await;
+        let unreferenced = upload(&storage, &lfs_storage, "the unreferenced content").await;
+
+        let blob = save(&storage, "blob", pointer(&referenced)).await;
+        let mut tree = b"100644 large.bin\0".to_vec();
+        tree.extend(blob.as_bytes());
+        let tree = save(&storage, "tree", tree).await;
+        let author = "mega <mega@example.com> 1700000000 +0800";
+        let commit = format!(
+            "tree {}\nauthor {}\ncommitter {}\n\nadd the large file\n",
+            tree.to_plain_str(),
+            author,
+            author
+        );
+        let commit = save(&storage, "commit", commit.into_bytes()).await;
+        storage
+            .update_ref("/projects/lfs", "refs/heads/main", &commit.to_plain_str())
+            .await
+            .unwrap();
+
+        // nothing is removed by a dry run
+        let summary = lfs_gc_in(storage.clone(), lfs_storage.clone(), true)
+            .await
+            .unwrap();
+        assert_eq!(summary.kept, 1);
+        assert_eq!(summary.removed.len(), 1);
+        assert_eq!(summary.removed[0].oid, unreferenced.oid);
+        assert_eq!(summary.reclaimed(), unreferenced.size as u64);
+        assert!(lfs_storage.exist(&unreferenced).await);
+
+        let summary = lfs_gc_in(storage.clone(), lfs_storage.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary.to_string(),
+            format!(
+                "kept: 1, removed: 1, reclaimed: {} bytes",
+                unreferenced.size
+            )
+        );
+        assert!(lfs_storage.exist(&referenced).await);
+        assert!(!lfs_storage.exist(&unreferenced).await);
+        let metas = storage.lfs_get_metas().await.unwrap();
+        assert_eq!(metas.len(), 1);
+        assert_eq!(metas[0].oid, referenced.oid);
+
+        // and nothing is left to remove
+        let summary = lfs_gc_in(storage, lfs_storage, false).await.unwrap();
+        assert_eq!(summary.kept, 1);
+        assert!(summary.removed.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
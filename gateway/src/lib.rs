@@ -4,26 +4,224 @@
 //!
 //!
 
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use database::driver::mysql::storage::MysqlStorage;
+use clap::Args;
+use database::driver::lfs::s3::S3Storage;
+use database::driver::lfs::storage::{ContentStore, LfsStorage};
+use database::{driver::ObjectStorage, DataSource};
+use git::internal::pack::encode::{DEFAULT_DEPTH, DEFAULT_WINDOW};
+use git::internal::pack::preload::{
+    default_decode_threads, DecodeOptions, DEFAULT_MAX_DELTA_DEPTH,
+};
+use git::lfs::partial::{self, PartialUploads};
 use git::lfs::LfsConfig;
-use https::HttpOptions;
+use git::protocol::hooks::{ReceiveHook, ScriptHooks};
+use git::protocol::progress::DEFAULT_KEEPALIVE;
+use https::{HttpOptions, LfsStorageType};
+use tokio::net::TcpListener;
 use webhook::WebhookOptions;
+pub mod auth;
+pub mod body_limit;
+pub mod bundle;
+pub mod client_ip;
+pub mod compression;
+pub mod cors;
+pub mod export;
+pub mod health;
 pub mod https;
+pub mod import;
+pub mod lfs_gc;
+pub mod logging;
+pub mod metrics;
+pub mod migrate;
+pub mod pack_inspect;
+pub mod rate_limit;
+pub mod read_through;
+pub mod repo_config;
+pub mod replication;
+pub mod shutdown;
 pub mod ssh;
+pub mod timeout;
+pub mod tls;
+pub mod uds;
 pub mod webhook;
 mod model;
 mod api_service;
 
+/// The delta compression of the packs to fetch, like the options of `git pack-objects`, the
+/// keepalives while they are built, and the decode of the pushed ones.
+#[derive(Args, Clone, Copy, Debug)]
+pub struct PackOptions {
+    /// Compare each object with this many others for the best delta base, 0 to disable the
+    /// delta compression
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_WINDOW)]
+    pub window: usize,
+
+    /// The max length of the delta chains
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_DEPTH)]
+    pub depth: usize,
+
+    /// Send a keepalive in the side-band if nothing else is sent for this many seconds while a
+    /// pack to fetch is built, 0 to disable them
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_KEEPALIVE.as_secs())]
+    pub upload_pack_keepalive: u64,
+
+    /// The threads to decode the pack of a push, one per CPU by default
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub decode_threads: Option<u16>,
+
+    /// Reject a pushed pack of which a delta chain is longer than this, so it can't stall the
+    /// decode
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_DELTA_DEPTH)]
+    pub max_delta_depth: usize,
+}
+
+impl PackOptions {
+    /// The interval of the keepalives, none if they are disabled.
+    pub fn keepalive(&self) -> Option<Duration> {
+        (self.upload_pack_keepalive > 0).then(|| Duration::from_secs(self.upload_pack_keepalive))
+    }
+
+    /// How the pack of a push is decoded.
+    pub fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            threads: self
+                .decode_threads
+                .map_or_else(default_decode_threads, usize::from),
+            max_delta_depth: self.max_delta_depth,
+        }
+    }
+}
+
+/// The hooks of the pushes, see [`ScriptHooks`].
+#[derive(Args, Clone, Debug, Default)]
+pub struct HookOptions {
+    /// The executable run before the refs of a push are updated, which rejects the push by a
+    /// non-zero exit. The updates are the `<old-id> <new-id> <ref-name>` lines of the stdin,
+    /// and the output is relayed to the client
+    #[arg(long, value_name = "FILE")]
+    pub pre_receive_hook: Option<PathBuf>,
+
+    /// The executable run after the refs of a push are updated, of the same stdin
+    #[arg(long, value_name = "FILE")]
+    pub post_receive_hook: Option<PathBuf>,
+}
+
+impl HookOptions {
+    /// The hooks of the executables, none if neither is set.
+    pub fn hooks(&self) -> Option<Arc<dyn ReceiveHook>> {
+        if self.pre_receive_hook.is_none() && self.post_receive_hook.is_none() {
+            return None;
+        }
+        Some(Arc::new(ScriptHooks {
+            pre_receive: self.pre_receive_hook.clone(),
+            post_receive: self.post_receive_hook.clone(),
+        }))
+    }
+}
+
+/// Bind the address of a server, the error tells the address, e.g. of which the port is in use.
+pub async fn bind(host: &str, port: u16) -> io::Result<TcpListener> {
+    // an IPv6 host may be like `[::1]` of the urls
+    let ip = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    TcpListener::bind((ip, port))
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to bind {}:{}: {}", host, port, e)))
+}
+
+/// Bind the admin port of the `/metrics` on the host of the server.
+pub async fn bind_metrics(
+    host: &str,
+    metrics_port: Option<u16>,
+) -> io::Result<Option<std::net::TcpListener>> {
+    match metrics_port {
+        Some(port) => Ok(Some(bind(host, port).await?.into_std()?)),
+        None => Ok(None),
+    }
+}
+
+/// Create the directory of the LFS objects and the partial uploads if it is missing, and check
+/// it is a writable directory, so a bad path fails the server at the startup rather than the
+/// first upload.
+pub fn prepare_lfs_content_path(path: &Path) -> io::Result<()> {
+    let error = |e: io::Error, reason: &str| {
+        io::Error::new(
+            e.kind(),
+            format!("the LFS content path {} {}: {}", path.display(), reason, e),
+        )
+    };
+    if path.exists() && !path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("the LFS content path {} is not a directory", path.display()),
+        ));
+    }
+    fs::create_dir_all(path).map_err(|e| error(e, "cannot be created"))?;
+    let probe = path.join(format!(".write-test-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| error(e, "is not writable"))?;
+    fs::remove_file(&probe).map_err(|e| error(e, "is not writable"))
+}
+
+/// The options of a server which decide the storage of it.
+pub trait StorageOptions {
+    fn data_source(&self) -> &DataSource;
+
+    /// Build the storage of the LFS objects content.
+    fn lfs_storage(&self) -> Arc<dyn LfsStorage>;
+}
+
+impl StorageOptions for HttpOptions {
+    fn data_source(&self) -> &DataSource {
+        &self.data_source
+    }
+
+    fn lfs_storage(&self) -> Arc<dyn LfsStorage> {
+        match self.lfs_storage {
+            LfsStorageType::Local => Arc::new(ContentStore::new(self.lfs_content_path.clone())),
+            LfsStorageType::S3 => Arc::new(S3Storage::new(self.s3.clone().into())),
+        }
+    }
+}
+
+impl StorageOptions for WebhookOptions {
+    fn data_source(&self) -> &DataSource {
+        &self.data_source
+    }
+
+    fn lfs_storage(&self) -> Arc<dyn LfsStorage> {
+        Arc::new(ContentStore::new(self.lfs_content_path.clone()))
+    }
+}
+
+/// Select the storage by the options. It is not connected until replaced by the one
+/// from `database::init`, see [`LfsConfig::with_storage`].
+pub fn storage_from_options<O: StorageOptions>(opts: &O) -> Arc<dyn ObjectStorage> {
+    database::disconnected_storage(opts.data_source())
+}
+
+/// Select the storage of the LFS objects content by the options.
+pub fn lfs_storage_from_options<O: StorageOptions>(opts: &O) -> Arc<dyn LfsStorage> {
+    opts.lfs_storage()
+}
 
 impl From<HttpOptions> for LfsConfig {
     fn from(value: HttpOptions) -> Self {
         Self {
+            storage: storage_from_options(&value),
+            lfs_storage: lfs_storage_from_options(&value),
+            partial_uploads: value.partial_uploads(),
+            verify_download: value.lfs_verify_download,
+            max_object_size: value.max_lfs_object_size,
             host: value.host,
             port: value.port,
-            lfs_content_path: value.lfs_content_path,
-            storage: Arc::new(MysqlStorage::default()),
         }
     }
 }
@@ -31,13 +229,223 @@ impl From<HttpOptions> for LfsConfig {
 impl From<WebhookOptions> for LfsConfig {
     fn from(value: WebhookOptions) -> Self {
         Self {
+            storage: storage_from_options(&value),
+            lfs_storage: lfs_storage_from_options(&value),
+            partial_uploads: PartialUploads::new(
+                value.lfs_content_path.join("partial"),
+                partial::DEFAULT_TTL,
+            ),
+            verify_download: false,
+            max_object_size: None,
             host: value.host,
             port: value.port,
-            lfs_content_path: value.lfs_content_path,
-            storage: Arc::new(MysqlStorage::default()),
         }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use clap::Parser;
+    use common::errors::MegaError;
+    use database::driver::lfs::storage::MetaObject;
+    use database::driver::{ObjectStorage, RefUpdate};
+    use entity::{commit, git_obj, refs, repo_config, symrefs};
+    use git::lfs::LfsConfig;
+    use sea_orm::DatabaseConnection;
+
+    use crate::https::{HttpOptions, LfsStorageType};
+    use crate::{bind, lfs_storage_from_options, prepare_lfs_content_path};
+
+    #[derive(Default)]
+    pub(crate) struct MockStorage {
+        connection: DatabaseConnection,
+        saved: AtomicUsize,
+        objects: Mutex<HashMap<String, git_obj::Model>>,
+        refs: Mutex<Vec<refs::Model>>,
+    }
+
+    impl MockStorage {
+        /// The number of the objects saved by `save_obj_data`.
+        pub(crate) fn saved(&self) -> usize {
+            self.saved.load(Ordering::Relaxed)
+        }
+
+        pub(crate) fn add_ref(&self, repo_path: &str, ref_name: &str, ref_git_id: &str) {
+            let now = chrono::Utc::now().naive_utc();
+            let mut refs = self.refs.lock().unwrap();
+            let id = refs.len() as i32 + 1;
+            refs.push(refs::Model {
+                id,
+                repo_path: repo_path.to_owned(),
+                ref_name: ref_name.to_owned(),
+                ref_git_id: ref_git_id.to_owned(),
+                created_at: now,
+                updated_at: now,
+            });
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStorage for MockStorage {
+        fn get_connection(&self) -> &DatabaseConnection {
+            &self.connection
+        }
+
+        async fn ping(&self) -> Result<(), MegaError> {
+            Ok(())
+        }
+
+        async fn save_obj_data(&self, objects: Vec<git_obj::ActiveModel>) -> Result<bool, MegaError> {
+            self.saved.fetch_add(objects.len(), Ordering::Relaxed);
+            let mut saved = self.objects.lock().unwrap();
+            for object in objects {
+                let model = git_obj::Model {
+                    id: object.id.unwrap(),
+                    git_id: object.git_id.unwrap(),
+                    object_type: object.object_type.unwrap(),
+                    data: object.data.unwrap(),
+                    compression: None,
+                };
+                saved.insert(model.git_id.clone(), model);
+            }
+            Ok(true)
+        }
+
+        async fn get_obj_data_by_id(&self, id: &str) -> Result<Option<git_obj::Model>, MegaError> {
+            Ok(self.objects.lock().unwrap().get(id).cloned())
+        }
+
+        async fn get_repo_config(
+            &self,
+            _repo_path: &str,
+        ) -> Result<Option<repo_config::Model>, MegaError> {
+            Ok(None)
+        }
+
+        async fn get_symref(
+            &self,
+            _repo_path: &str,
+            _ref_name: &str,
+        ) -> Result<Option<symrefs::Model>, MegaError> {
+            Ok(None)
+        }
+
+        async fn get_existing_obj_ids(
+            &self,
+            git_ids: Vec<String>,
+        ) -> Result<HashSet<String>, MegaError> {
+            let objects = self.objects.lock().unwrap();
+            Ok(git_ids
+                .into_iter()
+                .filter(|id| objects.contains_key(id))
+                .collect())
+        }
+
+        async fn search_refs(&self, path: &str) -> Result<Vec<refs::Model>, MegaError> {
+            // the refs of the repos of which the path is a prefix, like the postgres storage
+            let refs = self.refs.lock().unwrap();
+            Ok(refs
+                .iter()
+                .filter(|r| path.starts_with(&r.repo_path))
+                .cloned()
+                .collect())
+        }
+
+        async fn search_commits(&self, _: &str) -> Result<Vec<commit::Model>, MegaError> {
+            Ok(vec![])
+        }
+
+        async fn apply_ref_updates(
+            &self,
+            repo_path: &str,
+            updates: &[RefUpdate],
+            _atomic: bool,
+        ) -> Result<Vec<bool>, MegaError> {
+            // only checked, the refs of the tests are added by `add_ref`
+            let refs = self.refs.lock().unwrap();
+            Ok(updates
+                .iter()
+                .map(|update| {
+                    let current = refs
+                        .iter()
+                        .find(|r| r.repo_path == repo_path && r.ref_name == update.ref_name);
+                    update.is_current(current.map(|r| r.ref_git_id.as_str()))
+                })
+                .collect())
+        }
+    }
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        http: HttpOptions,
+    }
+
+    #[test]
+    fn test_lfs_config_with_storage() {
+        let options = Cli::parse_from(["mega", "--port", "8001"]).http;
+        let storage: Arc<dyn ObjectStorage> = Arc::new(MockStorage::default());
+        let config = LfsConfig::from(options).with_storage(storage.clone());
+        assert_eq!(config.port, 8001);
+        assert!(Arc::ptr_eq(&config.storage, &storage));
+    }
+
+    #[test]
+    fn test_lfs_storage_from_options() {
+        let meta = MetaObject {
+            oid: "6ae8a75555209fd6c44157c0aed8016e763ff435a19cf186f76863140143ff72".to_owned(),
+            size: 12,
+            exist: true,
+        };
+        let local = Cli::parse_from(["mega", "--lfs-content-path", "/tmp/mega_lfs"]).http;
+        assert_eq!(local.lfs_storage, LfsStorageType::Local);
+        assert!(lfs_storage_from_options(&local).download_url(&meta).is_none());
+
+        let s3 = Cli::parse_from([
+            "mega",
+            "--lfs-storage",
+            "s3",
+            "--s3-endpoint",
+            "http://127.0.0.1:9000",
+            "--s3-bucket",
+            "lfs",
+            "--s3-presign",
+        ])
+        .http;
+        let url = lfs_storage_from_options(&s3).download_url(&meta).unwrap();
+        assert!(url.starts_with("http://127.0.0.1:9000/lfs/6a/e8/a755"));
+    }
+
+    #[test]
+    fn test_prepare_lfs_content_path() {
+        let root = std::env::temp_dir().join(format!("mega_lfs_{}", std::process::id()));
+        let path = root.join("nested/lfs");
+        prepare_lfs_content_path(&path).unwrap();
+        assert!(path.is_dir());
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+        // and it is fine as it exists
+        prepare_lfs_content_path(&path).unwrap();
+
+        let file = root.join("file");
+        std::fs::write(&file, b"not a directory").unwrap();
+        let error = prepare_lfs_content_path(&file).unwrap_err();
+        assert!(error.to_string().contains("is not a directory"));
+        assert!(prepare_lfs_content_path(&file.join("lfs")).is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_ipv6() {
+        let listener = bind("::1", 0).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(addr.is_ipv6());
+        let error = bind("[::1]", addr.port()).await.unwrap_err();
+        assert!(error.to_string().contains("in use"), "{}", error);
+        assert!(bind("[::1]", 0).await.is_ok());
+    }
+}
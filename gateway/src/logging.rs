@@ -0,0 +1,161 @@
+//! The logs of the servers, as the text for the terminal or as the JSON lines for the log
+//! aggregation, e.g. `mega --log-format json --log-level gateway=debug,info https`.
+//!
+//! Each HTTP request is in a `request` span of the method, the path, the client IP, the status
+//! and the latency, so the events of the handlers can be told apart by the request they belong to.
+
+use std::time::Instant;
+
+use axum::{body::Body, http::Request, middleware::Next, response::Response};
+use clap::ValueEnum;
+use common::errors::MegaError;
+use tracing::{field, Instrument};
+use tracing_subscriber::EnvFilter;
+
+use crate::client_ip::ClientIp;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The human readable lines
+    #[default]
+    Text,
+    /// A JSON object a line, with the fields of the events and the spans
+    Json,
+}
+
+/// Install the global subscriber of the `filter`, which is a level like `info`, or the
+/// directives of `RUST_LOG` like `gateway=debug,info`.
+pub fn init(filter: &str, format: LogFormat) -> Result<(), MegaError> {
+    let env_filter = EnvFilter::try_new(filter)
+        .map_err(|e| MegaError::new(anyhow::anyhow!("invalid log level {}: {}", filter, e), 2))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(env_filter);
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_span_list(false).try_init(),
+    };
+    result.map_err(|e| MegaError::new(anyhow::anyhow!("{}", e), 1))
+}
+
+/// The middleware runs a request in a `request` span, and logs the status and the latency of
+/// it once the response is ready. The query is not logged, which may carry a token.
+pub async fn trace_layer(req: Request<Body>, next: Next<Body>) -> Response {
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+        client_ip = field::Empty,
+        status = field::Empty,
+        latency_ms = field::Empty,
+    );
+    if let Some(ClientIp(ip)) = req.extensions().get::<ClientIp>() {
+        span.record("client_ip", field::display(ip));
+    }
+    let start = Instant::now();
+    let resp = next.run(req).instrument(span.clone()).await;
+
+    span.record("status", resp.status().as_u16());
+    span.record("latency_ms", start.elapsed().as_secs_f64() * 1000.0);
+    span.in_scope(|| tracing::info!("Finished the request"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    use super::{init, trace_layer, LogFormat};
+    use crate::client_ip::ClientIp;
+
+    #[derive(Debug, Default, Clone)]
+    struct Fields(BTreeMap<String, String>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_owned(), format!("{:?}", value));
+        }
+    }
+
+    /// Keeps the fields of the closed spans.
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<(&'static str, Fields)>>>);
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Spans {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            ctx.span(id).unwrap().extensions_mut().insert(fields);
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let mut extensions = span.extensions_mut();
+            values.record(extensions.get_mut::<Fields>().unwrap());
+        }
+
+        fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+            let span = ctx.span(&id).unwrap();
+            let fields = span.extensions_mut().remove::<Fields>().unwrap();
+            self.0.lock().unwrap().push((span.name(), fields));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_span() {
+        let spans = Spans::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+
+        let app = Router::new()
+            .route("/hello", get(|| async { "hello" }))
+            .layer(middleware::from_fn(trace_layer));
+        let mut req = Request::get("/hello?token=secret")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ClientIp("2001:db8::1".parse().unwrap()));
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let req = Request::post("/missing").body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let spans = spans.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        let (name, fields) = &spans[0];
+        assert_eq!(*name, "request");
+        assert_eq!(fields.0["method"], "GET");
+        assert_eq!(fields.0["path"], "/hello");
+        assert_eq!(fields.0["client_ip"], "2001:db8::1");
+        assert_eq!(fields.0["status"], "200");
+        assert!(fields.0["latency_ms"].parse::<f64>().unwrap() >= 0.0);
+        let (_, fields) = &spans[1];
+        assert!(!fields.0.contains_key("client_ip"));
+        assert_eq!(fields.0["method"], "POST");
+        assert_eq!(fields.0["status"], "404");
+    }
+
+    #[test]
+    fn test_init_bad_level() {
+        let err = init("gateway=loud", LogFormat::Json).unwrap_err();
+        assert_eq!(err.code, 2);
+    }
+}
@@ -0,0 +1,194 @@
+//! The `GET /metrics` of the Prometheus, with the request counters and latencies of the HTTP
+//! servers, and the metrics of the git services, see `git::metrics`.
+
+use std::net::TcpListener;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Router, Server};
+use hyper::{Body, Request};
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    server: String,
+    route: String,
+    method: String,
+    status: u16,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RouteLabels {
+    server: String,
+    route: String,
+}
+
+struct HttpMetrics {
+    requests: Family<RequestLabels, Counter>,
+    duration: Family<RouteLabels, Histogram>,
+}
+
+fn http_metrics() -> &'static HttpMetrics {
+    static METRICS: OnceLock<HttpMetrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let metrics = HttpMetrics {
+            requests: Family::default(),
+            // from 1ms to about 30s
+            duration: Family::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(0.001, 2.0, 16))
+            }),
+        };
+        let mut registry = common::metrics::registry();
+        registry.register(
+            "http_requests",
+            "The HTTP requests handled",
+            metrics.requests.clone(),
+        );
+        registry.register(
+            "http_request_duration_seconds",
+            "The duration of handling the HTTP requests",
+            metrics.duration.clone(),
+        );
+        metrics
+    })
+}
+
+/// The route of a path, the repo paths are not used as labels to keep the cardinality low.
+fn route_name(path: &str) -> &'static str {
+    if path.ends_with("/info/refs") {
+        "info_refs"
+    } else if path.ends_with("/git-upload-pack") {
+        "upload_pack"
+    } else if path.ends_with("/git-receive-pack") {
+        "receive_pack"
+    } else if path.ends_with("/objects/batch") {
+        "lfs_batch"
+    } else if path.ends_with("/objects/verify") {
+        "lfs_verify"
+    } else if path.contains("/objects/") {
+        "lfs_object"
+    } else if path.contains("/locks") {
+        "lfs_locks"
+    } else if path.starts_with("/api/") {
+        "api"
+    } else {
+        match path {
+            "/healthz" => "healthz",
+            "/readyz" => "readyz",
+            "/metrics" => "metrics",
+            _ => "other",
+        }
+    }
+}
+
+/// The middleware counts the requests of the `server`, e.g. `http` or `webhook`.
+pub async fn metrics_layer(
+    State(server): State<&'static str>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let route = route_name(req.uri().path());
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let resp = next.run(req).await;
+
+    let metrics = http_metrics();
+    metrics
+        .requests
+        .get_or_create(&RequestLabels {
+            server: server.to_owned(),
+            route: route.to_owned(),
+            method,
+            status: resp.status().as_u16(),
+        })
+        .inc();
+    metrics
+        .duration
+        .get_or_create(&RouteLabels {
+            server: server.to_owned(),
+            route: route.to_owned(),
+        })
+        .observe(start.elapsed().as_secs_f64());
+    resp
+}
+
+pub async fn metrics_handler() -> impl IntoResponse {
+    // register the metrics before any of them are used
+    http_metrics();
+    git::metrics::metrics();
+    (
+        [(
+            CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        common::metrics::encode_text(),
+    )
+}
+
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(metrics_handler))
+}
+
+/// Serve the `/metrics` alone on the admin port, which is bound by the server so the port in use
+/// is an error of it.
+pub async fn serve(listener: TcpListener) -> Result<(), hyper::Error> {
+    if let Ok(addr) = listener.local_addr() {
+        tracing::info!("Serving the metrics on {}", addr);
+    }
+    Server::from_tcp(listener)?
+        .serve(router().into_make_service())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::route_name;
+    use crate::https::{app, tests::state};
+
+    #[test]
+    fn test_route_name() {
+        assert_eq!(route_name("/a/b.git/info/refs"), "info_refs");
+        assert_eq!(route_name("/a/b.git/git-upload-pack"), "upload_pack");
+        assert_eq!(route_name("/a/b.git/info/lfs/objects/batch"), "lfs_batch");
+        assert_eq!(route_name("/a/b.git/info/lfs/objects/6ae8a755"), "lfs_object");
+        assert_eq!(route_name("/a/b.git/info/lfs/locks/verify"), "lfs_locks");
+        assert_eq!(route_name("/a/b.git"), "other");
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let app = app(state());
+        let req = Request::get("/healthz").body(Body::empty()).unwrap();
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+
+        let line = text
+            .lines()
+            .find(|line| {
+                line.starts_with("mega_http_requests_total{")
+                    && line.contains("route=\"healthz\"")
+            })
+            .unwrap();
+        assert!(line.contains("server=\"http\""));
+        assert!(line.contains("status=\"200\""));
+        let count: u64 = line.rsplit(' ').next().unwrap().parse().unwrap();
+        assert!(count >= 1);
+        assert!(text.contains("# TYPE mega_pack_decode_seconds histogram"));
+    }
+}
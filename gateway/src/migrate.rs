@@ -0,0 +1,50 @@
+//! Apply the migrations of the schema by `mega db migrate`, and warn of the pending ones when a
+//! server starts, see [`database::migration`].
+
+use std::sync::Arc;
+
+use clap::Args;
+use common::errors::MegaError;
+use database::driver::ObjectStorage;
+use database::migration::{self, Migration};
+use database::DataSource;
+
+#[derive(Args, Clone, Debug)]
+pub struct MigrateOptions {
+    /// The database to migrate, e.g. `postgres://postgres@localhost/mega`, the one of the
+    /// `data_source` if not set
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    #[arg(short, long, value_enum, default_value = "postgres")]
+    pub data_source: DataSource,
+}
+
+/// Connect the database of the options, and apply the pending migrations, returns the applied
+/// ones.
+pub async fn migrate(options: &MigrateOptions) -> Result<Vec<&'static Migration>, MegaError> {
+    let storage =
+        database::storage_from_options(options.database_url.as_deref(), &options.data_source)
+            .await?;
+    migration::migrate(storage.get_connection()).await
+}
+
+/// Warn if the schema of the storage is not migrated, which is not applied by the server itself,
+/// so an upgrade of the schema is never a surprise of a restart.
+pub async fn warn_pending_migrations(storage: &Arc<dyn ObjectStorage>) {
+    match migration::pending_migrations(storage.get_connection()).await {
+        Ok(pending) if !pending.is_empty() => {
+            let versions: Vec<String> = pending
+                .iter()
+                .map(|migration| format!("{} {}", migration.version, migration.name))
+                .collect();
+            tracing::warn!(
+                "{} migrations of the database are pending ({}), run `mega db migrate` to apply them",
+                pending.len(),
+                versions.join(", ")
+            );
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to check the migrations of the database: {}", e),
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+/// The author, the committer or the tagger of an object.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Person {
+    pub name: String,
+    pub email: String,
+    /// The seconds since the epoch
+    pub timestamp: usize,
+    /// The offset like `+0800`
+    pub timezone: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub name: String,
+    /// The mode like `100644`, `100755`, `120000` of a symlink, `040000` or `160000` of a
+    /// submodule
+    pub mode: String,
+    /// `blob`, `tree` or `commit` of a submodule
+    #[serde(rename = "type")]
+    pub object_type: String,
+    /// `file`, `executable`, `symlink`, `directory` or `submodule` by the mode
+    pub kind: String,
+    pub oid: String,
+    /// The size of a blob, none of the others
+    pub size: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TreeDetail {
+    pub oid: String,
+    pub entries: Vec<TreeEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommitDetail {
+    pub oid: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Person,
+    pub committer: Person,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TagDetail {
+    pub oid: String,
+    /// The object tagged, usually a commit
+    pub object: String,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub tag: String,
+    pub tagger: Person,
+    pub message: String,
+}
+
+/// A page of the history, of which the `next_cursor` is the one of the next page if there are
+/// more.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommitList {
+    pub commits: Vec<CommitDetail>,
+    pub next_cursor: Option<String>,
+}
+
+/// The lines changed of a file like the ones of a unified diff.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Hunk {
+    /// The line like `@@ -1,3 +1,4 @@`
+    pub header: String,
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    /// The lines of the context, the deleted and the added ones after ` `, `-` and `+`, and the
+    /// `\ No newline at end of file` after a last line without one
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileDiff {
+    pub path: String,
+    /// The path before a rename
+    pub old_path: Option<String>,
+    /// `added`, `modified`, `deleted` or `renamed`
+    pub status: String,
+    pub old_oid: Option<String>,
+    pub new_oid: Option<String>,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    /// The file is binary, of which there is no hunk
+    pub binary: bool,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiffDetail {
+    /// The commit compared with, none of the empty tree before a root commit
+    pub base: Option<String>,
+    pub head: String,
+    pub files: Vec<FileDiff>,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlameLine {
+    /// The number of the line from 1
+    pub line: usize,
+    /// The commit which changes the line last
+    pub commit: String,
+    pub author: Person,
+    /// The number of the line in the file of the `commit`
+    pub original_line: usize,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlameDetail {
+    /// The commit of the ref blamed
+    pub commit: String,
+    pub path: String,
+    pub lines: Vec<BlameLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// The number of the line from 1
+    pub line: usize,
+    /// The line matched, of which a long one is cut
+    pub content: String,
+}
+
+/// A file of which the lines match a search, one line of JSON of the answer each.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SearchFile {
+    pub path: String,
+    pub oid: String,
+    pub matches: Vec<SearchMatch>,
+}
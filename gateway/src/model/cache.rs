@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use git::internal::object_cache::SharedObjectCache;
+
+/// The objects of the latest commits of a repo loaded by the warm-up by default.
+pub const DEFAULT_WARM_COMMITS: usize = 10;
+pub const MAX_WARM_COMMITS: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CacheStatus {
+    /// The bytes of the objects in the cache at most
+    pub capacity: usize,
+    pub objects: usize,
+    pub bytes: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+impl From<&SharedObjectCache> for CacheStatus {
+    fn from(cache: &SharedObjectCache) -> Self {
+        let (objects, bytes) = cache.size();
+        let stats = cache.stats();
+        CacheStatus {
+            capacity: cache.capacity(),
+            objects,
+            bytes,
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WarmCache {
+    pub repo: String,
+    /// The latest commits of the refs of which the objects are loaded, 10 by default
+    pub commits: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CacheWarmed {
+    pub repo: String,
+    /// The objects loaded into the cache, which were not in it
+    pub loaded: usize,
+    pub cache: CacheStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CacheFlushed {
+    /// The objects removed from the cache
+    pub flushed: usize,
+    pub cache: CacheStatus,
+}
@@ -1,2 +1,6 @@
+pub mod browse;
+pub mod cache;
 pub mod object_detail;
-pub mod query;
\ No newline at end of file
+pub mod query;
+pub mod repo;
+pub mod webhook;
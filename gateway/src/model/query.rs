@@ -11,3 +11,95 @@ pub struct DirectoryQuery {
 fn default_path() -> String {
     "/root".to_string()
 }
+
+/// The object of the `format`, `json` of a tree, a commit or a tag parsed, the raw data if not
+/// set.
+#[derive(Debug, Default, Deserialize)]
+pub struct ObjectQuery {
+    pub format: Option<String>,
+}
+
+/// The page of a listing, of the names after the `cursor`, which is the last one of the page
+/// before.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+impl PageQuery {
+    pub const DEFAULT_LIMIT: usize = 100;
+    pub const MAX_LIMIT: usize = 1000;
+
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+
+    /// The page of the sorted names, and the cursor of the next page if there are more.
+    pub fn page<T, F: Fn(&T) -> &str>(&self, sorted: Vec<T>, name: F) -> (Vec<T>, Option<String>) {
+        let mut page: Vec<T> = sorted
+            .into_iter()
+            .filter(|item| {
+                self.cursor
+                    .as_deref()
+                    .is_none_or(|cursor| name(item) > cursor)
+            })
+            .take(self.limit() + 1)
+            .collect();
+        if page.len() <= self.limit() {
+            return (page, None);
+        }
+        page.truncate(self.limit());
+        let next_cursor = page.last().map(|item| name(item).to_owned());
+        (page, next_cursor)
+    }
+}
+
+/// The page of the history of a ref, of the commits after the `cursor`, which is the last one of
+/// the page before, and of the ones changing the `path` if it's set.
+#[derive(Debug, Default, Deserialize)]
+pub struct CommitQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub path: Option<String>,
+}
+
+impl CommitQuery {
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(PageQuery::DEFAULT_LIMIT)
+            .clamp(1, PageQuery::MAX_LIMIT)
+    }
+}
+
+/// The diff from the `base` to the `head`, of which the `base` is the first parent of the `head`
+/// if not set, and the renames are detected if `renames` is true.
+#[derive(Debug, Default, Deserialize)]
+pub struct DiffQuery {
+    pub base: Option<String>,
+    pub head: String,
+    #[serde(default)]
+    pub renames: bool,
+}
+
+/// The search of the lines of the files at the `ref`, `HEAD` if not set, which contain the `q`,
+/// or match it of a regex if `regex` is true, at most the `limit` lines.
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchQuery {
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    pub q: String,
+    #[serde(default)]
+    pub regex: bool,
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(PageQuery::DEFAULT_LIMIT)
+            .clamp(1, PageQuery::MAX_LIMIT)
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Repo {
+    /// The path of the repo like `/projects/mega`
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepoList {
+    pub repos: Vec<Repo>,
+    /// The `cursor` of the next page, none of the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefList {
+    /// The full names of the refs like `refs/heads/main` to the object ids
+    pub refs: BTreeMap<String, String>,
+    /// The `cursor` of the next page, none of the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateRepo {
+    /// The path of the repo like `projects/mega`, of which a leading `/` and a trailing `.git`
+    /// are optional
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RepoHead {
+    /// The default branch which `HEAD` points to like `refs/heads/main`, of which the
+    /// `refs/heads/` is optional when it's set
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RepoDeleted {
+    pub name: String,
+    /// The number of the objects removed, which no other repo references
+    pub objects: usize,
+    /// The number of the LFS objects removed, which no other repo references
+    pub lfs_objects: usize,
+}
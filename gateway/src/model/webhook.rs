@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Webhook {
+    /// The id of the receiver by which the deliveries to it are queried
+    pub id: String,
+    pub url: String,
+}
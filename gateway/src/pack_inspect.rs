@@ -0,0 +1,163 @@
+//! Inspect a packfile without the storage, e.g. to debug a corrupted push.
+//!
+//! The objects are decoded by [`PackStream`], and the checksum at the end is verified. The
+//! bases of the deltas are looked up in the cache of it only, so the pack fails of a delta of
+//! which the base is not in the pack, e.g. of a thin pack, or is evicted from the cache.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use common::errors::MegaError;
+use git::internal::pack::stream::{DecodedObject, PackStream};
+
+#[derive(Args, Clone, Debug)]
+pub struct PackInspectOptions {
+    /// The packfile to inspect
+    #[arg(value_name = "FILE")]
+    pub file: PathBuf,
+
+    /// Keep at most this many resolved objects as the bases of the deltas, the
+    /// `MEGA_PACK_CACHE_SIZE` if not set
+    #[arg(long, value_name = "N")]
+    pub cache_size: Option<usize>,
+}
+
+/// The counts of the objects of a pack which is verified.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PackSummary {
+    pub objects: usize,
+    pub deltas: usize,
+    /// The length of the longest delta chain.
+    pub max_depth: usize,
+}
+
+impl fmt::Display for PackSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "objects: {}, deltas: {}, max depth: {}",
+            self.objects, self.deltas, self.max_depth
+        )
+    }
+}
+
+fn open(options: &PackInspectOptions) -> Result<File, MegaError> {
+    File::open(&options.file).map_err(|e| {
+        MegaError::new(
+            anyhow::anyhow!("failed to open {}: {}", options.file.display(), e),
+            1,
+        )
+    })
+}
+
+/// Decode the pack and verify the checksum of it.
+pub async fn verify_pack(options: &PackInspectOptions) -> Result<PackSummary, MegaError> {
+    inspect_pack(open(options)?, options.cache_size, |_| Ok(())).await
+}
+
+/// Decode the pack like [`verify_pack`], and write a line of each object to the output as it's
+/// decoded, like `git verify-pack -v`: the hash, the type, the size and the offset of it, and
+/// the depth and the base of a delta.
+pub async fn list_pack<W: Write>(
+    options: &PackInspectOptions,
+    out: &mut W,
+) -> Result<PackSummary, MegaError> {
+    inspect_pack(open(options)?, options.cache_size, |object| {
+        write!(
+            out,
+            "{} {} {} {}",
+            object.hash,
+            object.object_type,
+            object.data.len(),
+            object.offset
+        )?;
+        if let Some(base) = object.base {
+            write!(out, " {} {}", object.depth, base)?;
+        }
+        writeln!(out)
+    })
+    .await
+}
+
+/// Decode the pack, each object of which is passed to the function in order.
+pub async fn inspect_pack<R: Read>(
+    pack: R,
+    cache_size: Option<usize>,
+    mut each: impl FnMut(&DecodedObject) -> io::Result<()>,
+) -> Result<PackSummary, MegaError> {
+    let git_error = |e: git::errors::GitError| MegaError::new(e.into(), 1);
+    let mut stream = PackStream::new(pack).map_err(git_error)?;
+    if let Some(size) = cache_size {
+        stream = stream.with_cache_size(size);
+    }
+
+    let mut summary = PackSummary::default();
+    while let Some(object) = stream.next_object().await.map_err(git_error)? {
+        summary.objects += 1;
+        if object.base.is_some() {
+            summary.deltas += 1;
+        }
+        summary.max_depth = summary.max_depth.max(object.depth);
+        each(&object).map_err(|e| MegaError::new(e.into(), 1))?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use super::{inspect_pack, list_pack, PackInspectOptions, PackSummary};
+
+    const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+
+    #[tokio::test]
+    async fn test_list_pack() {
+        let options = PackInspectOptions {
+            file: PathBuf::from(PACK),
+            cache_size: None,
+        };
+        let mut out = Vec::new();
+        let summary = list_pack(&options, &mut out).await.unwrap();
+        assert_eq!(
+            summary,
+            PackSummary {
+                objects: 324,
+                deltas: 137,
+                max_depth: 7,
+            }
+        );
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 324);
+        // the last commit
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("d767d4967b3e14ede397c552b9d352af52a4bbd9 commit ")));
+        let delta = lines.iter().find(|line| line.split(' ').count() == 6);
+        assert!(delta.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_verify_bad_pack() {
+        let mut data = std::fs::read(PACK).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        let err = inspect_pack(Cursor::new(data), None, |_| Ok(()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, 1);
+        assert!(err.to_string().contains("checksum"), "{}", err);
+
+        // the base of a delta is evicted
+        let data = std::fs::read(PACK).unwrap();
+        assert!(inspect_pack(Cursor::new(data), Some(1), |_| Ok(()))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! The per-IP rate limiting of the git smart HTTP and LFS endpoints by token buckets.
+//!
+//! Each client has a bucket of `burst` tokens, which is refilled by `rate` tokens per second,
+//! and a request takes one token. The read and write requests are limited separately.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::http::header::RETRY_AFTER;
+use axum::middleware::Next;
+use axum::response::Response;
+use clap::Args;
+use hyper::{Body, Request, StatusCode};
+
+use crate::auth::{required_access, Access};
+use crate::client_ip::ClientIp;
+use crate::https::AppState;
+
+/// The buckets which are full are dropped when there are too many clients.
+const MAX_BUCKETS: usize = 10000;
+
+/// Parameters of the rate limiting, the requests are not limited if the rate is not set.
+#[derive(Args, Clone, Debug, Default)]
+pub struct RateLimitOptions {
+    /// The requests per second of each client to fetch or clone
+    #[arg(long, value_name = "RPS", value_parser = parse_rate)]
+    pub rate_limit_read: Option<f64>,
+
+    /// The max requests of each client to fetch or clone at once
+    #[arg(long, value_name = "N", default_value_t = 60)]
+    pub rate_limit_read_burst: u32,
+
+    /// The requests per second of each client to push
+    #[arg(long, value_name = "RPS", value_parser = parse_rate)]
+    pub rate_limit_write: Option<f64>,
+
+    /// The max requests of each client to push at once
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub rate_limit_write_burst: u32,
+}
+
+/// Parse the requests per second, which is positive as a bucket is never refilled of zero.
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("{} is not a positive number of requests", value)),
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32) -> RateLimiter {
+        RateLimiter {
+            rate,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token of the client, returns how long to wait if there is none.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// The limiters of the read and write requests.
+#[derive(Default)]
+pub struct RateLimits {
+    read: Option<RateLimiter>,
+    write: Option<RateLimiter>,
+}
+
+impl From<&RateLimitOptions> for RateLimits {
+    fn from(value: &RateLimitOptions) -> Self {
+        Self {
+            read: value
+                .rate_limit_read
+                .map(|rate| RateLimiter::new(rate, value.rate_limit_read_burst)),
+            write: value
+                .rate_limit_write
+                .map(|rate| RateLimiter::new(rate, value.rate_limit_write_burst)),
+        }
+    }
+}
+
+pub async fn rate_limit_layer(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let limits = &state.rate_limits;
+    let limiter = match required_access(&req) {
+        Access::Read => limits.read.as_ref(),
+        Access::Write | Access::Admin => limits.write.as_ref(),
+    };
+    // the client behind the trusted proxies
+    let client_ip = req.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip);
+    let (Some(limiter), Some(ip)) = (limiter, client_ip) else {
+        return next.run(req).await;
+    };
+    match limiter.check(ip, Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => {
+            tracing::info!("too many requests from {}", ip);
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(RETRY_AFTER, wait.as_secs_f64().ceil().max(1.0).to_string())
+                .body(axum::body::boxed(Body::from("Too many requests\n")))
+                .unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{parse_rate, RateLimiter};
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let ip = "127.0.0.1".parse().unwrap();
+        let other = "::1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check(ip, now).is_ok());
+        }
+        assert_eq!(limiter.check(ip, now), Err(Duration::from_millis(500)));
+        // the clients are limited separately
+        assert!(limiter.check(other, now).is_ok());
+
+        // a token is refilled in half a second
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.check(ip, later).is_ok());
+        assert!(limiter.check(ip, later).is_err());
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("0.5"), Ok(0.5));
+        for rate in ["0", "-1", "NaN", "inf", "fast"] {
+            assert!(parse_rate(rate).is_err(), "{}", rate);
+        }
+    }
+}
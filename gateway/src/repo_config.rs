@@ -0,0 +1,191 @@
+//! The settings of each repository, which override the options of the HTTP server for the
+//! requests of the repo, e.g. a larger LFS limit of a repo of the media files.
+//!
+//! They are saved in the storage as JSON by the repo path, e.g. `/projects/demo`, and read by
+//! `GET /api/v1/repo-config?repo_path=...` and replaced by the `PUT` of it, which needs the
+//! write access. A setting not set is the one of the server. The settings read are cached,
+//! and the cached ones are replaced when they are updated by the API.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use common::errors::MegaError;
+use database::driver::ObjectStorage;
+use entity::repo_config;
+use serde::{Deserialize, Serialize};
+
+use crate::webhook::filter::RefFilter;
+
+/// The cache is cleared when there are too many repos.
+const MAX_CACHED: usize = 10000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    /// The max size of an LFS object in bytes, checked by the batch API, see
+    /// `--max-lfs-object-size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lfs_object_size: Option<u64>,
+
+    /// Reject the pushed blobs larger than this which are not LFS pointers, see
+    /// `--enforce-lfs-threshold`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enforce_lfs_threshold: Option<u64>,
+
+    /// The receivers of the events of the repo instead of the `--webhook-url`s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_urls: Option<Vec<String>>,
+
+    /// The refs of which the events are delivered instead of the `--webhook-ref-filter`s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_ref_filters: Option<Vec<String>>,
+}
+
+impl RepoConfig {
+    /// Check the settings before they are saved.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(filters) = &self.webhook_ref_filters {
+            RefFilter::new(filters).map_err(|e| e.to_string())?;
+        }
+        for url in self.webhook_urls.iter().flatten() {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return Err(format!("invalid webhook url `{}`", url));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The settings of the repos read from the storage.
+#[derive(Default)]
+pub struct RepoConfigs {
+    cache: Mutex<HashMap<String, Arc<RepoConfig>>>,
+}
+
+impl RepoConfigs {
+    /// The settings of the repo, the default ones if they are never saved.
+    pub async fn get(
+        &self,
+        storage: &Arc<dyn ObjectStorage>,
+        repo_path: &str,
+    ) -> Result<Arc<RepoConfig>, MegaError> {
+        if let Some(config) = self.cache.lock().unwrap().get(repo_path) {
+            return Ok(config.clone());
+        }
+        let config = match storage.get_repo_config(repo_path).await? {
+            Some(model) => serde_json::from_str(&model.config).map_err(|e| {
+                MegaError::new(
+                    anyhow::anyhow!("invalid config of the repo {}: {}", repo_path, e),
+                    1,
+                )
+            })?,
+            None => RepoConfig::default(),
+        };
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.clear();
+        }
+        // an update meanwhile is kept rather than the settings read before it
+        Ok(cache
+            .entry(repo_path.to_owned())
+            .or_insert_with(|| Arc::new(config))
+            .clone())
+    }
+
+    /// Save the settings of the repo, which replace the cached ones.
+    pub async fn update(
+        &self,
+        storage: &Arc<dyn ObjectStorage>,
+        repo_path: &str,
+        config: RepoConfig,
+    ) -> Result<(), MegaError> {
+        storage
+            .save_repo_config(repo_config::Model {
+                repo_path: repo_path.to_owned(),
+                config: serde_json::to_string(&config).unwrap(),
+                updated_at: chrono::Utc::now().naive_utc(),
+            })
+            .await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(repo_path.to_owned(), Arc::new(config));
+        Ok(())
+    }
+
+    /// Drop the cached settings of the repo, e.g. which is deleted.
+    pub fn remove(&self, repo_path: &str) {
+        self.cache.lock().unwrap().remove(repo_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+
+    use super::{RepoConfig, RepoConfigs};
+
+    #[tokio::test]
+    async fn test_repo_configs() {
+        let storage: Arc<dyn ObjectStorage> = Arc::new(MemoryStorage::new());
+        let configs = RepoConfigs::default();
+        assert_eq!(
+            *configs.get(&storage, "/projects/demo").await.unwrap(),
+            RepoConfig::default()
+        );
+
+        let config = RepoConfig {
+            max_lfs_object_size: Some(1024),
+            ..Default::default()
+        };
+        configs
+            .update(&storage, "/projects/demo", config.clone())
+            .await
+            .unwrap();
+        // the cached default is replaced
+        assert_eq!(
+            *configs.get(&storage, "/projects/demo").await.unwrap(),
+            config
+        );
+        // and it's saved
+        let saved = RepoConfigs::default();
+        assert_eq!(
+            *saved.get(&storage, "/projects/demo").await.unwrap(),
+            config
+        );
+        assert_eq!(
+            storage
+                .get_repo_config("/projects/demo")
+                .await
+                .unwrap()
+                .unwrap()
+                .config,
+            r#"{"max_lfs_object_size":1024}"#
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(RepoConfig::default().validate().is_ok());
+        let config: RepoConfig = serde_json::from_str(
+            r#"{"webhook_urls": ["https://ci.example.com/hook"], "webhook_ref_filters": ["refs/heads/*"]}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let config = RepoConfig {
+            webhook_ref_filters: Some(vec!["regex:(".to_owned()]),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = RepoConfig {
+            webhook_urls: Some(vec!["ftp://example.com".to_owned()]),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        // a typo is not ignored
+        assert!(serde_json::from_str::<RepoConfig>(r#"{"max_lfs_size": 1}"#).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Stop the servers gracefully on SIGTERM or SIGINT, so a push is not killed in the middle of
+//! writing the objects.
+//!
+//! A [`Shutdown`] is shared by the servers of a process. Once it's triggered, they stop
+//! accepting the connections, and the requests in flight are finished in the grace period
+//! before the servers return.
+
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Args;
+use tokio::sync::{mpsc, watch};
+
+#[derive(Args, Clone, Copy, Debug)]
+pub struct ShutdownOptions {
+    /// On SIGTERM or SIGINT, wait at most this long for the requests in flight, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub shutdown_grace_period: u64,
+}
+
+impl ShutdownOptions {
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_period)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+    /// A shutdown triggered by [`Shutdown::trigger`] only.
+    pub fn new() -> Shutdown {
+        let (sender, _) = watch::channel(false);
+        Shutdown {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// A shutdown triggered by SIGTERM or SIGINT too.
+    pub fn on_signal() -> io::Result<Shutdown> {
+        let shutdown = Shutdown::new();
+        #[cfg(unix)]
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        let trigger = shutdown.clone();
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            let terminate = terminate.recv();
+            #[cfg(not(unix))]
+            let terminate = std::future::pending::<Option<()>>();
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate => {}
+            }
+            tracing::info!("Shutting down, no connection is accepted");
+            trigger.trigger();
+        });
+        Ok(shutdown)
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Completes once the shutdown is triggered, e.g. for `with_graceful_shutdown` of hyper.
+    pub fn triggered(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.sender.subscribe();
+        async move {
+            while !*receiver.borrow_and_update() {
+                if receiver.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Runs the `server` until it returns, which is expected to be soon after the shutdown, or
+    /// until the `grace` period after the shutdown, of which `None` is returned.
+    pub async fn graceful<F: Future>(&self, server: F, grace: Duration) -> Option<F::Output> {
+        tokio::pin!(server);
+        tokio::select! {
+            output = &mut server => return Some(output),
+            _ = self.triggered() => {}
+        }
+        match tokio::time::timeout(grace, server).await {
+            Ok(output) => Some(output),
+            Err(_) => {
+                tracing::warn!(
+                    "The requests in flight are not finished in {:?}, exiting",
+                    grace
+                );
+                None
+            }
+        }
+    }
+}
+
+/// The connections in flight, each holds a guard until it's closed.
+pub(crate) struct Connections {
+    sender: mpsc::Sender<()>,
+    receiver: mpsc::Receiver<()>,
+}
+
+impl Connections {
+    pub(crate) fn new() -> Connections {
+        let (sender, receiver) = mpsc::channel(1);
+        Connections { sender, receiver }
+    }
+
+    pub(crate) fn guard(&self) -> mpsc::Sender<()> {
+        self.sender.clone()
+    }
+
+    /// Completes once all the guards are dropped.
+    pub(crate) async fn closed(self) {
+        let Connections {
+            sender,
+            mut receiver,
+        } = self;
+        drop(sender);
+        let _ = receiver.recv().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Connections, Shutdown};
+
+    #[tokio::test]
+    async fn test_graceful() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.is_triggered());
+
+        // the connection is finished in the grace period
+        let connections = Connections::new();
+        let guard = connections.guard();
+        let trigger = shutdown.clone();
+        tokio::spawn(async move {
+            trigger.trigger();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(guard);
+        });
+        let server = connections.closed();
+        assert!(shutdown
+            .graceful(server, Duration::from_secs(10))
+            .await
+            .is_some());
+        assert!(shutdown.is_triggered());
+        // completes at once after the shutdown
+        shutdown.triggered().await;
+
+        // or not
+        let connections = Connections::new();
+        let _guard = connections.guard();
+        assert!(shutdown
+            .graceful(connections.closed(), Duration::from_millis(50))
+            .await
+            .is_none());
+    }
+}
@@ -1,24 +1,26 @@
 //!
 //!
 //!
-use std::collections::HashMap;
 use std::env;
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-
-use anyhow::Result;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use clap::Args;
 use database::DataSource;
 use ed25519_dalek::{SigningKey, SIGNATURE_LENGTH};
 use russh_keys::key::KeyPair;
 
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use git::protocol::ssh::{AuthorizedKeys, KeyStore, SshServer};
+use tokio::net::TcpListener;
 
-use git::protocol::ssh::SshServer;
+use crate::replication::{ReplicatedHooks, ReplicationOptions, Replicator};
+use crate::shutdown::{Connections, Shutdown, ShutdownOptions};
+use crate::timeout::{IdleStream, TimeoutOptions, Timeouts};
+use crate::{HookOptions, PackOptions};
 
 #[derive(Args, Clone, Debug)]
 pub struct SshOptions {
@@ -39,22 +41,83 @@ pub struct SshOptions {
 
     #[arg(short, long, value_enum, default_value = "postgres")]
     pub data_source: DataSource,
+
+    /// The database like `mysql://root@localhost/mega` or `memory://`, of which the scheme
+    /// selects the backend rather than the `data_source`
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    /// Serve the `/metrics` on this admin port
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    /// The public keys allowed to connect in the `authorized_keys` format, of which the
+    /// comment is the user name, everyone is allowed if not set
+    #[arg(long, value_name = "FILE")]
+    pub authorized_keys: Option<PathBuf>,
+
+    /// The Ed25519 host key, generated on the first run if absent, `$SSH_ROOT/id_rsa` or
+    /// `ssh_host_ed25519_key` if not set
+    #[arg(long, value_name = "FILE")]
+    pub host_key: Option<PathBuf>,
+
+    /// Replace the host key with a new one and exit, the old one is kept as `<FILE>.old`
+    #[arg(long)]
+    pub rotate_host_key: bool,
+
+    #[clap(flatten)]
+    pub pack: PackOptions,
+
+    /// Reject the pushes of the blobs larger than this many bytes which are not LFS pointers
+    #[arg(long, value_name = "BYTES")]
+    pub enforce_lfs_threshold: Option<u64>,
+
+    #[clap(flatten)]
+    pub hooks: HookOptions,
+
+    #[clap(flatten)]
+    pub replication: ReplicationOptions,
+
+    #[clap(flatten)]
+    pub timeouts: TimeoutOptions,
+
+    #[clap(flatten)]
+    pub shutdown: ShutdownOptions,
 }
 
-/// start a ssh server
-pub async fn server(command: &SshOptions) -> Result<(), std::io::Error> {
-    // we need to persist the key to prevent key expired after server restart.
-    let client_key = load_key().await.unwrap();
-    let client_pubkey = Arc::new(client_key.clone_public_key().unwrap());
+impl SshOptions {
+    pub fn host_key_path(&self) -> PathBuf {
+        match (&self.host_key, env::var_os("SSH_ROOT")) {
+            (Some(path), _) => path.clone(),
+            (None, Some(root)) => PathBuf::from(root).join("id_rsa"),
+            (None, None) => PathBuf::from(DEFAULT_HOST_KEY),
+        }
+    }
+}
 
-    let mut config = russh::server::Config {
-        inactivity_timeout: Some(std::time::Duration::from_secs(10)),
-        auth_rejection_time: std::time::Duration::from_secs(3),
-        ..Default::default()
-    };
-    config.keys.push(client_key);
+const DEFAULT_HOST_KEY: &str = "ssh_host_ed25519_key";
 
-    let config = Arc::new(config);
+/// start a ssh server, until the `shutdown` of which the sessions in flight are finished in the
+/// grace period
+pub async fn server(command: &SshOptions, shutdown: Shutdown) -> Result<(), std::io::Error> {
+    let key_path = command.host_key_path();
+    if command.rotate_host_key {
+        let key = rotate_host_key(&key_path).await?;
+        tracing::info!(
+            "rotated the host key {}, fingerprint SHA256:{}",
+            key_path.display(),
+            fingerprint(&key)
+        );
+        return Ok(());
+    }
+    // the host key is persisted, or the clients see a changed host key after restart
+    let host_key = load_host_key(&key_path).await?;
+    tracing::info!(
+        "the host key {}, fingerprint SHA256:{}",
+        key_path.display(),
+        fingerprint(&host_key)
+    );
+    let host_pubkey = Arc::new(host_key.clone_public_key().unwrap());
 
     let SshOptions {
         host,
@@ -63,52 +126,285 @@ pub async fn server(command: &SshOptions) -> Result<(), std::io::Error> {
         cert_path: _,
         lfs_content_path: _,
         data_source,
+        database_url,
+        metrics_port,
+        authorized_keys,
+        host_key: _,
+        rotate_host_key: _,
+        pack,
+        enforce_lfs_threshold,
+        hooks,
+        replication,
+        timeouts,
+        shutdown: shutdown_options,
     } = command;
-    let sh = SshServer {
-        client_pubkey,
-        clients: Arc::new(Mutex::new(HashMap::new())),
-        id: 0,
-        storage: database::init(data_source).await,
-        pack_protocol: None,
+    let listener = crate::bind(host, *port).await?;
+    let metrics_listener = crate::bind_metrics(host, *metrics_port).await?;
+    let key_store = match authorized_keys {
+        Some(path) => Some(Arc::new(AuthorizedKeys::load(path)?) as Arc<dyn KeyStore>),
+        None => None,
     };
-    let server_url = format!("{}:{}", host, port);
-    let addr = SocketAddr::from_str(&server_url).unwrap();
-    russh::server::run(config, addr, sh).await
+    let storage = database::storage_from_options(database_url.as_deref(), data_source)
+        .await
+        .map_err(|e| io::Error::other(format!("failed to connect the database: {}", e)))?;
+    crate::migrate::warn_pending_migrations(&storage).await;
+    let mut sh = SshServer::new(host_pubkey, storage, key_store);
+    sh.window = pack.window;
+    sh.depth = pack.depth;
+    sh.keepalive = pack.keepalive();
+    sh.decode = pack.decode_options();
+    sh.enforce_lfs_threshold = *enforce_lfs_threshold;
+    // the replications share the commit-graphs of the fetches
+    let replicator = Replicator::new(
+        replication,
+        sh.storage.clone(),
+        sh.commit_graphs.clone(),
+        *pack,
+    )?;
+    sh.hooks = ReplicatedHooks::wrap(hooks.hooks(), replicator.map(Arc::new).as_ref());
+    if let Some(metrics_listener) = metrics_listener {
+        tokio::spawn(crate::metrics::serve(metrics_listener));
+    }
+    let config = Arc::new(config(host_key));
+    let server = serve(listener, config, sh, shutdown.clone(), timeouts.timeouts());
+    match shutdown
+        .graceful(server, shutdown_options.grace_period())
+        .await
+    {
+        Some(result) => result,
+        None => Ok(()),
+    }
 }
 
-/// # Loads an SSH keypair.
-///
-/// This function follows the following steps:
-/// 1. It retrieves the root directory for the SSH key from the environment variable SSH_ROOT using env::var.
-/// 2. It constructs the path to the SSH private key file by joining the root directory with the filename "id_rsa" using PathBuf.
-/// 3. It checks if the key file exists. If it doesn't, it generates a new Ed25519 keypair using KeyPair::generate_ed25519.
-/// - The generated keypair is then written to the key file.
-/// 4. If the key file exists, it reads the keypair from the file.
-/// - The keypair is loaded from the file and returned.
-///
-/// # Returns
-///
-/// An asynchronous Result containing the loaded SSH keypair if successful, or an error if any of the steps fail.
-async fn load_key() -> Result<KeyPair> {
-    let key_root = env::var("SSH_ROOT").expect("SSH_ROOT is not set in .env file");
-    let key_path = PathBuf::from(key_root).join("id_rsa");
-    if !key_path.exists() {
-        // generate a keypair if not exists
-        let keys = KeyPair::generate_ed25519().unwrap();
-        let mut key_file = tokio::fs::File::create(&key_path).await.unwrap();
-
-        let KeyPair::Ed25519(inner_pair) = keys;
-
-        key_file.write_all(&inner_pair.to_keypair_bytes()).await?;
-
-        Ok(KeyPair::Ed25519(inner_pair))
-    } else {
-        // load the keypair from the file
-        let mut file = File::open(&key_path).await?;
-        let mut secret_key_bytes: [u8; SIGNATURE_LENGTH] = [0; SIGNATURE_LENGTH];
-        file.read_exact(&mut secret_key_bytes).await?;
-        let keypair = SigningKey::from_keypair_bytes(&secret_key_bytes)?;
-        tracing::info!("{:?}", keypair);
-        Ok(KeyPair::Ed25519(keypair))
+/// Accept the sessions until the `shutdown`, and return once all of them are closed, like
+/// `russh::server::run` which never stops accepting. The handshake of the `timeouts` is done by
+/// the authentication.
+async fn serve(
+    listener: TcpListener,
+    config: Arc<russh::server::Config>,
+    mut sh: SshServer,
+    shutdown: Shutdown,
+    timeouts: Timeouts,
+) -> io::Result<()> {
+    let connections = Connections::new();
+    loop {
+        let (socket, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.triggered() => break,
+        };
+        let handler = russh::server::Server::new_client(&mut sh, Some(peer));
+        let socket = IdleStream::new(socket, timeouts, handler.authenticated.clone());
+        let config = config.clone();
+        let guard = connections.guard();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let result = match russh::server::run_stream(config, socket, handler).await {
+                Ok(session) => session.await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to serve the session of {}: {}", peer, e);
+            }
+        });
+    }
+    drop(listener);
+    connections.closed().await;
+    Ok(())
+}
+
+fn config(host_key: KeyPair) -> russh::server::Config {
+    let mut config = russh::server::Config {
+        // The idle sessions are dropped by the `IdleStream` instead, which counts the data sent
+        // as well, so a fetch is not dropped while the client only receives the pack.
+        inactivity_timeout: None,
+        auth_rejection_time: std::time::Duration::from_secs(3),
+        ..Default::default()
+    };
+    config.keys.push(host_key);
+    config
+}
+
+/// The SHA-256 fingerprint of the public key in base64, as `ssh-keygen -l` prints after the
+/// `SHA256:` prefix.
+pub fn fingerprint(key: &KeyPair) -> String {
+    key.clone_public_key().unwrap().fingerprint()
+}
+
+/// Loads the host key saved as the Ed25519 keypair bytes, or generates and saves one if the
+/// file doesn't exist.
+pub async fn load_host_key(path: &Path) -> io::Result<KeyPair> {
+    match fs::read(path).await {
+        Ok(bytes) => {
+            let bytes = <&[u8; SIGNATURE_LENGTH]>::try_from(bytes.as_slice()).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: expect {} bytes", path.display(), SIGNATURE_LENGTH),
+                )
+            })?;
+            let key = SigningKey::from_keypair_bytes(bytes).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?;
+            Ok(KeyPair::Ed25519(key))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            tracing::info!("generate the host key {}", path.display());
+            generate_host_key(path).await
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the host key with a new one, the old one is renamed to `<path>.old` so it can be
+/// restored.
+pub async fn rotate_host_key(path: &Path) -> io::Result<KeyPair> {
+    if fs::try_exists(path).await? {
+        let mut old = path.as_os_str().to_owned();
+        old.push(".old");
+        fs::rename(path, old).await?;
+    }
+    generate_host_key(path).await
+}
+
+async fn generate_host_key(path: &Path) -> io::Result<KeyPair> {
+    let KeyPair::Ed25519(key) = KeyPair::generate_ed25519().unwrap();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    // the private key is only readable by the owner, as ssh requires
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(&key.to_keypair_bytes()).await?;
+    file.sync_all().await?;
+    Ok(KeyPair::Ed25519(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use async_trait::async_trait;
+    use database::DataSource;
+    use git::protocol::ssh::SshServer;
+    use russh::{client, server};
+    use russh_keys::key::{self, KeyPair};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+
+    use super::{config, fingerprint, load_host_key, rotate_host_key, serve};
+    use crate::shutdown::Shutdown;
+    use crate::timeout::Timeouts;
+
+    struct Client(Option<oneshot::Sender<String>>);
+
+    #[async_trait]
+    impl client::Handler for Client {
+        type Error = russh::Error;
+
+        async fn check_server_key(
+            mut self,
+            key: &key::PublicKey,
+        ) -> Result<(Self, bool), Self::Error> {
+            if let Some(tx) = self.0.take() {
+                let _ = tx.send(key.fingerprint());
+            }
+            Ok((self, true))
+        }
+    }
+
+    /// Starts a server with the host key file, and returns the fingerprint presented to a client.
+    async fn presented_fingerprint(path: &std::path::Path) -> String {
+        let host_key = load_host_key(path).await.unwrap();
+        let mut sh = SshServer::new(
+            Arc::new(host_key.clone_public_key().unwrap()),
+            database::disconnected_storage(&DataSource::Postgres),
+            None,
+        );
+        let config = Arc::new(config(host_key));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let handler = server::Server::new_client(&mut sh, Some(peer));
+            let _ = server::run_stream(config, socket, handler).await;
+        });
+
+        let (tx, rx) = oneshot::channel();
+        let client_config = Arc::new(client::Config::default());
+        let handle = client::connect(client_config, addr, Client(Some(tx)))
+            .await
+            .unwrap();
+        drop(handle);
+        server.abort();
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_persist_host_key() {
+        let dir = std::env::temp_dir().join(format!("mega_ssh_host_key_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("host_key");
+
+        let first = presented_fingerprint(&path).await;
+        assert!(path.exists());
+        let second = presented_fingerprint(&path).await;
+        assert_eq!(first, second);
+
+        let rotated = rotate_host_key(&path).await.unwrap();
+        assert_ne!(fingerprint(&rotated), first);
+        assert_eq!(presented_fingerprint(&path).await, fingerprint(&rotated));
+        let old = load_host_key(&dir.join("host_key.old")).await.unwrap();
+        assert_eq!(fingerprint(&old), first);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let host_key = KeyPair::generate_ed25519().unwrap();
+        let sh = SshServer::new(
+            Arc::new(host_key.clone_public_key().unwrap()),
+            database::disconnected_storage(&DataSource::Postgres),
+            None,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeouts = Timeouts {
+            handshake: Some(Duration::from_millis(300)),
+            idle: Some(Duration::from_millis(1000)),
+        };
+        let shutdown = Shutdown::new();
+        let config = Arc::new(config(host_key));
+        let server = tokio::spawn(serve(listener, config, sh, shutdown.clone(), timeouts));
+
+        // a client which never authenticates is dropped after the handshake timeout
+        let start = Instant::now();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        assert!(received.starts_with(b"SSH-2.0-"));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+
+        // the authenticated one is kept after it, until it's idle
+        let client_config = Arc::new(client::Config::default());
+        let mut handle = client::connect(client_config, addr, Client(None))
+            .await
+            .unwrap();
+        assert!(handle.authenticate_password("alice", "").await.unwrap());
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(!handle.is_closed());
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert!(handle.is_closed());
+
+        shutdown.trigger();
+        server.await.unwrap().unwrap();
     }
 }
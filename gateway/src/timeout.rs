@@ -0,0 +1,246 @@
+//! Drop the abandoned connections, e.g. the half-open clones, before they exhaust the file
+//! descriptors of the server.
+//!
+//! A connection has the `handshake_timeout` from when it's accepted until the handshake is done,
+//! which is the first request of HTTP or the authentication of SSH, so a client can't hold it by
+//! sending the bytes slowly. After that, it's dropped once no data is sent or received for the
+//! `idle_timeout`, so a long fetch is not dropped while the client only receives the pack.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use clap::Args;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+#[derive(Args, Clone, Copy, Debug)]
+pub struct TimeoutOptions {
+    /// Drop the connection which has sent no request, or is not authenticated by SSH, this long
+    /// after it's accepted, in seconds, 0 to disable
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub handshake_timeout: u64,
+
+    /// Drop the connection which sends and receives no data for this long after the handshake,
+    /// in seconds, 0 to disable
+    #[arg(long, value_name = "SECS", default_value_t = 300)]
+    pub idle_timeout: u64,
+}
+
+impl TimeoutOptions {
+    pub fn timeouts(&self) -> Timeouts {
+        let secs = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+        Timeouts {
+            handshake: secs(self.handshake_timeout),
+            idle: secs(self.idle_timeout),
+        }
+    }
+}
+
+/// The timeouts of the connections, none of which are dropped by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// The idle timeout applies during the handshake as well if it's none.
+    pub handshake: Option<Duration>,
+    pub idle: Option<Duration>,
+}
+
+/// The stream fails with [`io::ErrorKind::TimedOut`] once the handshake or the idle timeout is
+/// passed, by which the server of it drops the connection. The handshake is done by setting the
+/// `handshake_done` of it.
+///
+/// The timer is polled with the reads and the writes, so both of them are polled by the same
+/// task, as the servers do.
+pub struct IdleStream<S> {
+    inner: S,
+    timeouts: Timeouts,
+    handshake_done: Arc<AtomicBool>,
+    accepted: Instant,
+    last_active: Instant,
+    timer: Pin<Box<Sleep>>,
+}
+
+impl<S> IdleStream<S> {
+    pub fn new(inner: S, timeouts: Timeouts, handshake_done: Arc<AtomicBool>) -> IdleStream<S> {
+        let now = Instant::now();
+        IdleStream {
+            inner,
+            timeouts,
+            handshake_done,
+            accepted: now,
+            last_active: now,
+            timer: Box::pin(tokio::time::sleep_until(now)),
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let handshake = match self.handshake_done.load(Ordering::Relaxed) {
+            true => None,
+            false => self.timeouts.handshake.map(|t| self.accepted + t),
+        };
+        handshake.or_else(|| self.timeouts.idle.map(|t| self.last_active + t))
+    }
+
+    /// The error once the deadline is passed, before which the task is woken by the timer.
+    fn poll_timeout(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        let Some(deadline) = self.deadline() else {
+            return Poll::Pending;
+        };
+        if self.timer.deadline() != deadline {
+            self.timer.as_mut().reset(deadline);
+        }
+        ready!(self.timer.as_mut().poll(cx));
+        let phase = match self.handshake_done.load(Ordering::Relaxed) {
+            true => "idle",
+            false => "handshake",
+        };
+        Poll::Ready(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("the connection is dropped by the {} timeout", phase),
+        ))
+    }
+
+    fn written(
+        &mut self,
+        poll: Poll<io::Result<usize>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<usize>> {
+        match poll {
+            Poll::Ready(Ok(n)) => {
+                if n > 0 {
+                    self.last_active = Instant::now();
+                }
+                Poll::Ready(Ok(n))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => self.poll_timeout(cx).map(Err),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                if buf.filled().len() > filled {
+                    this.last_active = Instant::now();
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => this.poll_timeout(cx).map(Err),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.written(poll, cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        this.written(poll, cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_flush(cx) {
+            Poll::Pending => this.poll_timeout(cx).map(Err),
+            ready => ready,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{IdleStream, TimeoutOptions, Timeouts};
+
+    #[test]
+    fn test_timeouts() {
+        let options = TimeoutOptions {
+            handshake_timeout: 30,
+            idle_timeout: 0,
+        };
+        let timeouts = Timeouts {
+            handshake: Some(Duration::from_secs(30)),
+            idle: None,
+        };
+        assert_eq!(options.timeouts(), timeouts);
+    }
+
+    #[tokio::test]
+    async fn test_idle_stream() {
+        let timeouts = Timeouts {
+            handshake: Some(Duration::from_millis(200)),
+            idle: Some(Duration::from_millis(500)),
+        };
+        let (mut client, server) = tokio::io::duplex(64);
+        let done = Arc::new(AtomicBool::new(false));
+        let mut stream = IdleStream::new(server, timeouts, done.clone());
+        let mut buf = [0; 4];
+
+        // the data before the handshake is done doesn't extend it
+        let start = Instant::now();
+        client.write_all(b"ping").await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(err.to_string().contains("handshake"));
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // but each piece of the data extends the idle timeout after it
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut stream = IdleStream::new(server, timeouts, done.clone());
+        done.store(true, Ordering::Relaxed);
+        let start = Instant::now();
+        let writer = tokio::spawn(async move {
+            for _ in 0..3 {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                client.write_all(b"ping").await.unwrap();
+            }
+            client
+        });
+        for _ in 0..3 {
+            stream.read_exact(&mut buf).await.unwrap();
+        }
+        let _client = writer.await.unwrap();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert!(err.to_string().contains("idle"));
+        assert!(start.elapsed() >= Duration::from_millis(1400));
+    }
+}
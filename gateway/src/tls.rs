@@ -0,0 +1,235 @@
+//! Serve the HTTP service over TLS by rustls.
+//!
+//! The certificate is loaded at startup, and reloaded on `SIGHUP`. The new certificate is used
+//! by the new connections only, so the established ones are not dropped.
+//!
+//! On the shutdown, each connection is closed after the request in flight.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::https::serve_connection;
+use crate::shutdown::{Connections, Shutdown};
+use crate::timeout::{IdleStream, Timeouts};
+
+/// Load the certificate chain and the private key in PEM, the error tells which file is bad.
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> io::Result<ServerConfig> {
+    let invalid = |path: &Path, msg: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), msg),
+        )
+    };
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .map_err(|_| invalid(cert_path, "malformed certificate"))?;
+    if certs.is_empty() {
+        return Err(invalid(cert_path, "no certificate found"));
+    }
+
+    let key = rustls_pemfile::read_all(&mut open(key_path)?)
+        .map_err(|_| invalid(key_path, "malformed private key"))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(key_path, "no private key found"))?;
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            certs.into_iter().map(Certificate).collect(),
+            PrivateKey(key),
+        )
+        .map_err(|e| invalid(key_path, &e.to_string()))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+pub struct TlsServer {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    acceptor: RwLock<TlsAcceptor>,
+}
+
+impl TlsServer {
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> io::Result<TlsServer> {
+        let config = load_server_config(&cert_path, &key_path)?;
+        Ok(TlsServer {
+            cert_path,
+            key_path,
+            acceptor: RwLock::new(TlsAcceptor::from(Arc::new(config))),
+        })
+    }
+
+    /// Load the certificate again, the current one is kept if the new one is bad.
+    pub fn reload(&self) -> io::Result<()> {
+        let config = load_server_config(&self.cert_path, &self.key_path)?;
+        *self.acceptor.write().unwrap() = TlsAcceptor::from(Arc::new(config));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn reload_on_sighup(self: &Arc<Self>) -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let server = self.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match server.reload() {
+                    Ok(()) => tracing::info!("TLS certificate reloaded"),
+                    Err(e) => tracing::error!("Failed to reload TLS certificate: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Serve until the `shutdown`, then the connections are closed after the requests in flight
+    /// are finished, and it returns once all of them are closed. The TLS handshake is a part of
+    /// the handshake of the `timeouts`.
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+        app: Router,
+        shutdown: Shutdown,
+        timeouts: Timeouts,
+    ) -> io::Result<()> {
+        #[cfg(unix)]
+        self.reload_on_sighup()?;
+
+        let connections = Connections::new();
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = shutdown.triggered() => break,
+            };
+            let handshake_done = Arc::new(AtomicBool::new(false));
+            let stream = IdleStream::new(stream, timeouts, handshake_done.clone());
+            let acceptor = self.acceptor.read().unwrap().clone();
+            let app = app.clone();
+            let shutdown = shutdown.clone();
+            let guard = connections.guard();
+            tokio::spawn(async move {
+                let _guard = guard;
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        tracing::warn!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                };
+                let result =
+                    serve_connection(stream, app, Some(peer), handshake_done, shutdown).await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to serve connection {}: {}", peer, e);
+                }
+            });
+        }
+        drop(listener);
+        connections.closed().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use axum::routing::get;
+    use axum::Router;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::{self, Certificate, ClientConfig, RootCertStore, ServerName};
+    use tokio_rustls::TlsConnector;
+
+    use super::{load_server_config, TlsServer};
+    use crate::shutdown::Shutdown;
+    use crate::timeout::Timeouts;
+
+    /// Write a self-signed certificate of `localhost` to the temp dir.
+    fn self_signed(name: &str) -> (PathBuf, PathBuf, Vec<u8>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (cert_path, key_path, cert.serialize_der().unwrap())
+    }
+
+    #[test]
+    fn test_load_bad_files() {
+        let (cert_path, key_path, _) = self_signed("mega_tls_bad");
+        let missing = PathBuf::from("/nonexistent/cert.pem");
+        let err = load_server_config(&missing, &key_path).unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/cert.pem"));
+
+        // the key is not a certificate
+        let err = load_server_config(&key_path, &key_path).unwrap_err();
+        assert!(err.to_string().contains("no certificate found"));
+        let err = load_server_config(&cert_path, &cert_path).unwrap_err();
+        assert!(err.to_string().contains("no private key found"));
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake() {
+        let (cert_path, key_path, der) = self_signed("mega_tls_handshake");
+        let server = Arc::new(TlsServer::new(cert_path, key_path).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        tokio::spawn(server.serve(listener, app, Shutdown::new(), Timeouts::default()));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(der)).unwrap();
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(config));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let domain = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(domain, stream).await.unwrap();
+
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"));
+        assert!(resp.ends_with("hello"));
+
+        // the handshake fails with a client which doesn't trust the certificate
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let domain = ServerName::try_from("localhost").unwrap();
+        assert!(TlsConnector::from(Arc::new(config))
+            .connect(domain, stream)
+            .await
+            .is_err());
+    }
+}
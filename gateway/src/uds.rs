@@ -0,0 +1,204 @@
+//! Serve the HTTP service on a Unix domain socket, e.g. behind nginx on the same host, when the
+//! host is like `unix:/run/mega/http.sock`.
+//!
+//! The socket file is removed on the shutdown, and a stale one left by a crashed server is
+//! replaced at the startup. There is no peer address of the connections on the socket, so the
+//! rate limiting is by the `X-Forwarded-For` of the trusted proxy only.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use axum::Router;
+
+use crate::shutdown::Shutdown;
+use crate::timeout::Timeouts;
+
+/// The prefix of the host of a Unix domain socket.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// The socket file of the host like `unix:/path/to/sock`, `None` of a TCP host.
+pub fn socket_path(host: &str) -> Option<&Path> {
+    host.strip_prefix(UNIX_PREFIX).map(Path::new)
+}
+
+/// Parse the permissions of the socket file in octal, like `660` or `0660`.
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("{} is not an octal file mode like 660", mode)),
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixServer {
+    path: PathBuf,
+    listener: tokio::net::UnixListener,
+}
+
+#[cfg(unix)]
+impl UnixServer {
+    /// Bind the socket and set the permissions of it, by which the proxy is allowed to connect.
+    pub fn bind(path: &Path, mode: u32) -> io::Result<UnixServer> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        let error = |e: io::Error| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to bind {}: {}", path.display(), e),
+            )
+        };
+        // Only a socket is replaced, a regular file of the path is likely a mistake.
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(error(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the file exists and is not a socket",
+                )));
+            }
+            std::fs::remove_file(path).map_err(error)?;
+        }
+        let listener = tokio::net::UnixListener::bind(path).map_err(error)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).map_err(error)?;
+        Ok(UnixServer {
+            path: path.to_owned(),
+            listener,
+        })
+    }
+
+    /// Serve until the `shutdown` like the [`crate::tls::TlsServer`], then remove the socket.
+    pub async fn serve(
+        self,
+        app: Router,
+        shutdown: Shutdown,
+        timeouts: Timeouts,
+    ) -> io::Result<()> {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        use crate::https::serve_connection;
+        use crate::shutdown::Connections;
+        use crate::timeout::IdleStream;
+
+        let connections = Connections::new();
+        let result = loop {
+            let stream = tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => break Err(e),
+                },
+                _ = shutdown.triggered() => break Ok(()),
+            };
+            let handshake_done = Arc::new(AtomicBool::new(false));
+            let stream = IdleStream::new(stream, timeouts, handshake_done.clone());
+            let app = app.clone();
+            let shutdown = shutdown.clone();
+            let guard = connections.guard();
+            tokio::spawn(async move {
+                let _guard = guard;
+                let result = serve_connection(stream, app, None, handshake_done, shutdown).await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to serve connection on the socket: {}", e);
+                }
+            });
+        };
+        let UnixServer { path, listener } = self;
+        drop(listener);
+        if let Err(e) = std::fs::remove_file(&path) {
+            tracing::warn!("Failed to remove the socket {}: {}", path.display(), e);
+        }
+        connections.closed().await;
+        result
+    }
+}
+
+#[cfg(not(unix))]
+pub struct UnixServer {
+    _path: PathBuf,
+}
+
+#[cfg(not(unix))]
+impl UnixServer {
+    pub fn bind(path: &Path, _mode: u32) -> io::Result<UnixServer> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "failed to bind {}: the Unix domain sockets are not supported on this platform",
+                path.display()
+            ),
+        ))
+    }
+
+    pub async fn serve(
+        self,
+        _app: Router,
+        _shutdown: Shutdown,
+        _timeouts: Timeouts,
+    ) -> io::Result<()> {
+        unreachable!("the socket is never bound")
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::path::Path;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    use super::{parse_mode, socket_path, UnixServer};
+    use crate::https::{app, tests::state};
+    use crate::shutdown::Shutdown;
+    use crate::timeout::Timeouts;
+
+    #[test]
+    fn test_parse_host() {
+        assert_eq!(
+            socket_path("unix:/run/mega/http.sock"),
+            Some(Path::new("/run/mega/http.sock"))
+        );
+        assert_eq!(socket_path("127.0.0.1"), None);
+        assert_eq!(parse_mode("660"), Ok(0o660));
+        assert_eq!(parse_mode("0600"), Ok(0o600));
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("7777").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serve_on_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join("mega_uds_test.sock");
+        // a stale socket of a crashed server
+        drop(std::os::unix::net::UnixListener::bind(&path));
+        let server = UnixServer::bind(&path, 0o600).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let shutdown = Shutdown::default();
+        let server = server.serve(app(state()), shutdown.clone(), Timeouts::default());
+        let handle = tokio::spawn(server);
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: mega\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        assert!(resp.contains(r#""status":"ok""#), "{}", resp);
+
+        shutdown.trigger();
+        handle.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_bind_regular_file() {
+        let path = std::env::temp_dir().join("mega_uds_file.sock");
+        std::fs::write(&path, b"").unwrap();
+        let err = UnixServer::bind(&path, 0o660).err().unwrap();
+        assert!(err.to_string().contains("not a socket"), "{}", err);
+        assert!(path.exists());
+    }
+}
@@ -5,12 +5,12 @@
 //!
 
 use std::path::PathBuf;
-use std::str::FromStr;
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 
 use anyhow::Result;
 
 use axum::extract::{State};
+use axum::middleware;
 use axum::response::Response;
 use axum::routing::post;
 use axum::{Router, Server};
@@ -25,6 +25,17 @@ use sync::service;
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 
+use crate::{logging, metrics};
+use crate::shutdown::{Shutdown, ShutdownOptions};
+use delivery::{DeliveryOptions, Dispatcher};
+
+pub mod delivery;
+pub mod event;
+pub mod filter;
+pub mod log;
+
+pub use event::{CommitInfo, Event, PingEvent, PushEvent, RefDeleteEvent, TagEvent};
+
 
 /// Parameters for starting the HTTP service
 #[derive(Args, Clone, Debug)]
@@ -47,6 +58,21 @@ pub struct WebhookOptions {
 
     #[arg(short, long, value_enum, default_value = "postgres")]
     pub data_source: DataSource,
+
+    /// The database like `mysql://root@localhost/mega` or `memory://`, of which the scheme
+    /// selects the backend rather than the `data_source`
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    /// Serve the `/metrics` on this admin port
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    #[clap(flatten)]
+    pub delivery: DeliveryOptions,
+
+    #[clap(flatten)]
+    pub shutdown: ShutdownOptions,
 }
 
 #[derive(Clone)]
@@ -59,20 +85,28 @@ pub fn remove_git_suffix(uri: Uri, git_suffix: &str) -> PathBuf {
     PathBuf::from(uri.path().replace(".git", "").replace(git_suffix, ""))
 }
 
-pub async fn webhook_server(options: &WebhookOptions) -> Result<(), Box<dyn std::error::Error>> {
+/// Serve until the `shutdown`, of which the requests in flight are finished in the grace period.
+pub async fn webhook_server(
+    options: &WebhookOptions,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Read environment variables
-    let github_app_id = env::var("GITHUB_APP_ID").expect("Missing GITHUB_APP_ID");
-    let github_private_key = env::var("GITHUB_PRIVATE_KEY").expect("Missing GITHUB_PRIVATE_KEY");
-    let _webhook_secret = env::var("GITHUB_WEBHOOK_SECRET").expect("Missing GITHUB_WEBHOOK_SECRET");
+    let var = |name: &str| env::var(name).map_err(|_| format!("Missing {}", name));
+    let github_app_id = var("GITHUB_APP_ID")?;
+    let github_private_key = var("GITHUB_PRIVATE_KEY")?;
+    let _webhook_secret = var("GITHUB_WEBHOOK_SECRET")?;
 
     // Create RSA private key from the provided environment variable
     let rsa_key = EncodingKey::from_rsa_pem(github_private_key.as_bytes())
-        .expect("Failed to load private key");
+        .map_err(|e| format!("Failed to load private key: {}", e))?;
+    let github_app_id = github_app_id
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid GITHUB_APP_ID {}: {}", github_app_id, e))?;
     // Create Octocrab instance for GitHub App authentication
     let _octocrab = Octocrab::builder()
-        .app(AppId::from(github_app_id.parse::<u64>().unwrap()), rsa_key)
+        .app(AppId::from(github_app_id), rsa_key)
         .build()
-        .expect("Failed to create Octocrab instance");
+        .map_err(|e| format!("Failed to create Octocrab instance: {}", e))?;
 
     let WebhookOptions {
         host,
@@ -81,44 +115,63 @@ pub async fn webhook_server(options: &WebhookOptions) -> Result<(), Box<dyn std:
         cert_path: _,
         lfs_content_path: _,
         data_source,
+        database_url,
+        metrics_port,
+        delivery,
+        shutdown: shutdown_options,
     } = options;
-    let server_url = format!("{}:{}", host, port);
+    let listener = crate::bind(host, *port).await?;
+    let metrics_listener = crate::bind_metrics(host, *metrics_port).await?;
+
+    if !delivery.urls.is_empty() {
+        // Deliver the events pending before the restart.
+        Arc::new(Dispatcher::new(delivery)?).resume()?;
+    }
 
     let state = AppState {
-        storage: database::init(data_source).await,
+        storage: database::storage_from_options(database_url.as_deref(), data_source)
+            .await
+            .map_err(|e| format!("Failed to connect the database: {}", e))?,
         options: options.to_owned(),
     };
     let app = Router::new()
         .route("/", post(post_method_router))
+        .layer(middleware::from_fn_with_state("webhook", metrics::metrics_layer))
+        .layer(middleware::from_fn(logging::trace_layer))
         .layer(ServiceBuilder::new().layer(CorsLayer::new().allow_origin(Any)))
         .with_state(state);
 
-    let addr = SocketAddr::from_str(&server_url).unwrap();
-    Server::bind(&addr).serve(app.into_make_service()).await?;
+    if let Some(metrics_listener) = metrics_listener {
+        tokio::spawn(metrics::serve(metrics_listener));
+    }
+    let server = Server::from_tcp(listener.into_std()?)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.triggered());
+    shutdown
+        .graceful(server, shutdown_options.grace_period())
+        .await
+        .transpose()?;
 
     Ok(())
 }
 
 async fn post_method_router(
     state: State<AppState>,
-    uri: Uri,
     req: Request<Body>,
 ) -> Result<Response<Body>, (StatusCode, String)> {
-
-    println!("{:?}", uri.path());
     // resolve the issue event
     let issue_event = service::resolve_issue_event(req).await;
     match issue_event.action().as_str(){
         "opened" => {
             state.storage.save_issue(issue_event.convert_to_model()).await.unwrap();
             let issue = state.storage.get_issue_by_id(issue_event.id()).await.unwrap().unwrap();
-            println!("{:?}", issue);
+            tracing::debug!(?issue, "Saved the issue");
         },
         "reopened" | 
         "closed" => {
             state.storage.update_issue(issue_event.convert_to_model()).await.unwrap();
             let issue_ = state.storage.get_issue_by_id(issue_event.id()).await.unwrap().unwrap();
-            println!("{:?}", issue_);
+            tracing::debug!(issue = ?issue_, "Updated the issue");
         }
         _ => {},
     }
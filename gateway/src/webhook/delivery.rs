@@ -0,0 +1,695 @@
+//! The delivery of the webhook events, e.g. the push events, to the receivers.
+//!
+//! A delivery is saved in the queue directory until it's finished, so the pending ones are
+//! delivered again after a restart by [`Dispatcher::resume`]. It's successful on 2xx, and
+//! retried on 5xx, timeouts and connection errors with the exponential backoff, but not on the
+//! other status.
+//!
+//! With a secret, each body is signed by HMAC-SHA256 in the `X-Mega-Signature-256` header in
+//! the format of GitHub, i.e. `sha256=` and the hex digest, which is checked by the receivers
+//! with [`verify_signature`].
+//!
+//! A receiver is tested by [`Dispatcher::ping`], which sends a `ping` event once in the same
+//! way, so the url and the secret are checked without a push.
+//!
+//! Each attempt, including those of the pings, is recorded in the [`DeliveryLog`] under the
+//! queue directory.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use hmac::{Hmac, Mac};
+use hyper::client::HttpConnector;
+use hyper::body::HttpBody;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::task::JoinHandle;
+
+use super::event::PingEvent;
+use super::filter::RefFilter;
+use super::log::{DeliveryAttempt, DeliveryLog, MAX_RESPONSE_SNIPPET};
+
+pub const SIGNATURE_HEADER: &str = "X-Mega-Signature-256";
+
+/// The signature of the body sent in the [`SIGNATURE_HEADER`].
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Check the [`SIGNATURE_HEADER`] of a delivery against the raw body received, in constant
+/// time.
+pub fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature
+        .strip_prefix("sha256=")
+        .and_then(|digest| hex::decode(digest).ok())
+    else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac.verify_slice(&digest).is_ok()
+}
+
+/// Parameters of delivering the events to the webhook receivers
+#[derive(Args, Clone, Debug)]
+pub struct DeliveryOptions {
+    /// The url of the receiver, can be repeated for several receivers
+    #[arg(long = "webhook-url", value_name = "URL")]
+    pub urls: Vec<String>,
+
+    /// The max attempts to deliver an event, including the first one
+    #[arg(long = "webhook-max-attempts", default_value_t = 5)]
+    pub max_attempts: u32,
+
+    /// The delay before the first retry, doubled for each of the next ones
+    #[arg(long = "webhook-base-delay-ms", default_value_t = 1000)]
+    pub base_delay_ms: u64,
+
+    #[arg(long = "webhook-max-delay-ms", default_value_t = 600_000)]
+    pub max_delay_ms: u64,
+
+    /// The timeout of each attempt
+    #[arg(long = "webhook-timeout-ms", default_value_t = 10_000)]
+    pub timeout_ms: u64,
+
+    /// The directory of the pending deliveries
+    #[arg(long = "webhook-queue-path", default_value_os_t = PathBuf::from("webhook_deliveries"))]
+    pub queue_path: PathBuf,
+
+    /// Deliver the events of the matched refs only, e.g. `refs/heads/main`, `refs/tags/v*`,
+    /// `regex:^refs/heads/release-[0-9]+$`, or `!refs/heads/tmp/**` to exclude, can be repeated
+    #[arg(long = "webhook-ref-filter", value_name = "PATTERN")]
+    pub ref_filters: Vec<String>,
+
+    /// Sign the deliveries with the secret shared with the receivers
+    #[arg(long = "webhook-secret", env = "MEGA_WEBHOOK_SECRET", hide_env_values = true)]
+    pub secret: Option<String>,
+
+    /// The days of which the delivery attempts are kept in the log
+    #[arg(long = "webhook-log-retention-days", default_value_t = 30)]
+    pub log_retention_days: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay after the `attempt` failed, which is a random one between the half and the
+    /// whole of the exponential backoff, so the retries of different events are spread.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let half = backoff / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Delivery {
+    pub id: String,
+    pub url: String,
+    pub event: String,
+    pub payload: serde_json::Value,
+    /// The attempts made, saved so the retries are limited across restarts.
+    pub attempts: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Delivered,
+    Retry,
+    Rejected,
+}
+
+/// Why a delivery has no response.
+#[derive(Debug)]
+enum SendError {
+    InvalidUrl(hyper::http::Error),
+    Failed(hyper::Error),
+    Timeout,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::InvalidUrl(e) => write!(f, "invalid url: {}", e),
+            SendError::Failed(e) => write!(f, "{}", e),
+            SendError::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+/// The result of a test delivery to a receiver by [`Dispatcher::ping`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PingResult {
+    pub url: String,
+    /// The status of the response, `None` if there is no response
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    /// Why there is no response, e.g. the connection is refused
+    pub error: Option<String>,
+}
+
+pub struct Dispatcher {
+    urls: Vec<String>,
+    policy: RetryPolicy,
+    timeout: Duration,
+    queue_path: PathBuf,
+    secret: Option<String>,
+    filter: RefFilter,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    next_id: AtomicU64,
+    log: DeliveryLog,
+    log_retention_days: u32,
+}
+
+impl Dispatcher {
+    pub fn new(options: &DeliveryOptions) -> io::Result<Dispatcher> {
+        std::fs::create_dir_all(&options.queue_path)?;
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Dispatcher {
+            urls: options.urls.clone(),
+            policy: RetryPolicy {
+                max_attempts: options.max_attempts.max(1),
+                base_delay: Duration::from_millis(options.base_delay_ms),
+                max_delay: Duration::from_millis(options.max_delay_ms),
+            },
+            timeout: Duration::from_millis(options.timeout_ms),
+            queue_path: options.queue_path.clone(),
+            secret: options.secret.clone(),
+            filter: RefFilter::new(&options.ref_filters)?,
+            client: Client::builder().build(https),
+            next_id: AtomicU64::new(0),
+            log: DeliveryLog::new(options.queue_path.join("log"))?,
+            log_retention_days: options.log_retention_days,
+        })
+    }
+
+    /// The attempts of the receiver of the `webhook_id` from the newest, at most `limit`.
+    pub fn attempts(&self, webhook_id: &str, limit: usize) -> io::Result<Vec<DeliveryAttempt>> {
+        self.log.attempts(webhook_id, limit)
+    }
+
+    /// Remove the attempts older than the `--webhook-log-retention-days` from the log.
+    pub fn prune_log(&self) -> io::Result<usize> {
+        self.log.prune(self.log_retention_days)
+    }
+
+    /// The receivers of the events.
+    pub fn urls(&self) -> &Vec<String> {
+        &self.urls
+    }
+
+    /// Whether the events of the ref are delivered.
+    pub fn accepts(&self, ref_name: &str) -> bool {
+        self.filter.matches(ref_name)
+    }
+
+    fn new_id(&self) -> String {
+        format!(
+            "{}-{}",
+            chrono::Utc::now().timestamp_micros(),
+            self.next_id.fetch_add(1, Ordering::SeqCst)
+        )
+    }
+
+    fn delivery_path(&self, id: &str) -> PathBuf {
+        self.queue_path.join(format!("{}.json", id))
+    }
+
+    fn save(&self, delivery: &Delivery) -> io::Result<()> {
+        std::fs::write(
+            self.delivery_path(&delivery.id),
+            serde_json::to_vec(delivery)?,
+        )
+    }
+
+    fn remove(&self, delivery: &Delivery) {
+        if let Err(e) = std::fs::remove_file(self.delivery_path(&delivery.id)) {
+            tracing::warn!("Failed to remove the delivery {}: {}", delivery.id, e);
+        }
+    }
+
+    /// Send the event to all the receivers in the background, the deliveries are saved before
+    /// this returns.
+    pub fn dispatch(
+        self: &Arc<Self>,
+        event: &str,
+        payload: serde_json::Value,
+    ) -> io::Result<Vec<JoinHandle<bool>>> {
+        self.dispatch_to(&self.urls, event, payload)
+    }
+
+    /// Send the event to the `urls` instead of the receivers, e.g. the ones of a repo.
+    pub fn dispatch_to(
+        self: &Arc<Self>,
+        urls: &[String],
+        event: &str,
+        payload: serde_json::Value,
+    ) -> io::Result<Vec<JoinHandle<bool>>> {
+        let mut handles = Vec::new();
+        for url in urls {
+            let delivery = Delivery {
+                id: self.new_id(),
+                url: url.to_owned(),
+                event: event.to_owned(),
+                payload: payload.clone(),
+                attempts: 0,
+            };
+            self.save(&delivery)?;
+            handles.push(tokio::spawn(self.clone().deliver(delivery)));
+        }
+        Ok(handles)
+    }
+
+    /// Deliver the pending deliveries saved before the restart.
+    pub fn resume(self: &Arc<Self>) -> io::Result<Vec<JoinHandle<bool>>> {
+        let mut handles = Vec::new();
+        for entry in std::fs::read_dir(&self.queue_path)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match serde_json::from_slice::<Delivery>(&std::fs::read(&path)?) {
+                Ok(delivery) => handles.push(tokio::spawn(self.clone().deliver(delivery))),
+                Err(e) => tracing::warn!("Invalid delivery {}: {}", path.display(), e),
+            }
+        }
+        Ok(handles)
+    }
+
+    /// Returns whether the delivery is successful, it's removed from the queue either way
+    /// when finished.
+    async fn deliver(self: Arc<Self>, mut delivery: Delivery) -> bool {
+        loop {
+            delivery.attempts += 1;
+            if let Err(e) = self.save(&delivery) {
+                tracing::warn!("Failed to save the delivery {}: {}", delivery.id, e);
+            }
+            match self.send(&delivery).await {
+                Outcome::Delivered => {
+                    self.remove(&delivery);
+                    return true;
+                }
+                Outcome::Rejected => {
+                    self.remove(&delivery);
+                    return false;
+                }
+                Outcome::Retry if delivery.attempts >= self.policy.max_attempts => {
+                    tracing::error!(
+                        "Give up the delivery {} to {} after {} attempts",
+                        delivery.id,
+                        delivery.url,
+                        delivery.attempts
+                    );
+                    self.remove(&delivery);
+                    return false;
+                }
+                Outcome::Retry => tokio::time::sleep(self.policy.delay(delivery.attempts)).await,
+            }
+        }
+    }
+
+    /// Send the event to each of the `urls` once, which is neither queued nor retried, and
+    /// tell how the receivers respond.
+    pub async fn ping(&self, urls: &[String], payload: serde_json::Value) -> Vec<PingResult> {
+        let pings = urls.iter().map(|url| {
+            let delivery = Delivery {
+                id: self.new_id(),
+                url: url.to_owned(),
+                event: String::from(PingEvent::NAME),
+                payload: payload.clone(),
+                attempts: 1,
+            };
+            async move {
+                let start = Instant::now();
+                let result = self.attempt(&delivery).await;
+                let latency_ms = start.elapsed().as_millis() as u64;
+                let (status, error) = match result {
+                    Ok(status) => (Some(status.as_u16()), None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                PingResult {
+                    url: delivery.url,
+                    status,
+                    latency_ms,
+                    error,
+                }
+            }
+        });
+        futures::future::join_all(pings).await
+    }
+
+    async fn send(&self, delivery: &Delivery) -> Outcome {
+        match self.attempt(delivery).await {
+            Ok(status) if status.is_success() => Outcome::Delivered,
+            Ok(status) if status.is_server_error() => {
+                tracing::warn!("Delivery {} failed: {}", delivery.id, status);
+                Outcome::Retry
+            }
+            Ok(status) => {
+                tracing::warn!("Delivery {} is rejected: {}", delivery.id, status);
+                Outcome::Rejected
+            }
+            Err(SendError::InvalidUrl(e)) => {
+                tracing::error!("Invalid webhook url {}: {}", delivery.url, e);
+                Outcome::Rejected
+            }
+            Err(e) => {
+                tracing::warn!("Delivery {} failed: {}", delivery.id, e);
+                Outcome::Retry
+            }
+        }
+    }
+
+    /// Send the delivery once and record the attempt, returns the status of the response.
+    async fn attempt(&self, delivery: &Delivery) -> Result<StatusCode, SendError> {
+        let result = self.request(delivery).await;
+        let mut attempt = DeliveryAttempt::new(
+            &delivery.id,
+            &delivery.url,
+            &delivery.event,
+            delivery.attempts,
+        );
+        match &result {
+            Ok((status, response)) => {
+                attempt.status = Some(status.as_u16());
+                attempt.response = Some(response.to_owned());
+            }
+            Err(e) => attempt.error = Some(e.to_string()),
+        }
+        if let Err(e) = self.log.record(&attempt) {
+            tracing::warn!("Failed to log the delivery {}: {}", delivery.id, e);
+        }
+        result.map(|(status, _)| status)
+    }
+
+    /// Send the delivery once, returns the status and the start of the body of the response.
+    async fn request(&self, delivery: &Delivery) -> Result<(StatusCode, String), SendError> {
+        // The signature is of the exact bytes sent.
+        let body = delivery.payload.to_string();
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(&delivery.url)
+            .header("Content-Type", "application/json")
+            .header("X-Mega-Event", &delivery.event)
+            .header("X-Mega-Delivery", &delivery.id);
+        if let Some(secret) = &self.secret {
+            req = req.header(SIGNATURE_HEADER, sign(secret.as_bytes(), body.as_bytes()));
+        }
+        let req = req.body(Body::from(body)).map_err(SendError::InvalidUrl)?;
+        let exchange = async {
+            let mut resp = self.client.request(req).await?;
+            let mut snippet = Vec::new();
+            while snippet.len() < MAX_RESPONSE_SNIPPET {
+                match resp.body_mut().data().await {
+                    Some(chunk) => snippet.extend_from_slice(&chunk?),
+                    None => break,
+                }
+            }
+            snippet.truncate(MAX_RESPONSE_SNIPPET);
+            let snippet = String::from_utf8_lossy(&snippet).into_owned();
+            Ok((resp.status(), snippet))
+        };
+        match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => Err(SendError::Failed(e)),
+            Err(_) => Err(SendError::Timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::{Router, Server};
+    use hyper::StatusCode;
+    use serde_json::json;
+
+    use super::{
+        sign, verify_signature, Delivery, DeliveryOptions, Dispatcher, RetryPolicy,
+        SIGNATURE_HEADER,
+    };
+    use crate::webhook::log::webhook_id;
+    use crate::webhook::PingEvent;
+
+    /// The receiver which responds the status in order, then 200.
+    #[derive(Clone, Default)]
+    struct Receiver {
+        statuses: Arc<Mutex<Vec<StatusCode>>>,
+        attempts: Arc<AtomicUsize>,
+        delivered: Arc<Mutex<Vec<String>>>,
+        signatures: Arc<Mutex<Vec<String>>>,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: String,
+    ) -> (StatusCode, &'static str) {
+        receiver.attempts.fetch_add(1, Ordering::SeqCst);
+        if let Some(event) = headers.get("X-Mega-Event") {
+            let event = event.to_str().unwrap().to_owned();
+            receiver.events.lock().unwrap().push(event);
+        }
+        if let Some(signature) = headers.get(SIGNATURE_HEADER) {
+            let signature = signature.to_str().unwrap().to_owned();
+            receiver.signatures.lock().unwrap().push(signature);
+        }
+        let mut statuses = receiver.statuses.lock().unwrap();
+        if statuses.is_empty() {
+            receiver.delivered.lock().unwrap().push(body);
+            (StatusCode::OK, "delivered")
+        } else {
+            (statuses.remove(0), "failed")
+        }
+    }
+
+    fn start_receiver(statuses: Vec<StatusCode>) -> (SocketAddr, Receiver) {
+        let receiver = Receiver {
+            statuses: Arc::new(Mutex::new(statuses)),
+            ..Default::default()
+        };
+        let app = Router::new()
+            .route("/", post(receive))
+            .with_state(receiver.clone());
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, receiver)
+    }
+
+    fn options(addr: SocketAddr, name: &str) -> DeliveryOptions {
+        let queue_path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&queue_path);
+        DeliveryOptions {
+            urls: vec![format!("http://{}/", addr)],
+            max_attempts: 5,
+            base_delay_ms: 10,
+            max_delay_ms: 100,
+            timeout_ms: 1000,
+            queue_path,
+            ref_filters: vec![],
+            secret: None,
+            log_retention_days: 30,
+        }
+    }
+
+    fn dispatcher(addr: SocketAddr, name: &str) -> (Arc<Dispatcher>, PathBuf) {
+        let options = options(addr, name);
+        (Arc::new(Dispatcher::new(&options).unwrap()), options.queue_path)
+    }
+
+    /// The deliveries in the queue, besides the log.
+    fn pending(queue_path: &Path) -> usize {
+        std::fs::read_dir(queue_path)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().is_file())
+            .count()
+    }
+
+    #[test]
+    fn test_sign() {
+        // the example of the GitHub docs
+        let secret = b"It's a Secret to Everybody";
+        let signature = sign(secret, b"Hello, World!");
+        assert_eq!(
+            signature,
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+        assert!(verify_signature(secret, b"Hello, World!", &signature));
+        assert!(!verify_signature(secret, b"Hello, World?", &signature));
+        assert!(!verify_signature(b"wrong", b"Hello, World!", &signature));
+        assert!(!verify_signature(secret, b"Hello, World!", "sha1=757107ea"));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        for (attempt, backoff) in [(1, 100), (2, 200), (3, 400), (5, 1000), (30, 1000)] {
+            let delay = policy.delay(attempt);
+            assert!(delay >= Duration::from_millis(backoff / 2));
+            assert!(delay <= Duration::from_millis(backoff));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_server_error() {
+        let failures = vec![StatusCode::INTERNAL_SERVER_ERROR, StatusCode::BAD_GATEWAY];
+        let (addr, receiver) = start_receiver(failures);
+        let (dispatcher, queue_path) = dispatcher(addr, "mega_webhook_retry");
+
+        let handles = dispatcher.dispatch("push", json!({"ref": "main"})).unwrap();
+        for handle in handles {
+            assert!(handle.await.unwrap());
+        }
+        assert_eq!(receiver.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(*receiver.delivered.lock().unwrap(), vec![r#"{"ref":"main"}"#]);
+        assert_eq!(pending(&queue_path), 0);
+
+        // all the attempts are logged, from the newest
+        let url = format!("http://{}/", addr);
+        let attempts = dispatcher.attempts(&webhook_id(&url), 10).unwrap();
+        let outcomes: Vec<_> = attempts
+            .iter()
+            .map(|a| (a.attempt, a.status, a.response.as_deref()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (3, Some(200), Some("delivered")),
+                (2, Some(502), Some("failed")),
+                (1, Some(500), Some("failed")),
+            ]
+        );
+        let delivery_id = &attempts[0].delivery_id;
+        assert!(attempts.iter().all(|a| &a.delivery_id == delivery_id));
+        assert!(attempts.iter().all(|a| a.url == url && a.event == "push"));
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_client_error() {
+        let (addr, receiver) = start_receiver(vec![StatusCode::BAD_REQUEST]);
+        let (dispatcher, queue_path) = dispatcher(addr, "mega_webhook_rejected");
+
+        let handles = dispatcher.dispatch("push", json!({})).unwrap();
+        for handle in handles {
+            assert!(!handle.await.unwrap());
+        }
+        assert_eq!(receiver.attempts.load(Ordering::SeqCst), 1);
+        assert!(receiver.delivered.lock().unwrap().is_empty());
+        assert_eq!(pending(&queue_path), 0);
+    }
+
+    #[tokio::test]
+    async fn test_signed_delivery() {
+        let (addr, receiver) = start_receiver(vec![]);
+        let options = DeliveryOptions {
+            secret: Some("secret".to_owned()),
+            ..options(addr, "mega_webhook_signed")
+        };
+        let dispatcher = Arc::new(Dispatcher::new(&options).unwrap());
+
+        for handle in dispatcher.dispatch("push", json!({"ref": "main"})).unwrap() {
+            assert!(handle.await.unwrap());
+        }
+        let body = receiver.delivered.lock().unwrap()[0].clone();
+        let signature = receiver.signatures.lock().unwrap()[0].clone();
+        assert!(verify_signature(b"secret", body.as_bytes(), &signature));
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let (addr, receiver) = start_receiver(vec![StatusCode::BAD_GATEWAY]);
+        let options = DeliveryOptions {
+            secret: Some("secret".to_owned()),
+            ..options(addr, "mega_webhook_ping")
+        };
+        let dispatcher = Dispatcher::new(&options).unwrap();
+        let payload = serde_json::to_value(PingEvent::new(Some("/projects/mega"))).unwrap();
+
+        // neither retried nor queued
+        let results = dispatcher.ping(&options.urls, payload.clone()).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].url, options.urls[0]);
+        assert_eq!(results[0].status, Some(502));
+        assert_eq!(results[0].error, None);
+        assert_eq!(receiver.attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(pending(&options.queue_path), 0);
+
+        let results = dispatcher.ping(&options.urls, payload).await;
+        assert_eq!(results[0].status, Some(200));
+        let body = receiver.delivered.lock().unwrap()[0].clone();
+        assert_eq!(
+            serde_json::from_str::<PingEvent>(&body).unwrap(),
+            PingEvent::new(Some("/projects/mega"))
+        );
+        let signature = receiver.signatures.lock().unwrap()[1].clone();
+        assert_eq!(signature, sign(b"secret", body.as_bytes()));
+        assert_eq!(*receiver.events.lock().unwrap(), ["ping", "ping"]);
+
+        // no receiver
+        let unreachable = vec![String::from("http://127.0.0.1:1/")];
+        let results = dispatcher.ping(&unreachable, json!({})).await;
+        assert_eq!(results[0].status, None);
+        assert!(results[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_resume_pending_deliveries() {
+        let (addr, receiver) = start_receiver(vec![]);
+        let (dispatcher, queue_path) = dispatcher(addr, "mega_webhook_resume");
+        // saved before the restart
+        let delivery = Delivery {
+            id: "pending".to_owned(),
+            url: format!("http://{}/", addr),
+            event: "push".to_owned(),
+            payload: json!({"ref": "main"}),
+            attempts: 2,
+        };
+        std::fs::write(
+            queue_path.join("pending.json"),
+            serde_json::to_vec(&delivery).unwrap(),
+        )
+        .unwrap();
+
+        for handle in dispatcher.resume().unwrap() {
+            assert!(handle.await.unwrap());
+        }
+        assert_eq!(receiver.delivered.lock().unwrap().len(), 1);
+        assert_eq!(pending(&queue_path), 0);
+    }
+}
@@ -0,0 +1,278 @@
+//! The events sent to the webhook receivers, of which the name is in the `X-Mega-Event` header
+//! and the body is one of the structs here, so the receivers deserialize it by the name.
+//!
+//! A push is split into an event for each updated ref: the deleted refs are `ref_delete`, the
+//! created or moved tags are `tag`, and the others are `push`.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use common::utils::ZERO_ID;
+use database::driver::ObjectStorage;
+use git::protocol::{CommandType, RefCommand};
+use serde::{Deserialize, Serialize};
+
+/// The commits listed in a push event at most, the same as GitHub.
+pub const MAX_COMMITS: usize = 20;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub id: String,
+    pub message: String,
+    /// The `name <email>` of the author.
+    pub author: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PushEvent {
+    pub repo: String,
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub before: String,
+    pub after: String,
+    /// The authenticated user, `None` if the server is open.
+    pub pusher: Option<String>,
+    /// The new commits from the newest, at most [`MAX_COMMITS`].
+    pub commits: Vec<CommitInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TagEvent {
+    pub repo: String,
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub before: String,
+    pub after: String,
+    pub pusher: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RefDeleteEvent {
+    pub repo: String,
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub before: String,
+    pub after: String,
+    pub pusher: Option<String>,
+}
+
+/// The synthetic event of a test delivery, which is sent to check the receiver rather than of
+/// any push.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PingEvent {
+    /// The repo of which the receivers are tested, `None` for the ones of the server.
+    pub repo: Option<String>,
+    pub message: String,
+}
+
+impl PingEvent {
+    pub const NAME: &'static str = "ping";
+
+    pub fn new(repo: Option<&str>) -> PingEvent {
+        PingEvent {
+            repo: repo.map(str::to_owned),
+            message: String::from("This is a test delivery of mega"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Event {
+    Push(PushEvent),
+    Tag(TagEvent),
+    RefDelete(RefDeleteEvent),
+}
+
+impl Event {
+    /// The event of a ref updated by the push, the `commits` are only used by the `push`.
+    pub fn from_command(
+        repo: &str,
+        pusher: Option<&str>,
+        command: &RefCommand,
+        commits: Vec<CommitInfo>,
+    ) -> Event {
+        let (repo, ref_name, pusher) = (
+            repo.to_owned(),
+            command.ref_name.clone(),
+            pusher.map(str::to_owned),
+        );
+        let (before, after) = (command.old_id.clone(), command.new_id.clone());
+        match command.command_type {
+            CommandType::Delete => Event::RefDelete(RefDeleteEvent {
+                repo,
+                ref_name,
+                before,
+                after,
+                pusher,
+            }),
+            _ if ref_name.starts_with("refs/tags/") => Event::Tag(TagEvent {
+                repo,
+                ref_name,
+                before,
+                after,
+                pusher,
+            }),
+            _ => Event::Push(PushEvent {
+                repo,
+                ref_name,
+                before,
+                after,
+                pusher,
+                commits,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Push(_) => "push",
+            Event::Tag(_) => "tag",
+            Event::RefDelete(_) => "ref_delete",
+        }
+    }
+
+    pub fn ref_name(&self) -> &str {
+        match self {
+            Event::Push(e) => &e.ref_name,
+            Event::Tag(e) => &e.ref_name,
+            Event::RefDelete(e) => &e.ref_name,
+        }
+    }
+}
+
+/// The `name <email>` of the signature saved as `author name <email> timestamp timezone`.
+fn signature_name(signature: &str) -> Option<String> {
+    let (_, rest) = signature.split_once(' ')?;
+    let end = rest.find('>')?;
+    Some(rest[..=end].to_owned())
+}
+
+/// The commits reachable from `after` but not `before`, from the newest. The commits missing
+/// in the storage are skipped, so the list may be shorter than the push.
+pub async fn new_commits(
+    storage: &Arc<dyn ObjectStorage>,
+    before: &str,
+    after: &str,
+) -> Vec<CommitInfo> {
+    let mut commits = Vec::new();
+    if after == ZERO_ID || after == before {
+        return commits;
+    }
+    let mut visited = HashSet::from([before.to_owned()]);
+    let mut pending = vec![after.to_owned()];
+    while let Some(id) = pending.pop() {
+        if commits.len() >= MAX_COMMITS {
+            break;
+        }
+        if !visited.insert(id.clone()) {
+            continue;
+        }
+        let Ok(Some(commit)) = storage.get_commit_by_hash(&id).await else {
+            continue;
+        };
+        // the first parent is walked first
+        pending.extend(commit.pid.iter().rev().cloned());
+        commits.push(CommitInfo {
+            id: commit.git_id,
+            message: commit.content.unwrap_or_default(),
+            author: commit.author.as_deref().and_then(signature_name),
+        });
+    }
+    commits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use database::driver::ObjectStorage;
+    use git::protocol::RefCommand;
+    use serde_json::json;
+
+    use super::{new_commits, signature_name, CommitInfo, Event};
+    use crate::tests::MockStorage;
+
+    const OLD: &str = "1111111111111111111111111111111111111111";
+    const NEW: &str = "2222222222222222222222222222222222222222";
+    const ZERO: &str = "0000000000000000000000000000000000000000";
+
+    fn ref_event(ref_name: &str, old_id: &str, new_id: &str, commits: Vec<CommitInfo>) -> Event {
+        let command = RefCommand::new(old_id.to_owned(), new_id.to_owned(), ref_name.to_owned());
+        Event::from_command("/projects/repo", Some("alice"), &command, commits)
+    }
+
+    #[tokio::test]
+    async fn test_push_event() {
+        let commit = CommitInfo {
+            id: NEW.to_owned(),
+            message: "fix\n".to_owned(),
+            author: Some("Alice <alice@example.com>".to_owned()),
+        };
+        let event = ref_event("refs/heads/main", OLD, NEW, vec![commit]);
+        assert_eq!(event.name(), "push");
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "repo": "/projects/repo",
+                "ref": "refs/heads/main",
+                "before": OLD,
+                "after": NEW,
+                "pusher": "alice",
+                "commits": [{
+                    "id": NEW,
+                    "message": "fix\n",
+                    "author": "Alice <alice@example.com>",
+                }],
+            })
+        );
+
+        // no new commit, e.g. moved to a commit which is already pushed
+        let storage: Arc<dyn ObjectStorage> = Arc::new(MockStorage::default());
+        let commits = new_commits(&storage, NEW, NEW).await;
+        let event = ref_event("refs/heads/main", OLD, NEW, commits);
+        assert_eq!(serde_json::to_value(&event).unwrap()["commits"], json!([]));
+    }
+
+    #[test]
+    fn test_tag_event() {
+        let event = ref_event("refs/tags/v1.0", ZERO, NEW, vec![]);
+        assert_eq!(event.name(), "tag");
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "repo": "/projects/repo",
+                "ref": "refs/tags/v1.0",
+                "before": ZERO,
+                "after": NEW,
+                "pusher": "alice",
+            })
+        );
+    }
+
+    #[test]
+    fn test_ref_delete_event() {
+        let event = ref_event("refs/tags/v1.0", OLD, ZERO, vec![]);
+        assert_eq!(event.name(), "ref_delete");
+        assert_eq!(event.ref_name(), "refs/tags/v1.0");
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "repo": "/projects/repo",
+                "ref": "refs/tags/v1.0",
+                "before": OLD,
+                "after": ZERO,
+                "pusher": "alice",
+            })
+        );
+    }
+
+    #[test]
+    fn test_signature_name() {
+        assert_eq!(
+            signature_name("author Alice <alice@example.com> 1691000000 +0800").as_deref(),
+            Some("Alice <alice@example.com>")
+        );
+        assert_eq!(signature_name("invalid"), None);
+    }
+}
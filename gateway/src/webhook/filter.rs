@@ -0,0 +1,114 @@
+//! Select the refs of which the events are delivered.
+//!
+//! A pattern is a glob, e.g. `refs/tags/v*`, in which `*` matches within a path segment and
+//! `**` matches across them, or a regex with the `regex:` prefix. The patterns with the `!`
+//! prefix exclude the refs, e.g. `!refs/heads/tmp/**`. A ref is selected if it matches any of
+//! the other patterns, or there is none of them, and none of the excluded.
+
+use std::io;
+
+use regex::Regex;
+
+#[derive(Debug, Default)]
+pub struct RefFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+impl RefFilter {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> io::Result<RefFilter> {
+        let mut filter = RefFilter::default();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let (negative, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern),
+            };
+            let regex = match pattern.strip_prefix("regex:") {
+                Some(regex) => regex.to_owned(),
+                None => glob_to_regex(pattern),
+            };
+            let regex = Regex::new(&regex).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid ref filter `{}`: {}", pattern, e),
+                )
+            })?;
+            if negative {
+                filter.exclude.push(regex);
+            } else {
+                filter.include.push(regex);
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, ref_name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(ref_name)))
+            && !self.exclude.iter().any(|r| r.is_match(ref_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RefFilter;
+
+    #[test]
+    fn test_match_all_by_default() {
+        let filter = RefFilter::new::<&str>(&[]).unwrap();
+        assert!(filter.matches("refs/heads/main"));
+        assert!(filter.matches("refs/tags/v1.0"));
+    }
+
+    #[test]
+    fn test_match() {
+        let filter = RefFilter::new(&["refs/heads/main", "regex:^refs/tags/v[0-9]+\\."]).unwrap();
+        assert!(filter.matches("refs/heads/main"));
+        assert!(filter.matches("refs/tags/v1.0"));
+        assert!(!filter.matches("refs/heads/main2"));
+        assert!(!filter.matches("refs/heads/dev"));
+        assert!(!filter.matches("refs/tags/nightly"));
+
+        let filter = RefFilter::new(&["refs/heads/*"]).unwrap();
+        assert!(filter.matches("refs/heads/dev"));
+        assert!(!filter.matches("refs/heads/feature/a"));
+        let filter = RefFilter::new(&["refs/heads/**"]).unwrap();
+        assert!(filter.matches("refs/heads/feature/a"));
+    }
+
+    #[test]
+    fn test_negation() {
+        let filter = RefFilter::new(&["!refs/heads/tmp/**"]).unwrap();
+        assert!(filter.matches("refs/heads/main"));
+        assert!(!filter.matches("refs/heads/tmp/a/b"));
+
+        let filter = RefFilter::new(&["refs/heads/**", "!refs/heads/tmp/*"]).unwrap();
+        assert!(filter.matches("refs/heads/main"));
+        assert!(!filter.matches("refs/heads/tmp/a"));
+        assert!(!filter.matches("refs/tags/v1.0"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let err = RefFilter::new(&["regex:refs/(heads"]).unwrap_err();
+        assert!(err.to_string().starts_with("invalid ref filter `regex:refs/(heads`"));
+    }
+}
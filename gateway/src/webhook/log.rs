@@ -0,0 +1,187 @@
+//! The log of the delivery attempts, by which the failed deliveries are inspected after they
+//! are given up, e.g. by `GET /api/v1/webhooks/:id/deliveries`.
+//!
+//! The attempts are appended as the JSON lines to a file of each day (UTC) in the log
+//! directory, so the old ones are pruned by removing the files of the days before the
+//! retention. A receiver is identified by the [`webhook_id`] of its url, which is the same for
+//! the receivers of the server and the ones of the repos.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{NaiveDate, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The bytes of the response body kept in the log at most.
+pub const MAX_RESPONSE_SNIPPET: usize = 256;
+
+/// The id of the receiver of the url, the first 16 hex digits of the SHA-256 of it.
+pub fn webhook_id(url: &str) -> String {
+    hex::encode(&Sha256::digest(url.as_bytes())[..8])
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeliveryAttempt {
+    /// The id of the delivery, which is the same for the retries of it
+    pub delivery_id: String,
+    pub webhook_id: String,
+    pub url: String,
+    pub event: String,
+    /// The number of the attempt from 1
+    pub attempt: u32,
+    /// The status of the response, `None` if there is no response
+    pub status: Option<u16>,
+    /// The start of the response body, at most [`MAX_RESPONSE_SNIPPET`] bytes
+    pub response: Option<String>,
+    /// Why there is no response, e.g. a timeout
+    pub error: Option<String>,
+    /// The time of the attempt in RFC 3339
+    pub timestamp: String,
+}
+
+impl DeliveryAttempt {
+    /// The attempt of now, of which the outcome is set by the caller.
+    pub fn new(delivery_id: &str, url: &str, event: &str, attempt: u32) -> DeliveryAttempt {
+        DeliveryAttempt {
+            delivery_id: delivery_id.to_owned(),
+            webhook_id: webhook_id(url),
+            url: url.to_owned(),
+            event: event.to_owned(),
+            attempt,
+            status: None,
+            response: None,
+            error: None,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+}
+
+pub struct DeliveryLog {
+    path: PathBuf,
+    /// The appends of the attempts delivered at once are not interleaved.
+    lock: Mutex<()>,
+}
+
+impl DeliveryLog {
+    pub fn new(path: PathBuf) -> io::Result<DeliveryLog> {
+        std::fs::create_dir_all(&path)?;
+        Ok(DeliveryLog {
+            path,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn day_path(&self, day: NaiveDate) -> PathBuf {
+        self.path.join(format!("{}.jsonl", day.format("%Y-%m-%d")))
+    }
+
+    /// The days of the files in the log, from the newest.
+    fn days(&self) -> io::Result<Vec<NaiveDate>> {
+        let mut days = Vec::new();
+        for entry in std::fs::read_dir(&self.path)? {
+            let name = entry?.file_name();
+            let day = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".jsonl"))
+                .and_then(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok());
+            days.extend(day);
+        }
+        days.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(days)
+    }
+
+    /// Append the attempt to the file of today.
+    pub fn record(&self, attempt: &DeliveryAttempt) -> io::Result<()> {
+        let mut line = serde_json::to_vec(attempt)?;
+        line.push(b'\n');
+        let _guard = self.lock.lock().unwrap();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.day_path(Utc::now().date_naive()))?
+            .write_all(&line)
+    }
+
+    /// The attempts of the receiver of the `webhook_id` from the newest, at most `limit`.
+    pub fn attempts(&self, webhook_id: &str, limit: usize) -> io::Result<Vec<DeliveryAttempt>> {
+        let mut attempts = Vec::new();
+        for day in self.days()? {
+            let content = match std::fs::read_to_string(self.day_path(day)) {
+                Ok(content) => content,
+                // pruned meanwhile
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            // a line being appended or broken by a crash is skipped
+            let day_attempts = content
+                .lines()
+                .rev()
+                .filter_map(|line| serde_json::from_str::<DeliveryAttempt>(line).ok())
+                .filter(|attempt| attempt.webhook_id == webhook_id);
+            for attempt in day_attempts {
+                if attempts.len() == limit {
+                    return Ok(attempts);
+                }
+                attempts.push(attempt);
+            }
+        }
+        Ok(attempts)
+    }
+
+    /// Remove the files of the days older than the `retention_days`, returns how many are
+    /// removed. The file of today is always kept.
+    pub fn prune(&self, retention_days: u32) -> io::Result<usize> {
+        let today = Utc::now().date_naive();
+        let mut removed = 0;
+        for day in self.days()? {
+            if (today - day).num_days() > i64::from(retention_days) {
+                std::fs::remove_file(self.day_path(day))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::{webhook_id, DeliveryAttempt, DeliveryLog};
+
+    #[test]
+    fn test_attempts_and_prune() {
+        let path = std::env::temp_dir().join(format!("mega_delivery_log_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let log = DeliveryLog::new(path.clone()).unwrap();
+        let (url, other) = ("http://127.0.0.1/a", "http://127.0.0.1/b");
+        assert_eq!(webhook_id(url).len(), 16);
+        assert_ne!(webhook_id(url), webhook_id(other));
+
+        for attempt in 1..=3 {
+            log.record(&DeliveryAttempt::new("1", url, "push", attempt))
+                .unwrap();
+        }
+        log.record(&DeliveryAttempt::new("2", other, "push", 1))
+            .unwrap();
+        let attempts = log.attempts(&webhook_id(url), 2).unwrap();
+        let numbers: Vec<u32> = attempts.iter().map(|a| a.attempt).collect();
+        assert_eq!(numbers, [3, 2]);
+        assert_eq!(log.attempts(&webhook_id(other), 10).unwrap().len(), 1);
+
+        // the file of a day long ago
+        let old = Utc::now().date_naive() - Duration::days(40);
+        let line = serde_json::to_string(&DeliveryAttempt::new("0", url, "push", 1)).unwrap();
+        std::fs::write(log.day_path(old), line + "\n").unwrap();
+        // and one which is not of the log
+        std::fs::write(path.join("README"), "").unwrap();
+        assert_eq!(log.attempts(&webhook_id(url), 10).unwrap().len(), 4);
+        assert_eq!(log.prune(30).unwrap(), 1);
+        assert_eq!(log.prune(30).unwrap(), 0);
+        assert_eq!(log.attempts(&webhook_id(url), 10).unwrap().len(), 3);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -7,6 +7,8 @@ use std::string::FromUtf8Error;
 
 use thiserror::Error;
 
+use crate::internal::pack::cache::CacheError;
+
 #[derive(Error, Debug)]
 #[allow(unused)]
 pub enum GitError {
@@ -63,6 +65,9 @@ pub enum GitError {
 
     #[error("UTF-8 conversion error: {0}")]
     ConversionError(String),
+
+    #[error("Cache Error Info:{0}")]
+    CacheError(String),
 }
 
 impl From<FromUtf8Error> for GitError {
@@ -71,3 +76,9 @@ impl From<FromUtf8Error> for GitError {
         GitError::ConversionError(err.to_string())
     }
 }
+
+impl From<CacheError> for GitError {
+    fn from(err: CacheError) -> Self {
+        GitError::CacheError(err.to_string())
+    }
+}
@@ -7,17 +7,64 @@ use std::fmt::Display;
 
 use bstr::ByteSlice;
 use colored::Colorize;
-use sha1::{Digest, Sha1};
+use sha2::{Digest, Sha256};
 use serde::{Deserialize, Serialize};
-/// The Hash struct which only contain the u8 array :`[u8;20]` is used to represent Git hash IDs,
-/// which are 40-character hexadecimal strings computed using the SHA-1 algorithm. In Git, each object
+
+use self::backend::Sha1Backend;
+
+pub mod backend;
+
+/// The object format of a repository, which decides the algorithm and length of the
+/// hash IDs. It is set by `git init --object-format=<format>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum HashKind {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashKind {
+    /// The size of the raw hash in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            HashKind::Sha1 => 20,
+            HashKind::Sha256 => 32,
+        }
+    }
+
+    /// The length of the hexadecimal string of the hash
+    pub fn hex_len(&self) -> usize {
+        self.size() * 2
+    }
+
+    /// Get the object format by the size of a raw hash
+    pub fn from_size(size: usize) -> Option<HashKind> {
+        match size {
+            20 => Some(HashKind::Sha1),
+            32 => Some(HashKind::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// The Hash enum which only contain the u8 array is used to represent Git hash IDs, which are
+/// 40-character hexadecimal strings computed using the SHA-1 algorithm, or 64-character ones
+/// computed using the SHA-256 algorithm of the new object format. In Git, each object
 /// is assigned a unique hash ID based on its content, which is used to identify
-/// the object's location in the Git database.The Hash struct provides a convenient
-/// way to store and manipulate Git hash IDs by using a separate struct for hash IDs to make
+/// the object's location in the Git database.The Hash enum provides a convenient
+/// way to store and manipulate Git hash IDs by using a separate type for hash IDs to make
 /// code more readable and maintainable.
 #[allow(unused)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Default,Deserialize, Serialize)]
-pub struct Hash(pub [u8; 20]);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Hash {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+impl Default for Hash {
+    fn default() -> Self {
+        Hash::Sha1([0u8; 20])
+    }
+}
 pub trait CompHash {
     fn compute_hash(&self) -> Hash;
 }
@@ -37,7 +84,7 @@ impl redis::ToRedisArgs for Hash{
     fn write_redis_args<W>(&self, out: &mut W)
     where
         W: ?Sized + redis::RedisWrite {
-        out.write_arg(&self.0)
+        out.write_arg(self.as_bytes())
     }
 }
 impl Hash {
@@ -49,45 +96,118 @@ impl Hash {
     /// let hash = Hash::new(&vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0]);
     /// assert_eq!(hash.to_plain_str(), "e89ad5a9631c3efdded7e3ecce79b4d0fedce1bf");
     /// ```
-    pub fn new(data: &Vec<u8>) -> Hash {
-        // Create a Sha1 object for calculating the SHA-1 hash
-        let mut hasher = Sha1::new();
-        // Input the data into the Sha1 object
+    pub fn new(data: &[u8]) -> Hash {
+        Hash::new_with_kind(HashKind::Sha1, data)
+    }
+
+    /// Calculate the hash of data with the algorithm of the object format
+    /// # Example
+    /// ```
+    /// use git::hash::{Hash, HashKind};
+    ///
+    /// let hash = Hash::new_with_kind(HashKind::Sha256, b"");
+    /// assert_eq!(
+    ///     hash.to_plain_str(),
+    ///     "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    /// );
+    /// ```
+    pub fn new_with_kind(kind: HashKind, data: &[u8]) -> Hash {
+        let mut hasher = Hash::hasher_with_kind(kind);
         hasher.update(data);
-        // Get the result of the hash
-        let hash_result = hasher.finalize();
-        // Convert the result to a 20-byte array
-        let result = <[u8; 20]>::from(hash_result);
+        hasher.finalize()
+    }
 
-        Hash(result)
+    /// The incremental SHA-1 of the data fed in chunks, so it's not buffered to be hashed
+    /// # Example
+    /// ```
+    /// use git::hash::Hash;
+    ///
+    /// let mut hasher = Hash::hasher();
+    /// hasher.update(b"blob 14\0");
+    /// hasher.update(b"Hello, World!\n");
+    /// assert_eq!(
+    ///     hasher.finalize().to_plain_str(),
+    ///     "8ab686eafeb1f44702738c8b0f24f2567c36da6d"
+    /// );
+    /// ```
+    pub fn hasher() -> Hasher {
+        Hash::hasher_with_kind(HashKind::Sha1)
     }
 
-    /// Create Hash from a byte array
+    /// The incremental hash with the algorithm of the object format
+    pub fn hasher_with_kind(kind: HashKind) -> Hasher {
+        match kind {
+            HashKind::Sha1 => Hasher::Sha1(backend::Sha1::default()),
+            HashKind::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    /// Create Hash from a byte array, the object format is decided by the length
     pub fn new_from_bytes(bytes: &[u8]) -> Hash {
-        let mut h = Hash::default();
-        h.0.copy_from_slice(bytes);
-        h
+        match HashKind::from_size(bytes.len()) {
+            Some(HashKind::Sha256) => Hash::Sha256(bytes.try_into().unwrap()),
+            _ => Hash::Sha1(bytes.try_into().unwrap()),
+        }
     }
 
-    /// Create Hash from a string, which is a 40-character hexadecimal string
+    /// Create Hash from a string, which is a 40-character (SHA-1) or 64-character (SHA-256)
+    /// hexadecimal string
     pub fn new_from_str(s: &str) -> Hash {
-        let mut h = Hash::default();
-        h.0.copy_from_slice(&hex::decode(s).unwrap());
-        h
+        Hash::new_from_bytes(&hex::decode(s).unwrap())
     }
 
     /// Create a Hash value by the row value
     pub fn from_row(hex_hash: &[u8]) -> Hash {
-        Hash(<[u8; 20]>::try_from(hex_hash).unwrap())
+        Hash::new_from_bytes(hex_hash)
+    }
+
+    /// The object format of the hash
+    pub fn kind(&self) -> HashKind {
+        match self {
+            Hash::Sha1(_) => HashKind::Sha1,
+            Hash::Sha256(_) => HashKind::Sha256,
+        }
+    }
+
+    /// The raw bytes of the hash
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Hash::Sha1(h) => h,
+            Hash::Sha256(h) => h,
+        }
     }
 
     /// Create plain String without the color chars
     pub fn to_plain_str(self) -> String {
-        hex::encode(self.0)
+        hex::encode(self.as_bytes())
     }
 
     pub fn to_data(self) -> Vec<u8> {
-        self.0.repeatn(1)
+        self.as_bytes().repeatn(1)
+    }
+}
+
+/// The hash of the data fed by [`Hasher::update`], e.g. of a large blob as it streams, which is
+/// the same as [`Hash::new_with_kind`] of the whole data.
+#[derive(Clone)]
+pub enum Hasher {
+    Sha1(backend::Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(h) => Sha1Backend::update(h, data),
+            Hasher::Sha256(h) => Digest::update(h, data),
+        }
+    }
+
+    pub fn finalize(self) -> Hash {
+        match self {
+            Hasher::Sha1(h) => Hash::Sha1(h.finalize()),
+            Hasher::Sha256(h) => Hash::Sha256(h.finalize().into()),
+        }
     }
 }
 
@@ -146,4 +266,41 @@ mod tests {
             "8ab686eafeb1f44702738c8b0f24f2567c36da6d"
         );
     }
+
+    #[test]
+    fn test_hash_kinds() {
+        use super::{Hash, HashKind};
+        // blob content of `Hello, World!` with LF
+        let data = b"blob 14\0Hello, World!\n".to_vec();
+        let sha1 = Hash::new_with_kind(HashKind::Sha1, &data);
+        let sha256 = Hash::new_with_kind(HashKind::Sha256, &data);
+        assert_eq!(sha1, Hash::new(&data));
+        assert_eq!(sha1.to_plain_str(), "8ab686eafeb1f44702738c8b0f24f2567c36da6d");
+        assert_eq!(sha1.to_plain_str().len(), HashKind::Sha1.hex_len());
+        assert_eq!(sha256.to_plain_str().len(), 64);
+        assert_eq!(sha256.kind(), HashKind::Sha256);
+        assert_eq!(sha256.to_data().len(), 32);
+        // parse by the length of the string
+        assert_eq!(Hash::new_from_str(&sha256.to_plain_str()), sha256);
+        assert_eq!(Hash::new_from_str(&sha1.to_plain_str()), sha1);
+    }
+
+    #[test]
+    fn test_hasher_chunks() {
+        use super::{Hash, HashKind};
+
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for kind in [HashKind::Sha1, HashKind::Sha256] {
+            let expected = Hash::new_with_kind(kind, &data);
+            for size in [1, 7, 64, 4096, data.len()] {
+                let mut hasher = Hash::hasher_with_kind(kind);
+                for chunk in data.chunks(size) {
+                    hasher.update(chunk);
+                    hasher.update(b"");
+                }
+                assert_eq!(hasher.finalize(), expected);
+            }
+        }
+        assert_eq!(Hash::hasher().finalize(), Hash::new(b""));
+    }
 }
@@ -0,0 +1,137 @@
+//! The implementations of SHA-1 of the object ids, one of which is selected at compile time by
+//! the features, and the ids are the same whichever is selected:
+//!
+//! - the `sha1` crate by default, which uses the SHA-NI instructions if the CPU has them;
+//! - the libcrypto of OpenSSL with the `openssl_sha1` feature, of the assembly for more CPUs.
+
+use sha1::Digest;
+
+/// An incremental SHA-1 of an implementation.
+pub trait Sha1Backend: Default + Clone + Send {
+    fn update(&mut self, data: &[u8]);
+
+    fn finalize(self) -> [u8; 20];
+
+    /// The SHA-1 of the whole data.
+    fn digest(data: &[u8]) -> [u8; 20] {
+        let mut hasher = Self::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// The SHA-1 of the `sha1` crate.
+#[derive(Default, Clone)]
+pub struct RustCrypto(sha1::Sha1);
+
+impl Sha1Backend for RustCrypto {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> [u8; 20] {
+        self.0.finalize().into()
+    }
+}
+
+/// The SHA-1 of the libcrypto of OpenSSL.
+#[cfg(feature = "openssl_sha1")]
+#[derive(Clone)]
+pub struct OpenSsl(openssl::sha::Sha1);
+
+#[cfg(feature = "openssl_sha1")]
+impl Default for OpenSsl {
+    fn default() -> Self {
+        OpenSsl(openssl::sha::Sha1::new())
+    }
+}
+
+#[cfg(feature = "openssl_sha1")]
+impl Sha1Backend for OpenSsl {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> [u8; 20] {
+        self.0.finish()
+    }
+}
+
+/// The implementation selected by the features.
+#[cfg(feature = "openssl_sha1")]
+pub type Sha1 = OpenSsl;
+#[cfg(not(feature = "openssl_sha1"))]
+pub type Sha1 = RustCrypto;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{RustCrypto, Sha1, Sha1Backend};
+
+    /// The data and the SHA-1 of it, of FIPS 180 and the empty blob of git.
+    const VECTORS: [(&[u8], &str); 3] = [
+        (b"", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+        (b"abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+        (b"blob 0\0", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"),
+    ];
+
+    fn chunked<B: Sha1Backend>(data: &[u8], size: usize) -> [u8; 20] {
+        let mut hasher = B::default();
+        for chunk in data.chunks(size) {
+            hasher.update(chunk);
+        }
+        hasher.finalize()
+    }
+
+    #[test]
+    fn test_backend_digest() {
+        for (data, expected) in VECTORS {
+            assert_eq!(hex::encode(RustCrypto::digest(data)), expected);
+            assert_eq!(hex::encode(Sha1::digest(data)), expected);
+        }
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        assert_eq!(chunked::<Sha1>(&data, 4096), Sha1::digest(&data));
+    }
+
+    #[cfg(feature = "openssl_sha1")]
+    #[test]
+    fn test_backends_same_digest() {
+        use super::OpenSsl;
+
+        for (data, _) in VECTORS {
+            assert_eq!(OpenSsl::digest(data), RustCrypto::digest(data));
+        }
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        for size in [1, 63, 64, 65, 8192] {
+            assert_eq!(
+                chunked::<OpenSsl>(&data, size),
+                chunked::<RustCrypto>(&data, size)
+            );
+        }
+    }
+
+    fn throughput<B: Sha1Backend>(name: &str, data: &[u8]) {
+        let start = Instant::now();
+        let digest = chunked::<B>(data, 64 * 1024);
+        let elapsed = start.elapsed();
+        println!(
+            "{}: {:.0} MB/s, {}",
+            name,
+            data.len() as f64 / elapsed.as_secs_f64() / 1e6,
+            hex::encode(digest)
+        );
+    }
+
+    /// Hash a stream of 100MB by the chunks of 64KiB, run it with
+    /// `cargo test --release -p git --features openssl_sha1 test_backend_throughput -- --ignored --nocapture`.
+    /// It only prints the throughput of each backend, which depends on the CPU.
+    #[test]
+    #[ignore = "benchmark"]
+    fn test_backend_throughput() {
+        let data: Vec<u8> = (0..100 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        throughput::<RustCrypto>("sha1", &data);
+        #[cfg(feature = "openssl_sha1")]
+        throughput::<super::OpenSsl>("openssl", &data);
+    }
+}
@@ -0,0 +1,366 @@
+//! The commit-graph of a repository, the parents and the generation numbers of the commits,
+//! which answers the reachability of the fetch negotiation without loading the commits from the
+//! storage again and again.
+//!
+//! The generation of a root commit is 1, and the one of the others is one more than the greatest
+//! of the parents. So a commit is only reachable from the commits of a greater generation, and
+//! the walks stop at the generation of the commit looked for.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::hash::Hash;
+
+#[derive(Debug, Clone)]
+struct GraphNode {
+    parents: Vec<Hash>,
+    generation: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommitGraph {
+    nodes: HashMap<Hash, GraphNode>,
+}
+
+impl CommitGraph {
+    pub fn new() -> CommitGraph {
+        CommitGraph::default()
+    }
+
+    /// The graph of the `(commit, parents)`, in any order.
+    pub fn from_commits<I: IntoIterator<Item = (Hash, Vec<Hash>)>>(commits: I) -> CommitGraph {
+        let mut graph = CommitGraph::new();
+        graph.add_commits(commits);
+        graph
+    }
+
+    /// Add the new `(commit, parents)` in any order, e.g. of a push; the ones in the graph are
+    /// skipped. A parent not in the graph nor in the `commits` is taken as the parent of a root,
+    /// e.g. of a shallow history.
+    pub fn add_commits<I: IntoIterator<Item = (Hash, Vec<Hash>)>>(&mut self, commits: I) {
+        let pending: HashMap<Hash, Vec<Hash>> = commits
+            .into_iter()
+            .filter(|(id, _)| !self.nodes.contains_key(id))
+            .collect();
+        // the parents before the children, by the post-order of a walk
+        for start in pending.keys() {
+            let mut stack = vec![(*start, false)];
+            while let Some((id, visited)) = stack.pop() {
+                if self.nodes.contains_key(&id) {
+                    continue;
+                }
+                let parents = &pending[&id];
+                if visited {
+                    let generation = parents
+                        .iter()
+                        .filter_map(|p| self.nodes.get(p))
+                        .map(|p| p.generation)
+                        .max()
+                        .unwrap_or(0)
+                        + 1;
+                    self.nodes.insert(
+                        id,
+                        GraphNode {
+                            parents: parents.clone(),
+                            generation,
+                        },
+                    );
+                } else {
+                    stack.push((id, true));
+                    stack.extend(
+                        parents
+                            .iter()
+                            .filter(|p| pending.contains_key(p) && !self.nodes.contains_key(p))
+                            .map(|p| (*p, false)),
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, id: &Hash) -> bool {
+        self.nodes.contains_key(id)
+    }
+
+    pub fn generation(&self, id: &Hash) -> Option<u32> {
+        self.nodes.get(id).map(|node| node.generation)
+    }
+
+    pub fn parents(&self, id: &Hash) -> Option<&[Hash]> {
+        self.nodes.get(id).map(|node| &node.parents[..])
+    }
+
+    /// Whether `a` is reachable from `b`, and `a` is of `b` itself, the same as
+    /// `git merge-base --is-ancestor a b`. It's false if either is not in the graph.
+    pub fn is_ancestor(&self, a: Hash, b: Hash) -> bool {
+        let (Some(target), true) = (self.generation(&a), self.contains(&b)) else {
+            return false;
+        };
+        let mut seen = HashSet::new();
+        let mut stack = vec![b];
+        while let Some(id) = stack.pop() {
+            if id == a {
+                return true;
+            }
+            let Some(node) = self.nodes.get(&id) else {
+                continue;
+            };
+            // the parents are of lower generations than `a`
+            if node.generation <= target || !seen.insert(id) {
+                continue;
+            }
+            stack.extend(&node.parents);
+        }
+        false
+    }
+
+    /// The best common ancestors of `a` and `b`, none of which is reachable from the others,
+    /// the same as `git merge-base --all a b`, from the greatest generation.
+    pub fn merge_bases(&self, a: Hash, b: Hash) -> Vec<Hash> {
+        let of_b = self.ancestors(&[b]);
+        let common: Vec<Hash> = self
+            .ancestors(&[a])
+            .into_iter()
+            .filter(|id| of_b.contains(id))
+            .collect();
+        let parents: Vec<Hash> = common
+            .iter()
+            .flat_map(|id| self.nodes[id].parents.iter().copied())
+            .collect();
+        let below = self.ancestors(&parents);
+        let mut bases: Vec<Hash> = common
+            .into_iter()
+            .filter(|id| !below.contains(id))
+            .collect();
+        bases.sort_by_key(|id| (std::cmp::Reverse(self.nodes[id].generation), *id));
+        bases
+    }
+
+    /// The commits reachable from the `include`s but not from the `exclude`s, the same as
+    /// `git rev-list include ^exclude`, from the greatest generation, e.g. the commits to send
+    /// of the `want`s and the `have`s.
+    pub fn rev_list(&self, include: &[Hash], exclude: &[Hash]) -> Vec<Hash> {
+        let excluded = self.ancestors(exclude);
+        let mut seen = HashSet::new();
+        let mut stack: Vec<Hash> = include.to_vec();
+        let mut commits = Vec::new();
+        while let Some(id) = stack.pop() {
+            if excluded.contains(&id) || !seen.insert(id) {
+                continue;
+            }
+            if let Some(node) = self.nodes.get(&id) {
+                commits.push(id);
+                stack.extend(&node.parents);
+            }
+        }
+        commits.sort_by_key(|id| (std::cmp::Reverse(self.nodes[id].generation), *id));
+        commits
+    }
+
+    /// The commits in the graph reachable from the `tips`, the `tips` included.
+    fn ancestors(&self, tips: &[Hash]) -> HashSet<Hash> {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<Hash> = tips.to_vec();
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes.get(&id) {
+                if seen.insert(id) {
+                    stack.extend(&node.parents);
+                }
+            }
+        }
+        seen
+    }
+}
+
+/// The commit-graphs of the repositories shared by the connections of a server, each is built
+/// once by the first fetch of the repository, and the pushes add the new commits to it.
+#[derive(Debug, Default)]
+pub struct CommitGraphCache {
+    graphs: RwLock<HashMap<PathBuf, Arc<CommitGraph>>>,
+}
+
+impl CommitGraphCache {
+    pub fn get(&self, repo: &Path) -> Option<Arc<CommitGraph>> {
+        self.graphs.read().unwrap().get(repo).cloned()
+    }
+
+    pub fn insert(&self, repo: &Path, graph: CommitGraph) -> Arc<CommitGraph> {
+        let graph = Arc::new(graph);
+        self.graphs
+            .write()
+            .unwrap()
+            .insert(repo.to_path_buf(), graph.clone());
+        graph
+    }
+
+    /// Add the commits to the graph of the repository if it's built, the graphs in use by
+    /// the fetches are not changed.
+    pub fn add_commits<I: IntoIterator<Item = (Hash, Vec<Hash>)>>(&self, repo: &Path, commits: I) {
+        if let Some(graph) = self.graphs.write().unwrap().get_mut(repo) {
+            Arc::make_mut(graph).add_commits(commits);
+        }
+    }
+
+    /// Drop the graph of the repository, e.g. which is deleted.
+    pub fn remove(&self, repo: &Path) {
+        self.graphs.write().unwrap().remove(repo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    use super::{CommitGraph, CommitGraphCache};
+    use crate::hash::Hash;
+
+    /// The history of `n` commits of some branches and merges, each of the parents of the
+    /// earlier ones, and the roots of the first and the 100th.
+    fn history(n: usize) -> Vec<(Hash, Vec<Hash>)> {
+        let id = |i: usize| Hash::new(i.to_string().as_bytes());
+        let mut seed: u64 = 42;
+        let mut random = |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+        (0..n)
+            .map(|i| {
+                let parents = match i {
+                    0 | 100 => vec![],
+                    _ if random(5) == 0 => {
+                        // a merge of an older commit
+                        let other = random(i);
+                        if other == i - 1 {
+                            vec![id(i - 1)]
+                        } else {
+                            vec![id(i - 1), id(other)]
+                        }
+                    }
+                    // a branch of a recent commit
+                    _ => vec![id(i - 1 - random(i.min(4)))],
+                };
+                (id(i), parents)
+            })
+            .collect()
+    }
+
+    /// The ancestors of the commit by walking all the parents.
+    fn naive_ancestors(commits: &[(Hash, Vec<Hash>)], tip: Hash) -> HashSet<Hash> {
+        let parents: std::collections::HashMap<_, _> = commits.iter().cloned().collect();
+        let mut seen = HashSet::new();
+        let mut stack = vec![tip];
+        while let Some(id) = stack.pop() {
+            if seen.insert(id) {
+                stack.extend(parents[&id].iter().copied());
+            }
+        }
+        seen
+    }
+
+    #[test]
+    fn test_is_ancestor() {
+        let commits = history(300);
+        let mut shuffled = commits.clone();
+        shuffled.reverse();
+        let graph = CommitGraph::from_commits(shuffled);
+        assert_eq!(graph.len(), 300);
+        assert_eq!(graph.generation(&commits[0].0), Some(1));
+        assert_eq!(graph.generation(&commits[100].0), Some(1));
+        for (id, parents) in &commits {
+            for p in parents {
+                assert!(graph.generation(p) < graph.generation(id));
+            }
+        }
+
+        for b in (0..300).step_by(7) {
+            let ancestors = naive_ancestors(&commits, commits[b].0);
+            for (a, _) in &commits {
+                assert_eq!(graph.is_ancestor(*a, commits[b].0), ancestors.contains(a));
+            }
+        }
+        assert!(!graph.is_ancestor(Hash::new(b"missing"), commits[299].0));
+        assert!(!graph.is_ancestor(commits[0].0, Hash::new(b"missing")));
+    }
+
+    #[test]
+    fn test_merge_bases() {
+        let commits = history(300);
+        let graph = CommitGraph::from_commits(commits.clone());
+        for (a, b) in [(299, 298), (250, 120), (150, 90), (99, 100), (57, 201)] {
+            let (a, b) = (commits[a].0, commits[b].0);
+            let (of_a, of_b) = (naive_ancestors(&commits, a), naive_ancestors(&commits, b));
+            let common: Vec<Hash> = of_a.intersection(&of_b).copied().collect();
+            let mut expected: Vec<Hash> = common
+                .iter()
+                .filter(|c| {
+                    !common
+                        .iter()
+                        .any(|o| o != *c && naive_ancestors(&commits, *o).contains(c))
+                })
+                .copied()
+                .collect();
+            expected.sort();
+            let mut bases = graph.merge_bases(a, b);
+            bases.sort();
+            assert_eq!(bases, expected);
+        }
+        // of the histories of the different roots
+        assert!(graph.merge_bases(commits[99].0, commits[100].0).is_empty());
+    }
+
+    #[test]
+    fn test_rev_list() {
+        let commits = history(300);
+        let graph = CommitGraph::from_commits(commits.clone());
+        let (want, have) = (commits[299].0, commits[200].0);
+        let expected: HashSet<Hash> = naive_ancestors(&commits, want)
+            .difference(&naive_ancestors(&commits, have))
+            .copied()
+            .collect();
+        let sent = graph.rev_list(&[want], &[have]);
+        assert_eq!(sent.len(), expected.len());
+        assert_eq!(sent.iter().copied().collect::<HashSet<_>>(), expected);
+        assert_eq!(sent[0], want);
+        assert_eq!(graph.rev_list(&[have], &[want]), vec![]);
+    }
+
+    #[test]
+    fn test_add_commits() {
+        let commits = history(300);
+        let cache = CommitGraphCache::default();
+        let repo = Path::new("/projects/graph");
+        // not built yet
+        cache.add_commits(repo, commits[200..].iter().cloned());
+        assert!(cache.get(repo).is_none());
+
+        let in_use = cache.insert(repo, CommitGraph::from_commits(commits[..200].to_vec()));
+        // the new commits of a push in any order, and the old ones again
+        let mut pushed = commits[150..].to_vec();
+        pushed.reverse();
+        cache.add_commits(repo, pushed);
+        let graph = cache.get(repo).unwrap();
+        assert_eq!(in_use.len(), 200);
+        assert_eq!(graph.len(), 300);
+
+        let full = CommitGraph::from_commits(commits.clone());
+        for (id, _) in &commits {
+            assert_eq!(graph.generation(id), full.generation(id));
+            assert_eq!(
+                graph.is_ancestor(*id, commits[299].0),
+                full.is_ancestor(*id, commits[299].0)
+            );
+        }
+    }
+}
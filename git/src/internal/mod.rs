@@ -3,6 +3,8 @@
 //!
 //!
 //!
+pub mod commit_graph;
+pub mod object_cache;
 pub mod object;
 pub mod pack;
 pub mod zlib;
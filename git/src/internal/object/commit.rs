@@ -231,7 +231,7 @@ impl ObjectT for Commit {
         };
 
         Commit {
-            id: Hash([0u8; 20]),
+            id: Hash::default(),
             tree_id,
             parent_tree_ids,
             author,
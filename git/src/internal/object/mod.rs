@@ -15,6 +15,7 @@ pub mod tree;
 
 use self::{blob::Blob, commit::Commit, meta::Meta, tag::Tag, tree::Tree};
 use super::{pack::delta::DeltaReader, zlib::stream::inflate::ReadBoxed, ObjectType};
+use crate::hash::backend::Sha1Backend;
 use crate::hash::Hash;
 use database::utils::id_generator::generate_id;
 use entity::{
@@ -22,7 +23,6 @@ use entity::{
     git_obj,
 };
 use sea_orm::Set;
-use sha1::Digest;
 use std::{
     fmt::Display,
     io::{BufRead, Read},
@@ -81,10 +81,8 @@ pub trait ObjectT: Send + Sync + Display {
     {
         let mut content: Vec<u8> = Vec::with_capacity(size);
         read.read_to_end(&mut content).unwrap();
-        let h = read.hash.clone();
-        let hash_str = h.finalize();
         let mut result = Self::new_from_data(content);
-        result.set_hash(Hash::new_from_str(&format!("{:x}", hash_str)));
+        result.set_hash(Hash::Sha1(read.hash.clone().finalize()));
 
         result
     }
@@ -96,10 +94,8 @@ pub trait ObjectT: Send + Sync + Display {
     {
         let mut content: Vec<u8> = Vec::with_capacity(read.len());
         read.read_to_end(&mut content).unwrap();
-        let h = read.hash.clone();
-        let hash_str = h.finalize();
         let mut result = Self::new_from_data(content);
-        result.set_hash(Hash::new_from_str(&format!("{:x}", hash_str)));
+        result.set_hash(Hash::Sha1(read.hash.clone().finalize()));
         result
     }
 
@@ -317,7 +317,7 @@ impl ObjectT for Tree {
         }
 
         Tree {
-            id: Hash([0u8; 20]),
+            id: Hash::default(),
             tree_items,
         }
     }
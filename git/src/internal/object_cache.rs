@@ -0,0 +1,215 @@
+//! The objects of the fetches cached by a server and shared by the connections of it, like the
+//! [`CommitGraphCache`](crate::internal::commit_graph::CommitGraphCache), so the objects of
+//! the hot repositories are not loaded from the storage by every fetch.
+//!
+//! The objects never change as they are addressed by the content, so a cached one is never
+//! stale, though it's kept after the repositories of it are deleted until it's evicted or the
+//! cache is flushed. The LRU is bounded by the bytes of the objects, and an object larger than
+//! an eighth of the capacity is not cached, so it can't evict all the others.
+
+use std::sync::Mutex;
+
+use common::errors::MegaError;
+use database::driver::ObjectStorage;
+use entity::git_obj;
+use lru::LruCache;
+
+use crate::internal::pack::cache::CacheStats;
+
+/// The bytes of the objects in the cache at most by default.
+pub const DEFAULT_CAPACITY: usize = 64 * 1024 * 1024;
+
+struct Inner {
+    objects: LruCache<String, git_obj::Model>,
+    bytes: usize,
+    stats: CacheStats,
+}
+
+pub struct SharedObjectCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+/// The cache is disabled by default, of which nothing is kept.
+impl Default for SharedObjectCache {
+    fn default() -> Self {
+        SharedObjectCache::new(0)
+    }
+}
+
+impl SharedObjectCache {
+    /// The cache of the `capacity` bytes, 0 to disable it.
+    pub fn new(capacity: usize) -> SharedObjectCache {
+        SharedObjectCache {
+            capacity,
+            inner: Mutex::new(Inner {
+                objects: LruCache::unbounded(),
+                bytes: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number and the bytes of the cached objects.
+    pub fn size(&self) -> (usize, usize) {
+        let inner = self.inner.lock().unwrap();
+        (inner.objects.len(), inner.bytes)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.inner.lock().unwrap().stats
+    }
+
+    pub fn contains(&self, git_id: &str) -> bool {
+        self.inner.lock().unwrap().objects.contains(git_id)
+    }
+
+    pub fn get(&self, git_id: &str) -> Option<git_obj::Model> {
+        let mut inner = self.inner.lock().unwrap();
+        let found = inner.objects.get(git_id).cloned();
+        match found {
+            Some(_) => inner.stats.hits += 1,
+            None => inner.stats.misses += 1,
+        }
+        found
+    }
+
+    /// Cache the object, returns whether it's a new one in the cache.
+    pub fn insert(&self, object: git_obj::Model) -> bool {
+        let size = object.data.len();
+        if self.capacity == 0 || size > self.capacity / 8 {
+            return false;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.objects.contains(&object.git_id) {
+            return false;
+        }
+        inner.bytes += size;
+        inner.objects.put(object.git_id.clone(), object);
+        inner.stats.puts += 1;
+        while inner.bytes > self.capacity {
+            let Some((_, evicted)) = inner.objects.pop_lru() else {
+                break;
+            };
+            inner.bytes -= evicted.data.len();
+            inner.stats.evictions += 1;
+        }
+        true
+    }
+
+    /// Remove all the objects and reset the stats, returns how many objects are removed.
+    pub fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let removed = inner.objects.len();
+        inner.objects.clear();
+        inner.bytes = 0;
+        inner.stats = CacheStats::default();
+        removed
+    }
+
+    /// The objects of the ids, of which the missing ones in the cache are loaded from the
+    /// `storage` and cached. Like the `get_obj_data_by_ids` of it, the ones not in the storage
+    /// are skipped, and the order is not kept.
+    pub async fn get_many(
+        &self,
+        storage: &dyn ObjectStorage,
+        git_ids: Vec<String>,
+    ) -> Result<Vec<git_obj::Model>, MegaError> {
+        if self.capacity == 0 {
+            return storage.get_obj_data_by_ids(git_ids).await;
+        }
+        let mut objects = Vec::with_capacity(git_ids.len());
+        let mut missing = Vec::new();
+        for git_id in git_ids {
+            match self.get(&git_id) {
+                Some(object) => objects.push(object),
+                None => missing.push(git_id),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(objects);
+        }
+        let loaded = storage.get_obj_data_by_ids(missing).await?;
+        for object in &loaded {
+            self.insert(object.clone());
+        }
+        objects.extend(loaded);
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+    use entity::git_obj;
+    use sea_orm::Set;
+
+    use super::SharedObjectCache;
+
+    fn object(git_id: &str, size: usize) -> git_obj::Model {
+        git_obj::Model {
+            id: 0,
+            git_id: git_id.to_owned(),
+            object_type: "blob".to_owned(),
+            data: vec![b'a'; size],
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_insert_and_evict() {
+        let cache = SharedObjectCache::new(800);
+        assert!(cache.insert(object("a", 100)));
+        assert!(!cache.insert(object("a", 100)));
+        // larger than an eighth of the capacity
+        assert!(!cache.insert(object("large", 101)));
+        for id in ["b", "c", "d", "e", "f", "g", "h"] {
+            assert!(cache.insert(object(id, 100)));
+        }
+        assert_eq!(cache.size(), (8, 800));
+        // the least recently used one is evicted
+        assert!(cache.get("a").is_some());
+        assert!(cache.insert(object("i", 100)));
+        assert!(!cache.contains("b"));
+        assert!(cache.contains("a"));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.puts, stats.evictions), (1, 9, 1));
+
+        assert_eq!(cache.clear(), 8);
+        assert_eq!(cache.size(), (0, 0));
+        assert_eq!(cache.stats().puts, 0);
+        // disabled
+        assert!(!SharedObjectCache::default().insert(object("a", 0)));
+    }
+
+    #[tokio::test]
+    async fn test_get_many() {
+        let storage = MemoryStorage::new();
+        let saved = ["a", "b"].map(|id| {
+            let object = object(id, 10);
+            git_obj::ActiveModel {
+                id: Set(object.id),
+                git_id: Set(object.git_id),
+                object_type: Set(object.object_type),
+                data: Set(object.data),
+                compression: Set(None),
+            }
+        });
+        storage.save_obj_data(saved.to_vec()).await.unwrap();
+        let cache = SharedObjectCache::new(1024);
+        let ids = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        assert_eq!(
+            cache.get_many(&storage, ids.clone()).await.unwrap().len(),
+            2
+        );
+        assert_eq!(cache.size(), (2, 20));
+        assert_eq!(cache.stats().misses, 3);
+        assert_eq!(cache.get_many(&storage, ids).await.unwrap().len(), 2);
+        assert_eq!(cache.stats().hits, 2);
+    }
+}
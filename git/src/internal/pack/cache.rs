@@ -1,6 +1,12 @@
 use crate::hash::Hash;
 use lru::LruCache;
-use std::{collections::HashMap, num::NonZeroUsize};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Mutex, RwLock},
+};
+use thiserror::Error;
 
 #[derive(Hash, Clone, PartialEq, Eq)]
 struct OffHash {
@@ -13,8 +19,78 @@ pub trait _Cache{
     fn new(size: Option<usize>) -> Self where Self: Sized;
     fn get_hash(&self, offset: usize) -> Option<Hash>;
     fn get(&mut self, offset: usize) -> Option<Self::T>;
-    fn put(&mut self, offset: usize, hash: Hash, obj: Self::T);
+    fn put(&mut self, offset: usize, hash: Hash, obj: Self::T) -> Result<(), CacheError>;
     fn get_by_hash(&mut self, h: Hash) -> Option<Self::T>;
+    fn stats(&self) -> CacheStats;
+
+    /// Invalidate the object of the offset, e.g. after it's rewritten, so the next lookup of
+    /// it misses. The offset is forgotten as well.
+    fn remove(&mut self, offset: usize);
+    /// Invalidate the object of the hash, and forget the offsets of it.
+    fn remove_by_hash(&mut self, h: Hash);
+    /// Invalidate all the objects and the offsets.
+    fn clear(&mut self);
+
+    /// Put all the objects, the backend of a remote store should override it to
+    /// save the round-trips.
+    fn put_batch(&mut self, items: Vec<(usize, Hash, Self::T)>) -> Result<(), CacheError> {
+        for (offset, hash, obj) in items {
+            self.put(offset, hash, obj)?;
+        }
+        Ok(())
+    }
+
+    /// Get the objects of all the offsets in order, `None` for the missing one.
+    fn get_many(&mut self, offsets: &[usize]) -> Vec<Option<Self::T>> {
+        offsets.iter().map(|offset| self.get(*offset)).collect()
+    }
+
+    /// Set the callback of the objects evicted by the cache, e.g. to write them back to the
+    /// storage. The backends which keep no object in the memory ignore it, e.g. the kv store
+    /// manages the eviction itself.
+    fn set_on_evict(&mut self, _on_evict: EvictCallback<Self::T>) {}
+}
+
+/// The object is not saved by the backend of the cache, e.g. the kv store is down. The
+/// offset and the hash are still known by [`_Cache::get_hash`], so the caller may load the
+/// object from elsewhere later.
+#[derive(Error, Debug)]
+#[error("failed to cache the objects: {0}")]
+pub struct CacheError(pub String);
+
+/// Counters of the cache operations, used to profile the decode of a pack and
+/// pick the cache size empirically.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    pub puts: usize,
+}
+
+impl std::ops::Add for CacheStats {
+    type Output = CacheStats;
+
+    fn add(self, other: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            evictions: self.evictions + other.evictions,
+            puts: self.puts + other.puts,
+        }
+    }
+}
+
+impl CacheStats {
+    /// The ratio of misses in all lookups, 0.0 if there is no lookup yet.
+    pub fn miss_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.misses as f64 / lookups as f64
+        }
+    }
 }
 
 
@@ -31,11 +107,27 @@ pub trait _Cache{
 ///           ↗
 ///     Hash
 /// ```
+///
+/// The offset of each hash is indexed as well, which is kept after the object is evicted like
+/// the `ioffset`, so an evicted object is found by the hash for the fallback.
 pub struct ObjectCache<T> {
     ioffset: HashMap<usize, OffHash>,
+    hoffset: HashMap<Hash, usize>,
     ihash: LruCache<Hash, OffHash>,
     inner: LruCache<OffHash, T>,
+    fallback: Option<CacheFallback<T>>,
+    on_evict: Option<EvictCallback<T>>,
+    stats: CacheStats,
 }
+
+/// A resolver consulted by [`ObjectCache`] when the LRU misses, e.g. loading the
+/// object from the DataBase or another sink target by its hash.
+pub type CacheFallback<T> = Box<dyn Fn(Hash) -> Option<T> + Send>;
+
+/// A callback of the hash and the object dropped by the LRU of [`ObjectCache`], see
+/// [`_Cache::set_on_evict`].
+pub type EvictCallback<T> = Box<dyn FnMut(Hash, &T) + Send>;
+
 /// The Size of Object Cache during the decode operation should be talked about.
 /// There are --window and --depth options in the process of git pack packaging
 ///
@@ -51,14 +143,97 @@ pub struct ObjectCache<T> {
 ///
 /// But After the test, The size "50" also may meet a "cache miss" problem . This Size
 /// adjust to 300 more, the decode operation is normal.
-/// TODO : deal with "cache miss", get the miss object from DataBase or other sink target.
-const CACHE_SIZE: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(1000) };
+/// A "cache miss" can be resolved by a fallback, see [`ObjectCache::with_fallback`].
+///
+/// The size can be overridden by the `MEGA_PACK_CACHE_SIZE` env.
+const CACHE_SIZE: NonZeroUsize = NonZeroUsize::new(1000).unwrap();
+const CACHE_SIZE_ENV: &str = "MEGA_PACK_CACHE_SIZE";
+
+/// Get the cache size from the `MEGA_PACK_CACHE_SIZE` env, or [`CACHE_SIZE`] if absent.
+fn default_cache_size() -> NonZeroUsize {
+    parse_cache_size(std::env::var(CACHE_SIZE_ENV).ok())
+}
+
+fn parse_cache_size(value: Option<String>) -> NonZeroUsize {
+    let Some(value) = value else {
+        return CACHE_SIZE;
+    };
+    match value.trim().parse::<NonZeroUsize>() {
+        Ok(size) => size,
+        Err(_) => {
+            tracing::warn!(
+                "Invalid {}: {:?}, use the default size {}",
+                CACHE_SIZE_ENV,
+                value,
+                CACHE_SIZE
+            );
+            CACHE_SIZE
+        }
+    }
+}
+
 impl<T> Default for ObjectCache<T> {
     fn default() -> Self {
+        let size = default_cache_size();
         Self {
             ioffset: HashMap::new(),
-            ihash: LruCache::new(CACHE_SIZE),
-            inner: LruCache::new(CACHE_SIZE),
+            hoffset: HashMap::new(),
+            ihash: LruCache::new(size),
+            inner: LruCache::new(size),
+            fallback: None,
+            on_evict: None,
+            stats: CacheStats::default(),
+        }
+    }
+}
+
+impl<T> ObjectCache<T>
+where
+    T: Clone,
+{
+    /// Set the resolver used to get the miss object, the resolved object will be
+    /// put back to the LRU so the next lookup can hit.
+    pub fn with_fallback<F>(mut self, fallback: F) -> Self
+    where
+        F: Fn(Hash) -> Option<T> + Send + 'static,
+    {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// Set the callback of the evicted objects, e.g. to save them in the storage, from which
+    /// the fallback may load them again. The offsets of them are still known by
+    /// [`_Cache::get_hash`].
+    pub fn with_on_evict<F>(mut self, on_evict: F) -> Self
+    where
+        F: FnMut(Hash, &T) + Send + 'static,
+    {
+        self.on_evict = Some(Box::new(on_evict));
+        self
+    }
+
+    /// The offset of the hash, of the object evicted as well.
+    fn offset_of(&self, h: Hash) -> Option<usize> {
+        self.hoffset.get(&h).copied()
+    }
+
+    fn resolve_miss(&mut self, oh: OffHash) -> Option<T> {
+        self.stats.misses += 1;
+        let obj = (self.fallback.as_ref()?)(oh.h)?;
+        self.ihash.put(oh.h, oh.clone());
+        self.insert_inner(oh, obj.clone());
+        Some(obj)
+    }
+
+    fn insert_inner(&mut self, oh: OffHash, obj: T) {
+        // `push` also returns the old entry of the same key, which is not an eviction
+        if let Some((old, evicted)) = self.inner.push(oh.clone(), obj) {
+            if old != oh {
+                self.stats.evictions += 1;
+                if let Some(on_evict) = self.on_evict.as_mut() {
+                    on_evict(old.h, &evicted);
+                }
+            }
         }
     }
 }
@@ -71,61 +246,268 @@ where
         let lru_size = if let Some(size) = size {
             NonZeroUsize::new(size).unwrap()
         } else {
-            CACHE_SIZE
+            default_cache_size()
         };
         ObjectCache {
             ioffset: HashMap::new(),
+            hoffset: HashMap::new(),
             ihash: LruCache::new(lru_size),
             inner: LruCache::new(lru_size),
+            fallback: None,
+            on_evict: None,
+            stats: CacheStats::default(),
         }
     }
     fn get_hash(&self, offset: usize) -> Option<Hash> {
         self.ioffset.get(&offset).map(|oh| oh.h)
     }
-    fn put(&mut self, offset: usize, hash: Hash, obj: T) {
+    fn put(&mut self, offset: usize, hash: Hash, obj: T) -> Result<(), CacheError> {
         let oh: OffHash = OffHash { o: offset, h: hash };
-        self.ioffset.insert(offset, oh.clone());
+        if let Some(old) = self.ioffset.insert(offset, oh.clone()) {
+            if old.h != hash && self.hoffset.get(&old.h) == Some(&offset) {
+                self.hoffset.remove(&old.h);
+            }
+        }
+        self.hoffset.insert(hash, offset);
         self.ihash.put(hash, oh.clone());
-        self.inner.put(oh, obj);
+        self.insert_inner(oh, obj);
+        self.stats.puts += 1;
+        Ok(())
     }
 
     fn get(&mut self, offset: usize) -> Option<T> {
-        let oh = self.ioffset.get(&offset)?;
-        self.ihash.get(&oh.h)?;
-        self.inner.get(oh).cloned()
+        let Some(oh) = self.ioffset.get(&offset).cloned() else {
+            self.stats.misses += 1;
+            return None;
+        };
+        if self.ihash.get(&oh.h).is_some() {
+            if let Some(obj) = self.inner.get(&oh) {
+                self.stats.hits += 1;
+                return Some(obj.clone());
+            }
+        }
+        self.resolve_miss(oh)
     }
 
     fn get_by_hash(&mut self, h: Hash) -> Option<T> {
-        let oh = self.ihash.get(&h)?;
-        self.inner.get(oh).cloned()
+        if let Some(oh) = self.ihash.get(&h) {
+            if let Some(obj) = self.inner.get(oh) {
+                self.stats.hits += 1;
+                return Some(obj.clone());
+            }
+        }
+        // the offset of an evicted object is still kept in `hoffset`
+        let Some(o) = self.offset_of(h) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.resolve_miss(OffHash { o, h })
     }
 
-    
+    fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// The removed objects are not passed to the callback of the evictions.
+    fn remove(&mut self, offset: usize) {
+        let Some(oh) = self.ioffset.remove(&offset) else {
+            return;
+        };
+        if self.hoffset.get(&oh.h) == Some(&offset) {
+            // the same object may be of another offset
+            match self.ioffset.values().find(|other| other.h == oh.h) {
+                Some(other) => self.hoffset.insert(oh.h, other.o),
+                None => self.hoffset.remove(&oh.h),
+            };
+        }
+        self.inner.pop(&oh);
+        if self.ihash.peek(&oh.h) == Some(&oh) {
+            self.ihash.pop(&oh.h);
+        }
+    }
+
+    fn remove_by_hash(&mut self, h: Hash) {
+        self.ioffset.retain(|_, oh| oh.h != h);
+        self.hoffset.remove(&h);
+        self.ihash.pop(&h);
+        // the same object may be of many offsets
+        let stale: Vec<OffHash> = self
+            .inner
+            .iter()
+            .filter(|(oh, _)| oh.h == h)
+            .map(|(oh, _)| oh.clone())
+            .collect();
+        for oh in stale {
+            self.inner.pop(&oh);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.ioffset.clear();
+        self.hoffset.clear();
+        self.ihash.clear();
+        self.inner.clear();
+    }
+
+    fn set_on_evict(&mut self, on_evict: EvictCallback<T>) {
+        self.on_evict = Some(on_evict);
+    }
+}
+
+/// The shards of a [`SyncObjectCache`] by default.
+const SHARDS: usize = 16;
+
+/// An [`ObjectCache`] shared by the threads, e.g. of the decode, of which the methods take
+/// `&self`.
+///
+/// The objects are sharded by the hashes, each shard of which is an LRU of its own lock, so the
+/// threads contend only if they get or put the objects of the same shard. The hash of each
+/// offset is indexed by the shards of the offsets likewise. No two locks are held at once, and
+/// an object is put before its offset is indexed, so it's found by the offset once it's indexed,
+/// unless it's evicted. The LRU order is kept in each shard rather than the whole cache.
+pub struct SyncObjectCache<T> {
+    shards: Vec<Mutex<ObjectCache<T>>>,
+    offsets: Vec<RwLock<HashMap<usize, Hash>>>,
+    /// The misses of the offsets which are not indexed, which are of no shard.
+    misses: AtomicUsize,
+}
+
+impl<T> SyncObjectCache<T>
+where
+    T: Clone,
+{
+    /// The cache of the size in all, see [`_Cache::new`], in the default number of shards.
+    pub fn new(size: Option<usize>) -> Self {
+        Self::with_shards(size, SHARDS)
+    }
+
+    /// The cache of the size in all, split into the shards evenly.
+    pub fn with_shards(size: Option<usize>, shards: usize) -> Self {
+        let shards = shards.max(1);
+        let size = size.unwrap_or(default_cache_size().get());
+        let shard_size = size.div_ceil(shards).max(1);
+        SyncObjectCache {
+            shards: (0..shards)
+                .map(|_| Mutex::new(ObjectCache::new(Some(shard_size))))
+                .collect(),
+            offsets: (0..shards).map(|_| RwLock::default()).collect(),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, hash: &Hash) -> &Mutex<ObjectCache<T>> {
+        let bytes = hash.as_bytes();
+        let key = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        &self.shards[key % self.shards.len()]
+    }
+
+    fn offsets(&self, offset: usize) -> &RwLock<HashMap<usize, Hash>> {
+        &self.offsets[offset % self.offsets.len()]
+    }
+
+    pub fn get_hash(&self, offset: usize) -> Option<Hash> {
+        self.offsets(offset).read().unwrap().get(&offset).copied()
+    }
+
+    pub fn put(&self, offset: usize, hash: Hash, obj: T) -> Result<(), CacheError> {
+        self.shard(&hash).lock().unwrap().put(offset, hash, obj)?;
+        self.offsets(offset).write().unwrap().insert(offset, hash);
+        Ok(())
+    }
+
+    pub fn get(&self, offset: usize) -> Option<T> {
+        let Some(hash) = self.get_hash(offset) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        self.shard(&hash).lock().unwrap().get(offset)
+    }
+
+    pub fn get_by_hash(&self, h: Hash) -> Option<T> {
+        self.shard(&h).lock().unwrap().get_by_hash(h)
+    }
+
+    /// The stats of all the shards.
+    pub fn stats(&self) -> CacheStats {
+        let misses = CacheStats {
+            misses: self.misses.load(Ordering::Relaxed),
+            ..CacheStats::default()
+        };
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().stats())
+            .fold(misses, |all, stats| all + stats)
+    }
 }
 
 pub mod kvstore{
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
+    use std::marker::PhantomData;
+    use std::time::Duration;
     use crate::internal::pack::Hash;
-    //use kvcache::connector::fake::FakeKVstore;
     use kvcache::connector::redis::RedisClient;
+    use kvcache::connector::Connector;
     use kvcache::KVCache;
-    use super:: _Cache;
+    use super::{CacheError, CacheStats, _Cache};
 
-    pub struct ObjectCache<T> {
+    /// The seconds after which the objects expire from the kv store, never if it's not set
+    /// or 0.
+    const CACHE_TTL_ENV: &str = "MEGA_PACK_CACHE_TTL";
+
+    fn default_ttl() -> Option<Duration> {
+        parse_ttl(std::env::var(CACHE_TTL_ENV).ok())
+    }
+
+    pub(super) fn parse_ttl(value: Option<String>) -> Option<Duration> {
+        let value = value?;
+        match value.trim().parse::<u64>() {
+            Ok(secs) => (secs > 0).then(|| Duration::from_secs(secs)),
+            Err(_) => {
+                tracing::warn!(
+                    "Invalid {}: {:?}, the objects never expire",
+                    CACHE_TTL_ENV,
+                    value
+                );
+                None
+            }
+        }
+    }
+
+    /// The objects are kept in a kv store, Redis by default, or e.g.
+    /// [`FakeKVStore`](kvcache::connector::fake::FakeKVStore) in memory. They expire after
+    /// the ttl of the `MEGA_PACK_CACHE_TTL`, if it's set, so the kv store doesn't grow forever.
+    /// An expired object is a miss to get.
+    pub struct ObjectCache<T, C = RedisClient<Hash, T>> {
         ioffset:  HashMap<usize, Hash>,
-        inner : KVCache<RedisClient<Hash,T>>
+        inner : KVCache<C>,
+        ttl: Option<Duration>,
+        stats: CacheStats,
+        t: PhantomData<T>,
     }
-    impl<T> Default for ObjectCache<T> where T : redis::ToRedisArgs + redis::FromRedisValue + Clone {
+    impl<T, C> Default for ObjectCache<T, C> where C: Connector<K = Hash, V = T> {
         fn default() -> Self {
             Self {
                 ioffset: HashMap::new(),
                 inner: KVCache::new(),
+                ttl: default_ttl(),
+                stats: CacheStats::default(),
+                t: PhantomData,
             }
         }
     }
-    impl<T> _Cache for  ObjectCache<T>
+
+    impl<T, C> ObjectCache<T, C> {
+        /// Expire the objects put later after the ttl rather than the `MEGA_PACK_CACHE_TTL`,
+        /// never if it's none.
+        pub fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+            self.ttl = ttl;
+            self
+        }
+    }
+    impl<T, C> _Cache for  ObjectCache<T, C>
     where
-        T: Clone + redis::ToRedisArgs + redis::FromRedisValue ,
+        T: Clone,
+        C: Connector<K = Hash, V = T>,
     {
         type T = T; 
         fn new(_size: Option<usize>) -> Self {
@@ -134,33 +516,415 @@ pub mod kvstore{
         fn get_hash(&self, offset: usize) -> Option<Hash> {
             self.ioffset.get(&offset).copied()
         }
-        fn put(&mut self, offset: usize, hash: Hash, obj: T) {
+        /// The offset is kept even if the store fails after the retries, so the hash of it is
+        /// known, and it's a miss to get the object.
+        fn put(&mut self, offset: usize, hash: Hash, obj: T) -> Result<(), CacheError> {
             self.ioffset.insert(offset, hash);
-            self.inner.set(hash, obj).unwrap();
+            match self.ttl {
+                Some(ttl) => self.inner.set_ex(hash, obj, ttl),
+                None => self.inner.set(hash, obj),
+            }
+            .map_err(|e| CacheError(format!("{}: {}", hash, e)))?;
+            self.stats.puts += 1;
+            Ok(())
         }
     
         fn get(&mut self, offset: usize) -> Option<T> {
-            let h = self.ioffset.get(&offset)?;
-            self.inner.get(*h)    
+            match self.ioffset.get(&offset) {
+                Some(h) => {
+                    let obj = self.inner.get(*h);
+                    self.record(obj)
+                }
+                None => self.record(None),
+            }
         }
     
         fn get_by_hash(&mut self, h: Hash) -> Option<T> {
-            self.inner.get(h)  
+            let obj = self.inner.get(h);
+            self.record(obj)
+        }
+
+        /// The kv store manages the eviction itself, so `evictions` is always 0.
+        fn stats(&self) -> CacheStats {
+            self.stats
+        }
+
+        /// The object is kept by the hash in the kv store, so the other offsets of it are
+        /// forgotten as well.
+        fn remove(&mut self, offset: usize) {
+            if let Some(h) = self.ioffset.get(&offset).copied() {
+                self.remove_by_hash(h);
+            }
+        }
+
+        /// A failure of the kv store is only a warning, the offsets are forgotten anyway.
+        fn remove_by_hash(&mut self, h: Hash) {
+            self.ioffset.retain(|_, hash| *hash != h);
+            if let Err(err) = self.inner.delete(h) {
+                tracing::warn!("Failed to remove {} from the kv store: {}", h, err);
+            }
+        }
+
+        /// Only the objects of the known offsets are removed, the others of the kv store, e.g.
+        /// of the other instances, are kept.
+        fn clear(&mut self) {
+            let hashes: HashSet<Hash> = self.ioffset.drain().map(|(_, h)| h).collect();
+            for h in hashes {
+                if let Err(err) = self.inner.delete(h) {
+                    tracing::warn!("Failed to remove {} from the kv store: {}", h, err);
+                }
+            }
+        }
+
+        /// Send all the objects in one pipeline rather than one round-trip per object,
+        /// which matters when thousands of objects are decoded from a pack.
+        fn put_batch(&mut self, items: Vec<(usize, Hash, T)>) -> Result<(), CacheError> {
+            let len = items.len();
+            let pairs = items
+                .into_iter()
+                .map(|(offset, hash, obj)| {
+                    self.ioffset.insert(offset, hash);
+                    (hash, obj)
+                })
+                .collect();
+            match self.ttl {
+                Some(ttl) => self.inner.set_batch_ex(pairs, ttl),
+                None => self.inner.set_batch(pairs),
+            }
+            .map_err(|e| CacheError(e.to_string()))?;
+            self.stats.puts += len;
+            Ok(())
+        }
+
+        /// Get all the known objects by one `MGET`.
+        fn get_many(&mut self, offsets: &[usize]) -> Vec<Option<T>> {
+            let hashes: Vec<Option<Hash>> = offsets.iter().map(|o| self.get_hash(*o)).collect();
+            let known = hashes.iter().flatten().copied().collect();
+            let mut found = self.inner.get_many(known).into_iter();
+            hashes
+                .into_iter()
+                .map(|h| {
+                    let obj = h.and_then(|_| found.next().flatten());
+                    self.record(obj)
+                })
+                .collect()
+        }
+    }
+
+    impl<T, C> ObjectCache<T, C> {
+        fn record(&mut self, obj: Option<T>) -> Option<T> {
+            if obj.is_some() {
+                self.stats.hits += 1;
+            } else {
+                self.stats.misses += 1;
+            }
+            obj
         }
-    
-        
     }
     
 }
 
 
+/// The objects are kept in the memory, and in a kv store behind it, which holds many more of
+/// them, e.g. of a large pack.
+///
+/// The memory is consulted before the kv store, and an object got from the kv store is put
+/// back to the memory, so the hot objects are got at the speed of the LRU.
+pub mod tiered {
+    use kvcache::connector::redis::RedisClient;
+    use kvcache::connector::Connector;
+
+    use super::{_Cache, kvstore, CacheError, CacheStats, EvictCallback, ObjectCache};
+    use crate::internal::pack::Hash;
+
+    pub struct TieredCache<T, C = RedisClient<Hash, T>> {
+        memory: ObjectCache<T>,
+        kv: kvstore::ObjectCache<T, C>,
+        stats: CacheStats,
+    }
+
+    impl<T, C> TieredCache<T, C>
+    where
+        T: Clone,
+        C: Connector<K = Hash, V = T>,
+    {
+        /// Put the object got from the kv store back to the memory.
+        fn promote(&mut self, offset: usize, hash: Hash, obj: Option<T>) -> Option<T> {
+            let Some(obj) = obj else {
+                self.stats.misses += 1;
+                return None;
+            };
+            self.stats.hits += 1;
+            let _ = self.memory.put(offset, hash, obj.clone());
+            Some(obj)
+        }
+    }
+
+    impl<T, C> _Cache for TieredCache<T, C>
+    where
+        T: Clone,
+        C: Connector<K = Hash, V = T>,
+    {
+        type T = T;
+
+        /// The size is of the memory, the kv store manages the size of itself.
+        fn new(size: Option<usize>) -> Self {
+            TieredCache {
+                memory: ObjectCache::new(size),
+                kv: kvstore::ObjectCache::new(None),
+                stats: CacheStats::default(),
+            }
+        }
+
+        fn get_hash(&self, offset: usize) -> Option<Hash> {
+            self.memory
+                .get_hash(offset)
+                .or_else(|| self.kv.get_hash(offset))
+        }
+
+        /// The object is put in the memory even if the kv store fails, which is only a warning,
+        /// so the object is a miss once it's evicted from the memory.
+        fn put(&mut self, offset: usize, hash: Hash, obj: T) -> Result<(), CacheError> {
+            self.memory.put(offset, hash, obj.clone())?;
+            if let Err(err) = self.kv.put(offset, hash, obj) {
+                tracing::warn!("The object is cached in the memory only: {}", err);
+            }
+            self.stats.puts += 1;
+            Ok(())
+        }
+
+        fn get(&mut self, offset: usize) -> Option<T> {
+            if let Some(obj) = self.memory.get(offset) {
+                self.stats.hits += 1;
+                return Some(obj);
+            }
+            let Some(hash) = self.get_hash(offset) else {
+                self.stats.misses += 1;
+                return None;
+            };
+            let obj = self.kv.get(offset);
+            self.promote(offset, hash, obj)
+        }
+
+        fn get_by_hash(&mut self, h: Hash) -> Option<T> {
+            if let Some(obj) = self.memory.get_by_hash(h) {
+                self.stats.hits += 1;
+                return Some(obj);
+            }
+            // the memory knows the offsets of the evicted objects too
+            let Some(offset) = self.memory.offset_of(h) else {
+                self.stats.misses += 1;
+                return None;
+            };
+            let obj = self.kv.get_by_hash(h);
+            self.promote(offset, h, obj)
+        }
+
+        /// The hits and the misses of both, and the evictions from the memory.
+        fn stats(&self) -> CacheStats {
+            CacheStats {
+                evictions: self.memory.stats().evictions,
+                ..self.stats
+            }
+        }
+
+        fn remove(&mut self, offset: usize) {
+            self.memory.remove(offset);
+            self.kv.remove(offset);
+        }
+
+        fn remove_by_hash(&mut self, h: Hash) {
+            self.memory.remove_by_hash(h);
+            self.kv.remove_by_hash(h);
+        }
+
+        fn clear(&mut self) {
+            self.memory.clear();
+            self.kv.clear();
+        }
+
+        fn set_on_evict(&mut self, on_evict: EvictCallback<T>) {
+            self.memory.set_on_evict(on_evict);
+        }
+    }
+}
+
+/// An on-disk `ObjectCache` backed by sled, so the cached objects survive the process
+/// restarts and the repeated imports of the same pack are fast.
+///
+/// The objects are serialized the same way as the redis one, by `ToRedisArgs` and
+/// `FromRedisValue`.
+#[cfg(feature = "sled")]
+pub mod sledstore {
+    use std::{marker::PhantomData, path::Path};
+
+    use super::{CacheError, CacheStats, _Cache};
+    use crate::internal::pack::Hash;
+
+    /// The path of the sled database used by [`_Cache::new`].
+    const SLED_PATH_ENV: &str = "MEGA_PACK_CACHE_SLED_PATH";
+
+    struct Trees {
+        /// offset → hash
+        ioffset: sled::Tree,
+        /// hash → object
+        inner: sled::Tree,
+    }
+
+    /// The cache of which the database failed to open has no trees, so it's always a miss.
+    pub struct ObjectCache<T> {
+        trees: Option<Trees>,
+        stats: CacheStats,
+        t: PhantomData<T>,
+    }
+
+    impl<T> ObjectCache<T> {
+        /// Open (or create) the cache at the given path.
+        pub fn open<P: AsRef<Path>>(path: P) -> sled::Result<Self> {
+            Self::from_db(&sled::open(path)?)
+        }
+
+        /// Create a cache of its own at a unique temporary path, which is removed once it's
+        /// dropped.
+        pub fn temporary() -> sled::Result<Self> {
+            Self::from_db(&sled::Config::new().temporary(true).open()?)
+        }
+
+        /// Build the cache on the trees of an opened sled database.
+        pub fn from_db(db: &sled::Db) -> sled::Result<Self> {
+            Ok(Self::with_trees(Some(Trees {
+                ioffset: db.open_tree("pack_cache_offset")?,
+                inner: db.open_tree("pack_cache_object")?,
+            })))
+        }
+
+        fn with_trees(trees: Option<Trees>) -> Self {
+            Self {
+                trees,
+                stats: CacheStats::default(),
+                t: PhantomData,
+            }
+        }
+
+        fn record(&mut self, obj: Option<T>) -> Option<T> {
+            if obj.is_some() {
+                self.stats.hits += 1;
+            } else {
+                self.stats.misses += 1;
+            }
+            obj
+        }
+    }
+
+    impl<T> _Cache for ObjectCache<T>
+    where
+        T: Clone + redis::ToRedisArgs + redis::FromRedisValue,
+    {
+        type T = T;
+        /// The cache at the path of the `MEGA_PACK_CACHE_SLED_PATH`, which survives the
+        /// restarts, or a temporary one if it's not set, so the caches of the packs decoded at
+        /// the same time don't lock the same database. A failure to open it is only an error.
+        fn new(_size: Option<usize>) -> Self {
+            let opened = match std::env::var_os(SLED_PATH_ENV) {
+                Some(path) => Self::open(path),
+                None => Self::temporary(),
+            };
+            opened.unwrap_or_else(|err| {
+                tracing::error!("Failed to open the sled cache, nothing is cached: {}", err);
+                Self::with_trees(None)
+            })
+        }
+        fn get_hash(&self, offset: usize) -> Option<Hash> {
+            let trees = self.trees.as_ref()?;
+            let h = trees.ioffset.get((offset as u64).to_be_bytes()).ok()??;
+            Some(Hash::new_from_bytes(&h))
+        }
+        fn put(&mut self, offset: usize, hash: Hash, obj: T) -> Result<(), CacheError> {
+            let Some(trees) = self.trees.as_ref() else {
+                let msg = format!("{}: the sled cache is not opened", hash);
+                return Err(CacheError(msg));
+            };
+            let to_error = |e: sled::Error| CacheError(format!("{}: {}", hash, e));
+            trees
+                .ioffset
+                .insert((offset as u64).to_be_bytes(), hash.as_bytes())
+                .map_err(to_error)?;
+            trees
+                .inner
+                .insert(hash.as_bytes(), obj.to_redis_args().concat())
+                .map_err(to_error)?;
+            self.stats.puts += 1;
+            Ok(())
+        }
+
+        fn get(&mut self, offset: usize) -> Option<T> {
+            match self.get_hash(offset) {
+                Some(h) => self.get_by_hash(h),
+                None => self.record(None),
+            }
+        }
+
+        fn get_by_hash(&mut self, h: Hash) -> Option<T> {
+            let obj = self
+                .trees
+                .as_ref()
+                .and_then(|trees| trees.inner.get(h.as_bytes()).ok().flatten())
+                .and_then(|data| T::from_redis_value(&redis::Value::Data(data.to_vec())).ok());
+            self.record(obj)
+        }
+
+        /// Nothing is evicted from the disk, so `evictions` is always 0.
+        fn stats(&self) -> CacheStats {
+            self.stats
+        }
+
+        fn remove(&mut self, offset: usize) {
+            if let Some(h) = self.get_hash(offset) {
+                self.remove_by_hash(h);
+            }
+        }
+
+        fn remove_by_hash(&mut self, h: Hash) {
+            let Some(trees) = self.trees.as_ref() else {
+                return;
+            };
+            let offsets = trees
+                .ioffset
+                .iter()
+                .flatten()
+                .filter(|(_, hash)| hash.as_ref() == h.as_bytes())
+                .map(|(offset, _)| offset);
+            for offset in offsets.collect::<Vec<_>>() {
+                let _ = trees.ioffset.remove(offset);
+            }
+            if let Err(err) = trees.inner.remove(h.as_bytes()) {
+                tracing::warn!("Failed to remove {} from the sled cache: {}", h, err);
+            }
+        }
+
+        fn clear(&mut self) {
+            let Some(trees) = self.trees.as_ref() else {
+                return;
+            };
+            if let Err(err) = trees.ioffset.clear().and_then(|_| trees.inner.clear()) {
+                tracing::warn!("Failed to clear the sled cache: {}", err);
+            }
+        }
+    }
+}
+
+
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
 
     use serde_json::to_vec;
 
-    use super::{ObjectCache, _Cache};
+    use super::{parse_cache_size, CacheStats, ObjectCache, SyncObjectCache, CACHE_SIZE, _Cache};
     use crate::{hash::Hash, internal::object::blob};
     #[test] //TODO: to test
     fn test_cache() {
@@ -168,14 +932,540 @@ mod test {
 
         let data = to_vec("sdfsdfsdf").unwrap();
         let h1 = Hash::new(&data);
-        cache.put(2, h1, Arc::new(blob::Blob { id: h1, data }));
+        cache.put(2, h1, Arc::new(blob::Blob { id: h1, data })).unwrap();
 
         let data = to_vec("a222222222222").unwrap();
         let h1 = Hash::new(&data);
-        cache.put(3, h1, Arc::new(blob::Blob { id: h1, data }));
+        cache.put(3, h1, Arc::new(blob::Blob { id: h1, data })).unwrap();
 
         let data = to_vec("33333333").unwrap();
         let h1 = Hash::new(&data);
-        cache.put(4, h1, Arc::new(blob::Blob { id: h1, data }));
+        cache.put(4, h1, Arc::new(blob::Blob { id: h1, data })).unwrap();
+    }
+
+    #[test]
+    fn test_cache_fallback() {
+        let mut sink = HashMap::new();
+        let mut hashes = Vec::new();
+        for data in ["sdfsdfsdf", "a222222222222", "33333333"] {
+            let data = to_vec(data).unwrap();
+            let h = Hash::new(&data);
+            sink.insert(h, Arc::new(blob::Blob { id: h, data }));
+            hashes.push(h);
+        }
+        let db = sink.clone();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut cache = ObjectCache::new(Some(2)).with_fallback(move |h| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            db.get(&h).cloned()
+        });
+        for (i, h) in hashes.iter().enumerate() {
+            cache.put(i, *h, sink[h].clone()).unwrap();
+        }
+
+        // the first object is evicted and recovered through the fallback
+        let obj = cache.get(0).unwrap();
+        assert_eq!(obj.id, hashes[0]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // re-inserted, so the next lookup hits the LRU
+        assert_eq!(cache.get_by_hash(hashes[0]).unwrap().id, hashes[0]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // an unknown hash is still a miss
+        assert!(cache.get_by_hash(Hash::default()).is_none());
+    }
+
+    #[test]
+    fn test_cache_hash_index() {
+        let mut sink = HashMap::new();
+        let mut hashes = Vec::new();
+        for data in ["sdfsdfsdf", "a222222222222", "33333333"] {
+            let data = to_vec(data).unwrap();
+            let h = Hash::new(&data);
+            sink.insert(h, Arc::new(blob::Blob { id: h, data }));
+            hashes.push(h);
+        }
+        let db = sink.clone();
+        let mut cache = ObjectCache::new(Some(1)).with_fallback(move |h| db.get(&h).cloned());
+        cache.put(0, hashes[0], sink[&hashes[0]].clone()).unwrap();
+        cache.put(5, hashes[0], sink[&hashes[0]].clone()).unwrap();
+        cache.put(1, hashes[1], sink[&hashes[1]].clone()).unwrap();
+
+        // evicted, but the hash is still of the other offset of it
+        cache.remove(5);
+        assert_eq!(cache.get_by_hash(hashes[0]).unwrap().id, hashes[0]);
+        assert_eq!(cache.stats().misses, 1);
+        // the offset of another object
+        cache.put(1, hashes[2], sink[&hashes[2]].clone()).unwrap();
+        assert!(cache.get_by_hash(hashes[1]).is_none());
+        assert_eq!(cache.get_by_hash(hashes[2]).unwrap().id, hashes[2]);
+    }
+
+    #[test]
+    fn test_cache_on_evict() {
+        let store: Arc<Mutex<HashMap<Hash, Arc<blob::Blob>>>> = Arc::default();
+        let written = store.clone();
+        let read = store.clone();
+        let mut cache = ObjectCache::new(Some(2))
+            .with_on_evict(move |h, obj: &Arc<blob::Blob>| {
+                written.lock().unwrap().insert(h, obj.clone());
+            })
+            .with_fallback(move |h| read.lock().unwrap().get(&h).cloned());
+        let mut hashes = Vec::new();
+        for (i, data) in ["sdfsdfsdf", "a222222222222", "33333333"]
+            .iter()
+            .enumerate()
+        {
+            let data = to_vec(data).unwrap();
+            let h = Hash::new(&data);
+            cache
+                .put(i, h, Arc::new(blob::Blob { id: h, data }))
+                .unwrap();
+            hashes.push(h);
+        }
+        // only the first object is evicted, and written back
+        assert_eq!(
+            store.lock().unwrap().keys().collect::<Vec<_>>(),
+            [&hashes[0]]
+        );
+        assert_eq!(store.lock().unwrap()[&hashes[0]].id, hashes[0]);
+        // re-put of the same key is not an eviction
+        let obj = cache.get(2).unwrap();
+        cache.put(2, hashes[2], obj).unwrap();
+        assert_eq!(store.lock().unwrap().len(), 1);
+
+        // the offset of the evicted object is still known, so it's loaded back, which evicts
+        // the least recently used one
+        assert_eq!(cache.get_hash(0), Some(hashes[0]));
+        assert_eq!(cache.get(0).unwrap().id, hashes[0]);
+        assert!(store.lock().unwrap().contains_key(&hashes[1]));
+        assert_eq!(cache.stats().evictions, 2);
+    }
+
+    #[test]
+    fn test_cache_remove() {
+        let mut cache = ObjectCache::new(None);
+        let mut hashes = Vec::new();
+        for (i, data) in ["sdfsdfsdf", "a222222222222", "33333333"]
+            .iter()
+            .enumerate()
+        {
+            let data = to_vec(data).unwrap();
+            let h = Hash::new(&data);
+            cache
+                .put(i, h, Arc::new(blob::Blob { id: h, data }))
+                .unwrap();
+            hashes.push(h);
+        }
+        // the same object of another offset
+        let obj = cache.get(2).unwrap();
+        cache.put(5, hashes[2], obj).unwrap();
+
+        cache.remove(0);
+        assert!(cache.get(0).is_none());
+        assert!(cache.get_by_hash(hashes[0]).is_none());
+        assert_eq!(cache.get_hash(0), None);
+        cache.remove_by_hash(hashes[2]);
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(5).is_none());
+        assert!(cache.get_by_hash(hashes[2]).is_none());
+        assert_eq!(cache.get_hash(5), None);
+        // the others are kept
+        assert_eq!(cache.get(1).unwrap().id, hashes[1]);
+        assert_eq!(cache.stats().evictions, 0);
+
+        cache.clear();
+        assert!(cache.get(1).is_none());
+        assert!(cache.get_by_hash(hashes[1]).is_none());
+        assert_eq!(cache.get_hash(1), None);
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let mut cache = ObjectCache::new(Some(2));
+        let mut hashes = Vec::new();
+        for (i, data) in ["sdfsdfsdf", "a222222222222", "33333333"].iter().enumerate() {
+            let data = to_vec(data).unwrap();
+            let h = Hash::new(&data);
+            cache.put(i, h, Arc::new(blob::Blob { id: h, data })).unwrap();
+            hashes.push(h);
+        }
+        // offset 0 is evicted by the third put
+        assert!(cache.get(0).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get_by_hash(hashes[2]).is_some());
+        assert!(cache.get(9).is_none());
+        // re-put of the same key is not an eviction
+        let obj = cache.get(2).unwrap();
+        cache.put(2, hashes[2], obj).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(
+            stats,
+            CacheStats {
+                hits: 3,
+                misses: 2,
+                evictions: 1,
+                puts: 4,
+            }
+        );
+        assert_eq!(stats.miss_rate(), 0.4);
+    }
+
+    #[test]
+    fn test_sync_cache_threads() {
+        const THREADS: usize = 8;
+        const OBJECTS: usize = 2000;
+        let cache = Arc::new(SyncObjectCache::new(Some(THREADS * OBJECTS * 2)));
+        let hash = |offset: usize| Hash::new(offset.to_string().as_bytes());
+        let (done, wait) = mpsc::channel();
+        let threads: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let cache = cache.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    for i in 0..OBJECTS {
+                        let offset = i * THREADS + t;
+                        cache.put(offset, hash(offset), offset).unwrap();
+                        assert_eq!(cache.get(offset), Some(offset));
+                        // the objects of the other threads meanwhile
+                        let other = i * THREADS + (t + 1) % THREADS;
+                        if let Some(obj) = cache.get_by_hash(hash(other)) {
+                            assert_eq!(obj, other);
+                        }
+                    }
+                    done.send(()).unwrap();
+                })
+            })
+            .collect();
+        for _ in 0..THREADS {
+            wait.recv_timeout(Duration::from_secs(60))
+                .expect("the threads are deadlocked");
+        }
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        // nothing is lost
+        for offset in 0..THREADS * OBJECTS {
+            assert_eq!(cache.get_hash(offset), Some(hash(offset)));
+            assert_eq!(cache.get(offset), Some(offset));
+            assert_eq!(cache.get_by_hash(hash(offset)), Some(offset));
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.puts, THREADS * OBJECTS);
+        assert_eq!(stats.evictions, 0);
+        assert_eq!(cache.get(THREADS * OBJECTS), None);
+        assert_eq!(cache.stats().misses, stats.misses + 1);
+    }
+
+    #[test]
+    fn test_parse_cache_size() {
+        assert_eq!(parse_cache_size(None), CACHE_SIZE);
+        assert_eq!(parse_cache_size(Some("300".to_owned())).get(), 300);
+        // zero or unparseable values fall back to the default
+        assert_eq!(parse_cache_size(Some("0".to_owned())), CACHE_SIZE);
+        assert_eq!(parse_cache_size(Some("abc".to_owned())), CACHE_SIZE);
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_cache() {
+        use crate::internal::pack::cache::sledstore;
+        use redis::{ErrorKind, FromRedisValue, RedisError, ToRedisArgs};
+
+        #[derive(Clone)]
+        struct CachedBlob(blob::Blob);
+        impl ToRedisArgs for CachedBlob {
+            fn write_redis_args<W>(&self, out: &mut W)
+            where
+                W: ?Sized + redis::RedisWrite,
+            {
+                out.write_arg(&self.0.data)
+            }
+        }
+        impl FromRedisValue for CachedBlob {
+            fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
+                match v {
+                    redis::Value::Data(data) => Ok(CachedBlob(blob::Blob {
+                        id: Hash::new(data),
+                        data: data.clone(),
+                    })),
+                    _ => Err(RedisError::from((ErrorKind::TypeError, "not a blob"))),
+                }
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("mega_sled_cache_{}", std::process::id()));
+        let data = to_vec("sdfsdfsdf").unwrap();
+        let h = Hash::new(&data);
+        {
+            let mut cache = sledstore::ObjectCache::open(&path).unwrap();
+            cache.put(2, h, CachedBlob(blob::Blob { id: h, data: data.clone() })).unwrap();
+            assert_eq!(cache.get(2).unwrap().0, blob::Blob { id: h, data: data.clone() });
+        }
+        // reopen the cache, the objects survive
+        let mut cache = sledstore::ObjectCache::<CachedBlob>::open(&path).unwrap();
+        assert_eq!(cache.get_hash(2), Some(h));
+        assert_eq!(cache.get_by_hash(h).unwrap().0.data, data);
+        assert!(cache.get(3).is_none());
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 1);
+        drop(cache);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_caches_of_their_own() {
+        use super::sledstore;
+        let mut a = sledstore::ObjectCache::<String>::new(None);
+        let mut b = sledstore::ObjectCache::<String>::new(None);
+        let hash = Hash::new(b"sled");
+        a.put(1, hash, "a".to_string()).unwrap();
+        assert_eq!(a.get(1), Some("a".to_string()));
+        assert_eq!(b.get(1), None);
+        b.put(1, hash, "b".to_string()).unwrap();
+        assert_eq!(a.get_by_hash(hash), Some("a".to_string()));
+    }
+
+    #[test]
+    fn test_cache_batch() {
+        let mut cache = ObjectCache::new(None);
+        let items: Vec<_> = ["sdfsdfsdf", "a222222222222", "33333333"]
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                let data = to_vec(data).unwrap();
+                let h = Hash::new(&data);
+                (i * 10, h, Arc::new(blob::Blob { id: h, data }))
+            })
+            .collect();
+        cache.put_batch(items.clone()).unwrap();
+
+        let objs = cache.get_many(&[20, 5, 0]);
+        assert_eq!(objs[0].as_ref().unwrap().id, items[2].1);
+        assert!(objs[1].is_none());
+        assert_eq!(objs[2].as_ref().unwrap().id, items[0].1);
+        // the offset → hash map is consistent with the batch
+        for (offset, hash, _) in &items {
+            assert_eq!(cache.get_hash(*offset), Some(*hash));
+        }
+        assert_eq!(cache.stats().puts, 3);
+    }
+
+    /// `put_batch` of 10k objects is one round trip, so it beats the per-object `put`.
+    #[test]
+    #[ignore = "need_redis_environment"]
+    fn test_redis_batch_speedup() {
+        use super::kvstore;
+        use std::time::Instant;
+
+        std::env::set_var("REDIS_CONFIG", "redis://127.0.0.1:6379");
+        let items: Vec<(usize, Hash, Vec<u8>)> = (0..10_000usize)
+            .map(|i| {
+                let data = i.to_be_bytes().to_vec();
+                (i, Hash::new(&data), data)
+            })
+            .collect();
+
+        let mut cache: kvstore::ObjectCache<Vec<u8>> = kvstore::ObjectCache::new(None);
+        let start = Instant::now();
+        for (offset, hash, obj) in items.clone() {
+            cache.put(offset, hash, obj).unwrap();
+        }
+        let single = start.elapsed();
+
+        let mut cache: kvstore::ObjectCache<Vec<u8>> = kvstore::ObjectCache::new(None);
+        let start = Instant::now();
+        cache.put_batch(items.clone()).unwrap();
+        let batch = start.elapsed();
+        assert!(batch < single, "put: {:?}, put_batch: {:?}", single, batch);
+
+        let offsets: Vec<usize> = items.iter().map(|(o, _, _)| *o).collect();
+        let objs = cache.get_many(&offsets);
+        assert!(objs.iter().zip(items).all(|(o, (_, _, data))| o.as_ref() == Some(&data)));
+    }
+
+    #[test]
+    fn test_cache_sha256_key() {
+        use crate::hash::HashKind;
+
+        let mut cache = ObjectCache::new(None);
+        let data = to_vec("sdfsdfsdf").unwrap();
+        let h1 = Hash::new_with_kind(HashKind::Sha1, &data);
+        let h256 = Hash::new_with_kind(HashKind::Sha256, &data);
+        cache.put(1, h1, Arc::new(blob::Blob { id: h1, data: data.clone() })).unwrap();
+        cache.put(2, h256, Arc::new(blob::Blob { id: h256, data })).unwrap();
+        assert_eq!(cache.get_by_hash(h256).unwrap().id, h256);
+        assert_eq!(cache.get_hash(1), Some(h1));
+        assert_eq!(cache.get_hash(2), Some(h256));
+    }
+
+    #[test]
+    fn test_kvstore_cache_in_memory() {
+        use super::kvstore;
+        use kvcache::connector::fake::FakeKVStore;
+
+        let mut cache: kvstore::ObjectCache<Vec<u8>, FakeKVStore<Hash, Vec<u8>>> =
+            kvstore::ObjectCache::new(None);
+        let data = to_vec("sdfsdfsdf").unwrap();
+        let h1 = Hash::new(&data);
+        cache.put(2, h1, data.clone()).unwrap();
+        assert_eq!(cache.get(2), Some(data.clone()));
+        assert_eq!(cache.get_by_hash(h1), Some(data.clone()));
+        assert_eq!(cache.get_hash(2), Some(h1));
+        assert_eq!(cache.get(3), None);
+        assert_eq!(cache.get_by_hash(Hash::new(&to_vec("a222").unwrap())), None);
+
+        let data2 = to_vec("a222222222222").unwrap();
+        let h2 = Hash::new(&data2);
+        cache.put_batch(vec![(3, h2, data2.clone())]).unwrap();
+        assert_eq!(cache.get_many(&[3, 4, 2]), vec![Some(data2), None, Some(data)]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.puts), (4, 3, 2));
+    }
+
+    #[test]
+    fn test_tiered_cache() {
+        use super::tiered::TieredCache;
+        use kvcache::connector::fake::FakeKVStore;
+
+        let mut cache: TieredCache<Vec<u8>, FakeKVStore<Hash, Vec<u8>>> = TieredCache::new(Some(1));
+        let objects: Vec<(Hash, Vec<u8>)> = ["sdfsdfsdf", "a222222222222", "33333333"]
+            .iter()
+            .map(|data| {
+                let data = to_vec(data).unwrap();
+                (Hash::new(&data), data)
+            })
+            .collect();
+        for (i, (h, data)) in objects.iter().enumerate() {
+            cache.put(i, *h, data.clone()).unwrap();
+        }
+        // only the last one is in the memory, the evicted ones are got from the kv store
+        assert_eq!(cache.get(2), Some(objects[2].1.clone()));
+        assert_eq!(cache.get(0), Some(objects[0].1.clone()));
+        assert_eq!(cache.get_by_hash(objects[1].0), Some(objects[1].1.clone()));
+        assert_eq!(cache.get_hash(1), Some(objects[1].0));
+        assert_eq!(cache.get(3), None);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.puts), (3, 1, 3));
+
+        // promoted to the memory, so it's got without another eviction
+        let evictions = stats.evictions;
+        assert_eq!(cache.get_by_hash(objects[1].0), Some(objects[1].1.clone()));
+        assert_eq!(cache.stats().evictions, evictions);
+        assert_eq!(cache.get(2), Some(objects[2].1.clone()));
+        assert_eq!(cache.stats().evictions, evictions + 1);
+    }
+
+    #[test]
+    fn test_kvstore_cache_ttl() {
+        use super::kvstore::{self, parse_ttl};
+        use kvcache::connector::fake::FakeKVStore;
+
+        let mut cache: kvstore::ObjectCache<Vec<u8>, FakeKVStore<Hash, Vec<u8>>> =
+            kvstore::ObjectCache::new(None).with_ttl(Some(Duration::from_millis(50)));
+        let data = to_vec("sdfsdfsdf").unwrap();
+        let h1 = Hash::new(&data);
+        cache.put(2, h1, data.clone()).unwrap();
+        let data2 = to_vec("a222222222222").unwrap();
+        let h2 = Hash::new(&data2);
+        cache.put_batch(vec![(3, h2, data2.clone())]).unwrap();
+        assert_eq!(cache.get(2), Some(data));
+        assert_eq!(cache.get_by_hash(h2), Some(data2));
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(3), None);
+        // the offsets are still known
+        assert_eq!(cache.get_hash(2), Some(h1));
+
+        assert_eq!(parse_ttl(None), None);
+        assert_eq!(
+            parse_ttl(Some("60".to_owned())),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(parse_ttl(Some("0".to_owned())), None);
+        assert_eq!(parse_ttl(Some("abc".to_owned())), None);
+    }
+
+    #[test]
+    fn test_kvstore_cache_remove() {
+        use super::kvstore;
+        use super::tiered::TieredCache;
+        use kvcache::connector::fake::FakeKVStore;
+
+        let mut cache: kvstore::ObjectCache<Vec<u8>, FakeKVStore<Hash, Vec<u8>>> =
+            kvstore::ObjectCache::new(None);
+        let objects: Vec<(Hash, Vec<u8>)> = ["sdfsdfsdf", "a222222222222", "33333333"]
+            .iter()
+            .map(|data| {
+                let data = to_vec(data).unwrap();
+                (Hash::new(&data), data)
+            })
+            .collect();
+        for (i, (h, data)) in objects.iter().enumerate() {
+            cache.put(i, *h, data.clone()).unwrap();
+        }
+        cache.remove(0);
+        assert_eq!(cache.get(0), None);
+        assert_eq!(cache.get_by_hash(objects[0].0), None);
+        assert_eq!(cache.get_hash(0), None);
+        cache.remove_by_hash(objects[1].0);
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get_hash(1), None);
+        assert_eq!(cache.get(2), Some(objects[2].1.clone()));
+        cache.clear();
+        assert_eq!(cache.get_by_hash(objects[2].0), None);
+
+        // removed from both of the tiers
+        let mut cache: TieredCache<Vec<u8>, FakeKVStore<Hash, Vec<u8>>> = TieredCache::new(Some(1));
+        for (i, (h, data)) in objects.iter().enumerate() {
+            cache.put(i, *h, data.clone()).unwrap();
+        }
+        cache.remove(2);
+        cache.remove_by_hash(objects[0].0);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(0), None);
+        assert_eq!(cache.get(1), Some(objects[1].1.clone()));
+        cache.clear();
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get_hash(1), None);
+    }
+
+    /// A kv store which is down.
+    struct FailingStore<K, V>(std::marker::PhantomData<(K, V)>);
+
+    impl<K, V> kvcache::connector::Connector for FailingStore<K, V> {
+        type K = K;
+        type V = V;
+        fn get(&self, _key: K) -> Option<V> {
+            None
+        }
+        fn set(&self, _key: K, _v: V) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("connection refused"))
+        }
+        fn delete(&self, _key: K) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("connection refused"))
+        }
+        fn new() -> Self {
+            FailingStore(std::marker::PhantomData)
+        }
+    }
+
+    #[test]
+    fn test_kvstore_cache_failing_store() {
+        use super::kvstore;
+
+        let mut cache: kvstore::ObjectCache<Vec<u8>, FailingStore<Hash, Vec<u8>>> =
+            kvstore::ObjectCache::new(None);
+        let data = to_vec("sdfsdfsdf").unwrap();
+        let h1 = Hash::new(&data);
+        let err = cache.put(2, h1, data.clone()).unwrap_err();
+        assert!(err.to_string().contains("connection refused"));
+        assert!(cache.put_batch(vec![(3, h1, data)]).is_err());
+        // the offset is still known, but the object is a miss
+        assert_eq!(cache.get_hash(2), Some(h1));
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.stats().puts, 0);
     }
 }
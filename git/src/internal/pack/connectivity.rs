@@ -0,0 +1,198 @@
+//! The connectivity check of a pushed pack: every object referenced by the objects of the pack,
+//! i.e. the trees and the parents of the commits, the items of the trees and the objects of the
+//! tags, is either in the pack or saved by the earlier pushes. A broken pack is rejected before
+//! any object of it is saved, otherwise the storage would have the objects of which a clone
+//! fails.
+//!
+//! The pack is decoded by [`PackStream`] with the storage, so the bases of a thin pack are
+//! resolved the same as by the decoding which saves it.
+
+use std::collections::HashSet;
+use std::io::Read;
+use std::sync::Arc;
+
+use database::driver::ObjectStorage;
+
+use super::stream::PackStream;
+use crate::hash::Hash;
+use crate::internal::object::commit::Commit;
+use crate::internal::object::tag::Tag;
+use crate::internal::object::tree::{Tree, TreeItemMode};
+use crate::internal::object::ObjectT;
+use crate::internal::ObjectType;
+
+/// The objects referenced by the pack, or the `tips` of the refs to update, which are neither in
+/// the pack nor in the storage, sorted. The commits of the submodules are not followed.
+pub async fn missing_objects<R: Read>(
+    pack: R,
+    storage: Arc<dyn ObjectStorage>,
+    tips: &[Hash],
+) -> Result<Vec<Hash>, anyhow::Error> {
+    let mut stream = PackStream::new(pack)?;
+    stream.set_storage(Some(storage.clone()));
+
+    let mut in_pack = HashSet::new();
+    let mut referenced: HashSet<Hash> = tips.iter().copied().collect();
+    while let Some(object) = stream.next_object().await? {
+        in_pack.insert(object.hash);
+        match object.object_type {
+            ObjectType::Commit => {
+                let commit = Commit::new_from_data(object.data.clone());
+                referenced.insert(commit.tree_id);
+                referenced.extend(commit.parent_tree_ids);
+            }
+            ObjectType::Tree => referenced.extend(
+                Tree::new_from_data(object.data.clone())
+                    .tree_items
+                    .into_iter()
+                    .filter(|item| item.mode != TreeItemMode::Commit)
+                    .map(|item| item.id),
+            ),
+            ObjectType::Tag => {
+                referenced.insert(Tag::new_from_data(object.data.clone()).object_hash);
+            }
+            _ => {}
+        }
+    }
+
+    let outside: Vec<String> = referenced
+        .difference(&in_pack)
+        .map(|id| id.to_plain_str())
+        .collect();
+    if outside.is_empty() {
+        return Ok(vec![]);
+    }
+    let saved = storage
+        .get_existing_obj_ids(outside.clone())
+        .await
+        .map_err(|e| {
+            e.error
+                .unwrap_or_else(|| anyhow::anyhow!("failed to check the objects"))
+        })?;
+    let mut missing: Vec<Hash> = outside
+        .into_iter()
+        .filter(|id| !saved.contains(id))
+        .map(|id| Hash::new_from_str(&id))
+        .collect();
+    missing.sort();
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use super::missing_objects;
+    use crate::hash::Hash;
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::ObjectT;
+    use crate::internal::pack::encode::{write_pack, DEFAULT_DEPTH, DEFAULT_WINDOW};
+    use crate::internal::pack::preload::{decode_load, PackPreload};
+    use crate::internal::pack::tests::{thin_pack_base, MemoryStorage, THIN_PACK};
+    use crate::protocol::{PackProtocol, Protocol};
+    use common::utils::ZERO_ID;
+
+    const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+    const HEAD: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+
+    #[tokio::test]
+    async fn test_missing_objects() {
+        // all the objects of a repository
+        let storage = Arc::new(MemoryStorage::default());
+        let tips = [Hash::new_from_str(HEAD)];
+        let missing = missing_objects(File::open(PACK).unwrap(), storage.clone(), &tips)
+            .await
+            .unwrap();
+        assert!(missing.is_empty());
+
+        // the tip is missing, e.g. of a ref to update but not pushed
+        let other = [Hash::new(b"not pushed")];
+        let missing = missing_objects(File::open(PACK).unwrap(), storage.clone(), &other)
+            .await
+            .unwrap();
+        assert_eq!(missing, other);
+
+        // a commit of which the tree and the parent are not pushed, but saved earlier
+        let data = std::fs::read(PACK).unwrap();
+        let preload = PackPreload::new(BufReader::new(Cursor::new(data)));
+        decode_load(preload, storage.clone()).await.unwrap();
+        let mut commit = Commit::new_from_data(storage.objects.lock().unwrap()[HEAD].1.clone());
+        commit.set_hash(tips[0]);
+        let (tree, parents) = (commit.tree_id, commit.parent_tree_ids.clone());
+        let pack = write_pack(vec![Arc::new(commit)], DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap();
+        let missing = missing_objects(Cursor::new(&pack), storage, &tips)
+            .await
+            .unwrap();
+        assert!(missing.is_empty());
+
+        // and not saved
+        let missing = missing_objects(
+            Cursor::new(&pack),
+            Arc::new(MemoryStorage::default()),
+            &tips,
+        )
+        .await
+        .unwrap();
+        let mut expected = parents;
+        expected.push(tree);
+        expected.sort();
+        assert_eq!(missing, expected);
+    }
+
+    #[tokio::test]
+    async fn test_missing_objects_thin_pack() {
+        // the base of the delta is resolved by the storage
+        let storage = Arc::new(MemoryStorage::with_objects(vec![thin_pack_base()]));
+        let missing = missing_objects(File::open(THIN_PACK).unwrap(), storage, &[])
+            .await
+            .unwrap();
+        // the parent commit only, which is not pushed again
+        assert_eq!(
+            missing,
+            [Hash::new_from_str(
+                "5894bbd662e9db8444482fbf251a618689121cf9"
+            )]
+        );
+
+        // or the pack is broken
+        let storage = Arc::new(MemoryStorage::default());
+        assert!(
+            missing_objects(File::open(THIN_PACK).unwrap(), storage, &[])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_rejected() {
+        let storage = Arc::new(MemoryStorage::default());
+        let data = std::fs::read(PACK).unwrap();
+        let preload = PackPreload::new(BufReader::new(Cursor::new(data)));
+        decode_load(preload, storage.clone()).await.unwrap();
+        let mut commit = Commit::new_from_data(storage.objects.lock().unwrap()[HEAD].1.clone());
+        commit.set_hash(Hash::new_from_str(HEAD));
+        let pack = write_pack(vec![Arc::new(commit)], DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap();
+
+        // the commit only is pushed to a new repository
+        let storage = Arc::new(MemoryStorage::default());
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/new"),
+            storage.clone(),
+            Protocol::Http,
+        );
+        let line = format!("{} {} refs/heads/main\0report-status\n", ZERO_ID, HEAD);
+        let mut body = format!("{:04x}{}0000", line.len() + 4, line).into_bytes();
+        body.extend(pack);
+        let pack = protocol.git_receive_pack(Bytes::from(body)).await.unwrap();
+        let report = protocol.git_receive_pack(pack).await.unwrap();
+        let report = String::from_utf8_lossy(&report);
+        assert!(report.contains("unpack ok\n"));
+        assert!(report.contains("ng refs/heads/main missing necessary objects"));
+        assert!(storage.objects.lock().unwrap().is_empty());
+    }
+}
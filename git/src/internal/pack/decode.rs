@@ -1,11 +1,8 @@
 use std::io::{self, BufRead};
 use std::io::{Read, Seek};
 
-use sha1::digest::core_api::CoreWrapper;
-use sha1::Digest;
-use sha1::Sha1;
-
 use super::{iterator::EntriesIter, Pack};
+use crate::hash::backend::{Sha1, Sha1Backend};
 use crate::hash::Hash;
 use crate::{errors::GitError, utils};
 #[allow(unused)]
@@ -39,7 +36,7 @@ impl Pack {
         let mut iterator = EntriesIter::new(&mut reader, pack.number_of_objects as u32);
         for _ in 0..pack.number_of_objects {
             let obj = iterator.next_obj().await?;
-            println!("{}", obj);
+            tracing::debug!("{}", obj);
         }
         drop(iterator);
 
@@ -85,8 +82,9 @@ impl Pack {
 /// A BufReader for hash count during the pack data stream "read".
 pub struct HashCounter<R> {
     inner: R,
-    hash: CoreWrapper<sha1::Sha1Core>,
+    hash: Sha1,
     count_hash: bool,
+    position: usize,
 }
 impl<R> HashCounter<R>
 where
@@ -95,13 +93,18 @@ where
     pub fn new(inner: R, count_hash: bool) -> Self {
         Self {
             inner,
-            hash: Sha1::new(),
+            hash: Sha1::default(),
             count_hash,
+            position: 0,
         }
     }
+    /// The bytes read from the start, i.e. the offset of the next entry in the pack.
+    pub fn position(&self) -> usize {
+        self.position
+    }
     pub fn final_hash(&self) -> Hash {
-        let re: [u8; 20] = self.hash.clone().finalize().into();
-        Hash(re)
+        let re: [u8; 20] = self.hash.clone().finalize();
+        Hash::Sha1(re)
     }
 }
 impl<R> BufRead for HashCounter<R>
@@ -118,6 +121,7 @@ where
             self.hash.update(&buffer[..amt]);
         }
         self.inner.consume(amt);
+        self.position += amt;
     }
 }
 impl<R> Read for HashCounter<R>
@@ -129,6 +133,7 @@ where
         if self.count_hash {
             self.hash.update(&buf[..o]);
         }
+        self.position += o;
         Ok(o)
     }
 }
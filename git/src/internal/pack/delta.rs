@@ -1,8 +1,8 @@
-use sha1::digest::core_api::CoreWrapper;
-use sha1::{Digest, Sha1};
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read};
 use std::sync::Arc;
 
+use crate::hash::backend::{Sha1, Sha1Backend};
 use crate::internal::object::ObjectT;
 use crate::{errors::GitError, utils};
 
@@ -10,6 +10,11 @@ const COPY_INSTRUCTION_FLAG: u8 = 1 << 7;
 const COPY_OFFSET_BYTES: u8 = 4;
 const COPY_SIZE_BYTES: u8 = 3;
 const COPY_ZERO_SIZE: usize = 0x10000;
+const DATA_INSTRUCTION_MAX: usize = 0x7f;
+/// The length of the blocks of the base indexed by [`delta`] to find the copies.
+const DELTA_BLOCK_SIZE: usize = 16;
+/// The offsets kept of a block repeated in the base.
+const DELTA_BUCKET_SIZE: usize = 8;
 
 /// The Delta Reader to deal with the Delta Object.
 ///
@@ -19,17 +24,17 @@ const COPY_ZERO_SIZE: usize = 0x10000;
 pub struct DeltaReader {
     result: BufReader<Cursor<Vec<u8>>>,
     len: usize,
-    pub hash: CoreWrapper<sha1::Sha1Core>,
+    pub hash: Sha1,
 }
 impl DeltaReader {
     pub async fn new(reader: &mut impl Read, base_object: Arc<dyn ObjectT>) -> Self {
         let copy_obj = base_object.clone();
         let buffer = AsyncDeltaBuffer::new(reader, base_object).await;
 
-        let mut h = Sha1::new();
+        let mut h = Sha1::default();
         h.update(copy_obj.get_type().to_bytes());
         h.update(b" ");
-        h.update(buffer.result_size.to_string());
+        h.update(buffer.result_size.to_string().as_bytes());
         h.update(b"\0");
 
         //buffer.read_to_end(&mut result).await.unwrap();
@@ -154,11 +159,24 @@ async fn process_delta(
     }
 }
 
-pub fn undelta(mut stream: &mut impl Read, base_info: &Vec<u8>) -> Vec<u8> {
+pub fn undelta(stream: &mut impl Read, base_info: &Vec<u8>) -> Vec<u8> {
+    try_undelta(stream, base_info).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Apply the delta to the base, the bad delta or the wrong base is an error rather than a
+/// panic, e.g. of a thin pack of which the base is from the storage.
+pub fn try_undelta(mut stream: &mut impl Read, base_info: &[u8]) -> Result<Vec<u8>, GitError> {
+    let delta_err = |e: std::io::Error| GitError::DeltaObjectError(format!("Invalid delta: {}", e));
     // Read the bash object size & Result Size
-    let base_size = utils::read_size_encoding(&mut stream).unwrap();
-    assert!(base_info.len() == base_size);
-    let result_size = utils::read_size_encoding(&mut stream).unwrap();
+    let base_size = utils::read_size_encoding(&mut stream).map_err(delta_err)?;
+    if base_info.len() != base_size {
+        return Err(GitError::DeltaObjectError(format!(
+            "The base is of {} bytes rather than {}",
+            base_info.len(),
+            base_size
+        )));
+    }
+    let result_size = utils::read_size_encoding(&mut stream).map_err(delta_err)?;
     let mut buffer = Vec::with_capacity(result_size);
     loop {
         // Check if the stream has ended, meaning the new object is done
@@ -166,10 +184,10 @@ pub fn undelta(mut stream: &mut impl Read, base_info: &Vec<u8>) -> Vec<u8> {
             Ok([instruction]) => instruction,
             Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
             Err(err) => {
-                panic!(
-                    "{}",
-                    GitError::DeltaObjectError(format!("Wrong instruction in delta :{}", err))
-                );
+                return Err(GitError::DeltaObjectError(format!(
+                    "Wrong instruction in delta :{}",
+                    err
+                )));
             }
         };
 
@@ -177,42 +195,184 @@ pub fn undelta(mut stream: &mut impl Read, base_info: &Vec<u8>) -> Vec<u8> {
             // Data instruction; the instruction byte specifies the number of data bytes
             if instruction == 0 {
                 // Appending 0 bytes doesn't make sense, so git disallows it
-                panic!(
-                    "{}",
-                    GitError::DeltaObjectError(String::from("Invalid data instruction"))
-                );
+                return Err(GitError::DeltaObjectError(String::from(
+                    "Invalid data instruction",
+                )));
             }
 
             // Append the provided bytes
             let mut data = vec![0; instruction as usize];
-            stream.read_exact(&mut data).unwrap();
+            stream.read_exact(&mut data).map_err(delta_err)?;
             buffer.extend_from_slice(&data);
-        // result.extend_from_slice(&data);
         } else {
             // Copy instruction
             let mut nonzero_bytes = instruction;
             let offset =
                 utils::read_partial_int(&mut stream, COPY_OFFSET_BYTES, &mut nonzero_bytes)
-                    .unwrap();
+                    .map_err(delta_err)?;
             let mut size =
-                utils::read_partial_int(&mut stream, COPY_SIZE_BYTES, &mut nonzero_bytes).unwrap();
+                utils::read_partial_int(&mut stream, COPY_SIZE_BYTES, &mut nonzero_bytes)
+                    .map_err(delta_err)?;
             if size == 0 {
                 // Copying 0 bytes doesn't make sense, so git assumes a different size
                 size = COPY_ZERO_SIZE;
             }
             // Copy bytes from the base object
-            let base_data = base_info
-                .get(offset..(offset + size))
-                .ok_or_else(|| GitError::DeltaObjectError("Invalid copy instruction".to_string()));
+            let data = base_info.get(offset..(offset + size)).ok_or_else(|| {
+                GitError::DeltaObjectError("Invalid copy instruction".to_string())
+            })?;
+            buffer.extend_from_slice(data);
+        }
+    }
+    if buffer.len() != result_size {
+        return Err(GitError::DeltaObjectError(format!(
+            "The result is of {} bytes rather than {}",
+            buffer.len(),
+            result_size
+        )));
+    }
+    Ok(buffer)
+}
 
-            match base_data {
-                Ok(data) => buffer.extend_from_slice(data),
-                Err(e) => panic!("{}", e),
+/// Compute the delta of the `target` from the `base`, which is applied by [`try_undelta`].
+///
+/// The blocks of the base are indexed by the content and looked up at each byte of the target,
+/// like the `diff-delta` of git, so it takes the time linear in the sizes even if the two are
+/// not alike at all, unlike the diff of the lines.
+pub fn delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut result = utils::write_size_encoding(base.len());
+    result.append(&mut utils::write_size_encoding(target.len()));
+
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    let last_block = base.len().saturating_sub(DELTA_BLOCK_SIZE - 1);
+    for offset in (0..last_block).step_by(DELTA_BLOCK_SIZE) {
+        let bucket = index
+            .entry(&base[offset..offset + DELTA_BLOCK_SIZE])
+            .or_default();
+        if bucket.len() < DELTA_BUCKET_SIZE {
+            bucket.push(offset);
+        }
+    }
+
+    // the bytes from `data_start` are not copied yet
+    let mut data_start = 0;
+    let mut i = 0;
+    while i + DELTA_BLOCK_SIZE <= target.len() {
+        // the offset in the base, the start in the target and the length of the longest copy
+        let mut best = (0, 0, 0);
+        if let Some(bucket) = index.get(&target[i..i + DELTA_BLOCK_SIZE]) {
+            for &offset in bucket {
+                let mut back = 0;
+                while back < i - data_start
+                    && back < offset
+                    && base[offset - back - 1] == target[i - back - 1]
+                {
+                    back += 1;
+                }
+                let forward = base[offset..]
+                    .iter()
+                    .zip(&target[i..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if back + forward > best.2 {
+                    best = (offset - back, i - back, back + forward);
+                }
+            }
+        }
+        let (offset, start, len) = best;
+        if len == 0 {
+            i += 1;
+            continue;
+        }
+        push_data(&mut result, &target[data_start..start]);
+        push_copy(&mut result, offset, len);
+        i = start + len;
+        data_start = i;
+    }
+    push_data(&mut result, &target[data_start..]);
+    result
+}
+
+fn push_data(result: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(DATA_INSTRUCTION_MAX) {
+        result.push(chunk.len() as u8);
+        result.extend_from_slice(chunk);
+    }
+}
+
+fn push_copy(result: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(COPY_ZERO_SIZE);
+        // the size of 0x10000 is of no bytes
+        let encoded_size = if size == COPY_ZERO_SIZE { 0 } else { size };
+        let mut instruction = COPY_INSTRUCTION_FLAG;
+        let mut bytes = Vec::with_capacity(7);
+        for i in 0..COPY_OFFSET_BYTES {
+            let byte = (offset >> (i * 8)) as u8;
+            if byte != 0 {
+                instruction |= 1 << i;
+                bytes.push(byte);
+            }
+        }
+        for i in 0..COPY_SIZE_BYTES {
+            let byte = (encoded_size >> (i * 8)) as u8;
+            if byte != 0 {
+                instruction |= 1 << (COPY_OFFSET_BYTES + i);
+                bytes.push(byte);
             }
         }
+        result.push(instruction);
+        result.append(&mut bytes);
+        offset += size;
+        len -= size;
     }
-    assert!(buffer.len() == result_size);
-    buffer
 }
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::io::Cursor;
+
+    use super::{delta, try_undelta};
+
+    /// Bytes of a simple LCG, which are not alike for the different seeds.
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let d = delta(base, target);
+        assert_eq!(try_undelta(&mut Cursor::new(&d), base).unwrap(), target);
+        d
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = random_bytes(1, 200_000);
+
+        // inserted at the start, removed in the middle and appended at the end, of the copies
+        // longer than 0x10000 too
+        let mut target = b"inserted".to_vec();
+        target.extend_from_slice(&base[..90_000]);
+        target.extend_from_slice(&base[100_000..]);
+        target.extend_from_slice(&random_bytes(2, 300));
+        let d = round_trip(&base, &target);
+        assert!(d.len() < 400, "{}", d.len());
+
+        // not alike at all
+        let other = random_bytes(3, 50_000);
+        let d = round_trip(&base, &other);
+        assert!(d.len() > other.len());
+
+        round_trip(&base, &[]);
+        round_trip(&[], &other[..1000]);
+        round_trip(&base[..10], &base[..15]);
+    }
+}
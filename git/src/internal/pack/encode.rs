@@ -1,94 +1,216 @@
-use entity::git_obj;
-use sha1::{Digest, Sha1};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
 use std::io::{Cursor, Write};
 use std::sync::Arc;
 
-use crate::internal::diff::DeltaDiff;
+use crate::hash::backend::{Sha1, Sha1Backend};
+use crate::hash::Hash;
 use crate::internal::object::ObjectT;
 use crate::internal::zlib::stream::deflate::Write as Writer;
+use crate::internal::ObjectType;
+use crate::utils;
 
 use std::io::Error;
 
-use super::header::EntryHeader;
+use super::cache::{_Cache, ObjectCache};
+use super::delta::delta;
 
-const SLID_WINDWOS: usize = 20;
+/// The objects compared to each one for the best delta base, the `--window` of `git pack-objects`.
+pub const DEFAULT_WINDOW: usize = 10;
+/// The max length of a delta chain, the `--depth` of `git pack-objects`.
+pub const DEFAULT_DEPTH: usize = 50;
 
-#[allow(unused)]
-struct Encoder<W> {
+/// The objects smaller than it are not worth a delta.
+const MIN_DELTA_SIZE: usize = 50;
+
+/// An object written in the pack, which may be the base of the deltas in the window.
+struct Candidate {
+    data: Vec<u8>,
+    /// The length of the delta chain to the object, 0 if it's not a delta.
+    depth: usize,
+}
+
+/// The base of a delta, in the pack before it or not in the pack.
+enum Base {
+    Offset(usize),
+    Hash(Hash),
+}
+
+/// Write a pack to the `inner` object by object, and the checksum by [`Encoder::finish`].
+pub struct Encoder<W> {
     inner: W,
     hash: Sha1,
+    /// The offset of the next entry.
+    offset: usize,
 }
-#[allow(unused)]
+
 impl<W> Encoder<W>
 where
     W: Write,
 {
+    /// Writes the header of a pack of `object_number` objects.
     pub fn init(object_number: usize, mut inner: W) -> Self {
         let head = encode_header(object_number);
         inner.write_all(&head).unwrap();
-        let mut hash = Sha1::new();
+        let mut hash = Sha1::default();
         hash.update(&head);
-        Self { inner, hash }
+        Self {
+            inner,
+            hash,
+            offset: head.len(),
+        }
     }
+
+    fn write_entry(&mut self, entry: &[u8]) -> Result<(), Error> {
+        self.hash.update(entry);
+        self.inner.write_all(entry)?;
+        self.offset += entry.len();
+        Ok(())
+    }
+
+    /// Writes the objects as they are, without the delta compression.
     pub fn add_objects(&mut self, obj_vec: Vec<Arc<dyn ObjectT>>) -> Result<(), Error> {
-        let ls = obj_vec.len();
         for obj in obj_vec {
             let obj_data = encode_one_object(obj)?;
-            self.hash.update(&obj_data);
-            self.inner.write_all(&obj_data)?;
+            self.write_entry(&obj_data)?;
         }
         Ok(())
     }
-    /// Added batch insertion support for offset delta compression.
-    /// Note: The input array should meet the requirements of magic sorting, otherwise a good delta compression rate cannot be obtained
-    pub fn add_oject_model(&mut self, obj_vec: Vec<git_obj::Model>) -> Result<(), Error> {
-        let batch_size = obj_vec.len();
-        for i in 0..batch_size {
-            let mut best_j = SLID_WINDWOS + 1;
-            let mut best_ssam_rate: f64 = 0.0;
-            // delta from base object by slid window
-            for j in 1..SLID_WINDWOS {
-                if i < j {
-                    break;
-                }
-                let pos = i - j;
-                if !obj_vec[pos].object_type.eq(&obj_vec[i].object_type) {
-                    break;
+
+    /// Writes the objects with the offset delta compression, and returns the number of deltas.
+    ///
+    /// Like `git pack-objects`, the objects are sorted by the type and the size from the largest,
+    /// each one is compared with the `window` objects of the same type before it, and written as
+    /// the delta of the one saving the most space. The delta chains are at most `depth` long, so
+    /// the `window` or the `depth` of 0 disables the compression.
+    pub fn add_objects_with_delta(
+        &mut self,
+        obj_vec: Vec<Arc<dyn ObjectT>>,
+        window: usize,
+        depth: usize,
+    ) -> Result<usize, Error> {
+        self.add_thin_objects(obj_vec, &HashMap::new(), window, depth)
+    }
+
+    /// Like [`Encoder::add_objects_with_delta`], but an object may also be written as the
+    /// hash delta of the base of it in `bases`, which is not in the pack, e.g. a thin pack of
+    /// which the client has the bases. The bases are of the objects by the hashes.
+    pub fn add_thin_objects(
+        &mut self,
+        obj_vec: Vec<Arc<dyn ObjectT>>,
+        bases: &HashMap<Hash, Arc<dyn ObjectT>>,
+        window: usize,
+        depth: usize,
+    ) -> Result<usize, Error> {
+        let mut objects: Vec<(ObjectType, Hash, Vec<u8>)> = obj_vec
+            .into_iter()
+            .map(|obj| (obj.get_type(), obj.get_hash(), obj.get_raw()))
+            .collect();
+        objects.sort_by_key(|(t, _, data)| (t.type2number(), Reverse(data.len())));
+
+        // the candidates are looked up by the offsets in the window
+        let mut cache: ObjectCache<Arc<Candidate>> = ObjectCache::new(Some(window.max(1)));
+        let mut candidates: VecDeque<usize> = VecDeque::with_capacity(window);
+        let mut deltas = 0;
+        let mut last_type = None;
+        for (object_type, hash, data) in objects {
+            if last_type != Some(object_type) {
+                candidates.clear();
+                last_type = Some(object_type);
+            }
+            let offset = self.offset;
+            let mut best: Option<(Base, Vec<u8>, usize)> = None;
+            let max_size = data.len() / 2;
+            if data.len() >= MIN_DELTA_SIZE && window > 0 && depth > 0 {
+                // the base of the client is full there, after the pack is completed by it
+                if let Some(base) = bases.get(&hash) {
+                    let delta = delta(&base.get_raw(), &data);
+                    if delta.len() < max_size {
+                        best = Some((Base::Hash(base.get_hash()), delta, 1));
+                    }
                 }
-                let differ = DeltaDiff::new(&obj_vec[i - j].data, &obj_vec[i].data);
-                let diff_rate = differ.get_ssam_rate();
-                if (diff_rate > best_ssam_rate) && diff_rate > 0.5 {
-                    best_ssam_rate = diff_rate;
-                    best_j = j;
+                for base_offset in candidates.iter() {
+                    let Some(base) = cache.get(*base_offset) else {
+                        continue;
+                    };
+                    if base.depth >= depth || data.len() < base.data.len() / 32 {
+                        continue;
+                    }
+                    let delta = delta(&base.data, &data);
+                    let smallest = best.as_ref().map_or(max_size, |(_, d, _)| d.len());
+                    if delta.len() < smallest {
+                        best = Some((Base::Offset(*base_offset), delta, base.depth + 1));
+                    }
                 }
             }
-            let obj_data = if best_j == SLID_WINDWOS + 1 {
-                encode_one_ojbect(
-                    EntryHeader::from_string(&obj_vec[i].object_type).to_number(),
-                    obj_vec[i].data.len(),
-                    &obj_vec[i].data,
-                )
-            } else {
-                let differ = DeltaDiff::new(&obj_vec[i - best_j].data, &obj_vec[i].data);
-                let after = differ.encode();
-                encode_one_ojbect(6, after.len(), &after)
+
+            let candidate = match best {
+                Some((base, delta, chain)) => {
+                    let entry = match base {
+                        Base::Offset(base_offset) => {
+                            encode_offset_delta(offset - base_offset, &delta)?
+                        }
+                        Base::Hash(base_hash) => encode_hash_delta(base_hash, &delta)?,
+                    };
+                    self.write_entry(&entry)?;
+                    deltas += 1;
+                    Candidate { data, depth: chain }
+                }
+                None => {
+                    let entry = encode_one_ojbect(object_type.type2number(), data.len(), &data)?;
+                    self.write_entry(&entry)?;
+                    Candidate { data, depth: 0 }
+                }
+            };
+            if window > 0 {
+                cache
+                    .put(offset, hash, Arc::new(candidate))
+                    .map_err(Error::other)?;
+                if candidates.len() == window {
+                    candidates.pop_front();
+                }
+                candidates.push_back(offset);
             }
-            .unwrap();
-            self.hash.update(&obj_data);
-            self.inner.write_all(&obj_data)?;
         }
-        Ok(())
+        Ok(deltas)
     }
+
+    /// Writes the checksum of the pack, after all the objects.
     pub fn finish(&mut self) -> Result<(), Error> {
         let hash_result = self.hash.clone().finalize();
         self.inner.write_all(&hash_result)?;
         Ok(())
     }
 }
-//
+
+/// Encode the objects in a pack with the delta compression, see
+/// [`Encoder::add_objects_with_delta`] for the `window` and the `depth`, and [`Encoder`] to write
+/// the pack to a stream rather than in memory.
+pub fn write_pack(
+    obj_vec: Vec<Arc<dyn ObjectT>>,
+    window: usize,
+    depth: usize,
+) -> Result<Vec<u8>, Error> {
+    write_thin_pack(obj_vec, &HashMap::new(), window, depth)
+}
+
+/// Encode the objects in a thin pack, of which the deltas may be of the `bases` not in it, see
+/// [`Encoder::add_thin_objects`].
+pub fn write_thin_pack(
+    obj_vec: Vec<Arc<dyn ObjectT>>,
+    bases: &HashMap<Hash, Arc<dyn ObjectT>>,
+    window: usize,
+    depth: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut out_data = Vec::new();
+    let mut encoder = Encoder::init(obj_vec.len(), &mut out_data);
+    encoder.add_thin_objects(obj_vec, bases, window, depth)?;
+    encoder.finish()?;
+    Ok(out_data)
+}
 
 pub fn pack_encode(obj_vec: Vec<Arc<dyn ObjectT>>) -> Result<Vec<u8>, Error> {
-    let mut hash = Sha1::new();
+    let mut hash = Sha1::default();
     let mut out_data = Vec::new();
     let header_data = encode_header(obj_vec.len());
     hash.update(&header_data);
@@ -109,7 +231,6 @@ fn encode_header(object_number: usize) -> Vec<u8> {
         b'P', b'A', b'C', b'K', // The logotype of the Pack File
         0, 0, 0, 2,
     ]; // THe Version  of the Pack File
-    assert_ne!(object_number, 0); // guarantee self.number_of_objects!=0
     assert!(object_number < (1 << 32));
     //TODO: GitError:numbers of objects should  < 4G ,
     //Encode the number of object  into file
@@ -121,21 +242,7 @@ fn encode_one_object(obj: Arc<dyn ObjectT>) -> Result<Vec<u8>, Error> {
     let mut out = Writer::new(Vec::new());
     let obj_data = obj.get_raw();
     let size = obj_data.len();
-    let mut header_data = vec![(0x80 | (obj.get_type().type2number() << 4)) + (size & 0x0f) as u8];
-    let mut _size = size >> 4;
-    if _size > 0 {
-        while _size > 0 {
-            if _size >> 7 > 0 {
-                header_data.push((0x80 | _size) as u8);
-                _size >>= 7;
-            } else {
-                header_data.push((_size) as u8);
-                break;
-            }
-        }
-    } else {
-        header_data.push(0);
-    }
+    let mut header_data = entry_header(obj.get_type().type2number(), size);
 
     if let Err(err) = std::io::copy(&mut Cursor::new(obj_data), &mut out) {
         match err.kind() {
@@ -151,22 +258,42 @@ fn encode_one_object(obj: Arc<dyn ObjectT>) -> Result<Vec<u8>, Error> {
 }
 
 fn encode_one_ojbect(git_type: u8, size: usize, data: &[u8]) -> Result<Vec<u8>, Error> {
-    let mut out = Writer::new(Vec::new());
-    let mut header_data = vec![(0x80 | (git_type << 4)) + (size & 0x0f) as u8];
-    let mut _size = size >> 4;
-    if _size > 0 {
-        while _size > 0 {
-            if _size >> 7 > 0 {
-                header_data.push((0x80 | _size) as u8);
-                _size >>= 7;
-            } else {
-                header_data.push((_size) as u8);
-                break;
-            }
-        }
-    } else {
-        header_data.push(0);
+    let mut header_data = entry_header(git_type, size);
+    header_data.append(&mut deflate(data)?);
+    Ok(header_data)
+}
+
+/// The entry of an offset delta, of which the base is `distance` bytes before it.
+fn encode_offset_delta(distance: usize, delta: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut entry = entry_header(ObjectType::OffsetDelta.type2number(), delta.len());
+    entry.append(&mut utils::write_offset_encoding(distance as u64));
+    entry.append(&mut deflate(delta)?);
+    Ok(entry)
+}
+
+/// The entry of a hash delta, of which the base is the object of the `base` hash.
+fn encode_hash_delta(base: Hash, delta: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut entry = entry_header(ObjectType::HashDelta.type2number(), delta.len());
+    entry.extend_from_slice(base.as_bytes());
+    entry.append(&mut deflate(delta)?);
+    Ok(entry)
+}
+
+/// The type and the size of an entry in the fewest bytes like git, as the decoder counts the
+/// bytes of a header by the size.
+fn entry_header(git_type: u8, size: usize) -> Vec<u8> {
+    let mut header_data = vec![(git_type << 4) | (size & 0x0f) as u8];
+    let mut size = size >> 4;
+    while size > 0 {
+        *header_data.last_mut().unwrap() |= 0x80;
+        header_data.push((size & 0x7f) as u8);
+        size >>= 7;
     }
+    header_data
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut out = Writer::new(Vec::new());
     if let Err(err) = std::io::copy(&mut Cursor::new(data), &mut out) {
         match err.kind() {
             std::io::ErrorKind::Other => return Err(err),
@@ -176,8 +303,7 @@ fn encode_one_ojbect(git_type: u8, size: usize, data: &[u8]) -> Result<Vec<u8>,
         }
     };
     out.flush().expect("zlib flush should never fail");
-    header_data.append(&mut out.into_inner());
-    Ok(header_data)
+    Ok(out.into_inner())
 }
 
 fn u32_vec(value: u32) -> Vec<u8> {
@@ -201,14 +327,71 @@ mod tests {
             pack::Pack,
         },
     };
-    use std::io::Cursor;
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::{Cursor, Read};
     use std::sync::Arc;
 
-    use super::{pack_encode, Encoder};
+    use sha1::{Digest, Sha1};
+
+    use database::driver::memory::storage::MemoryStorage;
+
+    use super::{
+        entry_header, pack_encode, write_pack, write_thin_pack, Encoder, DEFAULT_DEPTH,
+        DEFAULT_WINDOW,
+    };
+    use crate::internal::pack::preload::{decode_load, PackPreload};
+    use crate::internal::pack::stream::PackStream;
+    use crate::internal::zlib::stream::inflate::ReadPlain;
+    use crate::internal::ObjectType;
+    use crate::utils;
+
+    /// Blobs of which each one is the last with a line changed, and an unrelated one.
+    fn similar_blobs() -> Vec<Arc<dyn ObjectT>> {
+        let mut lines: Vec<String> = (0..200).map(|i| format!("line {}\n", i)).collect();
+        let mut obj_vec: Vec<Arc<dyn ObjectT>> = Vec::new();
+        for i in 0..8 {
+            lines[i * 20] = format!("changed {}\n", i);
+            let blob = Blob::new_from_data(lines.concat().into_bytes());
+            obj_vec.push(Arc::new(blob));
+        }
+        let data = (0..100).map(|i| (i * 7 % 251) as u8).collect();
+        obj_vec.push(Arc::new(Blob::new_from_data(data)));
+        obj_vec
+    }
+
+    fn blob_hash(data: &[u8]) -> String {
+        let mut h = Sha1::default();
+        h.update(format!("blob {}\0", data.len()));
+        h.update(data);
+        hex::encode(h.finalize())
+    }
+
+    /// The length of the delta chain of each entry by the offset.
+    fn delta_depths(pack: &[u8]) -> Vec<usize> {
+        let mut reader = Cursor::new(&pack[12..pack.len() - 20]);
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        let mut result = Vec::new();
+        while (reader.position() as usize) < reader.get_ref().len() {
+            let offset = reader.position() as usize + 12;
+            let (type_num, _) = utils::read_type_and_size(&mut reader).unwrap();
+            let depth = if ObjectType::number2type(type_num).unwrap() == ObjectType::OffsetDelta {
+                let distance = utils::read_offset_encoding(&mut reader, &mut 0).unwrap();
+                depths[&(offset - distance as usize)] + 1
+            } else {
+                0
+            };
+            ReadPlain::new(&mut reader)
+                .read_to_end(&mut Vec::new())
+                .unwrap();
+            depths.insert(offset, depth);
+            result.push(depth);
+        }
+        result
+    }
 
     #[test]
     fn test_a_simple_encode() {
-        let id = Hash([0u8; 20]);
+        let id = Hash::default();
         let data = String::from("hello,1").into_bytes();
         let mut obj_vec: Vec<Arc<dyn ObjectT>> = Vec::new();
         let b1 = Blob { id, data };
@@ -224,7 +407,7 @@ mod tests {
 
     #[test]
     fn test_pack_encoder() {
-        let id = Hash([0u8; 20]);
+        let id = Hash::default();
         let mut pack_data = Vec::with_capacity(1000);
         // Encoder::init
         let mut encoder = Encoder::init(2, &mut pack_data);
@@ -242,4 +425,117 @@ mod tests {
         let mut buff = Cursor::new(pack_data);
         block_on(Pack::decode(&mut buff)).unwrap();
     }
+
+    #[tokio::test]
+    async fn test_write_pack_round_trip() {
+        let obj_vec = similar_blobs();
+        let expected: BTreeMap<String, Vec<u8>> = obj_vec
+            .iter()
+            .map(|obj| (blob_hash(&obj.get_raw()), obj.get_raw()))
+            .collect();
+
+        let mut pack_data = Vec::new();
+        let mut encoder = Encoder::init(obj_vec.len(), &mut pack_data);
+        let deltas = encoder
+            .add_objects_with_delta(obj_vec.clone(), DEFAULT_WINDOW, DEFAULT_DEPTH)
+            .unwrap();
+        encoder.finish().unwrap();
+        // all but the largest of the similar ones and the unrelated one
+        assert_eq!(deltas, 7);
+        assert_eq!(
+            pack_data,
+            write_pack(obj_vec.clone(), DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap()
+        );
+        assert!(pack_data.len() < pack_encode(obj_vec).unwrap().len() / 2);
+
+        let mut stream = PackStream::new(Cursor::new(pack_data.clone())).unwrap();
+        let mut decoded = BTreeMap::new();
+        while let Some(object) = stream.next_object().await.unwrap() {
+            assert_eq!(object.object_type, ObjectType::Blob);
+            decoded.insert(object.hash.to_plain_str(), object.data.clone());
+        }
+        assert_eq!(decoded, expected);
+
+        let mut buff = Cursor::new(pack_data);
+        Pack::decode(&mut buff).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_preload_small_deltas() {
+        // of the fewest bytes, as the preload counts the bytes of a header by the size
+        assert_eq!(entry_header(3, 11), [0x3b]);
+        assert_eq!(entry_header(1, 815), [0x9f, 0x32]);
+        // each one a line longer than the next, so the deltas are of a few bytes
+        let lines: String = (0..200).map(|i| format!("line {}\n", i)).collect();
+        let obj_vec: Vec<Arc<dyn ObjectT>> = ["", "x\n", "x\ny\n", "x\ny\nz\n"]
+            .into_iter()
+            .map(|tail| -> Arc<dyn ObjectT> {
+                Arc::new(Blob::new_from_data(
+                    format!("{}{}", lines, tail).into_bytes(),
+                ))
+            })
+            .collect();
+        let pack_data = write_pack(obj_vec, DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap();
+        assert_eq!(
+            delta_depths(&pack_data).iter().filter(|d| **d > 0).count(),
+            3
+        );
+        let storage = Arc::new(MemoryStorage::default());
+        let preload = PackPreload::new(Cursor::new(pack_data));
+        decode_load(preload, storage.clone()).await.unwrap();
+
+        // and the pack of no objects, e.g. of the refs to the commits the other side has
+        let pack_data = write_pack(Vec::new(), DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap();
+        assert_eq!(pack_data.len(), 32);
+        let preload = PackPreload::new(Cursor::new(pack_data));
+        decode_load(preload, storage).await.unwrap();
+    }
+
+    #[test]
+    fn test_write_pack_depth() {
+        let pack_data = write_pack(similar_blobs(), DEFAULT_WINDOW, 1).unwrap();
+        let depths = delta_depths(&pack_data);
+        assert_eq!(depths.len(), 9);
+        assert_eq!(depths.iter().max(), Some(&1));
+
+        let pack_data = write_pack(similar_blobs(), DEFAULT_WINDOW, 2).unwrap();
+        assert_eq!(delta_depths(&pack_data).iter().max(), Some(&2));
+
+        // no compression with either of them 0
+        for (window, depth) in [(0, DEFAULT_DEPTH), (DEFAULT_WINDOW, 0)] {
+            let pack_data = write_pack(similar_blobs(), window, depth).unwrap();
+            assert!(delta_depths(&pack_data).iter().all(|depth| *depth == 0));
+        }
+    }
+
+    #[test]
+    fn test_write_thin_pack() {
+        let blobs = similar_blobs();
+        let base = blobs[0].clone();
+        let mut object = Blob::new_from_data(blobs[1].get_raw());
+        object.id = Hash::new_from_str(&blob_hash(&object.data));
+        let bases = HashMap::from([(object.id, base.clone())]);
+        let pack_data = write_thin_pack(
+            vec![Arc::new(object)],
+            &bases,
+            DEFAULT_WINDOW,
+            DEFAULT_DEPTH,
+        )
+        .unwrap();
+
+        // the hash delta of the base not in the pack
+        let mut reader = Cursor::new(&pack_data[12..]);
+        let (type_num, _) = utils::read_type_and_size(&mut reader).unwrap();
+        assert_eq!(
+            ObjectType::number2type(type_num).unwrap(),
+            ObjectType::HashDelta
+        );
+        assert_eq!(utils::read_hash(&mut reader).unwrap(), base.get_hash());
+        assert!(pack_data.len() < pack_encode(blobs[1..2].to_vec()).unwrap().len() / 2);
+
+        // and the full object without the base
+        let pack_data =
+            write_thin_pack(blobs[1..2].to_vec(), &bases, DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap();
+        assert_eq!(delta_depths(&pack_data), [0]);
+    }
 }
@@ -132,7 +132,7 @@ impl<BR: std::io::BufRead> EntriesIter<BR> {
                         .await
                         .unwrap()
                         .ok_or_else(|| {
-                            println!("wrong base hash value :{}", hash);
+                            tracing::error!("wrong base hash value :{}", hash);
                             GitError::DeltaObjectError(
                                 "cant' find base obj from hash value ".to_string(),
                             )
@@ -167,7 +167,7 @@ impl<BR: std::io::BufRead> EntriesIter<BR> {
 
         let result = obj.clone();
         let h = Arc::clone(&obj).get_hash();
-        self.cache.put(self.offset, h, obj);
+        self.cache.put(self.offset, h, obj)?;
         self.offset += iter_offset;
         Ok(result)
     }
@@ -233,7 +233,7 @@ impl<BR: std::io::BufRead> EntriesIter<BR> {
                             .await
                             .unwrap()
                             .ok_or_else(|| {
-                                println!("wrong base offset :{}", base_offset);
+                                tracing::error!("wrong base offset :{}", base_offset);
                                 GitError::DeltaObjectError(
                                     "cant' find base obj from offset".to_string(),
                                 )
@@ -258,7 +258,7 @@ impl<BR: std::io::BufRead> EntriesIter<BR> {
                         .await
                         .unwrap()
                         .ok_or_else(|| {
-                            println!("wrong base hash value :{}", hash);
+                            tracing::error!("wrong base hash value :{}", hash);
                             GitError::DeltaObjectError(
                                 "cant' find base obj from hash value ".to_string(),
                             )
@@ -295,19 +295,19 @@ impl<BR: std::io::BufRead> EntriesIter<BR> {
         match obj.clone() {
             GitObjects::COMMIT(a) => {
                 h = a.get_hash();
-                self.cache.put(self.offset, h, Arc::new(a));
+                self.cache.put(self.offset, h, Arc::new(a))?;
             }
             GitObjects::TREE(a) => {
                 h = a.get_hash();
-                self.cache.put(self.offset, h, Arc::new(a));
+                self.cache.put(self.offset, h, Arc::new(a))?;
             }
             GitObjects::BLOB(a) => {
                 h = a.get_hash();
-                self.cache.put(self.offset, h, Arc::new(a));
+                self.cache.put(self.offset, h, Arc::new(a))?;
             }
             GitObjects::TAG(a) => {
                 h = a.get_hash();
-                self.cache.put(self.offset, h, Arc::new(a));
+                self.cache.put(self.offset, h, Arc::new(a))?;
             }
         };
 
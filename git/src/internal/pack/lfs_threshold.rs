@@ -0,0 +1,251 @@
+//! The check of the large files of a pushed pack: a blob larger than the threshold must be the
+//! pointer of an LFS object, so the repository keeps small and the large files are served by
+//! the LFS storage. It's checked before any object of the pack is saved, as the connectivity.
+//!
+//! The blobs are named by the paths in the trees of the pack, which are all the trees from the
+//! pushed commits down to the changed blobs.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+
+use database::driver::ObjectStorage;
+
+use super::stream::PackStream;
+use crate::hash::Hash;
+use crate::internal::object::commit::Commit;
+use crate::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use crate::internal::object::ObjectT;
+use crate::internal::ObjectType;
+use crate::lfs::pointer::pointer_oid;
+
+/// A blob of the pack larger than the threshold, which is not an LFS pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OversizedBlob {
+    pub id: Hash,
+    pub size: usize,
+    /// The path of the blob in a pushed commit, none if no tree of the pack has it.
+    pub path: Option<String>,
+}
+
+impl fmt::Display for OversizedBlob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{} ({} bytes)", path, self.size),
+            None => write!(f, "blob {} ({} bytes)", self.id.to_plain_str(), self.size),
+        }
+    }
+}
+
+/// The blobs of the pack larger than `threshold` bytes which are not LFS pointers, sorted by
+/// the paths.
+pub async fn oversized_blobs<R: Read>(
+    pack: R,
+    storage: Arc<dyn ObjectStorage>,
+    threshold: u64,
+) -> Result<Vec<OversizedBlob>, anyhow::Error> {
+    let mut stream = PackStream::new(pack)?;
+    stream.set_storage(Some(storage));
+
+    let mut blobs = HashMap::new();
+    let mut trees: HashMap<Hash, Vec<TreeItem>> = HashMap::new();
+    let mut roots = VecDeque::new();
+    while let Some(object) = stream.next_object().await? {
+        match object.object_type {
+            ObjectType::Blob
+                if object.data.len() as u64 > threshold && pointer_oid(&object.data).is_none() =>
+            {
+                blobs.insert(object.hash, object.data.len());
+            }
+            ObjectType::Tree => {
+                trees.insert(
+                    object.hash,
+                    Tree::new_from_data(object.data.clone()).tree_items,
+                );
+            }
+            ObjectType::Commit => {
+                roots.push_back((
+                    Commit::new_from_data(object.data.clone()).tree_id,
+                    String::new(),
+                ));
+            }
+            _ => {}
+        }
+    }
+    if blobs.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut paths = HashMap::new();
+    let mut visited = HashSet::new();
+    let mut queue = roots;
+    while let Some((id, dir)) = queue.pop_front() {
+        if !visited.insert(id) {
+            continue;
+        }
+        // the trees not in the pack are saved, so none of their blobs is pushed
+        let Some(items) = trees.get(&id) else {
+            continue;
+        };
+        for item in items {
+            let path = if dir.is_empty() {
+                item.name.clone()
+            } else {
+                format!("{}/{}", dir, item.name)
+            };
+            match item.mode {
+                TreeItemMode::Tree => queue.push_back((item.id, path)),
+                TreeItemMode::Commit => {}
+                _ if blobs.contains_key(&item.id) => {
+                    paths.entry(item.id).or_insert(path);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut oversized: Vec<OversizedBlob> = blobs
+        .into_iter()
+        .map(|(id, size)| OversizedBlob {
+            id,
+            size,
+            path: paths.remove(&id),
+        })
+        .collect();
+    oversized.sort_by(|a, b| (&a.path, a.id).cmp(&(&b.path, b.id)));
+    Ok(oversized)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use common::utils::ZERO_ID;
+    use database::driver::memory::storage::MemoryStorage;
+
+    use super::oversized_blobs;
+    use crate::hash::Hash;
+    use crate::internal::object::blob::Blob;
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use crate::internal::object::ObjectT;
+    use crate::internal::pack::encode::{write_pack, DEFAULT_DEPTH, DEFAULT_WINDOW};
+    use crate::protocol::{PackProtocol, Protocol};
+
+    const THRESHOLD: u64 = 100;
+
+    /// The object of which the id is the hash of the data.
+    fn object<T: ObjectT>(data: Vec<u8>) -> T {
+        let mut object = T::new_from_data(data);
+        let raw = object.get_raw();
+        let mut hasher = Hash::hasher();
+        hasher.update(object.get_type().to_bytes());
+        hasher.update(format!(" {}\0", raw.len()).as_bytes());
+        hasher.update(&raw);
+        object.set_hash(hasher.finalize());
+        object
+    }
+
+    fn tree(items: &[(TreeItemMode, Hash, &str)]) -> Tree {
+        let data = items
+            .iter()
+            .flat_map(|(mode, id, name)| TreeItem::new(*mode, *id, name.to_string()).to_data())
+            .collect();
+        object(data)
+    }
+
+    /// The pack of a commit of `README.md` and `assets/<name>` of the data, and the id of it.
+    fn pack(name: &str, data: Vec<u8>) -> (Vec<u8>, Hash) {
+        let readme: Blob = object(b"# the large files\n".to_vec());
+        let file: Blob = object(data);
+        let assets = tree(&[(TreeItemMode::Blob, file.id, name)]);
+        let root = tree(&[
+            (TreeItemMode::Blob, readme.id, "README.md"),
+            (TreeItemMode::Tree, assets.id, "assets"),
+        ]);
+        let commit: Commit = object(
+            format!(
+                "tree {}\nauthor A <a@example.com> 1700000000 +0000\ncommitter A <a@example.com> 1700000000 +0000\n\nadd {}\n",
+                root.id.to_plain_str(),
+                name
+            )
+            .into_bytes(),
+        );
+        let id = commit.id;
+        let objects: Vec<Arc<dyn ObjectT>> = vec![
+            Arc::new(commit),
+            Arc::new(root),
+            Arc::new(assets),
+            Arc::new(readme),
+            Arc::new(file),
+        ];
+        (
+            write_pack(objects, DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap(),
+            id,
+        )
+    }
+
+    /// The pointer file of an object of 10MB, which is larger than the threshold itself.
+    fn pointer() -> Vec<u8> {
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 10485760\n",
+            "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393"
+        )
+        .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_oversized_blobs() {
+        let storage = Arc::new(MemoryStorage::new());
+        let (pack_data, _) = pack("video.mp4", vec![7; 1000]);
+        let oversized = oversized_blobs(Cursor::new(&pack_data), storage.clone(), THRESHOLD)
+            .await
+            .unwrap();
+        assert_eq!(oversized.len(), 1);
+        assert_eq!(oversized[0].path.as_deref(), Some("assets/video.mp4"));
+        assert_eq!(oversized[0].to_string(), "assets/video.mp4 (1000 bytes)");
+
+        let (pack_data, _) = pack("video.mp4", pointer());
+        assert!(pointer().len() as u64 > THRESHOLD);
+        let oversized = oversized_blobs(Cursor::new(&pack_data), storage, THRESHOLD)
+            .await
+            .unwrap();
+        assert!(oversized.is_empty());
+    }
+
+    async fn push(data: Vec<u8>) -> String {
+        let (pack_data, id) = pack("video.mp4", data);
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/large"),
+            Arc::new(MemoryStorage::new()),
+            Protocol::Http,
+        );
+        protocol.enforce_lfs_threshold = Some(THRESHOLD);
+        let line = format!(
+            "{} {} refs/heads/main\0report-status\n",
+            ZERO_ID,
+            id.to_plain_str()
+        );
+        let mut body = format!("{:04x}{}0000", line.len() + 4, line).into_bytes();
+        body.extend(pack_data);
+        let pack_data = protocol.git_receive_pack(Bytes::from(body)).await.unwrap();
+        let report = protocol.git_receive_pack(pack_data).await.unwrap();
+        String::from_utf8_lossy(&report).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_lfs_threshold() {
+        let report = push(vec![7; 1000]).await;
+        assert!(report.contains("unpack ok\n"));
+        assert!(report.contains(
+            "ng refs/heads/main assets/video.mp4 (1000 bytes) exceeds the LFS threshold of 100 bytes"
+        ));
+
+        let report = push(pointer()).await;
+        assert!(report.contains("ok refs/heads/main"), "{}", report);
+    }
+}
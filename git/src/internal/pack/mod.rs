@@ -12,7 +12,8 @@ use super::object::ObjectT;
 use crate::hash::Hash;
 use std::{path::PathBuf, sync::Arc};
 
-mod cache;
+pub mod cache;
+pub mod connectivity;
 mod counter;
 mod cqueue;
 pub mod decode;
@@ -20,7 +21,9 @@ pub mod delta;
 pub mod encode;
 mod header;
 pub mod iterator;
+pub mod lfs_threshold;
 pub mod preload;
+pub mod stream;
 /// ### Represents a Git pack file.
 ///  `head`: The file header, typically "PACK"<br>
 /// `version`: The pack file version <br>
@@ -147,4 +150,203 @@ pub mod git_object_size {
 }
 
 #[cfg(test)]
-mod tests {}
+pub(crate) mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use common::errors::MegaError;
+    use database::driver::ObjectStorage;
+    use entity::{commit, git_obj, mr, node, refs, symrefs};
+    use sea_orm::DatabaseConnection;
+
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::ObjectT;
+
+    /// A thin pack of a commit changing the line 500 of `a.txt`, of which the blob is a
+    /// REF_DELTA of the [`thin_pack_base`] not in the pack.
+    pub(crate) const THIN_PACK: &str =
+        "../tests/data/packs/thin/pack-e60f0d0d68f706cd7dad59d208dc13d731272c60.pack";
+
+    /// A pack of 30 commits changing `a.txt`, of which the blobs are the REF_DELTAs of the
+    /// others in the pack, as packed without `--delta-base-offset`.
+    pub(crate) const REF_DELTA_PACK: &str =
+        "../tests/data/packs/ref-delta/pack-76bac3bd5af5a3137ce1b3b7e27838116ea00ed7.pack";
+
+    /// The `(id, type, data)` of the base of the [`THIN_PACK`], the lines `line 0` to `line 999`.
+    pub(crate) fn thin_pack_base() -> (String, String, Vec<u8>) {
+        let data: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        (
+            "79866961e2b34d03e6cd066635a487466491f9d7".to_owned(),
+            "blob".to_owned(),
+            data.into_bytes(),
+        )
+    }
+
+    /// Keep the git objects in memory, e.g. the decoded ones or the bases of a thin pack.
+    #[derive(Default)]
+    pub(crate) struct MemoryStorage {
+        connection: DatabaseConnection,
+        pub(crate) objects: Mutex<HashMap<String, (String, Vec<u8>)>>,
+        pub(crate) refs: Mutex<Vec<refs::Model>>,
+    }
+
+    impl MemoryStorage {
+        pub(crate) fn with_objects(objects: Vec<(String, String, Vec<u8>)>) -> MemoryStorage {
+            let storage = MemoryStorage::default();
+            storage.objects.lock().unwrap().extend(
+                objects
+                    .into_iter()
+                    .map(|(id, object_type, data)| (id, (object_type, data))),
+            );
+            storage
+        }
+
+        pub(crate) fn add_ref(&self, repo_path: &str, ref_name: &str, ref_git_id: &str) {
+            let mut refs = self.refs.lock().unwrap();
+            let now = chrono::Utc::now().naive_utc();
+            let id = refs.len() as i32 + 1;
+            refs.push(refs::Model {
+                id,
+                repo_path: repo_path.to_owned(),
+                ref_name: ref_name.to_owned(),
+                ref_git_id: ref_git_id.to_owned(),
+                created_at: now,
+                updated_at: now,
+            });
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStorage for MemoryStorage {
+        fn get_connection(&self) -> &DatabaseConnection {
+            &self.connection
+        }
+
+        async fn save_mr_objects(&self, _: Vec<mr::ActiveModel>) -> Result<bool, MegaError> {
+            Ok(true)
+        }
+
+        async fn save_obj_data(
+            &self,
+            models: Vec<git_obj::ActiveModel>,
+        ) -> Result<bool, MegaError> {
+            let mut objects = self.objects.lock().unwrap();
+            for model in models {
+                objects.insert(
+                    model.git_id.unwrap(),
+                    (model.object_type.unwrap(), model.data.unwrap()),
+                );
+            }
+            Ok(true)
+        }
+
+        async fn get_obj_data_by_id(&self, id: &str) -> Result<Option<git_obj::Model>, MegaError> {
+            let objects = self.objects.lock().unwrap();
+            Ok(objects.get(id).map(|(object_type, data)| git_obj::Model {
+                id: 0,
+                git_id: id.to_owned(),
+                object_type: object_type.clone(),
+                data: data.clone(),
+                compression: None,
+            }))
+        }
+
+        async fn get_obj_data_by_ids(
+            &self,
+            ids: Vec<String>,
+        ) -> Result<Vec<git_obj::Model>, MegaError> {
+            let mut models = Vec::new();
+            for id in ids {
+                models.extend(self.get_obj_data_by_id(&id).await?);
+            }
+            Ok(models)
+        }
+
+        async fn get_existing_obj_ids(
+            &self,
+            ids: Vec<String>,
+        ) -> Result<HashSet<String>, MegaError> {
+            let objects = self.objects.lock().unwrap();
+            Ok(ids.into_iter().filter(|id| objects.contains_key(id)).collect())
+        }
+
+        async fn search_refs(&self, _: &str) -> Result<Vec<refs::Model>, MegaError> {
+            Ok(vec![])
+        }
+
+        async fn get_ref_object_id(&self, repo_path: &str) -> Result<Vec<refs::Model>, MegaError> {
+            let refs = self.refs.lock().unwrap();
+            Ok(refs
+                .iter()
+                .filter(|r| r.repo_path == repo_path)
+                .cloned()
+                .collect())
+        }
+
+        /// The commits of all the repositories, parsed from the objects.
+        async fn get_all_commits_by_path(&self, _: &str) -> Result<Vec<commit::Model>, MegaError> {
+            let objects = self.objects.lock().unwrap();
+            let now = chrono::Utc::now().naive_utc();
+            Ok(objects
+                .iter()
+                .filter(|(_, (object_type, _))| object_type == "commit")
+                .map(|(id, (_, data))| {
+                    let commit = Commit::new_from_data(data.clone());
+                    commit::Model {
+                        id: 0,
+                        git_id: id.clone(),
+                        tree: commit.tree_id.to_plain_str(),
+                        pid: commit
+                            .parent_tree_ids
+                            .iter()
+                            .map(|id| id.to_plain_str())
+                            .collect(),
+                        repo_path: String::new(),
+                        author: Some(String::from_utf8(commit.author.to_data().unwrap()).unwrap()),
+                        committer: Some(
+                            String::from_utf8(commit.committer.to_data().unwrap()).unwrap(),
+                        ),
+                        content: Some(commit.message),
+                        created_at: now,
+                        updated_at: now,
+                    }
+                })
+                .collect())
+        }
+
+        async fn search_commits(&self, _: &str) -> Result<Vec<commit::Model>, MegaError> {
+            Ok(vec![])
+        }
+
+        /// The trees and the blobs of all the repositories, of the ids and the types only.
+        async fn get_node_by_path(&self, _: &Path) -> Result<Vec<node::Model>, MegaError> {
+            let objects = self.objects.lock().unwrap();
+            let now = chrono::Utc::now().naive_utc();
+            Ok(objects
+                .iter()
+                .filter(|(_, (object_type, _))| object_type == "tree" || object_type == "blob")
+                .map(|(id, (object_type, _))| node::Model {
+                    id: 0,
+                    node_id: 0,
+                    git_id: id.clone(),
+                    last_commit: String::new(),
+                    node_type: object_type.clone(),
+                    name: None,
+                    mode: vec![],
+                    content_sha: None,
+                    size: 0,
+                    repo_path: String::new(),
+                    full_path: String::new(),
+                    created_at: now,
+                    updated_at: now,
+                })
+                .collect())
+        }
+
+        async fn get_symref(&self, _: &str, _: &str) -> Result<Option<symrefs::Model>, MegaError> {
+            Ok(None)
+        }
+    }
+}
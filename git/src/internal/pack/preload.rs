@@ -1,20 +1,16 @@
-use super::{counter::GitTypeCounter, delta::undelta, EntryHeader, Pack};
+use super::{counter::GitTypeCounter, delta::try_undelta, EntryHeader, Pack};
 use crate::{
     errors::GitError,
+    hash::backend::{Sha1, Sha1Backend},
     internal::{
         pack::{counter::DecodeCounter, cqueue::CircularQueue, Hash},
         zlib::stream::inflate::ReadPlain,
+        ObjectType,
     },
-    utils,
+    metrics, utils,
 };
 
-#[cfg(not(feature="redis_cache"))]
-#[cfg(feature="lru_cache")]
-use super::cache::{ObjectCache, _Cache};
-
-#[cfg(not(feature="lru_cache"))]
-#[cfg(feature="redis_cache")]
-use super::cache::{kvstore::ObjectCache, _Cache};
+use super::cache::SyncObjectCache;
 
 use serde::{Deserialize, Serialize};
 use async_recursion::async_recursion;
@@ -24,7 +20,6 @@ use num_cpus;
 
 use redis::{ToRedisArgs, FromRedisValue, RedisError, ErrorKind};
 use sea_orm::Set;
-use sha1::{Digest, Sha1};
 use std::{
     collections::HashMap,
     io::{Cursor, Read},
@@ -42,6 +37,9 @@ struct Entry {
     offset: usize,
     data: Vec<u8>,
     hash: Option<Hash>,
+    /// The length of the delta chain to the object once it's resolved, 0 of a base object.
+    #[serde(default)]
+    depth: usize,
 }
 
 impl Entry {
@@ -60,6 +58,7 @@ impl Entry {
             git_id: Set(self.hash.unwrap().to_plain_str()),
             object_type: Set(String::from_utf8_lossy(self.header.to_bytes()).to_string()),
             data: Set(self.data),
+            compression: Set(None),
         }
     }
 }
@@ -176,6 +175,7 @@ impl PackPreload {
                 offset,
                 data: content,
                 hash: None,
+                depth: 0,
             });
             map.insert(offset, i);
             offset += iter_offset;
@@ -197,6 +197,48 @@ impl PackPreload {
     }
 }
 
+/// The object cache shared by the decode threads.
+type SharedCache = Arc<SyncObjectCache<Entry>>;
+
+/// The max length of the delta chains by default, much longer than the `--depth` of
+/// `git pack-objects`, 50 by default.
+pub const DEFAULT_MAX_DELTA_DEPTH: usize = 250;
+
+/// The number of the decode threads by default, one per CPU.
+pub fn default_decode_threads() -> usize {
+    num_cpus::get()
+}
+
+/// How a pack is decoded by [`decode_load_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// The threads, each of which resolves a range of the objects, 1 to decode them serially.
+    pub threads: usize,
+    /// Reject the deltas of which the chains are longer than this rather than resolve them, so
+    /// a malicious pack of the endless chains can't stall the decode.
+    pub max_delta_depth: usize,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            threads: default_decode_threads(),
+            max_delta_depth: DEFAULT_MAX_DELTA_DEPTH,
+        }
+    }
+}
+
+/// The state shared by the decode threads.
+#[derive(Clone)]
+struct DecodeContext {
+    data: Arc<RwLock<PackPreload>>,
+    storage: Arc<dyn ObjectStorage>,
+    counter: Arc<Mutex<DecodeCounter>>,
+    cache: SharedCache,
+    mr_id: i64,
+    max_delta_depth: usize,
+}
+
 /// Decide the preloaded objects, and store it .
 /// `decode_load` function used for decoding and loading data.
 ///
@@ -214,42 +256,119 @@ impl PackPreload {
 /// and `GitError` represents any potential error that might occur during the process.
 ///
 pub async fn decode_load(p: PackPreload, storage: Arc<dyn ObjectStorage>) -> Result<i64, GitError> {
+    decode_load_with(p, storage, DecodeOptions::default()).await
+}
+
+/// Decode like [`decode_load`] by the options.
+///
+/// The chains of the offset deltas are resolved from the preloaded data, so the ranges are
+/// independent, while the resolved objects are shared by the cache of all the threads. A ref
+/// delta of which the base is decoded by another thread meanwhile is deferred, and resolved
+/// after all the threads, when the base is saved.
+pub async fn decode_load_with(
+    p: PackPreload,
+    storage: Arc<dyn ObjectStorage>,
+    options: DecodeOptions,
+) -> Result<i64, GitError> {
+    let decode_start = Instant::now();
     let decode_counter: Arc<Mutex<DecodeCounter>> = Arc::new(Mutex::new(DecodeCounter::default()));
     let all_len = p.len();
     tracing::info!("Decode the preload git object\n{}", p.counter);
-    let (cpu_number, chunk) = thread_chunk(all_len);
+    let (base_objects, delta_objects) = (p.counter.base_count(), p.counter.delta_count());
+    let (cpu_number, chunk) = thread_chunk(all_len, options.threads.max(1));
     tracing::info!("Deal with the object using {} threads. ", cpu_number);
     let share: Arc<RwLock<PackPreload>> = Arc::new(RwLock::new(p));
 
+    let mut object_cache_size = 1000;
+    utils::get_env_number("GIT_INTERNAL_DECODE_CACHE_SIZE", &mut object_cache_size);
+    // as large as the caches of the threads were
+    let cache: SharedCache = Arc::new(SyncObjectCache::new(Some(object_cache_size * cpu_number)));
+
     let mr_id = generate_id();
+    let context = DecodeContext {
+        data: share,
+        storage,
+        counter: decode_counter.clone(),
+        cache: cache.clone(),
+        mr_id,
+        max_delta_depth: options.max_delta_depth,
+    };
 
     let producer_handles: Vec<_> = (0..cpu_number)
         .map(|i| {
-            let shard_clone = Arc::clone(&share);
-            let st_clone = storage.clone();
-            let counter_clone = decode_counter.clone();
+            let context = context.clone();
             let begin = i * chunk;
             let end = if i == cpu_number - 1 {
                 all_len
             } else {
                 (i + 1) * chunk
             };
-            tokio::spawn(async move {
-                produce_object(shard_clone, st_clone, begin, end, counter_clone, mr_id).await;
-            })
+            tokio::spawn(
+                async move { produce_object(&context, (begin..end).collect(), true).await },
+            )
         })
         .collect();
 
+    let mut deferred = Vec::new();
+    let mut result = Ok(());
     for handle in producer_handles {
-        let _ = handle.await;
+        match handle.await {
+            Ok(Ok(objects)) => deferred.extend(objects),
+            // wait for all the threads, and report the first error
+            Ok(Err(e)) => result = result.and(Err(e)),
+            Err(e) => {
+                result = result.and(Err(GitError::InvalidPackFile(format!(
+                    "decode thread failed: {}",
+                    e
+                ))))
+            }
+        }
     }
+    result?;
+    resolve_deferred(&context, deferred).await?;
 
+    let cache_stats = cache.stats();
     let re = decode_counter.lock().unwrap();
-    tracing::info!("Summary : {}", re);
+    tracing::info!(
+        objects = all_len,
+        base_objects,
+        delta_objects,
+        cache_hits = cache_stats.hits,
+        cache_misses = cache_stats.misses,
+        cache_evictions = cache_stats.evictions,
+        elapsed_ms = decode_start.elapsed().as_millis() as u64,
+        "Decoded the pack, {}",
+        re
+    );
+    let metrics = metrics::metrics();
+    metrics
+        .pack_decode_seconds
+        .observe(decode_start.elapsed().as_secs_f64());
+    metrics.set_cache_stats(&cache_stats);
 
     Ok(mr_id)
 }
 
+/// Resolve the deferred ref deltas until all are, each round resolves the ones of which the
+/// bases are saved by the last round. It fails of the missing base once a round resolves none.
+async fn resolve_deferred(
+    context: &DecodeContext,
+    mut deferred: Vec<usize>,
+) -> Result<(), GitError> {
+    while !deferred.is_empty() {
+        tracing::debug!("Resolving {} deferred ref deltas", deferred.len());
+        let count = deferred.len();
+        let left = produce_object(context, deferred, true).await?;
+        if left.len() == count {
+            // none is resolved, so the error of the missing base
+            produce_object(context, left, false).await?;
+            break;
+        }
+        deferred = left;
+    }
+    Ok(())
+}
+
 use super::counter::CounterType::*;
 /// Asynchronous function to produce Git objects.
 ///
@@ -257,28 +376,30 @@ use super::counter::CounterType::*;
 ///
 /// # Arguments
 ///
-/// - `data`: A shared `Arc<RwLock<PackPreload>>` containing the preload data.
-/// - `storage`: A shared `Arc<dyn ObjectStorage>` trait object providing storage capabilities.
-/// - `range_begin`: The starting index of the range of entries to process.
-/// - `range_end`: The ending index of the range of entries to process.
-/// - `counter`: A shared `Arc<Mutex<DecodeCounter>>` for counting decode operations.
-/// - `mr_id`: An identifier for the produced Git objects.
+/// - `context`: The preload data, the storage, the counter and the cache shared by the threads.
+/// - `objects`: The indexes of the entries to process, in order.
+/// - `defer`: Defer the ref deltas of which the bases are not found, rather than fail.
 ///
+/// Returns the indexes of the deferred ref deltas, or the error of a delta of which the base is
+/// neither in the pack nor in the storage, or of which the chain is too long.
 async fn produce_object(
-    data: Arc<RwLock<PackPreload>>,
-    storage: Arc<dyn ObjectStorage>,
-    range_begin: usize,
-    range_end: usize,
-    counter: Arc<Mutex<DecodeCounter>>,
-    mr_id: i64,
-) {
+    context: &DecodeContext,
+    objects: Vec<usize>,
+    defer: bool,
+) -> Result<Vec<usize>, GitError> {
+    let DecodeContext {
+        data,
+        storage,
+        counter,
+        cache,
+        mr_id,
+        max_delta_depth,
+    } = context;
+    let (mr_id, max_delta_depth) = (*mr_id, *max_delta_depth);
     let mut mr_to_obj_model = Vec::<mr::ActiveModel>::with_capacity(1001);
     let mut git_obj_model = Vec::<git_obj::ActiveModel>::with_capacity(1001);
+    let mut deferred = Vec::new();
 
-    let mut object_cache_size = 1000;
-    utils::get_env_number("GIT_INTERNAL_DECODE_CACHE_SIZE", &mut object_cache_size);
-
-    let mut cache: ObjectCache<Entry> = ObjectCache::new(Some(object_cache_size));
     let start = Instant::now();
     let mut batch_size = 10000;
     utils::get_env_number("GIT_INTERNAL_DECODE_STORAGE_BATCH_SIZE", &mut batch_size);
@@ -290,39 +411,54 @@ async fn produce_object(
     );
 
     let mut save_queue: CircularQueue<_> = CircularQueue::new(save_task_wait_number);
-    for i in range_begin..range_end {
+    for i in objects {
         let read_auth = data.read().await;
         let e = &read_auth.entries[i];
 
         let result_entity;
         match e.header {
             EntryHeader::RefDelta { base_id } => {
-                let base_type;
-                let base_data = if let Some(b_obj) = cache.get_by_hash(base_id) {
-                    {
-                        counter.lock().unwrap().count(CacheHit);
-                    }
-                    base_type = b_obj.header;
-                    b_obj.data
-                } else {
-                    let db_obj = storage
-                        .get_obj_data_by_id(&base_id.to_plain_str())
-                        .await
-                        .unwrap()
-                        .unwrap();
-                    base_type = EntryHeader::from_string(&db_obj.object_type);
-                    {
-                        counter.lock().unwrap().count(DB);
-                    }
-                    db_obj.data
-                };
-
-                let re = undelta(&mut Cursor::new(e.data.clone()), &base_data);
+                let (base_type, base_data, base_depth) =
+                    if let Some(b_obj) = cache.get_by_hash(base_id) {
+                        {
+                            counter.lock().unwrap().count(CacheHit);
+                        }
+                        (b_obj.header, b_obj.data, b_obj.depth)
+                    } else {
+                        // the base of a thin pack is not in the pack, but the storage
+                        let db_obj = storage
+                            .get_obj_data_by_id(&base_id.to_plain_str())
+                            .await
+                            .map_err(|e| GitError::DeltaObjectError(e.to_string()))?;
+                        let Some(db_obj) = db_obj else {
+                            if defer {
+                                // the base may be decoded by another thread meanwhile
+                                deferred.push(i);
+                                continue;
+                            }
+                            return Err(GitError::DeltaObjectError(format!(
+                                "can't find the base {} in the pack or the storage",
+                                base_id
+                            )));
+                        };
+                        ObjectType::from_string(&db_obj.object_type)?;
+                        {
+                            counter.lock().unwrap().count(DB);
+                        }
+                        (
+                            EntryHeader::from_string(&db_obj.object_type),
+                            db_obj.data,
+                            0,
+                        )
+                    };
+
+                let re = try_undelta(&mut Cursor::new(&e.data), &base_data)?;
                 let undelta_obj = Entry {
                     header: base_type,
                     offset: e.offset,
                     data: re,
                     hash: None,
+                    depth: base_depth + 1,
                 };
                 result_entity = compute_hash(undelta_obj);
                 {
@@ -330,7 +466,9 @@ async fn produce_object(
                 }
             }
             EntryHeader::OfsDelta { base_distance: _ } => {
-                let re_obj = delta_offset_obj(data.clone(), e, &mut cache, counter.clone()).await;
+                let re_obj =
+                    delta_offset_obj(data.clone(), e, cache, counter.clone(), 0, max_delta_depth)
+                        .await?;
                 result_entity = compute_hash(re_obj);
                 {
                     counter.lock().unwrap().count(Delta);
@@ -343,7 +481,10 @@ async fn produce_object(
                 result_entity = compute_hash(e.clone());
             }
         }
-        cache.put(e.offset, result_entity.hash.unwrap(), result_entity.clone());
+        if result_entity.depth > max_delta_depth {
+            return Err(too_deep(e.offset, max_delta_depth));
+        }
+        cache.put(e.offset, result_entity.hash.unwrap(), result_entity.clone())?;
         mr_to_obj_model.push(result_entity.clone().convert_to_mr_model(mr_id));
         git_obj_model.push(result_entity.convert_to_data_model());
 
@@ -355,11 +496,11 @@ async fn produce_object(
                 stc.save_mr_objects(mr_to_obj_model).await.unwrap();
                 stc.save_obj_data(git_obj_model).await.unwrap();
             });
-            println!("put new batch ");
+            tracing::debug!("Saving a batch of git objects");
             // if the save queue if full , wait the fist queue finish
             if save_queue.is_full() {
                 let first_h: tokio::task::JoinHandle<()> = save_queue.dequeue().unwrap();
-                println!("to await from full queue ");
+                tracing::debug!("Waiting for the full save queue");
                 first_h.await.unwrap();
                 save_queue.enqueue(h).unwrap();
             } else {
@@ -376,12 +517,13 @@ async fn produce_object(
         storage.save_obj_data(git_obj_model).await.unwrap();
     }
     // await the remaining threads
-    println!("await last thread");
+    tracing::debug!("Waiting for the last saves");
     while let Some(h) = save_queue.dequeue() {
         h.await.unwrap();
     }
     let end = start.elapsed().as_millis();
     tracing::info!("Git Object Produce thread one  time cost:{} ms", end);
+    Ok(deferred)
 }
 
 /// Asynchronous function to perform delta offset operation.
@@ -392,20 +534,28 @@ async fn produce_object(
 ///
 /// - `data`: A shared `Arc<RwLock<PackPreload>>` containing the preload data.
 /// - `delta_obj`: A reference to the `Entry` representing the delta object to process.
-/// - `cache`: A mutable reference to the `ObjectCache<Entry>` used for caching objects.
+/// - `cache`: The object cache shared by the threads.
 /// - `counter`: A shared `Arc<Mutex<DecodeCounter>>` for counting decode operations.
+/// - `above`: The number of the deltas of the chain above the delta object.
+/// - `max_depth`: The max length of the chain, which is not resolved deeper than it.
 ///
 /// # Returns
 ///
-/// The function returns an `Entry` representing the result of the delta offset operation.
+/// The function returns an `Entry` representing the result of the delta offset operation,
+/// or the error of an invalid offset or delta, or of a chain longer than the max depth.
 ///
 #[async_recursion] //TODO del recursion
 async fn delta_offset_obj(
     data: Arc<RwLock<PackPreload>>,
     delta_obj: &Entry,
-    cache: &mut ObjectCache<Entry>,
+    cache: &SharedCache,
     counter: Arc<Mutex<DecodeCounter>>,
-) -> Entry {
+    above: usize,
+    max_depth: usize,
+) -> Result<Entry, GitError> {
+    if above >= max_depth {
+        return Err(too_deep(delta_obj.offset, max_depth));
+    }
     let share = data.read().await;
     if let EntryHeader::OfsDelta { base_distance } = delta_obj.header {
         let basic_type;
@@ -418,35 +568,50 @@ async fn delta_offset_obj(
             buff_obj = b_obj;
             base_obj = &buff_obj;
         } else {
-            let pos = share.map.get(&base_distance).unwrap();
+            let pos = share.map.get(&base_distance).ok_or_else(|| {
+                GitError::DeltaObjectError(format!("no object at the offset {}", base_distance))
+            })?;
             base_obj = &share.entries[*pos];
         }
 
         let re;
+        let depth;
         // check its weather need to deeper recursion
         if !base_obj.header.is_base() {
             {
                 counter.lock().unwrap().count(Depth);
             }
-            let d_obj = delta_offset_obj(data.clone(), base_obj, cache, counter).await;
-            re = undelta(&mut Cursor::new(&delta_obj.data), &d_obj.data);
+            let d_obj =
+                delta_offset_obj(data.clone(), base_obj, cache, counter, above + 1, max_depth)
+                    .await?;
+            re = try_undelta(&mut Cursor::new(&delta_obj.data), &d_obj.data)?;
             basic_type = d_obj.header;
+            depth = d_obj.depth + 1;
         } else {
             basic_type = base_obj.header.clone();
-            re = undelta(&mut Cursor::new(&delta_obj.data), &base_obj.data);
+            re = try_undelta(&mut Cursor::new(&delta_obj.data), &base_obj.data)?;
+            depth = base_obj.depth + 1;
         }
 
-        Entry {
+        Ok(Entry {
             header: basic_type,
             offset: delta_obj.offset,
             data: re,
             hash: None,
-        }
+            depth,
+        })
     } else {
         panic!("cat't call by base obj ");
     }
 }
 
+fn too_deep(offset: usize, max_depth: usize) -> GitError {
+    GitError::DeltaObjectError(format!(
+        "the delta chain of the object at the offset {} is longer than {}",
+        offset, max_depth
+    ))
+}
+
 fn compute_hash(mut e: Entry) -> Entry {
     match e.header {
         EntryHeader::RefDelta { base_id: _ } => panic!("this methon can't call by delta"),
@@ -454,19 +619,18 @@ fn compute_hash(mut e: Entry) -> Entry {
         _ => (),
     }
 
-    let mut h = Sha1::new();
+    let mut h = Sha1::default();
     h.update(e.header.to_bytes());
     h.update(b" ");
-    h.update(e.data.len().to_string());
+    h.update(e.data.len().to_string().as_bytes());
     h.update(b"\0");
     h.update(&e.data);
-    let re: [u8; 20] = h.finalize().into();
-    e.hash = Some(Hash(re));
+    let re: [u8; 20] = h.finalize();
+    e.hash = Some(Hash::Sha1(re));
     e
 }
 
-fn thread_chunk(len: usize) -> (usize, usize) {
-    let cpu_number: usize = num_cpus::get();
+fn thread_chunk(len: usize, cpu_number: usize) -> (usize, usize) {
     if len < cpu_number {
         (cpu_number, 0)
     } else {
@@ -476,11 +640,45 @@ fn thread_chunk(len: usize) -> (usize, usize) {
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::BufReader, path::Path};
-
-    use crate::internal::pack::preload::PackPreload;
+    use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use tokio::sync::RwLock;
+
+    use crate::errors::GitError;
+    use crate::internal::object::blob::Blob;
+    use crate::internal::object::ObjectT;
+    use crate::internal::pack::cache::SyncObjectCache;
+    use crate::internal::pack::counter::DecodeCounter;
+    use crate::internal::pack::encode::write_pack;
+    use crate::internal::pack::preload::{
+        decode_load, decode_load_with, produce_object, resolve_deferred, DecodeContext,
+        DecodeOptions, PackPreload, DEFAULT_MAX_DELTA_DEPTH,
+    };
+    use crate::internal::pack::tests::{thin_pack_base, MemoryStorage, REF_DELTA_PACK, THIN_PACK};
     use tokio::test;
 
+    const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+
+    /// The objects decoded of the pack by the threads.
+    async fn decode(pack: &str, threads: usize) -> HashMap<String, (String, Vec<u8>)> {
+        let storage = Arc::new(MemoryStorage::default());
+        let preload = PackPreload::new(BufReader::new(File::open(pack).unwrap()));
+        let options = DecodeOptions {
+            threads,
+            ..Default::default()
+        };
+        decode_load_with(preload, storage.clone(), options)
+            .await
+            .unwrap();
+        let objects = storage.objects.lock().unwrap();
+        objects.clone()
+    }
+
     #[test]
     async fn preload_read_decode() {
         
@@ -493,6 +691,107 @@ mod tests {
        
     }
     
+    #[test]
+    async fn test_decode_thin_pack() {
+        let storage = Arc::new(MemoryStorage::with_objects(vec![thin_pack_base()]));
+        let preload = PackPreload::new(BufReader::new(File::open(THIN_PACK).unwrap()));
+        decode_load(preload, storage.clone()).await.unwrap();
+        let objects = storage.objects.lock().unwrap();
+        let (object_type, blob) = &objects["5e45ac99cbd565a48b7a70b8ebf683d9afdd0ea2"];
+        assert_eq!(object_type, "blob");
+        assert!(String::from_utf8_lossy(blob).contains("line five hundred\n"));
+    }
+
+    #[test]
+    async fn test_decode_thin_pack_without_base() {
+        let storage = Arc::new(MemoryStorage::default());
+        let preload = PackPreload::new(BufReader::new(File::open(THIN_PACK).unwrap()));
+        let err = decode_load(preload, storage).await.unwrap_err();
+        assert!(matches!(err, GitError::DeltaObjectError(_)));
+        assert!(err
+            .to_string()
+            .contains("79866961e2b34d03e6cd066635a487466491f9d7"));
+    }
+
+    #[test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_decode_threads() {
+        for (pack, count) in [(PACK, 324), (REF_DELTA_PACK, 90)] {
+            let serial = decode(pack, 1).await;
+            assert_eq!(serial.len(), count);
+            for threads in [2, 4, 7] {
+                assert_eq!(decode(pack, threads).await, serial, "{} threads", threads);
+            }
+        }
+    }
+
+    #[test]
+    async fn test_deferred_ref_deltas() {
+        let storage = Arc::new(MemoryStorage::default());
+        let preload = PackPreload::new(BufReader::new(File::open(REF_DELTA_PACK).unwrap()));
+        let objects = (0..preload.len()).rev().collect();
+        let context = DecodeContext {
+            data: Arc::new(RwLock::new(preload)),
+            storage: storage.clone(),
+            counter: Arc::<Mutex<DecodeCounter>>::default(),
+            cache: Arc::new(SyncObjectCache::new(Some(1000))),
+            mr_id: 1,
+            max_delta_depth: DEFAULT_MAX_DELTA_DEPTH,
+        };
+        // the deltas before the bases, as decoded by the other threads
+        let deferred = produce_object(&context, objects, true).await.unwrap();
+        assert!(!deferred.is_empty());
+        resolve_deferred(&context, deferred).await.unwrap();
+        let serial = decode(REF_DELTA_PACK, 1).await;
+        assert_eq!(*storage.objects.lock().unwrap(), serial);
+    }
+
+    #[test]
+    async fn test_max_delta_depth() {
+        // each blob a line shorter than the last, so each is the delta of the last
+        let lines: Vec<String> = (0..100).map(|i| format!("line {}\n", i)).collect();
+        let blobs: Vec<Arc<dyn ObjectT>> = (0..40)
+            .map(|i| -> Arc<dyn ObjectT> {
+                let data = format!("version {}\n{}", i, lines[..100 - i].concat());
+                Arc::new(Blob::new_from_data(data.into_bytes()))
+            })
+            .collect();
+        let pack = write_pack(blobs, 1, 100).unwrap();
+        let decode = |max_delta_depth| {
+            let preload = PackPreload::new(Cursor::new(pack.clone()));
+            let options = DecodeOptions {
+                threads: 1,
+                max_delta_depth,
+            };
+            decode_load_with(preload, Arc::new(MemoryStorage::default()), options)
+        };
+
+        let err = decode(10).await.unwrap_err();
+        assert!(matches!(err, GitError::DeltaObjectError(_)));
+        assert!(err.to_string().contains("longer than 10"), "{}", err);
+        // the chain of the 40 blobs
+        assert!(decode(38).await.is_err());
+        decode(39).await.unwrap();
+        decode(DEFAULT_MAX_DELTA_DEPTH).await.unwrap();
+    }
+
+    /// Compare the decode time of the threads, e.g. of a large pack by `MEGA_BENCH_PACK`:
+    /// `cargo test -p git --release -- --ignored bench_decode_threads --nocapture`.
+    #[test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_decode_threads() {
+        let pack = std::env::var("MEGA_BENCH_PACK").unwrap_or(PACK.to_owned());
+        for threads in [1, 2, 4, 8] {
+            let start = Instant::now();
+            let objects = decode(&pack, threads).await;
+            println!(
+                "{} objects by {} threads: {} ms",
+                objects.len(),
+                threads,
+                start.elapsed().as_millis()
+            );
+        }
+    }
+
     #[test]
     #[ignore]
     async fn test_demo_channel() {
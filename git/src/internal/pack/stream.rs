@@ -0,0 +1,342 @@
+//! Decode a pack from a stream object by object, so a push of gigabytes is not buffered in
+//! memory the way of [`PackPreload`](super::preload::PackPreload).
+//!
+//! The resolved objects are kept in an [`ObjectCache`] by the offset and the hash, in which the
+//! bases of the deltas are looked up as they arrive. The offsets of the evicted objects are
+//! still known, so the bases of the back-references are loaded from the storage then, as are
+//! the bases not in the pack.
+
+use std::io::{self, BufReader, Cursor, Read};
+use std::sync::Arc;
+
+use database::driver::ObjectStorage;
+
+use super::cache::{_Cache, CacheStats, ObjectCache};
+use super::decode::HashCounter;
+use super::delta::undelta;
+use super::Pack;
+use crate::errors::GitError;
+use crate::hash::Hash;
+use crate::internal::zlib::stream::inflate::ReadPlain;
+use crate::internal::ObjectType;
+use crate::utils;
+
+/// An object of the pack with the deltas applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedObject {
+    pub object_type: ObjectType,
+    pub hash: Hash,
+    /// The offset of the entry in the pack.
+    pub offset: usize,
+    pub data: Vec<u8>,
+    /// The base of a delta, the object of which it's resolved.
+    pub base: Option<Hash>,
+    /// The length of the delta chain to the object, 0 of a base object.
+    pub depth: usize,
+}
+
+impl DecodedObject {
+    fn new(object_type: ObjectType, offset: usize, data: Vec<u8>) -> DecodedObject {
+        let mut h = Hash::hasher();
+        h.update(object_type.to_bytes());
+        h.update(b" ");
+        h.update(data.len().to_string().as_bytes());
+        h.update(b"\0");
+        h.update(&data);
+        let hash = h.finalize();
+        DecodedObject {
+            object_type,
+            hash,
+            offset,
+            data,
+            base: None,
+            depth: 0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeProgress {
+    pub processed: usize,
+    pub total: usize,
+}
+
+impl std::fmt::Display for DecodeProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.processed, self.total)
+    }
+}
+
+fn io_error(e: io::Error) -> GitError {
+    GitError::InvalidPackFile(e.to_string())
+}
+
+pub struct PackStream<R> {
+    reader: HashCounter<BufReader<R>>,
+    progress: DecodeProgress,
+    cache: ObjectCache<Arc<DecodedObject>>,
+    storage: Option<Arc<dyn ObjectStorage>>,
+    /// The checksum at the end is verified.
+    finished: bool,
+}
+
+impl<R: Read> PackStream<R> {
+    /// Reads the header of the pack, the objects are decoded by [`PackStream::next_object`].
+    pub fn new(r: R) -> Result<Self, GitError> {
+        let mut reader = HashCounter::new(BufReader::new(r), true);
+        let pack = Pack::check_header(&mut reader)?;
+        Ok(PackStream {
+            reader,
+            progress: DecodeProgress {
+                processed: 0,
+                total: pack.number_of_objects(),
+            },
+            cache: ObjectCache::new(None),
+            storage: None,
+            finished: false,
+        })
+    }
+
+    /// Keep at most `size` resolved objects rather than the `MEGA_PACK_CACHE_SIZE`.
+    pub fn with_cache_size(mut self, size: usize) -> Self {
+        self.cache = ObjectCache::new(Some(size));
+        self
+    }
+
+    /// The storage of the bases not in the cache, e.g. the objects saved by the earlier pushes.
+    pub fn set_storage(&mut self, s: Option<Arc<dyn ObjectStorage>>) {
+        self.storage = s;
+    }
+
+    pub fn progress(&self) -> DecodeProgress {
+        self.progress
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// The next object in the pack, `None` after the last one, of which the checksum of the
+    /// whole pack is verified.
+    pub async fn next_object(&mut self) -> Result<Option<Arc<DecodedObject>>, GitError> {
+        if self.progress.processed == self.progress.total {
+            if !self.finished {
+                self.verify_checksum()?;
+                self.finished = true;
+                let stats = self.cache.stats();
+                tracing::info!(
+                    objects = self.progress.total,
+                    cache_hits = stats.hits,
+                    cache_misses = stats.misses,
+                    cache_evictions = stats.evictions,
+                    "Decoded the pack stream"
+                );
+            }
+            return Ok(None);
+        }
+        if self.progress.processed.is_multiple_of(10000) {
+            tracing::info!("Decoding git objects: {}", self.progress);
+        }
+
+        let offset = self.reader.position();
+        let (type_num, _) = utils::read_type_and_size(&mut self.reader).map_err(io_error)?;
+        let object = match ObjectType::number2type(type_num)? {
+            ObjectType::OffsetDelta => {
+                let distance = utils::read_offset_encoding(&mut self.reader, &mut 0)
+                    .map_err(io_error)? as usize;
+                let base_offset = offset.checked_sub(distance).ok_or_else(|| {
+                    GitError::InvalidObjectInfo("Invalid OffsetDelta offset".to_string())
+                })?;
+                let base = self.base_by_offset(base_offset).await?;
+                self.undelta(&base, offset)?
+            }
+            ObjectType::HashDelta => {
+                let hash = utils::read_hash(&mut self.reader).map_err(io_error)?;
+                let base = self.base_by_hash(hash).await?;
+                self.undelta(&base, offset)?
+            }
+            object_type => DecodedObject::new(object_type, offset, self.inflate()?),
+        };
+
+        let object = Arc::new(object);
+        self.cache.put(offset, object.hash, object.clone())?;
+        self.progress.processed += 1;
+        Ok(Some(object))
+    }
+
+    fn inflate(&mut self) -> Result<Vec<u8>, GitError> {
+        let mut data = Vec::new();
+        ReadPlain::new(&mut self.reader)
+            .read_to_end(&mut data)
+            .map_err(io_error)?;
+        Ok(data)
+    }
+
+    fn undelta(&mut self, base: &DecodedObject, offset: usize) -> Result<DecodedObject, GitError> {
+        let delta = self.inflate()?;
+        let data = undelta(&mut Cursor::new(delta), &base.data);
+        Ok(DecodedObject {
+            base: Some(base.hash),
+            depth: base.depth + 1,
+            ..DecodedObject::new(base.object_type, offset, data)
+        })
+    }
+
+    async fn base_by_offset(&mut self, offset: usize) -> Result<Arc<DecodedObject>, GitError> {
+        if let Some(base) = self.cache.get(offset) {
+            return Ok(base);
+        }
+        // evicted from the cache, which knows the hash of every offset
+        let hash = self.cache.get_hash(offset).ok_or_else(|| {
+            GitError::DeltaObjectError(format!("no object at the offset {}", offset))
+        })?;
+        self.load_base(hash).await
+    }
+
+    async fn base_by_hash(&mut self, hash: Hash) -> Result<Arc<DecodedObject>, GitError> {
+        match self.cache.get_by_hash(hash) {
+            Some(base) => Ok(base),
+            None => self.load_base(hash).await,
+        }
+    }
+
+    async fn load_base(&mut self, hash: Hash) -> Result<Arc<DecodedObject>, GitError> {
+        let Some(storage) = &self.storage else {
+            return Err(GitError::DeltaObjectError(format!(
+                "can't find the base {} without a storage",
+                hash
+            )));
+        };
+        let model = storage
+            .get_obj_data_by_id(&hash.to_plain_str())
+            .await
+            .map_err(|e| GitError::DeltaObjectError(e.to_string()))?
+            .ok_or_else(|| {
+                GitError::DeltaObjectError(format!(
+                    "can't find the base {} in the pack or the storage",
+                    hash
+                ))
+            })?;
+        let object_type = ObjectType::from_string(&model.object_type)?;
+        Ok(Arc::new(DecodedObject {
+            object_type,
+            hash,
+            offset: 0,
+            data: model.data,
+            base: None,
+            depth: 0,
+        }))
+    }
+
+    fn verify_checksum(&mut self) -> Result<(), GitError> {
+        let hash = self.reader.final_hash();
+        let mut tail = [0u8; 20];
+        self.reader.read_exact(&mut tail).map_err(io_error)?;
+        let signature = Hash::new_from_bytes(&tail);
+        if hash != signature {
+            return Err(GitError::InvalidPackFile(format!(
+                "the checksum {} is not {}",
+                signature, hash
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::{BufReader, Cursor, Read};
+    use std::sync::Arc;
+
+    use database::driver::ObjectStorage;
+
+    use super::{DecodeProgress, PackStream};
+    use crate::errors::GitError;
+    use crate::internal::pack::preload::{decode_load, PackPreload};
+    use crate::internal::pack::tests::{thin_pack_base, MemoryStorage, THIN_PACK};
+
+    /// Of the deltas in chains at most 7 deep.
+    const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+
+    /// A source returning at most a few bytes at a time, like a slow network.
+    struct Chunked(Cursor<Vec<u8>>);
+
+    impl Read for Chunked {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(7);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    type Objects = BTreeMap<String, (String, Vec<u8>)>;
+
+    async fn decode_stream(
+        data: Vec<u8>,
+        cache_size: usize,
+        storage: Option<Arc<dyn ObjectStorage>>,
+    ) -> Result<(Objects, DecodeProgress), GitError> {
+        let mut stream = PackStream::new(Chunked(Cursor::new(data)))?.with_cache_size(cache_size);
+        stream.set_storage(storage);
+        let mut objects = BTreeMap::new();
+        while let Some(object) = stream.next_object().await? {
+            objects.insert(
+                object.hash.to_plain_str(),
+                (object.object_type.to_string(), object.data.clone()),
+            );
+        }
+        Ok((objects, stream.progress()))
+    }
+
+    #[tokio::test]
+    async fn test_decode_in_chunks() {
+        let data = std::fs::read(PACK).unwrap();
+        let (streamed, progress) = decode_stream(data.clone(), 1000, None).await.unwrap();
+        assert_eq!(progress.processed, progress.total);
+        assert_eq!(streamed.len(), progress.total);
+
+        // the same objects as the buffered path
+        let storage = Arc::new(MemoryStorage::default());
+        let preload = PackPreload::new(BufReader::new(Cursor::new(data.clone())));
+        decode_load(preload, storage.clone()).await.unwrap();
+        let buffered: Objects = storage
+            .objects
+            .lock()
+            .unwrap()
+            .clone()
+            .into_iter()
+            .collect();
+        assert_eq!(streamed, buffered);
+
+        // the evicted bases are loaded from the storage
+        assert!(decode_stream(data.clone(), 1, None).await.is_err());
+        let (evicted, _) = decode_stream(data, 1, Some(storage)).await.unwrap();
+        assert_eq!(evicted, buffered);
+    }
+
+    #[tokio::test]
+    async fn test_decode_corrupted_checksum() {
+        let mut data = std::fs::read(PACK).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert!(matches!(
+            decode_stream(data, 1000, None).await,
+            Err(GitError::InvalidPackFile(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decode_thin_pack() {
+        let data = std::fs::read(THIN_PACK).unwrap();
+        let err = decode_stream(data.clone(), 1000, None).await.unwrap_err();
+        assert!(matches!(err, GitError::DeltaObjectError(_)));
+
+        let storage = MemoryStorage::with_objects(vec![thin_pack_base()]);
+        let (objects, _) = decode_stream(data, 1000, Some(Arc::new(storage)))
+            .await
+            .unwrap();
+        let (object_type, blob) = &objects["5e45ac99cbd565a48b7a70b8ebf683d9afdd0ea2"];
+        assert_eq!(object_type, "blob");
+        assert!(String::from_utf8_lossy(blob).contains("line five hundred\n"));
+    }
+}
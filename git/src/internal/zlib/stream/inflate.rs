@@ -2,7 +2,7 @@ use std::{io, io::BufRead};
 
 use crate::internal::ObjectType;
 use flate2::{Decompress, FlushDecompress, Status};
-use sha1::{digest::core_api::CoreWrapper, Digest, Sha1};
+use crate::hash::backend::{Sha1, Sha1Backend};
 /// ReadBoxed is to unzip information from a  DEFLATE stream,
 /// which hash [`BufRead`] trait.
 /// For a continuous stream of DEFLATE information, the structure
@@ -15,7 +15,7 @@ pub struct ReadBoxed<R> {
     pub decompressor: Box<Decompress>,
     /// the [`_count_hash`] decide whether to calculate the hash value in the [`read`] method
     _count_hash: bool,
-    pub hash: CoreWrapper<sha1::Sha1Core>,
+    pub hash: Sha1,
 }
 impl<R> ReadBoxed<R>
 where
@@ -24,10 +24,10 @@ where
     /// Nen a ReadBoxed for zlib read, the Output ReadBoxed is for the Common Object,
     /// but not for the Delta Object,if that ,see new_for_delta method below.
     pub fn new(inner: R, obj_type: ObjectType, size: usize) -> Self {
-        let mut hash = sha1::Sha1::new();
+        let mut hash = Sha1::default();
         hash.update(obj_type.to_bytes());
         hash.update(b" ");
-        hash.update(size.to_string());
+        hash.update(size.to_string().as_bytes());
         hash.update(b"\0");
         ReadBoxed {
             inner,
@@ -40,7 +40,7 @@ where
     pub fn new_for_delta(inner: R) -> Self {
         ReadBoxed {
             inner,
-            hash: Sha1::new(),
+            hash: Sha1::default(),
             _count_hash: false,
             decompressor: Box::new(Decompress::new(true)),
         }
@@ -2,21 +2,28 @@
 //!
 //!
 use std::collections::HashMap;
-use std::io::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use anyhow::Result;
 use axum::body::Body;
-use axum::http::{Response, StatusCode};
-use bytes::{BufMut, BytesMut};
+use axum::http::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, RANGE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, Response, StatusCode};
+use bytes::{Bytes, BytesMut};
 use chrono::{prelude::*, Duration};
-use database::driver::lfs::storage::{ContentStore, MetaObject};
+use common::errors::GitLFSError;
+use database::driver::lfs::storage::{ByteStream, LfsStorage, MetaObject};
 use database::driver::lfs::structs::BatchResponse;
 use database::driver::lfs::structs::*;
 use futures::StreamExt;
 use hyper::Request;
 use rand::prelude::*;
+use sha2::{Digest, Sha256};
 
 use super::LfsConfig;
+use crate::metrics;
+use crate::protocol::http;
 
 pub async fn lfs_retrieve_lock(
     config: &LfsConfig,
@@ -28,15 +35,19 @@ pub async fn lfs_retrieve_lock(
         .as_ref()
         .unwrap_or(&"".to_string())
         .to_string();
+    let id = match lock_list_query.id.as_ref() {
+        Some(val) => val.to_owned(),
+        None => "".to_owned(),
+    };
     let path = match lock_list_query.path.as_ref() {
         Some(val) => val.to_owned(),
         None => "".to_owned(),
     };
-    let cursor = match lock_list_query.path.as_ref() {
+    let cursor = match lock_list_query.cursor.as_ref() {
         Some(val) => val.to_owned(),
         None => "".to_owned(),
     };
-    let limit = match lock_list_query.path.as_ref() {
+    let limit = match lock_list_query.limit.as_ref() {
         Some(val) => val.to_owned(),
         None => "".to_owned(),
     };
@@ -45,7 +56,7 @@ pub async fn lfs_retrieve_lock(
 
     let (locks, next_cursor, ok) = match config
         .storage
-        .lfs_get_filtered_locks(&repo, &path, &cursor, &limit)
+        .lfs_get_filtered_locks(&repo, &id, &path, &cursor, &limit)
         .await
     {
         Ok((locks, next)) => (locks, next, true),
@@ -68,7 +79,6 @@ pub async fn lfs_retrieve_lock(
     }
 
     let locks_response = serde_json::to_string(&lock_list).unwrap();
-    println!("{:?}", locks_response);
     let body = Body::from(locks_response);
 
     Ok(resp.body(body).unwrap())
@@ -82,18 +92,20 @@ pub async fn lfs_verify_lock(
     let mut resp = Response::builder();
     resp = resp.header("Content-Type", "application/vnd.git-lfs+json");
 
-    let (_parts, mut body) = req.into_parts();
+    let (parts, mut body) = req.into_parts();
+    let user = authenticated_user(&parts)?;
 
     let mut request_body = BytesMut::new();
 
     while let Some(chunk) = body.next().await {
         tracing::info!("client sends :{:?}", chunk);
-        let bytes = chunk.unwrap();
+        let bytes = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
         request_body.extend_from_slice(&bytes);
     }
 
     let verifiable_lock_request: VerifiableLockRequest =
-        serde_json::from_slice(request_body.freeze().as_ref()).unwrap();
+        serde_json::from_slice(request_body.freeze().as_ref())
+            .map_err(|e| unprocessable(&format!("Invalid verify request: {}", e)))?;
     let mut limit = verifiable_lock_request.limit.unwrap_or(0);
     if limit == 0 {
         limit = 100;
@@ -104,6 +116,7 @@ pub async fn lfs_verify_lock(
         .lfs_get_filtered_locks(
             &verifiable_lock_request.refs.name,
             "",
+            "",
             &verifiable_lock_request
                 .cursor
                 .unwrap_or("".to_string())
@@ -133,7 +146,7 @@ pub async fn lfs_verify_lock(
         lock_list.next_cursor = next_cursor;
 
         for lock in locks.iter() {
-            if Option::is_none(&lock.owner) {
+            if lock.owner.is_none() || lock.owner.as_ref() == Some(&user) {
                 lock_list.ours.push(lock.clone());
             } else {
                 lock_list.theirs.push(lock.clone());
@@ -155,23 +168,25 @@ pub async fn lfs_create_lock(
     let mut resp = Response::builder();
     resp = resp.header("Content-Type", "application/vnd.git-lfs+json");
 
-    let (_parts, mut body) = req.into_parts();
+    let (parts, mut body) = req.into_parts();
+    let user = authenticated_user(&parts)?;
 
     let mut request_body = BytesMut::new();
 
     while let Some(chunk) = body.next().await {
         tracing::info!("client sends :{:?}", chunk);
-        let bytes = chunk.unwrap();
+        let bytes = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
         request_body.extend_from_slice(&bytes);
     }
 
-    let lock_request: LockRequest = serde_json::from_slice(request_body.freeze().as_ref()).unwrap();
-    println!("{:?}", lock_request);
+    let lock_request: LockRequest = serde_json::from_slice(request_body.freeze().as_ref())
+        .map_err(|e| unprocessable(&format!("Invalid lock request: {}", e)))?;
     tracing::info!("acquired: {:?}", lock_request);
     let res = config
         .storage
         .lfs_get_filtered_locks(
             &lock_request.refs.name,
+            "",
             &lock_request.path.to_string(),
             "",
             "1",
@@ -190,8 +205,17 @@ pub async fn lfs_create_lock(
         ));
     }
 
-    if !locks.is_empty() {
-        return Err((StatusCode::CONFLICT, "Lock already exist".to_string()));
+    // Response the existing lock, so the client knows who holds it.
+    if let Some(lock) = locks.into_iter().next() {
+        let lock_response = LockResponse {
+            lock,
+            message: "Lock already exist".to_string(),
+        };
+        let lock_response = serde_json::to_string(&lock_response).unwrap();
+        return Ok(resp
+            .status(StatusCode::CONFLICT)
+            .body(Body::from(lock_response))
+            .unwrap());
     }
 
     let lock = Lock {
@@ -204,7 +228,7 @@ pub async fn lfs_create_lock(
             random_num
         },
         path: lock_request.path.to_owned(),
-        owner: None,
+        owner: Some(user),
         locked_at: {
             let locked_at: DateTime<Utc> = Utc::now();
             locked_at.to_rfc3339()
@@ -245,13 +269,14 @@ pub async fn lfs_delete_lock(
     let mut resp = Response::builder();
     resp = resp.header("Content-Type", "application/vnd.git-lfs+json");
 
-    let (_parts, mut body) = req.into_parts();
+    let (parts, mut body) = req.into_parts();
+    let user = authenticated_user(&parts)?;
 
     let mut request_body = BytesMut::new();
 
     while let Some(chunk) = body.next().await {
         tracing::info!("client sends :{:?}", chunk);
-        let bytes = chunk.unwrap();
+        let bytes = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
         request_body.extend_from_slice(&bytes);
     }
 
@@ -265,47 +290,38 @@ pub async fn lfs_delete_lock(
             "Deserialize operation failed!".to_string(),
         ));
     }
-    let unlock_request: UnlockRequest =
-        serde_json::from_slice(request_body.freeze().as_ref()).unwrap();
+    let unlock_request: UnlockRequest = serde_json::from_slice(request_body.freeze().as_ref())
+        .map_err(|e| unprocessable(&format!("Invalid unlock request: {}", e)))?;
 
     let res = config
         .storage
         .lfs_delete_lock(
             &unlock_request.refs.name,
-            None,
+            Some(user.name),
             id,
             unlock_request.force.unwrap_or(false),
         )
         .await;
 
-    let (deleted_lock, ok) = match res {
-        Ok(lock) => (lock, true),
-        Err(_) => (
-            Lock {
-                id: "".to_string(),
-                path: "".to_string(),
-                owner: None,
-                locked_at: { DateTime::<Utc>::MIN_UTC.to_rfc3339() },
-            },
-            false,
-        ),
+    let deleted_lock = match res {
+        Ok(lock) => lock,
+        Err(GitLFSError::NotFound(_)) => {
+            return Err((StatusCode::NOT_FOUND, "Unable to find lock!".to_string()));
+        }
+        Err(GitLFSError::Forbidden(_)) => {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Lock is owned by others, use force to delete it!".to_string(),
+            ));
+        }
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Delete operation failed!".to_string(),
+            ));
+        }
     };
 
-    if !ok {
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Delete operation failed!".to_string(),
-        ));
-    }
-
-    if deleted_lock.id.is_empty()
-        && deleted_lock.path.is_empty()
-        && deleted_lock.owner.is_none()
-        && deleted_lock.locked_at == DateTime::<Utc>::MIN_UTC.to_rfc3339()
-    {
-        return Err((StatusCode::NOT_FOUND, "Unable to find lock!".to_string()));
-    }
-
     let unlock_response = UnlockResponse {
         lock: deleted_lock,
         message: "".to_string(),
@@ -317,6 +333,52 @@ pub async fn lfs_delete_lock(
     Ok(resp.body(body).unwrap())
 }
 
+/// Get the user of the request checked by the auth layer, the locks are only of the users.
+fn authenticated_user(parts: &Parts) -> Result<User, (StatusCode, String)> {
+    parts.extensions.get::<User>().cloned().ok_or((
+        StatusCode::UNAUTHORIZED,
+        "Authentication required".to_string(),
+    ))
+}
+
+/// The oid is the SHA-256 of the content in lowercase hex.
+fn is_valid_oid(oid: &str) -> bool {
+    oid.len() == 64
+        && oid
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn is_valid_object(object: &RequestVars) -> bool {
+    is_valid_oid(&object.oid) && object.size >= 0
+}
+
+fn object_error(object: &RequestVars, code: i64, message: &str) -> Representation {
+    Representation {
+        oid: object.oid.to_owned(),
+        size: object.size,
+        authenticated: None,
+        actions: None,
+        error: Some(ObjectError {
+            code,
+            message: message.to_owned(),
+        }),
+    }
+}
+
+fn exceeds_limit(config: &LfsConfig, size: i64) -> bool {
+    config
+        .max_object_size
+        .is_some_and(|limit| size as u64 > limit)
+}
+
+fn unprocessable(message: &str) -> (StatusCode, String) {
+    (StatusCode::UNPROCESSABLE_ENTITY, message.to_owned())
+}
+
+/// The batch API of the `basic` transfer. The existing objects have the `download` action to
+/// download, and no action to upload. The missing ones have the `upload` and `verify` actions
+/// to upload, and the 404 error to download.
 pub async fn lfs_process_batch(
     config: &LfsConfig,
     req: Request<Body>,
@@ -330,11 +392,24 @@ pub async fn lfs_process_batch(
 
     while let Some(chunk) = body.next().await {
         tracing::info!("client sends :{:?}", chunk);
-        let bytes = chunk.unwrap();
+        let bytes = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
         request_body.extend_from_slice(&bytes);
     }
 
-    let mut batch_vars: BatchVars = serde_json::from_slice(request_body.freeze().as_ref()).unwrap();
+    let mut batch_vars: BatchVars = serde_json::from_slice(request_body.freeze().as_ref())
+        .map_err(|e| unprocessable(&format!("Invalid batch request: {}", e)))?;
+    let upload = match batch_vars.operation.as_str() {
+        "upload" => true,
+        "download" => false,
+        _ => {
+            return Err(unprocessable(
+                "Invalid operation, expect upload or download",
+            ))
+        }
+    };
+    if !batch_vars.transfers.is_empty() && !batch_vars.transfers.iter().any(|t| t == "basic") {
+        return Err(unprocessable("Only the basic transfer is supported"));
+    }
 
     let bvo = &mut batch_vars.objects;
     for request in bvo {
@@ -344,39 +419,59 @@ pub async fn lfs_process_batch(
 
     let mut response_objects = Vec::<Representation>::new();
 
-    // let db = Arc::new(state.storage.clone());
-    // let config = Arc::new(state.config.clone());
-
-    let server_url = format!("http://{}:{}", config.host, config.port);
+    // an IPv6 address is in brackets in the urls
+    let server_url = match config.host.parse::<std::net::Ipv6Addr>() {
+        Ok(_) => format!("http://[{}]:{}", config.host, config.port),
+        Err(_) => format!("http://{}:{}", config.host, config.port),
+    };
 
-    let content_store = ContentStore::new(config.lfs_content_path.to_owned());
     for object in batch_vars.objects {
+        if !is_valid_object(&object) {
+            response_objects.push(object_error(&object, 422, "Invalid oid or size"));
+            continue;
+        }
+        if upload && exceeds_limit(config, object.size) {
+            let message = format!(
+                "The size exceeds the limit of {} bytes",
+                config.max_object_size.unwrap_or_default()
+            );
+            response_objects.push(object_error(&object, 422, &message));
+            continue;
+        }
         let meta = config.storage.lfs_get_meta(&object).await;
 
         // Found
         let found = meta.is_ok();
         let mut meta = meta.unwrap_or_default();
-        if found && content_store.exist(&meta) {
-            response_objects.push(represent(&object, &meta, true, false, false, &server_url).await);
+        if found && config.lfs_storage.exist(&meta).await {
+            if upload {
+                // Nothing to upload, so no action.
+                response_objects
+                    .push(represent(&object, &meta, false, false, false, &server_url).await);
+                continue;
+            }
+            let mut rep = represent(&object, &meta, true, false, false, &server_url).await;
+            if let Some(href) = config.lfs_storage.download_url(&meta) {
+                // download from the storage backend directly, the url is authorized by itself
+                if let Some(link) = rep.actions.as_mut().and_then(|a| a.get_mut("download")) {
+                    link.href = href;
+                    link.header.remove("Authorization");
+                }
+            }
+            response_objects.push(rep);
             continue;
         }
 
         // Not found
-        if batch_vars.operation == "upload" {
-            meta = config.storage.lfs_put_meta(&object).await.unwrap();
-            response_objects.push(represent(&object, &meta, false, true, false, &server_url).await);
+        if upload {
+            meta = config
+                .storage
+                .lfs_put_meta(&object)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            response_objects.push(represent(&object, &meta, false, true, true, &server_url).await);
         } else {
-            let rep = Representation {
-                oid: object.oid.to_owned(),
-                size: object.size,
-                authenticated: None,
-                actions: None,
-                error: Some(ObjectError {
-                    code: 404,
-                    message: "Not found".to_owned(),
-                }),
-            };
-            response_objects.push(rep);
+            response_objects.push(object_error(&object, 404, "Not found"));
         }
     }
 
@@ -387,7 +482,6 @@ pub async fn lfs_process_batch(
     };
 
     let json = serde_json::to_string(&batch_response).unwrap();
-    //DEBUG
 
     let mut resp = Response::builder();
     resp = resp.status(200);
@@ -395,41 +489,88 @@ pub async fn lfs_process_batch(
 
     let body = Body::from(json);
     let resp = resp.body(body).unwrap();
-    println!("Sending: {:?}", resp);
+    tracing::debug!("Sending: {:?}", resp);
 
     Ok(resp)
 }
 
+/// The `verify` action after the upload, which confirms the whole object is in the storage.
+/// The meta of the object missing or of the other size is removed, so it's uploaded again.
+pub async fn lfs_verify_object(
+    config: &LfsConfig,
+    req: Request<Body>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let object: RequestVars = serde_json::from_slice(&body)
+        .map_err(|e| unprocessable(&format!("Invalid verify request: {}", e)))?;
+    if !is_valid_object(&object) {
+        return Err(unprocessable("Invalid oid or size"));
+    }
+
+    let meta = config
+        .storage
+        .lfs_get_meta(&object)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Object not found".to_owned()))?;
+    if meta.size != object.size || !config.lfs_storage.exist(&meta).await {
+        config.storage.lfs_delete_meta(&object).await.ok();
+        return Err((StatusCode::NOT_FOUND, "Object not uploaded".to_owned()));
+    }
+    Ok(Response::builder()
+        .header("Content-Type", "application/vnd.git-lfs+json")
+        .body(Body::empty())
+        .unwrap())
+}
+
 pub async fn lfs_upload_object(
     config: &LfsConfig,
     oid: &str,
     req: Request<Body>,
 ) -> Result<Response<Body>, (StatusCode, String)> {
     tracing::info!("req: {:?}", req);
-    // Load request parameters into struct.
-    let request_vars = RequestVars {
-        oid: oid.to_string(),
-        authorization: "".to_string(),
-        ..Default::default()
+    let meta = upload_meta(config, oid).await?;
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("The content is larger than the size {}", meta.size),
+        )
     };
-
-    let content_store = ContentStore::new(config.lfs_content_path.to_owned());
-
-    let meta = config.storage.lfs_get_meta(&request_vars).await.unwrap();
-
-    let (_parts, mut body) = req.into_parts();
-
-    let mut request_body = BytesMut::new();
-
-    while let Some(chunk) = body.next().await {
-        tracing::info!("client sends :{:?}", chunk);
-        let bytes = chunk.unwrap();
-        request_body.extend_from_slice(&bytes);
+    if exceeds_limit(config, meta.size) {
+        delete_meta(config, oid).await?;
+        return Err(too_large());
     }
 
-    let ok = content_store.put(&meta, request_body.freeze().as_ref());
+    let (_parts, body) = req.into_parts();
+
+    // Stream the content to the storage rather than buffering the whole object, and abort it
+    // as soon as it's larger than the size of the batch request.
+    let size = meta.size as u64;
+    let mut received = 0;
+    let oversized = Arc::new(AtomicBool::new(false));
+    let flag = oversized.clone();
+    let body = Box::pin(body.map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        received += chunk.len() as u64;
+        if received > size {
+            flag.store(true, Ordering::Relaxed);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the content is larger than the size",
+            ));
+        }
+        metrics::metrics().add_lfs_bytes("upload", chunk.len());
+        Ok(chunk)
+    }));
+    let result = config.lfs_storage.put(&meta, body).await;
+    if oversized.load(Ordering::Relaxed) {
+        delete_meta(config, oid).await?;
+        return Err(too_large());
+    }
+    let ok = result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     if !ok {
-        config.storage.lfs_delete_meta(&request_vars).await.unwrap();
+        delete_meta(config, oid).await?;
         return Err((
             StatusCode::NOT_ACCEPTABLE,
             String::from("Header not acceptable!"),
@@ -442,14 +583,165 @@ pub async fn lfs_upload_object(
     Ok(resp)
 }
 
+/// The bytes of the resumable upload already received, e.g. after the upload is interrupted.
+pub const UPLOAD_OFFSET: &str = "Upload-Offset";
+/// The size of the whole object.
+pub const UPLOAD_LENGTH: &str = "Upload-Length";
+
+/// The `start-end/size` of `Content-Range: bytes start-end/size`, the `end` is inclusive.
+fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, size) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end, size) = (start.parse().ok()?, end.parse().ok()?, size.parse().ok()?);
+    (start <= end && end < size).then_some((start, end, size))
+}
+
+async fn upload_meta(config: &LfsConfig, oid: &str) -> Result<MetaObject, (StatusCode, String)> {
+    if !is_valid_oid(oid) {
+        return Err(unprocessable("Invalid oid"));
+    }
+    let request_vars = RequestVars {
+        oid: oid.to_owned(),
+        ..Default::default()
+    };
+    config
+        .storage
+        .lfs_get_meta(&request_vars)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Object not found".to_owned()))
+}
+
+/// Delete the meta of the object of which the upload failed, so it's uploaded again.
+async fn delete_meta(config: &LfsConfig, oid: &str) -> Result<(), (StatusCode, String)> {
+    let request_vars = RequestVars {
+        oid: oid.to_owned(),
+        ..Default::default()
+    };
+    config
+        .storage
+        .lfs_delete_meta(&request_vars)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+fn upload_offset(status: StatusCode, offset: u64, size: i64) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(UPLOAD_OFFSET, offset)
+        .header(UPLOAD_LENGTH, size)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// The `HEAD` of the object to upload, of which the bytes already received are in the
+/// `Upload-Offset` header, so the client appends the rest from there.
+pub async fn lfs_upload_status(
+    config: &LfsConfig,
+    oid: &str,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let meta = upload_meta(config, oid).await?;
+    let offset = if config.lfs_storage.exist(&meta).await {
+        meta.size as u64
+    } else {
+        config
+            .partial_uploads
+            .offset(oid)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    };
+    Ok(upload_offset(StatusCode::OK, offset, meta.size))
+}
+
+/// The `PATCH` of a chunk of the object in `Content-Range`, which starts at the bytes already
+/// received. The object is moved to the storage after the last chunk if the content matches
+/// the oid, and `200` is returned, or `204` with the new `Upload-Offset` before that.
+pub async fn lfs_append_object(
+    config: &LfsConfig,
+    oid: &str,
+    req: Request<Body>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let meta = upload_meta(config, oid).await?;
+    if config.lfs_storage.exist(&meta).await {
+        return Ok(upload_offset(StatusCode::OK, meta.size as u64, meta.size));
+    }
+    if exceeds_limit(config, meta.size) {
+        delete_meta(config, oid).await?;
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "The size exceeds the limit of {} bytes",
+                config.max_object_size.unwrap_or_default()
+            ),
+        ));
+    }
+    let (start, end, size) = req
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_range)
+        .filter(|(_, _, size)| *size == meta.size as u64)
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "Invalid Content-Range, expect `bytes start-end/size`".to_owned(),
+        ))?;
+
+    let io_err = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::InvalidInput => (StatusCode::BAD_REQUEST, e.to_string()),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let partial_uploads = &config.partial_uploads;
+    let current = partial_uploads.offset(oid).await.map_err(io_err)?;
+    if start != current {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Expect the range starting at {}", current),
+        ));
+    }
+    let body = Box::pin(req.into_body().map(|chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        metrics::metrics().add_lfs_bytes("upload", chunk.len());
+        Ok(chunk)
+    }));
+    let offset = partial_uploads
+        .append(oid, start, end - start + 1, body)
+        .await
+        .map_err(io_err)?;
+    if offset < size {
+        return Ok(upload_offset(StatusCode::NO_CONTENT, offset, meta.size));
+    }
+
+    // verify the whole content before it's moved to the storage
+    if !partial_uploads.verify(oid).await.map_err(io_err)? {
+        partial_uploads.remove(oid).await.map_err(io_err)?;
+        return Err(unprocessable("The content doesn't match the oid"));
+    }
+    let content = partial_uploads.content(oid).await.map_err(io_err)?;
+    let ok = config
+        .lfs_storage
+        .put(&meta, content)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    partial_uploads.remove(oid).await.map_err(io_err)?;
+    if !ok {
+        return Err(unprocessable("The content doesn't match the oid"));
+    }
+    Ok(upload_offset(StatusCode::OK, offset, meta.size))
+}
+
+/// Download the content of the object, or `304 Not Modified` if the `If-None-Match` of the
+/// `headers` is the `ETag` of it, which is the oid.
+///
+/// The `Range` of the `headers` is answered by `206 Partial Content` of the bytes of it, which
+/// are read from the storage only, or of the `multipart/byteranges` of several ranges. A range
+/// is not verified even if the [`LfsConfig::verify_download`], as the SHA-256 is of the whole
+/// content.
 pub async fn lfs_download_object(
     config: &LfsConfig,
     oid: &str,
+    headers: &HeaderMap,
 ) -> Result<Response<Body>, (StatusCode, String)> {
     tracing::info!("start downloading LFS object");
 
-    let content_store = ContentStore::new(config.lfs_content_path.to_owned());
-
     // Load request parameters into struct.
     let request_vars = RequestVars {
         oid: oid.to_owned(),
@@ -457,26 +749,210 @@ pub async fn lfs_download_object(
         ..Default::default()
     };
 
-    let meta = config.storage.lfs_get_meta(&request_vars).await.unwrap();
-
-    let mut file = content_store.get(&meta, 0);
+    let meta = config
+        .storage
+        .lfs_get_meta(&request_vars)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Object not found".to_owned()))?;
+    let etag = http::etag(&meta.oid);
+    if http::if_none_match(headers, &etag) {
+        return Ok(http::not_modified(&etag));
+    }
+    let size = meta.size as u64;
+    let ranges = match headers.get(RANGE) {
+        Some(range) => match range.to_str().ok().and_then(|r| parse_range(r, size)) {
+            Some(ranges) if !ranges.is_empty() => Some(ranges),
+            _ => return Ok(range_not_satisfiable(size)),
+        },
+        None => None,
+    };
+    let storage_err = |e: GitLFSError| match e {
+        GitLFSError::NotFound(_) => (StatusCode::NOT_FOUND, e.to_string()),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
 
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).unwrap();
-    let mut bytes = BytesMut::new();
-    bytes.put(buffer.as_ref());
-    let mut resp = Response::builder();
-    resp = resp.status(200);
-    let body = Body::from(bytes.freeze());
+    let mut resp = Response::builder()
+        .header(ETAG, etag)
+        .header(ACCEPT_RANGES, "bytes");
+    let stream = match ranges.as_deref() {
+        None => {
+            let mut stream = config
+                .lfs_storage
+                .get(&meta, 0)
+                .await
+                .map_err(storage_err)?;
+            if config.verify_download {
+                stream = verify_content(stream, meta.oid.clone());
+                // an object of a chunk is checked before the status is sent
+                match stream.next().await {
+                    Some(Err(e)) if e.kind() == std::io::ErrorKind::InvalidData => {
+                        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+                    }
+                    Some(first) => {
+                        stream = Box::pin(futures::stream::once(async { first }).chain(stream))
+                    }
+                    None => {}
+                }
+            }
+            resp = resp.status(StatusCode::OK);
+            stream
+        }
+        Some(&[(start, end)]) => {
+            let stream = config
+                .lfs_storage
+                .get_range(&meta, start, end)
+                .await
+                .map_err(storage_err)?;
+            resp = resp
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, content_range(start, end, size))
+                .header(CONTENT_LENGTH, end - start);
+            stream
+        }
+        Some(ranges) => {
+            // the object is checked before the status is sent, like the one of a range
+            if !config.lfs_storage.exist(&meta).await {
+                return Err(storage_err(GitLFSError::NotFound(meta.oid.clone())));
+            }
+            let boundary = format!("{:016x}", rand::thread_rng().gen::<u64>());
+            resp = resp.status(StatusCode::PARTIAL_CONTENT).header(
+                CONTENT_TYPE,
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+            byteranges(config.lfs_storage.clone(), meta, ranges.to_vec(), boundary)
+        }
+    };
+    let body = Body::wrap_stream(stream.inspect(|chunk| {
+        if let Ok(chunk) = chunk {
+            metrics::metrics().add_lfs_bytes("download", chunk.len());
+        }
+    }));
     Ok(resp.body(body).unwrap())
 }
 
+/// The ranges of a request at most, so an object is not requested by the tiny pieces.
+const MAX_RANGES: usize = 16;
+
+/// Parse the `Range` like `bytes=0-499,1000-` or `bytes=-500` of the last 500 bytes to the
+/// ranges `start..end` of the object of the `size`. The ranges after the end of it are skipped,
+/// none if the header is malformed or has more than [`MAX_RANGES`] ranges.
+fn parse_range(range: &str, size: u64) -> Option<Vec<(u64, u64)>> {
+    let number = |n: &str| {
+        let n = n.trim();
+        if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        n.parse::<u64>().ok()
+    };
+    let specs = range.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let (first, last) = spec.split_once('-')?;
+        let (start, end) = match (first.trim(), last.trim()) {
+            ("", suffix) => (size - number(suffix)?.min(size), size),
+            (first, "") => (number(first)?, size),
+            (first, last) => {
+                let (first, last) = (number(first)?, number(last)?);
+                if last < first {
+                    return None;
+                }
+                (first, last.saturating_add(1).min(size))
+            }
+        };
+        if start < end {
+            ranges.push((start, end));
+        }
+    }
+    (ranges.len() <= MAX_RANGES).then_some(ranges)
+}
+
+fn content_range(start: u64, end: u64, size: u64) -> String {
+    format!("bytes {}-{}/{}", start, end - 1, size)
+}
+
+/// The `416 Range Not Satisfiable` of the object of the `size`.
+fn range_not_satisfiable(size: u64) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header(CONTENT_RANGE, format!("bytes */{}", size))
+        .body(Body::from("The range is not satisfiable"))
+        .unwrap()
+}
+
+/// The `multipart/byteranges` of the ranges of the object, of which each one is read from the
+/// storage as it's sent.
+fn byteranges(
+    lfs_storage: Arc<dyn LfsStorage>,
+    meta: MetaObject,
+    ranges: Vec<(u64, u64)>,
+    boundary: String,
+) -> ByteStream {
+    let close = format!("\r\n--{}--\r\n", boundary);
+    let parts = futures::stream::iter(ranges)
+        .then(move |(start, end)| {
+            let (lfs_storage, meta) = (lfs_storage.clone(), meta.clone());
+            let header = format!(
+                "\r\n--{}\r\nContent-Type: application/octet-stream\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_range(start, end, meta.size as u64)
+            );
+            async move {
+                let header = futures::stream::once(async { Ok(Bytes::from(header)) });
+                match lfs_storage.get_range(&meta, start, end).await {
+                    Ok(content) => header.chain(content).boxed(),
+                    Err(e) => {
+                        let e = std::io::Error::other(e.to_string());
+                        futures::stream::once(async { Err(e) }).boxed()
+                    }
+                }
+            }
+        })
+        .flatten();
+    Box::pin(parts.chain(futures::stream::once(async { Ok(Bytes::from(close)) })))
+}
+
+/// The content of which the SHA-256 is checked against the oid as it's streamed. The last chunk
+/// is held back until the check, so a corrupted object is never sent as a whole, the response
+/// is aborted by the error instead.
+fn verify_content(stream: ByteStream, oid: String) -> ByteStream {
+    let state = (stream, Sha256::new(), None::<Bytes>);
+    Box::pin(futures::stream::unfold(Some(state), move |state| {
+        let oid = oid.clone();
+        async move {
+            let (mut stream, mut hasher, mut last) = state?;
+            loop {
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        hasher.update(&chunk);
+                        if let Some(previous) = last.replace(chunk) {
+                            return Some((Ok(previous), Some((stream, hasher, last))));
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None => {
+                        let hash = hex::encode(hasher.finalize());
+                        if hash == oid {
+                            return last.map(|last| (Ok(last), None));
+                        }
+                        tracing::error!("LFS object {} is corrupted, the sha256 is {}", oid, hash);
+                        let e = std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("LFS object {} failed the integrity check", oid),
+                        );
+                        return Some((Err(e), None));
+                    }
+                }
+            }
+        }
+    }))
+}
+
 pub async fn represent(
     rv: &RequestVars,
     meta: &MetaObject,
     download: bool,
     upload: bool,
-    use_tus: bool,
+    verify: bool,
     server_url: &str,
 ) -> Representation {
     let mut rep = Representation {
@@ -526,9 +1002,7 @@ pub async fn represent(
                 },
             },
         );
-        rep.actions = Some(actions);
-        if use_tus {
-            let mut actions = HashMap::new();
+        if verify {
             actions.insert(
                 "verify".to_string(),
                 Link {
@@ -540,9 +1014,685 @@ pub async fn represent(
                     },
                 },
             );
-            rep.actions = Some(actions);
         }
+        rep.actions = Some(actions);
     }
 
     rep
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::header::{
+        ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE,
+    };
+    use axum::http::{HeaderMap, Response, StatusCode};
+    use database::driver::lfs::storage::{bytes_stream, read_to_end, ContentStore, MetaObject};
+    use database::driver::lfs::structs::{
+        LockList, LockListQuery, RequestVars, User, VerifiableLockList,
+    };
+    use database::driver::memory::storage::MemoryStorage;
+    use hyper::Request;
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        lfs_append_object, lfs_create_lock, lfs_delete_lock, lfs_download_object,
+        lfs_process_batch, lfs_retrieve_lock, lfs_upload_object, lfs_upload_status,
+        lfs_verify_lock, lfs_verify_object, parse_range, UPLOAD_OFFSET,
+    };
+    use crate::lfs::partial::{PartialUploads, DEFAULT_TTL};
+    use crate::lfs::LfsConfig;
+
+    fn config() -> LfsConfig {
+        config_in("mega_lfs_locks")
+    }
+
+    fn config_in(dir: &str) -> LfsConfig {
+        let dir = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        LfsConfig {
+            host: "127.0.0.1".to_owned(),
+            port: 8000,
+            storage: Arc::new(MemoryStorage::new()),
+            lfs_storage: Arc::new(ContentStore::new(dir.clone())),
+            partial_uploads: PartialUploads::new(dir.join("partial"), DEFAULT_TTL),
+            verify_download: false,
+            max_object_size: None,
+        }
+    }
+
+    /// "hello world\n"
+    const OID: &str = "a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447";
+
+    /// Save the meta and the content of the `OID`.
+    async fn save_object(config: &LfsConfig) {
+        let object = RequestVars {
+            oid: OID.to_owned(),
+            size: 12,
+            ..Default::default()
+        };
+        let meta = config.storage.lfs_put_meta(&object).await.unwrap();
+        let body = bytes_stream("hello world\n");
+        assert!(config.lfs_storage.put(&meta, body).await.unwrap());
+    }
+
+    async fn batch(config: &LfsConfig, body: Value) -> Result<Value, StatusCode> {
+        let req = Request::post("/objects/batch")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        match lfs_process_batch(config, req).await {
+            Ok(resp) => Ok(body_json(resp.into_body()).await),
+            Err((status, _)) => Err(status),
+        }
+    }
+
+    /// The request of the user checked by the auth layer
+    fn request(user: &str, body: &str) -> Request<Body> {
+        let mut req = Request::builder()
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        req.extensions_mut().insert(User {
+            name: user.to_owned(),
+        });
+        req
+    }
+
+    async fn lock(config: &LfsConfig, user: &str, path: &str) -> StatusCode {
+        let body = format!(
+            r#"{{"path": "{}", "ref": {{"name": "refs/heads/main"}}}}"#,
+            path
+        );
+        lfs_create_lock(config, request(user, &body))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    async fn body_json<T: serde::de::DeserializeOwned>(body: Body) -> T {
+        serde_json::from_slice(&hyper::body::to_bytes(body).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_lock() {
+        let config = config();
+        assert_eq!(lock(&config, "alice", "a.bin").await, StatusCode::CREATED);
+        // the path is locked already
+        assert_eq!(lock(&config, "bob", "a.bin").await, StatusCode::CONFLICT);
+
+        let locks = config
+            .storage
+            .lfs_get_locks("refs/heads/main")
+            .await
+            .unwrap();
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].owner.as_ref().unwrap().name, "alice");
+    }
+
+    #[tokio::test]
+    async fn test_lock_unauthenticated() {
+        let config = config();
+        // the basic auth header isn't checked here, but by the auth layer
+        let req = Request::builder()
+            .header("Authorization", "Basic YWxpY2U6c2VjcmV0")
+            .body(Body::from(
+                r#"{"path": "a.bin", "ref": {"name": "refs/heads/main"}}"#,
+            ))
+            .unwrap();
+        let err = lfs_create_lock(&config, req).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::UNAUTHORIZED);
+        assert!(config
+            .storage
+            .lfs_get_locks("refs/heads/main")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lock_invalid_body() {
+        let config = config();
+        let invalid = || request("alice", r#"{"path": "#);
+        let err = lfs_create_lock(&config, invalid()).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::UNPROCESSABLE_ENTITY);
+        let err = lfs_verify_lock(&config, invalid()).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::UNPROCESSABLE_ENTITY);
+        let err = lfs_delete_lock(&config, "1", invalid()).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_list_locks() {
+        let config = config();
+        for path in ["a.bin", "b.bin", "c.bin"] {
+            assert_eq!(lock(&config, "alice", path).await, StatusCode::CREATED);
+        }
+        let query = |cursor: Option<String>| LockListQuery {
+            path: None,
+            id: None,
+            cursor,
+            limit: Some("2".to_owned()),
+            refspec: Some("refs/heads/main".to_owned()),
+        };
+        let resp = lfs_retrieve_lock(&config, query(None)).await.unwrap();
+        let page: LockList = body_json(resp.into_body()).await;
+        assert_eq!(page.locks.len(), 2);
+        assert!(!page.next_cursor.is_empty());
+
+        let resp = lfs_retrieve_lock(&config, query(Some(page.next_cursor)))
+            .await
+            .unwrap();
+        let page: LockList = body_json(resp.into_body()).await;
+        assert_eq!(page.locks.len(), 1);
+        assert_eq!(page.locks[0].path, "c.bin");
+        assert!(page.next_cursor.is_empty());
+
+        // the id of the lock not in the first page
+        let id = page.locks[0].id.clone();
+        let query = LockListQuery {
+            id: Some(id.clone()),
+            ..query(None)
+        };
+        let resp = lfs_retrieve_lock(&config, query).await.unwrap();
+        let page: LockList = body_json(resp.into_body()).await;
+        assert_eq!(page.locks.len(), 1);
+        assert_eq!(page.locks[0].id, id);
+    }
+
+    #[tokio::test]
+    async fn test_verify_locks() {
+        let config = config();
+        assert_eq!(lock(&config, "alice", "a.bin").await, StatusCode::CREATED);
+        assert_eq!(lock(&config, "bob", "b.bin").await, StatusCode::CREATED);
+
+        let body = r#"{"ref": {"name": "refs/heads/main"}}"#;
+        let resp = lfs_verify_lock(&config, request("alice", body))
+            .await
+            .unwrap();
+        let list: VerifiableLockList = body_json(resp.into_body()).await;
+        assert_eq!(list.ours.len(), 1);
+        assert_eq!(list.ours[0].path, "a.bin");
+        assert_eq!(list.theirs.len(), 1);
+        assert_eq!(list.theirs[0].path, "b.bin");
+    }
+
+    #[tokio::test]
+    async fn test_delete_lock() {
+        let config = config();
+        assert_eq!(lock(&config, "alice", "a.bin").await, StatusCode::CREATED);
+        let locks = config
+            .storage
+            .lfs_get_locks("refs/heads/main")
+            .await
+            .unwrap();
+        let id = locks[0].id.clone();
+        let unlock = |force: bool| {
+            format!(
+                r#"{{"force": {}, "ref": {{"name": "refs/heads/main"}}}}"#,
+                force
+            )
+        };
+        let err = lfs_delete_lock(&config, &id, request("bob", &unlock(false)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+        let resp = lfs_delete_lock(&config, &id, request("bob", &unlock(true)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(config
+            .storage
+            .lfs_get_locks("refs/heads/main")
+            .await
+            .is_err());
+
+        let err = lfs_delete_lock(&config, &id, request("alice", &unlock(false)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_batch_download() {
+        let config = config_in("mega_lfs_batch_download");
+        save_object(&config).await;
+        let missing = "6bbd052ab054ef222c1c87be60cd191addedd24cc882d1f5f7f7be61dc61bb3a";
+        let body = json!({
+            "operation": "download",
+            "transfers": ["basic"],
+            "objects": [{"oid": OID, "size": 12}, {"oid": missing, "size": 8}],
+        });
+        let resp = batch(&config, body).await.unwrap();
+        assert_eq!(resp["transfer"], "basic");
+        let download = &resp["objects"][0]["actions"]["download"];
+        assert_eq!(
+            download["href"],
+            format!("http://127.0.0.1:8000/objects/{}", OID)
+        );
+        assert!(download["expires_at"].is_string());
+        assert!(resp["objects"][0]["actions"].get("upload").is_none());
+        assert_eq!(resp["objects"][1]["error"]["code"], 404);
+    }
+
+    #[tokio::test]
+    async fn test_batch_upload() {
+        let config = config_in("mega_lfs_batch_upload");
+        let body = json!({
+            "operation": "upload",
+            "transfers": ["basic"],
+            "objects": [{"oid": OID, "size": 12}],
+        });
+        let resp = batch(&config, body.clone()).await.unwrap();
+        let actions = &resp["objects"][0]["actions"];
+        assert_eq!(
+            actions["upload"]["href"],
+            format!("http://127.0.0.1:8000/objects/{}", OID)
+        );
+        assert_eq!(
+            actions["verify"]["href"],
+            "http://127.0.0.1:8000/objects/verify"
+        );
+
+        // no action if the object is uploaded already
+        save_object(&config).await;
+        let resp = batch(&config, body).await.unwrap();
+        assert_eq!(resp["objects"][0]["oid"], OID);
+        assert!(resp["objects"][0].get("actions").is_none());
+        assert!(resp["objects"][0].get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_upload_size_limit() {
+        let mut config = config_in("mega_lfs_batch_upload_limit");
+        config.max_object_size = Some(10);
+        let body = json!({
+            "operation": "upload",
+            "objects": [{"oid": OID, "size": 12}],
+        });
+        let resp = batch(&config, body).await.unwrap();
+        assert_eq!(resp["objects"][0]["error"]["code"], 422);
+        assert!(resp["objects"][0].get("actions").is_none());
+        let object = RequestVars {
+            oid: OID.to_owned(),
+            ..Default::default()
+        };
+        assert!(config.storage.lfs_get_meta(&object).await.is_err());
+
+        config.max_object_size = Some(12);
+        let body = json!({
+            "operation": "upload",
+            "objects": [{"oid": OID, "size": 12}],
+        });
+        let resp = batch(&config, body).await.unwrap();
+        assert!(resp["objects"][0]["actions"]["upload"].is_object());
+    }
+
+    /// Upload "hello world\n" of the `OID` by the batch of the `size`.
+    async fn upload(config: &LfsConfig, size: i64) -> Result<Response<Body>, (StatusCode, String)> {
+        let body = json!({
+            "operation": "upload",
+            "objects": [{"oid": OID, "size": size}],
+        });
+        batch(config, body).await.unwrap();
+        let req = Request::put(format!("/objects/{}", OID))
+            .body(Body::from("hello world\n"))
+            .unwrap();
+        lfs_upload_object(config, OID, req).await
+    }
+
+    #[tokio::test]
+    async fn test_upload_size_mismatch() {
+        let config = config_in("mega_lfs_upload_mismatch");
+        let object = RequestVars {
+            oid: OID.to_owned(),
+            ..Default::default()
+        };
+
+        // the content is larger than the size declared
+        let (status, _) = upload(&config, 5).await.unwrap_err();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(config.storage.lfs_get_meta(&object).await.is_err());
+        let meta = MetaObject {
+            oid: OID.to_owned(),
+            size: 12,
+            exist: true,
+        };
+        assert!(!config.lfs_storage.exist(&meta).await);
+
+        // or smaller
+        let (status, _) = upload(&config, 20).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        assert!(config.storage.lfs_get_meta(&object).await.is_err());
+
+        let resp = upload(&config, 12).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(config.lfs_storage.exist(&meta).await);
+    }
+
+    #[tokio::test]
+    async fn test_upload_unknown_object() {
+        let config = config_in("mega_lfs_upload_unknown");
+        let put = |oid: &str| {
+            Request::put(format!("/objects/{}", oid))
+                .body(Body::from("hello world\n"))
+                .unwrap()
+        };
+        // not requested by a batch
+        let (status, _) = lfs_upload_object(&config, OID, put(OID)).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let oid = "../../etc/passwd";
+        let (status, _) = lfs_upload_object(&config, oid, put(oid)).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_verify_object() {
+        let config = config_in("mega_lfs_verify");
+        let verify = |size: i64| {
+            let body = json!({"oid": OID, "size": size}).to_string();
+            Request::post("/objects/verify")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        // the meta is saved by the batch, but the content is not uploaded
+        let object = RequestVars {
+            oid: OID.to_owned(),
+            size: 12,
+            ..Default::default()
+        };
+        config.storage.lfs_put_meta(&object).await.unwrap();
+        let (status, _) = lfs_verify_object(&config, verify(12)).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(config.storage.lfs_get_meta(&object).await.is_err());
+
+        save_object(&config).await;
+        let resp = lfs_verify_object(&config, verify(12)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let (status, _) = lfs_verify_object(&config, verify(13)).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_batch_validation() {
+        let config = config_in("mega_lfs_batch_invalid");
+        let body = json!({
+            "operation": "upload",
+            "transfers": ["basic"],
+            "objects": [{"oid": "../../etc/passwd", "size": 12}, {"oid": OID, "size": -1}],
+        });
+        let resp = batch(&config, body).await.unwrap();
+        assert_eq!(resp["objects"][0]["error"]["code"], 422);
+        assert_eq!(resp["objects"][1]["error"]["code"], 422);
+
+        // no size, the unknown operation and transfer
+        let invalid = [
+            json!({"operation": "upload", "objects": [{"oid": OID}]}),
+            json!({"operation": "delete", "objects": []}),
+            json!({"operation": "download", "transfers": ["tus"], "objects": []}),
+        ];
+        for body in invalid {
+            let status = batch(&config, body).await.unwrap_err();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    fn patch(range: &str, body: Body) -> Request<Body> {
+        Request::patch(format!("/objects/{}", OID))
+            .header("Content-Range", range)
+            .body(body)
+            .unwrap()
+    }
+
+    async fn upload_offset(config: &LfsConfig) -> String {
+        let resp = lfs_upload_status(config, OID).await.unwrap();
+        resp.headers()[UPLOAD_OFFSET].to_str().unwrap().to_owned()
+    }
+
+    async fn put_meta(config: &LfsConfig) -> MetaObject {
+        let object = RequestVars {
+            oid: OID.to_owned(),
+            size: 12,
+            ..Default::default()
+        };
+        config.storage.lfs_put_meta(&object).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let config = config_in("mega_lfs_resumable_upload");
+        let meta = put_meta(&config).await;
+        assert_eq!(upload_offset(&config).await, "0");
+
+        // the connection is interrupted after the first 6 bytes
+        let interrupted = futures::stream::iter(vec![
+            Ok(bytes::Bytes::from("hello ")),
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset)),
+        ]);
+        let req = patch("bytes 0-11/12", Body::wrap_stream(interrupted));
+        assert!(lfs_append_object(&config, OID, req).await.is_err());
+        assert!(!config.lfs_storage.exist(&meta).await);
+        assert_eq!(upload_offset(&config).await, "6");
+
+        // resume from a wrong offset
+        let req = patch("bytes 0-11/12", Body::from("hello world\n"));
+        let (status, _) = lfs_append_object(&config, OID, req).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let req = patch("bytes 6-11/12", Body::from("world\n"));
+        let resp = lfs_append_object(&config, OID, req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(upload_offset(&config).await, "12");
+        let content = config.lfs_storage.get(&meta, 0).await.unwrap();
+        assert_eq!(read_to_end(content).await.unwrap(), b"hello world\n");
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload_mismatch() {
+        let config = config_in("mega_lfs_resumable_mismatch");
+        let meta = put_meta(&config).await;
+
+        let req = patch("bytes 0-5/12", Body::from("hello "));
+        let resp = lfs_append_object(&config, OID, req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers()[UPLOAD_OFFSET], "6");
+
+        let req = patch("bytes 6-11/12", Body::from("earth\n"));
+        let (status, _) = lfs_append_object(&config, OID, req).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!config.lfs_storage.exist(&meta).await);
+        // start again
+        assert_eq!(upload_offset(&config).await, "0");
+
+        // not the size of the object
+        let req = patch("bytes 0-5/13", Body::from("hello "));
+        let (status, _) = lfs_append_object(&config, OID, req).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload_limit() {
+        let mut config = config_in("mega_lfs_resumable_limit");
+        put_meta(&config).await;
+        config.max_object_size = Some(10);
+        let req = patch("bytes 0-5/12", Body::from("hello "));
+        let (status, _) = lfs_append_object(&config, OID, req).await.unwrap_err();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let (status, _) = lfs_upload_status(&config, OID).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_download_verified() {
+        let mut config = config_in("mega_lfs_download_verified");
+        config.verify_download = true;
+        save_object(&config).await;
+        let resp = lfs_download_object(&config, OID, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"hello world\n");
+
+        // the bit-rot of the same size
+        let dir = std::env::temp_dir().join("mega_lfs_download_verified");
+        let path = dir.join(&OID[0..2]).join(&OID[2..4]).join(&OID[4..]);
+        std::fs::write(&path, "hello World\n").unwrap();
+        let (status, message) = lfs_download_object(&config, OID, &HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(message.contains("integrity check"));
+
+        // not checked if disabled
+        config.verify_download = false;
+        let resp = lfs_download_object(&config, OID, &HeaderMap::new())
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"hello World\n");
+    }
+
+    #[tokio::test]
+    async fn test_download_not_modified() {
+        let config = config_in("mega_lfs_download_not_modified");
+        let (status, _) = lfs_download_object(&config, OID, &HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        save_object(&config).await;
+        let resp = lfs_download_object(&config, OID, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers()[ETAG].clone();
+        assert_eq!(etag, format!("\"{}\"", OID));
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"hello world\n");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag.clone());
+        let resp = lfs_download_object(&config, OID, &headers).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()[ETAG], etag);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        // of another object
+        headers.insert(IF_NONE_MATCH, "\"1234\"".parse().unwrap());
+        let resp = lfs_download_object(&config, OID, &headers).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_download_range() {
+        let config = config_in("mega_lfs_download_range");
+        save_object(&config).await;
+        let download = |range: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RANGE, range.parse().unwrap());
+            let config = &config;
+            async move { lfs_download_object(config, OID, &headers).await.unwrap() }
+        };
+        let content = |resp: Response<Body>| async {
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let resp = download("bytes=0-4").await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(resp.headers()[CONTENT_RANGE], "bytes 0-4/12");
+        assert_eq!(resp.headers()[CONTENT_LENGTH], "5");
+        assert_eq!(content(resp).await, "hello");
+
+        // open-ended, and of the last bytes
+        let resp = download("bytes=6-").await;
+        assert_eq!(resp.headers()[CONTENT_RANGE], "bytes 6-11/12");
+        assert_eq!(content(resp).await, "world\n");
+        let resp = download("bytes=-3").await;
+        assert_eq!(resp.headers()[CONTENT_RANGE], "bytes 9-11/12");
+        assert_eq!(content(resp).await, "ld\n");
+        // the end after the object
+        let resp = download("bytes=6-100").await;
+        assert_eq!(content(resp).await, "world\n");
+
+        let resp = download("bytes=0-1, 6-7").await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = resp.headers()[CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_owned();
+        let part = |range: &str, body: &str| {
+            format!(
+                "\r\n--{}\r\nContent-Type: application/octet-stream\r\nContent-Range: {}\r\n\r\n{}",
+                boundary, range, body
+            )
+        };
+        let expected = part("bytes 0-1/12", "he") + &part("bytes 6-7/12", "wo");
+        let expected = expected + &format!("\r\n--{}--\r\n", boundary);
+        assert_eq!(content(resp).await, expected);
+
+        // unsatisfiable or malformed
+        for range in ["bytes=12-", "bytes=-0", "bytes=5-2", "bytes=a-", "lines=0-"] {
+            let resp = download(range).await;
+            let status = resp.status();
+            assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE, "{}", range);
+            assert_eq!(resp.headers()[CONTENT_RANGE], "bytes */12");
+        }
+        // of the whole object
+        let resp = lfs_download_object(&config, OID, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[ACCEPT_RANGES], "bytes");
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-0", 10), Some(vec![(0, 1)]));
+        assert_eq!(parse_range("bytes=-20", 10), Some(vec![(0, 10)]));
+        // the unsatisfiable ones are skipped
+        assert_eq!(parse_range("bytes=20-, 8-", 10), Some(vec![(8, 10)]));
+        assert_eq!(parse_range("bytes=+1-2", 10), None);
+        assert_eq!(parse_range("bytes=1", 10), None);
+        let many = vec!["0-0"; 17].join(",");
+        assert_eq!(parse_range(&format!("bytes={}", many), 10), None);
+    }
+
+    #[tokio::test]
+    async fn test_download_verified_large() {
+        let mut config = config_in("mega_lfs_download_verified_large");
+        config.verify_download = true;
+        // of several chunks, of which the status is sent before the check
+        let content = vec![b'a'; 200 * 1024];
+        let object = RequestVars {
+            oid: hex::encode(Sha256::digest(&content)),
+            size: content.len() as i64,
+            ..Default::default()
+        };
+        let meta = config.storage.lfs_put_meta(&object).await.unwrap();
+        let body = bytes_stream(content.clone());
+        assert!(config.lfs_storage.put(&meta, body).await.unwrap());
+        let resp = lfs_download_object(&config, &object.oid, &HeaderMap::new())
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], &content[..]);
+
+        let mut corrupted = content;
+        corrupted[150 * 1024] = b'b';
+        let oid = &object.oid;
+        let dir = std::env::temp_dir().join("mega_lfs_download_verified_large");
+        let path = dir.join(&oid[0..2]).join(&oid[2..4]).join(&oid[4..]);
+        std::fs::write(&path, &corrupted).unwrap();
+        let resp = lfs_download_object(&config, oid, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // the response is aborted rather than completed
+        assert!(hyper::body::to_bytes(resp.into_body()).await.is_err());
+    }
+}
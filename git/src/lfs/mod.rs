@@ -1,8 +1,12 @@
-use std::{path::PathBuf, sync::Arc};
+use std::sync::Arc;
 
-use database::driver::ObjectStorage;
+use database::driver::{lfs::storage::LfsStorage, ObjectStorage};
+
+use self::partial::PartialUploads;
 
 pub mod http;
+pub mod partial;
+pub mod pointer;
 
 #[derive(Clone)]
 pub struct LfsConfig {
@@ -10,7 +14,25 @@ pub struct LfsConfig {
 
     pub port: u16,
 
-    pub lfs_content_path: PathBuf,
-
     pub storage: Arc<dyn ObjectStorage>,
+
+    /// The storage of the objects content, e.g. the local files or S3.
+    pub lfs_storage: Arc<dyn LfsStorage>,
+
+    /// The content of the interrupted uploads, which are resumed later.
+    pub partial_uploads: PartialUploads,
+
+    /// Check the content of the objects against the oid as they are downloaded.
+    pub verify_download: bool,
+
+    /// The max size of an object to upload, no limit if not set.
+    pub max_object_size: Option<u64>,
+}
+
+impl LfsConfig {
+    /// Replace the storage, e.g. with the connected one of the server state or a mock in tests.
+    pub fn with_storage(mut self, storage: Arc<dyn ObjectStorage>) -> Self {
+        self.storage = storage;
+        self
+    }
 }
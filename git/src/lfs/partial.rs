@@ -0,0 +1,228 @@
+//! The partial content of the resumable uploads, saved in the local files even if the
+//! objects are in S3, so an interrupted upload is continued from where it stopped rather than
+//! from zero.
+//!
+//! The partial upload not continued in the TTL is expired, and the client starts again.
+
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use database::driver::lfs::storage::ByteStream;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Keep the partial uploads for a day by default.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug)]
+pub struct PartialUploads {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl PartialUploads {
+    pub fn new(dir: PathBuf, ttl: Duration) -> PartialUploads {
+        PartialUploads { dir, ttl }
+    }
+
+    /// The oid is checked by the caller, so it's a valid file name.
+    fn path(&self, oid: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", oid))
+    }
+
+    fn is_expired(&self, modified: SystemTime) -> bool {
+        modified.elapsed().is_ok_and(|elapsed| elapsed > self.ttl)
+    }
+
+    /// The bytes already received of the object, the expired upload is removed and `0` is
+    /// returned.
+    pub async fn offset(&self, oid: &str) -> io::Result<u64> {
+        let path = self.path(oid);
+        let metadata = match fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        if self.is_expired(metadata.modified()?) {
+            fs::remove_file(&path).await?;
+            return Ok(0);
+        }
+        Ok(metadata.len())
+    }
+
+    /// Appends the body at the `offset`, which must be the bytes already received, and at most
+    /// `limit` bytes. The received bytes are kept even if the body is interrupted, and the new
+    /// offset is returned.
+    pub async fn append(
+        &self,
+        oid: &str,
+        offset: u64,
+        limit: u64,
+        mut body: ByteStream,
+    ) -> io::Result<u64> {
+        let current = self.offset(oid).await?;
+        if offset != current {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expect the offset {}", current),
+            ));
+        }
+        fs::create_dir_all(&self.dir).await?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(oid))
+            .await?;
+        let mut written = 0;
+        let result = async {
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                if written + chunk.len() as u64 > limit {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "the body is longer than the range",
+                    ));
+                }
+                file.write_all(&chunk).await?;
+                written += chunk.len() as u64;
+            }
+            Ok(())
+        }
+        .await;
+        file.flush().await?;
+        if let Err(e) = result {
+            if e.kind() == io::ErrorKind::InvalidInput {
+                // drop the whole chunk, the client sends it again in the range
+                file.set_len(offset).await?;
+            }
+            return Err(e);
+        }
+        Ok(offset + written)
+    }
+
+    /// Whether the sha256 of the received content is the oid.
+    pub async fn verify(&self, oid: &str) -> io::Result<bool> {
+        let mut file = File::open(self.path(oid)).await?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(hex::encode(hasher.finalize()) == oid)
+    }
+
+    /// The received content, e.g. to be saved in the storage after verified.
+    pub async fn content(&self, oid: &str) -> io::Result<ByteStream> {
+        let file = File::open(self.path(oid)).await?;
+        let stream = futures::stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let mut buf = vec![0; 64 * 1024];
+            match file.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(bytes::Bytes::from(buf)), Some(file)))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+
+    pub async fn remove(&self, oid: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(oid)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the expired uploads, and returns how many are removed.
+    pub async fn remove_expired(&self) -> io::Result<usize> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            if self.is_expired(entry.metadata().await?.modified()?) {
+                fs::remove_file(entry.path()).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use database::driver::lfs::storage::bytes_stream;
+
+    use super::PartialUploads;
+
+    const OID: &str = "a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447";
+
+    #[tokio::test]
+    async fn test_append() {
+        let dir = std::env::temp_dir().join("mega_lfs_partial");
+        let _ = std::fs::remove_dir_all(&dir);
+        let uploads = PartialUploads::new(dir.clone(), Duration::from_secs(60));
+        assert_eq!(uploads.offset(OID).await.unwrap(), 0);
+
+        let offset = uploads
+            .append(OID, 0, 6, bytes_stream("hello "))
+            .await
+            .unwrap();
+        assert_eq!(offset, 6);
+        // not at the end of the received bytes
+        assert!(uploads
+            .append(OID, 0, 6, bytes_stream("hello "))
+            .await
+            .is_err());
+        // longer than the range
+        assert!(uploads
+            .append(OID, 6, 2, bytes_stream("world\n"))
+            .await
+            .is_err());
+        assert_eq!(uploads.offset(OID).await.unwrap(), 6);
+        assert!(!uploads.verify(OID).await.unwrap());
+
+        let offset = uploads
+            .append(OID, 6, 6, bytes_stream("world\n"))
+            .await
+            .unwrap();
+        assert_eq!(offset, 12);
+        assert!(uploads.verify(OID).await.unwrap());
+        uploads.remove(OID).await.unwrap();
+        assert_eq!(uploads.offset(OID).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_expire() {
+        let dir = std::env::temp_dir().join("mega_lfs_partial_expire");
+        let _ = std::fs::remove_dir_all(&dir);
+        let uploads = PartialUploads::new(dir.clone(), Duration::ZERO);
+        uploads
+            .append(OID, 0, 6, bytes_stream("hello "))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(uploads.remove_expired().await.unwrap(), 1);
+
+        uploads
+            .append(OID, 0, 6, bytes_stream("hello "))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(uploads.offset(OID).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,43 @@
+//! The pointer files of the LFS objects, which are the blobs saved in the repository in place of
+//! the content, see <https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md>.
+
+/// The max size of a pointer file, the larger blobs are not parsed.
+pub const POINTER_MAX_SIZE: usize = 1024;
+
+/// The oid of the LFS object of which the blob is the pointer file.
+pub fn pointer_oid(data: &[u8]) -> Option<String> {
+    if data.len() > POINTER_MAX_SIZE {
+        return None;
+    }
+    let mut lines = std::str::from_utf8(data).ok()?.lines();
+    if !lines
+        .next()?
+        .starts_with("version https://git-lfs.github.com/spec/")
+    {
+        return None;
+    }
+    lines
+        .find_map(|line| line.strip_prefix("oid sha256:"))
+        .filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pointer_oid;
+
+    #[test]
+    fn test_pointer_oid() {
+        let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+        let data = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+            oid
+        );
+        assert_eq!(pointer_oid(data.as_bytes()).as_deref(), Some(oid));
+        assert_eq!(pointer_oid(b"oid sha256:1234\n"), None);
+        assert_eq!(
+            pointer_oid(b"version https://git-lfs.github.com/spec/v1\noid sha256:1234\n"),
+            None
+        );
+    }
+}
@@ -7,8 +7,13 @@ pub mod errors;
 pub mod hash;
 pub mod internal;
 pub mod lfs;
+pub mod metrics;
 pub mod protocol;
 pub mod structure;
 pub mod utils;
+
+/// The version of the git and LFS services.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[cfg(test)]
 mod tests {}
@@ -0,0 +1,98 @@
+//! The metrics of the pack decoding, LFS transfer and SSH requests, which are registered in
+//! the registry of `common::metrics` on first use.
+
+use std::sync::OnceLock;
+
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+
+use crate::internal::pack::cache::CacheStats;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct DirectionLabels {
+    /// `upload` or `download`
+    pub direction: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct CommandLabels {
+    pub command: String,
+}
+
+pub struct GitMetrics {
+    pub pack_decode_seconds: Histogram,
+    /// The object cache stats of the last decoded pack.
+    pub pack_cache_hits: Gauge,
+    pub pack_cache_misses: Gauge,
+    pub pack_cache_evictions: Gauge,
+    pub lfs_bytes: Family<DirectionLabels, Counter>,
+    pub ssh_requests: Family<CommandLabels, Counter>,
+}
+
+impl GitMetrics {
+    fn register() -> GitMetrics {
+        let metrics = GitMetrics {
+            // from 10ms to about 5min
+            pack_decode_seconds: Histogram::new(exponential_buckets(0.01, 2.0, 15)),
+            pack_cache_hits: Gauge::default(),
+            pack_cache_misses: Gauge::default(),
+            pack_cache_evictions: Gauge::default(),
+            lfs_bytes: Family::default(),
+            ssh_requests: Family::default(),
+        };
+        let mut registry = common::metrics::registry();
+        registry.register(
+            "pack_decode_seconds",
+            "The duration of decoding a pack",
+            metrics.pack_decode_seconds.clone(),
+        );
+        registry.register(
+            "pack_cache_hits",
+            "The object cache hits of the last decoded pack",
+            metrics.pack_cache_hits.clone(),
+        );
+        registry.register(
+            "pack_cache_misses",
+            "The object cache misses of the last decoded pack",
+            metrics.pack_cache_misses.clone(),
+        );
+        registry.register(
+            "pack_cache_evictions",
+            "The object cache evictions of the last decoded pack",
+            metrics.pack_cache_evictions.clone(),
+        );
+        registry.register(
+            "lfs_bytes",
+            "The bytes of the LFS objects transferred",
+            metrics.lfs_bytes.clone(),
+        );
+        registry.register(
+            "ssh_requests",
+            "The git commands requested by SSH",
+            metrics.ssh_requests.clone(),
+        );
+        metrics
+    }
+
+    pub fn set_cache_stats(&self, stats: &CacheStats) {
+        self.pack_cache_hits.set(stats.hits as i64);
+        self.pack_cache_misses.set(stats.misses as i64);
+        self.pack_cache_evictions.set(stats.evictions as i64);
+    }
+
+    pub fn add_lfs_bytes(&self, direction: &str, bytes: usize) {
+        self.lfs_bytes
+            .get_or_create(&DirectionLabels {
+                direction: direction.to_owned(),
+            })
+            .inc_by(bytes as u64);
+    }
+}
+
+pub fn metrics() -> &'static GitMetrics {
+    static METRICS: OnceLock<GitMetrics> = OnceLock::new();
+    METRICS.get_or_init(GitMetrics::register)
+}
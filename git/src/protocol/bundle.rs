@@ -0,0 +1,183 @@
+//! The bundles of the repositories, see `git help gitformat-bundle`, and the `bundle-uri`
+//! command of the protocol v2 which tells the clients where to download the bundle of a
+//! repository, see `git help gitprotocol-v2`.
+//!
+//! A client of the `transfer.bundleURI` downloads the bundle before the fetch, e.g. from a
+//! static hosting or a CDN, and then fetches only the objects of the refs updated since the
+//! bundle is made, so the heavy transfer of a clone is offloaded from the server.
+
+use std::io::BufRead;
+
+use anyhow::{anyhow, Result};
+use bytes::{BufMut, BytesMut};
+
+use super::pack::{add_pkt_line_string, PackContent, PKT_LINE_END_MARKER};
+use super::symref::HEAD;
+use super::PackProtocol;
+use crate::hash::Hash;
+
+/// The first line of a bundle of the v2.
+pub const SIGNATURE: &str = "# v2 git bundle\n";
+
+/// The header of the bundle of the refs like `(id, name)`, one `<id> <name>` line of each ended
+/// by an empty line, without the prerequisites as a bundle of all the objects has none.
+pub fn header(refs: &[(String, String)]) -> String {
+    let mut header = SIGNATURE.to_owned();
+    for (id, name) in refs {
+        header.push_str(&format!("{} {}\n", id, name));
+    }
+    header.push('\n');
+    header
+}
+
+/// The refs of the header of a bundle, none if it's not a bundle of the v2 of all the objects.
+pub fn parse_header<R: BufRead>(mut reader: R) -> std::io::Result<Option<Vec<(String, String)>>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line != SIGNATURE {
+        return Ok(None);
+    }
+    let mut refs = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let Some(line) = line.strip_suffix('\n') else {
+            return Ok(None);
+        };
+        if line.is_empty() {
+            return Ok(Some(refs));
+        }
+        match line.split_once(' ') {
+            Some((id, name)) if !id.starts_with('-') => refs.push((id.to_owned(), name.to_owned())),
+            _ => return Ok(None),
+        }
+    }
+}
+
+impl PackProtocol {
+    /// The refs of the bundle like `(id, name)` sorted by the names after the `HEAD`, which are
+    /// the ones the client sees of the commits. The annotated tags are fetched after the
+    /// bundle, as the pack of it is the one of a clone.
+    pub async fn bundle_refs(&self) -> Result<Vec<(String, String)>> {
+        let mut git_refs = self
+            .visible_refs()
+            .await
+            .map_err(|e| anyhow!("failed to list the refs: {}", e))?;
+        git_refs.sort_by(|a, b| a.ref_name.cmp(&b.ref_name));
+        let head = self
+            .head(&git_refs)
+            .await
+            .map_err(|e| anyhow!("failed to read the HEAD: {}", e))?;
+        let graph = self.commit_graph().await;
+        let refs = head
+            .map(|head| (head.id, HEAD.to_owned()))
+            .into_iter()
+            .chain(git_refs.into_iter().map(|r| (r.ref_git_id, r.ref_name)))
+            .filter(|(id, _)| graph.contains(&Hash::new_from_str(id)))
+            .collect();
+        Ok(refs)
+    }
+
+    /// The bundle of the [`bundle_refs`](PackProtocol::bundle_refs) and the pack of a clone.
+    pub async fn bundle(&self) -> Result<Vec<u8>> {
+        let refs = self.bundle_refs().await?;
+        let mut data = header(&refs).into_bytes();
+        data.extend(self.build_pack(PackContent::Full).await?);
+        Ok(data)
+    }
+
+    /// The answer of the `bundle-uri`, which lists the bundle of the repository if it has one.
+    pub(crate) fn bundle_uri_list(&self) -> BytesMut {
+        let mut buf = BytesMut::new();
+        if let Some(uri) = &self.bundle_uri {
+            for line in [
+                "bundle.version=1".to_owned(),
+                "bundle.mode=all".to_owned(),
+                format!("bundle.all.uri={}", uri),
+            ] {
+                add_pkt_line_string(&mut buf, line + "\n");
+            }
+        }
+        buf.put(&PKT_LINE_END_MARKER[..]);
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::{header, parse_header, SIGNATURE};
+    use crate::internal::pack::preload::{decode_load, PackPreload};
+    use crate::internal::pack::tests::MemoryStorage;
+    use crate::protocol::{PackProtocol, Protocol};
+
+    const PACK: &str = "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+    const COMMIT: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+
+    #[test]
+    fn test_header() {
+        let refs = vec![
+            (COMMIT.to_owned(), "HEAD".to_owned()),
+            (COMMIT.to_owned(), "refs/heads/main".to_owned()),
+        ];
+        let data = header(&refs);
+        assert_eq!(
+            data,
+            format!("{}{c} HEAD\n{c} refs/heads/main\n\n", SIGNATURE, c = COMMIT)
+        );
+        assert_eq!(
+            parse_header(Cursor::new(format!("{}PACK", data))).unwrap(),
+            Some(refs)
+        );
+        for data in [
+            "# v3 git bundle\n\n".to_owned(),
+            format!("{}-{} the prerequisite\n\n", SIGNATURE, COMMIT),
+            format!("{}{} HEAD\n", SIGNATURE, COMMIT),
+        ] {
+            assert_eq!(parse_header(Cursor::new(data)).unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bundle() {
+        let storage = Arc::new(MemoryStorage::default());
+        let data = std::fs::read(PACK).unwrap();
+        let preload = PackPreload::new(BufReader::new(Cursor::new(data)));
+        decode_load(preload, storage.clone()).await.unwrap();
+        storage.add_ref("/projects/d50df", "refs/heads/main", COMMIT);
+        // not of a commit in the storage
+        storage.add_ref("/projects/d50df", "refs/tags/v1", &"1".repeat(40));
+        let mut protocol =
+            PackProtocol::new(PathBuf::from("/projects/d50df"), storage, Protocol::Http);
+
+        let bundle = protocol.bundle().await.unwrap();
+        let mut reader = Cursor::new(&bundle);
+        let refs = parse_header(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            refs,
+            [
+                (COMMIT.to_owned(), "HEAD".to_owned()),
+                (COMMIT.to_owned(), "refs/heads/main".to_owned())
+            ]
+        );
+        // the pack of all the objects after the header
+        let pack = &bundle[reader.position() as usize..];
+        let decoded = Arc::new(MemoryStorage::default());
+        let preload = PackPreload::new(BufReader::new(Cursor::new(pack.to_vec())));
+        decode_load(preload, decoded.clone()).await.unwrap();
+        assert!(decoded.objects.lock().unwrap().contains_key(COMMIT));
+
+        assert_eq!(&protocol.bundle_uri_list()[..], b"0000");
+        protocol.bundle_uri = Some("https://cdn.example.com/projects/d50df.bundle".to_owned());
+        assert_eq!(
+            String::from_utf8(protocol.bundle_uri_list().to_vec()).unwrap(),
+            "0015bundle.version=1\n0014bundle.mode=all\n\
+             0041bundle.all.uri=https://cdn.example.com/projects/d50df.bundle\n0000"
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! The dumb HTTP protocol, of which the clients fetch the files of a bare repository by plain
+//! GETs instead of the negotiated packs, e.g. the old clients, `GIT_SMART_HTTP=0`, or a proxy
+//! which only passes the static files. It's read-only.
+//!
+//! The files are made of the storage: `info/refs` as written by `git update-server-info`,
+//! `HEAD`, and the loose objects compressed by zlib. The storage has no packs, so
+//! `objects/info/packs` is empty, the `objects/pack/*` are not found, and the clients fetch the
+//! loose objects one by one from the tips of the refs.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use common::errors::MegaError;
+use database::driver::ObjectStorage;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::internal::object::tag::Tag;
+use crate::internal::object::ObjectT;
+use crate::protocol::pack::matches_prefix;
+use crate::protocol::symref::{default_branch, HEAD};
+
+/// A file of the dumb protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumbFile {
+    InfoRefs,
+    Head,
+    /// The `objects/info/packs`
+    Packs,
+    /// The loose object of the git id
+    Object(String),
+    /// A file in `objects/pack`
+    Pack(String),
+}
+
+impl DumbFile {
+    /// The repo and the file of the request path, e.g. `/projects/demo` and the object of
+    /// `/projects/demo.git/objects/8a/b686eafeb1f44702738c8b0f24f2567c36da6d`.
+    pub fn parse(path: &str) -> Option<(PathBuf, DumbFile)> {
+        let (repo, file) = if let Some(repo) = path.strip_suffix("/info/refs") {
+            (repo, DumbFile::InfoRefs)
+        } else if let Some(repo) = path.strip_suffix("/HEAD") {
+            (repo, DumbFile::Head)
+        } else if let Some(repo) = path.strip_suffix("/objects/info/packs") {
+            (repo, DumbFile::Packs)
+        } else {
+            let (rest, name) = path.rsplit_once('/')?;
+            let (repo, dir) = rest.rsplit_once('/')?;
+            let (repo, objects) = repo.rsplit_once('/')?;
+            if objects != "objects" {
+                return None;
+            }
+            let is_hex = |s: &str| {
+                s.bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+            };
+            if dir == "pack" {
+                (repo, DumbFile::Pack(name.to_owned()))
+            } else if dir.len() == 2 && name.len() == 38 && is_hex(dir) && is_hex(name) {
+                (repo, DumbFile::Object(format!("{}{}", dir, name)))
+            } else {
+                return None;
+            }
+        };
+        Some((
+            PathBuf::from(repo.strip_suffix(".git").unwrap_or(repo)),
+            file,
+        ))
+    }
+}
+
+/// The refs of the repo, one `<id>\t<name>` per line sorted by the names, and the peeled
+/// objects of the annotated tags in the `<name>^{}` lines. Only the refs of the `prefixes` are
+/// listed if there are any.
+pub async fn info_refs(
+    storage: &Arc<dyn ObjectStorage>,
+    repo_path: &str,
+    prefixes: &[String],
+) -> Result<String, MegaError> {
+    let mut refs = storage.get_ref_object_id(repo_path).await?;
+    refs.retain(|r| matches_prefix(prefixes, &r.ref_name));
+    refs.sort_by(|a, b| a.ref_name.cmp(&b.ref_name));
+    let mut lines = String::new();
+    for r in refs {
+        lines.push_str(&format!("{}\t{}\n", r.ref_git_id, r.ref_name));
+        if !r.ref_name.starts_with("refs/tags/") {
+            continue;
+        }
+        if let Some(object) = storage.get_obj_data_by_id(&r.ref_git_id).await? {
+            if object.object_type == "tag" {
+                let tag = Tag::new_from_data(object.data);
+                lines.push_str(&format!(
+                    "{}\t{}^{{}}\n",
+                    tag.object_hash.to_plain_str(),
+                    r.ref_name
+                ));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+/// The `HEAD` of the repo, which is the branch of the target of it saved, or the `main` or the
+/// `master` branch, or the first one, see [`default_branch`], none if there is no branch.
+pub async fn head(
+    storage: &Arc<dyn ObjectStorage>,
+    repo_path: &str,
+) -> Result<Option<String>, MegaError> {
+    let refs = storage.get_ref_object_id(repo_path).await?;
+    let target = storage.get_symref(repo_path, HEAD).await?;
+    let head = default_branch(target.as_ref().map(|s| s.target.as_str()), &refs);
+    Ok(head.map(|r| format!("ref: {}\n", r.ref_name)))
+}
+
+/// The loose object of the git id, i.e. the header and the data compressed by zlib, none if
+/// it's not saved.
+pub async fn loose_object(
+    storage: &Arc<dyn ObjectStorage>,
+    git_id: &str,
+) -> Result<Option<Vec<u8>>, MegaError> {
+    let Some(object) = storage.get_obj_data_by_id(git_id).await? else {
+        return Ok(None);
+    };
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(format!("{} {}\0", object.object_type, object.data.len()).as_bytes())?;
+    encoder.write_all(&object.data)?;
+    Ok(Some(encoder.finish()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+    use entity::{git_obj, symrefs};
+    use flate2::read::ZlibDecoder;
+    use sea_orm::Set;
+
+    use super::{head, info_refs, loose_object, DumbFile};
+    use crate::hash::Hash;
+
+    #[test]
+    fn test_parse() {
+        let repo = PathBuf::from("/projects/demo");
+        assert_eq!(
+            DumbFile::parse("/projects/demo.git/info/refs"),
+            Some((repo.clone(), DumbFile::InfoRefs))
+        );
+        assert_eq!(
+            DumbFile::parse("/projects/demo/HEAD"),
+            Some((repo.clone(), DumbFile::Head))
+        );
+        assert_eq!(
+            DumbFile::parse("/projects/demo.git/objects/info/packs"),
+            Some((repo.clone(), DumbFile::Packs))
+        );
+        assert_eq!(
+            DumbFile::parse("/projects/demo.git/objects/8a/b686eafeb1f44702738c8b0f24f2567c36da6d"),
+            Some((
+                repo.clone(),
+                DumbFile::Object("8ab686eafeb1f44702738c8b0f24f2567c36da6d".to_owned())
+            ))
+        );
+        assert_eq!(
+            DumbFile::parse("/projects/demo.git/objects/pack/pack-1.idx"),
+            Some((repo, DumbFile::Pack("pack-1.idx".to_owned())))
+        );
+        assert_eq!(DumbFile::parse("/projects/demo.git/objects/8a/b686"), None);
+        assert_eq!(DumbFile::parse("/projects/demo.git/git-upload-pack"), None);
+    }
+
+    #[tokio::test]
+    async fn test_info_refs_and_objects() {
+        let storage: Arc<dyn ObjectStorage> = Arc::new(MemoryStorage::new());
+        let commit = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+        let data = format!(
+            "object {}\ntype commit\ntag v1\ntagger A <a@example.com> 1700000000 +0000\n\nv1\n",
+            commit
+        );
+        let tag = Hash::new(format!("tag {}\0{}", data.len(), data).as_bytes()).to_plain_str();
+        storage
+            .put_object(git_obj::ActiveModel {
+                id: Set(1),
+                git_id: Set(tag.clone()),
+                object_type: Set("tag".to_owned()),
+                data: Set(data.clone().into_bytes()),
+                compression: Set(None),
+            })
+            .await
+            .unwrap();
+        assert_eq!(head(&storage, "/projects/demo").await.unwrap(), None);
+        storage
+            .update_ref("/projects/demo", "refs/tags/v1", &tag)
+            .await
+            .unwrap();
+        storage
+            .update_ref("/projects/demo", "refs/heads/dev", commit)
+            .await
+            .unwrap();
+        storage
+            .update_ref("/projects/demo", "refs/heads/main", commit)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            info_refs(&storage, "/projects/demo", &[]).await.unwrap(),
+            format!(
+                "{commit}\trefs/heads/dev\n{commit}\trefs/heads/main\n{tag}\trefs/tags/v1\n{commit}\trefs/tags/v1^{{}}\n"
+            )
+        );
+        let prefixes = ["refs/heads/m".to_owned(), "refs/tags/".to_owned()];
+        assert_eq!(
+            info_refs(&storage, "/projects/demo", &prefixes)
+                .await
+                .unwrap(),
+            format!(
+                "{commit}\trefs/heads/main\n{tag}\trefs/tags/v1\n{commit}\trefs/tags/v1^{{}}\n"
+            )
+        );
+        assert_eq!(
+            head(&storage, "/projects/demo").await.unwrap().as_deref(),
+            Some("ref: refs/heads/main\n")
+        );
+        storage
+            .save_symref(symrefs::Model {
+                repo_path: "/projects/demo".to_owned(),
+                ref_name: "HEAD".to_owned(),
+                target: "refs/heads/dev".to_owned(),
+                updated_at: chrono::Utc::now().naive_utc(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            head(&storage, "/projects/demo").await.unwrap().as_deref(),
+            Some("ref: refs/heads/dev\n")
+        );
+
+        let compressed = loose_object(&storage, &tag).await.unwrap().unwrap();
+        let mut object = String::new();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_string(&mut object)
+            .unwrap();
+        assert_eq!(object, format!("tag {}\0{}", data.len(), data));
+        assert_eq!(loose_object(&storage, commit).await.unwrap(), None);
+    }
+}
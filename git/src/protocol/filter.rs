@@ -0,0 +1,168 @@
+//! The partial clone of the upload-pack, e.g. `git clone --filter=blob:none`, of which the
+//! blobs are left out of the pack, and the client, which keeps the remote as a promisor, fetches
+//! them on demand later.
+//!
+//! The objects asked for by the `want`s are always sent, so a missing blob is fetched by the
+//! id of it even with the same filter.
+
+use std::str::FromStr;
+
+/// The filter of the objects to send, of the `filter` line of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// No blob
+    BlobNone,
+    /// The blobs smaller than this many bytes only
+    BlobLimit(u64),
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// The filter spec, `blob:none` or `blob:limit=<n>[kmg]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "blob:none" {
+            return Ok(Filter::BlobNone);
+        }
+        let limit = s
+            .strip_prefix("blob:limit=")
+            .ok_or_else(|| format!("unsupported filter: {}", s))?;
+        let (digits, unit) = match limit.char_indices().last() {
+            Some((i, 'k')) => (&limit[..i], 1 << 10),
+            Some((i, 'm')) => (&limit[..i], 1 << 20),
+            Some((i, 'g')) => (&limit[..i], 1 << 30),
+            _ => (limit, 1),
+        };
+        digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(unit))
+            .map(Filter::BlobLimit)
+            .ok_or_else(|| format!("invalid blob limit: {}", limit))
+    }
+}
+
+impl Filter {
+    /// Whether a blob of the size is sent.
+    pub fn includes_blob(&self, size: usize) -> bool {
+        match self {
+            Filter::BlobNone => false,
+            Filter::BlobLimit(limit) => (size as u64) < *limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use bytes::{BufMut, BytesMut};
+
+    use super::Filter;
+    use crate::internal::pack::stream::PackStream;
+    use crate::internal::pack::tests::MemoryStorage;
+    use crate::internal::ObjectType;
+    use crate::protocol::tests::{pkt_line, storage, HEAD};
+    use crate::protocol::{PackProtocol, Protocol};
+
+    /// The objects of the pack sent for the `want` and the `filter`, by the id.
+    async fn fetch(
+        storage: Arc<MemoryStorage>,
+        want: &str,
+        filter: &str,
+    ) -> (HashMap<String, (ObjectType, usize)>, BytesMut) {
+        let mut protocol =
+            PackProtocol::new(PathBuf::from("/projects/d50df"), storage, Protocol::Http);
+        let mut request = BytesMut::new();
+        pkt_line(&mut request, &format!("want {} side-band-64k\n", want));
+        pkt_line(&mut request, &format!("filter {}\n", filter));
+        request.put(&b"0000"[..]);
+        pkt_line(&mut request, "done\n");
+        let (pack, buf) = protocol
+            .git_upload_pack(&mut request.freeze())
+            .await
+            .unwrap();
+        let mut objects = HashMap::new();
+        if !pack.is_empty() {
+            let mut stream = PackStream::new(Cursor::new(pack)).unwrap();
+            while let Some(object) = stream.next_object().await.unwrap() {
+                objects.insert(
+                    object.hash.to_plain_str(),
+                    (object.object_type, object.data.len()),
+                );
+            }
+        }
+        (objects, buf)
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!("blob:none".parse(), Ok(Filter::BlobNone));
+        assert_eq!("blob:limit=100".parse(), Ok(Filter::BlobLimit(100)));
+        assert_eq!("blob:limit=2k".parse(), Ok(Filter::BlobLimit(2048)));
+        assert_eq!("blob:limit=1m".parse(), Ok(Filter::BlobLimit(1 << 20)));
+        assert!("tree:0".parse::<Filter>().is_err());
+        assert!("blob:limit=".parse::<Filter>().is_err());
+        assert!("blob:limit=10x".parse::<Filter>().is_err());
+
+        assert!(!Filter::BlobNone.includes_blob(0));
+        assert!(Filter::BlobLimit(10).includes_blob(9));
+        assert!(!Filter::BlobLimit(10).includes_blob(10));
+    }
+
+    #[tokio::test]
+    async fn test_upload_pack_blob_none() {
+        let storage = storage().await;
+        let (objects, buf) = fetch(storage.clone(), HEAD, "blob:none").await;
+        assert_eq!(&buf[..], b"0008NAK\n");
+        // all the commits and the trees of the pack
+        assert_eq!(objects.len(), 37 + 154);
+        assert!(objects.values().all(|(t, _)| *t != ObjectType::Blob));
+
+        // and a blob left out is fetched by the id
+        let blob = storage
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, (object_type, _))| object_type == "blob")
+            .map(|(id, _)| id.clone())
+            .unwrap();
+        let (objects, _) = fetch(storage, &blob, "blob:none").await;
+        assert_eq!(objects.keys().collect::<Vec<_>>(), vec![&blob]);
+    }
+
+    #[tokio::test]
+    async fn test_upload_pack_blob_limit() {
+        let storage = storage().await;
+        let limit = 1000;
+        let (objects, _) = fetch(storage.clone(), HEAD, &format!("blob:limit={}", limit)).await;
+        let blobs: HashMap<String, usize> = storage
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, (object_type, _))| object_type == "blob")
+            .map(|(id, (_, data))| (id.clone(), data.len()))
+            .collect();
+        let (small, large): (Vec<_>, Vec<_>) = blobs.iter().partition(|(_, size)| **size < limit);
+        assert!(!small.is_empty() && !large.is_empty());
+        for (id, _) in small {
+            assert_eq!(objects[id].0, ObjectType::Blob);
+        }
+        for (id, _) in large {
+            assert!(!objects.contains_key(id));
+        }
+        assert_eq!(
+            objects.len(),
+            37 + 154 + blobs.values().filter(|size| **size < limit).count()
+        );
+
+        let (objects, buf) = fetch(storage, HEAD, "sparse:oid=main").await;
+        assert!(objects.is_empty());
+        assert_eq!(&buf[..], b"002cERR unsupported filter: sparse:oid=main\n");
+    }
+}
@@ -0,0 +1,180 @@
+//! The hooks of the pushes, like the `pre-receive` and `post-receive` of git.
+//!
+//! The `pre-receive` checks the ref updates of a checked pack before anything is saved, and
+//! rejects all of them by an error, e.g. of a policy of the commit messages. The `post-receive`
+//! is run after the refs are updated, e.g. to notify a CI. The output of both is relayed to the
+//! client as the progress, which git prints with the `remote:` prefix, once the hook exits.
+//!
+//! The [`ScriptHooks`] run the executables like git: the updates are written to the stdin, one
+//! `<old-id> SP <new-id> SP <ref-name> LF` per line, a non-zero exit of the `pre-receive`
+//! rejects the push, and the repo path is the `MEGA_REPO_PATH` of the environment.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::RefCommand;
+
+#[async_trait]
+pub trait ReceiveHook: Send + Sync {
+    /// Check the updates before they are saved, the push is rejected by the error of the
+    /// reason. The output is relayed to the client.
+    async fn pre_receive(
+        &self,
+        _repo_path: &Path,
+        _updates: &[RefCommand],
+        _output: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Run after the updates are saved, of which the output is relayed to the client.
+    async fn post_receive(
+        &self,
+        _repo_path: &Path,
+        _updates: &[RefCommand],
+        _output: &mut Vec<u8>,
+    ) {
+    }
+}
+
+/// The hooks of the executables, none of which is run if it's not set.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptHooks {
+    pub pre_receive: Option<PathBuf>,
+    pub post_receive: Option<PathBuf>,
+}
+
+impl ScriptHooks {
+    /// Run the script, of which the stdout and then the stderr are appended to the output,
+    /// returns if it exits successfully.
+    async fn run(
+        script: &Path,
+        repo_path: &Path,
+        updates: &[RefCommand],
+        output: &mut Vec<u8>,
+    ) -> std::io::Result<bool> {
+        let mut child = Command::new(script)
+            .env("MEGA_REPO_PATH", repo_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let input: String = updates
+            .iter()
+            .map(|c| format!("{} {} {}\n", c.old_id, c.new_id, c.ref_name))
+            .collect();
+        let mut stdin = child.stdin.take().unwrap();
+        // Written meanwhile, so a script which doesn't read all of it still exits.
+        let write = tokio::spawn(async move { stdin.write_all(input.as_bytes()).await });
+        let result = child.wait_with_output().await?;
+        // A broken pipe of a script which ignores the stdin is fine.
+        let _ = write.await;
+        output.extend(result.stdout);
+        output.extend(result.stderr);
+        Ok(result.status.success())
+    }
+}
+
+#[async_trait]
+impl ReceiveHook for ScriptHooks {
+    async fn pre_receive(
+        &self,
+        repo_path: &Path,
+        updates: &[RefCommand],
+        output: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let Some(script) = &self.pre_receive else {
+            return Ok(());
+        };
+        match ScriptHooks::run(script, repo_path, updates, output).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(String::from("pre-receive hook declined")),
+            Err(e) => {
+                tracing::error!("Failed to run the pre-receive hook {:?}: {}", script, e);
+                Err(String::from("pre-receive hook failed"))
+            }
+        }
+    }
+
+    async fn post_receive(&self, repo_path: &Path, updates: &[RefCommand], output: &mut Vec<u8>) {
+        let Some(script) = &self.post_receive else {
+            return;
+        };
+        match ScriptHooks::run(script, repo_path, updates, output).await {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!("The post-receive hook {:?} failed", script),
+            Err(e) => tracing::error!("Failed to run the post-receive hook {:?}: {}", script, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    use super::{ReceiveHook, ScriptHooks};
+    use crate::protocol::RefCommand;
+
+    /// Write the executable script of the body in the temp dir.
+    fn script(name: &str, body: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mega_hook_{}_{}", std::process::id(), name));
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_script_hooks() {
+        let updates = vec![RefCommand::new(
+            "0".repeat(40),
+            "1".repeat(40),
+            String::from("refs/heads/main"),
+        )];
+        let repo = Path::new("/projects/demo");
+        let accept = script(
+            "accept",
+            "read old new name; echo \"$MEGA_REPO_PATH $name\"",
+        );
+        let reject = script("reject", "echo 'the commits must be signed' >&2; exit 1");
+        let hooks = ScriptHooks {
+            pre_receive: Some(accept.clone()),
+            post_receive: Some(reject.clone()),
+        };
+
+        let mut output = Vec::new();
+        assert_eq!(hooks.pre_receive(repo, &updates, &mut output).await, Ok(()));
+        assert_eq!(output, b"/projects/demo refs/heads/main\n");
+        // the failed post-receive changes nothing but the output
+        let mut output = Vec::new();
+        hooks.post_receive(repo, &updates, &mut output).await;
+        assert_eq!(output, b"the commits must be signed\n");
+
+        let hooks = ScriptHooks {
+            pre_receive: Some(reject.clone()),
+            post_receive: None,
+        };
+        let mut output = Vec::new();
+        assert_eq!(
+            hooks.pre_receive(repo, &updates, &mut output).await,
+            Err(String::from("pre-receive hook declined"))
+        );
+        assert_eq!(output, b"the commits must be signed\n");
+
+        let hooks = ScriptHooks {
+            pre_receive: Some(PathBuf::from("/nonexistent/pre-receive")),
+            post_receive: None,
+        };
+        assert!(hooks
+            .pre_receive(repo, &updates, &mut output)
+            .await
+            .is_err());
+        std::fs::remove_file(accept).unwrap();
+        std::fs::remove_file(reject).unwrap();
+    }
+}
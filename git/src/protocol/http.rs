@@ -4,21 +4,23 @@
 //!
 //!
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io::Read;
 
 use anyhow::Result;
 use axum::body::Body;
 use axum::http::response::Builder;
-use axum::http::{Response, StatusCode};
+use axum::http::{header, HeaderMap, HeaderValue, Response, StatusCode};
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 
-use futures::StreamExt;
-use hyper::body::Sender;
+use flate2::read::GzDecoder;
+use futures::{stream, StreamExt};
 use hyper::Request;
 
-use tokio::io::{AsyncReadExt, BufReader};
+use tokio::sync::mpsc;
 
-use super::{pack, PackProtocol};
+use super::{progress, PackProtocol};
 
 /// # Build Response headers for Smart Server.
 /// Clients MUST NOT reuse or revalidate a cached response.
@@ -38,47 +40,32 @@ pub fn build_res_header(content_type: String) -> Builder {
     resp
 }
 
-/// # Sends a Git pack to the remote server.
-///
-/// This function takes a `Sender` for sending data to the remote server, the `result` vector
-/// containing the pack data, and the `pack_protocol` describing the pack transfer protocol.
-/// It asynchronously reads the pack data from the `result` vector in chunks, formats it using the
-/// side-band format specified by the `pack_protocol`, and sends it to the remote server using
-/// the `Sender`.
-///
-/// # Arguments
-///
-/// * `sender` - The sender for sending data to the remote server.
-/// * `result` - The vector containing the pack data to be sent.
-/// * `pack_protocol` - The pack protocol describing the pack transfer.
-///
-/// # Returns
-///
-/// * `Ok(())` - If the pack data is successfully sent to the remote server.
-/// * `Err((StatusCode, &'static str))` - If there is an error during the sending process, with the
-///   error status code and a corresponding error message.
-pub async fn send_pack(
-    mut sender: Sender,
-    result: Vec<u8>,
-    pack_protocol: PackProtocol,
-) -> Result<(), (StatusCode, &'static str)> {
-    let mut reader = BufReader::new(result.as_slice());
-    loop {
-        let mut temp = BytesMut::new();
-        temp.reserve(65500);
-        let length = reader.read_buf(&mut temp).await.unwrap();
-        if temp.is_empty() {
-            let mut bytes_out = BytesMut::new();
-            bytes_out.put_slice(pack::PKT_LINE_END_MARKER);
-            tracing::info!("send: bytes_out: {:?}", bytes_out.clone().freeze());
-            sender.send_data(bytes_out.freeze()).await.unwrap();
-            return Ok(());
-        }
-        let bytes_out = pack_protocol.build_side_band_format(temp, length);
-        tracing::info!("send: packet length: {:?}", bytes_out.len());
-        sender.send_data(bytes_out.freeze()).await.unwrap();
-    }
+/// The strong `ETag` of an object by the id of it, which never changes as the objects are
+/// addressed by the content, e.g. the loose objects and the LFS objects.
+pub fn etag(id: &str) -> String {
+    format!("\"{}\"", id)
+}
+
+/// Whether the `If-None-Match` of the request matches the `etag`, so the client has the object
+/// already. The tags are compared weakly like RFC 9110, i.e. `W/"id"` matches as well.
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// The `304 Not Modified` of the object of the `etag`.
+pub fn not_modified(etag: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(header::ETAG, etag)
+        .body(Body::empty())
+        .unwrap()
 }
+
 /// # Handles a Git upload pack request and prepares the response.
 ///
 /// The function takes a `req` parameter representing the HTTP request received and a `pack_protocol`
@@ -88,44 +75,75 @@ pub async fn send_pack(
 /// of the request body using `body.next().await`. The chunks are concatenated into the `upload_request`
 /// buffer.
 ///
-/// The `pack_protocol` is then used to process the `upload_request` using the `git_upload_pack` method.
+/// The `pack_protocol` is then used to process the `upload_request` using the `git_upload_pack` method,
+/// or as a command of the protocol v2 if the client speaks it, see [`v2`](super::v2).
 /// It returns the `send_pack_data` and `buf` containing the response data.
 ///
 /// A response header is constructed using the `build_res_header` function with a content type of
-/// "application/x-git-upload-pack-result". The response body channel is created using `Body::channel()`.
+/// "application/x-git-upload-pack-result". The response body is streamed from a channel.
 ///
-/// The `buf` is sent as the initial data using the `sender` to establish the response body.
+/// The `buf` is sent as the initial data to establish the response body.
 ///
-/// A new task is spawned to send the remaining `send_pack_data` using the `send_pack` function.
+/// A new task is spawned to build and send the pack by [`progress::send_pack`], with the progress
+/// and the keepalives in the side-band meanwhile.
 ///
 /// Finally, the constructed response with the response body is returned.
 pub async fn git_upload_pack(
     req: Request<Body>,
     mut pack_protocol: PackProtocol,
 ) -> Result<Response<Body>, (StatusCode, String)> {
-    let (_parts, mut body) = req.into_parts();
+    let (parts, mut body) = req.into_parts();
 
     let mut upload_request = BytesMut::new();
 
     while let Some(chunk) = body.next().await {
         tracing::info!("client sends :{:?}", chunk);
-        let bytes = chunk.unwrap();
+        let bytes = chunk.map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("failed to read the request: {}", e),
+            )
+        })?;
         upload_request.extend_from_slice(&bytes);
     }
+    // git compresses the large requests, e.g. of the blobs fetched on demand by a partial clone
+    if parts.headers.get(header::CONTENT_ENCODING) == Some(&HeaderValue::from_static("gzip")) {
+        let mut data = Vec::new();
+        GzDecoder::new(&upload_request[..])
+            .read_to_end(&mut data)
+            .map_err(|e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid gzip request: {}", e),
+                )
+            })?;
+        upload_request = BytesMut::from(&data[..]);
+    }
 
-    let (send_pack_data, buf) = pack_protocol
-        .git_upload_pack(&mut upload_request.freeze())
-        .await
-        .unwrap();
+    let (content, buf) = if pack_protocol.v2 {
+        pack_protocol.v2_request(&upload_request).await.unwrap()
+    } else {
+        pack_protocol
+            .negotiate_upload_pack(&mut upload_request.freeze())
+            .await
+            .unwrap()
+    };
     let resp = build_res_header("application/x-git-upload-pack-result".to_owned());
 
     tracing::info!("send buf: {:?}", buf);
 
-    let (mut sender, body) = Body::channel();
-    sender.send_data(buf.freeze()).await.unwrap();
+    let (sender, receiver) = mpsc::channel(16);
+    sender.send(buf.freeze()).await.unwrap();
 
-    tokio::spawn(send_pack(sender, send_pack_data, pack_protocol));
-    Ok(resp.body(body).unwrap())
+    // no pack of the shallow update only, or of an `ERR`
+    if let Some(content) = content {
+        tokio::spawn(progress::send_pack(pack_protocol, content, sender));
+    }
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        let bytes = receiver.recv().await?;
+        Some((Ok::<_, Infallible>(bytes), receiver))
+    });
+    Ok(resp.body(Body::wrap_stream(stream)).unwrap())
 }
 
 /// # Handles a Git receive pack request and prepares the response.
@@ -148,14 +166,15 @@ pub async fn git_upload_pack(
 /// "application/x-git-receive-pack-result". The response body is set to `body`.
 ///
 /// Finally, the constructed response is returned.
+/// The `pack_protocol` keeps the ref commands of the push after it returns.
 pub async fn git_receive_pack(
     req: Request<Body>,
-    mut pack_protocol: PackProtocol,
+    pack_protocol: &mut PackProtocol,
 ) -> Result<Response<Body>, (StatusCode, String)> {
     let (_parts, mut body) = req.into_parts();
     let mut combined_body_bytes = Vec::new();
     while let Some(chunk) = body.next().await {
-        let body_bytes = chunk.unwrap();
+        let body_bytes = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
         combined_body_bytes.extend(&body_bytes);
     }
 
@@ -174,4 +193,27 @@ pub async fn git_receive_pack(
     Ok(resp)
 }
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use axum::http::{header, HeaderMap};
+
+    use super::{etag, if_none_match};
+
+    #[test]
+    fn test_if_none_match() {
+        let tag = etag("d767d4967b3e14ede397c552b9d352af52a4bbd9");
+        assert_eq!(tag, "\"d767d4967b3e14ede397c552b9d352af52a4bbd9\"");
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, value.parse().unwrap());
+            headers
+        };
+        assert!(!if_none_match(&HeaderMap::new(), &tag));
+        assert!(if_none_match(&headers(&tag), &tag));
+        let tags = format!("\"abc\", W/{}", tag);
+        assert!(if_none_match(&headers(&tags), &tag));
+        assert!(if_none_match(&headers("*"), &tag));
+        assert!(!if_none_match(&headers("\"abc\""), &tag));
+        // not quoted
+        assert!(!if_none_match(&headers(&tag[1..41]), &tag));
+    }
+}
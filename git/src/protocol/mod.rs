@@ -3,32 +3,50 @@
 //!
 //!
 //!
+pub mod bundle;
+pub mod dumb;
+pub mod filter;
+pub mod hooks;
 pub mod http;
+pub mod namespace;
+pub mod negotiation;
 pub mod pack;
+pub mod progress;
+pub mod shallow;
 pub mod ssh;
+pub mod symref;
+pub mod v2;
 
 use std::{
     io::Cursor,
-    path::{Path, PathBuf},
+    path::PathBuf,
     str::FromStr,
     sync::Arc,
+    time::Duration,
 };
 
-use database::driver::{mysql::storage::MysqlStorage, ObjectStorage};
+use database::driver::{memory::storage::MemoryStorage, ObjectStorage, RefUpdate};
 
 use crate::{
     errors::GitError,
+    internal::commit_graph::CommitGraphCache,
+    internal::object_cache::SharedObjectCache,
     internal::pack::{
         decode::HashCounter,
-        preload::{decode_load, PackPreload},
+        encode::{DEFAULT_DEPTH, DEFAULT_WINDOW},
+        preload::{decode_load_with, DecodeOptions, PackPreload},
+    },
+    protocol::{
+        filter::Filter, hooks::ReceiveHook, pack::SP, progress::DEFAULT_KEEPALIVE,
+        shallow::ShallowInfo,
     },
-    protocol::pack::SP,
 };
 
 use bytes::Bytes;
 use common::{errors::MegaError, utils::ZERO_ID};
 use entity::{mr_info, refs};
 use sea_orm::{ActiveValue::NotSet, Set};
+use tokio::sync::mpsc::UnboundedSender;
 
 #[derive(Clone)]
 pub struct PackProtocol {
@@ -39,6 +57,41 @@ pub struct PackProtocol {
     pub command_list: Vec<RefCommand>,
     // only needed in ssh protocal
     pub service_type: Option<ServiceType>,
+    /// The delta compression of the packs to fetch, see
+    /// [`Encoder::add_objects_with_delta`](crate::internal::pack::encode::Encoder::add_objects_with_delta).
+    pub window: usize,
+    pub depth: usize,
+    /// The shallow update sent to the client, which is sent once in a stateful connection.
+    pub shallow: Option<ShallowInfo>,
+    /// The filter of the blobs of a partial clone.
+    pub filter: Option<Filter>,
+    /// The commit-graphs of the negotiation, shared by the connections of the server.
+    pub commit_graphs: Arc<CommitGraphCache>,
+    /// The objects of the fetches, shared by the connections of the server.
+    pub objects: Arc<SharedObjectCache>,
+    /// Reject the pushed blobs larger than this many bytes which are not LFS pointers.
+    pub enforce_lfs_threshold: Option<u64>,
+    /// The hooks of the pushes.
+    pub hooks: Option<Arc<dyn ReceiveHook>>,
+    /// How the pack of a push is decoded, see
+    /// [`decode_load_with`](crate::internal::pack::preload::decode_load_with).
+    pub decode: DecodeOptions,
+    /// The interval of the keepalives while the pack of an upload-pack is built, see
+    /// [`progress`].
+    pub keepalive: Option<Duration>,
+    /// The receiver of the progress of the pack being built.
+    pub progress: Option<UnboundedSender<String>>,
+    /// The namespace of the refs the client sees and pushes, see [`namespace`].
+    pub namespace: Option<String>,
+    /// Whether the client of the upload-pack speaks the protocol v2, see [`v2`].
+    pub v2: bool,
+    /// Only the refs of these prefixes are advertised if there are any, e.g. `refs/heads/` of
+    /// the branches, so the client of a repository of a huge number of refs doesn't get all of
+    /// them.
+    pub ref_prefixes: Vec<String>,
+    /// The URI of the bundle of the repository advertised to the clients of the v2, see
+    /// [`bundle`].
+    pub bundle_uri: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy, Default)]
@@ -87,13 +140,23 @@ pub enum Capability {
     MultiAck,
     MultiAckDetailed,
     NoDone,
+    /// The deltas of the pack may be of the objects of the client, which are not sent.
+    ThinPack,
+    /// Send no progress in the side-band 2, e.g. of `git clone --quiet`.
+    NoProgress,
     SideBand,
     SideBand64k,
     ReportStatus,
     ReportStatusv2,
     OfsDelta,
+    Shallow,
     DeepenSince,
     DeepenNot,
+    Filter,
+    /// Apply all the ref updates of the push or none of them.
+    Atomic,
+    /// Check the push without saving anything.
+    DryRun,
 }
 
 impl FromStr for Capability {
@@ -109,8 +172,14 @@ impl FromStr for Capability {
             "multi_ack" => Ok(Capability::MultiAck),
             "multi_ack_detailed" => Ok(Capability::MultiAckDetailed),
             "no-done" => Ok(Capability::NoDone),
+            "thin-pack" => Ok(Capability::ThinPack),
+            "no-progress" => Ok(Capability::NoProgress),
+            "shallow" => Ok(Capability::Shallow),
             "deepen-since" => Ok(Capability::DeepenSince),
             "deepen-not" => Ok(Capability::DeepenNot),
+            "filter" => Ok(Capability::Filter),
+            "atomic" => Ok(Capability::Atomic),
+            "dry-run" => Ok(Capability::DryRun),
             _ => Err(()),
         }
     }
@@ -182,6 +251,7 @@ impl RefCommand {
         &mut self,
         storage: Arc<dyn ObjectStorage>,
         pack_file: &mut Bytes,
+        options: DecodeOptions,
     ) -> Result<i64, anyhow::Error> {
         let result: Result<i64, GitError> = {
             let count_hash: bool = true;
@@ -217,7 +287,7 @@ impl RefCommand {
             // // pack.signature = read_tail_hash(&mut reader);
             // // assert_eq!(_hash, pack.signature);
             let p = PackPreload::new(reader);
-            let mr_id = decode_load(p, storage.clone()).await?;
+            let mr_id = decode_load_with(p, storage.clone(), options).await?;
             storage.save_mr_info(self.new_mr_info(mr_id)).await.unwrap();
             Ok(mr_id)
         };
@@ -249,6 +319,11 @@ impl RefCommand {
         }
     }
 
+    /// The command is not failed.
+    pub fn is_ok(&self) -> bool {
+        self.status == RefCommand::OK_STATUS
+    }
+
     pub fn failed(&mut self, msg: String) {
         self.status = RefCommand::FAILED_STATUS.to_owned();
         self.error_msg = msg;
@@ -276,23 +351,15 @@ impl RefCommand {
         }
     }
 
-    pub async fn save_to_db(&self, storage: Arc<dyn ObjectStorage>, path: &Path) {
-        match self.command_type {
-            CommandType::Create => {
-                storage
-                    .save_refs(vec![self.convert_to_model(path.to_str().unwrap())])
-                    .await
-                    .unwrap();
-            }
-            CommandType::Delete => storage.delete_refs(self.old_id.clone(), path).await,
-            CommandType::Update => {
-                storage
-                    .update_refs(self.old_id.clone(), self.new_id.clone(), path)
-                    .await;
-            }
+    /// The update of the ref by the command, applied only if the ref is still the old id.
+    pub fn to_ref_update(&self) -> RefUpdate {
+        let id = |id: &String| (*id != ZERO_ID).then(|| id.clone());
+        RefUpdate {
+            ref_name: self.ref_name.clone(),
+            old_id: id(&self.old_id),
+            new_id: id(&self.new_id),
         }
     }
-
 }
 impl PackProtocol {
     pub fn new(path: PathBuf, storage: Arc<dyn ObjectStorage>, protocol: Protocol) -> Self {
@@ -303,6 +370,21 @@ impl PackProtocol {
             storage,
             command_list: Vec::new(),
             service_type: None,
+            window: DEFAULT_WINDOW,
+            depth: DEFAULT_DEPTH,
+            shallow: None,
+            filter: None,
+            commit_graphs: Arc::default(),
+            objects: Arc::default(),
+            enforce_lfs_threshold: None,
+            hooks: None,
+            decode: DecodeOptions::default(),
+            keepalive: Some(DEFAULT_KEEPALIVE),
+            progress: None,
+            namespace: None,
+            v2: false,
+            ref_prefixes: Vec::new(),
+            bundle_uri: None,
         }
     }
 
@@ -311,12 +393,66 @@ impl PackProtocol {
             protocol: Protocol::default(),
             capabilities: Vec::new(),
             path: PathBuf::new(),
-            storage: Arc::new(MysqlStorage::default()),
+            storage: Arc::new(MemoryStorage::new()),
             command_list: Vec::new(),
             service_type: None,
+            window: DEFAULT_WINDOW,
+            depth: DEFAULT_DEPTH,
+            shallow: None,
+            filter: None,
+            commit_graphs: Arc::default(),
+            objects: Arc::default(),
+            enforce_lfs_threshold: None,
+            hooks: None,
+            decode: DecodeOptions::default(),
+            keepalive: Some(DEFAULT_KEEPALIVE),
+            progress: None,
+            namespace: None,
+            v2: false,
+            ref_prefixes: Vec::new(),
+            bundle_uri: None,
+        }
+    }
+
+    /// The push is only checked, of which the objects and the refs are not saved.
+    pub fn dry_run(&self) -> bool {
+        self.capabilities.contains(&Capability::DryRun)
+    }
+
+    /// Report the progress of the pack being built, which is sent to the client if it's shown.
+    pub fn report_progress(&self, message: String) {
+        if let Some(progress) = &self.progress {
+            let _ = progress.send(message);
         }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+pub(crate) mod tests {
+    use std::io::{BufReader, Cursor};
+    use std::sync::Arc;
+
+    use bytes::{BufMut, Bytes, BytesMut};
+
+    use crate::internal::pack::preload::{decode_load, PackPreload};
+    use crate::internal::pack::tests::MemoryStorage;
+
+    /// The pack of the tests of the protocol, of which the [`HEAD`] is the last commit.
+    pub(crate) const PACK: &str =
+        "../tests/data/packs/pack-d50df695086eea6253a237cb5ac44af1629e7ced.pack";
+    pub(crate) const HEAD: &str = "d767d4967b3e14ede397c552b9d352af52a4bbd9";
+
+    /// The storage of the objects of the [`PACK`].
+    pub(crate) async fn storage() -> Arc<MemoryStorage> {
+        let storage = Arc::new(MemoryStorage::default());
+        let data = std::fs::read(PACK).unwrap();
+        let preload = PackPreload::new(BufReader::new(Cursor::new(data)));
+        decode_load(preload, storage.clone()).await.unwrap();
+        storage
+    }
+
+    /// Append the line to the buffer as a pkt-line.
+    pub(crate) fn pkt_line(buf: &mut BytesMut, line: &str) {
+        buf.put(Bytes::from(format!("{:04x}{}", line.len() + 4, line)));
+    }
+}
@@ -0,0 +1,157 @@
+//! The ref namespaces of the repositories, by which a repository is served as many ones, e.g.
+//! of the tenants of a server, the same as the `GIT_NAMESPACE` of git, see
+//! `git help gitnamespaces`.
+//!
+//! The refs of the namespace `a` are saved as `refs/namespaces/a/refs/...`, and the ones of
+//! `a/b` as `refs/namespaces/a/refs/namespaces/b/refs/...`. The clients of a namespace see the
+//! refs of it without the prefix and nothing else, and the refs pushed by them are saved in it,
+//! while the objects are shared by all the namespaces of the repository.
+
+use common::errors::MegaError;
+use entity::refs;
+
+use super::PackProtocol;
+
+/// The segment of the path of a namespaced repository, e.g.
+/// `/projects/demo.git/namespaces/a` of the namespace `a` of `/projects/demo`.
+pub const PATH_SEGMENT: &str = ".git/namespaces/";
+
+/// The prefix of the refs of the namespace.
+pub fn ref_prefix(namespace: &str) -> String {
+    namespace
+        .split('/')
+        .map(|name| format!("refs/namespaces/{}/", name))
+        .collect()
+}
+
+/// Whether the namespace is the names of the letters, the digits, `-`, `_` and `.` split by
+/// `/`, none of which starts with a `.`.
+pub fn is_valid(namespace: &str) -> bool {
+    namespace.split('/').all(|name| {
+        !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    })
+}
+
+/// Split the namespace off the path of a repository by the [`PATH_SEGMENT`], none if the
+/// namespace of it is not valid.
+pub fn split_path(path: &str) -> Option<(&str, Option<&str>)> {
+    match path.split_once(PATH_SEGMENT) {
+        Some((repo, namespace)) => {
+            let namespace = namespace.trim_end_matches('/');
+            is_valid(namespace).then_some((repo, Some(namespace)))
+        }
+        None => Some((path, None)),
+    }
+}
+
+impl PackProtocol {
+    /// The name of the ref saved of the name the client sees.
+    pub fn full_ref_name(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}{}", ref_prefix(namespace), name),
+            None => name.to_owned(),
+        }
+    }
+
+    /// The refs of the repository the client sees, which are the ones of the namespace of it
+    /// without the prefix if it has one.
+    pub async fn visible_refs(&self) -> Result<Vec<refs::Model>, MegaError> {
+        let refs = self
+            .storage
+            .get_ref_object_id(self.path.to_str().unwrap())
+            .await?;
+        let Some(namespace) = &self.namespace else {
+            return Ok(refs);
+        };
+        let prefix = ref_prefix(namespace);
+        Ok(refs
+            .into_iter()
+            .filter_map(|mut r| {
+                r.ref_name = r.ref_name.strip_prefix(&prefix)?.to_owned();
+                // the refs of the namespaces in it are not of it
+                (!r.ref_name.starts_with("refs/namespaces/")).then_some(r)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use common::utils::ZERO_ID;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::{ObjectStorage, RefUpdate};
+
+    use super::{is_valid, ref_prefix, split_path};
+    use crate::protocol::{PackProtocol, Protocol};
+
+    #[test]
+    fn test_namespace_path() {
+        assert_eq!(ref_prefix("a"), "refs/namespaces/a/");
+        assert_eq!(ref_prefix("a/b"), "refs/namespaces/a/refs/namespaces/b/");
+        assert!(is_valid("tenant-1/team_a.b"));
+        for namespace in ["", "a//b", "../a", ".a", "a b", "a:b"] {
+            assert!(!is_valid(namespace), "{}", namespace);
+        }
+        assert_eq!(
+            split_path("/projects/demo.git/namespaces/a/"),
+            Some(("/projects/demo", Some("a")))
+        );
+        assert_eq!(
+            split_path("/projects/demo.git"),
+            Some(("/projects/demo.git", None))
+        );
+        assert_eq!(split_path("/projects/demo.git/namespaces/../b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_visible_refs() {
+        let storage = Arc::new(MemoryStorage::new());
+        let update = |name: &str| RefUpdate {
+            ref_name: name.to_owned(),
+            old_id: None,
+            new_id: Some(ZERO_ID.replace('0', "1")),
+        };
+        let updates = [
+            update("refs/heads/main"),
+            update("refs/namespaces/a/refs/heads/main"),
+            update("refs/namespaces/a/refs/namespaces/b/refs/heads/dev"),
+        ];
+        storage
+            .apply_ref_updates("/projects/demo", &updates, true)
+            .await
+            .unwrap();
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/demo"),
+            storage.clone(),
+            Protocol::Http,
+        );
+        let names = |protocol: &PackProtocol| {
+            let protocol = protocol.clone();
+            async move {
+                let refs = protocol.visible_refs().await.unwrap();
+                let mut names: Vec<String> = refs.into_iter().map(|r| r.ref_name).collect();
+                names.sort();
+                names
+            }
+        };
+        assert_eq!(names(&protocol).await.len(), 3);
+
+        protocol.namespace = Some("a".to_owned());
+        assert_eq!(names(&protocol).await, ["refs/heads/main"]);
+        assert_eq!(
+            protocol.full_ref_name("refs/heads/dev"),
+            "refs/namespaces/a/refs/heads/dev"
+        );
+        protocol.namespace = Some("a/b".to_owned());
+        assert_eq!(names(&protocol).await, ["refs/heads/dev"]);
+        protocol.namespace = Some("c".to_owned());
+        assert!(names(&protocol).await.is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+//! The negotiation of the upload-pack, by which the server finds the commits of the `have`s in
+//! common with the client, so only the objects the client doesn't have are sent.
+//!
+//! The `have`s are answered the same as `git upload-pack` by the `multi_ack` or the
+//! `multi_ack_detailed` of the client: each flush of them is answered by the `ACK`s of the
+//! common ones and a `NAK`, and once every `want` reaches a common commit, the server is ready
+//! to send the pack, which is sent after the `done` of the client, or right away of the
+//! `no-done`.
+
+use std::collections::HashSet;
+
+use bytes::BytesMut;
+
+use super::pack::{add_pkt_line_string, UploadRequest};
+use super::Capability;
+use crate::hash::Hash;
+use crate::internal::commit_graph::CommitGraph;
+
+/// How the common commits are acknowledged, by the capabilities of the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckMode {
+    /// Only the first common commit is acknowledged.
+    Single,
+    /// Each common commit is acknowledged by `ACK id continue`.
+    MultiAck,
+    /// Each common commit is acknowledged by `ACK id common`, and `ACK id ready` tells the
+    /// client to stop sending the `have`s.
+    Detailed,
+}
+
+impl AckMode {
+    pub fn of(capabilities: &[Capability]) -> AckMode {
+        if capabilities.contains(&Capability::MultiAckDetailed) {
+            AckMode::Detailed
+        } else if capabilities.contains(&Capability::MultiAck) {
+            AckMode::MultiAck
+        } else {
+            AckMode::Single
+        }
+    }
+}
+
+/// The outcome of the `have`s of a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Negotiation {
+    /// The `have`s in the repository, which the pack is not sent of.
+    pub common: HashSet<String>,
+    /// Whether the pack is sent after the answer, by the `done` or by the `no-done`.
+    pub done: bool,
+}
+
+/// The commits found in common so far.
+struct State<'a> {
+    graph: &'a CommitGraph,
+    want: Vec<Hash>,
+    mode: AckMode,
+    common: Vec<Hash>,
+    /// The last common `have`.
+    last: Option<&'a str>,
+    sent_ready: bool,
+    /// Whether every `want` reaches a common commit, computed again only once there are more
+    /// common commits.
+    give_up: Option<bool>,
+}
+
+impl<'a> State<'a> {
+    /// Whether the client needs no more `have`s.
+    fn ok_to_give_up(&mut self) -> bool {
+        let (graph, want, common) = (self.graph, &self.want, &self.common);
+        *self.give_up.get_or_insert_with(|| {
+            want.iter()
+                .all(|w| common.iter().any(|c| graph.is_ancestor(*c, *w)))
+        })
+    }
+
+    /// Answer the `have`s of a round, returns whether any of them is common and whether any
+    /// is not.
+    fn answer(&mut self, have: &'a [String], buf: &mut BytesMut) -> (bool, bool) {
+        let (mut got_common, mut got_other) = (false, false);
+        for id in have {
+            let hash = Hash::new_from_str(id);
+            if !self.graph.contains(&hash) {
+                got_other = true;
+                if self.mode != AckMode::Single && self.ok_to_give_up() {
+                    if self.mode == AckMode::Detailed {
+                        self.sent_ready = true;
+                        add_pkt_line_string(buf, format!("ACK {} ready\n", id));
+                    } else {
+                        add_pkt_line_string(buf, format!("ACK {} continue\n", id));
+                    }
+                }
+                continue;
+            }
+            got_common = true;
+            if !self.common.contains(&hash) {
+                self.common.push(hash);
+                self.give_up = None;
+            }
+            self.last = Some(id);
+            match self.mode {
+                AckMode::Detailed => add_pkt_line_string(buf, format!("ACK {} common\n", id)),
+                AckMode::MultiAck => add_pkt_line_string(buf, format!("ACK {} continue\n", id)),
+                AckMode::Single if self.common.len() == 1 => {
+                    add_pkt_line_string(buf, format!("ACK {}\n", id))
+                }
+                AckMode::Single => {}
+            }
+        }
+        (got_common, got_other)
+    }
+}
+
+/// Answer the `have`s of the request to the `buf` round by round, each of which is ended by a
+/// flush, and then the `done`. The answer of a request is the start of the one of the same
+/// request with more rounds, so a stateful connection sends the answer of each round once the
+/// flush of it is received.
+pub fn negotiate(
+    graph: &CommitGraph,
+    request: &UploadRequest,
+    mode: AckMode,
+    no_done: bool,
+    buf: &mut BytesMut,
+) -> Negotiation {
+    let mut state = State {
+        graph,
+        want: request
+            .want
+            .iter()
+            .map(|id| Hash::new_from_str(id))
+            .collect(),
+        mode,
+        common: Vec::new(),
+        last: None,
+        sent_ready: false,
+        give_up: None,
+    };
+    let negotiation = |state: State, done| Negotiation {
+        common: state.common.iter().map(|id| id.to_plain_str()).collect(),
+        done,
+    };
+
+    let mut start = 0;
+    for &end in &request.rounds {
+        let (got_common, got_other) = state.answer(&request.have[start..end], buf);
+        start = end;
+        if mode == AckMode::Detailed && got_common && !got_other && state.ok_to_give_up() {
+            state.sent_ready = true;
+            let last = state.last.unwrap();
+            add_pkt_line_string(buf, format!("ACK {} ready\n", last));
+        }
+        if state.common.is_empty() || mode != AckMode::Single {
+            add_pkt_line_string(buf, String::from("NAK\n"));
+        }
+        if let Some(last) = state.last.filter(|_| no_done && state.sent_ready) {
+            // the pack follows without the `done`
+            add_pkt_line_string(buf, format!("ACK {}\n", last));
+            return negotiation(state, true);
+        }
+    }
+    // the `have`s after the last flush are answered only by the `done`
+    if !request.done {
+        return negotiation(state, false);
+    }
+    state.answer(&request.have[start..], buf);
+    match state.last {
+        Some(last) if mode != AckMode::Single => {
+            add_pkt_line_string(buf, format!("ACK {}\n", last))
+        }
+        Some(_) => {}
+        None => add_pkt_line_string(buf, String::from("NAK\n")),
+    }
+    negotiation(state, true)
+}
+
+/// The common `have`s of a `fetch` of the protocol v2, each of which is acknowledged by an
+/// `ACK`, and whether the server is ready to send the pack, i.e. every `want` reaches one of
+/// them. All the `have`s are answered at once, as there are no rounds in a request of it.
+pub fn acknowledge(graph: &CommitGraph, request: &UploadRequest) -> (Vec<String>, bool) {
+    let mut state = State {
+        graph,
+        want: request
+            .want
+            .iter()
+            .map(|id| Hash::new_from_str(id))
+            .collect(),
+        mode: AckMode::Detailed,
+        common: Vec::new(),
+        last: None,
+        sent_ready: false,
+        give_up: None,
+    };
+    for id in &request.have {
+        let hash = Hash::new_from_str(id);
+        if graph.contains(&hash) && !state.common.contains(&hash) {
+            state.common.push(hash);
+        }
+    }
+    let ready = !state.common.is_empty() && state.ok_to_give_up();
+    let common = state.common.iter().map(|id| id.to_plain_str()).collect();
+    (common, ready)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use bytes::{BufMut, BytesMut};
+
+    use super::{negotiate, AckMode, Negotiation};
+    use crate::hash::Hash;
+    use crate::internal::commit_graph::CommitGraph;
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::tree::{Tree, TreeItemMode};
+    use crate::internal::object::ObjectT;
+    use crate::internal::pack::stream::PackStream;
+    use crate::internal::pack::tests::MemoryStorage;
+    use crate::protocol::pack::UploadRequest;
+    use crate::protocol::tests::{pkt_line, storage, HEAD};
+    use crate::protocol::{PackProtocol, Protocol};
+
+    /// The objects of the tree and the subtrees, and the tree itself, without the gitlinks.
+    fn tree_objects(storage: &MemoryStorage, id: &str, objects: &mut HashSet<String>) {
+        objects.insert(id.to_owned());
+        let data = storage.objects.lock().unwrap()[id].1.clone();
+        for item in Tree::new_from_data(data).tree_items {
+            match item.mode {
+                TreeItemMode::Tree => tree_objects(storage, &item.id.to_plain_str(), objects),
+                TreeItemMode::Commit => {}
+                _ => {
+                    objects.insert(item.id.to_plain_str());
+                }
+            }
+        }
+    }
+
+    fn commit(storage: &MemoryStorage, id: &str) -> Commit {
+        Commit::new_from_data(storage.objects.lock().unwrap()[id].1.clone())
+    }
+
+    fn answer(lines: &[&str]) -> String {
+        lines
+            .iter()
+            .map(|line| format!("{:04x}{}\n", line.len() + 5, line))
+            .collect()
+    }
+
+    #[test]
+    fn test_negotiate() {
+        // a line of the commits 0 <- 1 <- 2 <- 3
+        let id = |i: usize| Hash::new(i.to_string().as_bytes());
+        let graph = CommitGraph::from_commits((0..4).map(|i| {
+            let parents = if i == 0 { vec![] } else { vec![id(i - 1)] };
+            (id(i), parents)
+        }));
+        let (c1, c2) = (id(1).to_plain_str(), id(2).to_plain_str());
+        let other = Hash::new(b"other").to_plain_str();
+        let mut request = UploadRequest {
+            want: HashSet::from([id(3).to_plain_str()]),
+            have: vec![other.clone(), c1.clone()],
+            rounds: vec![2],
+            ..Default::default()
+        };
+        let run = |request: &UploadRequest, mode, no_done| {
+            let mut buf = BytesMut::new();
+            let negotiation = negotiate(&graph, request, mode, no_done, &mut buf);
+            (String::from_utf8(buf.to_vec()).unwrap(), negotiation)
+        };
+
+        // not ready, of the `have` the server doesn't have
+        let first = answer(&[&format!("ACK {} common", c1), "NAK"]);
+        let (buf, negotiation) = run(&request, AckMode::Detailed, false);
+        assert_eq!(buf, first);
+        assert!(!negotiation.done);
+
+        // ready of the next round, which is answered after the one before
+        request.have.push(c2.clone());
+        request.rounds.push(3);
+        let (buf, negotiation) = run(&request, AckMode::Detailed, false);
+        let ready = [
+            format!("ACK {} common", c2),
+            format!("ACK {} ready", c2),
+            "NAK".to_owned(),
+        ];
+        let ready: Vec<&str> = ready.iter().map(String::as_str).collect();
+        assert_eq!(buf, first.clone() + &answer(&ready));
+        assert!(!negotiation.done);
+        // and the pack follows of the `no-done`
+        let (buf, negotiation) = run(&request, AckMode::Detailed, true);
+        let ack = format!("ACK {}", c2);
+        assert!(buf.ends_with(&answer(&["NAK", &ack])));
+        assert_eq!(
+            negotiation,
+            Negotiation {
+                common: HashSet::from([c1.clone(), c2.clone()]),
+                done: true,
+            }
+        );
+
+        // only the first common one is acknowledged without the `multi_ack`
+        request.done = true;
+        let (buf, negotiation) = run(&request, AckMode::Single, false);
+        assert_eq!(buf, answer(&[&format!("ACK {}", c1)]));
+        assert!(negotiation.done);
+        let (buf, _) = run(&request, AckMode::MultiAck, false);
+        assert!(buf.ends_with(&answer(&["NAK", &ack])));
+
+        // nothing in common
+        request.have = vec![other];
+        request.rounds = vec![];
+        let (buf, negotiation) = run(&request, AckMode::Detailed, false);
+        assert_eq!(buf, answer(&["NAK"]));
+        assert!(negotiation.common.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_ids() {
+        let storage = Arc::new(MemoryStorage::default());
+        let not_hex = "z".repeat(40);
+        for (want, have, err) in [
+            (HEAD, not_hex.as_str(), format!("invalid have: {}", not_hex)),
+            (HEAD, "d767d49", "invalid have: d767d49".to_owned()),
+            ("d767", HEAD, "invalid want: d767 ofs-delta".to_owned()),
+        ] {
+            let mut request = BytesMut::new();
+            pkt_line(&mut request, &format!("want {} ofs-delta\n", want));
+            request.put(&b"0000"[..]);
+            pkt_line(&mut request, &format!("have {}\n", have));
+            pkt_line(&mut request, "done\n");
+            let mut protocol =
+                PackProtocol::new("/projects/none".into(), storage.clone(), Protocol::Http);
+            let (pack, buf) = protocol
+                .git_upload_pack(&mut request.freeze())
+                .await
+                .unwrap();
+            assert!(pack.is_empty());
+            assert_eq!(&buf[4..], format!("ERR {}\n", err).as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_after_clone() {
+        let storage = storage().await;
+        let path = PathBuf::from("/projects/d50df");
+        // the client cloned the parent of the HEAD
+        let parent = commit(&storage, HEAD).parent_tree_ids[0].to_plain_str();
+        let fetch = |thin_pack: bool| {
+            let storage = storage.clone();
+            let path = path.clone();
+            let parent = parent.clone();
+            async move {
+                let mut protocol = PackProtocol::new(path, storage, Protocol::Http);
+                let caps = match thin_pack {
+                    true => "multi_ack_detailed side-band-64k thin-pack ofs-delta",
+                    false => "multi_ack_detailed side-band-64k ofs-delta",
+                };
+                let mut request = BytesMut::new();
+                pkt_line(&mut request, &format!("want {} {}\n", HEAD, caps));
+                request.put(&b"0000"[..]);
+                pkt_line(&mut request, &format!("have {}\n", parent));
+                pkt_line(&mut request, "done\n");
+                protocol
+                    .git_upload_pack(&mut request.freeze())
+                    .await
+                    .unwrap()
+            }
+        };
+        let (pack, buf) = fetch(true).await;
+        let ack = [format!("ACK {} common", parent), format!("ACK {}", parent)];
+        assert_eq!(
+            String::from_utf8(buf.to_vec()).unwrap(),
+            answer(&[&ack[0], &ack[1]])
+        );
+
+        // the bases of the thin pack are of the client
+        let mut stream = PackStream::new(Cursor::new(pack.clone())).unwrap();
+        stream.set_storage(Some(storage.clone()));
+        let mut sent = HashSet::new();
+        while let Some(object) = stream.next_object().await.unwrap() {
+            sent.insert(object.hash.to_plain_str());
+        }
+
+        // the new commits, and the objects of them not of the commits on the edge of them
+        let protocol = PackProtocol::new(path.clone(), storage.clone(), Protocol::Http);
+        let graph = protocol.commit_graph().await;
+        let new = graph.rev_list(&[Hash::new_from_str(HEAD)], &[Hash::new_from_str(&parent)]);
+        let mut expected: HashSet<String> = new.iter().map(|id| id.to_plain_str()).collect();
+        let mut edge_objects = HashSet::new();
+        let trees: HashMap<String, Commit> = new
+            .iter()
+            .chain(new.iter().flat_map(|id| graph.parents(id).unwrap()))
+            .map(|id| (id.to_plain_str(), commit(&storage, &id.to_plain_str())))
+            .collect();
+        for (id, c) in &trees {
+            let tree = c.tree_id.to_plain_str();
+            if new.contains(&Hash::new_from_str(id)) {
+                tree_objects(&storage, &tree, &mut expected);
+            } else {
+                tree_objects(&storage, &tree, &mut edge_objects);
+            }
+        }
+        expected.retain(|id| !edge_objects.contains(id));
+        assert!(!expected.is_empty());
+        assert_eq!(sent, expected);
+
+        // which is smaller than the whole tree of the HEAD, and than the pack which is not thin
+        let mut clone = HashSet::new();
+        tree_objects(
+            &storage,
+            &commit(&storage, HEAD).tree_id.to_plain_str(),
+            &mut clone,
+        );
+        assert!(sent.len() < clone.len());
+        let (full_pack, _) = fetch(false).await;
+        assert!(pack.len() < full_pack.len());
+    }
+}
@@ -3,13 +3,24 @@
 //!
 //!
 
+use crate::hash::Hash;
+use crate::internal::object::commit::Commit;
+use crate::internal::pack::{connectivity, lfs_threshold};
 use crate::protocol::ZERO_ID;
 use crate::structure::conversion;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use database::driver::RefUpdate;
+use entity::git_obj;
 use std::collections::HashSet;
+use std::io::Cursor;
 
-use super::{Capability, PackProtocol, Protocol, RefCommand, ServiceType, SideBind};
+use super::filter::Filter;
+use super::negotiation::{negotiate, AckMode};
+use super::shallow::Deepen;
+use super::symref::HEAD;
+use super::v2::is_object_id;
+use super::{Capability, CommandType, PackProtocol, Protocol, RefCommand, ServiceType, SideBind};
 
 const LF: char = '\n';
 
@@ -19,17 +30,57 @@ const NUL: char = '\0';
 
 pub const PKT_LINE_END_MARKER: &[u8; 4] = b"0000";
 
+/// The max data in a packet of the side-band-64k and the side-band, with the length and band.
+const MAX_SIDE_BAND_64K_DATA: usize = 65515;
+const MAX_SIDE_BAND_DATA: usize = 995;
+
 // The atomic, report-status, report-status-v2, delete-refs, quiet,
 // and push-cert capabilities are sent and recognized by the receive-pack (push to server) process.
-const RECEIVE_CAP_LIST: &str = "report-status report-status-v2 delete-refs quiet atomic ";
+// The `dry-run` of mega checks the push and reports the status of it, but nothing is saved,
+// e.g. for the checks of CI before a merge.
+const RECEIVE_CAP_LIST: &str = "report-status report-status-v2 delete-refs quiet atomic dry-run ";
 
 // The ofs-delta and side-band-64k capabilities are sent and recognized by both upload-pack and receive-pack protocols.
 // The agent and session-id capabilities may optionally be sent in both protocols.
 const CAP_LIST: &str = "side-band-64k ofs-delta object-format=sha1";
 
 // All other capabilities are only recognized by the upload-pack (fetch from server) process.
-const UPLOAD_CAP_LIST: &str =
-    "shallow deepen-since deepen-not deepen-relative multi_ack_detailed no-done ";
+// The `allow-*-sha1-in-want` are for the blobs fetched on demand by a partial clone.
+const UPLOAD_CAP_LIST: &str = "shallow deepen-since deepen-not filter allow-tip-sha1-in-want allow-reachable-sha1-in-want multi_ack_detailed no-done no-progress thin-pack ";
+
+/// The commands of an upload-pack request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadRequest {
+    pub want: HashSet<String>,
+    /// The `have`s in the order of the request.
+    pub have: Vec<String>,
+    /// The number of the `have`s before each flush after the `want`s, each of which asks for
+    /// the answer of the `have`s before it.
+    pub rounds: Vec<usize>,
+    /// The shallow commits of the client.
+    pub shallow: HashSet<String>,
+    pub deepen: Option<Deepen>,
+    /// The filter spec, e.g. `blob:none`.
+    pub filter: Option<String>,
+    pub done: bool,
+}
+
+/// The pack of an upload-pack request, which is built after the negotiation is answered.
+#[derive(Debug, Clone)]
+pub enum PackContent {
+    /// The commits of the shallow update, and the trees and the blobs of them.
+    Commits(Vec<Commit>),
+    /// The blobs and the trees wanted, e.g. of a partial clone.
+    Objects(Vec<git_obj::Model>),
+    /// All the objects of the repo.
+    Full,
+    /// The commits reachable from the `want`s but not from the common `have`s, and the trees
+    /// and the blobs of them which are not of the `have`s.
+    Incremental {
+        want: HashSet<String>,
+        have: HashSet<String>,
+    },
+}
 
 impl PackProtocol {
     /// # Retrieves the information about Git references (refs) for the specified service type.
@@ -38,16 +89,13 @@ impl PackProtocol {
     ///
     /// The `service_type` is extracted from the `PackProtocol` instance.
     ///
-    /// The function checks if the `object_id` of the head object in the storage is zero. If it is zero,
-    /// the name is set to "capabilities^{}" to include capability declarations behind a NUL on the first ref.
-    /// Otherwise, the name is set to "HEAD".
-    ///
-    /// The `cap_list` is determined based on the `service_type` and contains the appropriate capability lists.
+    /// The `HEAD` is the first ref if the `object_id` of it is not zero, and the `object_id` and `name`
+    /// pairs for other refs are retrieved using the `visible_refs` method. Only the refs of the
+    /// [`ref_prefixes`](PackProtocol::ref_prefixes) are advertised if there are any.
     ///
-    /// A packet line (`pkt_line`) is constructed using the `object_id`, `name`, `NUL` delimiter, `cap_list`, and line feed (`LF`).
-    /// The `pkt_line` is added to the `ref_list`.
+    /// The `cap_list` is determined based on the `service_type` and contains the appropriate capability lists,
+    /// which is behind a NUL on the first ref, or on the "capabilities^{}" of the zero id if there is no ref.
     ///
-    /// The `object_id` and `name` pairs for other refs are retrieved using the `get_ref_object_id` method from the storage.
     /// Each pair is used to construct a packet line (`pkt_line`) and added to the `ref_list`.
     ///
     /// The `build_smart_reply` method is called with the `ref_list`, `service_type`, and its string representation
@@ -57,62 +105,104 @@ impl PackProtocol {
     ///
     /// Finally, the constructed packet line stream is returned.
     pub async fn git_info_refs(&mut self, service_type: ServiceType) -> BytesMut {
-        // The stream MUST include capability declarations behind a NUL on the first ref.
-        let object_id = self.get_head_object_id(&self.path).await;
-        let name = if object_id == ZERO_ID {
-            "capabilities^{}"
-        } else {
-            "HEAD"
-        };
-        let cap_list = match service_type {
+        // the refs of the protocol v2 are listed by the `ls-refs` instead
+        if self.v2 && service_type == ServiceType::UploadPack {
+            return self.v2_capabilities();
+        }
+        let git_refs = self.visible_refs().await.unwrap();
+        let head = self
+            .head(&git_refs)
+            .await
+            .unwrap()
+            .filter(|_| matches_prefix(&self.ref_prefixes, HEAD));
+        let mut cap_list = match service_type {
             ServiceType::UploadPack => format!("{}{}", UPLOAD_CAP_LIST, CAP_LIST),
             ServiceType::ReceivePack => format!("{}{}", RECEIVE_CAP_LIST, CAP_LIST),
             // _ => CAP_LIST.to_owned(),
         };
-        let pkt_line = format!("{}{}{}{}{}{}", object_id, SP, name, NUL, cap_list, LF);
-        let mut ref_list = vec![pkt_line];
-
-        let git_refs = self
-            .storage
-            .get_ref_object_id(self.path.to_str().unwrap())
-            .await
-            .unwrap();
+        let mut ref_list = Vec::new();
+        if let Some(head) = head {
+            ref_list.push(format!("{}{}{}", head.id, SP, HEAD));
+            // the branch checked out by a clone
+            if let (Some(target), ServiceType::UploadPack) = (&head.target, service_type) {
+                cap_list.push_str(&format!("{}symref={}:{}", SP, HEAD, target));
+            }
+        }
         for git_ref in git_refs {
-            let pkt_line = format!("{}{}{}{}", git_ref.ref_git_id, SP, git_ref.ref_name, LF);
-            ref_list.push(pkt_line);
+            if matches_prefix(&self.ref_prefixes, &git_ref.ref_name) {
+                ref_list.push(format!("{}{}{}", git_ref.ref_git_id, SP, git_ref.ref_name));
+            }
+        }
+        // The stream MUST include capability declarations behind a NUL on the first ref.
+        match ref_list.first_mut() {
+            Some(first) => first.push_str(&format!("{}{}", NUL, cap_list)),
+            None => {
+                let pkt_line = format!("{}{}capabilities^{{}}{}{}", ZERO_ID, SP, NUL, cap_list);
+                ref_list.push(pkt_line);
+            }
+        }
+        for pkt_line in &mut ref_list {
+            pkt_line.push(LF);
         }
         let pkt_line_stream = self.build_smart_reply(&ref_list, service_type.to_string());
         tracing::info!("git_info_refs response: {:?}", pkt_line_stream);
         pkt_line_stream
     }
 
-    pub async fn git_upload_pack(
+    /// Parse the request, and the capabilities of the first line of it.
+    pub fn parse_upload_request(
         &mut self,
         upload_request: &mut Bytes,
-    ) -> Result<(Vec<u8>, BytesMut)> {
-        let mut want: HashSet<String> = HashSet::new();
-        let mut have: HashSet<String> = HashSet::new();
-
+    ) -> Result<UploadRequest, String> {
+        let mut request = UploadRequest::default();
         let mut read_first_line = false;
-        loop {
+        let mut read_wants = false;
+        while !upload_request.is_empty() {
             tracing::info!("loop start");
             let (bytes_take, pkt_line) = read_pkt_line(upload_request);
-            // read 0000 to continue and read empty str to break
+            // the first flush ends the `want`s, and each one after it a round of the `have`s
             if bytes_take == 0 {
-                if upload_request.is_empty() {
-                    break;
-                } else {
-                    continue;
+                if read_wants {
+                    request.rounds.push(request.have.len());
                 }
+                read_wants = true;
+                continue;
             }
             tracing::debug!("read line: {:?}", pkt_line);
             let dst = pkt_line.to_vec();
-            let commands = &dst[0..4];
+            let commands = dst.get(0..4).unwrap_or_default();
 
+            let line = String::from_utf8_lossy(&dst);
+            let line = line.trim_end();
             match commands {
-                b"want" => want.insert(String::from_utf8(dst[5..45].to_vec()).unwrap()),
-                b"have" => have.insert(String::from_utf8(dst[5..45].to_vec()).unwrap()),
-                b"done" => break,
+                b"want" => request.want.insert(line_id("want", line)?),
+                b"have" => {
+                    request.have.push(line_id("have", line)?);
+                    continue;
+                }
+                b"shal" => request.shallow.insert(line_id("shallow", line)?),
+                b"deep" => {
+                    if let Some(since) = line.strip_prefix("deepen-since ") {
+                        request.deepen = since.parse().ok().map(Deepen::Since);
+                    } else if let Some(name) = line.strip_prefix("deepen-not ") {
+                        match &mut request.deepen {
+                            Some(Deepen::Not(names)) => names.push(name.to_owned()),
+                            _ => request.deepen = Some(Deepen::Not(vec![name.to_owned()])),
+                        }
+                    } else if let Some(depth) = line.strip_prefix("deepen ") {
+                        // `deepen 0` is rejected later
+                        request.deepen = Some(Deepen::Depth(depth.parse().unwrap_or(0)));
+                    }
+                    continue;
+                }
+                b"filt" => {
+                    request.filter = line.strip_prefix("filter ").map(str::to_owned);
+                    continue;
+                }
+                b"done" => {
+                    request.done = true;
+                    break;
+                }
                 other => {
                     tracing::error!(
                         "unsupported command: {:?}",
@@ -122,10 +212,50 @@ impl PackProtocol {
                 }
             };
             if !read_first_line {
-                self.parse_capabilities(&String::from_utf8(dst[46..].to_vec()).unwrap());
+                self.parse_capabilities(line.get(46..).unwrap_or_default());
                 read_first_line = true;
             }
         }
+        Ok(request)
+    }
+
+    /// The ACKs or the NAK and the pack of the request, the pack is empty if only the shallow
+    /// update is sent, or an `ERR` is.
+    pub async fn git_upload_pack(
+        &mut self,
+        upload_request: &mut Bytes,
+    ) -> Result<(Vec<u8>, BytesMut)> {
+        let (content, buf) = self.negotiate_upload_pack(upload_request).await?;
+        let send_pack_data = match content {
+            Some(content) => self.build_pack(content).await?,
+            None => Vec::new(),
+        };
+        Ok((send_pack_data, buf))
+    }
+
+    /// The ACKs or the NAK of the request, and the content of the pack sent after them, none
+    /// if only the shallow update is sent, or an `ERR` is.
+    pub async fn negotiate_upload_pack(
+        &mut self,
+        upload_request: &mut Bytes,
+    ) -> Result<(Option<PackContent>, BytesMut)> {
+        let mut buf = BytesMut::new();
+        let request = match self.parse_upload_request(upload_request) {
+            Ok(request) => request,
+            Err(msg) => {
+                add_pkt_line_string(&mut buf, format!("ERR {}\n", msg));
+                return Ok((None, buf));
+            }
+        };
+        let UploadRequest {
+            want,
+            have,
+            shallow,
+            deepen,
+            filter,
+            done,
+            ..
+        } = &request;
 
         tracing::info!(
             "want commands: {:?}\n have commans: {:?}\n caps:{:?}",
@@ -134,49 +264,82 @@ impl PackProtocol {
             self.capabilities
         );
 
-        let mut send_pack_data = vec![];
-        let mut buf = BytesMut::new();
+        let mut content = None;
 
-        if have.is_empty() {
-            send_pack_data = self.get_full_pack_data(&self.path).await.unwrap();
-            add_pkt_line_string(&mut buf, String::from("NAK\n"));
-        } else {
-            if self.capabilities.contains(&Capability::MultiAckDetailed) {
-                // multi_ack_detailed mode, the server will differentiate the ACKs where it is signaling that
-                // it is ready to send data with ACK obj-id ready lines,
-                // and signals the identified common commits with ACK obj-id common lines
-                for hash in &have {
-                    if self.storage.get_commit_by_hash(hash).await.is_ok() {
-                        add_pkt_line_string(&mut buf, format!("ACK {} common\n", hash));
-                    }
-                    // no need to send NAK in this mode if missing commit?
-                }
-
-                send_pack_data = self
-                    .get_incremental_pack_data(&self.path, &want, &have)
-                    .await
-                    .unwrap();
+        match filter
+            .as_ref()
+            .map(|spec| spec.parse::<Filter>())
+            .transpose()
+        {
+            Ok(filter) => self.filter = filter,
+            Err(msg) => {
+                add_pkt_line_string(&mut buf, format!("ERR {}\n", msg));
+                return Ok((content, buf));
+            }
+        }
 
-                for hash in &want {
-                    if self.storage.get_commit_by_hash(hash).await.is_ok() {
-                        add_pkt_line_string(&mut buf, format!("ACK {} common\n", hash));
+        if let Some(deepen) = deepen {
+            if self.shallow.is_none() {
+                match self.shallow_info(want, shallow, deepen).await {
+                    Ok(info) => {
+                        info.write(&mut buf);
+                        self.shallow = Some(info);
                     }
-                    if self.capabilities.contains(&Capability::NoDone) {
-                        // If multi_ack_detailed and no-done are both present, then the sender is free to immediately send a pack
-                        // following its first "ACK obj-id ready" message.
-                        add_pkt_line_string(&mut buf, format!("ACK {} ready\n", hash));
+                    Err(msg) => {
+                        add_pkt_line_string(&mut buf, format!("ERR {}\n", msg));
+                        return Ok((content, buf));
                     }
                 }
-            } else {
-                tracing::error!("capability unsupported");
             }
-            // TODO: hard-code here
-            add_pkt_line_string(
-                &mut buf,
-                format!("ACK {} \n", "27dd8d4cf39f3868c6eee38b601bc9e9939304f5"),
-            );
+            // a request of the stateless connection before the `done`, answered without the
+            // pack, and no `have` is common of the shallow clone
+            if !done {
+                if !have.is_empty() {
+                    add_pkt_line_string(&mut buf, String::from("NAK\n"));
+                }
+                return Ok((content, buf));
+            }
         }
-        Ok((send_pack_data, buf))
+
+        if let Some(info) = &self.shallow {
+            content = Some(PackContent::Commits(info.commits.clone()));
+            add_pkt_line_string(&mut buf, String::from("NAK\n"));
+            return Ok((content, buf));
+        }
+
+        let graph = self.commit_graph().await;
+        let mode = AckMode::of(&self.capabilities);
+        let no_done = self.capabilities.contains(&Capability::NoDone);
+        let negotiation = negotiate(&graph, &request, mode, no_done, &mut buf);
+        if !negotiation.done {
+            // the next round of the `have`s is waited for
+            return Ok((content, buf));
+        }
+        content = if let Some(models) = self.wanted_objects(want).await? {
+            Some(PackContent::Objects(models))
+        } else if negotiation.common.is_empty() {
+            Some(PackContent::Full)
+        } else {
+            Some(PackContent::Incremental {
+                want: request.want,
+                have: negotiation.common,
+            })
+        };
+        Ok((content, buf))
+    }
+
+    /// Build the pack of the content, of which the progress is reported.
+    pub async fn build_pack(&self, content: PackContent) -> Result<Vec<u8>> {
+        let data = match content {
+            PackContent::Commits(commits) => self.get_commits_pack_data(commits).await?,
+            PackContent::Objects(models) => self.get_objects_pack_data(models).await,
+            PackContent::Full => self.get_full_pack_data(&self.path).await?,
+            PackContent::Incremental { want, have } => {
+                self.get_incremental_pack_data(&self.path, &want, &have)
+                    .await?
+            }
+        };
+        Ok(data)
     }
 
     pub async fn git_receive_pack(&mut self, mut body_bytes: Bytes) -> Result<Bytes> {
@@ -184,49 +347,220 @@ impl PackProtocol {
             tracing::debug!("bytes from client: {:?}", body_bytes);
         }
 
-        if body_bytes.starts_with(&[b'P', b'A', b'C', b'K']) {
-            let mut command_list = self.command_list.clone();
-            let command = command_list.last_mut().unwrap();
-            let mr_id = command
-                .unpack(self.storage.clone(), &mut body_bytes)
+        // the commands first, and then the pack of the rest
+        if self.command_list.is_empty() {
+            self.parse_commands(&mut body_bytes);
+            return Ok(body_bytes);
+        }
+
+        let mut command_list = self.command_list.clone();
+        let has_pack = body_bytes.starts_with(b"PACK");
+        let checked = if has_pack {
+            // nothing of a pack which is broken, or not connected to the saved objects, is saved
+            let tips: Vec<Hash> = command_list
+                .iter()
+                .filter(|c| c.command_type != CommandType::Delete)
+                .map(|c| Hash::new_from_str(&c.new_id))
+                .collect();
+            match connectivity::missing_objects(
+                Cursor::new(&body_bytes[..]),
+                self.storage.clone(),
+                &tips,
+            )
+            .await
+            {
+                Ok(missing) if !missing.is_empty() => {
+                    tracing::warn!(
+                        "rejected the pack of {:?}, missing {} objects, e.g. {}",
+                        self.path,
+                        missing.len(),
+                        missing[0]
+                    );
+                    Ok(Some("missing necessary objects".to_owned()))
+                }
+                Ok(_) => self.check_lfs_threshold(&body_bytes).await,
+                Err(err) => Err(err),
+            }
+        } else if command_list
+            .iter()
+            .any(|c| c.command_type != CommandType::Delete)
+        {
+            Ok(Some("missing necessary objects".to_owned()))
+        } else {
+            // only the deletes, which need no pack
+            Ok(None)
+        };
+        // the output of the hooks relayed to the client
+        let mut progress = Vec::new();
+        let checked = match (checked, &self.hooks) {
+            (Ok(None), Some(hooks)) => Ok(hooks
+                .pre_receive(&self.path, &command_list, &mut progress)
                 .await
-                .unwrap();
-            let path = &self.path;
-            let parse_obj_result =
-                conversion::save_node_from_mr(self.storage.clone(), mr_id, path).await;
-            if parse_obj_result.is_ok() {
-                command.save_to_db(self.storage.clone(), path).await;
-                self.handle_directory().await.unwrap();
-            } else {
-                tracing::error!("{}", parse_obj_result.err().unwrap());
-                command.failed(String::from("db operation failed"));
+                .err()),
+            (checked, _) => checked,
+        };
+        let pack = has_pack.then_some(&mut body_bytes);
+        let unpack_status = match checked {
+            Ok(None) if self.dry_run() => {
+                tracing::info!("checked the push of {:?} by a dry run", self.path);
+                "unpack ok\n".to_owned()
+            }
+            Ok(None) => match self.save_push(&mut command_list, pack).await {
+                Ok(()) => {
+                    self.post_receive(&command_list, &mut progress).await;
+                    "unpack ok\n".to_owned()
+                }
+                Err(err) => {
+                    tracing::error!("{}", err);
+                    fail_updates(&mut command_list, "unpacker error");
+                    format!("unpack {}\n", err)
+                }
+            },
+            Ok(Some(reason)) => {
+                fail_updates(&mut command_list, &reason);
+                "unpack ok\n".to_owned()
             }
-            // After receiving the pack data from the sender, the receiver sends a report
-            let mut report_status = BytesMut::new();
-            // TODO: replace this hard code "unpack ok\n"
-            add_pkt_line_string(&mut report_status, "unpack ok\n".to_owned());
-            for c in command_list {
-                add_pkt_line_string(&mut report_status, c.get_status());
+            Err(err) => {
+                tracing::error!("{}", err);
+                fail_updates(&mut command_list, "unpacker error");
+                format!("unpack {}\n", err)
             }
-            report_status.put(&PKT_LINE_END_MARKER[..]);
+        };
+        // After receiving the pack data from the sender, the receiver sends a report
+        let mut report_status = BytesMut::new();
+        add_pkt_line_string(&mut report_status, unpack_status);
+        for c in &command_list {
+            add_pkt_line_string(&mut report_status, c.get_status());
+        }
+        report_status.put(&PKT_LINE_END_MARKER[..]);
+        // the statuses are kept, e.g. for the events of the refs updated
+        self.command_list = command_list;
 
-            let length = report_status.len();
-            let mut buf = self.build_side_band_format(report_status, length);
-            buf.put(&PKT_LINE_END_MARKER[..]);
-            Ok(buf.into())
-        } else {
-            let (bytes_take, mut pkt_line) = read_pkt_line(&mut body_bytes);
+        let length = report_status.len();
+        let mut buf = self.build_progress(&progress);
+        buf.put(self.build_side_band_format(report_status, length));
+        buf.put(&PKT_LINE_END_MARKER[..]);
+        Ok(buf.into())
+    }
+
+    /// Read the commands of a push until the flush, the capabilities are behind a NUL of the
+    /// first one.
+    fn parse_commands(&mut self, body_bytes: &mut Bytes) {
+        loop {
+            let (bytes_take, mut pkt_line) = read_pkt_line(body_bytes);
             if bytes_take == 0 && pkt_line.is_empty() {
-                return Ok(body_bytes);
+                break;
             }
             let command = self.parse_ref_update(&mut pkt_line);
-            self.parse_capabilities(&String::from_utf8(pkt_line.to_vec()).unwrap());
-            tracing::debug!("init comamnd: {:?}, caps:{:?}", command, self.capabilities);
+            if self.command_list.is_empty() {
+                self.parse_capabilities(&String::from_utf8(pkt_line.to_vec()).unwrap());
+            }
+            tracing::debug!("init command: {:?}, caps:{:?}", command, self.capabilities);
             self.command_list.push(command);
-            Ok(body_bytes.split_off(4))
         }
     }
 
+    /// The reason to reject the pack if a blob of it is larger than the `enforce_lfs_threshold`
+    /// but not an LFS pointer, which names the blobs to track by LFS.
+    async fn check_lfs_threshold(&self, pack: &[u8]) -> Result<Option<String>> {
+        let Some(threshold) = self.enforce_lfs_threshold else {
+            return Ok(None);
+        };
+        let oversized =
+            lfs_threshold::oversized_blobs(Cursor::new(pack), self.storage.clone(), threshold)
+                .await?;
+        let Some(first) = oversized.first() else {
+            return Ok(None);
+        };
+        tracing::warn!(
+            "rejected the pack of {:?}, {} blobs larger than the LFS threshold, e.g. {}",
+            self.path,
+            oversized.len(),
+            first
+        );
+        let more = match oversized.len() {
+            1 => String::new(),
+            n => format!(" and {} more", n - 1),
+        };
+        Ok(Some(format!(
+            "{}{} exceeds the LFS threshold of {} bytes, track it by `git lfs track` and rewrite the commits by `git lfs migrate import`",
+            first, more, threshold
+        )))
+    }
+
+    /// Run the `post-receive` hook of the updates saved.
+    async fn post_receive(&self, command_list: &[RefCommand], progress: &mut Vec<u8>) {
+        let Some(hooks) = &self.hooks else {
+            return;
+        };
+        let saved: Vec<RefCommand> = command_list.iter().filter(|c| c.is_ok()).cloned().collect();
+        if !saved.is_empty() {
+            hooks.post_receive(&self.path, &saved, progress).await;
+        }
+    }
+
+    /// Save the objects of the checked pack, if it's not a push of the deletes only, and then
+    /// update the refs of all the commands.
+    async fn save_push(
+        &mut self,
+        command_list: &mut [RefCommand],
+        pack: Option<&mut Bytes>,
+    ) -> Result<()> {
+        if let Some(body_bytes) = pack {
+            // the objects of all the commands are in the pack
+            let command = command_list.last_mut().unwrap();
+            let mr_id = command
+                .unpack(self.storage.clone(), body_bytes, self.decode)
+                .await?;
+            let path = &self.path;
+            let parse_obj_result =
+                conversion::save_node_from_mr(self.storage.clone(), mr_id, path).await;
+            match parse_obj_result {
+                Ok(commits) => {
+                    self.handle_directory().await?;
+                    self.commit_graphs
+                        .add_commits(path, commits.into_iter().map(|c| (c.id, c.parent_tree_ids)));
+                }
+                Err(err) => {
+                    tracing::error!("{}", err);
+                    fail_updates(command_list, "db operation failed");
+                    return Ok(());
+                }
+            }
+        }
+        self.update_refs(command_list).await
+    }
+
+    /// Update the refs of the commands in a transaction of the storage. The command of a ref
+    /// which is not the old id any more, e.g. updated by another push meanwhile, is failed,
+    /// and so are all the others of an `atomic` push.
+    async fn update_refs(&self, command_list: &mut [RefCommand]) -> Result<()> {
+        let atomic = self.capabilities.contains(&Capability::Atomic);
+        // the refs of a namespace are saved in it
+        let updates: Vec<RefUpdate> = command_list
+            .iter()
+            .map(|c| RefUpdate {
+                ref_name: self.full_ref_name(&c.ref_name),
+                ..c.to_ref_update()
+            })
+            .collect();
+        let current = self
+            .storage
+            .apply_ref_updates(self.path.to_str().unwrap(), &updates, atomic)
+            .await
+            .map_err(|e| anyhow!("failed to update the refs: {}", e))?;
+        let stale = current.contains(&false);
+        for (command, current) in command_list.iter_mut().zip(current) {
+            if !current {
+                tracing::warn!("rejected the stale update of {:?}", command);
+                command.failed(String::from("stale ref"));
+            } else if atomic && stale {
+                command.failed(String::from("atomic push failed"));
+            }
+        }
+        Ok(())
+    }
+
     /// # Builds the packet data in the sideband format if the SideBand/64k capability is enabled.
     ///
     /// If the `SideBand` or `SideBand64k` capability is present in the `capabilities` vector,
@@ -259,6 +593,29 @@ impl PackProtocol {
         from_bytes
     }
 
+    /// The max data in a packet of the side-band of the client, none without a side-band.
+    pub fn max_side_band_data(&self) -> Option<usize> {
+        if self.capabilities.contains(&Capability::SideBand64k) {
+            Some(MAX_SIDE_BAND_64K_DATA)
+        } else if self.capabilities.contains(&Capability::SideBand) {
+            Some(MAX_SIDE_BAND_DATA)
+        } else {
+            None
+        }
+    }
+
+    /// The progress packets of the output in the side-band 2, which is dropped if there is no
+    /// side-band, like the messages of git.
+    pub fn build_progress(&self, output: &[u8]) -> BytesMut {
+        let Some(max_data) = self.max_side_band_data() else {
+            if !output.is_empty() {
+                tracing::debug!("dropped the progress without the side-band: {:?}", output);
+            }
+            return BytesMut::new();
+        };
+        side_band_packets(SideBind::ProgressInfo, output, max_data)
+    }
+
     pub fn build_smart_reply(&self, ref_list: &Vec<String>, service: String) -> BytesMut {
         let mut pkt_line_stream = BytesMut::new();
         if self.protocol == Protocol::Http {
@@ -277,8 +634,11 @@ impl PackProtocol {
         let cap_vec: Vec<_> = cap_str.split(' ').collect();
         for cap in cap_vec {
             let res = cap.trim().parse::<Capability>();
+            // the capabilities of a request sent again, e.g. of each round, are not duplicated
             if let Ok(cap) = res {
-                self.capabilities.push(cap);
+                if !self.capabilities.contains(&cap) {
+                    self.capabilities.push(cap);
+                }
             }
         }
     }
@@ -293,6 +653,19 @@ impl PackProtocol {
     }
 }
 
+/// The id of a `want`, a `have` or a `shallow` line, which may be followed by the capabilities.
+fn line_id(command: &str, line: &str) -> Result<String, String> {
+    let args = line
+        .strip_prefix(command)
+        .and_then(|args| args.strip_prefix(' '))
+        .unwrap_or_default();
+    let id = args.split(' ').next().unwrap_or_default();
+    if !is_object_id(id) {
+        return Err(format!("invalid {}: {}", command, args));
+    }
+    Ok(id.to_owned())
+}
+
 fn read_until_white_space(bytes: &mut Bytes) -> String {
     let mut buf = Vec::new();
     while bytes.has_remaining() {
@@ -305,7 +678,31 @@ fn read_until_white_space(bytes: &mut Bytes) -> String {
     String::from_utf8(buf).unwrap()
 }
 
-fn add_pkt_line_string(pkt_line_stream: &mut BytesMut, buf_str: String) {
+/// Fail all the commands, none of the refs is updated.
+fn fail_updates(command_list: &mut [RefCommand], msg: &str) {
+    for command in command_list.iter_mut() {
+        command.failed(msg.to_owned());
+    }
+}
+
+/// The packets of the data in the band, each of which has at most `max_data` of it.
+pub(crate) fn side_band_packets(band: SideBind, data: &[u8], max_data: usize) -> BytesMut {
+    let mut buf = BytesMut::new();
+    for chunk in data.chunks(max_data) {
+        buf.put(Bytes::from(format!("{:04x}", chunk.len() + 5)));
+        buf.put_u8(band.value());
+        buf.put(chunk);
+    }
+    buf
+}
+
+/// Whether the name of the ref is of any of the prefixes, e.g. `refs/heads/`, or there are no
+/// prefixes, like the `ref-prefix` of the protocol v2.
+pub fn matches_prefix(prefixes: &[String], name: &str) -> bool {
+    prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix))
+}
+
+pub(crate) fn add_pkt_line_string(pkt_line_stream: &mut BytesMut, buf_str: String) {
     let buf_str_length = buf_str.len() + 4;
     pkt_line_stream.put(Bytes::from(format!("{buf_str_length:04x}")));
     pkt_line_stream.put(buf_str.as_bytes());
@@ -361,9 +758,25 @@ pub fn read_pkt_line(bytes: &mut Bytes) -> (usize, Bytes) {
 
 #[cfg(test)]
 pub mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
     use bytes::{Bytes, BytesMut};
+    use common::utils::ZERO_ID;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::{ObjectStorage, RefUpdate};
 
-    use crate::protocol::{Capability, CommandType, PackProtocol, RefCommand};
+    use crate::hash::Hash;
+    use crate::internal::object::blob::Blob;
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use crate::internal::object::ObjectT;
+    use crate::internal::pack::encode::{write_pack, DEFAULT_DEPTH, DEFAULT_WINDOW};
+    use crate::protocol::hooks::ReceiveHook;
+    use crate::protocol::{
+        Capability, CommandType, PackProtocol, Protocol, RefCommand, ServiceType,
+    };
 
     use super::{add_pkt_line_string, read_pkt_line, read_until_white_space};
 
@@ -441,4 +854,366 @@ pub mod test {
             vec![Capability::ReportStatusv2, Capability::SideBand64k]
         );
     }
+
+    /// The object of which the id is the hash of the data.
+    fn object<T: ObjectT>(data: Vec<u8>) -> T {
+        let mut object = T::new_from_data(data);
+        let raw = object.get_raw();
+        let mut hasher = Hash::hasher();
+        hasher.update(object.get_type().to_bytes());
+        hasher.update(format!(" {}\0", raw.len()).as_bytes());
+        hasher.update(&raw);
+        object.set_hash(hasher.finalize());
+        object
+    }
+
+    /// The objects of a commit of a `README.md`, the commit first.
+    fn commit_objects() -> Vec<Arc<dyn ObjectT>> {
+        let readme: Blob = object(b"# demo\n".to_vec());
+        let tree: Tree =
+            object(TreeItem::new(TreeItemMode::Blob, readme.id, "README.md".to_owned()).to_data());
+        let commit: Commit = object(
+            format!(
+                "tree {}\nauthor A <a@example.com> 1700000000 +0000\ncommitter A <a@example.com> 1700000000 +0000\n\ninit\n",
+                tree.id.to_plain_str()
+            )
+            .into_bytes(),
+        );
+        vec![Arc::new(commit), Arc::new(tree), Arc::new(readme)]
+    }
+
+    /// Push the objects to `refs/heads/main` with the capabilities, returns the report.
+    async fn push(
+        storage: Arc<MemoryStorage>,
+        objects: Vec<Arc<dyn ObjectT>>,
+        new_id: Hash,
+        capabilities: &str,
+        hooks: Option<Arc<dyn ReceiveHook>>,
+    ) -> String {
+        let commands = [(ZERO_ID.to_owned(), new_id.to_plain_str(), "refs/heads/main")];
+        push_commands(storage, objects, &commands, capabilities, hooks).await
+    }
+
+    /// Push the objects by the commands of the old id, the new id and the ref, and the
+    /// capabilities of the first one, returns the report.
+    async fn push_commands(
+        storage: Arc<MemoryStorage>,
+        objects: Vec<Arc<dyn ObjectT>>,
+        commands: &[(String, String, &str)],
+        capabilities: &str,
+        hooks: Option<Arc<dyn ReceiveHook>>,
+    ) -> String {
+        let mut protocol =
+            PackProtocol::new(PathBuf::from("/projects/demo"), storage, Protocol::Http);
+        protocol.hooks = hooks;
+        let mut body = BytesMut::new();
+        for (i, (old_id, new_id, ref_name)) in commands.iter().enumerate() {
+            let line = match i {
+                0 => format!("{} {} {}\0{}\n", old_id, new_id, ref_name, capabilities),
+                _ => format!("{} {} {}\n", old_id, new_id, ref_name),
+            };
+            add_pkt_line_string(&mut body, line);
+        }
+        body.extend_from_slice(b"0000");
+        body.extend(write_pack(objects, DEFAULT_WINDOW, DEFAULT_DEPTH).unwrap());
+        let pack_data = protocol.git_receive_pack(body.freeze()).await.unwrap();
+        let report = protocol.git_receive_pack(pack_data).await.unwrap();
+        String::from_utf8_lossy(&report).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_dry_run() {
+        let storage = Arc::new(MemoryStorage::new());
+        let objects = commit_objects();
+        let commit = objects[0].get_hash();
+
+        let report = push(
+            storage.clone(),
+            objects.clone(),
+            commit,
+            "report-status dry-run",
+            None,
+        )
+        .await;
+        assert!(report.contains("unpack ok\n"), "{}", report);
+        assert!(report.contains("ok refs/heads/main"), "{}", report);
+        assert!(storage
+            .get_ref_object_id("/projects/demo")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .get_obj_data_by_id(&commit.to_plain_str())
+            .await
+            .unwrap()
+            .is_none());
+
+        // the commit without the tree is rejected, and nothing is saved either
+        let report = push(
+            storage.clone(),
+            objects[..1].to_vec(),
+            commit,
+            "report-status dry-run",
+            None,
+        )
+        .await;
+        assert!(
+            report.contains("ng refs/heads/main missing necessary objects"),
+            "{}",
+            report
+        );
+        assert!(storage
+            .get_ref_object_id("/projects/demo")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // which is the report of the push
+        let report = push(storage.clone(), objects, commit, "report-status", None).await;
+        assert!(report.contains("ok refs/heads/main"), "{}", report);
+        let refs = storage.get_ref_object_id("/projects/demo").await.unwrap();
+        assert_eq!(refs[0].ref_git_id, commit.to_plain_str());
+    }
+
+    /// The policy which accepts the pushes or not, and records the refs received.
+    struct Policy {
+        accept: bool,
+        received: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ReceiveHook for Policy {
+        async fn pre_receive(
+            &self,
+            _repo_path: &Path,
+            updates: &[RefCommand],
+            output: &mut Vec<u8>,
+        ) -> Result<(), String> {
+            output.extend(format!("checking {}\n", updates[0].ref_name).as_bytes());
+            match self.accept {
+                true => Ok(()),
+                false => Err(String::from("the commits must be signed")),
+            }
+        }
+
+        async fn post_receive(&self, _repo_path: &Path, updates: &[RefCommand], _: &mut Vec<u8>) {
+            let mut received = self.received.lock().unwrap();
+            received.extend(updates.iter().map(|c| c.ref_name.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_hooks() {
+        let storage = Arc::new(MemoryStorage::new());
+        let objects = commit_objects();
+        let commit = objects[0].get_hash();
+        let capabilities = "report-status side-band-64k";
+
+        let policy = Arc::new(Policy {
+            accept: false,
+            received: Mutex::default(),
+        });
+        let report = push(
+            storage.clone(),
+            objects.clone(),
+            commit,
+            capabilities,
+            Some(policy.clone()),
+        )
+        .await;
+        // the output is the progress before the report
+        assert!(
+            report.starts_with("001e\x02checking refs/heads/main\n"),
+            "{:?}",
+            report
+        );
+        assert!(
+            report.contains("ng refs/heads/main the commits must be signed"),
+            "{}",
+            report
+        );
+        assert!(storage
+            .get_ref_object_id("/projects/demo")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(policy.received.lock().unwrap().is_empty());
+
+        let policy = Arc::new(Policy {
+            accept: true,
+            received: Mutex::default(),
+        });
+        let report = push(
+            storage.clone(),
+            objects,
+            commit,
+            capabilities,
+            Some(policy.clone()),
+        )
+        .await;
+        assert!(report.contains("ok refs/heads/main"), "{}", report);
+        assert_eq!(
+            storage
+                .get_ref_object_id("/projects/demo")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(*policy.received.lock().unwrap(), ["refs/heads/main"]);
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_atomic() {
+        let storage = Arc::new(MemoryStorage::new());
+        let objects = commit_objects();
+        let commit = objects[0].get_hash().to_plain_str();
+        let report = push(
+            storage.clone(),
+            objects.clone(),
+            objects[0].get_hash(),
+            "",
+            None,
+        )
+        .await;
+        assert!(report.contains("ok refs/heads/main"), "{}", report);
+
+        // the client read main before it was created
+        let commands = [
+            (ZERO_ID.to_owned(), commit.clone(), "refs/heads/dev"),
+            (ZERO_ID.to_owned(), commit.clone(), "refs/heads/main"),
+            (ZERO_ID.to_owned(), commit.clone(), "refs/tags/v1"),
+        ];
+        let capabilities = "report-status atomic";
+        let report = push_commands(
+            storage.clone(),
+            objects.clone(),
+            &commands,
+            capabilities,
+            None,
+        )
+        .await;
+        assert!(
+            report.contains("ng refs/heads/main stale ref"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("ng refs/heads/dev atomic push failed"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("ng refs/tags/v1 atomic push failed"),
+            "{}",
+            report
+        );
+        let refs = storage.get_ref_object_id("/projects/demo").await.unwrap();
+        assert_eq!(refs.len(), 1);
+
+        // the valid ones are updated without the atomic
+        let report =
+            push_commands(storage.clone(), objects, &commands, "report-status", None).await;
+        assert!(report.contains("ok refs/heads/dev"), "{}", report);
+        assert!(
+            report.contains("ng refs/heads/main stale ref"),
+            "{}",
+            report
+        );
+        assert!(report.contains("ok refs/tags/v1"), "{}", report);
+        let refs = storage.get_ref_object_id("/projects/demo").await.unwrap();
+        assert_eq!(refs.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_deletes() {
+        let storage = Arc::new(MemoryStorage::new());
+        let objects = commit_objects();
+        let commit = objects[0].get_hash().to_plain_str();
+        let commands = [
+            (ZERO_ID.to_owned(), commit.clone(), "refs/heads/main"),
+            (ZERO_ID.to_owned(), commit.clone(), "refs/heads/dev"),
+        ];
+        push_commands(storage.clone(), objects, &commands, "report-status", None).await;
+
+        // a push of the deletes has no pack
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/demo"),
+            storage.clone(),
+            Protocol::Http,
+        );
+        let line = format!(
+            "{} {} refs/heads/dev\0report-status delete-refs\n",
+            commit, ZERO_ID
+        );
+        let mut body = BytesMut::new();
+        add_pkt_line_string(&mut body, line);
+        body.extend_from_slice(b"0000");
+        let rest = protocol.git_receive_pack(body.freeze()).await.unwrap();
+        let report = protocol.git_receive_pack(rest).await.unwrap();
+        let report = String::from_utf8_lossy(&report);
+        assert!(report.contains("ok refs/heads/dev"), "{}", report);
+        let refs = storage.get_ref_object_id("/projects/demo").await.unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].ref_name, "refs/heads/main");
+    }
+
+    #[tokio::test]
+    async fn test_info_refs_prefixes() {
+        let storage = Arc::new(MemoryStorage::new());
+        let commit = ZERO_ID.replace('0', "1");
+        let update = |name: String| RefUpdate {
+            ref_name: name,
+            old_id: None,
+            new_id: Some(commit.clone()),
+        };
+        let mut updates: Vec<RefUpdate> = (0..1000)
+            .map(|i| update(format!("refs/pull/{}/head", i)))
+            .collect();
+        for name in ["refs/heads/main", "refs/heads/dev", "refs/tags/v1"] {
+            updates.push(update(name.to_owned()));
+        }
+        storage
+            .apply_ref_updates("/projects/demo", &updates, true)
+            .await
+            .unwrap();
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/demo"),
+            storage.clone(),
+            Protocol::Ssh,
+        );
+        let advertised = |protocol: &PackProtocol| {
+            let mut protocol = protocol.clone();
+            async move {
+                let mut refs = protocol
+                    .git_info_refs(ServiceType::UploadPack)
+                    .await
+                    .freeze();
+                let mut names = Vec::new();
+                while let (1.., line) = read_pkt_line(&mut refs) {
+                    let line = String::from_utf8(line.to_vec()).unwrap();
+                    let (_, name) = line.trim_end().split_once(' ').unwrap();
+                    names.push(name.split('\0').next().unwrap().to_owned());
+                }
+                names.sort();
+                names
+            }
+        };
+        assert_eq!(advertised(&protocol).await.len(), 1004);
+
+        // only the branches, which carry the capabilities
+        protocol.ref_prefixes = vec!["refs/heads/".to_owned()];
+        assert_eq!(
+            advertised(&protocol).await,
+            ["refs/heads/dev", "refs/heads/main"]
+        );
+        protocol.ref_prefixes = vec!["refs/heads/main".to_owned(), "refs/tags/".to_owned()];
+        assert_eq!(
+            advertised(&protocol).await,
+            ["refs/heads/main", "refs/tags/v1"]
+        );
+        // nothing of the prefix, but the capabilities
+        protocol.ref_prefixes = vec!["refs/notes/".to_owned()];
+        assert_eq!(advertised(&protocol).await, ["capabilities^{}"]);
+    }
 }
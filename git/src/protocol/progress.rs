@@ -0,0 +1,281 @@
+//! The progress of the upload-pack, so the clients, and the proxies between them and the server,
+//! see the activity while the pack of a large repo is built, which may take minutes.
+//!
+//! The pack is built by a task, while the messages of it, e.g. `Enumerating objects`, are sent
+//! in the side-band 2, which git prints with the `remote:` prefix. An empty packet of the
+//! side-band 1 is sent as the keepalive if nothing else is sent for the interval, like the
+//! `uploadpack.keepAlive` of git. The pack is then sent with the progress of `Writing objects`.
+//! Neither is sent without a side-band, nor the progress if the client sends `no-progress`.
+
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::sync::mpsc::{self, Sender, UnboundedReceiver};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use super::pack::{side_band_packets, PackContent, PKT_LINE_END_MARKER};
+use super::{Capability, PackProtocol, SideBind};
+
+/// The interval of the keepalives by default, the same as git.
+pub const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// The packet of the side-band 1 without any data.
+const KEEPALIVE: &[u8] = b"0005\x01";
+
+/// How the pack is sent in the side-band.
+#[derive(Debug, Clone, Copy)]
+struct SideBand {
+    max_data: usize,
+    keepalive: Option<Duration>,
+    progress: bool,
+}
+
+/// Build the pack of the content and send it to the client by the sender, with the progress
+/// and the keepalives of the side-band. It returns once the pack is sent, or the receiver is
+/// dropped, e.g. of the client disconnected.
+pub async fn send_pack(mut protocol: PackProtocol, content: PackContent, sender: Sender<Bytes>) {
+    let Some(max_data) = protocol.max_side_band_data() else {
+        // only the pack, nothing else can be sent before it
+        match protocol.build_pack(content).await {
+            Ok(data) => {
+                let _ = sender.send(Bytes::from(data)).await;
+            }
+            Err(err) => tracing::error!("failed to build the pack of {:?}: {}", protocol.path, err),
+        }
+        return;
+    };
+    let side_band = SideBand {
+        max_data,
+        keepalive: protocol.keepalive,
+        progress: !protocol.capabilities.contains(&Capability::NoProgress),
+    };
+    let (progress, messages) = mpsc::unbounded_channel();
+    if side_band.progress {
+        protocol.progress = Some(progress);
+    }
+    let path = protocol.path.clone();
+    let build = tokio::spawn(async move { protocol.build_pack(content).await });
+    match relay(build, messages, side_band, &sender).await {
+        Some(Ok(data)) => {
+            send_pack_data(&data, side_band, &sender).await;
+        }
+        Some(Err(err)) => {
+            tracing::error!("failed to build the pack of {:?}: {}", path, err);
+            let message = format!("failed to build the pack: {}\n", err);
+            let _ = sender
+                .send(side_band_packets(SideBind::Error, message.as_bytes(), max_data).freeze())
+                .await;
+        }
+        None => tracing::info!("the client of {:?} is gone before the pack is built", path),
+    }
+}
+
+/// Send the progress and the keepalives until the pack is built, none if the receiver is
+/// dropped meanwhile.
+async fn relay(
+    mut build: JoinHandle<anyhow::Result<Vec<u8>>>,
+    mut messages: UnboundedReceiver<String>,
+    side_band: SideBand,
+    sender: &Sender<Bytes>,
+) -> Option<anyhow::Result<Vec<u8>>> {
+    let result = loop {
+        let deadline = side_band
+            .keepalive
+            .map(|keepalive| Instant::now() + keepalive);
+        let packet = tokio::select! {
+            result = &mut build => break result,
+            Some(message) = messages.recv() => {
+                side_band_packets(SideBind::ProgressInfo, message.as_bytes(), side_band.max_data)
+            }
+            _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                BytesMut::from(KEEPALIVE)
+            }
+        };
+        if sender.send(packet.freeze()).await.is_err() {
+            build.abort();
+            return None;
+        }
+    };
+    // the messages reported just before the pack is done
+    while let Ok(message) = messages.try_recv() {
+        let packet = side_band_packets(
+            SideBind::ProgressInfo,
+            message.as_bytes(),
+            side_band.max_data,
+        );
+        sender.send(packet.freeze()).await.ok()?;
+    }
+    Some(result.unwrap_or_else(|err| Err(err.into())))
+}
+
+/// Send the pack in the side-band 1, and the progress of it in the side-band 2.
+async fn send_pack_data(data: &[u8], side_band: SideBand, sender: &Sender<Bytes>) {
+    // the number of the objects in the header
+    let objects = data
+        .get(8..12)
+        .map(|n| u32::from_be_bytes(n.try_into().unwrap()))
+        .unwrap_or_default();
+    let mut reported = 0;
+    let mut sent = 0;
+    for chunk in data.chunks(side_band.max_data) {
+        let mut packet = side_band_packets(SideBind::PackfileData, chunk, side_band.max_data);
+        sent += chunk.len();
+        let percent = sent * 100 / data.len();
+        if side_band.progress && percent > reported && percent < 100 {
+            reported = percent;
+            let message = format!("Writing objects: {}%\r", percent);
+            packet.put(side_band_packets(
+                SideBind::ProgressInfo,
+                message.as_bytes(),
+                side_band.max_data,
+            ));
+        }
+        if sender.send(packet.freeze()).await.is_err() {
+            return;
+        }
+    }
+    let mut packet = BytesMut::new();
+    if side_band.progress {
+        let message = format!("Writing objects: 100% ({}/{}), done.\n", objects, objects);
+        packet.put(side_band_packets(
+            SideBind::ProgressInfo,
+            message.as_bytes(),
+            side_band.max_data,
+        ));
+    }
+    packet.put(&PKT_LINE_END_MARKER[..]);
+    let _ = sender.send(packet.freeze()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::ObjectStorage;
+    use entity::git_obj;
+    use sea_orm::Set;
+    use tokio::sync::mpsc;
+
+    use super::{relay, send_pack, SideBand};
+    use crate::hash::Hash;
+    use crate::protocol::pack::{read_pkt_line, PackContent};
+    use crate::protocol::{Capability, PackProtocol, Protocol};
+
+    /// The packets of the side-band stream, the band and the data of each.
+    async fn read_packets(mut receiver: mpsc::Receiver<Bytes>) -> Vec<(u8, Bytes)> {
+        let mut stream = Vec::new();
+        while let Some(bytes) = receiver.recv().await {
+            stream.extend_from_slice(&bytes);
+        }
+        let mut stream = Bytes::from(stream);
+        let mut packets = Vec::new();
+        while !stream.is_empty() {
+            let (length, mut line) = read_pkt_line(&mut stream);
+            if length == 0 {
+                break;
+            }
+            let band = line.split_to(1)[0];
+            packets.push((band, line));
+        }
+        packets
+    }
+
+    #[tokio::test]
+    async fn test_send_pack_with_progress() {
+        let storage = Arc::new(MemoryStorage::new());
+        // not compressed much, so the pack is sent in many packets
+        let data: Vec<u8> = (0..20000u32)
+            .flat_map(|i| (i * 7919).to_le_bytes())
+            .collect();
+        let git_id = Hash::new(&[format!("blob {}\0", data.len()).as_bytes(), &data].concat());
+        storage
+            .put_object(git_obj::ActiveModel {
+                id: Set(1),
+                git_id: Set(git_id.to_plain_str()),
+                object_type: Set("blob".to_owned()),
+                data: Set(data),
+                compression: Set(None),
+            })
+            .await
+            .unwrap();
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/demo"),
+            storage.clone(),
+            Protocol::Http,
+        );
+        protocol.capabilities = vec![Capability::SideBand];
+        let models = storage
+            .get_obj_data_by_ids(vec![git_id.to_plain_str()])
+            .await
+            .unwrap();
+
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(send_pack(
+            protocol.clone(),
+            PackContent::Objects(models.clone()),
+            sender,
+        ));
+        let packets = read_packets(receiver).await;
+        let progress = |packet: &(u8, Bytes)| (packet.0 == 2).then(|| packet.1.clone());
+        let messages: Vec<Bytes> = packets.iter().filter_map(progress).collect();
+        assert_eq!(messages[0], "Enumerating objects: 1, done.\n");
+        assert_eq!(messages[1], "Compressing objects: 100% (1/1), done.\n");
+        assert_eq!(
+            messages.last().unwrap(),
+            "Writing objects: 100% (1/1), done.\n"
+        );
+        // the progress of the writing is between the data of the pack
+        let first_data = packets.iter().position(|p| p.0 == 1).unwrap();
+        let last_data = packets.iter().rposition(|p| p.0 == 1).unwrap();
+        assert!(packets[first_data..last_data]
+            .iter()
+            .any(|p| p.0 == 2 && p.1.starts_with(b"Writing objects: ")));
+        let pack: Vec<u8> = packets
+            .iter()
+            .filter(|p| p.0 == 1)
+            .flat_map(|p| p.1.to_vec())
+            .collect();
+        assert_eq!(
+            pack,
+            protocol
+                .build_pack(PackContent::Objects(models.clone()))
+                .await
+                .unwrap()
+        );
+
+        // only the pack of `no-progress`
+        protocol.capabilities.push(Capability::NoProgress);
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(send_pack(protocol, PackContent::Objects(models), sender));
+        assert!(read_packets(receiver).await.iter().all(|p| p.0 == 1));
+    }
+
+    #[tokio::test]
+    async fn test_keepalive() {
+        let (_progress, messages) = mpsc::unbounded_channel();
+        let build = tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(b"PACK".to_vec())
+        });
+        let side_band = SideBand {
+            max_data: 995,
+            keepalive: Some(Duration::from_millis(20)),
+            progress: true,
+        };
+        let (sender, mut receiver) = mpsc::channel(100);
+        let result = relay(build, messages, side_band, &sender).await;
+        assert_eq!(result.unwrap().unwrap(), b"PACK");
+        drop(sender);
+        let mut keepalives = 0;
+        while let Some(packet) = receiver.recv().await {
+            assert_eq!(packet, "0005\x01");
+            keepalives += 1;
+        }
+        assert!(keepalives >= 3, "{}", keepalives);
+    }
+}
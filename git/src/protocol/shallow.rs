@@ -0,0 +1,381 @@
+//! The shallow clone of the upload-pack, e.g. `git clone --depth 1`, which asks for the history
+//! cut by the `deepen`, the `deepen-since` or the `deepen-not` of the request.
+//!
+//! The commits are walked from the `want`s until the limit, and the ones of which the parents
+//! are not sent are told to the client by the `shallow` lines before the `ACK`s or the `NAK`;
+//! the shallow commits of the client which get their parents now are told by the `unshallow`.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bytes::BytesMut;
+
+use super::pack::add_pkt_line_string;
+use super::PackProtocol;
+use crate::hash::Hash;
+use crate::internal::object::commit::Commit;
+use crate::internal::object::tag::Tag;
+use crate::internal::object::ObjectT;
+
+/// How deep the history of a shallow clone is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Deepen {
+    /// The commits at most this many from the `want`s, 1 for the `want`s only.
+    Depth(usize),
+    /// The commits committed at or after the timestamp.
+    Since(usize),
+    /// The commits not reachable from any of the refs.
+    Not(Vec<String>),
+}
+
+/// The shallow update of a request, and the commits to send of it.
+#[derive(Debug, Clone, Default)]
+pub struct ShallowInfo {
+    /// The commits sent without the parents.
+    pub shallow: Vec<Hash>,
+    /// The shallow commits of the client, of which the parents are sent now.
+    pub unshallow: Vec<Hash>,
+    pub commits: Vec<Commit>,
+}
+
+impl ShallowInfo {
+    /// The `shallow` and the `unshallow` lines, and the flush after them.
+    pub fn write(&self, buf: &mut BytesMut) {
+        for id in &self.shallow {
+            add_pkt_line_string(buf, format!("shallow {}\n", id));
+        }
+        for id in &self.unshallow {
+            add_pkt_line_string(buf, format!("unshallow {}\n", id));
+        }
+        buf.extend_from_slice(super::pack::PKT_LINE_END_MARKER);
+    }
+}
+
+/// Walk the `commits` from the `want`s breadth first until the `deepen` limit. The `want`s are
+/// always sent, and a commit is shallow if any of the parents of it is not sent.
+pub fn walk_shallow(
+    commits: &HashMap<Hash, Commit>,
+    want: &[Hash],
+    client_shallow: &HashSet<Hash>,
+    deepen: &Deepen,
+    excluded: &HashSet<Hash>,
+) -> ShallowInfo {
+    let mut info = ShallowInfo::default();
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<(Hash, usize)> = want.iter().map(|id| (*id, 1)).collect();
+    while let Some((id, depth)) = queue.pop_front() {
+        if !seen.insert(id) {
+            continue;
+        }
+        let Some(commit) = commits.get(&id) else {
+            continue;
+        };
+        let sent = |parent: &Hash| match deepen {
+            Deepen::Depth(limit) => depth < *limit,
+            Deepen::Since(since) => commits
+                .get(parent)
+                .is_some_and(|p| p.committer.timestamp >= *since),
+            Deepen::Not(_) => !excluded.contains(parent),
+        };
+        if commit.parent_tree_ids.iter().all(sent) {
+            if client_shallow.contains(&id) && !commit.parent_tree_ids.is_empty() {
+                info.unshallow.push(id);
+            }
+            queue.extend(commit.parent_tree_ids.iter().map(|p| (*p, depth + 1)));
+        } else {
+            info.shallow.push(id);
+        }
+        info.commits.push(commit.clone());
+    }
+    info
+}
+
+/// The commits reachable from the `tips`.
+fn reachable(commits: &HashMap<Hash, Commit>, tips: Vec<Hash>) -> HashSet<Hash> {
+    let mut seen = HashSet::new();
+    let mut stack = tips;
+    while let Some(id) = stack.pop() {
+        if seen.insert(id) {
+            if let Some(commit) = commits.get(&id) {
+                stack.extend(commit.parent_tree_ids.iter().copied());
+            }
+        }
+    }
+    seen
+}
+
+impl PackProtocol {
+    /// Walk the commits of the repository for the shallow clone. The error is the message of
+    /// the `ERR` line to the client, e.g. of a `deepen-not` which is not a ref.
+    pub async fn shallow_info(
+        &self,
+        want: &HashSet<String>,
+        client_shallow: &HashSet<String>,
+        deepen: &Deepen,
+    ) -> Result<ShallowInfo, String> {
+        if *deepen == Deepen::Depth(0) {
+            return Err("invalid deepen: 0".to_owned());
+        }
+        let repo = self.path.to_str().unwrap();
+        let commits: HashMap<Hash, Commit> = self
+            .storage
+            .get_all_commits_by_path(repo)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|model| {
+                let commit: Commit = model.into();
+                (commit.id, commit)
+            })
+            .collect();
+
+        let mut excluded = HashSet::new();
+        if let Deepen::Not(names) = deepen {
+            let refs = self.visible_refs().await.map_err(|e| e.to_string())?;
+            let mut tips = Vec::new();
+            for name in names {
+                // as `git rev-parse`, e.g. `v1.0` of `refs/tags/v1.0`
+                let r = refs
+                    .iter()
+                    .find(|r| {
+                        [
+                            name.clone(),
+                            format!("refs/{}", name),
+                            format!("refs/tags/{}", name),
+                            format!("refs/heads/{}", name),
+                        ]
+                        .contains(&r.ref_name)
+                    })
+                    .ok_or_else(|| format!("git upload-pack: deepen-not is not a ref: {}", name))?;
+                tips.push(self.peel(Hash::new_from_str(&r.ref_git_id), &commits).await);
+            }
+            excluded = reachable(&commits, tips);
+        }
+
+        let want: Vec<Hash> = want.iter().map(|id| Hash::new_from_str(id)).collect();
+        let client_shallow = client_shallow
+            .iter()
+            .map(|id| Hash::new_from_str(id))
+            .collect();
+        let info = walk_shallow(&commits, &want, &client_shallow, deepen, &excluded);
+        if info.commits.is_empty() {
+            return Err("no commits selected for shallow requests".to_owned());
+        }
+        Ok(info)
+    }
+
+    /// The commit of an annotated tag.
+    async fn peel(&self, mut id: Hash, commits: &HashMap<Hash, Commit>) -> Hash {
+        while !commits.contains_key(&id) {
+            match self.storage.get_obj_data_by_id(&id.to_plain_str()).await {
+                Ok(Some(model)) if model.object_type == "tag" => {
+                    id = Tag::new_from_data(model.data).object_hash;
+                }
+                _ => break,
+            }
+        }
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use bytes::{BufMut, BytesMut};
+
+    use super::{walk_shallow, Deepen};
+    use crate::hash::Hash;
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::tree::Tree;
+    use crate::internal::object::ObjectT;
+    use crate::internal::pack::stream::PackStream;
+    use crate::internal::pack::tests::MemoryStorage;
+    use crate::protocol::tests::{pkt_line, storage, HEAD};
+    use crate::protocol::{PackProtocol, Protocol};
+
+    /// The objects of the tree and the subtrees, and the tree itself.
+    fn tree_objects(storage: &MemoryStorage, id: &str, objects: &mut HashSet<String>) {
+        objects.insert(id.to_owned());
+        let data = storage.objects.lock().unwrap()[id].1.clone();
+        for item in Tree::new_from_data(data).tree_items {
+            let id = item.id.to_plain_str();
+            let object_type = storage.objects.lock().unwrap()[&id].0.clone();
+            if object_type == "tree" {
+                tree_objects(storage, &id, objects);
+            } else {
+                objects.insert(id);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_pack_depth_1() {
+        let storage = storage().await;
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/d50df"),
+            storage.clone(),
+            Protocol::Http,
+        );
+
+        // the stateless request of the shallow update only
+        let mut request = BytesMut::new();
+        pkt_line(
+            &mut request,
+            &format!("want {} multi_ack_detailed side-band-64k shallow\n", HEAD),
+        );
+        pkt_line(&mut request, "deepen 1\n");
+        request.put(&b"0000"[..]);
+        let (pack, buf) = protocol
+            .git_upload_pack(&mut request.clone().freeze())
+            .await
+            .unwrap();
+        assert!(pack.is_empty());
+        assert_eq!(&buf[..], format!("0035shallow {}\n0000", HEAD).as_bytes());
+
+        // and the pack after the `done`
+        pkt_line(&mut request, "done\n");
+        let mut protocol = PackProtocol::new(
+            PathBuf::from("/projects/d50df"),
+            storage.clone(),
+            Protocol::Http,
+        );
+        let (pack, buf) = protocol
+            .git_upload_pack(&mut request.freeze())
+            .await
+            .unwrap();
+        assert_eq!(
+            &buf[..],
+            format!("0035shallow {}\n00000008NAK\n", HEAD).as_bytes()
+        );
+
+        let mut stream = PackStream::new(Cursor::new(pack)).unwrap();
+        let mut sent = HashSet::new();
+        while let Some(object) = stream.next_object().await.unwrap() {
+            sent.insert(object.hash.to_plain_str());
+        }
+        let data = storage.objects.lock().unwrap()[HEAD].1.clone();
+        let tip = Commit::new_from_data(data);
+        let mut expected = HashSet::from([HEAD.to_owned()]);
+        tree_objects(&storage, &tip.tree_id.to_plain_str(), &mut expected);
+        assert_eq!(sent, expected);
+        // the same as `git clone --depth 1`
+        assert_eq!(sent.len(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_shallow() {
+        let storage = Arc::new(MemoryStorage::default());
+        let mut protocol =
+            PackProtocol::new(PathBuf::from("/projects/none"), storage, Protocol::Http);
+        let mut request = BytesMut::new();
+        pkt_line(&mut request, &format!("want {} shallow\n", HEAD));
+        pkt_line(&mut request, "shallow xyz\n");
+        pkt_line(&mut request, "deepen 1\n");
+        request.put(&b"0000"[..]);
+        let (pack, buf) = protocol
+            .git_upload_pack(&mut request.freeze())
+            .await
+            .unwrap();
+        assert!(pack.is_empty());
+        assert_eq!(&buf[4..], b"ERR invalid shallow: xyz\n");
+    }
+
+    #[tokio::test]
+    async fn test_walk_shallow() {
+        let storage = storage().await;
+        let commits: HashMap<Hash, Commit> = storage
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, (object_type, _))| object_type == "commit")
+            .map(|(id, (_, data))| {
+                let mut commit = Commit::new_from_data(data.clone());
+                commit.set_hash(Hash::new_from_str(id));
+                (commit.id, commit)
+            })
+            .collect();
+        let ids = |ids: &[&str]| -> HashSet<Hash> {
+            ids.iter().map(|id| Hash::new_from_str(id)).collect()
+        };
+        let want = [Hash::new_from_str(HEAD)];
+        let none = HashSet::new();
+
+        // as the `.git/shallow` of `git clone --depth 3`
+        let info = walk_shallow(&commits, &want, &none, &Deepen::Depth(3), &none);
+        assert_eq!(info.commits.len(), 6);
+        assert_eq!(
+            info.shallow.into_iter().collect::<HashSet<_>>(),
+            ids(&[
+                "50d998d3a26f8c0023226a45437083284af88355",
+                "550e99557a4fe3f72775c8311b6badd66c6e2a3c",
+                "c674ba3750e8ef10a4cea65f9acc305277077f34",
+            ])
+        );
+
+        // `git fetch --depth 3` of the clone of the depth 2
+        let client_shallow = ids(&[
+            "c8b60e643248e4b12c8e3c13eae58e914383ab0c",
+            "d6999f8f5be31b7f5e2b13f7a72c84c7c687ca71",
+        ]);
+        let info = walk_shallow(&commits, &want, &client_shallow, &Deepen::Depth(3), &none);
+        assert_eq!(
+            info.unshallow.into_iter().collect::<HashSet<_>>(),
+            client_shallow
+        );
+
+        // `--shallow-since`
+        let info = walk_shallow(&commits, &want, &none, &Deepen::Since(1678523228), &none);
+        assert_eq!(info.commits.len(), 4);
+        assert_eq!(
+            info.shallow.into_iter().collect::<HashSet<_>>(),
+            ids(&[
+                "50d998d3a26f8c0023226a45437083284af88355",
+                "c8b60e643248e4b12c8e3c13eae58e914383ab0c",
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deepen_not() {
+        let storage = storage().await;
+        storage.add_ref(
+            "/projects/d50df",
+            "refs/tags/old",
+            "a5c709196faf51169ea911b94bb8144f8fc5f297",
+        );
+        let protocol = PackProtocol::new(
+            PathBuf::from("/projects/d50df"),
+            storage.clone(),
+            Protocol::Http,
+        );
+        let want = HashSet::from([HEAD.to_owned()]);
+        let none = HashSet::new();
+
+        // as `git clone --shallow-exclude old`
+        let info = protocol
+            .shallow_info(&want, &none, &Deepen::Not(vec!["old".to_owned()]))
+            .await
+            .unwrap();
+        assert_eq!(info.commits.len(), 29);
+        assert_eq!(
+            info.shallow,
+            vec![Hash::new_from_str(
+                "d42272899ecd16e735e0e2cea47bc390c4c91b61"
+            )]
+        );
+
+        let err = protocol
+            .shallow_info(&want, &none, &Deepen::Not(vec!["missing".to_owned()]))
+            .await
+            .unwrap_err();
+        assert!(err.contains("missing"));
+        assert!(protocol
+            .shallow_info(&want, &none, &Deepen::Depth(0))
+            .await
+            .is_err());
+    }
+}
@@ -5,26 +5,104 @@
 //!
 
 use async_trait::async_trait;
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 use russh::server::{self, Auth, Msg, Session};
 use russh::{Channel, ChannelId};
 
 use database::driver::ObjectStorage;
 use russh_keys::key;
 use std::collections::HashMap;
-
-use std::path::PathBuf;
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncReadExt, BufReader};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use common::utils::ZERO_ID;
 
+use crate::internal::commit_graph::CommitGraphCache;
+use crate::internal::pack::encode::{DEFAULT_DEPTH, DEFAULT_WINDOW};
+use crate::internal::pack::preload::DecodeOptions;
+use crate::metrics;
 use crate::protocol::ServiceType;
 
-use super::pack::{self};
+use super::hooks::ReceiveHook;
+use super::namespace;
+use super::pack::PackContent;
+use super::progress::{self, DEFAULT_KEEPALIVE};
+use super::v2::{self, Request};
 use super::{PackProtocol, Protocol};
 
 type ClientMap = HashMap<(usize, ChannelId), Channel<Msg>>;
 
+/// The public keys of the users allowed to connect.
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    /// The user of the key, `None` if the key is not registered.
+    async fn find_user(&self, public_key: &key::PublicKey) -> Option<String>;
+}
+
+/// The keys in the `authorized_keys` format, of which the comment is the user name, e.g.
+/// `ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAA... alice`. The options before the key are ignored.
+#[derive(Default)]
+pub struct AuthorizedKeys {
+    keys: Vec<(key::PublicKey, String)>,
+}
+
+impl AuthorizedKeys {
+    pub fn load(path: &Path) -> io::Result<AuthorizedKeys> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let mut authorized_keys = AuthorizedKeys::default();
+        for (no, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}:{}: expect `[options] type key user`",
+                        path.display(),
+                        no + 1
+                    ),
+                )
+            };
+            let mut fields = line
+                .split_whitespace()
+                .skip_while(|field| !Self::is_key_type(field))
+                .skip(1);
+            let (Some(encoded), Some(user)) = (fields.next(), fields.next()) else {
+                return Err(invalid());
+            };
+            let public_key = russh_keys::parse_public_key_base64(encoded).map_err(|_| invalid())?;
+            authorized_keys.add_key(public_key, user);
+        }
+        Ok(authorized_keys)
+    }
+
+    fn is_key_type(field: &str) -> bool {
+        field.starts_with("ssh-") || field.starts_with("ecdsa-") || field.starts_with("sk-")
+    }
+
+    pub fn add_key(&mut self, public_key: key::PublicKey, user: &str) {
+        self.keys.push((public_key, user.to_owned()));
+    }
+}
+
+#[async_trait]
+impl KeyStore for AuthorizedKeys {
+    async fn find_user(&self, public_key: &key::PublicKey) -> Option<String> {
+        self.keys
+            .iter()
+            .find(|(key, _)| key == public_key)
+            .map(|(_, user)| user.to_owned())
+    }
+}
+
 #[derive(Clone)]
 pub struct SshServer {
     pub client_pubkey: Arc<russh_keys::key::PublicKey>,
@@ -33,12 +111,43 @@ pub struct SshServer {
     pub storage: Arc<dyn ObjectStorage>,
     // TODO: consider is it a good choice to bind data here, find a better solution to bind data with ssh client
     pub pack_protocol: Option<PackProtocol>,
+    /// Only the users of the keys are allowed if it's set, otherwise everyone is.
+    pub key_store: Option<Arc<dyn KeyStore>>,
+    /// The authenticated user of the client.
+    pub user: Option<String>,
+    /// Set once the client is authenticated, e.g. to end the handshake timeout of the
+    /// connection, which is a new one of each client.
+    pub authenticated: Arc<AtomicBool>,
+    /// The delta compression of the packs to fetch, see [`PackProtocol::window`].
+    pub window: usize,
+    pub depth: usize,
+    /// The commit-graphs shared by the clients, see [`PackProtocol::commit_graphs`].
+    pub commit_graphs: Arc<CommitGraphCache>,
+    /// See [`PackProtocol::enforce_lfs_threshold`].
+    pub enforce_lfs_threshold: Option<u64>,
+    /// See [`PackProtocol::hooks`].
+    pub hooks: Option<Arc<dyn ReceiveHook>>,
+    /// See [`PackProtocol::keepalive`].
+    pub keepalive: Option<Duration>,
+    /// See [`PackProtocol::decode`].
+    pub decode: DecodeOptions,
+    /// The data received of the request which is not complete yet.
+    pending: BytesMut,
+    /// The flushes of the upload-pack negotiation answered.
+    answered_flushes: usize,
+    /// The bytes of the answer of the upload-pack negotiation sent.
+    answered: usize,
+    /// The `GIT_PROTOCOL` of the client, e.g. `version=2` of the protocol v2.
+    git_protocol: Option<String>,
+    /// Whether the pack is being sent, which closes the channel once it's sent.
+    sending_pack: bool,
 }
 
 impl server::Server for SshServer {
     type Handler = Self;
     fn new_client(&mut self, _: Option<std::net::SocketAddr>) -> Self {
-        let s = self.clone();
+        let mut s = self.clone();
+        s.authenticated = Arc::default();
         self.id += 1;
         s
     }
@@ -79,67 +188,193 @@ impl server::Handler for SshServer {
     ) -> Result<(Self, Session), Self::Error> {
         let data = String::from_utf8_lossy(data).trim().to_owned();
         tracing::info!("exec: {:?},{}", channel, data);
-        let res = self.handle_git_command(&data).await;
-        session.data(channel, res.into());
+        let command = match data.split_whitespace().next() {
+            Some(command @ ("git-upload-pack" | "git-receive-pack")) => command.to_owned(),
+            _ => "other".to_owned(),
+        };
+        metrics::metrics()
+            .ssh_requests
+            .get_or_create(&metrics::CommandLabels { command })
+            .inc();
+        // the namespace of the refs is of the path, e.g. `/projects/demo.git/namespaces/a`
+        let command = parse_git_command(&data).and_then(|(service_type, path)| {
+            let (repo, namespace) = namespace::split_path(path.to_str()?)?;
+            Some((
+                service_type,
+                PathBuf::from(repo),
+                namespace.map(str::to_owned),
+            ))
+        });
+        match command {
+            Some((service_type, path, namespace)) => {
+                let res = self.handle_git_command(service_type, path, namespace).await;
+                session.data(channel, res.into());
+            }
+            None => {
+                let message = format!("fatal: unsupported command: {}\n", data);
+                session.extended_data(channel, 1, message.into_bytes().into());
+                session.exit_status_request(channel, 128);
+                session.close(channel);
+            }
+        }
+        Ok((self, session))
+    }
+
+    /// The `GIT_PROTOCOL` is sent before the git command, which git asks the ssh for by the
+    /// `SendEnv`.
+    async fn env_request(
+        mut self,
+        channel: ChannelId,
+        variable_name: &str,
+        variable_value: &str,
+        session: Session,
+    ) -> Result<(Self, Session), Self::Error> {
+        tracing::debug!("env: {:?},{}={}", channel, variable_name, variable_value);
+        if variable_name == "GIT_PROTOCOL" {
+            self.git_protocol = Some(variable_value.to_owned());
+        }
         Ok((self, session))
     }
 
     async fn auth_publickey(
-        self,
+        mut self,
         user: &str,
         public_key: &key::PublicKey,
     ) -> Result<(Self, Auth), Self::Error> {
-        tracing::info!("auth_publickey: {} / {:?}", user, public_key);
-        Ok((self, server::Auth::Accept))
+        tracing::info!("auth_publickey: {} / {}", user, public_key.fingerprint());
+        let Some(key_store) = &self.key_store else {
+            self.user = Some(user.to_owned());
+            return Ok((self, server::Auth::Accept));
+        };
+        match key_store.find_user(public_key).await {
+            Some(name) => {
+                self.user = Some(name);
+                Ok((self, server::Auth::Accept))
+            }
+            None => {
+                tracing::info!("unknown key of {}: {}", user, public_key.fingerprint());
+                Ok((
+                    self,
+                    server::Auth::Reject {
+                        proceed_with_methods: None,
+                    },
+                ))
+            }
+        }
     }
 
-    async fn auth_password(self, user: &str, password: &str) -> Result<(Self, Auth), Self::Error> {
-        tracing::info!("auth_password: {} / {}", user, password);
-        // in this example implementation, any username/password combination is accepted
+    async fn auth_password(self, user: &str, _: &str) -> Result<(Self, Auth), Self::Error> {
+        tracing::info!("auth_password: {}", user);
+        // only the public keys are checked
+        if self.key_store.is_some() {
+            return Ok((
+                self,
+                server::Auth::Reject {
+                    proceed_with_methods: None,
+                },
+            ));
+        }
         Ok((self, server::Auth::Accept))
     }
 
+    async fn auth_succeeded(self, session: Session) -> Result<(Self, Session), Self::Error> {
+        self.authenticated.store(true, Ordering::Relaxed);
+        Ok((self, session))
+    }
+
+    /// The requests are received in packets, which are buffered until the request is
+    /// complete: the `done` of the upload-pack, and the end of the pack data of the
+    /// receive-pack, i.e. the EOF, or the flush if only refs are deleted.
     async fn data(
         mut self,
         channel: ChannelId,
         data: &[u8],
         mut session: Session,
     ) -> Result<(Self, Session), Self::Error> {
-        let pack_protocol = self.pack_protocol.as_mut().unwrap();
-        let data_str = String::from_utf8_lossy(data).trim().to_owned();
-        tracing::info!(
-            "SSH: client sends data: {:?}, channel:{}",
-            data_str,
+        tracing::debug!(
+            "SSH: client sends {} bytes, channel:{}",
+            data.len(),
             channel
         );
-        match pack_protocol.service_type {
-            Some(ServiceType::UploadPack) => {
-                self.handle_upload_pack(channel, data, &mut session).await;
+        let Some(service_type) = self.pack_protocol.as_ref().and_then(|p| p.service_type) else {
+            return Err(anyhow::anyhow!("data before the git command"));
+        };
+        self.pending.extend_from_slice(data);
+        if self.pack_protocol.as_ref().is_some_and(|p| p.v2) {
+            self.handle_v2(channel, &mut session).await;
+            return Ok((self, session));
+        }
+        let lines = scan_pkt_lines(&self.pending);
+        match service_type {
+            ServiceType::UploadPack => {
+                if lines.first() == Some(&None) {
+                    // nothing wanted, e.g. `git ls-remote`
+                    self.finish(channel, &mut session);
+                } else if lines
+                    .iter()
+                    .any(|l| l.is_some_and(|l| l.starts_with(b"done")))
+                {
+                    self.handle_upload_pack(channel, &mut session).await;
+                } else {
+                    let flushes = lines.iter().filter(|l| l.is_none()).count();
+                    // the flush after the `want`s and the `deepen` waits for the shallow update
+                    let pack_protocol = self.pack_protocol.as_mut().unwrap();
+                    let deepen = lines
+                        .iter()
+                        .any(|l| l.is_some_and(|l| l.starts_with(b"deepen")));
+                    if flushes > 0 && deepen && pack_protocol.shallow.is_none() {
+                        let mut request = Bytes::copy_from_slice(&self.pending);
+                        let (_, buf) = pack_protocol.git_upload_pack(&mut request).await?;
+                        session.data(channel, buf.to_vec().into());
+                        if pack_protocol.shallow.is_none() {
+                            // the `ERR` of the request
+                            self.finish(channel, &mut session);
+                            return Ok((self, session));
+                        }
+                    }
+                    if deepen {
+                        // every flush after the `have`s waits for an answer until the `done`
+                        while self.answered_flushes + 1 < flushes {
+                            self.answered_flushes += 1;
+                            session.data(channel, b"0008NAK\n".to_vec().into());
+                        }
+                    } else if flushes > self.answered_flushes + 1 {
+                        // the ACKs of the new rounds, and the pack if it's ready of the `no-done`
+                        self.answered_flushes = flushes - 1;
+                        self.handle_upload_pack(channel, &mut session).await;
+                    }
+                }
             }
-            Some(ServiceType::ReceivePack) => {
-                self.handle_receive_pack(channel, data, &mut session).await;
+            ServiceType::ReceivePack => {
+                let only_deletes = lines.last() == Some(&None)
+                    && lines[..lines.len() - 1].iter().all(|line| {
+                        line.is_some_and(|line| line.get(41..81) == Some(ZERO_ID.as_bytes()))
+                    });
+                if only_deletes {
+                    self.handle_receive_pack(channel, &mut session).await;
+                }
             }
-            _ => panic!(),
-        };
+        }
         Ok((self, session))
     }
 
     async fn channel_eof(
-        self,
+        mut self,
         channel: ChannelId,
         mut session: Session,
     ) -> Result<(Self, Session), Self::Error> {
-        // session.close(channel);
-        // match session.flush() {
-        //     Ok(_) => {},
-        //     Err(e) => println!("Error flushing session: {:?}", e),
-        // }
-        // session.disconnect(Disconnect::ByApplication, "channel close", "en");
-        // match session.disconnect(None, "Closing session") {
-        //     Ok(_) => {},
-        //     Err(e) => println!("Error disconnecting session: {:?}", e),
-        // }
-        session.close(channel);
+        let receive_pack = self
+            .pack_protocol
+            .as_ref()
+            .is_some_and(|p| p.service_type == Some(ServiceType::ReceivePack));
+        if receive_pack && !self.pending.is_empty() {
+            // all the pack data is received
+            self.handle_receive_pack(channel, &mut session).await;
+        } else if !self.sending_pack {
+            // otherwise it's closed once the pack is sent, e.g. of the EOF the client of the
+            // protocol v2 sends after the `fetch`
+            session.close(channel);
+        }
         Ok((self, session))
     }
 
@@ -153,72 +388,510 @@ impl server::Handler for SshServer {
     // }
 }
 
+/// Parse the git command of the exec request, e.g. `git-upload-pack '/projects/repo.git'`.
+/// The path is relative to the root of the server, so it's rejected if there is any `..`.
+pub fn parse_git_command(command: &str) -> Option<(ServiceType, PathBuf)> {
+    let (service, path) = command.trim().split_once(' ')?;
+    let service_type = ServiceType::from_str(service).ok()?;
+    let path = path.trim();
+    let path = path
+        .strip_prefix('\'')
+        .and_then(|p| p.strip_suffix('\''))
+        .unwrap_or(path);
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+
+    let mut repo = PathBuf::from("/");
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => repo.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    if repo == Path::new("/") {
+        return None;
+    }
+    Some((service_type, repo))
+}
+
+/// The pkt-lines which are received completely, `None` is a flush.
+fn scan_pkt_lines(data: &[u8]) -> Vec<Option<&[u8]>> {
+    let mut lines = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 4 {
+        let Some(length) = std::str::from_utf8(&rest[..4])
+            .ok()
+            .and_then(|length| usize::from_str_radix(length, 16).ok())
+        else {
+            break;
+        };
+        if length == 0 {
+            lines.push(None);
+            rest = &rest[4..];
+            continue;
+        }
+        if length < 4 || rest.len() < length {
+            break;
+        }
+        lines.push(Some(&rest[4..length]));
+        rest = &rest[length..];
+    }
+    lines
+}
+
 impl SshServer {
-    async fn handle_git_command(&mut self, command: &str) -> String {
-        let command: Vec<_> = command.split(' ').collect();
-        // command:
-        // Push: git-receive-pack '/root/repotest/src.git'
-        // Pull: git-upload-pack '/root/repotest/src.git'
-        let path = command[1];
-        let end = path.len() - ".git'".len();
-        let mut pack_protocol = PackProtocol::new(
-            PathBuf::from(&path[1..end]),
-            self.storage.clone(),
-            Protocol::Ssh,
+    pub fn new(
+        client_pubkey: Arc<key::PublicKey>,
+        storage: Arc<dyn ObjectStorage>,
+        key_store: Option<Arc<dyn KeyStore>>,
+    ) -> SshServer {
+        SshServer {
+            client_pubkey,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            id: 0,
+            storage,
+            pack_protocol: None,
+            key_store,
+            user: None,
+            authenticated: Arc::default(),
+            window: DEFAULT_WINDOW,
+            depth: DEFAULT_DEPTH,
+            commit_graphs: Arc::default(),
+            enforce_lfs_threshold: None,
+            hooks: None,
+            keepalive: Some(DEFAULT_KEEPALIVE),
+            decode: DecodeOptions::default(),
+            pending: BytesMut::new(),
+            answered_flushes: 0,
+            answered: 0,
+            git_protocol: None,
+            sending_pack: false,
+        }
+    }
+
+    async fn handle_git_command(
+        &mut self,
+        service_type: ServiceType,
+        path: PathBuf,
+        namespace: Option<String>,
+    ) -> Vec<u8> {
+        tracing::info!(
+            "{:?} of {:?} in {:?} by {:?}",
+            service_type,
+            path,
+            namespace,
+            self.user
         );
-        let service_type = ServiceType::from_str(command[0]).unwrap();
+        let mut pack_protocol = PackProtocol::new(path, self.storage.clone(), Protocol::Ssh);
         pack_protocol.service_type = Some(service_type);
+        pack_protocol.use_v2(self.git_protocol.as_deref());
+        pack_protocol.namespace = namespace;
+        pack_protocol.window = self.window;
+        pack_protocol.depth = self.depth;
+        pack_protocol.commit_graphs = self.commit_graphs.clone();
+        pack_protocol.enforce_lfs_threshold = self.enforce_lfs_threshold;
+        pack_protocol.hooks = self.hooks.clone();
+        pack_protocol.keepalive = self.keepalive;
+        pack_protocol.decode = self.decode;
         let res = pack_protocol.git_info_refs(service_type).await;
 
         self.pack_protocol = Some(pack_protocol);
-        String::from_utf8(res.to_vec()).unwrap()
+        self.pending.clear();
+        self.answered_flushes = 0;
+        self.answered = 0;
+        self.sending_pack = false;
+        res.to_vec()
     }
 
-    async fn handle_upload_pack(&mut self, channel: ChannelId, data: &[u8], session: &mut Session) {
+    /// Exit the git command successfully.
+    fn finish(&mut self, channel: ChannelId, session: &mut Session) {
+        self.pending.clear();
+        session.exit_status_request(channel, 0);
+        session.eof(channel);
+        session.close(channel);
+    }
+
+    /// Answer the request received so far, which is kept until the pack is sent, as the answer
+    /// of the rounds of the `have`s sent before is the start of the one of it.
+    async fn handle_upload_pack(&mut self, channel: ChannelId, session: &mut Session) {
         let pack_protocol = self.pack_protocol.as_mut().unwrap();
+        let mut request = Bytes::copy_from_slice(&self.pending);
+        let done = scan_pkt_lines(&self.pending)
+            .iter()
+            .any(|l| l.is_some_and(|l| l.starts_with(b"done")));
 
-        let (send_pack_data, buf) = pack_protocol
-            .git_upload_pack(&mut Bytes::copy_from_slice(data))
+        let (content, mut buf) = pack_protocol
+            .negotiate_upload_pack(&mut request)
             .await
             .unwrap();
 
         tracing::info!("buf is {:?}", buf);
-        session.data(channel, String::from_utf8(buf.to_vec()).unwrap().into());
+        let buf = buf.split_off(self.answered.min(buf.len()));
+        self.answered += buf.len();
+        let err = buf.get(4..8) == Some(b"ERR ");
+        session.data(channel, buf.to_vec().into());
+        let Some(content) = content else {
+            // no pack follows an `ERR` of the request, or the `done` is waited for
+            if done || err {
+                self.finish(channel, session);
+            }
+            return;
+        };
+        self.send_pack(content, channel, session);
+    }
 
-        let mut reader = BufReader::new(send_pack_data.as_slice());
+    /// Answer the commands of the protocol v2 received completely, each of which is ended by a
+    /// flush, until the pack of a `fetch` is sent, or the flush alone by which the client is
+    /// done, e.g. after the `ls-refs` of `git ls-remote`.
+    async fn handle_v2(&mut self, channel: ChannelId, session: &mut Session) {
         loop {
-            let mut temp = BytesMut::new();
-            let length = reader.read_buf(&mut temp).await.unwrap();
-            if temp.is_empty() {
-                let mut bytes_out = BytesMut::new();
-                bytes_out.put_slice(pack::PKT_LINE_END_MARKER);
-                tracing::info!("send: ends: {:?}", bytes_out.clone().freeze());
-                session.data(channel, bytes_out.to_vec().into());
-                return;
+            let command = match v2::parse_request(&self.pending) {
+                Ok(Some((Request::Command(command), read))) => {
+                    let _ = self.pending.split_to(read);
+                    command
+                }
+                Ok(Some((Request::End, _))) => return self.finish(channel, session),
+                Ok(None) => return,
+                Err(msg) => {
+                    session.data(channel, v2::error(msg).to_vec().into());
+                    return self.finish(channel, session);
+                }
+            };
+            let pack_protocol = self.pack_protocol.as_mut().unwrap();
+            let (content, buf) = pack_protocol
+                .v2_command(&command)
+                .await
+                .unwrap_or_else(|err| (None, v2::error(err.to_string())));
+            let err = buf.get(4..8) == Some(b"ERR ");
+            session.data(channel, buf.to_vec().into());
+            if err {
+                return self.finish(channel, session);
+            }
+            if let Some(content) = content {
+                return self.send_pack(content, channel, session);
             }
-            let bytes_out = pack_protocol.build_side_band_format(temp, length);
-            tracing::info!("send: packet lentgh : {:?}", bytes_out.len());
-            session.data(channel, bytes_out.to_vec().into());
         }
     }
 
-    async fn handle_receive_pack(
-        &mut self,
-        channel: ChannelId,
-        data: &[u8],
-        session: &mut Session,
-    ) {
+    /// The pack is built and sent by a task, so the session keeps sending the progress and the
+    /// keepalives meanwhile, which are queued until the window of the client allows.
+    fn send_pack(&mut self, content: PackContent, channel: ChannelId, session: &mut Session) {
         let pack_protocol = self.pack_protocol.as_mut().unwrap();
+        let (sender, mut receiver) = mpsc::channel(16);
+        tokio::spawn(progress::send_pack(pack_protocol.clone(), content, sender));
+        let path = pack_protocol.path.clone();
+        let handle = session.handle();
+        tokio::spawn(async move {
+            while let Some(bytes) = receiver.recv().await {
+                if handle.data(channel, bytes.to_vec().into()).await.is_err() {
+                    tracing::info!("the channel of {:?} is closed", path);
+                    return;
+                }
+            }
+            tracing::info!("send: pack of {:?} finished", path);
+            let _ = handle.exit_status_request(channel, 0).await;
+            let _ = handle.eof(channel).await;
+            let _ = handle.close(channel).await;
+        });
+        self.pending.clear();
+        self.sending_pack = true;
+    }
 
-        let buf = pack_protocol
-            .git_receive_pack(Bytes::from(data.to_vec()))
-            .await
-            .unwrap();
+    async fn handle_receive_pack(&mut self, channel: ChannelId, session: &mut Session) {
+        let pack_protocol = self.pack_protocol.as_mut().unwrap();
+        let request = self.pending.split().freeze();
+
+        // the ref commands first, then the pack data
+        let pack_data = pack_protocol.git_receive_pack(request).await.unwrap();
+        let buf = pack_protocol.git_receive_pack(pack_data).await.unwrap();
         if !buf.is_empty() {
             tracing::info!("report status: {:?}", buf);
             session.data(channel, buf.to_vec().into());
-        } else {
-            session.close(channel);
         }
+        self.finish(channel, session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use std::path::Path;
+    use std::process::Command;
+
+    use async_trait::async_trait;
+    use database::driver::ObjectStorage;
+    use database::DataSource;
+    use russh::{client, server};
+    use russh_keys::key::{self, KeyPair};
+    use russh_keys::PublicKeyBase64;
+    use tokio::net::TcpListener;
+
+    use super::{parse_git_command, scan_pkt_lines, AuthorizedKeys, KeyStore, SshServer};
+    use crate::protocol::ServiceType;
+
+    struct Client;
+
+    #[async_trait]
+    impl client::Handler for Client {
+        type Error = russh::Error;
+
+        async fn check_server_key(self, _: &key::PublicKey) -> Result<(Self, bool), Self::Error> {
+            Ok((self, true))
+        }
+    }
+
+    async fn start_server(key_store: Arc<dyn KeyStore>) -> SocketAddr {
+        let storage = database::disconnected_storage(&DataSource::Postgres);
+        start_server_with(storage, key_store).await
+    }
+
+    async fn start_server_with(
+        storage: Arc<dyn ObjectStorage>,
+        key_store: Arc<dyn KeyStore>,
+    ) -> SocketAddr {
+        let host_key = KeyPair::generate_ed25519().unwrap();
+        let mut server = SshServer::new(
+            Arc::new(host_key.clone_public_key().unwrap()),
+            storage,
+            Some(key_store),
+        );
+        let mut config = server::Config {
+            auth_rejection_time: Duration::from_millis(10),
+            ..Default::default()
+        };
+        config.keys.push(host_key);
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, peer)) = listener.accept().await {
+                let handler = server::Server::new_client(&mut server, Some(peer));
+                tokio::spawn(server::run_stream(config.clone(), socket, handler));
+            }
+        });
+        addr
+    }
+
+    async fn authenticate(addr: SocketAddr, key: KeyPair) -> bool {
+        let config = Arc::new(client::Config::default());
+        let mut handle = client::connect(config, addr, Client).await.unwrap();
+        handle
+            .authenticate_publickey("git", Arc::new(key))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_auth_publickey() {
+        let allowed = KeyPair::generate_ed25519().unwrap();
+        let unknown = KeyPair::generate_ed25519().unwrap();
+        let mut keys = AuthorizedKeys::default();
+        keys.add_key(allowed.clone_public_key().unwrap(), "alice");
+        let addr = start_server(Arc::new(keys)).await;
+
+        assert!(authenticate(addr, allowed).await);
+        assert!(!authenticate(addr, unknown).await);
+    }
+
+    #[tokio::test]
+    async fn test_load_authorized_keys() {
+        let alice = KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap();
+        let bob = KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap();
+        let path = std::env::temp_dir().join("mega_authorized_keys");
+        let content = format!(
+            "# users\nssh-ed25519 {} alice\n\nno-pty ssh-ed25519 {} bob\n",
+            alice.public_key_base64(),
+            bob.public_key_base64()
+        );
+        std::fs::write(&path, content).unwrap();
+        let keys = AuthorizedKeys::load(&path).unwrap();
+        assert_eq!(keys.find_user(&alice).await.as_deref(), Some("alice"));
+        assert_eq!(keys.find_user(&bob).await.as_deref(), Some("bob"));
+
+        let other = KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap();
+        assert_eq!(keys.find_user(&other).await, None);
+
+        // no user
+        std::fs::write(&path, format!("ssh-ed25519 {}", alice.public_key_base64())).unwrap();
+        let err = AuthorizedKeys::load(&path).err().unwrap();
+        assert!(err
+            .to_string()
+            .ends_with(":1: expect `[options] type key user`"));
+    }
+
+    #[test]
+    fn test_parse_git_command() {
+        let (service_type, path) =
+            parse_git_command("git-upload-pack '/projects/repo.git'").unwrap();
+        assert_eq!(service_type, ServiceType::UploadPack);
+        assert_eq!(path, Path::new("/projects/repo"));
+        let (service_type, path) = parse_git_command("git-receive-pack 'projects/repo'").unwrap();
+        assert_eq!(service_type, ServiceType::ReceivePack);
+        assert_eq!(path, Path::new("/projects/repo"));
+
+        assert!(parse_git_command("git-upload-pack '/projects/../../etc'").is_none());
+        assert!(parse_git_command("git-upload-pack '../repo.git'").is_none());
+        assert!(parse_git_command("git-upload-pack '/'").is_none());
+        assert!(parse_git_command("git-upload-archive '/projects/repo.git'").is_none());
+        assert!(parse_git_command("ls /").is_none());
+    }
+
+    #[test]
+    fn test_scan_pkt_lines() {
+        let lines = scan_pkt_lines(b"0009done\n0000000ahave");
+        assert_eq!(lines, vec![Some(&b"done\n"[..]), None]);
+        assert!(scan_pkt_lines(b"00").is_empty());
+        // the pack data follows the flush
+        let lines = scan_pkt_lines(b"0000PACK");
+        assert_eq!(lines, vec![None]);
+    }
+
+    fn run(dir: &Path, program: &str, args: &[&str], ssh_command: &str) {
+        let status = Command::new(program)
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_SSH_COMMAND", ssh_command)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{} {:?} failed", program, args);
+    }
+
+    /// Push a temp repository to the server then clone it by the git and ssh commands, of
+    /// which the storage is `MEGA_DB_POSTGRESQL_URL`.
+    #[tokio::test]
+    #[ignore = "need_postgres_environment"]
+    async fn test_clone_over_ssh() {
+        let url = std::env::var("MEGA_DB_POSTGRESQL_URL").unwrap();
+        let storage = database::init_by_url(&url).await;
+        let dir = std::env::temp_dir().join("mega_ssh_clone");
+        let _ = std::fs::remove_dir_all(&dir);
+        let src = dir.join("src");
+        std::fs::create_dir_all(src.join("docs")).unwrap();
+
+        let key = dir.join("id_ed25519");
+        let key_path = key.to_str().unwrap();
+        run(
+            &dir,
+            "ssh-keygen",
+            &[
+                "-q", "-t", "ed25519", "-N", "", "-C", "alice", "-f", key_path,
+            ],
+            "",
+        );
+        let keys = AuthorizedKeys::load(&dir.join("id_ed25519.pub")).unwrap();
+        let addr = start_server_with(storage, Arc::new(keys)).await;
+
+        let ssh_command = format!(
+            "ssh -i {} -p {} -o StrictHostKeyChecking=no -o UserKnownHostsFile=/dev/null -o LogLevel=ERROR",
+            key_path,
+            addr.port()
+        );
+        let repo_url = format!(
+            "ssh://git@127.0.0.1:{}/projects/ssh-{}.git",
+            addr.port(),
+            std::process::id()
+        );
+        std::fs::write(src.join("README.md"), "hello\n").unwrap();
+        std::fs::write(src.join("docs/guide.md"), "guide\n").unwrap();
+
+        tokio::task::spawn_blocking(move || {
+            let git = |dir: &Path, args: &[&str]| run(dir, "git", args, &ssh_command);
+            git(&src, &["init", "-q", "-b", "main"]);
+            git(
+                &src,
+                &[
+                    "-c",
+                    "user.name=Alice",
+                    "-c",
+                    "user.email=alice@example.com",
+                    "commit",
+                    "-q",
+                    "--allow-empty",
+                    "-m",
+                    "empty",
+                ],
+            );
+            git(&src, &["add", "."]);
+            git(
+                &src,
+                &[
+                    "-c",
+                    "user.name=Alice",
+                    "-c",
+                    "user.email=alice@example.com",
+                    "commit",
+                    "-q",
+                    "-m",
+                    "init",
+                ],
+            );
+            git(&src, &["push", "-q", &repo_url, "main"]);
+
+            git(&dir, &["clone", "-q", &repo_url, "clone"]);
+            let clone = dir.join("clone");
+            assert_eq!(
+                std::fs::read_to_string(clone.join("README.md")).unwrap(),
+                "hello\n"
+            );
+            assert_eq!(
+                std::fs::read_to_string(clone.join("docs/guide.md")).unwrap(),
+                "guide\n"
+            );
+
+            // the clients of the protocol v0 are served as well
+            git(
+                &dir,
+                &[
+                    "-c",
+                    "protocol.version=0",
+                    "clone",
+                    "-q",
+                    &repo_url,
+                    "clone-v0",
+                ],
+            );
+            assert!(dir.join("clone-v0/README.md").exists());
+
+            // the fetch of the protocol v2, which negotiates the `have`s of the clone
+            std::fs::write(src.join("README.md"), "hello again\n").unwrap();
+            git(
+                &src,
+                &[
+                    "-c",
+                    "user.name=Alice",
+                    "-c",
+                    "user.email=alice@example.com",
+                    "commit",
+                    "-q",
+                    "-am",
+                    "again",
+                ],
+            );
+            git(&src, &["push", "-q", &repo_url, "main"]);
+            git(
+                &clone,
+                &["-c", "protocol.version=2", "pull", "-q", "--ff-only"],
+            );
+            assert_eq!(
+                std::fs::read_to_string(clone.join("README.md")).unwrap(),
+                "hello again\n"
+            );
+        })
+        .await
+        .unwrap();
     }
 }
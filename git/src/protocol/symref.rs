@@ -0,0 +1,197 @@
+//! The symbolic ref `HEAD` of the repositories, which points to the default branch checked out
+//! by a clone. It's advertised by the `symref=HEAD:refs/heads/main` capability of the v0, the
+//! `symref-target` of the `ls-refs` of the v2, and the `HEAD` file of the dumb protocol.
+//!
+//! The target of it is saved in the storage when it's set, e.g. by the API. The `HEAD` of a
+//! repository of which it's never set, or of which the branch doesn't exist yet, is the `main`
+//! or the `master` branch, or the first one of the names.
+
+use common::errors::MegaError;
+use common::utils::ZERO_ID;
+use entity::{refs, symrefs};
+
+use super::PackProtocol;
+
+pub const HEAD: &str = "HEAD";
+
+const BRANCH_PREFIX: &str = "refs/heads/";
+
+/// The full name of the branch of the name like `main` or `refs/heads/main`, none if it's not a
+/// valid name of a branch, see `git help check-ref-format`.
+pub fn branch_name(name: &str) -> Option<String> {
+    let name = name.strip_prefix(BRANCH_PREFIX).unwrap_or(name);
+    let valid = !name.is_empty()
+        && name != "@"
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock"))
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+    valid.then(|| format!("{}{}", BRANCH_PREFIX, name))
+}
+
+/// The branch of the refs the `HEAD` points to, which is the target of it if the branch exists,
+/// or the `main` or the `master` branch, or the first one of the names, none if there is no
+/// branch.
+pub fn default_branch<'a>(
+    target: Option<&str>,
+    refs: &'a [refs::Model],
+) -> Option<&'a refs::Model> {
+    let branches = || {
+        refs.iter()
+            .filter(|r| r.ref_name.starts_with(BRANCH_PREFIX))
+    };
+    let find = |name: &str| branches().find(|r| r.ref_name == name);
+    target
+        .and_then(find)
+        .or_else(|| find("refs/heads/main"))
+        .or_else(|| find("refs/heads/master"))
+        .or_else(|| branches().min_by(|a, b| a.ref_name.cmp(&b.ref_name)))
+}
+
+/// The `HEAD` the client sees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    pub id: String,
+    /// The branch it points to, none if it's not of a branch, e.g. the fake commit of a
+    /// directory of a repository.
+    pub target: Option<String>,
+}
+
+impl PackProtocol {
+    /// The target of the `HEAD` saved, of the namespace if the client has one.
+    pub async fn head_target(&self) -> Result<Option<String>, MegaError> {
+        let symref = self
+            .storage
+            .get_symref(self.path.to_str().unwrap(), &self.full_ref_name(HEAD))
+            .await?;
+        Ok(symref.map(|s| s.target))
+    }
+
+    /// Point the `HEAD` to the branch of the full name, which needn't exist yet.
+    pub async fn set_head(&self, target: &str) -> Result<(), MegaError> {
+        self.storage
+            .save_symref(symrefs::Model {
+                repo_path: self.path.to_str().unwrap().to_owned(),
+                ref_name: self.full_ref_name(HEAD),
+                target: target.to_owned(),
+                updated_at: chrono::Utc::now().naive_utc(),
+            })
+            .await
+    }
+
+    /// The `HEAD` of the refs the client sees, none if there is no ref. The one of the refs
+    /// without a branch is the first ref of them, or the one of
+    /// [`get_head_object_id`](PackProtocol::get_head_object_id) of a repository.
+    pub async fn head(&self, refs: &[refs::Model]) -> Result<Option<Head>, MegaError> {
+        let target = self.head_target().await?;
+        if let Some(branch) = default_branch(target.as_deref(), refs) {
+            return Ok(Some(Head {
+                id: branch.ref_git_id.clone(),
+                target: Some(branch.ref_name.clone()),
+            }));
+        }
+        let id = match &self.namespace {
+            Some(_) => refs.first().map(|r| r.ref_git_id.clone()),
+            None => Some(self.get_head_object_id(&self.path).await),
+        };
+        Ok(id
+            .filter(|id| id != ZERO_ID)
+            .map(|id| Head { id, target: None }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use database::driver::memory::storage::MemoryStorage;
+    use database::driver::{ObjectStorage, RefUpdate};
+
+    use super::{branch_name, Head};
+    use crate::protocol::{PackProtocol, Protocol};
+
+    #[test]
+    fn test_branch_name() {
+        assert_eq!(branch_name("main").as_deref(), Some("refs/heads/main"));
+        assert_eq!(
+            branch_name("refs/heads/feature/a").as_deref(),
+            Some("refs/heads/feature/a")
+        );
+        for name in [
+            "",
+            "refs/heads/",
+            "a..b",
+            "a b",
+            ".a",
+            "a/.b",
+            "a.lock",
+            "a/",
+            "a//b",
+            "a^",
+            "a:b",
+            "@",
+            "a@{1}",
+        ] {
+            assert_eq!(branch_name(name), None, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head() {
+        let storage = Arc::new(MemoryStorage::new());
+        let protocol = PackProtocol::new(
+            PathBuf::from("/projects/demo"),
+            storage.clone(),
+            Protocol::Http,
+        );
+        let head = |protocol: &PackProtocol| {
+            let protocol = protocol.clone();
+            async move {
+                let refs = protocol.visible_refs().await.unwrap();
+                protocol.head(&refs).await.unwrap()
+            }
+        };
+        let branch = |id: char, name: &str| Head {
+            id: id.to_string().repeat(40),
+            target: Some(name.to_owned()),
+        };
+        for (name, id) in [("refs/heads/b", '1'), ("refs/heads/dev", '2')] {
+            storage
+                .apply_ref_updates(
+                    "/projects/demo",
+                    &[RefUpdate {
+                        ref_name: name.to_owned(),
+                        old_id: None,
+                        new_id: Some(id.to_string().repeat(40)),
+                    }],
+                    true,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(head(&protocol).await, Some(branch('1', "refs/heads/b")));
+
+        protocol.set_head("refs/heads/dev").await.unwrap();
+        assert_eq!(
+            protocol.head_target().await.unwrap().as_deref(),
+            Some("refs/heads/dev")
+        );
+        assert_eq!(head(&protocol).await, Some(branch('2', "refs/heads/dev")));
+
+        // the branch doesn't exist yet
+        protocol.set_head("refs/heads/trunk").await.unwrap();
+        assert_eq!(head(&protocol).await, Some(branch('1', "refs/heads/b")));
+
+        // the namespaces have their own
+        let mut namespaced = protocol.clone();
+        namespaced.namespace = Some("a".to_owned());
+        assert_eq!(namespaced.head_target().await.unwrap(), None);
+        assert_eq!(head(&namespaced).await, None);
+    }
+}
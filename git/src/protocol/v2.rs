@@ -0,0 +1,557 @@
+//! The protocol v2 of the upload-pack, see `git help gitprotocol-v2`, which a client asks for
+//! by the `version=2` of the `Git-Protocol` header of HTTP, or of the `GIT_PROTOCOL` of SSH.
+//! The clients of the v0 and the v1 are served the same as before.
+//!
+//! The capabilities of the server are advertised instead of the refs, and then the client sends
+//! the commands, each of which is the `command=`, the capabilities, a delim, the arguments and a
+//! flush: `ls-refs` lists the refs, only the ones of the `ref-prefix`es if there are any, so a
+//! fetch of a branch of a huge repository doesn't get all the refs of it, with the branch of
+//! the `HEAD` of the `symrefs`, see [`symref`](super::symref), `fetch` answers the `have`s and
+//! sends the pack like the upload-pack of the v0, and `bundle-uri` tells where the bundle of the
+//! repository is if it has one, see [`bundle`](super::bundle). Each command of HTTP is a
+//! request, while the ones of SSH are sent in the same connection.
+
+use anyhow::{anyhow, Result};
+use bytes::{BufMut, BytesMut};
+
+use super::filter::Filter;
+use super::negotiation::acknowledge;
+use super::pack::{
+    add_pkt_line_string, matches_prefix, PackContent, UploadRequest, PKT_LINE_END_MARKER,
+};
+use super::shallow::Deepen;
+use super::symref::HEAD;
+use super::{Capability, PackProtocol, ServiceType};
+use crate::internal::object::tag::Tag;
+use crate::internal::object::ObjectT;
+
+/// The header of HTTP of the `GIT_PROTOCOL` of the client.
+pub const GIT_PROTOCOL_HEADER: &str = "git-protocol";
+
+/// The delim-pkt, which ends the capabilities of a command and the sections of a response.
+pub const DELIM_PKT: &[u8; 4] = b"0001";
+
+/// The capabilities advertised, of which `fetch` is of the arguments it supports besides the
+/// ones every server does.
+const CAPABILITIES: [&str; 5] = [
+    "version 2",
+    "ls-refs",
+    "fetch=shallow filter",
+    "server-option",
+    "object-format=sha1",
+];
+
+/// Whether the `GIT_PROTOCOL` of the client asks for the v2, the parameters of which are split
+/// by `:`, e.g. `version=2:object-format=sha1`.
+pub fn is_v2(git_protocol: &str) -> bool {
+    git_protocol
+        .split(':')
+        .any(|param| param.trim() == "version=2")
+}
+
+/// A command of the client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Command {
+    pub name: String,
+    /// The capabilities of the client, e.g. `agent=git/2.39.2`.
+    pub capabilities: Vec<String>,
+    pub args: Vec<String>,
+}
+
+impl Command {
+    /// The values of the arguments of the `key`, e.g. the ids of `want <id>`.
+    fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.args
+            .iter()
+            .filter_map(move |arg| arg.strip_prefix(key)?.strip_prefix(' '))
+    }
+
+    fn has(&self, arg: &str) -> bool {
+        self.args.iter().any(|a| a == arg)
+    }
+}
+
+/// A request of the client, which is a command, or a flush alone by which the client is done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Command(Command),
+    End,
+}
+
+/// The packets of the v2, of which the delim is not of the v0.
+#[derive(Debug, PartialEq, Eq)]
+enum Packet<'a> {
+    Flush,
+    Delim,
+    Line(&'a str),
+}
+
+/// The packet at the start of the data and the length of it, none if it's not received
+/// completely yet.
+fn read_packet(data: &[u8]) -> Result<Option<(Packet<'_>, usize)>, String> {
+    let Some(length) = data.get(..4) else {
+        return Ok(None);
+    };
+    let length = std::str::from_utf8(length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .ok_or_else(|| "invalid pkt-line length".to_owned())?;
+    match length {
+        0 => Ok(Some((Packet::Flush, 4))),
+        1 => Ok(Some((Packet::Delim, 4))),
+        2..=4 => Err(format!("unexpected pkt-line length: {}", length)),
+        _ if data.len() < length => Ok(None),
+        _ => {
+            let line = std::str::from_utf8(&data[4..length])
+                .map_err(|_| "the pkt-line is not utf-8".to_owned())?;
+            Ok(Some((Packet::Line(line.trim_end_matches('\n')), length)))
+        }
+    }
+}
+
+/// Parse the request at the start of the data, and the length of it, none if the flush of it is
+/// not received yet. The error is the message of the `ERR` to the client.
+pub fn parse_request(data: &[u8]) -> Result<Option<(Request, usize)>, String> {
+    let mut command = Command::default();
+    let mut read = 0;
+    let mut in_args = false;
+    loop {
+        let Some((packet, length)) = read_packet(&data[read..])? else {
+            return Ok(None);
+        };
+        read += length;
+        match packet {
+            Packet::Flush if read == length => return Ok(Some((Request::End, read))),
+            Packet::Flush => break,
+            Packet::Delim if in_args => return Err("unexpected delim-pkt".to_owned()),
+            Packet::Delim => in_args = true,
+            Packet::Line(line) if in_args => command.args.push(line.to_owned()),
+            Packet::Line(line) => match line.strip_prefix("command=") {
+                Some(name) if command.name.is_empty() => command.name = name.to_owned(),
+                Some(_) => return Err("more than one command".to_owned()),
+                None => command.capabilities.push(line.to_owned()),
+            },
+        }
+    }
+    match command.name.is_empty() {
+        true => Err("no command requested".to_owned()),
+        false => Ok(Some((Request::Command(command), read))),
+    }
+}
+
+/// Whether the id is of the 40 hex digits of an object.
+pub(crate) fn is_object_id(id: &str) -> bool {
+    id.len() == 40 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl PackProtocol {
+    /// The capabilities advertised to a client of the v2 instead of the refs, without the
+    /// `# service=` before them over HTTP, the same as `git http-backend`.
+    pub fn v2_capabilities(&self) -> BytesMut {
+        let mut buf = BytesMut::new();
+        for capability in CAPABILITIES {
+            add_pkt_line_string(&mut buf, format!("{}\n", capability));
+        }
+        if self.bundle_uri.is_some() {
+            add_pkt_line_string(&mut buf, String::from("bundle-uri\n"));
+        }
+        buf.put(&PKT_LINE_END_MARKER[..]);
+        buf
+    }
+
+    /// Whether the client of the upload-pack asks for the v2 by the `GIT_PROTOCOL`.
+    pub fn use_v2(&mut self, git_protocol: Option<&str>) {
+        self.v2 =
+            self.service_type != Some(ServiceType::ReceivePack) && git_protocol.is_some_and(is_v2);
+    }
+
+    /// Answer a request of HTTP, which is a command of the v2 alone. The answer of a request
+    /// which is not valid is an `ERR`.
+    pub async fn v2_request(&mut self, data: &[u8]) -> Result<(Option<PackContent>, BytesMut)> {
+        match parse_request(data) {
+            Ok(Some((Request::Command(command), _))) => self.v2_command(&command).await,
+            Ok(Some((Request::End, _))) => Ok((None, BytesMut::new())),
+            Ok(None) => Ok((None, error("the request is not complete".to_owned()))),
+            Err(msg) => Ok((None, error(msg))),
+        }
+    }
+
+    /// The answer of the command, and the content of the pack sent after it, none if the
+    /// answer is all of the response.
+    pub async fn v2_command(
+        &mut self,
+        command: &Command,
+    ) -> Result<(Option<PackContent>, BytesMut)> {
+        tracing::info!(
+            "v2 command {} of {:?}: {:?}",
+            command.name,
+            self.path,
+            command.args
+        );
+        match command.name.as_str() {
+            "ls-refs" => Ok((None, self.ls_refs(command).await?)),
+            "fetch" => self.fetch(command).await,
+            "bundle-uri" => Ok((None, self.bundle_uri_list())),
+            name => Ok((None, error(format!("unknown command: {}", name)))),
+        }
+    }
+
+    /// The refs of the `ref-prefix`es, or all of them, with the peeled objects of the
+    /// annotated tags of the `peel`, and the `symref-target` of the `HEAD` of the `symrefs`.
+    async fn ls_refs(&self, command: &Command) -> Result<BytesMut> {
+        let prefixes: Vec<String> = command.values("ref-prefix").map(str::to_owned).collect();
+        // and of the ones the protocol is limited to
+        let matched = |name: &str| {
+            matches_prefix(&prefixes, name) && matches_prefix(&self.ref_prefixes, name)
+        };
+        let peel = command.has("peel");
+
+        let git_refs = self
+            .visible_refs()
+            .await
+            .map_err(|e| anyhow!("failed to list the refs: {}", e))?;
+        let head = self
+            .head(&git_refs)
+            .await
+            .map_err(|e| anyhow!("failed to read the HEAD: {}", e))?;
+        let mut buf = BytesMut::new();
+        if let Some(head) = head.filter(|_| matched(HEAD)) {
+            let mut line = format!("{} {}", head.id, HEAD);
+            if let Some(target) = head.target.filter(|_| command.has("symrefs")) {
+                line.push_str(&format!(" symref-target:{}", target));
+            }
+            add_pkt_line_string(&mut buf, line + "\n");
+        }
+        for git_ref in git_refs.iter().filter(|r| matched(&r.ref_name)) {
+            let mut line = format!("{} {}", git_ref.ref_git_id, git_ref.ref_name);
+            if peel && git_ref.ref_name.starts_with("refs/tags/") {
+                let object = self
+                    .storage
+                    .get_obj_data_by_id(&git_ref.ref_git_id)
+                    .await
+                    .map_err(|e| anyhow!("failed to peel {}: {}", git_ref.ref_name, e))?;
+                if let Some(object) = object {
+                    if object.object_type == "tag" {
+                        let tag = Tag::new_from_data(object.data);
+                        line.push_str(&format!(" peeled:{}", tag.object_hash));
+                    }
+                }
+            }
+            add_pkt_line_string(&mut buf, line + "\n");
+        }
+        buf.put(&PKT_LINE_END_MARKER[..]);
+        Ok(buf)
+    }
+
+    /// The `acknowledgments` of the `have`s until the server is ready, and then the
+    /// `shallow-info` of a shallow fetch, and the `packfile`, the pack of which is always sent
+    /// in the side-band-64k.
+    async fn fetch(&mut self, command: &Command) -> Result<(Option<PackContent>, BytesMut)> {
+        let mut buf = BytesMut::new();
+        let request = match fetch_request(command) {
+            Ok(request) => request,
+            Err(msg) => return Ok((None, error(msg))),
+        };
+        for (arg, capability) in [
+            ("thin-pack", Capability::ThinPack),
+            ("no-progress", Capability::NoProgress),
+            ("ofs-delta", Capability::OfsDelta),
+        ] {
+            if command.has(arg) && !self.capabilities.contains(&capability) {
+                self.capabilities.push(capability);
+            }
+        }
+        if !self.capabilities.contains(&Capability::SideBand64k) {
+            self.capabilities.push(Capability::SideBand64k);
+        }
+        match request
+            .filter
+            .as_ref()
+            .map(|s| s.parse::<Filter>())
+            .transpose()
+        {
+            Ok(filter) => self.filter = filter,
+            Err(msg) => return Ok((None, error(msg))),
+        }
+
+        let graph = self.commit_graph().await;
+        let (common, ready) = acknowledge(&graph, &request);
+        if !request.done {
+            add_pkt_line_string(&mut buf, String::from("acknowledgments\n"));
+            if common.is_empty() {
+                add_pkt_line_string(&mut buf, String::from("NAK\n"));
+            }
+            for id in &common {
+                add_pkt_line_string(&mut buf, format!("ACK {}\n", id));
+            }
+            if !ready {
+                // the next request of the `have`s is waited for
+                buf.put(&PKT_LINE_END_MARKER[..]);
+                return Ok((None, buf));
+            }
+            add_pkt_line_string(&mut buf, String::from("ready\n"));
+            buf.put(&DELIM_PKT[..]);
+        }
+
+        let content = if let Some(deepen) = &request.deepen {
+            let info = match self
+                .shallow_info(&request.want, &request.shallow, deepen)
+                .await
+            {
+                Ok(info) => info,
+                Err(msg) => return Ok((None, error(msg))),
+            };
+            add_pkt_line_string(&mut buf, String::from("shallow-info\n"));
+            for id in &info.shallow {
+                add_pkt_line_string(&mut buf, format!("shallow {}\n", id));
+            }
+            for id in &info.unshallow {
+                add_pkt_line_string(&mut buf, format!("unshallow {}\n", id));
+            }
+            buf.put(&DELIM_PKT[..]);
+            PackContent::Commits(info.commits)
+        } else if let Some(models) = self.wanted_objects(&request.want).await? {
+            PackContent::Objects(models)
+        } else if common.is_empty() {
+            PackContent::Full
+        } else {
+            PackContent::Incremental {
+                want: request.want,
+                have: common.into_iter().collect(),
+            }
+        };
+        add_pkt_line_string(&mut buf, String::from("packfile\n"));
+        Ok((Some(content), buf))
+    }
+}
+
+/// The `want`s, the `have`s and the shallow arguments of a `fetch`.
+fn fetch_request(command: &Command) -> Result<UploadRequest, String> {
+    let mut request = UploadRequest {
+        done: command.has("done"),
+        filter: command.values("filter").next().map(str::to_owned),
+        ..Default::default()
+    };
+    for (key, ids) in [
+        ("want", &mut request.want),
+        ("shallow", &mut request.shallow),
+    ] {
+        for id in command.values(key) {
+            if !is_object_id(id) {
+                return Err(format!("invalid {}: {}", key, id));
+            }
+            ids.insert(id.to_owned());
+        }
+    }
+    for id in command.values("have") {
+        if !is_object_id(id) {
+            return Err(format!("invalid have: {}", id));
+        }
+        request.have.push(id.to_owned());
+    }
+    if request.want.is_empty() {
+        return Err("no want".to_owned());
+    }
+    if let Some(depth) = command.values("deepen").next() {
+        // `deepen 0` is rejected later
+        request.deepen = Some(Deepen::Depth(depth.parse().unwrap_or(0)));
+    } else if let Some(since) = command.values("deepen-since").next() {
+        request.deepen = since.parse().ok().map(Deepen::Since);
+    } else {
+        let names: Vec<String> = command.values("deepen-not").map(str::to_owned).collect();
+        request.deepen = (!names.is_empty()).then_some(Deepen::Not(names));
+    }
+    Ok(request)
+}
+
+/// The `ERR` line of the message to the client.
+pub(crate) fn error(msg: String) -> BytesMut {
+    let mut buf = BytesMut::new();
+    add_pkt_line_string(&mut buf, format!("ERR {}\n", msg));
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use bytes::{BufMut, BytesMut};
+
+    use super::{is_v2, parse_request, Command, Request};
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::ObjectT;
+    use crate::internal::pack::stream::PackStream;
+    use crate::protocol::pack::PackContent;
+    use crate::protocol::tests::{storage, HEAD};
+    use crate::protocol::{PackProtocol, Protocol, ServiceType};
+
+    const REPO: &str = "/projects/d50df";
+
+    async fn protocol() -> PackProtocol {
+        let storage = storage().await;
+        for name in ["refs/heads/main", "refs/heads/dev", "refs/tags/v1.0"] {
+            storage.add_ref(REPO, name, HEAD);
+        }
+        let mut protocol = PackProtocol::new(PathBuf::from(REPO), storage, Protocol::Http);
+        protocol.service_type = Some(ServiceType::UploadPack);
+        protocol
+    }
+
+    fn command(name: &str, args: &[&str]) -> Command {
+        Command {
+            name: name.to_owned(),
+            capabilities: vec!["agent=git/2.39.2".to_owned()],
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    fn pkt_lines(lines: &[&str]) -> String {
+        lines
+            .iter()
+            .map(|line| match *line {
+                "0000" | "0001" => line.to_string(),
+                line => format!("{:04x}{}\n", line.len() + 5, line),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert!(is_v2("version=2"));
+        assert!(is_v2("object-format=sha1:version=2"));
+        assert!(!is_v2("version=1"));
+
+        let data = pkt_lines(&[
+            "command=ls-refs",
+            "agent=git/2.39.2",
+            "0001",
+            "peel",
+            "ref-prefix refs/heads/",
+            "0000",
+        ]);
+        let mut data = BytesMut::from(data.as_bytes());
+        // incomplete before the flush
+        assert_eq!(parse_request(&data[..data.len() - 4]), Ok(None));
+        data.put(&b"0000"[..]);
+        let (request, read) = parse_request(&data).unwrap().unwrap();
+        let expected = command("ls-refs", &["peel", "ref-prefix refs/heads/"]);
+        assert_eq!(request, Request::Command(expected));
+        // the flush after it ends the connection
+        assert_eq!(parse_request(&data[read..]), Ok(Some((Request::End, 4))));
+
+        assert!(parse_request(pkt_lines(&["agent=git", "0000"]).as_bytes()).is_err());
+        assert!(parse_request(b"0003").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ls_refs() {
+        let mut protocol = protocol().await;
+        assert_eq!(
+            String::from_utf8(protocol.v2_capabilities().to_vec()).unwrap(),
+            pkt_lines(&[
+                "version 2",
+                "ls-refs",
+                "fetch=shallow filter",
+                "server-option",
+                "object-format=sha1",
+                "0000"
+            ])
+        );
+        protocol.use_v2(Some("version=2"));
+        assert!(protocol.v2);
+
+        let ls_refs = |args: &'static [&'static str]| {
+            let mut protocol = protocol.clone();
+            async move {
+                let (content, buf) = protocol
+                    .v2_command(&command("ls-refs", args))
+                    .await
+                    .unwrap();
+                assert!(content.is_none());
+                String::from_utf8(buf.to_vec()).unwrap()
+            }
+        };
+        // only the refs of the prefixes
+        let main = format!("{} refs/heads/main", HEAD);
+        let tag = format!("{} refs/tags/v1.0", HEAD);
+        assert_eq!(
+            ls_refs(&["ref-prefix refs/heads/m", "ref-prefix refs/tags/"]).await,
+            pkt_lines(&[&main, &tag, "0000"])
+        );
+        assert_eq!(ls_refs(&["ref-prefix refs/pull/"]).await, "0000");
+        assert_eq!(ls_refs(&["peel"]).await.matches(" refs/").count(), 3);
+
+        // the branch of the HEAD of the `symrefs`
+        let head = format!("{} HEAD", HEAD);
+        assert_eq!(
+            ls_refs(&["ref-prefix HEAD"]).await,
+            pkt_lines(&[&head, "0000"])
+        );
+        let symref = format!("{} HEAD symref-target:refs/heads/main", HEAD);
+        assert_eq!(
+            ls_refs(&["symrefs", "ref-prefix HEAD"]).await,
+            pkt_lines(&[&symref, "0000"])
+        );
+
+        let (_, buf) = protocol.v2_command(&command("bundle", &[])).await.unwrap();
+        assert_eq!(&buf[4..], b"ERR unknown command: bundle\n");
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let protocol = protocol().await;
+        let fetch = |args: Vec<String>| {
+            let mut protocol = protocol.clone();
+            async move {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                let (content, buf) = protocol.v2_command(&command("fetch", &args)).await.unwrap();
+                (protocol, content, String::from_utf8(buf.to_vec()).unwrap())
+            }
+        };
+        let objects = |protocol: PackProtocol, content: PackContent| async move {
+            let pack = protocol.build_pack(content).await.unwrap();
+            let mut stream = PackStream::new(Cursor::new(pack)).unwrap();
+            stream.set_storage(Some(protocol.storage.clone()));
+            let mut objects = HashSet::new();
+            while let Some(object) = stream.next_object().await.unwrap() {
+                objects.insert(object.hash.to_plain_str());
+            }
+            objects
+        };
+
+        // a clone, of which the pack follows right away
+        let want = format!("want {}", HEAD);
+        let (clone, content, buf) = fetch(vec![want.clone(), "done".to_owned()]).await;
+        assert_eq!(buf, pkt_lines(&["packfile"]));
+        let content = content.unwrap();
+        assert!(matches!(content, PackContent::Full));
+        let cloned = objects(clone, content).await;
+        assert!(cloned.contains(HEAD));
+
+        // nothing in common, the next `have`s are waited for
+        let other = format!("have {}", "1".repeat(40));
+        let (_, content, buf) = fetch(vec![want.clone(), other]).await;
+        assert!(content.is_none());
+        assert_eq!(buf, pkt_lines(&["acknowledgments", "NAK", "0000"]));
+
+        // a fetch after the clone of the parent, which is ready
+        let storage = protocol.storage.get_obj_data_by_id(HEAD).await.unwrap();
+        let parent = Commit::new_from_data(storage.unwrap().data).parent_tree_ids[0];
+        let have = format!("have {}", parent);
+        let args = vec![want, have, "thin-pack".to_owned(), "ofs-delta".to_owned()];
+        let (fetched, content, buf) = fetch(args).await;
+        let ack = format!("ACK {}", parent);
+        assert_eq!(
+            buf,
+            pkt_lines(&["acknowledgments", &ack, "ready", "0001", "packfile"])
+        );
+        let fetched_objects = objects(fetched, content.unwrap()).await;
+        assert!(fetched_objects.contains(HEAD));
+        assert!(fetched_objects.len() < cloned.len());
+
+        let (_, _, buf) = fetch(vec!["want HEAD".to_owned()]).await;
+        assert!(buf.contains("ERR invalid want: HEAD"));
+    }
+}
@@ -5,14 +5,17 @@ use std::{collections::HashSet, sync::Arc};
 use super::nodes::NodeBuilder;
 use crate::errors::GitError;
 use crate::hash::Hash;
+use crate::internal::commit_graph::CommitGraph;
 use crate::internal::object::blob::Blob;
 use crate::internal::object::commit::Commit;
-use crate::internal::object::tree::Tree;
+use crate::internal::object::tree::{Tree, TreeItemMode};
 use crate::internal::object::ObjectT;
-use crate::internal::pack::encode::pack_encode;
-use crate::protocol::PackProtocol;
+use crate::internal::pack::encode::write_thin_pack;
+use crate::protocol::filter::Filter;
+use crate::protocol::{Capability, PackProtocol};
 use anyhow::Result;
 use async_recursion::async_recursion;
+use common::errors::MegaError;
 use common::utils::ZERO_ID;
 use database::driver::ObjectStorage;
 use entity::{git_obj, refs, repo_directory};
@@ -45,13 +48,15 @@ impl PackProtocol {
         let blob_and_tree = self.storage.get_node_by_path(repo_path).await.unwrap();
         let git_ids = blob_and_tree
             .iter()
+            .filter(|model| model.node_type != "blob" || self.filter != Some(Filter::BlobNone))
             .map(|model| model.git_id.clone())
             .collect();
         // may take lots of time
-        let obj_datas = self.storage.get_obj_data_by_ids(git_ids).await.unwrap();
+        let obj_datas = self.get_objects(git_ids).await.unwrap();
         obj_datas.iter().for_each(|model| {
             let hash = Hash::new_from_str(&model.git_id);
             let obj: Arc<dyn ObjectT> = match model.object_type.as_str() {
+                "blob" if !includes_blob(self.filter, model) => return,
                 "blob" => {
                     let mut blob = Blob::new_from_data(model.data.clone());
                     blob.set_hash(hash);
@@ -67,43 +72,371 @@ impl PackProtocol {
             hash_object.insert(hash, obj);
         });
         let meta_vec: Vec<Arc<dyn ObjectT>> = hash_object.into_values().collect();
-        let result: Vec<u8> = pack_encode(meta_vec).unwrap();
-        Ok(result)
+        Ok(self.encode_pack(meta_vec))
     }
 
+    /// The pack of the commits reachable from the `want`s but not from the common `have`s, by
+    /// the commit-graph of the repository, and the trees and the blobs of them which are not of
+    /// the commits of the client on the edge of them.
+    ///
+    /// Of the `thin-pack` of the client, an object is sent as the delta of the one at the same
+    /// path of the edge, which is not in the pack, as `git pack-objects --thin`.
     pub async fn get_incremental_pack_data(
         &self,
-        repo_path: &Path,
+        _repo_path: &Path,
         want: &HashSet<String>,
-        _have: &HashSet<String>,
+        have: &HashSet<String>,
     ) -> Result<Vec<u8>, GitError> {
-        let mut hash_meta: HashMap<String, Arc<dyn ObjectT>> = HashMap::new();
-        let all_commits = self
+        let graph = self.commit_graph().await;
+        let to_hashes = |ids: &HashSet<String>| -> Vec<Hash> {
+            ids.iter().map(|id| Hash::new_from_str(id)).collect()
+        };
+        let missing = graph.rev_list(&to_hashes(want), &to_hashes(have));
+        let missing_set: HashSet<Hash> = missing.iter().copied().collect();
+        let mut edges = HashSet::new();
+        for id in &missing {
+            let parents = graph.parents(id).unwrap_or_default();
+            edges.extend(parents.iter().filter(|p| !missing_set.contains(p)).copied());
+        }
+
+        let commits = self.load_commits(missing).await;
+        let edge_commits = self.load_commits(edges.into_iter().collect()).await;
+        // the trees and the blobs of the edges by the paths, which the client has
+        let edge_roots = edge_commits
+            .iter()
+            .map(|c| (String::new(), c.tree_id))
+            .collect();
+        let mut edge_objects = PathObjects::default();
+        self.walk_trees(edge_roots, &mut edge_objects, &HashSet::new(), false)
+            .await;
+        let excluded = edge_objects.by_path.values().copied().collect();
+
+        let roots = commits.iter().map(|c| (String::new(), c.tree_id)).collect();
+        let mut objects = PathObjects::default();
+        self.walk_trees(roots, &mut objects, &excluded, true).await;
+
+        let mut bases: HashMap<Hash, Arc<dyn ObjectT>> = HashMap::new();
+        if self.capabilities.contains(&Capability::ThinPack) {
+            let mut base_blobs = Vec::new();
+            for (path, id) in &objects.by_path {
+                let Some(base) = edge_objects.by_path.get(path) else {
+                    continue;
+                };
+                // the base is of the same type, as the type of a delta is the one of the base
+                match (objects.trees.contains_key(id), edge_objects.trees.get(base)) {
+                    (true, Some(tree)) => {
+                        bases.insert(*id, tree.clone());
+                    }
+                    (false, None) if objects.objects.contains_key(id) => {
+                        base_blobs.push((*id, *base))
+                    }
+                    _ => {}
+                }
+            }
+            let ids = base_blobs
+                .iter()
+                .map(|(_, base)| base.to_plain_str())
+                .collect();
+            let loaded: HashMap<Hash, Arc<dyn ObjectT>> = self
+                .get_objects(ids)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|model| {
+                    let mut blob = Blob::new_from_data(model.data);
+                    blob.set_hash(Hash::new_from_str(&model.git_id));
+                    (blob.id, Arc::new(blob) as Arc<dyn ObjectT>)
+                })
+                .collect();
+            for (id, base) in base_blobs {
+                if let Some(blob) = loaded.get(&base) {
+                    bases.insert(id, blob.clone());
+                }
+            }
+        }
+
+        let mut meta_vec: Vec<Arc<dyn ObjectT>> = objects.objects.into_values().collect();
+        meta_vec.extend(commits.into_iter().map(|c| Arc::new(c) as Arc<dyn ObjectT>));
+        Ok(self.encode_thin_pack(meta_vec, &bases))
+    }
+
+    /// The commits of the ids in the storage.
+    async fn load_commits(&self, ids: Vec<Hash>) -> Vec<Commit> {
+        let ids = ids.iter().map(|id| id.to_plain_str()).collect();
+        let mut models = self.get_objects(ids).await.unwrap();
+        // an object may be saved more than once, of the repositories
+        let mut seen = HashSet::new();
+        models.retain(|model| seen.insert(model.git_id.clone()));
+        models
+            .into_iter()
+            .map(|model| {
+                let mut commit = Commit::new_from_data(model.data);
+                commit.set_hash(Hash::new_from_str(&model.git_id));
+                commit
+            })
+            .collect()
+    }
+
+    /// Walk the trees of the `roots` level by level, the subtrees and the blobs in `excluded`
+    /// are skipped. The blobs are loaded only if `with_blobs`, by the filter of the request.
+    async fn walk_trees(
+        &self,
+        roots: Vec<(String, Hash)>,
+        walked: &mut PathObjects,
+        excluded: &HashSet<Hash>,
+        with_blobs: bool,
+    ) {
+        let mut seen: HashSet<Hash> = HashSet::new();
+        let mut pending = roots;
+        let mut blobs = Vec::new();
+        while !pending.is_empty() {
+            let mut paths: HashMap<Hash, Vec<String>> = HashMap::new();
+            for (path, id) in std::mem::take(&mut pending) {
+                if !excluded.contains(&id) && seen.insert(id) {
+                    paths.entry(id).or_default().push(path);
+                } else if let Some(paths) = paths.get_mut(&id) {
+                    paths.push(path);
+                }
+            }
+            let ids = paths.keys().map(|id| id.to_plain_str()).collect();
+            for model in self.get_objects(ids).await.unwrap() {
+                let mut tree = Tree::new_from_data(model.data);
+                tree.set_hash(Hash::new_from_str(&model.git_id));
+                let Some(tree_paths) = paths.remove(&tree.id) else {
+                    // saved more than once
+                    continue;
+                };
+                for path in tree_paths {
+                    walked.by_path.insert(path.clone(), tree.id);
+                    for item in &tree.tree_items {
+                        let item_path = match path.is_empty() {
+                            true => item.name.clone(),
+                            false => format!("{}/{}", path, item.name),
+                        };
+                        match item.mode {
+                            TreeItemMode::Tree => pending.push((item_path, item.id)),
+                            // the commits of the submodules are not in the repository
+                            TreeItemMode::Commit => {}
+                            _ if excluded.contains(&item.id) => {}
+                            _ => {
+                                walked.by_path.insert(item_path, item.id);
+                                if seen.insert(item.id) {
+                                    blobs.push(item.id.to_plain_str());
+                                }
+                            }
+                        }
+                    }
+                }
+                walked.trees.insert(tree.id, Arc::new(tree.clone()));
+                walked.objects.insert(tree.id, Arc::new(tree));
+            }
+        }
+        // the blobs are not loaded at all of `blob:none`
+        if with_blobs && self.filter != Some(Filter::BlobNone) {
+            for model in self.get_objects(blobs).await.unwrap() {
+                if includes_blob(self.filter, &model) {
+                    let mut blob = Blob::new_from_data(model.data);
+                    blob.set_hash(Hash::new_from_str(&model.git_id));
+                    walked.objects.insert(blob.id, Arc::new(blob));
+                }
+            }
+        }
+    }
+
+    /// The commit-graph of the repository, which is built of the commits in the storage if
+    /// it's not in the cache.
+    pub async fn commit_graph(&self) -> Arc<CommitGraph> {
+        if let Some(graph) = self.commit_graphs.get(&self.path) {
+            return graph;
+        }
+        let commits = self
             .storage
-            .get_all_commits_by_path(repo_path.to_str().unwrap())
+            .get_all_commits_by_path(self.path.to_str().unwrap())
             .await
             .unwrap();
+        let graph = CommitGraph::from_commits(commits.into_iter().map(|model| {
+            let parents = model.pid.iter().map(|id| Hash::new_from_str(id)).collect();
+            (Hash::new_from_str(&model.git_id), parents)
+        }));
+        self.commit_graphs.insert(&self.path, graph)
+    }
 
-        for model in all_commits {
-            let commit_id = model.git_id.clone();
-            if want.contains(&commit_id) {
-                let c: Commit = model.into();
-                if let Some(root) = self
-                    .storage
-                    .get_obj_data_by_id(&c.tree_id.to_plain_str())
-                    .await
-                    .unwrap()
-                {
-                    get_child_trees(&root, &mut hash_meta, self.storage.clone()).await
+    /// The pack of the `commits` and all the trees and the blobs of them, without the parents
+    /// which are not in the `commits`, e.g. of a shallow clone.
+    pub async fn get_commits_pack_data(&self, commits: Vec<Commit>) -> Result<Vec<u8>, GitError> {
+        let mut hash_meta: HashMap<String, Arc<dyn ObjectT>> = HashMap::new();
+        for c in commits {
+            let tree_id = c.tree_id.to_plain_str();
+            if !hash_meta.contains_key(&tree_id) {
+                if let Some(root) = self.get_objects(vec![tree_id.clone()]).await.unwrap().pop() {
+                    self.get_child_trees(&root, &mut hash_meta).await
                 } else {
-                    return Err(GitError::InvalidTreeObject(c.tree_id.to_plain_str()));
+                    return Err(GitError::InvalidTreeObject(tree_id));
                 };
-                hash_meta.insert(commit_id, Arc::new(c));
+            }
+            hash_meta.insert(c.id.to_plain_str(), Arc::new(c));
+        }
+        let meta_vec: Vec<Arc<dyn ObjectT>> = hash_meta.into_values().collect();
+        Ok(self.encode_pack(meta_vec))
+    }
+
+    /// The `want`s if they are the blobs or the trees, e.g. of the blobs fetched on demand by a
+    /// partial clone, or `None` if any of them is not.
+    pub async fn wanted_objects(
+        &self,
+        want: &HashSet<String>,
+    ) -> Result<Option<Vec<git_obj::Model>>, GitError> {
+        let mut models = self
+            .get_objects(want.iter().cloned().collect())
+            .await
+            .unwrap();
+        // an object may be saved more than once, of the repositories
+        let mut seen = HashSet::new();
+        models.retain(|model| seen.insert(model.git_id.clone()));
+        if models.len() != want.len()
+            || models
+                .iter()
+                .any(|model| model.object_type != "blob" && model.object_type != "tree")
+        {
+            return Ok(None);
+        }
+        Ok(Some(models))
+    }
+
+    /// The pack of the [`wanted_objects`](PackProtocol::wanted_objects). The blobs of them are
+    /// sent whatever the filter is, the ones of the trees are filtered.
+    pub async fn get_objects_pack_data(&self, models: Vec<git_obj::Model>) -> Vec<u8> {
+        let mut hash_meta: HashMap<String, Arc<dyn ObjectT>> = HashMap::new();
+        for model in models {
+            if model.object_type == "tree" {
+                self.get_child_trees(&model, &mut hash_meta).await;
+            } else {
+                let mut blob = Blob::new_from_data(model.data);
+                blob.set_hash(Hash::new_from_str(&model.git_id));
+                hash_meta.insert(model.git_id, Arc::new(blob));
             }
         }
         let meta_vec: Vec<Arc<dyn ObjectT>> = hash_meta.into_values().collect();
-        let result: Vec<u8> = pack_encode(meta_vec).unwrap();
-        Ok(result)
+        self.encode_pack(meta_vec)
+    }
+
+    /// The objects of the ids in the storage, by the cache of the server.
+    pub async fn get_objects(
+        &self,
+        git_ids: Vec<String>,
+    ) -> Result<Vec<git_obj::Model>, MegaError> {
+        self.objects.get_many(self.storage.as_ref(), git_ids).await
+    }
+
+    /// Load the latest `commits` of the refs of the repository, and all the trees and the blobs
+    /// of them, into the cache of the objects, returns how many objects are new in the cache.
+    /// The objects are loaded from the newer commits, and it stops once the cache is full, so
+    /// the objects of the older commits don't evict the ones of the newer.
+    pub async fn warm_objects(&self, commits: usize) -> Result<usize, MegaError> {
+        let path = self.path.to_str().unwrap();
+        let tips: Vec<Hash> = self
+            .storage
+            .search_refs(path)
+            .await?
+            .into_iter()
+            .filter(|r| r.repo_path == path)
+            .map(|r| Hash::new_from_str(&r.ref_git_id))
+            .collect();
+        let graph = self.commit_graph().await;
+        let mut pending: Vec<String> = graph
+            .rev_list(&tips, &[])
+            .into_iter()
+            .take(commits)
+            .map(|id| id.to_plain_str())
+            .collect();
+        let mut seen: HashSet<String> = pending.iter().cloned().collect();
+        let mut loaded = 0;
+        while !pending.is_empty() && self.objects.size().1 < self.objects.capacity() {
+            let missing: Vec<String> = pending
+                .iter()
+                .filter(|id| !self.objects.contains(id))
+                .cloned()
+                .collect();
+            let objects = self.get_objects(std::mem::take(&mut pending)).await?;
+            loaded += missing
+                .iter()
+                .filter(|id| self.objects.contains(id))
+                .count();
+            for object in objects {
+                let children = match object.object_type.as_str() {
+                    "commit" => vec![Commit::new_from_data(object.data).tree_id],
+                    "tree" => Tree::new_from_data(object.data)
+                        .tree_items
+                        .into_iter()
+                        .map(|item| item.id)
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                for id in children {
+                    let id = id.to_plain_str();
+                    if seen.insert(id.clone()) {
+                        pending.push(id);
+                    }
+                }
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Retrieve all the sub trees and the blobs of the tree recursively.
+    #[async_recursion]
+    async fn get_child_trees(
+        &self,
+        root: &git_obj::Model,
+        hash_object: &mut HashMap<String, Arc<dyn ObjectT>>,
+    ) {
+        let mut t = Tree::new_from_data(root.data.clone());
+        // the id is not computed from the data
+        t.set_hash(Hash::new_from_str(&root.git_id));
+        let mut search_child_ids = vec![];
+        for item in &t.tree_items {
+            // the blobs are not loaded at all of `blob:none`
+            if self.filter == Some(Filter::BlobNone) && item.mode != TreeItemMode::Tree {
+                continue;
+            }
+            if !hash_object.contains_key(&item.id.to_plain_str()) {
+                search_child_ids.push(item.id.to_plain_str());
+            }
+        }
+        let objs = self.get_objects(search_child_ids).await.unwrap();
+        for obj in objs {
+            if obj.object_type == "tree" {
+                self.get_child_trees(&obj, hash_object).await;
+            } else if includes_blob(self.filter, &obj) {
+                let mut blob = Blob::new_from_data(obj.data.clone());
+                blob.set_hash(Hash::new_from_str(&obj.git_id));
+                hash_object.insert(obj.git_id.clone(), Arc::new(blob));
+            }
+        }
+        hash_object.insert(t.id.to_plain_str(), Arc::new(t));
+    }
+
+    /// Encode the objects in a pack with the delta compression, of which the progress is
+    /// reported.
+    fn encode_pack(&self, objects: Vec<Arc<dyn ObjectT>>) -> Vec<u8> {
+        self.encode_thin_pack(objects, &HashMap::new())
+    }
+
+    /// Like [`encode_pack`](PackProtocol::encode_pack), but the deltas may be of the `bases`
+    /// of the client, which are not in the pack.
+    fn encode_thin_pack(
+        &self,
+        objects: Vec<Arc<dyn ObjectT>>,
+        bases: &HashMap<Hash, Arc<dyn ObjectT>>,
+    ) -> Vec<u8> {
+        let count = objects.len();
+        self.report_progress(format!("Enumerating objects: {}, done.\n", count));
+        let data = write_thin_pack(objects, bases, self.window, self.depth).unwrap();
+        self.report_progress(format!(
+            "Compressing objects: 100% ({}/{}), done.\n",
+            count, count
+        ));
+        data
     }
 
     pub async fn get_head_object_id(&self, repo_path: &Path) -> String {
@@ -177,31 +510,17 @@ impl PackProtocol {
     }
 }
 
-// retrieve all sub trees recursively
-#[async_recursion]
-async fn get_child_trees(
-    root: &git_obj::Model,
-    hash_object: &mut HashMap<String, Arc<dyn ObjectT>>,
-    storage: Arc<dyn ObjectStorage>,
-) {
-    let t = Tree::new_from_data(root.data.clone());
-    let mut search_child_ids = vec![];
-    for item in &t.tree_items {
-        if !hash_object.contains_key(&item.id.to_plain_str()) {
-            search_child_ids.push(item.id.to_plain_str());
-        }
-    }
-    let objs = storage.get_obj_data_by_ids(search_child_ids).await.unwrap();
-    for obj in objs {
-        if obj.object_type == "tree" {
-            get_child_trees(&obj, hash_object, storage.clone()).await;
-        } else {
-            let blob = Blob::new_from_data(obj.data.clone());
-            hash_object.insert(obj.git_id.clone(), Arc::new(blob));
-        }
-    }
-    let tree = Tree::new_from_data(t.get_raw());
-    hash_object.insert(t.id.to_plain_str(), Arc::new(tree));
+/// The objects of the trees walked, by the ids.
+#[derive(Default)]
+struct PathObjects {
+    /// The id of each path of the trees, the root of which is the empty one.
+    by_path: HashMap<String, Hash>,
+    trees: HashMap<Hash, Arc<dyn ObjectT>>,
+    objects: HashMap<Hash, Arc<dyn ObjectT>>,
+}
+
+fn includes_blob(filter: Option<Filter>, blob: &git_obj::Model) -> bool {
+    filter.is_none_or(|filter| filter.includes_blob(blob.data.len()))
 }
 
 /// Generates a new commit for a subdirectory of the original project directory.
@@ -248,11 +567,12 @@ pub async fn generate_child_commit_and_refs(
     }
 }
 
+/// Save the nodes and the commits of the objects of the mr, and return the commits.
 pub async fn save_node_from_mr(
     storage: Arc<dyn ObjectStorage>,
     mr_id: i64,
     repo_path: &Path,
-) -> Result<(), anyhow::Error> {
+) -> Result<Vec<Commit>, anyhow::Error> {
     let tree_map: HashMap<Hash, Tree> = get_objects_from_mr(storage.clone(), mr_id, "tree").await;
     let blob_map: HashMap<Hash, Blob> = get_objects_from_mr(storage.clone(), mr_id, "blob").await;
     let commits: Vec<Commit> = get_objects_vec_from_mr(storage.clone(), mr_id, "commit").await;
@@ -261,12 +581,12 @@ pub async fn save_node_from_mr(
         tree_map,
         blob_map,
         repo_path: repo_path.to_path_buf(),
-        commits,
+        commits: commits.clone(),
     };
     let nodes = builder.build_node_tree().await.unwrap();
     builder.save_nodes(nodes).await.unwrap();
     builder.save_commits().await.unwrap();
-    Ok(())
+    Ok(commits)
 }
 
 pub async fn save_node_from_git_obj(
@@ -307,6 +627,7 @@ pub async fn save_node_from_git_obj(
             git_id: Set(m.git_id.clone()),
             object_type: Set(m.object_type.clone()),
             data: Set(m.data.clone()),
+            compression: Set(None),
         })
         .collect();
     storage.save_obj_data(git_obj_active_model).await.unwrap();
@@ -74,7 +74,7 @@ pub fn read_chars<R: Read>(input: &mut R, buf: &mut Vec<u8>, size: usize) -> io:
 pub fn read_hash<R: Read>(stream: &mut R) -> io::Result<Hash> {
     let bytes = read_bytes(stream)?;
 
-    Ok(Hash(bytes))
+    Ok(Hash::Sha1(bytes))
 }
 
 /// Read a vec until the delimiter is read
@@ -203,10 +203,11 @@ pub fn write_offset_encoding(number: u64) -> Vec<u8> {
     num.push((number & 0x7f) as u8);
     number >>= 7;
 
-    // Encode the remaining bits in subsequent bytes
+    // Encode the remaining bits in subsequent bytes, minus 1 as `read_offset_encoding` adds
     while number > 0 {
+        number -= 1;
         // Set the most significant bit to indicate continuation
-        num.push(((number & 0x7f) - 1) as u8 | 0x80);
+        num.push((number & 0x7f) as u8 | 0x80);
         number >>= 7;
     }
 
@@ -406,4 +407,15 @@ mod test {
         get_env_number("GIT_INTERNAL_DECODE_STORAGE_BATCH_SIZE", &mut batch_size);
         assert_eq!(batch_size, 10000);
     }
+
+    #[test]
+    fn test_offset_encoding() {
+        for number in [0, 1, 127, 128, 255, 16383, 16384, 16511, 2097152, u32::MAX as u64] {
+            let encoded = write_offset_encoding(number);
+            let mut consume = 0;
+            let decoded = read_offset_encoding(&mut encoded.as_slice(), &mut consume).unwrap();
+            assert_eq!(decoded, number);
+            assert_eq!(consume, encoded.len());
+        }
+    }
 }
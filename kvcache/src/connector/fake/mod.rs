@@ -1,11 +1,18 @@
 use super::Connector;
 use anyhow::Result;
-use std::{cell::RefCell, collections::HashMap, hash::Hash};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
 
-pub struct FakeKVstore<K, V> {
-    table: RefCell<HashMap<K, V>>,
+/// An in-memory connector, e.g. to test the users of a [`KVCache`](crate::KVCache) without a
+/// Redis server. The expired values are missed like Redis.
+pub struct FakeKVStore<K, V> {
+    table: RefCell<HashMap<K, (V, Option<Instant>)>>,
 }
-impl<K, V> Connector for FakeKVstore<K, V>
+impl<K, V> Connector for FakeKVStore<K, V>
 where
     K: Eq + Hash,
     V: Clone,
@@ -19,23 +26,38 @@ where
         }
     }
     fn get(&self, key: Self::K) -> Option<Self::V> {
-        self.table.borrow().get(&key).cloned()
+        match self.table.borrow().get(&key) {
+            Some((_, Some(deadline))) if *deadline <= Instant::now() => None,
+            Some((v, _)) => Some(v.clone()),
+            None => None,
+        }
     }
 
     fn set(&self, key: Self::K, v: Self::V) -> Result<()> {
-        self.table.borrow_mut().insert(key, v);
+        self.table.borrow_mut().insert(key, (v, None));
+        Ok(())
+    }
+
+    fn delete(&self, key: Self::K) -> Result<()> {
+        self.table.borrow_mut().remove(&key);
+        Ok(())
+    }
+
+    fn set_ex(&self, key: Self::K, v: Self::V, ttl: Duration) -> Result<()> {
+        let deadline = Instant::now() + ttl;
+        self.table.borrow_mut().insert(key, (v, Some(deadline)));
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FakeKVstore;
+    use super::FakeKVStore;
     use crate::KVCache;
 
     #[test]
     fn test_face_connect() {
-        let cache = KVCache::<FakeKVstore<_, _>>::new();
+        let cache = KVCache::<FakeKVStore<_, _>>::new();
         cache.set(3, 65).unwrap();
         cache.set(4, 45).unwrap();
         assert_eq!(cache.get(3), Some(65));
@@ -1,5 +1,7 @@
 pub mod fake;
 pub mod redis;
+use std::time::Duration;
+
 use anyhow::Result;
 
 
@@ -8,6 +10,39 @@ pub trait Connector {
     type V;
     fn get(&self, key: Self::K) -> Option<Self::V>;
     fn set(&self, key: Self::K, v: Self::V) -> Result<()>;
+    /// Remove the key, it's fine if there's none.
+    fn delete(&self, key: Self::K) -> Result<()>;
     fn new() -> Self;
+
+    /// Set all the pairs, the connector can override it to save the round-trips.
+    fn set_batch(&self, items: Vec<(Self::K, Self::V)>) -> Result<()> {
+        for (k, v) in items {
+            self.set(k, v)?;
+        }
+        Ok(())
+    }
+
+    /// Set the pair which expires after the ttl, the connector without the expiry keeps it.
+    fn set_ex(&self, key: Self::K, v: Self::V, _ttl: Duration) -> Result<()> {
+        self.set(key, v)
+    }
+
+    /// Set all the pairs like [`Connector::set_ex`].
+    fn set_batch_ex(&self, items: Vec<(Self::K, Self::V)>, ttl: Duration) -> Result<()> {
+        for (k, v) in items {
+            self.set_ex(k, v, ttl)?;
+        }
+        Ok(())
+    }
+
+    /// Get the values of all the keys in order, `None` for the missing one.
+    fn get_many(&self, keys: Vec<Self::K>) -> Vec<Option<Self::V>> {
+        keys.into_iter().map(|k| self.get(k)).collect()
+    }
+
+    /// Check the server is reachable, the connector without a server always is.
+    fn ping(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
@@ -1,13 +1,36 @@
 use crate::utils;
 use super::Connector;
 use anyhow::Result;
+use r2d2::{Pool, PooledConnection};
 use redis::{
-    Connection, ConnectionInfo, FromRedisValue,ToRedisArgs, IntoConnectionInfo,
+    Client, ConnectionInfo, ConnectionLike, FromRedisValue, IntoConnectionInfo, RedisError,
+    RedisResult, ToRedisArgs,
 };
-use std::{cell::RefCell, marker::PhantomData};
+use std::{marker::PhantomData, thread, time::Duration};
+
+/// The address of the server, e.g. `redis://127.0.0.1:6379`.
+const REDIS_CONFIG: &str = "REDIS_CONFIG";
+/// The max connections to the server kept in the pool.
+const REDIS_POOL_SIZE: &str = "REDIS_POOL_SIZE";
+/// How many times a command is retried on a new connection after an IO error.
+const REDIS_RETRIES: &str = "REDIS_RETRIES";
+/// The prefix of all the keys, e.g. `mega:prod:`, so the instances sharing a server don't see
+/// the keys of each other.
+const REDIS_KEY_PREFIX: &str = "REDIS_KEY_PREFIX";
+
+const DEFAULT_POOL_SIZE: u32 = 8;
+const DEFAULT_RETRIES: usize = 3;
+/// The wait before the first retry, doubled for each next one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+/// The wait of a ping to connect, and then for the answer.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct RedisClient<K, V> {
-    conn: RefCell<Connection>,
+    /// The client of the connections out of the pool, e.g. of the pings.
+    client: Client,
+    pool: Pool<Client>,
+    retries: usize,
+    prefix: Vec<u8>,
     k: PhantomData<K>,
     v: PhantomData<V>,
 }
@@ -20,48 +43,230 @@ where
     type K = K;
     type V = V;
     fn get(&self, key: Self::K) -> Option<Self::V> {
-        match redis::cmd("GET")
-            .arg(key)
-            .query(&mut self.conn.borrow_mut())
-        {
-            Ok(a) => Some(a),
-            Err(_) => None,
+        let key = self.key(&key);
+        let result = self.query(|con| redis::cmd("GET").arg(&key).query::<Option<V>>(con));
+        match result {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::warn!("redis GET failed: {}", err);
+                None
+            }
         }
     }
 
     fn set(&self, key: Self::K, v: Self::V) -> Result<()> {
-        match redis::cmd("SET")
-            .arg(key)
-            .arg(v)
-            .query::<bool>(&mut self.conn.borrow_mut())
-        {
-            Ok(_) => Ok(()),
-            Err(err) => Err(err.into()),
-        }
+        let key = self.key(&key);
+        self.query(|con| set_cmd(&key, &v, None).query::<()>(con))
+    }
+
+    fn delete(&self, key: Self::K) -> Result<()> {
+        let key = self.key(&key);
+        self.query(|con| redis::cmd("DEL").arg(&key).query::<()>(con))
     }
+
+    /// Set by `PX`, so the server removes the key after the ttl.
+    fn set_ex(&self, key: Self::K, v: Self::V, ttl: Duration) -> Result<()> {
+        let key = self.key(&key);
+        self.query(|con| set_cmd(&key, &v, Some(ttl)).query::<()>(con))
+    }
+
+    /// The address is from the `REDIS_CONFIG` env, the size of the pool from the
+    /// `REDIS_POOL_SIZE`, and the prefix of the keys from the `REDIS_KEY_PREFIX`. The server is
+    /// connected on demand, so it's fine to be down for now.
     fn new() -> RedisClient<K, V> {
-        let mut addr: String= String::new();
-        utils::get_env_number("REDIS_CONFIG", &mut addr);
-        let config :ConnectionInfo  = addr.into_connection_info().unwrap();
-        let c = Self::new_client(config.clone()).unwrap();
-        RedisClient {
-            conn: RefCell::new(c),
-            k: PhantomData,
-            v: PhantomData,
+        Self::from_env().unwrap().expect("REDIS_CONFIG is not set")
+    }
+
+    /// Use a pipeline, so the batch costs only one round-trip.
+    fn set_batch(&self, items: Vec<(Self::K, Self::V)>) -> Result<()> {
+        let items = self.keys_of(items);
+        self.query(|con| set_batch(con, &items, None))
+    }
+
+    fn set_batch_ex(&self, items: Vec<(Self::K, Self::V)>, ttl: Duration) -> Result<()> {
+        let items = self.keys_of(items);
+        self.query(|con| set_batch(con, &items, Some(ttl)))
+    }
+
+    /// Use `MGET`, so the batch costs only one round-trip.
+    fn get_many(&self, keys: Vec<Self::K>) -> Vec<Option<Self::V>> {
+        let keys: Vec<Vec<u8>> = keys.iter().map(|k| self.key(k)).collect();
+        match self.query(|con| get_many(con, &keys)) {
+            Ok(values) => values,
+            Err(err) => {
+                tracing::warn!("redis MGET failed: {}", err);
+                keys.iter().map(|_| None).collect()
+            }
+        }
+    }
+
+    /// Send a `PING` on a new connection without the retries, so a server down is told in the
+    /// [`PING_TIMEOUT`] rather than the timeout of the pool.
+    fn ping(&self) -> Result<()> {
+        let mut con = self.client.get_connection_with_timeout(PING_TIMEOUT)?;
+        con.set_read_timeout(Some(PING_TIMEOUT))?;
+        redis::cmd("PING").query::<()>(&mut con)?;
+        Ok(())
+    }
+}
+
+/// The key of the server, the prefix followed by the args of the key.
+fn prefixed<K: ToRedisArgs>(prefix: &[u8], key: &K) -> Vec<u8> {
+    let mut prefixed = prefix.to_vec();
+    for arg in key.to_redis_args() {
+        prefixed.extend(arg);
+    }
+    prefixed
+}
+
+/// The `SET` of the pair, which expires after the ttl if any.
+fn set_cmd<K, V>(key: &K, v: &V, ttl: Option<Duration>) -> redis::Cmd
+where
+    K: ToRedisArgs,
+    V: ToRedisArgs,
+{
+    let mut cmd = redis::cmd("SET");
+    cmd.arg(key).arg(v);
+    if let Some(ttl) = ttl {
+        // at least 1 ms, `PX 0` is an error
+        cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+    }
+    cmd
+}
+
+fn set_batch<C, K, V>(con: &mut C, items: &[(K, V)], ttl: Option<Duration>) -> RedisResult<()>
+where
+    C: ConnectionLike,
+    K: ToRedisArgs,
+    V: ToRedisArgs,
+{
+    if items.is_empty() {
+        return Ok(());
+    }
+    let mut pipe = redis::pipe();
+    for (k, v) in items {
+        pipe.add_command(set_cmd(k, v, ttl)).ignore();
+    }
+    pipe.query::<()>(con)
+}
+
+fn get_many<C, K, V>(con: &mut C, keys: &[K]) -> RedisResult<Vec<Option<V>>>
+where
+    C: ConnectionLike,
+    K: ToRedisArgs,
+    V: FromRedisValue,
+{
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut cmd = redis::cmd("MGET");
+    for k in keys {
+        cmd.arg(k);
+    }
+    let values = cmd.query::<Vec<redis::Value>>(con)?;
+    Ok(values
+        .iter()
+        .map(|v| match v {
+            redis::Value::Nil => None,
+            v => V::from_redis_value(v).ok(),
+        })
+        .collect())
+}
+
+/// Run the `query` on a connection from `connect`, and again on a new one after an IO error,
+/// e.g. the server is restarted, at most `retries` times.
+///
+/// The broken connection is dropped rather than reused, which the pool checks by
+/// `is_open` when it's returned.
+fn with_retry<C, T>(
+    retries: usize,
+    mut connect: impl FnMut() -> Result<C>,
+    mut query: impl FnMut(&mut C) -> RedisResult<T>,
+) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        let result = connect().and_then(|mut con| query(&mut con).map_err(Into::into));
+        let err = match result {
+            Ok(v) => return Ok(v),
+            Err(err) => err,
+        };
+        let transient = match err.downcast_ref::<RedisError>() {
+            Some(e) => e.is_io_error(),
+            // failed to get a connection of the pool
+            None => err.is::<r2d2::Error>(),
+        };
+        if !transient || attempt >= retries {
+            return Err(err);
         }
+        tracing::warn!(
+            "redis connection failed, retry {}/{}: {}",
+            attempt + 1,
+            retries,
+            err
+        );
+        thread::sleep(RETRY_BACKOFF * 2u32.pow(attempt as u32));
+        attempt += 1;
     }
 }
+
 impl<K, V> RedisClient<K, V>
 where
     K: ToRedisArgs,
     V: ToRedisArgs + FromRedisValue,
 {
-    fn new_client(info: ConnectionInfo) -> Result<Connection> {
-        
-        let client = redis::Client::open(info)?;
-        let con = client.get_connection()?;
-        // let ss:RefCell<C> = RefCell::new(con);
-        Ok(con)
+    /// The client configured like [`Connector::new`], none if the `REDIS_CONFIG` env is not set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(addr) = std::env::var(REDIS_CONFIG) else {
+            return Ok(None);
+        };
+        let mut pool_size = DEFAULT_POOL_SIZE;
+        utils::get_env_number(REDIS_POOL_SIZE, &mut pool_size);
+        let mut retries = DEFAULT_RETRIES;
+        utils::get_env_number(REDIS_RETRIES, &mut retries);
+        let mut prefix = String::new();
+        utils::get_env_number(REDIS_KEY_PREFIX, &mut prefix);
+        let client = Self::with_pool(addr.into_connection_info()?, pool_size, retries)?;
+        Ok(Some(client.with_prefix(prefix)))
+    }
+
+    /// Keep at most `pool_size` connections to the server, and retry a command `retries` times
+    /// on IO errors.
+    pub fn with_pool(info: ConnectionInfo, pool_size: u32, retries: usize) -> Result<Self> {
+        let client = Client::open(info)?;
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .min_idle(Some(0))
+            .connection_timeout(Duration::from_secs(5))
+            .build_unchecked(client.clone());
+        Ok(RedisClient {
+            client,
+            pool,
+            retries,
+            prefix: Vec::new(),
+            k: PhantomData,
+            v: PhantomData,
+        })
+    }
+
+    /// Put all the keys under the prefix, e.g. `mega:prod:`, none by default.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into().into_bytes();
+        self
+    }
+
+    fn key(&self, key: &K) -> Vec<u8> {
+        prefixed(&self.prefix, key)
+    }
+
+    fn keys_of(&self, items: Vec<(K, V)>) -> Vec<(Vec<u8>, V)> {
+        items.into_iter().map(|(k, v)| (self.key(&k), v)).collect()
+    }
+
+    fn query<T>(
+        &self,
+        query: impl FnMut(&mut PooledConnection<Client>) -> RedisResult<T>,
+    ) -> Result<T> {
+        with_retry(self.retries, || Ok(self.pool.get()?), query)
     }
 }
 
@@ -70,10 +275,13 @@ mod tests {
     use crate::connector::{redis::RedisClient, Connector};
     use crate::KVCache;
     use anyhow::Result;
-    use redis::{ErrorKind, FromRedisValue, RedisError, ToRedisArgs, cmd};
+    use redis::{
+        cmd, pipe, ConnectionLike, ErrorKind, FromRedisValue, IntoConnectionInfo, RedisError,
+        ToRedisArgs,
+    };
     use redis_test::{MockCmd, MockRedisConnection};
     use serde::{Deserialize, Serialize};
-    use std::{cell::RefCell, marker::PhantomData, vec};
+    use std::{cell::RefCell, collections::HashMap, marker::PhantomData, time::Duration, vec};
 
     #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
     struct TestMessage {
@@ -154,6 +362,13 @@ mod tests {
             }
         }
 
+        fn delete(&self, key: Self::K) -> Result<()> {
+            redis::cmd("DEL")
+                .arg(key)
+                .query::<()>(&mut self.conn.borrow_mut())
+                .map_err(Into::into)
+        }
+
         fn new() -> Self {
             let c = Self::new_client().unwrap();
             RedisMockClient {
@@ -209,6 +424,13 @@ mod tests {
         assert_eq!(cache.get(4), Some(b));
     }
 
+    #[test]
+    fn test_ping_unreachable() {
+        let info = "redis://127.0.0.1:1".into_connection_info().unwrap();
+        let client = RedisClient::<String, String>::with_pool(info, 1, 0).unwrap();
+        assert!(client.ping().is_err());
+    }
+
     #[test]
     fn test_mock_redis() {
         let cache = KVCache::<RedisMockClient<_, _>>::new();
@@ -225,4 +447,237 @@ mod tests {
         assert_eq!(cache.get(3), Some(a));
         assert_eq!(cache.get(4), Some(b));
     }
+
+    #[test]
+    fn test_mock_redis_batch() {
+        let a = TestMessage {
+            id: 12,
+            message: vec![1, 2, 3, 4, 5],
+        };
+        let b = TestMessage {
+            id: 13,
+            message: vec![4, 5, 6, 7, 8],
+        };
+        let mut conn = MockRedisConnection::new(vec![
+            MockCmd::with_values(
+                pipe()
+                    .cmd("SET")
+                    .arg(3)
+                    .arg(a.clone())
+                    .ignore()
+                    .cmd("SET")
+                    .arg(4)
+                    .arg(b.clone())
+                    .ignore(),
+                Ok(vec!["OK", "OK"]),
+            ),
+            MockCmd::new(
+                cmd("MGET").arg(3).arg(5).arg(4),
+                Ok(redis::Value::Bulk(vec![
+                    redis::Value::Data(serde_json::to_vec(&a).unwrap()),
+                    redis::Value::Nil,
+                    redis::Value::Data(serde_json::to_vec(&b).unwrap()),
+                ])),
+            ),
+        ]);
+        super::set_batch(&mut conn, &[(3, a.clone()), (4, b.clone())], None).unwrap();
+        let values: Vec<Option<TestMessage>> = super::get_many(&mut conn, &[3, 5, 4]).unwrap();
+        assert_eq!(values, vec![Some(a.clone()), None, Some(b)]);
+
+        // expired by the server after the ttl
+        let ttl = Duration::from_secs(60);
+        let mut conn = MockRedisConnection::new(vec![
+            MockCmd::new(
+                cmd("SET").arg(3).arg(a.clone()).arg("PX").arg(60000),
+                Ok("OK"),
+            ),
+            MockCmd::with_values(
+                pipe()
+                    .cmd("SET")
+                    .arg(3)
+                    .arg(a.clone())
+                    .arg("PX")
+                    .arg(60000)
+                    .ignore(),
+                Ok(vec!["OK"]),
+            ),
+        ]);
+        super::set_cmd(&3, &a, Some(ttl))
+            .query::<()>(&mut conn)
+            .unwrap();
+        super::set_batch(&mut conn, &[(3, a)], Some(ttl)).unwrap();
+    }
+
+    /// A server of the keys in the memory, which knows only `GET`, `SET`, `MGET` and `DEL`, and
+    /// answers a client error of the others.
+    #[derive(Default)]
+    struct MemoryServer(HashMap<Vec<u8>, Vec<u8>>);
+
+    impl ConnectionLike for MemoryServer {
+        fn req_packed_command(&mut self, cmd: &[u8]) -> redis::RedisResult<redis::Value> {
+            let redis::Value::Bulk(args) = redis::parse_redis_value(cmd)? else {
+                unreachable!("a command is an array");
+            };
+            let args: Vec<Vec<u8>> = args
+                .into_iter()
+                .map(|arg| Vec::<u8>::from_redis_value(&arg).unwrap())
+                .collect();
+            let value = |key: &Vec<u8>| match self.0.get(key) {
+                Some(v) => redis::Value::Data(v.clone()),
+                None => redis::Value::Nil,
+            };
+            Ok(match &args[0][..] {
+                b"GET" => value(&args[1]),
+                b"MGET" => redis::Value::Bulk(args[1..].iter().map(value).collect()),
+                b"SET" => {
+                    self.0.insert(args[1].clone(), args[2].clone());
+                    redis::Value::Okay
+                }
+                b"DEL" => redis::Value::Int(
+                    args[1..].iter().filter_map(|k| self.0.remove(k)).count() as i64,
+                ),
+                _ => {
+                    return Err(RedisError::from((
+                        ErrorKind::ClientError,
+                        "unsupported command",
+                    )))
+                }
+            })
+        }
+
+        fn req_packed_commands(
+            &mut self,
+            _cmd: &[u8],
+            _offset: usize,
+            _count: usize,
+        ) -> redis::RedisResult<Vec<redis::Value>> {
+            Err(RedisError::from((
+                ErrorKind::ClientError,
+                "unsupported pipeline",
+            )))
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            true
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_key_prefix() {
+        let a = TestMessage {
+            id: 12,
+            message: vec![1, 2, 3, 4, 5],
+        };
+        let b = TestMessage {
+            id: 13,
+            message: vec![4, 5, 6, 7, 8],
+        };
+        let (prod, test) = (b"mega:prod:", b"mega:test:");
+        let mut server = MemoryServer::default();
+        // the same key of the two instances
+        super::set_cmd(&super::prefixed(prod, &3), &a, None)
+            .query::<()>(&mut server)
+            .unwrap();
+        let get = |server: &mut MemoryServer, prefix: &[u8]| {
+            cmd("GET")
+                .arg(super::prefixed(prefix, &3))
+                .query::<Option<TestMessage>>(server)
+                .unwrap()
+        };
+        assert_eq!(get(&mut server, prod), Some(a.clone()));
+        assert_eq!(get(&mut server, test), None);
+
+        super::set_cmd(&super::prefixed(test, &3), &b, None)
+            .query::<()>(&mut server)
+            .unwrap();
+        assert_eq!(get(&mut server, prod), Some(a.clone()));
+        assert_eq!(get(&mut server, test), Some(b));
+        let keys = [super::prefixed(prod, &3), super::prefixed(prod, &4)];
+        let values: Vec<Option<TestMessage>> = super::get_many(&mut server, &keys).unwrap();
+        assert_eq!(values, vec![Some(a.clone()), None]);
+        // no prefix, the key as before
+        assert_eq!(super::prefixed(b"", &3), b"3");
+        assert_eq!(server.0.len(), 2);
+
+        // only the key of the prefix is removed
+        cmd("DEL")
+            .arg(super::prefixed(test, &3))
+            .query::<()>(&mut server)
+            .unwrap();
+        assert_eq!(get(&mut server, test), None);
+        assert_eq!(get(&mut server, prod), Some(a));
+        let err = cmd("FLUSHALL").query::<()>(&mut server).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ClientError);
+    }
+
+    #[test]
+    fn test_retry_dropped_connection() {
+        let a = TestMessage {
+            id: 12,
+            message: vec![1, 2, 3, 4, 5],
+        };
+        let reset = || {
+            let err = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+            MockRedisConnection::new(vec![MockCmd::new::<_, &str>(
+                cmd("GET").arg(3),
+                Err(RedisError::from(err)),
+            )])
+        };
+        let mut connections = vec![
+            MockRedisConnection::new(vec![MockCmd::new(
+                cmd("GET").arg(3),
+                Ok(serde_json::to_vec(&a).unwrap()),
+            )]),
+            reset(),
+        ];
+        let get =
+            |con: &mut MockRedisConnection| cmd("GET").arg(3).query::<Option<TestMessage>>(con);
+
+        // the connection is dropped once, and the command is sent again on a new one
+        let mut connects = 0;
+        let value = super::with_retry(
+            2,
+            || {
+                connects += 1;
+                Ok(connections.pop().unwrap())
+            },
+            get,
+        )
+        .unwrap();
+        assert_eq!(value, Some(a));
+        assert_eq!(connects, 2);
+
+        // give up after the retries
+        let mut connections = vec![reset(), reset(), reset()];
+        let result = super::with_retry(1, || Ok(connections.pop().unwrap()), get);
+        assert!(result.is_err());
+        assert_eq!(connections.len(), 1);
+
+        // the other errors are not retried
+        let mut connections = vec![MockRedisConnection::new(vec![MockCmd::new::<_, &str>(
+            cmd("GET").arg(3),
+            Err(RedisError::from((ErrorKind::ResponseError, "WRONGTYPE"))),
+        )])];
+        let mut connects = 0;
+        let result = super::with_retry(
+            3,
+            || {
+                connects += 1;
+                connections
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("no connection"))
+            },
+            get,
+        );
+        assert!(result.is_err());
+        assert_eq!(connects, 1);
+    }
 }
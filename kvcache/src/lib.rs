@@ -1,6 +1,7 @@
 pub mod connector;
 pub mod utils;
 use std::cell::RefCell;
+use std::time::Duration;
 use connector::Connector;
 use anyhow::Result;
 
@@ -26,6 +27,31 @@ where
     pub fn set(&self, key: C::K, value: C::V) -> Result<()> {
         self.con.borrow_mut().set(key, value)
     }
+
+    pub fn set_batch(&self, items: Vec<(C::K, C::V)>) -> Result<()> {
+        self.con.borrow_mut().set_batch(items)
+    }
+
+    pub fn delete(&self, key: C::K) -> Result<()> {
+        self.con.borrow_mut().delete(key)
+    }
+
+    /// Set the value which expires after the ttl.
+    pub fn set_ex(&self, key: C::K, value: C::V, ttl: Duration) -> Result<()> {
+        self.con.borrow_mut().set_ex(key, value, ttl)
+    }
+
+    pub fn set_batch_ex(&self, items: Vec<(C::K, C::V)>, ttl: Duration) -> Result<()> {
+        self.con.borrow_mut().set_batch_ex(items, ttl)
+    }
+
+    pub fn get_many(&self, keys: Vec<C::K>) -> Vec<Option<C::V>> {
+        self.con.borrow().get_many(keys)
+    }
+
+    pub fn ping(&self) -> Result<()> {
+        self.con.borrow().ping()
+    }
 }
 impl<C> Default for KVCache<C> where C: Connector,{
     fn default() -> Self {
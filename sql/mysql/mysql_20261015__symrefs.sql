@@ -0,0 +1,8 @@
+-- the symbolic refs of the repos like `HEAD`, which point to the other refs
+CREATE TABLE IF NOT EXISTS `symrefs` (
+  `repo_path` varchar(255) NOT NULL,
+  `ref_name` varchar(255) NOT NULL,
+  `target` varchar(255) NOT NULL,
+  `updated_at` datetime NOT NULL,
+  PRIMARY KEY (`repo_path`, `ref_name`)
+);
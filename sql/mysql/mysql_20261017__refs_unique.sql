@@ -0,0 +1,4 @@
+-- a ref of a repo is saved once, of the duplicates only the first one is kept
+DELETE `a` FROM `refs` `a` JOIN `refs` `b`
+  ON `a`.`repo_path` = `b`.`repo_path` AND `a`.`ref_name` = `b`.`ref_name` AND `a`.`id` > `b`.`id`;
+CREATE UNIQUE INDEX `uniq_refs_repo_path_ref_name` ON `refs` (`repo_path`, `ref_name`);
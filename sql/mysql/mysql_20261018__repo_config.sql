@@ -0,0 +1,7 @@
+-- the settings of each repository overriding the options of the server, in JSON
+CREATE TABLE IF NOT EXISTS `repo_config` (
+  `repo_path` varchar(255) NOT NULL,
+  `config` text NOT NULL,
+  `updated_at` datetime NOT NULL,
+  PRIMARY KEY (`repo_path`)
+);
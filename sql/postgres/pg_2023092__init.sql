@@ -11,9 +11,9 @@ CREATE TABLE IF NOT EXISTS "commit" (
   "created_at" TIMESTAMP NOT NULL,
   "updated_at" TIMESTAMP NOT NULL
 );
-CREATE INDEX "idx_c_git_id" ON "commit" ("git_id");
-CREATE INDEX "idx_c_tree" ON "commit" ("tree");
-CREATE INDEX "idx_c_repo_path" ON "commit" ("repo_path");
+CREATE INDEX IF NOT EXISTS "idx_c_git_id" ON "commit" ("git_id");
+CREATE INDEX IF NOT EXISTS "idx_c_tree" ON "commit" ("tree");
+CREATE INDEX IF NOT EXISTS "idx_c_repo_path" ON "commit" ("repo_path");
 
 
 CREATE TABLE IF NOT EXISTS "node" (
@@ -31,9 +31,9 @@ CREATE TABLE IF NOT EXISTS "node" (
   "created_at" TIMESTAMP NOT NULL,
   "updated_at" TIMESTAMP NOT NULL
 );
-CREATE INDEX "idx_node_git_id" ON "node" ("git_id");
-CREATE INDEX "idx_node_name" ON "node" ("name");
-CREATE INDEX "idx_node_repo_path" ON "node" ("repo_path");
+CREATE INDEX IF NOT EXISTS "idx_node_git_id" ON "node" ("git_id");
+CREATE INDEX IF NOT EXISTS "idx_node_name" ON "node" ("name");
+CREATE INDEX IF NOT EXISTS "idx_node_repo_path" ON "node" ("repo_path");
 
 
 CREATE TABLE IF NOT EXISTS "refs" (
@@ -55,8 +55,8 @@ CREATE TABLE IF NOT EXISTS "mr" (
   "created_at" TIMESTAMP NOT NULL,
   PRIMARY KEY ("id")
 );
-CREATE INDEX "idx_mr_hash" ON "mr" ("git_id");
-CREATE INDEX "idx_mr_id" ON "mr" ("mr_id", "object_type");
+CREATE INDEX IF NOT EXISTS "idx_mr_hash" ON "mr" ("git_id");
+CREATE INDEX IF NOT EXISTS "idx_mr_id" ON "mr" ("mr_id", "object_type");
 
 
 CREATE TABLE IF NOT EXISTS "git_obj" (
@@ -66,7 +66,7 @@ CREATE TABLE IF NOT EXISTS "git_obj" (
   "data" BYTEA,
   PRIMARY KEY ("id")
 );
-CREATE INDEX "idx_data_git_id" ON "git_obj" ("git_id");
+CREATE INDEX IF NOT EXISTS "idx_data_git_id" ON "git_obj" ("git_id");
 
 
 CREATE TABLE IF NOT EXISTS "mr_info" (
@@ -77,7 +77,7 @@ CREATE TABLE IF NOT EXISTS "mr_info" (
   "created_at" TIMESTAMP NOT NULL,
   "updated_at" TIMESTAMP NOT NULL
 );
-CREATE INDEX "idx_info_mr_id" ON "mr_info" ("mr_id");
+CREATE INDEX IF NOT EXISTS "idx_info_mr_id" ON "mr_info" ("mr_id");
 
 
 
@@ -119,5 +119,5 @@ CREATE TABLE IF NOT EXISTS "repo_directory"(
     "updated_at" TIMESTAMP NOT NULL
 );
 
-CREATE INDEX "idx_dir_pid" ON "repo_directory" ("pid");
-CREATE INDEX "idx_dir_path" ON "repo_directory" ("full_path");
+CREATE INDEX IF NOT EXISTS "idx_dir_pid" ON "repo_directory" ("pid");
+CREATE INDEX IF NOT EXISTS "idx_dir_path" ON "repo_directory" ("full_path");
@@ -0,0 +1,8 @@
+-- the symbolic refs of the repos like `HEAD`, which point to the other refs
+CREATE TABLE IF NOT EXISTS "symrefs" (
+  "repo_path" VARCHAR(255) NOT NULL,
+  "ref_name" VARCHAR(255) NOT NULL,
+  "target" VARCHAR(255) NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  PRIMARY KEY ("repo_path", "ref_name")
+);
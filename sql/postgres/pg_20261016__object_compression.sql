@@ -0,0 +1,2 @@
+-- the compression of the data of each object like `zstd`, none if it is saved as it is
+ALTER TABLE "git_obj" ADD COLUMN IF NOT EXISTS "compression" VARCHAR(16);
@@ -0,0 +1,7 @@
+-- the settings of each repository overriding the options of the server, in JSON
+CREATE TABLE IF NOT EXISTS "repo_config" (
+  "repo_path" VARCHAR(255) NOT NULL,
+  "config" TEXT NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  PRIMARY KEY ("repo_path")
+);
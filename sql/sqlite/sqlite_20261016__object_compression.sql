@@ -0,0 +1,2 @@
+-- the compression of the data of each object like `zstd`, none if it is saved as it is
+ALTER TABLE "git_obj" ADD COLUMN "compression" VARCHAR(16);
@@ -0,0 +1,4 @@
+-- a ref of a repo is saved once, of the duplicates only the first one is kept
+DELETE FROM "refs"
+  WHERE "id" NOT IN (SELECT MIN("id") FROM "refs" GROUP BY "repo_path", "ref_name");
+CREATE UNIQUE INDEX IF NOT EXISTS "uniq_refs_repo_path_ref_name" ON "refs" ("repo_path", "ref_name");
@@ -3,12 +3,13 @@
 //!
 //!
 //!
-use clap::{Arg, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgMatches, Command};
 use config as c;
 use serde::Deserialize;
 
 use crate::commands::{builtin, builtin_exec};
 use common::errors::{MegaError, MegaResult};
+use gateway::logging::{self, LogFormat};
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct Config {}
@@ -16,7 +17,7 @@ pub(crate) struct Config {}
 impl Config {
     pub fn new(path: &str) -> Result<Self, c::ConfigError> {
         let builder = c::Config::builder().add_source(c::File::new(path, c::FileFormat::Toml));
-        let config = builder.build().unwrap();
+        let config = builder.build()?;
 
         Config::from_config(&config)
     }
@@ -31,11 +32,16 @@ impl Config {
 }
 
 pub fn parse() -> MegaResult {
-    let matches = cli().try_get_matches().unwrap_or_else(|e| e.exit());
+    let matches = cli().try_get_matches()?;
+    let level = matches.get_one::<String>("log-level").unwrap();
+    let format = *matches.get_one::<LogFormat>("log-format").unwrap();
+    logging::init(level, format)?;
     let mut config = Config::default();
 
     if let Some(c) = matches.get_one::<String>("config").cloned() {
-        config = Config::new(c.as_str()).unwrap()
+        config = Config::new(c.as_str()).map_err(|e| {
+            MegaError::new(anyhow::anyhow!("failed to load the config {}: {}", c, e), 1)
+        })?;
     }
 
     let (cmd, subcommand_args) = match matches.subcommand() {
@@ -61,6 +67,22 @@ fn cli() -> Command {
                 .long("config")
                 .help("Sets a config file work directory"),
         )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .env("MEGA_LOG")
+                .global(true)
+                .default_value("info")
+                .help("Sets the log level, or the directives like `gateway=debug,info`"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .global(true)
+                .value_parser(value_parser!(LogFormat))
+                .default_value("text")
+                .help("Sets the format of the logs, `json` for the log aggregation"),
+        )
 }
 
 fn exec_subcommand(config: Config, cmd: &str, args: &ArgMatches) -> MegaResult {
@@ -0,0 +1,39 @@
+//! The `db` command of the maintenance of the database, e.g. `mega db migrate`.
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use crate::cli::Config;
+use common::errors::{MegaError, MegaResult};
+
+use gateway::migrate::{migrate, MigrateOptions};
+
+pub fn cli() -> Command {
+    Command::new("db")
+        .about("Manage the schema of the database")
+        .subcommand_required(true)
+        .subcommand(MigrateOptions::augment_args_for_update(
+            Command::new("migrate").about("Apply the pending migrations of the schema"),
+        ))
+}
+
+#[tokio::main]
+pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
+    match args.subcommand() {
+        Some(("migrate", args)) => {
+            let options = MigrateOptions::from_arg_matches(args)?;
+            let applied = migrate(&options).await?;
+            if applied.is_empty() {
+                println!("The database is up to date, nothing to migrate");
+            }
+            for migration in &applied {
+                println!("Applied {} {}", migration.version, migration.name);
+            }
+            Ok(())
+        }
+        Some((cmd, _)) => Err(MegaError::unknown_subcommand(cmd)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {}
@@ -0,0 +1,34 @@
+//!
+//!
+//!
+//!
+//!
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use crate::cli::Config;
+use common::errors::MegaResult;
+
+use gateway::export::{export_repo, ExportOptions};
+
+pub fn cli() -> Command {
+    ExportOptions::augment_args_for_update(
+        Command::new("export").about("Export a repository as a packfile or a git bundle"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
+    let options = ExportOptions::from_arg_matches(args)?;
+    // e.g. no ref matches or an object is missing in the storage
+    let summary = export_repo(&options).await?;
+    println!(
+        "Exported {} to {}: {}",
+        options.repo,
+        options.out.display(),
+        summary
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
@@ -8,7 +8,8 @@ use clap::{ArgMatches, Args, Command, FromArgMatches};
 use crate::{cli::Config, commands::https};
 use common::errors::MegaResult;
 
-use gateway::https::{http_server, HttpOptions};
+use gateway::https::{http_server, HttpOptions, HttpOptionsBuilder};
+use gateway::shutdown::Shutdown;
 
 pub fn cli() -> Command {
     HttpOptions::augment_args_for_update(Command::new("https").about("Start Git HTTPS server"))
@@ -16,13 +17,40 @@ pub fn cli() -> Command {
 
 #[tokio::main]
 pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
-    let server_matchers = HttpOptions::from_arg_matches(args)
-        .map_err(|err| err.exit())
-        .unwrap();
-    println!("{server_matchers:#?}");
-    https::http_server(&server_matchers).await.unwrap();
+    // e.g. the port is 0 or the parent of the lfs content path is missing
+    let server_matchers = HttpOptionsBuilder::from(HttpOptions::from_arg_matches(args)?).build()?;
+    tracing::info!(options = ?server_matchers, "Starting the HTTPS server");
+    // stop on SIGTERM or SIGINT after the requests in flight
+    let shutdown = Shutdown::on_signal()?;
+    // e.g. the TLS certificate is missing or malformed, or the port is in use
+    https::http_server(&server_matchers, shutdown).await?;
     Ok(())
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::net::TcpListener;
+
+    use super::{cli, exec};
+    use crate::cli::Config;
+
+    #[test]
+    fn test_exec_port_in_use() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port().to_string();
+        let args = cli()
+            .try_get_matches_from(["https", "--host", "127.0.0.1", "--port", &port])
+            .unwrap();
+        let err = exec(Config::default(), &args).unwrap_err();
+        assert_eq!(err.code, 1);
+        assert!(err.to_string().contains("failed to bind"), "{}", err);
+    }
+
+    #[test]
+    fn test_exec_bad_port() {
+        let err = cli()
+            .try_get_matches_from(["https", "--port", "http"])
+            .unwrap_err();
+        assert_eq!(common::errors::MegaError::from(err).code, 2);
+    }
+}
@@ -0,0 +1,29 @@
+//!
+//!
+//!
+//!
+//!
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use crate::cli::Config;
+use common::errors::MegaResult;
+
+use gateway::import::{import_pack, ImportOptions};
+
+pub fn cli() -> Command {
+    ImportOptions::augment_args_for_update(
+        Command::new("import").about("Import a packfile into the storage without the server"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
+    let options = ImportOptions::from_arg_matches(args)?;
+    // e.g. the pack is corrupted or the database is unreachable
+    let summary = import_pack(&options).await?;
+    println!("Imported {}: {}", options.pack.display(), summary);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
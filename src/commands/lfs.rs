@@ -0,0 +1,43 @@
+//! The `lfs` command of the maintenance of the LFS objects, e.g. `mega lfs gc --dry-run`.
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use crate::cli::Config;
+use common::errors::{MegaError, MegaResult};
+
+use gateway::lfs_gc::{lfs_gc, LfsGcOptions};
+
+pub fn cli() -> Command {
+    Command::new("lfs")
+        .about("Manage the LFS objects in the storage")
+        .subcommand_required(true)
+        .subcommand(LfsGcOptions::augment_args_for_update(
+            Command::new("gc").about("Remove the LFS objects not referenced by any ref"),
+        ))
+}
+
+#[tokio::main]
+pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
+    match args.subcommand() {
+        Some(("gc", args)) => {
+            let options = LfsGcOptions::from_arg_matches(args)?;
+            // e.g. an object reachable from a ref is missing, then nothing is removed
+            let summary = lfs_gc(&options).await?;
+            let action = if options.dry_run {
+                "Would remove"
+            } else {
+                "Removed"
+            };
+            for meta in &summary.removed {
+                println!("{} {} ({} bytes)", action, meta.oid, meta.size);
+            }
+            println!("LFS gc: {}", summary);
+            Ok(())
+        }
+        Some((cmd, _)) => Err(MegaError::unknown_subcommand(cmd)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {}
@@ -15,11 +15,9 @@ pub fn cli() -> Command {
 
 #[tokio::main]
 pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
-    let server_matchers = run_mda::MDAOptions::from_arg_matches(args)
-        .map_err(|err| err.exit())
-        .unwrap();
+    let server_matchers = run_mda::MDAOptions::from_arg_matches(args)?;
     // println!("{server_matchers:#?}");
-    run_mda::run(server_matchers).unwrap();
+    run_mda::run(server_matchers)?;
     Ok(())
 }
 
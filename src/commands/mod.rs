@@ -3,8 +3,13 @@
 //!
 //!
 //!
+mod db;
+mod export;
 mod https;
+mod import;
+mod lfs;
 mod p2p;
+mod pack;
 mod ssh;
 mod mda;
 mod webhook;
@@ -14,7 +19,7 @@ use crate::cli::Config;
 use common::errors::MegaResult;
 
 pub fn builtin() -> Vec<Command> {
-    vec![https::cli(), ssh::cli(), p2p::cli(),mda::cli(),webhook::cli()]
+    vec![https::cli(), ssh::cli(), p2p::cli(),mda::cli(),webhook::cli(), import::cli(), export::cli(), lfs::cli(), pack::cli(), db::cli()]
 }
 
 pub(crate) fn builtin_exec(cmd: &str) -> Option<fn(Config, &ArgMatches) -> MegaResult> {
@@ -24,6 +29,11 @@ pub(crate) fn builtin_exec(cmd: &str) -> Option<fn(Config, &ArgMatches) -> MegaR
         "p2p" => p2p::exec,
         "mda"=> mda::exec,
         "webhook" => webhook::exec,
+        "import" => import::exec,
+        "export" => export::exec,
+        "lfs" => lfs::exec,
+        "pack" => pack::exec,
+        "db" => db::exec,
         _ => return None,
     };
 
@@ -16,11 +16,9 @@ pub fn cli() -> Command {
 
 #[tokio::main]
 pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
-    let server_matchers = peer::P2pOptions::from_arg_matches(args)
-        .map_err(|err| err.exit())
-        .unwrap();
-    println!("{server_matchers:#?}");
-    peer::run(&server_matchers).await.unwrap();
+    let server_matchers = peer::P2pOptions::from_arg_matches(args)?;
+    tracing::info!(options = ?server_matchers, "Starting the p2p node");
+    peer::run(&server_matchers).await?;
     Ok(())
 }
 
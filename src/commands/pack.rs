@@ -0,0 +1,44 @@
+//! The `pack` command to inspect a packfile without the server, e.g. `mega pack verify <file>`.
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use crate::cli::Config;
+use common::errors::{MegaError, MegaResult};
+
+use gateway::pack_inspect::{list_pack, verify_pack, PackInspectOptions};
+
+pub fn cli() -> Command {
+    Command::new("pack")
+        .about("Inspect a packfile")
+        .subcommand_required(true)
+        .subcommand(PackInspectOptions::augment_args_for_update(
+            Command::new("verify").about("Decode the packfile and verify the checksum of it"),
+        ))
+        .subcommand(PackInspectOptions::augment_args_for_update(
+            Command::new("list").about("Print the hash, type, size and delta base of each object"),
+        ))
+}
+
+#[tokio::main]
+pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
+    match args.subcommand() {
+        Some(("verify", args)) => {
+            let options = PackInspectOptions::from_arg_matches(args)?;
+            // e.g. the checksum doesn't match or a delta is unresolved
+            let summary = verify_pack(&options).await?;
+            println!("{}: ok, {}", options.file.display(), summary);
+            Ok(())
+        }
+        Some(("list", args)) => {
+            let options = PackInspectOptions::from_arg_matches(args)?;
+            let summary = list_pack(&options, &mut std::io::stdout().lock()).await?;
+            println!("{}: ok, {}", options.file.display(), summary);
+            Ok(())
+        }
+        Some((cmd, _)) => Err(MegaError::unknown_subcommand(cmd)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {}
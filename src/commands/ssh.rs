@@ -7,6 +7,7 @@ use clap::{ArgMatches, Args, Command, FromArgMatches};
 
 use crate::{cli::Config, commands::ssh};
 use common::errors::MegaResult;
+use gateway::shutdown::Shutdown;
 use gateway::ssh::server;
 use gateway::ssh::SshOptions;
 
@@ -16,13 +17,38 @@ pub fn cli() -> Command {
 
 #[tokio::main]
 pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
-    let server_matchers = SshOptions::from_arg_matches(args)
-        .map_err(|err| err.exit())
-        .unwrap();
-    println!("{server_matchers:#?}");
-    ssh::server(&server_matchers).await.unwrap();
+    let server_matchers = SshOptions::from_arg_matches(args)?;
+    tracing::info!(options = ?server_matchers, "Starting the SSH server");
+    let shutdown = Shutdown::on_signal()?;
+    ssh::server(&server_matchers, shutdown).await?;
     Ok(())
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::net::TcpListener;
+
+    use super::{cli, exec};
+    use crate::cli::Config;
+
+    #[test]
+    fn test_exec_port_in_use() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port().to_string();
+        let key = std::env::temp_dir().join("mega_ssh_exec_host_key");
+        let args = cli()
+            .try_get_matches_from([
+                "ssh",
+                "--host",
+                "127.0.0.1",
+                "--port",
+                &port,
+                "--host-key",
+                key.to_str().unwrap(),
+            ])
+            .unwrap();
+        let err = exec(Config::default(), &args).unwrap_err();
+        assert_eq!(err.code, 1);
+        assert!(err.to_string().contains("failed to bind"), "{}", err);
+    }
+}
@@ -8,6 +8,7 @@ use clap::{ArgMatches, Args, Command, FromArgMatches};
 use crate::{cli::Config, commands::webhook};
 use common::errors::MegaResult;
 
+use gateway::shutdown::Shutdown;
 use gateway::webhook::{webhook_server, WebhookOptions};
 
 pub fn cli() -> Command {
@@ -16,11 +17,10 @@ pub fn cli() -> Command {
 
 #[tokio::main]
 pub(crate) async fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
-    let server_matchers = WebhookOptions::from_arg_matches(args)
-        .map_err(|err| err.exit())
-        .unwrap();
-    println!("{server_matchers:#?}");
-    webhook::webhook_server(&server_matchers).await.unwrap();
+    let server_matchers = WebhookOptions::from_arg_matches(args)?;
+    tracing::info!(options = ?server_matchers, "Starting the webhook server");
+    let shutdown = Shutdown::on_signal()?;
+    webhook::webhook_server(&server_matchers, shutdown).await?;
     Ok(())
 }
 
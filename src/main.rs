@@ -5,21 +5,20 @@
 //!
 //!
 
-use std::env;
 mod cli;
 mod commands;
 mod utils;
 
 fn main() {
-    env::set_var("RUST_LOG", "debug");
-    tracing_subscriber::fmt::init();
+    // `MEGA_LOG` of the `.env` too
     dotenvy::dotenv().ok();
 
-    // Parse the command line arguments
+    // Parse the command line arguments, and set up the logs by them
     let result = cli::parse();
 
-    // If there was an error, print it
+    // If there was an error, print it and exit with the code of it
     if let Err(e) = result {
-        e.print()
+        e.print();
+        std::process::exit(e.code);
     }
 }
@@ -22,7 +22,7 @@ pub async fn resolve_issue_event(
     }
 
     let issue_dto: IssueEventDto = serde_json::from_slice(request_body.freeze().as_ref()).unwrap();
-    println!("{:?}", issue_dto);
+    tracing::debug!(?issue_dto, "Received the issue event");
 
     issue_dto
     //Ok(resp.body(body).unwrap())